@@ -0,0 +1,71 @@
+// finalize and the summary json, with the ladder of one venue changing per
+// tick: an aggregate built afresh every tick against one kept across them.
+use arb_monitor::orderbook::{AggregatedOrderbook, Orderbook, Side, Stats, SummaryView};
+use bigdecimal::BigDecimal;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+
+const VENUES: usize = 8;
+const LEVELS: u64 = 50;
+
+fn book(venue: usize, tick: u64) -> Orderbook {
+    let mut ob = Orderbook::new(&format!("venue{}", venue));
+    // the book moves a cent per tick
+    let mid = 10_000_000 + venue as u64 * 100 + tick % 100;
+    for i in 1..=LEVELS {
+        let amount = BigDecimal::new((i * 37 % 1000).into(), 3);
+        ob.insert(
+            Side::Bid,
+            BigDecimal::new((mid - i).into(), 2),
+            amount.clone(),
+        );
+        ob.insert(Side::Ask, BigDecimal::new((mid + i).into(), 2), amount);
+    }
+    ob.set_timestamp(1_696_405_428_703 + tick as u128);
+    ob
+}
+
+fn publish(agg: &mut AggregatedOrderbook, stats: &Stats) -> usize {
+    agg.finalize().unwrap();
+    let view = SummaryView {
+        book: agg,
+        seq: 0,
+        opportunities: &[],
+        stats,
+    };
+    serde_json::to_string(&view).unwrap().len()
+}
+
+fn bench_finalize(c: &mut Criterion) {
+    let stats = Stats::default();
+    let mut books: Vec<Orderbook> = (0..VENUES).map(|v| book(v, 0)).collect();
+    let mut tick = 0u64;
+    let mut next = |books: &mut Vec<Orderbook>| {
+        tick += 1;
+        let venue = tick as usize % VENUES;
+        books[venue] = book(venue, tick);
+    };
+
+    c.bench_function("finalize/fresh", |b| {
+        b.iter(|| {
+            next(&mut books);
+            let mut agg = AggregatedOrderbook::new();
+            for ob in books.iter() {
+                agg.merge(ob);
+            }
+            black_box(publish(&mut agg, &stats))
+        })
+    });
+
+    let mut agg = AggregatedOrderbook::new();
+    c.bench_function("finalize/kept", |b| {
+        b.iter(|| {
+            next(&mut books);
+            agg.remerge(books.iter());
+            black_box(publish(&mut agg, &stats))
+        })
+    });
+}
+
+criterion_group!(benches, bench_finalize);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+// wire prices and sizes to BigDecimal, through BigDecimal's own parser
+// against the fixed point path.
+use arb_monitor::fixed;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+
+// a kraken and a bitstamp level
+const WIRE: [&str; 6] = [
+    "43468.00000",
+    "0.12661008",
+    "43196.60000",
+    "29737",
+    "0.67548438",
+    "1650.10",
+];
+
+fn bench_parse(c: &mut Criterion) {
+    for (name, enabled) in [("parse/bigdecimal", false), ("parse/fixed", true)] {
+        fixed::enable(enabled);
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                for s in WIRE {
+                    black_box(fixed::decimal(black_box(s)).unwrap());
+                }
+            })
+        });
+    }
+    fixed::enable(false);
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
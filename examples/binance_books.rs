@@ -0,0 +1,45 @@
+// Connect to binance and print the top of its btcusdt book, reconnecting
+// like the monitor does.
+//
+//     cargo run --example binance_books
+use arb_monitor::config::ExchangeSetting;
+use arb_monitor::exchange::{self, Exchange, RECONNECT_BACKOFF};
+
+#[actix_web::main]
+async fn main() -> anyhow::Result<()> {
+    let pairs = vec![ExchangeSetting::new("btcusdt")];
+    loop {
+        let mut client = Exchange::new("binance");
+        if let Err(e) = client.connect(pairs.clone()).await {
+            if exchange::is_permanent(&e) {
+                return Err(e);
+            }
+            eprintln!("connect: {}", e);
+            tokio::time::sleep(RECONNECT_BACKOFF).await;
+            continue;
+        }
+        loop {
+            match client.next().await {
+                Ok(Some(book)) => {
+                    let best = |level: Option<_>| {
+                        level.map_or("-".to_string(), |(price, amount)| {
+                            format!("{} x {}", price, amount)
+                        })
+                    };
+                    println!(
+                        "{} bid {} ask {}",
+                        book.timestamp(),
+                        best(book.best_bid()),
+                        best(book.best_ask())
+                    );
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("{}, reconnect", e);
+                    break;
+                }
+            }
+        }
+        client.clear()?;
+    }
+}
@@ -0,0 +1,807 @@
+pub mod telegram;
+pub mod webhook;
+
+use crate::config::{AlertConfig, AlertRule, AlertRuleKind, DetectorSetting};
+use crate::opportunity::tracker::{Event, State, Tracker};
+use crate::opportunity::{self, best_levels, bridge};
+use crate::orderbook::{get_unixtime, Level, Summary};
+use crate::status::{self, ExchangeStatus};
+use anyhow::Result;
+use bigdecimal::ToPrimitive;
+use futures_util::future::Future;
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::time::{interval, sleep, Duration};
+
+// number of levels per side attached to an alert
+const EXCERPT_LEVELS: usize = 5;
+// first retry waits this long, doubled on every following attempt
+const RETRY_BACKOFF_MS: u64 = 500;
+
+// the payload delivered to the alert targets
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Alert {
+    pub rule: &'static str,
+    // what triggered the rule, ex: exchange name, or buy->sell exchange
+    pub subject: String,
+    pub message: String,
+    pub timestamp: u128,
+    pub details: Value,
+    // relevant book excerpt
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+fn rule_name(kind: &AlertRuleKind) -> &'static str {
+    match kind {
+        AlertRuleKind::ArbOpportunity { .. } => "arb_opportunity",
+        AlertRuleKind::ExchangeDown { .. } => "exchange_down",
+        AlertRuleKind::CrossedBook { .. } => "crossed_book",
+        AlertRuleKind::StaleFeed { .. } => "stale_feed",
+        AlertRuleKind::Maintenance { .. } => "maintenance",
+        AlertRuleKind::Basis { .. } => "basis",
+        AlertRuleKind::Funding { .. } => "funding",
+        AlertRuleKind::IndexDeviation { .. } => "index_deviation",
+        AlertRuleKind::BridgedOpportunity { .. } => "bridged_opportunity",
+    }
+}
+
+// millis since the last update of the exchange book, if known
+fn book_age(summary: &Summary, exchange: &str, now: u128) -> Option<u64> {
+    let ts = summary.timestamp.get(exchange)?.parse::<u128>().ok()?;
+    Some(now.saturating_sub(ts) as u64)
+}
+
+fn excerpt(levels: &[Level], exchange: &str) -> Vec<Level> {
+    levels
+        .iter()
+        .filter(|l| l.exchange == exchange)
+        .take(EXCERPT_LEVELS)
+        .cloned()
+        .collect()
+}
+
+// Evaluates the alert rules against summaries and the status registry.
+// Cooldowns are kept per (rule, subject) for the lifetime of the evaluator.
+// Opportunity rules fire on the new / update events of the tracker, and a
+// closed event is only sent for a subject the rule alerted on.
+pub struct Evaluator {
+    rules: Vec<AlertRule>,
+    detector: DetectorSetting,
+    tracker: Tracker,
+    // (rule index, subject) -> unix millis of the last fired alert
+    cooldown: HashMap<(usize, String), u128>,
+}
+
+impl Evaluator {
+    pub fn new(rules: Vec<AlertRule>, detector: DetectorSetting, pair: &str) -> Evaluator {
+        Evaluator {
+            rules,
+            tracker: Tracker::new(detector.clone(), pair),
+            detector,
+            cooldown: HashMap::new(),
+        }
+    }
+
+    pub fn evaluate(
+        &mut self,
+        summary: &Summary,
+        status: &HashMap<String, ExchangeStatus>,
+        now: u128,
+    ) -> Vec<Alert> {
+        let mut detected = opportunity::detect(summary, &self.detector, status, now);
+        opportunity::portfolio::annotate(&mut detected);
+        let events = self.tracker.update(&detected, now);
+        let mut result = vec![];
+        for index in 0..self.rules.len() {
+            let rule = &self.rules[index];
+            let cooldown_ms = rule.cooldown_secs as u128 * 1000;
+            for alert in Self::check(&rule.kind, &events, summary, status, now) {
+                let key = (index, alert.subject.clone());
+                if alert.details["state"] == "closed" {
+                    if self.cooldown.remove(&key).is_some() {
+                        result.push(alert);
+                    }
+                    continue;
+                }
+                if let Some(last) = self.cooldown.get(&key) {
+                    if now < last + cooldown_ms {
+                        continue;
+                    }
+                }
+                self.cooldown.insert(key, now);
+                result.push(alert);
+            }
+        }
+        result
+    }
+
+    fn check(
+        kind: &AlertRuleKind,
+        events: &[Event],
+        summary: &Summary,
+        status: &HashMap<String, ExchangeStatus>,
+        now: u128,
+    ) -> Vec<Alert> {
+        let rule = rule_name(kind);
+        let mut result = vec![];
+        match kind {
+            AlertRuleKind::ArbOpportunity {
+                min_profit_bps,
+                min_quantity,
+                size,
+                min_confidence,
+            } => {
+                for e in events {
+                    let o = &e.opportunity;
+                    let closed = e.state == State::Closed;
+                    if !closed && o.confidence < *min_confidence {
+                        continue;
+                    }
+                    // the size bucket when the rule names one, else top of book
+                    let (profit_bps, quantity, buy_price, sell_price) = match size {
+                        Some(size) => match o.sizes.iter().find(|s| s.size == *size) {
+                            Some(s) => (s.profit_bps, &s.quantity, &s.buy_vwap, &s.sell_vwap),
+                            None if closed => {
+                                (o.profit_bps, &o.quantity, &o.buy_price, &o.sell_price)
+                            }
+                            None => continue,
+                        },
+                        None => (o.profit_bps, &o.quantity, &o.buy_price, &o.sell_price),
+                    };
+                    let quantity = quantity.to_f64().unwrap_or(0.);
+                    // a close is judged by the evaluator against past alerts
+                    if !closed && (profit_bps < *min_profit_bps || quantity < *min_quantity) {
+                        continue;
+                    }
+                    let (buy, sell) = (&o.buy_exchange, &o.sell_exchange);
+                    let sizes: Vec<Value> = o
+                        .sizes
+                        .iter()
+                        .map(|s| {
+                            json!({
+                                "size": s.size,
+                                "notional": s.notional.to_string(),
+                                "quantity": s.quantity.to_f64(),
+                                "buy_vwap": s.buy_vwap.to_string(),
+                                "sell_vwap": s.sell_vwap.to_string(),
+                                "profit_bps": s.profit_bps,
+                                "partial": s.partial,
+                            })
+                        })
+                        .collect();
+                    result.push(Alert {
+                        rule,
+                        subject: o.route(),
+                        message: if closed {
+                            format!(
+                                "buy {}, sell {} closed after {} ms, last {:.2} bps",
+                                buy,
+                                sell,
+                                now.saturating_sub(e.opened),
+                                profit_bps
+                            )
+                        } else {
+                            format!(
+                                "buy {} at {}, sell {} at {}: {:.2} bps",
+                                buy, buy_price, sell, sell_price, profit_bps
+                            )
+                        },
+                        timestamp: now,
+                        details: json!({
+                            "state": e.state,
+                            "opened": e.opened as u64,
+                            "buy_exchange": buy,
+                            "sell_exchange": sell,
+                            "buy_price": buy_price.to_string(),
+                            "sell_price": sell_price.to_string(),
+                            "profit_bps": profit_bps,
+                            "quantity": quantity,
+                            "size": size,
+                            "sizes": sizes,
+                            "max_size": o.max_size,
+                            "confidence": o.confidence,
+                            "net": o.net,
+                            "buy_age_ms": book_age(summary, buy, now),
+                            "sell_age_ms": book_age(summary, sell, now),
+                        }),
+                        bids: excerpt(&summary.bids, sell),
+                        asks: excerpt(&summary.asks, buy),
+                    });
+                }
+            }
+            AlertRuleKind::BridgedOpportunity {
+                buy,
+                sell,
+                bridge,
+                bridge_cost_bps,
+                min_profit_bps,
+                min_quantity,
+            } => {
+                let o = match bridge::detect(summary, buy, sell, bridge, *bridge_cost_bps, now) {
+                    Some(o) => o,
+                    None => return result,
+                };
+                let quantity = o.quantity.to_f64().unwrap_or(0.);
+                if o.profit_bps < *min_profit_bps || quantity < *min_quantity {
+                    return result;
+                }
+                let (buy_exchange, sell_exchange) = (buy.split(':').next(), sell.split(':').next());
+                result.push(Alert {
+                    rule,
+                    subject: format!("{}->{}", buy, sell),
+                    message: format!(
+                        "buy {} at {}, sell {} at {} ({} via {} {}, {} ms old): {:.2} bps",
+                        buy,
+                        o.buy_price,
+                        sell,
+                        o.sell_price,
+                        o.converted_price.round(8).normalized(),
+                        bridge,
+                        o.rate.value.round(8).normalized(),
+                        o.rate_age_ms,
+                        o.profit_bps
+                    ),
+                    timestamp: now,
+                    details: json!({
+                        "buy": buy,
+                        "sell": sell,
+                        "buy_price": o.buy_price.to_string(),
+                        "sell_price": o.sell_price.to_string(),
+                        "converted_price": o.converted_price.round(8).normalized().to_string(),
+                        "bridge": bridge,
+                        "rate": o.rate.value.round(8).normalized().to_string(),
+                        "rate_source": o.rate.source,
+                        "rate_age_ms": o.rate_age_ms,
+                        "bridge_cost_bps": bridge_cost_bps,
+                        "profit_bps": o.profit_bps,
+                        "quantity": quantity,
+                    }),
+                    bids: excerpt(&summary.bids, sell_exchange.unwrap_or(sell)),
+                    asks: excerpt(&summary.asks, buy_exchange.unwrap_or(buy)),
+                });
+            }
+            AlertRuleKind::ExchangeDown { after_secs } => {
+                for (exchange, st) in status.iter() {
+                    let down_ms = now.saturating_sub(st.since);
+                    if st.connected || down_ms < *after_secs as u128 * 1000 {
+                        continue;
+                    }
+                    result.push(Alert {
+                        rule,
+                        subject: exchange.clone(),
+                        message: format!("{} down for {} secs", exchange, down_ms / 1000),
+                        timestamp: now,
+                        details: json!({
+                            "exchange": exchange,
+                            "down_secs": (down_ms / 1000) as u64,
+                            "reconnects": st.reconnects,
+                        }),
+                        bids: vec![],
+                        asks: vec![],
+                    });
+                }
+            }
+            AlertRuleKind::CrossedBook { exchange } => {
+                let bid = best_levels(&summary.bids).remove(exchange.as_str());
+                let ask = best_levels(&summary.asks).remove(exchange.as_str());
+                if let (Some((bid, _)), Some((ask, _))) = (bid, ask) {
+                    if bid >= ask {
+                        result.push(Alert {
+                            rule,
+                            subject: exchange.clone(),
+                            message: format!("{} crossed: bid {} >= ask {}", exchange, bid, ask),
+                            timestamp: now,
+                            details: json!({
+                                "exchange": exchange,
+                                "best_bid": bid.to_string(),
+                                "best_ask": ask.to_string(),
+                            }),
+                            bids: excerpt(&summary.bids, exchange),
+                            asks: excerpt(&summary.asks, exchange),
+                        });
+                    }
+                }
+            }
+            AlertRuleKind::StaleFeed { after_secs } => {
+                for (exchange, ts) in summary.timestamp.iter() {
+                    let ts = match ts.parse::<u128>() {
+                        Ok(ts) => ts,
+                        Err(_) => continue,
+                    };
+                    let age_ms = now.saturating_sub(ts);
+                    if age_ms < *after_secs as u128 * 1000 {
+                        continue;
+                    }
+                    result.push(Alert {
+                        rule,
+                        subject: exchange.clone(),
+                        message: format!("{} no update for {} ms", exchange, age_ms),
+                        timestamp: now,
+                        details: json!({
+                            "exchange": exchange,
+                            "age_ms": age_ms as u64,
+                        }),
+                        bids: excerpt(&summary.bids, exchange),
+                        asks: excerpt(&summary.asks, exchange),
+                    });
+                }
+            }
+            AlertRuleKind::Maintenance { after_secs } => {
+                for (exchange, st) in status.iter() {
+                    let Some(maintenance) = st.maintenance.as_ref() else {
+                        continue;
+                    };
+                    let for_ms = now.saturating_sub(maintenance.since);
+                    if for_ms < *after_secs as u128 * 1000 {
+                        continue;
+                    }
+                    result.push(Alert {
+                        rule,
+                        subject: exchange.clone(),
+                        message: format!(
+                            "{} under maintenance ({}) for {} secs",
+                            exchange,
+                            maintenance.status,
+                            for_ms / 1000
+                        ),
+                        timestamp: now,
+                        details: json!({
+                            "exchange": exchange,
+                            "status": maintenance.status,
+                            "since": maintenance.since as u64,
+                        }),
+                        bids: vec![],
+                        asks: vec![],
+                    });
+                }
+            }
+            AlertRuleKind::Basis { min_abs_bps } => {
+                for (exchange, d) in summary.derivatives.iter() {
+                    let basis_bps = match d.basis_bps {
+                        Some(b) if b.abs() >= *min_abs_bps => b,
+                        _ => continue,
+                    };
+                    result.push(Alert {
+                        rule,
+                        subject: exchange.clone(),
+                        message: format!("{} basis {:.2} bps", exchange, basis_bps),
+                        timestamp: now,
+                        details: json!({
+                            "exchange": exchange,
+                            "basis_bps": basis_bps,
+                            "mark_price": d.mark_price,
+                            "index_price": d.index_price,
+                        }),
+                        bids: vec![],
+                        asks: vec![],
+                    });
+                }
+            }
+            AlertRuleKind::Funding { min_abs_rate } => {
+                for (exchange, d) in summary.derivatives.iter() {
+                    let rate = match d.funding_rate.parse::<f64>() {
+                        Ok(r) if r.abs() >= *min_abs_rate => r,
+                        _ => continue,
+                    };
+                    result.push(Alert {
+                        rule,
+                        subject: exchange.clone(),
+                        message: format!("{} funding rate {}", exchange, d.funding_rate),
+                        timestamp: now,
+                        details: json!({
+                            "exchange": exchange,
+                            "funding_rate": rate,
+                            "next_funding_time": d.next_funding_time,
+                        }),
+                        bids: vec![],
+                        asks: vec![],
+                    });
+                }
+            }
+            AlertRuleKind::IndexDeviation { min_abs_bps } => {
+                for (source, bps) in summary.stats.mid_vs_index_bps.iter() {
+                    if bps.abs() < *min_abs_bps {
+                        continue;
+                    }
+                    result.push(Alert {
+                        rule,
+                        subject: source.clone(),
+                        message: format!("mid {:.2} bps off the {} index", bps, source),
+                        timestamp: now,
+                        details: json!({
+                            "source": source,
+                            "mid_vs_index_bps": bps,
+                            "index_price": summary.index_prices.get(source),
+                        }),
+                        bids: vec![],
+                        asks: vec![],
+                    });
+                }
+            }
+        }
+        result
+    }
+}
+
+// run `f` until it succeeds, at most `retries` more times after the first failure
+pub async fn retry<F, Fut>(name: &str, retries: u32, mut f: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut backoff = RETRY_BACKOFF_MS;
+    let mut attempt = 0;
+    loop {
+        match f().await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < retries => {
+                warn!("{} delivery failed: {}, retry in {}ms", name, e, backoff);
+                sleep(Duration::from_millis(backoff)).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// alert task: evaluates every published summary, and re-evaluates the latest one
+// every second so that time based rules fire even if all feeds went silent.
+pub async fn run(
+    config: AlertConfig,
+    detector: DetectorSetting,
+    pair: String,
+    mut rx: broadcast::Receiver<Arc<Summary>>,
+) {
+    let mut evaluator = Evaluator::new(config.rules, detector, &pair);
+    let webhook = config.webhook.map(webhook::Webhook::new);
+    let telegram = config.telegram.map(telegram::spawn);
+    let mut ticker = interval(Duration::from_secs(1));
+    let mut last: Option<Arc<Summary>> = None;
+    loop {
+        tokio::select! {
+            result = rx.recv() => match result {
+                Ok(summary) => last = Some(summary),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("alert evaluator lagged {} summaries", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            _ = ticker.tick() => {}
+        }
+        let summary = match last.as_ref() {
+            Some(summary) => summary,
+            None => continue,
+        };
+        for alert in evaluator.evaluate(summary, &status::snapshot(), get_unixtime()) {
+            info!("alert {}: {}", alert.rule, alert.message);
+            if let Some(webhook) = webhook.clone() {
+                let alert = alert.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = webhook.send(&alert).await {
+                        error!("webhook delivery dropped: {}", e);
+                    }
+                });
+            }
+            if let Some(telegram) = telegram.as_ref() {
+                if let Err(e) = telegram.send(alert) {
+                    error!("telegram queue closed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::orderbook::DerivativesSummary;
+    use crate::status::Maintenance;
+    use std::collections::BTreeMap;
+
+    pub(crate) fn level(exchange: &str, price: &str, amount: &str) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price: price.to_string(),
+            amount: amount.to_string(),
+            age_ms: None,
+        }
+    }
+
+    pub(crate) fn summary(bids: Vec<Level>, asks: Vec<Level>, now: u128) -> Summary {
+        let mut timestamp = BTreeMap::new();
+        for l in bids.iter().chain(asks.iter()) {
+            timestamp.insert(l.exchange.clone(), now.to_string());
+        }
+        Summary {
+            schema_version: crate::schema::SCHEMA_VERSION,
+            seq: 0,
+            spread: "0".to_string(),
+            crossed: false,
+            bids,
+            asks,
+            timestamp,
+            volume: BTreeMap::new(),
+            last_price: BTreeMap::new(),
+            depth: BTreeMap::new(),
+            index_prices: BTreeMap::new(),
+            consolidated: None,
+            derivatives: BTreeMap::new(),
+            opportunities: vec![],
+            stats: Default::default(),
+        }
+    }
+
+    fn arb_rule(cooldown_secs: u64) -> AlertRule {
+        AlertRule {
+            kind: AlertRuleKind::ArbOpportunity {
+                min_profit_bps: 10.,
+                min_quantity: 0.5,
+                size: None,
+                min_confidence: 0.,
+            },
+            cooldown_secs,
+        }
+    }
+
+    #[test]
+    fn test_arb_opportunity() {
+        let mut evaluator =
+            Evaluator::new(vec![arb_rule(60)], DetectorSetting::default(), "BTC-AUD");
+        let now = 1_000_000;
+        // kraken bid 101 vs btcmarkets ask 100: 100 bps
+        let s = summary(
+            vec![level("kraken", "101", "1"), level("btcmarkets", "99", "1")],
+            vec![level("btcmarkets", "100", "2"), level("kraken", "102", "1")],
+            now,
+        );
+        let alerts = evaluator.evaluate(&s, &HashMap::new(), now);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].subject, "btcmarkets->kraken");
+        assert_eq!(alerts[0].details["state"], "new");
+        assert_eq!(alerts[0].details["profit_bps"], 100.);
+        assert_eq!(alerts[0].details["quantity"], 1.);
+        assert_eq!(alerts[0].details["buy_age_ms"], 0);
+        assert_eq!(alerts[0].bids, vec![level("kraken", "101", "1")]);
+        assert_eq!(alerts[0].asks, vec![level("btcmarkets", "100", "2")]);
+
+        // same summary in cooldown
+        assert!(evaluator
+            .evaluate(&s, &HashMap::new(), now + 1000)
+            .is_empty());
+        // cooldown expired
+        let alerts = evaluator.evaluate(&s, &HashMap::new(), now + 60_000);
+        assert_eq!(alerts[0].details["state"], "update");
+
+        // gone: closed once, even in cooldown
+        let flat = summary(vec![], vec![], now);
+        let alerts = evaluator.evaluate(&flat, &HashMap::new(), now + 61_000);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].details["state"], "closed");
+        assert_eq!(alerts[0].details["opened"], now as u64);
+        assert!(evaluator
+            .evaluate(&flat, &HashMap::new(), now + 62_000)
+            .is_empty());
+
+        // too thin to trade
+        let thin = summary(
+            vec![level("kraken", "101", "0.1")],
+            vec![level("btcmarkets", "100", "2")],
+            now,
+        );
+        let mut evaluator =
+            Evaluator::new(vec![arb_rule(60)], DetectorSetting::default(), "BTC-AUD");
+        assert!(evaluator.evaluate(&thin, &HashMap::new(), now).is_empty());
+    }
+
+    #[test]
+    fn test_arb_opportunity_size() {
+        let now = 1_000_000;
+        let rule = |size| AlertRule {
+            kind: AlertRuleKind::ArbOpportunity {
+                min_profit_bps: 50.,
+                min_quantity: 0.,
+                size: Some(size),
+                min_confidence: 0.,
+            },
+            cooldown_secs: 60,
+        };
+        let detector = DetectorSetting {
+            sizes: vec![100., 1000.],
+            min_profit_bps: 50.,
+            ..DetectorSetting::default()
+        };
+        let mut evaluator = Evaluator::new(vec![rule(100.), rule(1000.)], detector, "BTC-AUD");
+        // 100 bps on top, but only 1 unit before the ask steps up
+        let s = summary(
+            vec![level("kraken", "101", "20")],
+            vec![
+                level("btcmarkets", "100", "1"),
+                level("btcmarkets", "101", "20"),
+            ],
+            now,
+        );
+        let alerts = evaluator.evaluate(&s, &HashMap::new(), now);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].details["size"], 100.);
+        assert_eq!(alerts[0].details["profit_bps"], 100.);
+        assert_eq!(alerts[0].details["max_size"], 100.);
+        assert_eq!(alerts[0].details["sizes"][1]["profit_bps"], 10.);
+        assert_eq!(alerts[0].details["sizes"][1]["partial"], false);
+    }
+
+    #[test]
+    fn test_bridged_opportunity() {
+        bridge::set("alert_audusd", "0.67".parse().unwrap(), "static", 1000);
+        let rule = |bridge: &str| AlertRule {
+            kind: AlertRuleKind::BridgedOpportunity {
+                buy: "binance:BTC-USDT".to_string(),
+                sell: "btcmarkets:BTC-AUD".to_string(),
+                bridge: bridge.to_string(),
+                bridge_cost_bps: 20.,
+                min_profit_bps: 50.,
+                min_quantity: 0.,
+            },
+            cooldown_secs: 60,
+        };
+        let mut evaluator = Evaluator::new(
+            vec![rule("rate:alert_audusd"), rule("rate:unknown")],
+            DetectorSetting::default(),
+            "BTC-AUD",
+        );
+        let s = summary(
+            vec![level("btcmarkets", "150000", "0.5")],
+            vec![level("binance", "99500", "1")],
+            3000,
+        );
+        let alerts = evaluator.evaluate(&s, &HashMap::new(), 3000);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].subject, "binance:BTC-USDT->btcmarkets:BTC-AUD");
+        assert_eq!(alerts[0].details["converted_price"], "100500");
+        assert_eq!(alerts[0].details["rate"], "0.67");
+        assert_eq!(alerts[0].details["rate_age_ms"], 2000);
+        assert_eq!(alerts[0].asks, vec![level("binance", "99500", "1")]);
+    }
+
+    #[test]
+    fn test_status_rules() {
+        let now = 1_000_000;
+        let mut evaluator = Evaluator::new(
+            vec![
+                AlertRule {
+                    kind: AlertRuleKind::ExchangeDown { after_secs: 10 },
+                    cooldown_secs: 60,
+                },
+                AlertRule {
+                    kind: AlertRuleKind::StaleFeed { after_secs: 5 },
+                    cooldown_secs: 60,
+                },
+                AlertRule {
+                    kind: AlertRuleKind::CrossedBook {
+                        exchange: "kraken".to_string(),
+                    },
+                    cooldown_secs: 60,
+                },
+                AlertRule {
+                    kind: AlertRuleKind::Maintenance { after_secs: 5 },
+                    cooldown_secs: 60,
+                },
+            ],
+            DetectorSetting::default(),
+            "BTC-AUD",
+        );
+        let mut s = summary(
+            vec![level("kraken", "101", "1")],
+            vec![level("kraken", "100", "1"), level("coinjar", "103", "1")],
+            now,
+        );
+        s.timestamp
+            .insert("coinjar".to_string(), (now - 6000).to_string());
+        let status = HashMap::from([
+            (
+                "bitstamp".to_string(),
+                ExchangeStatus {
+                    connected: false,
+                    since: now - 11_000,
+                    reconnects: 3,
+                    ..Default::default()
+                },
+            ),
+            (
+                "kraken".to_string(),
+                ExchangeStatus {
+                    connected: true,
+                    since: now - 11_000,
+                    reconnects: 0,
+                    maintenance: Some(Maintenance {
+                        status: "cancel_only".to_string(),
+                        since: now - 6000,
+                    }),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let alerts = evaluator.evaluate(&s, &status, now);
+        let fired: Vec<(&str, &str)> = alerts
+            .iter()
+            .map(|a| (a.rule, a.subject.as_str()))
+            .collect();
+        assert_eq!(
+            fired,
+            vec![
+                ("exchange_down", "bitstamp"),
+                ("stale_feed", "coinjar"),
+                ("crossed_book", "kraken"),
+                ("maintenance", "kraken"),
+            ]
+        );
+        assert_eq!(alerts[3].details["status"], "cancel_only");
+        assert!(evaluator.evaluate(&s, &status, now + 1000).is_empty());
+    }
+
+    #[test]
+    fn test_derivatives_rules() {
+        let mut evaluator = Evaluator::new(
+            vec![
+                AlertRule {
+                    kind: AlertRuleKind::Basis { min_abs_bps: 30. },
+                    cooldown_secs: 60,
+                },
+                AlertRule {
+                    kind: AlertRuleKind::Funding {
+                        min_abs_rate: 0.0005,
+                    },
+                    cooldown_secs: 60,
+                },
+            ],
+            DetectorSetting::default(),
+            "BTC-AUD",
+        );
+        let mut s = summary(vec![], vec![], 0);
+        let mut perp = DerivativesSummary {
+            mark_price: "100.5".to_string(),
+            index_price: "100.1".to_string(),
+            funding_rate: "0.0001".to_string(),
+            next_funding_time: 0,
+            basis_bps: Some(-50.),
+        };
+        s.derivatives
+            .insert("binance_futures".to_string(), perp.clone());
+        perp.basis_bps = Some(10.);
+        perp.funding_rate = "-0.00075".to_string();
+        s.derivatives.insert("other_futures".to_string(), perp);
+        let alerts = evaluator.evaluate(&s, &HashMap::new(), 0);
+        let fired: Vec<(&str, &str)> = alerts
+            .iter()
+            .map(|a| (a.rule, a.subject.as_str()))
+            .collect();
+        assert_eq!(
+            fired,
+            vec![("basis", "binance_futures"), ("funding", "other_futures")]
+        );
+        assert_eq!(alerts[1].details["funding_rate"], -0.00075);
+    }
+
+    #[test]
+    fn test_index_deviation_rule() {
+        let rule = AlertRule {
+            kind: AlertRuleKind::IndexDeviation { min_abs_bps: 25. },
+            cooldown_secs: 60,
+        };
+        let mut evaluator = Evaluator::new(vec![rule], DetectorSetting::default(), "BTC-USD");
+        let mut s = summary(vec![], vec![], 0);
+        s.index_prices.insert("okx".to_string(), "99.5".to_string());
+        s.stats.mid_vs_index_bps.insert("okx".to_string(), 50.25);
+        s.stats.mid_vs_index_bps.insert("kraken".to_string(), -10.);
+        let alerts = evaluator.evaluate(&s, &HashMap::new(), 0);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].subject, "okx");
+        assert_eq!(alerts[0].details["index_price"], "99.5");
+    }
+}
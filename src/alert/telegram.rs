@@ -0,0 +1,258 @@
+use super::{retry, Alert};
+use crate::config::TelegramSetting;
+use anyhow::Result;
+use log::error;
+use serde_json::json;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{sleep_until, timeout_at, Duration, Instant};
+
+// characters that have to be escaped anywhere in a MarkdownV2 message
+const RESERVED: &str = "_*[]()~`>#+-=|{}.!\\";
+
+pub fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if RESERVED.contains(c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+fn detail(alert: &Alert, key: &str) -> String {
+    match &alert.details[key] {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => "?".to_string(),
+        v => v.to_string(),
+    }
+}
+
+// compact human-readable form of a single alert
+pub fn render(alert: &Alert) -> String {
+    let mut lines = vec![format!(
+        "*{}* {}",
+        escape(alert.rule),
+        escape(&alert.subject)
+    )];
+    if alert.rule == "arb_opportunity" {
+        let profit_bps = alert.details["profit_bps"].as_f64().unwrap_or(0.);
+        lines.push(escape(&format!(
+            "buy {} @ {}, sell {} @ {}",
+            detail(alert, "buy_exchange"),
+            detail(alert, "buy_price"),
+            detail(alert, "sell_exchange"),
+            detail(alert, "sell_price"),
+        )));
+        lines.push(escape(&format!(
+            "profit {:.2} bps, size {}",
+            profit_bps,
+            detail(alert, "quantity"),
+        )));
+        lines.push(escape(&format!(
+            "book age {}ms / {}ms",
+            detail(alert, "buy_age_ms"),
+            detail(alert, "sell_age_ms"),
+        )));
+    } else {
+        lines.push(escape(&alert.message));
+    }
+    lines.join("\n")
+}
+
+// one message listing every alert of a burst
+pub fn digest(alerts: &[Alert]) -> String {
+    let mut lines = vec![format!("*{} alerts*", alerts.len())];
+    for alert in alerts {
+        lines.push(escape(&format!("- {} {}", alert.rule, alert.subject)));
+    }
+    lines.join("\n")
+}
+
+// turn the alerts collected in one window into the messages to send
+pub fn collapse(alerts: &[Alert], threshold: usize) -> Vec<String> {
+    if alerts.len() > threshold {
+        vec![digest(alerts)]
+    } else {
+        alerts.iter().map(render).collect()
+    }
+}
+
+pub struct Telegram {
+    client: reqwest::Client,
+    setting: TelegramSetting,
+}
+
+impl Telegram {
+    pub fn new(setting: TelegramSetting) -> Telegram {
+        Telegram {
+            client: reqwest::Client::new(),
+            setting,
+        }
+    }
+
+    async fn post(&self, text: &str) -> Result<()> {
+        let url = format!(
+            "{}/bot{}/sendMessage",
+            self.setting.api_url, self.setting.bot_token
+        );
+        self.client
+            .post(url)
+            .json(&json!({
+                "chat_id": self.setting.chat_id,
+                "text": text,
+                "parse_mode": "MarkdownV2",
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    pub async fn send(&self, text: &str) -> Result<()> {
+        retry("telegram", self.setting.max_retries, || self.post(text)).await
+    }
+}
+
+// Alerts are buffered for one digest window and then sent, keeping at least
+// min_interval_ms between two messages to respect the per-chat rate limit.
+pub(crate) async fn deliver(telegram: Telegram, mut rx: UnboundedReceiver<Alert>) {
+    let window = Duration::from_millis(telegram.setting.digest_window_ms);
+    let gap = Duration::from_millis(telegram.setting.min_interval_ms);
+    let mut last_sent: Option<Instant> = None;
+    while let Some(first) = rx.recv().await {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + window;
+        while let Ok(Some(alert)) = timeout_at(deadline, rx.recv()).await {
+            batch.push(alert);
+        }
+        for text in collapse(&batch, telegram.setting.digest_threshold) {
+            if let Some(t) = last_sent {
+                sleep_until(t + gap).await;
+            }
+            if let Err(e) = telegram.send(&text).await {
+                error!("telegram delivery dropped: {}", e);
+            }
+            last_sent = Some(Instant::now());
+        }
+    }
+}
+
+// start the delivery task. Alerts pushed to the returned sender get delivered.
+pub fn spawn(setting: TelegramSetting) -> UnboundedSender<Alert> {
+    let (tx, rx) = unbounded_channel();
+    tokio::spawn(deliver(Telegram::new(setting), rx));
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+    use crate::alert::Evaluator;
+    use crate::config::{AlertRule, AlertRuleKind, DetectorSetting};
+    use std::collections::HashMap;
+
+    fn stale(exchange: &str) -> Alert {
+        Alert {
+            rule: "stale_feed",
+            subject: exchange.to_string(),
+            message: format!("{} no update for 6000 ms", exchange),
+            timestamp: 0,
+            details: serde_json::Value::Null,
+            bids: vec![],
+            asks: vec![],
+        }
+    }
+
+    fn setting(api_url: String) -> TelegramSetting {
+        TelegramSetting {
+            bot_token: "123:abc".to_string(),
+            chat_id: "42".to_string(),
+            api_url,
+            max_retries: 0,
+            digest_threshold: 2,
+            digest_window_ms: 100,
+            min_interval_ms: 10,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(escape("1.05"), "1\\.05");
+        assert_eq!(escape("a_b-c(d)!"), "a\\_b\\-c\\(d\\)\\!");
+
+        let now = 1_000_000;
+        let mut s = summary(
+            vec![level("kraken", "1.06", "2.5")],
+            vec![level("coinjar", "1.05", "3")],
+            now,
+        );
+        s.timestamp
+            .insert("kraken".to_string(), (now - 120).to_string());
+        let mut evaluator = Evaluator::new(
+            vec![AlertRule {
+                kind: AlertRuleKind::ArbOpportunity {
+                    min_profit_bps: 10.,
+                    min_quantity: 0.,
+                    size: None,
+                    min_confidence: 0.,
+                },
+                cooldown_secs: 60,
+            }],
+            DetectorSetting::default(),
+            "BTC-AUD",
+        );
+        let alerts = evaluator.evaluate(&s, &HashMap::new(), now);
+        assert_eq!(
+            render(&alerts[0]),
+            "*arb\\_opportunity* coinjar\\-\\>kraken\n\
+             buy coinjar @ 1\\.05, sell kraken @ 1\\.06\n\
+             profit 95\\.24 bps, size 2\\.5\n\
+             book age 0ms / 120ms"
+        );
+        assert_eq!(
+            render(&stale("btcmarkets")),
+            "*stale\\_feed* btcmarkets\nbtcmarkets no update for 6000 ms"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_digest() {
+        let mut server = mockito::Server::new_async().await;
+        let digest = server
+            .mock("POST", "/bot123:abc/sendMessage")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "chat_id": "42",
+                "parse_mode": "MarkdownV2",
+                "text": "*3 alerts*\n\\- stale\\_feed a\n\\- stale\\_feed b\n\\- stale\\_feed c",
+            })))
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+        let (tx, rx) = unbounded_channel();
+        for exchange in ["a", "b", "c"] {
+            tx.send(stale(exchange)).unwrap();
+        }
+        drop(tx);
+        deliver(Telegram::new(setting(server.url())), rx).await;
+        digest.assert_async().await;
+
+        // below the threshold every alert is its own message
+        let single = server
+            .mock("POST", "/bot123:abc/sendMessage")
+            .match_body(mockito::Matcher::Regex("stale\\\\\\\\_feed".to_string()))
+            .with_status(200)
+            .expect(2)
+            .create_async()
+            .await;
+        let (tx, rx) = unbounded_channel();
+        for exchange in ["a", "b"] {
+            tx.send(stale(exchange)).unwrap();
+        }
+        drop(tx);
+        deliver(Telegram::new(setting(server.url())), rx).await;
+        single.assert_async().await;
+    }
+}
@@ -0,0 +1,138 @@
+use super::{retry, Alert};
+use crate::config::WebhookSetting;
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// POSTs alerts as json to the configured url
+#[derive(Clone)]
+pub struct Webhook {
+    client: reqwest::Client,
+    setting: WebhookSetting,
+}
+
+// hex encoded HMAC-SHA256 of the request body
+pub fn sign(secret: &str, body: &[u8]) -> Result<String> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|e| anyhow!("{:?}", e))?;
+    mac.update(body);
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+impl Webhook {
+    pub fn new(setting: WebhookSetting) -> Webhook {
+        Webhook {
+            client: reqwest::Client::new(),
+            setting,
+        }
+    }
+
+    async fn post(&self, body: &[u8]) -> Result<()> {
+        let mut request = self
+            .client
+            .post(&self.setting.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_vec());
+        if let Some(secret) = &self.setting.secret {
+            request = request.header("X-Signature", format!("sha256={}", sign(secret, body)?));
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    pub async fn send(&self, alert: &Alert) -> Result<()> {
+        let body = serde_json::to_vec(alert)?;
+        retry("webhook", self.setting.max_retries, || self.post(&body)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+    use crate::alert::Evaluator;
+    use crate::config::{AlertRule, AlertRuleKind, DetectorSetting};
+    use std::collections::HashMap;
+
+    fn setting(url: String, max_retries: u32) -> WebhookSetting {
+        WebhookSetting {
+            url,
+            secret: Some("secret".to_string()),
+            max_retries,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fire_and_cooldown() {
+        let mut server = mockito::Server::new_async().await;
+        let now = 1_000_000;
+        let s = summary(
+            vec![level("kraken", "101", "1")],
+            vec![level("btcmarkets", "100", "1")],
+            now,
+        );
+        let mut evaluator = Evaluator::new(
+            vec![AlertRule {
+                kind: AlertRuleKind::ArbOpportunity {
+                    min_profit_bps: 50.,
+                    min_quantity: 0.,
+                    size: None,
+                    min_confidence: 0.,
+                },
+                cooldown_secs: 60,
+            }],
+            DetectorSetting::default(),
+            "BTC-AUD",
+        );
+        let alerts = evaluator.evaluate(&s, &HashMap::new(), now);
+        let body = serde_json::to_vec(&alerts[0]).unwrap();
+        let mock = server
+            .mock("POST", "/alert")
+            .match_header(
+                "X-Signature",
+                format!("sha256={}", sign("secret", &body).unwrap()).as_str(),
+            )
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "rule": "arb_opportunity",
+                "subject": "btcmarkets->kraken",
+            })))
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+        let webhook = Webhook::new(setting(format!("{}/alert", server.url()), 0));
+        for alert in alerts {
+            webhook.send(&alert).await.unwrap();
+        }
+        // the same opportunity within the cooldown never reaches the target
+        for t in [now + 1000, now + 2000] {
+            for alert in evaluator.evaluate(&s, &HashMap::new(), t) {
+                webhook.send(&alert).await.unwrap();
+            }
+        }
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_bounded_retry() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/alert")
+            .with_status(500)
+            .expect(3)
+            .create_async()
+            .await;
+        let webhook = Webhook::new(setting(format!("{}/alert", server.url()), 2));
+        let alert = Alert {
+            rule: "stale_feed",
+            subject: "kraken".to_string(),
+            message: "".to_string(),
+            timestamp: 0,
+            details: serde_json::Value::Null,
+            bids: vec![],
+            asks: vec![],
+        };
+        assert!(webhook.send(&alert).await.is_err());
+        mock.assert_async().await;
+    }
+}
@@ -0,0 +1,168 @@
+// When the amount of every level of every book last changed. Some venues
+// re-send their whole ladder every update while others only send what
+// changed, so a level being there says nothing about it being fresh. Each
+// book is diffed against the levels kept for its exchange: an unchanged
+// level keeps its time, a full snapshot re-sending it included. Bounded by
+// the depth of the books.
+use crate::orderbook::{AggregatedOrderbook, LevelAges, Orderbook};
+use bigdecimal::BigDecimal;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+// price -> (amount, unix millis it was set at)
+type Since = BTreeMap<BigDecimal, (BigDecimal, u128)>;
+
+#[derive(Default)]
+struct BookAges {
+    bid: Since,
+    ask: Since,
+}
+
+// the levels gone from the book are dropped, the new or resized ones
+// stamped with now
+fn diff(since: &mut Since, levels: &BTreeMap<BigDecimal, BigDecimal>, now: u128) {
+    since.retain(|price, _| levels.contains_key(price));
+    for (price, amount) in levels.iter() {
+        match since.get_mut(price) {
+            Some((kept, _)) if kept == amount => {}
+            Some(entry) => *entry = (amount.clone(), now),
+            None => {
+                since.insert(price.clone(), (amount.clone(), now));
+            }
+        }
+    }
+}
+
+fn ages(since: &Since, now: u128) -> BTreeMap<BigDecimal, u64> {
+    since
+        .iter()
+        .map(|(price, (_, at))| (price.clone(), now.saturating_sub(*at) as u64))
+        .collect()
+}
+
+// exchange -> its levels, None when level_ages is off
+static AGES: Lazy<Mutex<Option<HashMap<String, BookAges>>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn init() {
+    *AGES.lock().unwrap() = Some(HashMap::new());
+}
+
+// every book of the ingest stage
+pub fn observe(orderbook: &Orderbook, now: u128) {
+    let mut tmp = AGES.lock().unwrap();
+    if let Some(books) = tmp.as_mut() {
+        let book = books.entry(orderbook.name().to_string()).or_default();
+        diff(&mut book.bid, &orderbook.bid, now);
+        diff(&mut book.ask, &orderbook.ask, now);
+    }
+}
+
+// age_ms on the levels of the aggregate, when on
+pub fn apply(agg: &mut AggregatedOrderbook, now: u128) {
+    let tmp = AGES.lock().unwrap();
+    if let Some(books) = tmp.as_ref() {
+        let mut level_ages = LevelAges::default();
+        for (exchange, book) in books.iter() {
+            if agg.timestamp.contains_key(exchange) {
+                level_ages
+                    .bid
+                    .insert(exchange.clone(), ages(&book.bid, now));
+                level_ages
+                    .ask
+                    .insert(exchange.clone(), ages(&book.ask, now));
+            }
+        }
+        agg.level_ages = Some(level_ages);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apitree::wsapi::{ParseOutcome, WS_APIMAP};
+    use crate::orderbook::SummaryView;
+    use crate::orderbook::{Stats, Summary};
+
+    fn levels<'a>(levels: &[(&'a str, &'a str)]) -> Vec<[&'a str; 2]> {
+        levels.iter().map(|(p, a)| [*p, *a]).collect()
+    }
+
+    // a btcmarkets orderbook message: the whole ladder every time
+    fn refresh(bids: &[(&str, &str)], asks: &[(&str, &str)]) -> Orderbook {
+        let raw = serde_json::json!({
+            "marketId": "BTC-AGES",
+            "messageType": "orderbook",
+            "bids": levels(bids),
+            "asks": levels(asks),
+        });
+        let parse = WS_APIMAP.get("btcmarkets").unwrap().parse;
+        match parse(&raw.to_string()).unwrap() {
+            ParseOutcome::Book(ob) => (*ob).clone(),
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    fn summary(ob: &Orderbook, now: u128) -> Summary {
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(ob);
+        apply(&mut agg, now);
+        let summary = agg.finalize().unwrap();
+        // the streamed view carries the same ages
+        let view = SummaryView {
+            book: &agg,
+            seq: 1,
+            opportunities: &[],
+            stats: &Stats::default(),
+        };
+        let view = serde_json::to_value(&view).unwrap();
+        assert_eq!(view["bids"], serde_json::to_value(&summary.bids).unwrap());
+        assert_eq!(view["asks"], serde_json::to_value(&summary.asks).unwrap());
+        summary
+    }
+
+    #[test]
+    fn test_level_ages() {
+        init();
+        let bids = [("100", "1"), ("99", "2")];
+        let asks = [("101", "1"), ("102", "3")];
+        let mut best = vec![];
+        // the same ladder re-sent every 100ms
+        for now in [1000, 1100, 1200] {
+            let ob = refresh(&bids, &asks);
+            observe(&ob, now);
+            let published = summary(&ob, now);
+            best.push(published.bids[0].age_ms.unwrap());
+            assert_eq!(published.asks[1].age_ms, Some(now as u64 - 1000));
+        }
+        assert_eq!(best, [0, 100, 200]);
+
+        // resized, gone and new levels start over, the others keep growing
+        let ob = refresh(&[("100", "1"), ("98", "2")], &[("101", "5"), ("102", "3")]);
+        observe(&ob, 1300);
+        let published = summary(&ob, 1350);
+        let age = |levels: &[crate::orderbook::Level]| -> Vec<(String, Option<u64>)> {
+            levels.iter().map(|l| (l.price.clone(), l.age_ms)).collect()
+        };
+        let expected = |rows: &[(&str, u64)]| -> Vec<(String, Option<u64>)> {
+            rows.iter()
+                .map(|(p, a)| (p.to_string(), Some(*a)))
+                .collect()
+        };
+        assert_eq!(age(&published.bids), expected(&[("100", 350), ("98", 50)]));
+        assert_eq!(age(&published.asks), expected(&[("101", 50), ("102", 350)]));
+        // a level back after it was gone is new again
+        let ob = refresh(&bids, &asks);
+        observe(&ob, 1400);
+        assert_eq!(summary(&ob, 1400).bids[1].age_ms, Some(0));
+
+        // not tracked, not written
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&ob);
+        let untracked = agg.finalize().unwrap();
+        assert_eq!(untracked.bids[0].age_ms, None);
+        assert!(!serde_json::to_string(&untracked)
+            .unwrap()
+            .contains("age_ms"));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::config::{ContributionSetting, ShallowBooks};
+use crate::orderbook::AggregatedOrderbook;
+use bigdecimal::{BigDecimal, ToPrimitive};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+
+// what each exchange held of one aggregate, weights summing to 1 per side
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Shares {
+    // exchange -> credit for the best price, split between ties
+    best_bid: HashMap<String, f64>,
+    best_ask: HashMap<String, f64>,
+    // exchange -> its part of the size within the top levels, both sides
+    depth: HashMap<String, f64>,
+    // counted in depth with a book shallower than the levels
+    partial: HashSet<String>,
+}
+
+fn best(levels: Option<(&BigDecimal, &Vec<(String, BigDecimal)>)>) -> HashMap<String, f64> {
+    let Some((_, holders)) = levels else {
+        return HashMap::new();
+    };
+    let credit = 1. / holders.len() as f64;
+    holders.iter().map(|(e, _)| (e.clone(), credit)).collect()
+}
+
+impl Shares {
+    fn of(agg: &AggregatedOrderbook, levels: usize, shallow_books: ShallowBooks) -> Shares {
+        let shallow: HashSet<&String> = agg
+            .depth
+            .iter()
+            .filter(|(_, depth)| depth.shallower_than(levels))
+            .map(|(exchange, _)| exchange)
+            .collect();
+        let mut size: HashMap<String, f64> = HashMap::new();
+        let top = agg
+            .bid
+            .iter()
+            .rev()
+            .take(levels)
+            .chain(agg.ask.iter().take(levels));
+        for (_, holders) in top {
+            for (exchange, amount) in holders {
+                if shallow_books == ShallowBooks::Exclude && shallow.contains(exchange) {
+                    continue;
+                }
+                *size.entry(exchange.clone()).or_default() += amount.to_f64().unwrap_or(0.);
+            }
+        }
+        let total: f64 = size.values().sum();
+        if total > 0. {
+            size.values_mut().for_each(|s| *s /= total);
+        } else {
+            size.clear();
+        }
+        Shares {
+            best_bid: best(agg.bid.last_key_value()),
+            best_ask: best(agg.ask.first_key_value()),
+            partial: match shallow_books {
+                ShallowBooks::Annotate => shallow.into_iter().cloned().collect(),
+                ShallowBooks::Exclude => HashSet::new(),
+            },
+            depth: size,
+        }
+    }
+}
+
+// the shares of one bucket, weighted by the millis they were held
+#[derive(Default)]
+struct Bucket {
+    start: u128,
+    held_ms: f64,
+    best_bid: HashMap<String, f64>,
+    best_ask: HashMap<String, f64>,
+    depth: HashMap<String, f64>,
+    partial: HashSet<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Contribution {
+    // fraction of the time the exchange held the aggregate best bid / ask
+    pub best_bid: f64,
+    pub best_ask: f64,
+    // its average part of the size within the top `levels` of each side
+    pub depth: f64,
+    // its book had fewer than `levels` on a side within the window, depth
+    // is over what it had. Left out when it didn't.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Report {
+    pub window_secs: u64,
+    pub levels: usize,
+    // time the shares were held, at most window_secs
+    pub covered_secs: f64,
+    pub exchanges: BTreeMap<String, Contribution>,
+}
+
+// Time weighted shares of the best prices and of the top of book depth.
+// Every aggregate holds until the next one, its shares are credited for
+// that long to the bucket of the next one, a bucket at most: a longer gap
+// is a stalled pipeline, not a book that held. Buckets of bucket_secs are
+// dropped once out of the window, so the memory is bounded by the window.
+pub struct Accumulator {
+    setting: ContributionSetting,
+    // oldest first
+    buckets: VecDeque<Bucket>,
+    // the latest aggregate's shares and when it came
+    last: Option<(u128, Shares)>,
+}
+
+impl Accumulator {
+    pub fn new(setting: ContributionSetting) -> Accumulator {
+        Accumulator {
+            setting,
+            buckets: VecDeque::new(),
+            last: None,
+        }
+    }
+
+    pub fn update(&mut self, agg: &AggregatedOrderbook, now: u128) {
+        let shares = Shares::of(agg, self.setting.levels, self.setting.shallow_books);
+        if let Some((since, held)) = self.last.replace((now, shares)) {
+            let bucket_ms = self.setting.bucket_secs as u128 * 1000;
+            let held_ms = now.saturating_sub(since).min(bucket_ms);
+            self.credit(&held, held_ms as f64, now);
+        }
+        let window_ms = self.setting.window_secs as u128 * 1000;
+        while let Some(bucket) = self.buckets.front() {
+            if bucket.start + window_ms > now {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    fn credit(&mut self, shares: &Shares, held_ms: f64, now: u128) {
+        if held_ms <= 0. {
+            return;
+        }
+        let bucket_ms = self.setting.bucket_secs as u128 * 1000;
+        let start = now - now % bucket_ms;
+        if self.buckets.back().is_none_or(|b| b.start != start) {
+            self.buckets.push_back(Bucket {
+                start,
+                ..Default::default()
+            });
+        }
+        let bucket = self.buckets.back_mut().unwrap();
+        bucket.held_ms += held_ms;
+        bucket.partial.extend(shares.partial.iter().cloned());
+        for (from, to) in [
+            (&shares.best_bid, &mut bucket.best_bid),
+            (&shares.best_ask, &mut bucket.best_ask),
+            (&shares.depth, &mut bucket.depth),
+        ] {
+            for (exchange, share) in from.iter() {
+                *to.entry(exchange.clone()).or_default() += share * held_ms;
+            }
+        }
+    }
+
+    pub fn report(&self) -> Report {
+        let held_ms: f64 = self.buckets.iter().map(|b| b.held_ms).sum();
+        let mut sums: BTreeMap<String, [f64; 3]> = BTreeMap::new();
+        let mut partial = HashSet::new();
+        for bucket in self.buckets.iter() {
+            partial.extend(bucket.partial.iter());
+            for (i, map) in [&bucket.best_bid, &bucket.best_ask, &bucket.depth]
+                .iter()
+                .enumerate()
+            {
+                for (exchange, weighted) in map.iter() {
+                    sums.entry(exchange.clone()).or_default()[i] += weighted;
+                }
+            }
+        }
+        let share = |weighted: f64| if held_ms > 0. { weighted / held_ms } else { 0. };
+        Report {
+            window_secs: self.setting.window_secs,
+            levels: self.setting.levels,
+            covered_secs: held_ms / 1000.,
+            exchanges: sums
+                .into_iter()
+                .map(|(exchange, [bid, ask, depth])| {
+                    let contribution = Contribution {
+                        best_bid: share(bid),
+                        best_ask: share(ask),
+                        depth: share(depth),
+                        partial: partial.contains(&exchange),
+                    };
+                    (exchange, contribution)
+                })
+                .collect(),
+        }
+    }
+}
+
+static ACCUMULATOR: Lazy<Mutex<Option<Accumulator>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn init(setting: ContributionSetting) {
+    *ACCUMULATOR.lock().unwrap() = Some(Accumulator::new(setting));
+}
+
+// feed an aggregate. No-op until init.
+pub fn update(agg: &AggregatedOrderbook, now: u128) {
+    if let Some(accumulator) = ACCUMULATOR.lock().unwrap().as_mut() {
+        accumulator.update(agg, now);
+    }
+}
+
+// None when disabled
+pub fn snapshot() -> Option<Report> {
+    ACCUMULATOR.lock().unwrap().as_ref().map(|a| a.report())
+}
+
+// prometheus text exposition of the shares, empty when disabled
+pub fn prometheus() -> String {
+    let mut out = String::new();
+    let Some(report) = snapshot() else {
+        return out;
+    };
+    let name = "arb_monitor_contribution_share";
+    let _ = writeln!(
+        out,
+        "# HELP {} share of the best prices and of the top depth over the window",
+        name
+    );
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for (exchange, c) in report.exchanges.iter() {
+        for (kind, share) in [
+            ("best_bid", c.best_bid),
+            ("best_ask", c.best_ask),
+            ("depth", c.depth),
+        ] {
+            let _ = writeln!(
+                out,
+                "{}{{exchange=\"{}\",kind=\"{}\"}} {}",
+                name, exchange, kind, share
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Orderbook, Side};
+
+    fn book(exchange: &str, bid: i64, ask: i64, amount: i64) -> Orderbook {
+        let mut ob = Orderbook::new(exchange);
+        ob.insert(Side::Bid, bid.into(), amount.into());
+        ob.insert(Side::Ask, ask.into(), amount.into());
+        ob
+    }
+
+    #[test]
+    fn test_shares() {
+        let mut a = Accumulator::new(ContributionSetting {
+            window_secs: 3600,
+            bucket_secs: 60,
+            levels: 10,
+            shallow_books: ShallowBooks::Annotate,
+        });
+        // A holds the best bid 3 ticks out of 4. Both quote the same ask,
+        // and A shows 3 times the size of B.
+        for tick in 0..=400u128 {
+            let a_bid = if tick % 4 == 3 { 99 } else { 101 };
+            let mut agg = AggregatedOrderbook::new();
+            agg.merge(&book("A", a_bid, 102, 3));
+            agg.merge(&book("B", 100, 102, 1));
+            a.update(&agg, 1_700_000_000_000 + tick * 1000);
+        }
+        let report = a.report();
+        assert_eq!(report.covered_secs, 400.);
+        let round = |v: f64| (v * 1000.).round() / 1000.;
+        let shares = |report: &Report, e: &str| {
+            let c = &report.exchanges[e];
+            (round(c.best_bid), round(c.best_ask), round(c.depth))
+        };
+        assert_eq!(shares(&report, "A"), (0.75, 0.5, 0.75));
+        assert_eq!(shares(&report, "B"), (0.25, 0.5, 0.25));
+
+        // B alone for an hour, the buckets of A are out of the window
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&book("B", 100, 102, 1));
+        for tick in 401..=4000u128 {
+            a.update(&agg, 1_700_000_000_000 + tick * 1000);
+        }
+        let report = a.report();
+        assert!(report.covered_secs <= 3600.);
+        assert!(!report.exchanges.contains_key("A"));
+        assert_eq!(shares(&report, "B"), (1., 1., 1.));
+        // a stalled pipeline is credited a bucket at most
+        a.update(&agg, 1_700_000_000_000 + 5_000_000);
+        assert!(a.report().covered_secs <= 3600.);
+        assert!(prometheus().is_empty());
+    }
+}
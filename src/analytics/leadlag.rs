@@ -0,0 +1,300 @@
+use crate::config::LeadLagSetting;
+use crate::opportunity::best_levels;
+use crate::orderbook::Summary;
+use bigdecimal::{BigDecimal, ToPrimitive};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::Mutex;
+
+// a move of one exchange's mid beyond threshold_bps
+struct Move {
+    time: u128,
+    up: bool,
+    // exchanges this move followed, it can't lead them back
+    followed: BTreeSet<String>,
+    // exchanges that followed this move, it leads each only once
+    led: BTreeSet<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct PairStats {
+    // "a/b", in name order
+    pub pair: String,
+    // lead events kept for the pair
+    pub events: usize,
+    // exchange -> percentage of the events it led
+    pub leads: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Report {
+    pub window_ms: u64,
+    pub pairs: Vec<PairStats>,
+}
+
+// Event based lead counting: when an exchange's mid moves by threshold_bps,
+// the latest same direction move of every other exchange in the previous
+// window_ms is counted as leading it. Everything is bounded: the moves by
+// window and capacity, the outcomes by capacity per exchange pair.
+pub struct Analyzer {
+    setting: LeadLagSetting,
+    // exchange -> mid at its last move
+    reference: HashMap<String, f64>,
+    // exchange -> recent moves, oldest first
+    moves: HashMap<String, VecDeque<Move>>,
+    // (a, b) in name order -> last outcomes, true when a led
+    outcomes: BTreeMap<(String, String), VecDeque<bool>>,
+}
+
+// mid of every exchange, converted once
+fn mids(summary: &Summary) -> BTreeMap<String, f64> {
+    let bids = best_levels(&summary.bids);
+    let asks = best_levels(&summary.asks);
+    let mut result = BTreeMap::new();
+    for (exchange, (bid, _)) in bids.iter() {
+        if let Some((ask, _)) = asks.get(exchange) {
+            if let Some(mid) = ((bid + ask) / BigDecimal::from(2)).to_f64() {
+                result.insert(exchange.to_string(), mid);
+            }
+        }
+    }
+    result
+}
+
+impl Analyzer {
+    pub fn new(setting: LeadLagSetting) -> Analyzer {
+        Analyzer {
+            setting,
+            reference: HashMap::new(),
+            moves: HashMap::new(),
+            outcomes: BTreeMap::new(),
+        }
+    }
+
+    fn record(&mut self, leader: &str, follower: &str) {
+        let (key, led) = if leader < follower {
+            ((leader.to_string(), follower.to_string()), true)
+        } else {
+            ((follower.to_string(), leader.to_string()), false)
+        };
+        let outcomes = self.outcomes.entry(key).or_default();
+        if outcomes.len() == self.setting.capacity {
+            outcomes.pop_front();
+        }
+        outcomes.push_back(led);
+    }
+
+    // feed one published summary
+    pub fn update(&mut self, summary: &Summary, now: u128) {
+        let since = now.saturating_sub(self.setting.window_ms as u128);
+        for moves in self.moves.values_mut() {
+            while moves.front().is_some_and(|m| m.time < since) {
+                moves.pop_front();
+            }
+        }
+
+        for (exchange, mid) in mids(summary) {
+            let reference = *self.reference.entry(exchange.clone()).or_insert(mid);
+            if reference <= 0. {
+                self.reference.insert(exchange, mid);
+                continue;
+            }
+            let change_bps = (mid / reference - 1.) * 10000.;
+            if change_bps.abs() < self.setting.threshold_bps {
+                continue;
+            }
+            self.reference.insert(exchange.clone(), mid);
+            let up = change_bps > 0.;
+
+            let mut leaders = vec![];
+            for (other, moves) in self.moves.iter_mut() {
+                if *other == exchange {
+                    continue;
+                }
+                let lead = moves.iter_mut().rev().find(|m| {
+                    m.time < now
+                        && m.up == up
+                        && !m.followed.contains(&exchange)
+                        && !m.led.contains(&exchange)
+                });
+                if let Some(m) = lead {
+                    m.led.insert(exchange.clone());
+                    leaders.push(other.clone());
+                }
+            }
+            for leader in leaders.iter() {
+                self.record(leader, &exchange);
+            }
+
+            let moves = self.moves.entry(exchange).or_default();
+            if moves.len() == self.setting.capacity {
+                moves.pop_front();
+            }
+            moves.push_back(Move {
+                time: now,
+                up,
+                followed: leaders.into_iter().collect(),
+                led: BTreeSet::new(),
+            });
+        }
+    }
+
+    pub fn report(&self) -> Report {
+        let pairs = self
+            .outcomes
+            .iter()
+            .map(|((a, b), outcomes)| {
+                let events = outcomes.len();
+                let a_led = outcomes.iter().filter(|led| **led).count();
+                let pct = |n: usize| n as f64 * 100. / events as f64;
+                PairStats {
+                    pair: format!("{}/{}", a, b),
+                    events,
+                    leads: BTreeMap::from([
+                        (a.clone(), pct(a_led)),
+                        (b.clone(), pct(events - a_led)),
+                    ]),
+                }
+            })
+            .collect();
+        Report {
+            window_ms: self.setting.window_ms,
+            pairs,
+        }
+    }
+
+    // the exchange leading the largest share of the events it is part of,
+    // if that share is a majority
+    pub fn leader(&self) -> Option<String> {
+        let mut share: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+        for ((a, b), outcomes) in self.outcomes.iter() {
+            let a_led = outcomes.iter().filter(|led| **led).count();
+            let entry = share.entry(a).or_default();
+            entry.0 += a_led;
+            entry.1 += outcomes.len();
+            let entry = share.entry(b).or_default();
+            entry.0 += outcomes.len() - a_led;
+            entry.1 += outcomes.len();
+        }
+        share
+            .into_iter()
+            .filter(|(_, (led, total))| *total > 0 && led * 2 > *total)
+            .max_by(|x, y| (x.1 .0 * y.1 .1).cmp(&(y.1 .0 * x.1 .1)))
+            .map(|(exchange, _)| exchange.to_string())
+    }
+}
+
+static ANALYZER: Lazy<Mutex<Option<Analyzer>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn init(setting: LeadLagSetting) {
+    *ANALYZER.lock().unwrap() = Some(Analyzer::new(setting));
+}
+
+// feed a summary and return the current leader. No-op until init.
+pub fn update(summary: &Summary, now: u128) -> Option<String> {
+    let mut tmp = ANALYZER.lock().unwrap();
+    let analyzer = tmp.as_mut()?;
+    analyzer.update(summary, now);
+    analyzer.leader()
+}
+
+// None when the analyzer is disabled
+pub fn snapshot() -> Option<Report> {
+    ANALYZER.lock().unwrap().as_ref().map(|a| a.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+
+    // bid and ask 1 away from the mid of every exchange
+    fn book(mids: &[(&str, i64)]) -> Summary {
+        let bids = mids
+            .iter()
+            .map(|(e, mid)| level(e, &(mid - 1).to_string(), "1"))
+            .collect();
+        let asks = mids
+            .iter()
+            .map(|(e, mid)| level(e, &(mid + 1).to_string(), "1"))
+            .collect();
+        summary(bids, asks, 0)
+    }
+
+    #[test]
+    fn test_binance_leads() {
+        let mut a = Analyzer::new(LeadLagSetting {
+            threshold_bps: 5.,
+            window_ms: 250,
+            capacity: 4,
+        });
+        // binance moves, btcmarkets and kraken follow one 100ms tick later.
+        // They move together, so neither leads the other.
+        let path = [
+            (10000, 10000, 10000),
+            (10010, 10000, 10000),
+            (10010, 10010, 10010),
+            (10000, 10010, 10010),
+            (10000, 10000, 10000),
+            (10020, 10000, 10000),
+            (10020, 10020, 10020),
+            // a 1 bps wiggle is under the threshold
+            (10021, 10020, 10020),
+        ];
+        for (tick, (binance, btcmarkets, kraken)) in path.iter().enumerate() {
+            let s = book(&[
+                ("binance", *binance),
+                ("btcmarkets", *btcmarkets),
+                ("kraken", *kraken),
+            ]);
+            a.update(&s, tick as u128 * 100);
+        }
+        let report = a.report();
+        assert_eq!(
+            report.pairs,
+            vec![
+                PairStats {
+                    pair: "binance/btcmarkets".to_string(),
+                    events: 3,
+                    leads: BTreeMap::from([
+                        ("binance".to_string(), 100.),
+                        ("btcmarkets".to_string(), 0.),
+                    ]),
+                },
+                PairStats {
+                    pair: "binance/kraken".to_string(),
+                    events: 3,
+                    leads: BTreeMap::from([
+                        ("binance".to_string(), 100.),
+                        ("kraken".to_string(), 0.),
+                    ]),
+                },
+            ]
+        );
+        assert_eq!(a.leader(), Some("binance".to_string()));
+
+        // out of the window: a late follower is not counted
+        let s = book(&[("binance", 10040), ("btcmarkets", 10020)]);
+        a.update(&s, 1000);
+        let s = book(&[("binance", 10040), ("btcmarkets", 10040)]);
+        a.update(&s, 2500);
+        assert_eq!(a.report().pairs[0].events, 3);
+
+        // the ring buffer keeps the last 4 events of a pair
+        for i in 0..3 {
+            let up = 10060 + i * 20;
+            a.update(
+                &book(&[("binance", 10040 + i * 20), ("btcmarkets", up)]),
+                3000 + i as u128 * 200,
+            );
+            a.update(
+                &book(&[("binance", up), ("btcmarkets", up)]),
+                3100 + i as u128 * 200,
+            );
+        }
+        let pair = &a.report().pairs[0];
+        assert_eq!(pair.events, 4);
+        assert_eq!(pair.leads["btcmarkets"], 75.);
+    }
+}
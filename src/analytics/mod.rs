@@ -0,0 +1,5 @@
+pub mod ages;
+pub mod contribution;
+pub mod leadlag;
+pub mod traded;
+pub mod volatility;
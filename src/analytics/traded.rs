@@ -0,0 +1,209 @@
+use crate::orderbook::{AggregatedOrderbook, Trade, TradedVolume};
+use bigdecimal::{BigDecimal, Zero};
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+// same span as the 24h volume the venues report
+const RETENTION_MS: u128 = 24 * 3_600_000;
+// windows of stats.traded
+const WINDOWS_SECS: [u64; 2] = [60, 300];
+
+// Rolling traded volume of one exchange/pair. Trades are summed into one
+// slot per second, oldest first, so a busy venue holds at most a day of
+// seconds instead of a day of prints.
+#[derive(Default)]
+pub struct Accumulator {
+    // (second in unix millis, volume traded in it)
+    slots: VecDeque<(u128, BigDecimal)>,
+    // sum of the slots
+    total: BigDecimal,
+}
+
+impl Accumulator {
+    pub fn record(&mut self, timestamp: u128, quantity: &BigDecimal) {
+        let second = timestamp - timestamp % 1000;
+        self.total += quantity;
+        match self.slots.back_mut() {
+            // a late print is counted in the latest second
+            Some((last, sum)) if *last >= second => *sum += quantity,
+            _ => self.slots.push_back((second, quantity.clone())),
+        }
+    }
+
+    pub fn prune(&mut self, now: u128) {
+        while let Some((second, _)) = self.slots.front() {
+            if second + RETENTION_MS > now {
+                break;
+            }
+            let (_, sum) = self.slots.pop_front().unwrap();
+            self.total -= sum;
+        }
+    }
+
+    pub fn total(&self) -> &BigDecimal {
+        &self.total
+    }
+
+    // volume of the seconds within window_ms of now
+    pub fn window(&self, now: u128, window_ms: u128) -> BigDecimal {
+        self.slots
+            .iter()
+            .rev()
+            .take_while(|(second, _)| second + window_ms > now)
+            .fold(BigDecimal::zero(), |acc, (_, sum)| acc + sum)
+    }
+}
+
+// (exchange, pair) -> accumulator, only for the exchanges subscribed to trades
+static VOLUMES: Lazy<Mutex<HashMap<(String, String), Accumulator>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn record(exchange: &str, trades: &[Trade], now: u128) {
+    let mut tmp = VOLUMES.lock().unwrap();
+    for trade in trades {
+        let accumulator = tmp
+            .entry((exchange.to_string(), trade.pair.clone()))
+            .or_default();
+        accumulator.record(trade.timestamp, &trade.quantity);
+        accumulator.prune(now);
+    }
+}
+
+// Use the traded volume of the exchanges that report none, and return the
+// short window volumes of the exchanges in the aggregate.
+pub fn apply(agg: &mut AggregatedOrderbook, now: u128) -> Vec<TradedVolume> {
+    let mut tmp = VOLUMES.lock().unwrap();
+    if tmp.is_empty() {
+        return vec![];
+    }
+    // exchange -> (24h, per window)
+    let mut traded: BTreeMap<String, (BigDecimal, Vec<BigDecimal>)> = BTreeMap::new();
+    for ((exchange, _pair), accumulator) in tmp.iter_mut() {
+        if !agg.timestamp.contains_key(exchange) {
+            continue;
+        }
+        accumulator.prune(now);
+        let entry = traded.entry(exchange.clone()).or_insert_with(|| {
+            (
+                BigDecimal::zero(),
+                vec![BigDecimal::zero(); WINDOWS_SECS.len()],
+            )
+        });
+        entry.0 += accumulator.total();
+        for (sum, secs) in entry.1.iter_mut().zip(WINDOWS_SECS) {
+            *sum += accumulator.window(now, secs as u128 * 1000);
+        }
+    }
+    for (exchange, (total, _)) in traded.iter() {
+        agg.volume.fill_zero(exchange, total);
+    }
+    if traded.is_empty() {
+        return vec![];
+    }
+    WINDOWS_SECS
+        .iter()
+        .enumerate()
+        .map(|(i, secs)| TradedVolume {
+            window_secs: *secs,
+            volume: traded
+                .iter()
+                .map(|(exchange, (_, windows))| (exchange.clone(), windows[i].to_string()))
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apitree;
+    use crate::orderbook::Orderbook;
+    use std::str::FromStr;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_rolling_sums() {
+        let mut a = Accumulator::default();
+        let start = 1_700_000_000_000;
+        a.record(start, &dec("1.5"));
+        // same second, one slot
+        a.record(start + 400, &dec("0.5"));
+        a.record(start + 120_000, &dec("2"));
+        a.record(start + 290_000, &dec("0.25"));
+        assert_eq!(a.slots.len(), 3);
+        let now = start + 300_000;
+        assert_eq!(a.window(now, 60_000), dec("0.25"));
+        assert_eq!(a.window(now, 300_000), dec("2.25"));
+        assert_eq!(a.total(), &dec("4.25"));
+
+        // the first second ages out of the day
+        a.prune(start + RETENTION_MS);
+        assert_eq!(a.total(), &dec("2.25"));
+        a.prune(start + RETENTION_MS + 290_000);
+        assert_eq!(a.total(), &dec("0"));
+        assert!(a.slots.is_empty());
+    }
+
+    #[test]
+    fn test_trade_fixtures() {
+        let fixtures = [
+            (
+                "binance",
+                r#"{"e":"trade","E":1700000000100,"s":"TRDUSDT","t":12345,"p":"37000.10","q":"0.015","T":1700000000000,"m":true,"M":true}"#,
+            ),
+            (
+                "binance",
+                r#"{"e":"trade","E":1700000030100,"s":"TRDUSDT","t":12346,"p":"37000.20","q":"0.5","T":1700000030000,"m":false,"M":true}"#,
+            ),
+            (
+                "bitstamp",
+                r#"{"data":{"id":305,"timestamp":"1700000010","amount":0.2,"amount_str":"0.20000000","price":37001,"price_str":"37001","type":0,"microtimestamp":"1700000010250000","buy_order_id":1,"sell_order_id":2},"channel":"live_trades_trdusd","event":"trade"}"#,
+            ),
+            (
+                "kraken",
+                r#"[337,[["37002.10000","0.10000000","1700000020.321597","s","l",""],["37002.00000","0.05000000","1700000021.000100","b","m",""]],"trade","TRD/USD"]"#,
+            ),
+        ];
+        let now = 1_700_000_040_000;
+        let mut agg = crate::orderbook::AggregatedOrderbook::new();
+        for exchange in ["binance", "bitstamp", "kraken"] {
+            let mut ob = Orderbook::new(exchange);
+            if exchange == "kraken" {
+                // reported by the ticker, kept
+                ob.volume = dec("1234.5");
+            }
+            agg.merge(&ob);
+        }
+        for (exchange, raw) in fixtures {
+            let parse = apitree::ws(exchange).unwrap().parse_trades.unwrap();
+            let trades = parse(raw).unwrap().unwrap();
+            record(exchange, &trades, now);
+        }
+        // not a trade frame
+        let parse = apitree::ws("binance").unwrap().parse_trades.unwrap();
+        assert_eq!(parse(r#"{"result":null,"id":2}"#).unwrap(), None);
+
+        let windows = apply(&mut agg, now);
+        assert_eq!(agg.volume["binance"], dec("0.515"));
+        assert_eq!(agg.volume["bitstamp"], dec("0.2"));
+        assert_eq!(agg.volume["kraken"], dec("1234.5"));
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[0].window_secs, 60);
+        assert_eq!(
+            windows[0].volume,
+            BTreeMap::from([
+                ("binance".to_string(), "0.515".to_string()),
+                ("bitstamp".to_string(), "0.20000000".to_string()),
+                ("kraken".to_string(), "0.15000000".to_string()),
+            ])
+        );
+        // only the last 30s of binance within a 1m window a bit later
+        let windows = apply(&mut agg, now + 30_000);
+        assert_eq!(windows[0].volume["binance"], "0.5");
+        assert_eq!(windows[1].volume["binance"], "0.515");
+    }
+}
@@ -0,0 +1,136 @@
+use crate::config::VolatilitySetting;
+use crate::orderbook::{Regime, Summary, Volatility, WindowVolatility};
+use bigdecimal::{BigDecimal, ToPrimitive};
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+// mid of the aggregate top of book. The only BigDecimal -> f64 conversion of a sample.
+pub fn aggregate_mid(summary: &Summary) -> Option<f64> {
+    let bid = BigDecimal::from_str(&summary.bids.first()?.price).ok()?;
+    let ask = BigDecimal::from_str(&summary.asks.first()?.price).ok()?;
+    ((bid + ask) / BigDecimal::from(2)).to_f64()
+}
+
+// Realized volatility over rolling time windows: the square root of the sum
+// of squared log returns between consecutive samples. Log returns of close
+// mids stay well scaled, and the sums are taken fresh over the window on
+// every sample, so no error accumulates from adding and removing terms.
+pub struct Estimator {
+    setting: VolatilitySetting,
+    last: Option<f64>,
+    // (unix millis, squared log return), oldest first, over the longest window
+    returns: VecDeque<(u128, f64)>,
+}
+
+impl Estimator {
+    pub fn new(setting: VolatilitySetting) -> Estimator {
+        Estimator {
+            setting,
+            last: None,
+            returns: VecDeque::new(),
+        }
+    }
+
+    fn regime(&self, bps: f64) -> Regime {
+        if bps < self.setting.quiet_bps {
+            Regime::Quiet
+        } else if bps > self.setting.volatile_bps {
+            Regime::Volatile
+        } else {
+            Regime::Normal
+        }
+    }
+
+    // feed the mid published at `now`
+    pub fn update(&mut self, mid: f64, now: u128) -> Volatility {
+        if let Some(last) = self.last {
+            if mid > 0. && last > 0. {
+                let r = (mid / last).ln();
+                self.returns.push_back((now, r * r));
+            }
+        }
+        if mid > 0. {
+            self.last = Some(mid);
+        }
+        let longest = self.setting.windows_secs.iter().max().copied().unwrap_or(0);
+        let since = now.saturating_sub(longest as u128 * 1000);
+        while self.returns.front().is_some_and(|(t, _)| *t <= since) {
+            self.returns.pop_front();
+        }
+
+        let windows: Vec<WindowVolatility> = self
+            .setting
+            .windows_secs
+            .iter()
+            .map(|secs| {
+                let since = now.saturating_sub(*secs as u128 * 1000);
+                let (sum, samples) = self
+                    .returns
+                    .iter()
+                    .rev()
+                    .take_while(|(t, _)| *t > since)
+                    .fold((0., 0), |(sum, n), (_, r2)| (sum + r2, n + 1));
+                WindowVolatility {
+                    window_secs: *secs,
+                    bps: sum.sqrt() * 10000.,
+                    samples,
+                }
+            })
+            .collect();
+        let regime = self.regime(windows.first().map(|w| w.bps).unwrap_or(0.));
+        Volatility { windows, regime }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+
+    #[test]
+    fn test_volatility() {
+        let s = summary(
+            vec![level("kraken", "100", "1")],
+            vec![level("coinjar", "101", "1")],
+            0,
+        );
+        assert_eq!(aggregate_mid(&s), Some(100.5));
+
+        let mut e = Estimator::new(VolatilitySetting {
+            windows_secs: vec![10, 60],
+            quiet_bps: 5.,
+            volatile_bps: 30.,
+        });
+        // flat for 10 secs
+        for i in 0..10 {
+            let v = e.update(100., i * 1000);
+            assert_eq!(v.regime, Regime::Quiet);
+            assert_eq!(v.windows[0].bps, 0.);
+        }
+        // two 10 bps steps: sqrt(2) * ln(1.001) in bps
+        e.update(100. * 1.001, 10_000);
+        let v = e.update(100. * 1.001 * 1.001, 11_000);
+        let step = 1.001f64.ln();
+        assert!((v.windows[0].bps - (2. * step * step).sqrt() * 10000.).abs() < 1e-9);
+        assert!((v.windows[0].bps - 14.135).abs() < 1e-3);
+        assert_eq!(v.windows[0].samples, 10);
+        assert_eq!(v.windows[1].samples, 11);
+        assert_eq!(v.regime, Regime::Normal);
+
+        // a violent back and forth: 50 bps each way
+        e.update(100. * 1.001 * 1.001 * 1.005, 12_000);
+        let v = e.update(100. * 1.001 * 1.001, 13_000);
+        assert!(v.windows[0].bps > 70.);
+        assert_eq!(v.regime, Regime::Volatile);
+
+        // calm again: the moves leave the 10 secs window, not the 60 secs one
+        let mut v = v;
+        for i in 14..=23 {
+            v = e.update(100. * 1.001 * 1.001, i * 1000);
+        }
+        assert_eq!(v.windows[0].bps, 0.);
+        assert_eq!(v.regime, Regime::Quiet);
+        assert!(v.windows[1].bps > 70.);
+        assert_eq!(v.windows[1].samples, 23);
+    }
+}
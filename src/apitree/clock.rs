@@ -0,0 +1,173 @@
+use crate::config::TimeSyncSetting;
+use crate::orderbook::get_unixtime;
+use crate::status;
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use log::{error, info};
+use serde_json::Value;
+use tokio::time::{interval, Duration};
+
+type ServerTime = fn(&Value) -> Option<i128>;
+
+fn binance_time(body: &Value) -> Option<i128> {
+    body.get("serverTime")?.as_i64().map(i128::from)
+}
+
+fn okx_time(body: &Value) -> Option<i128> {
+    body.pointer("/data/0/ts")?.as_str()?.parse().ok()
+}
+
+fn btcmarkets_time(body: &Value) -> Option<i128> {
+    let time = DateTime::parse_from_rfc3339(body.get("timestamp")?.as_str()?).ok()?;
+    Some(time.timestamp_millis() as i128)
+}
+
+// server time endpoint of the venues that have one, with the unix millis
+// of its response
+pub fn endpoint(exchange: &str) -> Option<(&'static str, ServerTime)> {
+    match exchange {
+        "binance" => Some(("https://api.binance.com/api/v3/time", binance_time)),
+        "binanceus" => Some(("https://api.binance.us/api/v3/time", binance_time)),
+        "okx" => Some(("https://www.okx.com/api/v5/public/time", okx_time)),
+        "btcmarkets" => Some(("https://api.btcmarkets.net/v3/time", btcmarkets_time)),
+        _ => None,
+    }
+}
+
+// (venue clock minus ours, round trip), taking the server time as read
+// half way through the round trip
+pub async fn probe(client: &reqwest::Client, url: &str, parse: ServerTime) -> Result<(i64, u64)> {
+    let sent = get_unixtime();
+    let body: Value = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let received = get_unixtime();
+    let server = parse(&body).ok_or_else(|| anyhow!("no server time in {}", body))?;
+    let midpoint = ((sent + received) / 2) as i128;
+    Ok(((server - midpoint) as i64, (received - sent) as u64))
+}
+
+async fn sync(
+    client: &reqwest::Client,
+    exchange: &str,
+    url: &str,
+    parse: ServerTime,
+    max_offset_ms: u64,
+) {
+    match probe(client, url, parse).await {
+        Ok((offset, rtt)) => {
+            let skewed = offset.unsigned_abs() > max_offset_ms;
+            if skewed {
+                error!(
+                    "clock of {} is {}ms off ours (rtt {}ms), above {}ms",
+                    exchange, offset, rtt, max_offset_ms
+                );
+            } else {
+                info!("clock of {}: offset {}ms, rtt {}ms", exchange, offset, rtt);
+            }
+            status::clock_synced(exchange, offset, rtt, skewed);
+        }
+        Err(e) => error!("time sync {}: {}", exchange, e),
+    }
+}
+
+// Probes every exchange with a time endpoint now and every interval_secs.
+// The others are skipped, their timestamps are taken as they come.
+pub fn start<'a>(exchanges: impl Iterator<Item = &'a String>, setting: &TimeSyncSetting) {
+    for exchange in exchanges {
+        let Some((url, parse)) = endpoint(exchange) else {
+            continue;
+        };
+        let exchange = exchange.clone();
+        let (every, max_offset_ms) = (setting.interval_secs, setting.max_offset_ms);
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            let mut ticker = interval(Duration::from_secs(every));
+            loop {
+                ticker.tick().await;
+                sync(&client, &exchange, url, parse, max_offset_ms).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[tokio::test]
+    async fn test_skewed_clock() {
+        let mut server = mockito::Server::new_async().await;
+        // the venues run 5s ahead of us
+        let skew = 5000;
+        let venue_now = get_unixtime() as i64 + skew;
+        server
+            .mock("GET", "/api/v3/time")
+            .with_body(format!(r#"{{"serverTime":{}}}"#, venue_now))
+            .create_async()
+            .await;
+        let iso = Utc
+            .timestamp_millis_opt(venue_now)
+            .unwrap()
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        server
+            .mock("GET", "/v3/time")
+            .with_body(format!(r#"{{"timestamp":"{}"}}"#, iso))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/v5/public/time")
+            .with_body(format!(
+                r#"{{"code":"0","data":[{{"ts":"{}"}}]}}"#,
+                venue_now
+            ))
+            .create_async()
+            .await;
+        let client = reqwest::Client::new();
+        for (exchange, path) in [
+            ("binance", "/api/v3/time"),
+            ("btcmarkets", "/v3/time"),
+            ("okx", "/api/v5/public/time"),
+        ] {
+            let (_, parse) = endpoint(exchange).unwrap();
+            let url = format!("{}{}", server.url(), path);
+            let (offset, rtt) = probe(&client, &url, parse).await.unwrap();
+            // read a little after the mock was created
+            assert!(
+                (skew - 1000..=skew).contains(&offset),
+                "{} {}",
+                exchange,
+                offset
+            );
+            assert!(rtt < 1000);
+        }
+        assert!(endpoint("bitstamp").is_none());
+
+        let (_, parse) = endpoint("binance").unwrap();
+        let url = format!("{}/api/v3/time", server.url());
+        status::connected("clock_test");
+        sync(&client, "clock_test", &url, parse, 1000).await;
+        let st = status::snapshot().remove("clock_test").unwrap();
+        assert!(st.clock_skewed);
+        let offset = st.clock_offset_ms.unwrap();
+
+        // a trade stamped 120ms before now on the venue's clock
+        let now = get_unixtime();
+        let venue_ts = (now as i64 + offset - 120) as u128;
+        let local = status::local_time("clock_test", venue_ts);
+        assert_eq!(local, now - 120);
+        status::venue_message("clock_test", local, now);
+        let st = status::snapshot().remove("clock_test").unwrap();
+        assert_eq!(st.latency_ms, Some(120));
+        assert!(status::health()
+            .clock_skewed
+            .contains(&"clock_test".to_string()));
+        // not probed, taken as is
+        assert_eq!(status::local_time("clock_test_unsynced", 1000), 1000);
+    }
+}
@@ -0,0 +1,233 @@
+// Replays a capture, one raw frame per line, through two parsers of the
+// same exchange and finds the first frame they disagree on. Books compare
+// but for their timestamps. Each parser runs over the whole capture from a
+// cleared state in turn, so two parsers sharing their state compare too.
+use super::wsapi::{ParseFunc, ParseOutcome, WS_APIMAP};
+use crate::orderbook::Orderbook;
+use anyhow::{anyhow, Result};
+use bigdecimal::BigDecimal;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+pub struct Parser {
+    pub parse: ParseFunc,
+    pub clear: fn(),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    // 0 based line of the capture
+    pub frame: usize,
+    pub raw: String,
+    // one line per difference, baseline != candidate
+    pub diff: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "frame {} diverges", self.frame)?;
+        write!(f, "raw: {}", self.raw)?;
+        for line in self.diff.iter() {
+            write!(f, "\n  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+// (baseline, candidate) of an exchange. The baseline is "legacy", the
+// parser kept behind the legacy-parsers feature, the candidate the current.
+pub fn parsers(exchange: &str, baseline: &str) -> Result<(Parser, Parser)> {
+    let api = WS_APIMAP
+        .get(exchange)
+        .ok_or_else(|| anyhow!("unknown exchange {}", exchange))?;
+    let candidate = Parser {
+        parse: api.parse,
+        clear: api.clear,
+    };
+    if baseline != "legacy" {
+        return Err(anyhow!("unknown baseline {}, only legacy is", baseline));
+    }
+    #[cfg(feature = "legacy-parsers")]
+    {
+        let (parse, clear) = super::legacy::parser(exchange)
+            .ok_or_else(|| anyhow!("no legacy parser for {}", exchange))?;
+        Ok((Parser { parse, clear }, candidate))
+    }
+    #[cfg(not(feature = "legacy-parsers"))]
+    {
+        let _ = candidate;
+        Err(anyhow!("built without the legacy-parsers feature"))
+    }
+}
+
+// an Err kept as its message
+type Outcome = std::result::Result<ParseOutcome, String>;
+
+fn replay(frames: &[&str], parser: &Parser) -> Vec<Outcome> {
+    (parser.clear)();
+    let outcomes = frames
+        .iter()
+        .map(|raw| (parser.parse)(raw).map_err(|e| e.to_string()))
+        .collect();
+    (parser.clear)();
+    outcomes
+}
+
+fn describe(outcome: &Outcome) -> String {
+    match outcome {
+        Ok(ParseOutcome::Book(_)) => "Book".to_string(),
+        Ok(outcome) => format!("{:?}", outcome),
+        Err(e) => format!("Err({})", e),
+    }
+}
+
+// best first
+fn levels(
+    side: &str,
+    baseline: &BTreeMap<BigDecimal, BigDecimal>,
+    candidate: &BTreeMap<BigDecimal, BigDecimal>,
+    diff: &mut Vec<String>,
+) {
+    let prices: BTreeSet<&BigDecimal> = baseline.keys().chain(candidate.keys()).collect();
+    let prices: Vec<&BigDecimal> = match side {
+        "bid" => prices.into_iter().rev().collect(),
+        _ => prices.into_iter().collect(),
+    };
+    let show = |amount: Option<&BigDecimal>| amount.map_or("-".to_string(), |a| a.to_string());
+    for price in prices {
+        let (a, b) = (baseline.get(price), candidate.get(price));
+        if a != b {
+            diff.push(format!("{} {}: {} != {}", side, price, show(a), show(b)));
+        }
+    }
+}
+
+fn book_diff(baseline: &Orderbook, candidate: &Orderbook) -> Vec<String> {
+    let mut diff = vec![];
+    let fields = [
+        ("name", baseline.name.clone(), candidate.name.clone()),
+        (
+            "pair",
+            format!("{:?}", baseline.pair),
+            format!("{:?}", candidate.pair),
+        ),
+        (
+            "volume",
+            baseline.volume.to_string(),
+            candidate.volume.to_string(),
+        ),
+        (
+            "last_price",
+            baseline.last_price.to_string(),
+            candidate.last_price.to_string(),
+        ),
+        (
+            "derivatives",
+            format!("{:?}", baseline.derivatives),
+            format!("{:?}", candidate.derivatives),
+        ),
+    ];
+    for (name, a, b) in fields {
+        if a != b {
+            diff.push(format!("{}: {} != {}", name, a, b));
+        }
+    }
+    levels("bid", &baseline.bid, &candidate.bid, &mut diff);
+    levels("ask", &baseline.ask, &candidate.ask, &mut diff);
+    diff
+}
+
+// None when every frame gives the same outcome
+pub fn compare(capture: &str, baseline: &Parser, candidate: &Parser) -> Option<Divergence> {
+    let frames: Vec<&str> = capture.lines().collect();
+    let expected = replay(&frames, baseline);
+    let actual = replay(&frames, candidate);
+    for (frame, (a, b)) in expected.iter().zip(actual.iter()).enumerate() {
+        let diff = match (a, b) {
+            (Ok(ParseOutcome::Book(a)), Ok(ParseOutcome::Book(b))) => book_diff(a, b),
+            (a, b) if a == b => vec![],
+            (a, b) => vec![format!("outcome: {} != {}", describe(a), describe(b))],
+        };
+        if !diff.is_empty() {
+            return Some(Divergence {
+                frame,
+                raw: frames[frame].to_string(),
+                diff,
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::wsapi::kraken_forget;
+    use super::*;
+    use std::sync::Arc;
+
+    // a pair of its own, the other tests of the kraken parser run alongside
+    fn capture() -> String {
+        include_str!("../test_resource/kraken_book.jsonl").replace("XBT/USD", "XBT/CMP")
+    }
+
+    fn kraken() -> Parser {
+        Parser {
+            parse: WS_APIMAP.get("kraken").unwrap().parse,
+            clear: || kraken_forget("XBT/CMP"),
+        }
+    }
+
+    // the current parser, but the new best bid of the third frame is lost
+    fn perturbed(raw: &str) -> Result<ParseOutcome> {
+        let outcome = (kraken().parse)(raw)?;
+        match outcome {
+            ParseOutcome::Book(ob) if raw.contains(r#""b":[["43000.20000","0.50000000""#) => {
+                let mut ob = (*ob).clone();
+                ob.bid.remove(&"43000.2".parse().unwrap());
+                Ok(ParseOutcome::Book(Arc::new(ob)))
+            }
+            outcome => Ok(outcome),
+        }
+    }
+
+    #[test]
+    fn test_compare_parsers() {
+        // the kraken books share one state, replayed in turn they still agree
+        let capture = capture();
+        assert_eq!(compare(&capture, &kraken(), &kraken()), None);
+
+        let baseline = Parser {
+            parse: perturbed,
+            clear: kraken().clear,
+        };
+        let divergence = compare(&capture, &baseline, &kraken()).unwrap();
+        assert_eq!(divergence.frame, 2);
+        assert_eq!(divergence.raw, capture.lines().nth(2).unwrap());
+        assert_eq!(divergence.diff, ["bid 43000.20000: - != 0.50000000"]);
+        assert!(divergence
+            .to_string()
+            .starts_with("frame 2 diverges\nraw: [336,"));
+
+        // an error against a book
+        let broken = Parser {
+            parse: |raw| match raw.contains("\"as\"") {
+                true => Err(anyhow!("kraken: bad row")),
+                false => (kraken().parse)(raw),
+            },
+            clear: kraken().clear,
+        };
+        let divergence = compare(&capture, &kraken(), &broken).unwrap();
+        assert_eq!(divergence.frame, 0);
+        assert_eq!(divergence.diff, ["outcome: Book != Err(kraken: bad row)"]);
+    }
+
+    #[cfg(feature = "legacy-parsers")]
+    #[test]
+    fn test_legacy_kraken() {
+        // the legacy parser can't read the republished rows under "as"
+        let (baseline, _) = parsers("kraken", "legacy").unwrap();
+        let divergence = compare(&capture(), &baseline, &kraken()).unwrap();
+        assert_eq!(divergence.frame, 5);
+        assert!(divergence.diff[0].starts_with("outcome: Err("));
+    }
+}
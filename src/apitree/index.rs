@@ -0,0 +1,206 @@
+// Index / reference prices some venues publish, polled over rest like the
+// tickers: a scalar series per source to check the aggregate mid against.
+// They never touch the ladders.
+use super::ticker::number;
+use crate::config::IndexSource;
+use crate::orderbook::{get_unixtime, AggregatedOrderbook};
+use crate::sink::statsd;
+use anyhow::Result;
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use tokio::time::{interval, Duration};
+
+// (url of the index, pointer of its price)
+pub fn endpoint(exchange: &str, symbol: &str) -> Option<(String, &'static str)> {
+    match exchange {
+        // ex: BTC-USD
+        "okx" => Some((
+            format!(
+                "https://www.okx.com/api/v5/market/index-tickers?instId={}",
+                symbol.to_uppercase()
+            ),
+            "/data/0/idxPx",
+        )),
+        // the index of a futures contract, ex: PF_XBTUSD
+        "kraken" => Some((
+            format!(
+                "https://futures.kraken.com/derivatives/api/v3/tickers/{}",
+                symbol.to_uppercase()
+            ),
+            "/ticker/indexPrice",
+        )),
+        // ex: BTC-PERP
+        "coinbase_international" => Some((
+            format!(
+                "https://api.international.coinbase.com/api/v1/instruments/{}/quote",
+                symbol.to_uppercase()
+            ),
+            "/index_price",
+        )),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct IndexPrice {
+    pub price: BigDecimal,
+    // unix millis of the last successful poll
+    pub fetched: u128,
+}
+
+// source -> latest polled index
+static INDEXES: Lazy<Mutex<HashMap<String, IndexPrice>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub async fn fetch(client: &reqwest::Client, url: &str, price: &str) -> Result<BigDecimal> {
+    let body: Value = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    number(&body, price)
+}
+
+// a failed poll keeps the previous value
+pub fn update(source: &str, polled: Result<BigDecimal>, now: u128) {
+    match polled {
+        Ok(price) => {
+            let index = IndexPrice {
+                price,
+                fetched: now,
+            };
+            INDEXES.lock().unwrap().insert(source.to_string(), index);
+        }
+        Err(e) => {
+            statsd::count("index.errors", 1, &[("source", source)]);
+            warn!("index {}: {}", source, e);
+        }
+    }
+}
+
+async fn poll(source: String, url: String, price: &str, poll_secs: u64) {
+    let client = reqwest::Client::new();
+    let mut ticker = interval(Duration::from_secs(poll_secs));
+    loop {
+        ticker.tick().await;
+        let polled = fetch(&client, &url, price).await;
+        update(&source, polled, get_unixtime());
+    }
+}
+
+// a poller per configured source, the sources are checked by the config
+pub fn start(sources: &[IndexSource]) {
+    for source in sources {
+        if let Some((url, price)) = endpoint(&source.exchange, &source.symbol) {
+            info!(
+                "index {} of {} polled every {}s",
+                source.symbol, source.exchange, source.poll_secs
+            );
+            tokio::spawn(poll(source.exchange.clone(), url, price, source.poll_secs));
+        }
+    }
+}
+
+// Put the polled indexes in the index_prices of the aggregate and return
+// how far its spot mid is from each, in bps. Empty without a spot mid.
+pub fn apply(agg: &mut AggregatedOrderbook) -> BTreeMap<String, f64> {
+    let tmp = INDEXES.lock().unwrap();
+    let mid = agg.spot_mid();
+    let mut deviation = BTreeMap::new();
+    for (source, index) in tmp.iter() {
+        agg.index_prices.insert(source.clone(), index.price.clone());
+        if let Some(mid) = mid.as_ref().filter(|_| !index.price.is_zero()) {
+            let bps = (mid - &index.price) / &index.price * BigDecimal::from(10000);
+            if let Some(bps) = bps.to_f64() {
+                deviation.insert(source.clone(), bps);
+            }
+        }
+    }
+    deviation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Orderbook, Side};
+    use anyhow::anyhow;
+    use std::str::FromStr;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fetch_index() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/api/v5/market/index-tickers?instId=BTC-USD")
+            .with_body(r#"{"code":"0","msg":"","data":[{"instId":"BTC-USD","idxPx":"43012.5","ts":"1696405428703"}]}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/derivatives/api/v3/tickers/PF_XBTUSD")
+            .with_body(r#"{"result":"success","ticker":{"symbol":"PF_XBTUSD","markPrice":43020,"indexPrice":43010.25}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/v1/instruments/BTC-PERP/quote")
+            .with_status(503)
+            .create_async()
+            .await;
+        let client = reqwest::Client::new();
+        let fetch_at = |exchange: &str, symbol: &str, host: &str| {
+            let (url, price) = endpoint(exchange, symbol).unwrap();
+            (url.replace(host, &server.url()), price)
+        };
+        let (url, price) = fetch_at("okx", "btc-usd", "https://www.okx.com");
+        assert_eq!(fetch(&client, &url, price).await.unwrap(), dec("43012.5"));
+        let (url, price) = fetch_at("kraken", "PF_XBTUSD", "https://futures.kraken.com");
+        assert_eq!(fetch(&client, &url, price).await.unwrap(), dec("43010.25"));
+        let host = "https://api.international.coinbase.com";
+        let (url, price) = fetch_at("coinbase_international", "BTC-PERP", host);
+        assert!(fetch(&client, &url, price).await.is_err());
+        assert!(endpoint("bitstamp", "btcusd").is_none());
+    }
+
+    #[test]
+    fn test_mid_vs_index() {
+        // spot mid 100, the perp is left out of it
+        let mut spot = Orderbook::new("spot");
+        spot.insert(Side::Bid, dec("99"), dec("1"));
+        spot.insert(Side::Ask, dec("101"), dec("1"));
+        let mut perp = Orderbook::new("perp");
+        perp.insert(Side::Bid, dec("120"), dec("1"));
+        perp.insert(Side::Ask, dec("121"), dec("1"));
+        perp.derivatives = Some(Default::default());
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&spot);
+        agg.merge(&perp);
+
+        update("idx_okx", Ok(dec("99.5")), 1000);
+        update("idx_kraken", Ok(dec("100.5")), 1000);
+        update("idx_zero", Ok(dec("0")), 1000);
+        // a failing poll keeps the last value
+        update("idx_kraken", Err(anyhow!("http 503")), 2000);
+        let deviation = apply(&mut agg);
+        let bps = |source: &str| (deviation[source] * 1e4).round() / 1e4;
+        assert_eq!(bps("idx_okx"), 50.2513);
+        assert_eq!(bps("idx_kraken"), -49.7512);
+        assert!(!deviation.contains_key("idx_zero"));
+        assert_eq!(agg.index_prices["idx_kraken"], dec("100.5"));
+
+        let summary = agg.finalize().unwrap();
+        assert_eq!(summary.index_prices["idx_okx"], "99.5");
+
+        // no spot mid, no deviation
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&perp);
+        assert!(apply(&mut agg).is_empty());
+        assert_eq!(agg.index_prices["idx_okx"], dec("99.5"));
+    }
+}
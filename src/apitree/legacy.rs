@@ -0,0 +1,165 @@
+// The parsers as they were before their last rewrite, kept for one release
+// so compare-parsers can replay a capture through both. Their state is
+// their own, apart from the one of the current parsers.
+use super::evict_stale;
+use super::wsapi::{kraken_checksum, kraken_top, kraken_untop, ParseFunc, ParseOutcome, Touched};
+use crate::fixed::decimal;
+use crate::orderbook::{get_unixtime, Orderbook, Side};
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// exchange -> (parse, clear) of its legacy parser
+pub fn parser(exchange: &str) -> Option<(ParseFunc, fn())> {
+    match exchange {
+        "kraken" => Some((kraken_parser, kraken_clear)),
+        _ => None,
+    }
+}
+
+// before the republished levels: every as / bs replaced the book
+static KRAKEN: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static KRAKEN_TOP: Lazy<Mutex<HashMap<String, Touched>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn kraken_clear() {
+    KRAKEN.lock().unwrap().clear();
+    KRAKEN_TOP.lock().unwrap().clear();
+}
+
+fn kraken_parser(raw: &str) -> Result<ParseOutcome> {
+    if raw.starts_with('{') {
+        // systemStatus, heartbeat, subscriptionStatus
+        let result: Value = serde_json::from_str(raw)?;
+        if let Some(e) = result["errorMessage"].as_str() {
+            return Ok(ParseOutcome::ProtocolError(format!("kraken: {}", e)));
+        }
+        return Ok(ParseOutcome::Ignore);
+    }
+    let result: Vec<Value> = serde_json::from_str(raw)?;
+    if result.len() < 4 {
+        return Err(anyhow!("kraken: short message"));
+    }
+    let channel_name: String = serde_json::from_value(result[result.len() - 2].clone())?;
+    if !channel_name.starts_with("book") && channel_name != "ticker" {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let pair: String = serde_json::from_value(result[result.len() - 1].clone())?;
+    let mut tmp = KRAKEN.lock().unwrap();
+    evict_stale(&mut tmp, &pair, get_unixtime());
+    let mut top = KRAKEN_TOP.lock().unwrap();
+    let entry = tmp
+        .entry(pair.clone())
+        .or_insert_with(|| Arc::new(Orderbook::new("kraken")));
+    if channel_name.starts_with("book") {
+        #[derive(Deserialize, Debug)]
+        struct Data {
+            #[serde(default)]
+            r#as: Vec<[String; 3]>,
+            #[serde(default)]
+            bs: Vec<[String; 3]>,
+            #[serde(default)]
+            a: Vec<Vec<String>>,
+            #[serde(default)]
+            b: Vec<Vec<String>>,
+            // checksum of the book after the update
+            #[serde(default)]
+            c: String,
+        }
+        // channel_id: u64
+        // data: object
+        // - as: Vec<[String; 3]>
+        // - bs: Vec<[String; 3]>
+        // channel_name: String
+        // pair: String
+
+        let ob = Arc::make_mut(entry);
+        if let Some(touched) = top.remove(&pair) {
+            kraken_untop(ob, touched);
+        }
+        let mut checksum = None;
+        for r in result[1..result.len() - 2].iter() {
+            let data: Data = serde_json::from_value(r.clone())?;
+            if !data.c.is_empty() {
+                checksum = Some(data.c.parse::<u32>()?);
+            }
+
+            if !data.bs.is_empty() || !data.r#as.is_empty() {
+                ob.bid.clear();
+                ob.ask.clear();
+            }
+            for [price_str, quantity_str, _timestamp] in data.bs {
+                let price = decimal(&price_str)?;
+                let quantity = decimal(&quantity_str)?;
+                ob.insert(Side::Bid, price, quantity);
+            }
+            for v in data.b {
+                let price_str: &str = &v[0];
+                let quantity_str: &str = &v[1];
+                let price = decimal(price_str)?;
+                let quantity = decimal(quantity_str)?;
+                ob.insert(Side::Bid, price, quantity);
+            }
+            for [price_str, quantity_str, _timestamp] in data.r#as {
+                let price = decimal(&price_str)?;
+                let quantity = decimal(&quantity_str)?;
+                ob.insert(Side::Ask, price, quantity);
+            }
+            for v in data.a {
+                let price_str: &str = &v[0];
+                let quantity_str: &str = &v[1];
+                let price = decimal(price_str)?;
+                let quantity = decimal(quantity_str)?;
+                ob.insert(Side::Ask, price, quantity);
+            }
+        }
+        // we're subscribing to book-25, so do cleanup here
+        // the exchange/mod.rs side only gets the shared snapshot,
+        // so the orderbook didn't explicitly trim the orderbook.
+        ob.trim(25);
+        if let Some(expected) = checksum {
+            let actual = kraken_checksum(ob);
+            if actual != expected {
+                return Ok(ParseOutcome::FatalDesync(format!(
+                    "kraken: checksum {} != {}",
+                    actual, expected
+                )));
+            }
+        }
+        return Ok(ParseOutcome::Book(entry.clone()));
+    } else {
+        // data:
+        // - a: best ask [3] (price, whole lot volume, lot volume)
+        // - b: best bid [3]
+        // - c: close [2]
+        // - v: volume [2] (today, last24hr)
+        #[derive(Deserialize, Debug)]
+        struct Data {
+            #[serde(default)]
+            a: Option<(String, Value, String)>,
+            #[serde(default)]
+            b: Option<(String, Value, String)>,
+            #[serde(default)]
+            c: [String; 2],
+            #[serde(default)]
+            v: [String; 2],
+        }
+        let ob = Arc::make_mut(entry);
+        let touched = top.entry(pair).or_default();
+        for r in result[1..result.len() - 2].iter() {
+            let data: Data = serde_json::from_value(r.clone())?;
+            ob.volume = decimal(&data.v[1])?;
+            ob.last_price = decimal(&data.c[0])?;
+            for (side, level) in [(Side::Bid, data.b), (Side::Ask, data.a)] {
+                if let Some((price, _, volume)) = level {
+                    touched.extend(kraken_top(ob, side, decimal(&price)?, decimal(&volume)?));
+                }
+            }
+        }
+    }
+    Ok(ParseOutcome::Book(entry.clone()))
+}
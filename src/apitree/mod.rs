@@ -1,15 +1,223 @@
+pub mod clock;
+pub mod compare;
+pub mod index;
+#[cfg(feature = "legacy-parsers")]
+pub mod legacy;
 pub mod restapi;
+pub mod symbol_meta;
+pub mod ticker;
 pub mod wsapi;
+use crate::config::{Credentials, ParserStateSetting};
+use crate::orderbook::Orderbook;
 use anyhow::{anyhow, Result};
+use log::info;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 
+// defaults of ParserStateSetting until set_retention
+static TTL_MS: AtomicU64 = AtomicU64::new(3_600_000);
+static MAX_TRADES: AtomicUsize = AtomicUsize::new(10_000);
+static MAX_BOOKS: AtomicUsize = AtomicUsize::new(1000);
+
+/// How long, and how many, books and trades the parsers keep per exchange.
+pub fn set_retention(setting: &ParserStateSetting) {
+    TTL_MS.store(setting.ttl_secs * 1000, Ordering::Relaxed);
+    MAX_TRADES.store(setting.max_trades, Ordering::Relaxed);
+    MAX_BOOKS.store(setting.max_books, Ordering::Relaxed);
+}
+
+pub(crate) fn max_trades() -> usize {
+    MAX_TRADES.load(Ordering::Relaxed)
+}
+
+// Drop the books of a parser state map not updated within the ttl, then
+// the least recently updated ones beyond max_books. `keep` is the key being
+// updated, it is never dropped: a quiet book that just got a delta must not
+// lose its levels. Returns the number of dropped books.
+pub(crate) fn evict_stale(
+    map: &mut HashMap<String, Arc<Orderbook>>,
+    keep: &str,
+    now: u128,
+) -> usize {
+    let ttl = TTL_MS.load(Ordering::Relaxed) as u128;
+    evict(map, keep, now, ttl, MAX_BOOKS.load(Ordering::Relaxed))
+}
+
+fn evict(
+    map: &mut HashMap<String, Arc<Orderbook>>,
+    keep: &str,
+    now: u128,
+    ttl: u128,
+    max_books: usize,
+) -> usize {
+    let before = map.len();
+    map.retain(|key, ob| key == keep || ob.timestamp + ttl >= now);
+    if map.len() > max_books {
+        let mut oldest: Vec<(u128, String)> = map
+            .iter()
+            .filter(|(key, _)| *key != keep)
+            .map(|(key, ob)| (ob.timestamp, key.clone()))
+            .collect();
+        oldest.sort();
+        for (_, key) in oldest.iter().take(map.len() - max_books) {
+            map.remove(key);
+        }
+    }
+    let evicted = before - map.len();
+    if evicted > 0 {
+        info!("dropped {} stale parser books", evicted);
+    }
+    evicted
+}
+
+// (primary, secondary) currency codes of an independentreserve pair,
+// upper case with BTC spelled XBT. The canonical form is XBT-AUD, any case.
+pub fn indreserve_currencies(pair: &str) -> Result<(String, String)> {
+    let codes: Vec<String> = pair.split('-').map(|c| c.to_uppercase()).collect();
+    match codes.as_slice() {
+        [primary, secondary]
+            if [primary, secondary]
+                .iter()
+                .all(|c| !c.is_empty() && c.chars().all(|ch| ch.is_ascii_alphanumeric())) =>
+        {
+            let primary = if primary == "BTC" { "XBT" } else { primary };
+            Ok((primary.to_string(), secondary.clone()))
+        }
+        _ => Err(anyhow!(
+            "independentreserve: pair should be like XBT-AUD, got {}",
+            pair
+        )),
+    }
+}
+
+/// The pair can be subscribed to on the exchange.
+pub fn check_pair(exchange: &str, pair: &str) -> Result<()> {
+    if let Ok(api) = ws(exchange) {
+        api.channel(pair)?;
+    }
+    Ok(())
+}
+
+/// The credentials the venue wants before it streams, if any.
+pub fn check_login(exchange: &str, credentials: Option<&Credentials>) -> Result<()> {
+    match ws(exchange) {
+        Ok(api) if api.login.is_some() && credentials.is_none() => Err(anyhow!(
+            "{}: needs credentials to stream the books",
+            exchange
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// No more distinct pairs than the venue streams on a connection, ex: one
+/// on luno, whose url names the pair.
+pub fn check_pairs(exchange: &str, count: usize) -> Result<()> {
+    match ws(exchange) {
+        Ok(api) if api.max_pairs.is_some_and(|max| count > max) => Err(anyhow!(
+            "{}: {} pairs configured, a connection streams {} at most",
+            exchange,
+            count,
+            api.max_pairs.unwrap_or_default()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The pair can be subscribed to at this depth and interval. Only checked
+/// when one of them is set.
+pub fn check_depth(
+    exchange: &str,
+    pair: &str,
+    depth: Option<u32>,
+    interval_ms: Option<u64>,
+) -> Result<()> {
+    if depth.is_none() && interval_ms.is_none() {
+        return Ok(());
+    }
+    let api = ws(exchange)?;
+    api.depth_text(&api.channel(pair)?, depth, interval_ms)
+        .map_err(|e| anyhow!("{}: {}", exchange, e))?;
+    Ok(())
+}
+
+// (map name, entries) of every parser state map
+pub fn state_sizes() -> Vec<(&'static str, usize)> {
+    let mut result = wsapi::state_sizes();
+    result.push(("coinspot_trades", restapi::trades_size()));
+    result
+}
+
+/// The websocket api of the exchange `name`.
 pub fn ws(name: &str) -> Result<&'static wsapi::Api> {
     wsapi::WS_APIMAP
         .get(name)
         .ok_or_else(|| anyhow!("Exchange not supported"))
 }
 
+/// The rest api of the exchange `name`, for the ones polled.
 pub fn rest(name: &str) -> Result<restapi::Api> {
     restapi::REST_APIMAP
         .get(name)
         .ok_or_else(|| anyhow!("Exchange not supported"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indreserve_pair() {
+        let api = ws("independentreserve").unwrap();
+        for pair in ["XBT-AUD", "Xbt-Aud", "btc-aud"] {
+            assert_eq!(api.channel(pair).unwrap(), "orderbook/20/btc/aud");
+            let codes = restapi::indreserve_codes(pair).unwrap();
+            assert_eq!(codes, ("Xbt".to_string(), "Aud".to_string()));
+        }
+        assert_eq!(api.channel("USDT-AUD").unwrap(), "orderbook/20/usdt/aud");
+        // the forms of the other venues, or the channel itself
+        for pair in [
+            "XBT/AUD",
+            "xbtaud",
+            "orderbook/20/btc/aud",
+            "XBT-AUD-1",
+            "-AUD",
+        ] {
+            let e = check_pair("independentreserve", pair).unwrap_err();
+            assert!(e.to_string().contains("should be like XBT-AUD"), "{}", e);
+        }
+        // no normalization elsewhere
+        assert!(check_pair("kraken", "XBT/AUD").is_ok());
+        assert_eq!(ws("kraken").unwrap().channel("XBT/AUD").unwrap(), "XBT/AUD");
+    }
+
+    #[test]
+    fn test_evict_stale() {
+        let book = |timestamp| {
+            let mut ob = Orderbook::new("kraken");
+            ob.timestamp = timestamp;
+            Arc::new(ob)
+        };
+        let now = 10_000_000;
+        let mut map = HashMap::from([
+            ("stale".to_string(), book(0)),
+            ("quiet".to_string(), book(0)),
+            ("active".to_string(), book(now - 1000)),
+        ]);
+        // the default ttl is an hour
+        assert_eq!(evict_stale(&mut map, "quiet", now), 1);
+        let mut keys: Vec<&String> = map.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["active", "quiet"]);
+
+        // a flood of channels, capped to the latest updated ones
+        let mut map: HashMap<String, Arc<Orderbook>> = (0..10)
+            .map(|i| (format!("channel{}", i), book(now - 100 + i)))
+            .collect();
+        map.insert("quiet".to_string(), book(now - 1000));
+        assert_eq!(evict(&mut map, "quiet", now, 3_600_000, 3), 8);
+        let mut keys: Vec<&String> = map.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["channel8", "channel9", "quiet"]);
+    }
+}
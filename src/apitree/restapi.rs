@@ -1,22 +1,89 @@
-use crate::orderbook::{Orderbook, Side};
+use super::{max_trades, ticker};
+use crate::orderbook::{get_unixtime, Orderbook, Side};
 use anyhow::{anyhow, Result};
-use bigdecimal::BigDecimal;
+use bigdecimal::{BigDecimal, Zero};
 use chrono::prelude::*;
 use chrono::Duration;
 use futures_util::future::{join3, Future};
-use log::info;
+use log::{info, warn};
 use once_cell::sync::Lazy;
-use serde::de;
+use serde::de::{self, DeserializeOwned};
 use serde::Deserialize;
+use serde_json::Value;
 use std::collections::BTreeMap;
 use std::fmt;
 use std::ops::Bound::{Excluded, Included};
 use std::ops::Sub;
 use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 
-type OrderbookBoxedFuture = Box<dyn Fn(String) -> Pin<Box<dyn Future<Output = Result<Orderbook>>>>>;
+type OrderbookBoxedFuture =
+    Box<dyn Fn(String) -> Pin<Box<dyn Future<Output = Result<Orderbook>> + Send>> + Send + Sync>;
+
+// rest_timeout_secs, read when the client is built
+static TIMEOUT_SECS: AtomicU64 = AtomicU64::new(10);
+
+pub fn set_timeout(secs: u64) {
+    TIMEOUT_SECS.store(secs, Ordering::Relaxed);
+}
+
+// shared by the pollers, so they reuse its connection pool. Every call
+// gives up after rest_timeout_secs.
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(
+            TIMEOUT_SECS.load(Ordering::Relaxed),
+        ))
+        .build()
+        .unwrap()
+});
+
+/// An error status of a rest call, with the Retry-After the venue sent
+/// along, if any.
+#[derive(Debug)]
+pub struct HttpError {
+    pub url: String,
+    pub status: u16,
+    pub retry_after: Option<std::time::Duration>,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: http {}", self.url, self.status)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+// Retry-After as seconds or as an http date, none for a date gone by
+fn retry_after(value: &str, now: DateTime<Utc>) -> Option<std::time::Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(std::time::Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (at.with_timezone(&Utc) - now).to_std().ok()
+}
+
+// the response, or an HttpError for an error status
+pub(crate) fn checked(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return Ok(response);
+    }
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| retry_after(v, Utc::now()));
+    Err(HttpError {
+        url: response.url().to_string(),
+        status: status.as_u16(),
+        retry_after,
+    }
+    .into())
+}
 
 pub struct Api {
     pub endpoint: &'static str,
@@ -78,140 +145,146 @@ where
 struct CoinspotTrade {
     //coin: String,
     //market: String,
-    amount: f64,
+    #[serde(deserialize_with = "decimal")]
+    amount: BigDecimal,
     //total: f64,
     //rate: f64,
     #[serde(deserialize_with = "from_datestr")]
     solddate: NaiveDateTime,
 }
 
+// a number coinspot sends either as a json number or as a string
+fn decimal<'de, D>(d: D) -> Result<BigDecimal, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    match Value::deserialize(d)? {
+        Value::String(s) => BigDecimal::from_str(&s).map_err(de::Error::custom),
+        Value::Number(n) => BigDecimal::from_str(&n.to_string()).map_err(de::Error::custom),
+        other => Err(de::Error::custom(format!("not a number: {}", other))),
+    }
+}
+
 static COINSPOT_TRADES: Lazy<Mutex<BTreeMap<NaiveDateTime, CoinspotTrade>>> =
     Lazy::new(|| Mutex::new(BTreeMap::new()));
 
+// keep the latest `max` trades, against a venue replaying its history
+fn cap_trades(trades: &mut BTreeMap<NaiveDateTime, CoinspotTrade>, max: usize) {
+    while trades.len() > max {
+        trades.pop_first();
+    }
+}
+
+pub fn trades_size() -> usize {
+    COINSPOT_TRADES.lock().unwrap().len()
+}
+
+// A coinspot call. Its errors may come with a 200 and "status": "error",
+// and "message" is left out at times.
+async fn coinspot_get<T: DeserializeOwned>(url: String) -> Result<T> {
+    info!("calling {}...", url);
+    let body: Value = checked(CLIENT.get(&url).send().await?)?.json().await?;
+    let status = body["status"].as_str().unwrap_or_default();
+    if status != "ok" {
+        let message = body["message"].as_str().unwrap_or_default();
+        return Err(anyhow!("{}: {} {}", url, status, message));
+    }
+    Ok(serde_json::from_value(body)?)
+}
+
 async fn coinspot_orderbook(pair: String) -> Result<Orderbook> {
     let api = REST_APIMAP.get("coinspot").unwrap();
-    let endpoint = api.endpoint;
-    let mut ob = Orderbook::new("coinspot");
-
-    let api = format!("{}/pubapi/v2/orders/open/{}", endpoint, pair);
-    info!("calling {}...", api);
+    coinspot_book(api.endpoint, pair).await
+}
 
+// Only the open orders make the book. A failed last price or trades call
+// leaves that field at 0, which ticker::apply fills with the value of the
+// last poll where both went through, annotated with its age.
+async fn coinspot_book(endpoint: &str, pair: String) -> Result<Orderbook> {
     #[derive(Deserialize, Debug)]
     struct Level {
-        amount: f64, // amount that was bought
-        rate: f64,   // latest buy/sell price for that coin
+        #[serde(deserialize_with = "decimal")]
+        amount: BigDecimal, // amount that was bought
+        #[serde(deserialize_with = "decimal")]
+        rate: BigDecimal, // latest buy/sell price for that coin
     }
     #[derive(Deserialize, Debug)]
     struct OpenMarketOrders {
-        status: String,
-        message: String,
+        #[serde(default)]
         buyorders: Vec<Level>,
+        #[serde(default)]
         sellorders: Vec<Level>,
     }
-    let order_fut = async move {
-        let response = reqwest::get(&api).await?;
-        let orders: OpenMarketOrders = response.json().await?;
-        Result::<_, anyhow::Error>::Ok(orders)
-    };
-
     #[derive(Deserialize, Debug)]
     struct Price {
-        last: String,
+        #[serde(deserialize_with = "decimal")]
+        last: BigDecimal,
     }
     #[derive(Deserialize, Debug)]
     struct LatestPrice {
-        status: String,
-        #[serde(default)]
-        message: String,
         prices: Price,
     }
-    let [coin, market]: [&str; 2] = pair
-        .split('/')
-        .collect::<Vec<&str>>()
-        .try_into()
-        .map_err(|e| anyhow!("{:?}", e))?;
-    let api = if market == "AUD" {
-        format!("{}/pubapi/v2/latest/{}", endpoint, coin)
-    } else {
-        format!("{}/pubapi/v2/latest/{}", endpoint, pair)
-    };
-    info!("calling {}...", api);
-    let price_fut = async move {
-        let response = reqwest::get(&api).await?;
-        let last_price: LatestPrice = response.json().await?;
-        Result::<_, anyhow::Error>::Ok(last_price)
-    };
-
     #[derive(Deserialize, Debug)]
     struct Trades {
-        status: String,
         #[serde(default)]
-        message: String,
         buyorders: Vec<CoinspotTrade>,
         // this is not used. Volume on buy == volume on sell
         // sellorders: Vec<CoinspotTrade>,
     }
 
-    let api = format!("{}/pubapi/v2/orders/completed/{}", endpoint, pair);
-    info!("calling {}...", api);
-    let trade_fut = async move {
-        let response = reqwest::get(&api).await?;
-        let trades: Trades = response.json().await?;
-        Result::<_, anyhow::Error>::Ok(trades)
+    let [coin, market]: [&str; 2] = pair
+        .split('/')
+        .collect::<Vec<&str>>()
+        .try_into()
+        .map_err(|e| anyhow!("{:?}", e))?;
+    let latest = if market == "AUD" {
+        format!("{}/pubapi/v2/latest/{}", endpoint, coin)
+    } else {
+        format!("{}/pubapi/v2/latest/{}", endpoint, pair)
     };
-    let (orders_r, last_price_r, trade_r) = join3(order_fut, price_fut, trade_fut).await;
-    let orders = orders_r?;
-    let last_price = last_price_r?;
-    let trades = trade_r?;
-
-    if trades.status != "ok" {
-        return Err(anyhow!("trade {} {}", trades.status, trades.message));
-    }
-    let mut total_amount = 0.;
-    {
-        let mut tmp = COINSPOT_TRADES.lock().unwrap();
-        for trade in trades.buyorders {
-            tmp.insert(trade.solddate, trade);
-        }
-        let now = Utc::now().naive_utc();
-        let past = now.sub(Duration::hours(24));
-        for (_, trade) in tmp.range((Excluded(&past), Included(&now))) {
-            total_amount += trade.amount;
-        }
-        *tmp = tmp.split_off(&past);
-    }
-    ob.volume = BigDecimal::from_str(&format!("{}", total_amount))
-        .map_err(|e| anyhow!("parse volume fail: {:?}", e))?;
-
+    let (orders, last_price, trades) = join3(
+        coinspot_get::<OpenMarketOrders>(format!("{}/pubapi/v2/orders/open/{}", endpoint, pair)),
+        coinspot_get::<LatestPrice>(latest),
+        coinspot_get::<Trades>(format!("{}/pubapi/v2/orders/completed/{}", endpoint, pair)),
+    )
+    .await;
+    let fresh = last_price.is_ok() && trades.is_ok();
+    let orders = orders?;
     info!("{:?}", orders);
-    if orders.status != "ok" {
-        return Err(anyhow!("orders {}: {}", orders.status, orders.message));
-    }
+    let mut ob = Orderbook::new("coinspot");
     for lvl in orders.buyorders {
-        let price = BigDecimal::from_str(&format!("{}", lvl.rate))
-            .map_err(|e| anyhow!("parse price fail: {}", e))?;
-        let volume = BigDecimal::from_str(&format!("{}", lvl.amount))
-            .map_err(|e| anyhow!("volume price fail: {}", e))?;
-        ob.insert(Side::Bid, price, volume);
+        ob.insert(Side::Bid, lvl.rate, lvl.amount);
     }
     for lvl in orders.sellorders {
-        let price = BigDecimal::from_str(&format!("{}", lvl.rate))
-            .map_err(|e| anyhow!("parse price fail: {}", e))?;
-        let volume = BigDecimal::from_str(&format!("{}", lvl.amount))
-            .map_err(|e| anyhow!("parse volume fail: {}", e))?;
-        ob.insert(Side::Ask, price, volume);
+        ob.insert(Side::Ask, lvl.rate, lvl.amount);
     }
 
-    if last_price.status != "ok" {
-        return Err(anyhow!(
-            "last_price {}: {}",
-            last_price.status,
-            last_price.message
-        ));
+    match trades {
+        Ok(trades) => {
+            let mut total_amount = BigDecimal::zero();
+            let mut tmp = COINSPOT_TRADES.lock().unwrap();
+            for trade in trades.buyorders {
+                tmp.insert(trade.solddate, trade);
+            }
+            let now = Utc::now().naive_utc();
+            let past = now.sub(Duration::hours(24));
+            for (_, trade) in tmp.range((Excluded(&past), Included(&now))) {
+                total_amount += &trade.amount;
+            }
+            *tmp = tmp.split_off(&past);
+            cap_trades(&mut tmp, max_trades());
+            ob.volume = total_amount;
+        }
+        Err(e) => warn!("coinspot trades, volume left at 0 for the ticker: {}", e),
+    }
+    match last_price {
+        Ok(last_price) => ob.last_price = last_price.prices.last,
+        Err(e) => warn!("coinspot last price, left at 0 for the ticker: {}", e),
+    }
+    if fresh {
+        let polled = Ok((ob.last_price.clone(), ob.volume.clone()));
+        ticker::update("coinspot", polled, get_unixtime());
     }
-    ob.last_price = BigDecimal::from_str(&last_price.prices.last)
-        .map_err(|e| anyhow!("parse last price fail {}", e))?;
-
     Ok(ob)
 }
 
@@ -231,12 +304,22 @@ async fn btcmarkets_orderbook(pair: String) -> Result<Orderbook> {
     let endpoint = api.endpoint;
     let api = format!("{}/v3/markets/{}/orderbook", endpoint, pair);
     info!("calling {}...", api);
-    let response = reqwest::get(&api).await.map_err(|e| anyhow!("{:?}", e))?;
+    let response = CLIENT
+        .get(&api)
+        .send()
+        .await
+        .map_err(|e| anyhow!("{:?}", e))?;
+    let response = checked(response)?;
     let shot: OrderbookSnapshot = response.json().await.map_err(|e| anyhow!("{}", e))?;
 
     let api = format!("{}/v3/markets/{}/ticker", endpoint, pair);
     info!("calling {}...", api);
-    let response = reqwest::get(&api).await.map_err(|e| anyhow!("{:?}", e))?;
+    let response = CLIENT
+        .get(&api)
+        .send()
+        .await
+        .map_err(|e| anyhow!("{:?}", e))?;
+    let response = checked(response)?;
     let sum: MarketSummary = response.json().await.map_err(|e| anyhow!("{}", e))?;
     let mut ob = Orderbook::new("btcmarkets");
 
@@ -257,6 +340,13 @@ async fn btcmarkets_orderbook(pair: String) -> Result<Orderbook> {
     Ok(ob)
 }
 
+// XBT-AUD -> (Xbt, Aud), the currency codes of the rest queries
+pub(crate) fn indreserve_codes(pair: &str) -> Result<(String, String)> {
+    let (primary, secondary) = super::indreserve_currencies(pair)?;
+    let code = |c: &str| c[..1].to_string() + &c[1..].to_lowercase();
+    Ok((code(&primary), code(&secondary)))
+}
+
 async fn independentreserve_orderbook(pair: String) -> Result<Orderbook> {
     let api = REST_APIMAP.get("independentreserve").unwrap();
     #[derive(Deserialize, Debug)]
@@ -277,20 +367,20 @@ async fn independentreserve_orderbook(pair: String) -> Result<Orderbook> {
         last_price: f64,
         day_volume_xbt: f64,
     }
-    let args: Vec<&str> = pair.split('-').collect();
-    if args.len() != 2 {
-        return Err(anyhow!(
-            "pair in wrong format: should be Xbt-aud but got {}",
-            pair
-        ));
-    }
+    let (primary, secondary) = indreserve_codes(&pair)?;
+    let args = [primary, secondary];
     let endpoint = api.endpoint;
     let api = format!(
         "{}/Public/GetOrderbook?primaryCurrencyCode={}&secondaryCurrencyCode={}",
         endpoint, args[0], args[1]
     );
     info!("calling {}...", api);
-    let response = reqwest::get(&api).await.map_err(|e| anyhow!("{:?}", e))?;
+    let response = CLIENT
+        .get(&api)
+        .send()
+        .await
+        .map_err(|e| anyhow!("{:?}", e))?;
+    let response = checked(response)?;
     let shot: OrderbookSnapshot = response.json().await.map_err(|e| anyhow!("{}", e))?;
 
     let api = format!(
@@ -298,7 +388,12 @@ async fn independentreserve_orderbook(pair: String) -> Result<Orderbook> {
         endpoint, args[0], args[1]
     );
     info!("calling {}...", api);
-    let response = reqwest::get(&api).await.map_err(|e| anyhow!("{:?}", e))?;
+    let response = CLIENT
+        .get(&api)
+        .send()
+        .await
+        .map_err(|e| anyhow!("{:?}", e))?;
+    let response = checked(response)?;
     let sum: MarketSummary = response.json().await.map_err(|e| anyhow!("{}", e))?;
     let mut ob = Orderbook::new("independentreserve");
     for level in shot.buy_orders {
@@ -321,3 +416,153 @@ async fn independentreserve_orderbook(pair: String) -> Result<Orderbook> {
         .map_err(|e| anyhow!("parse volume fail: {:?}", e))?;
     Ok(ob)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::AggregatedOrderbook;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_cap_trades() {
+        let start =
+            NaiveDateTime::parse_from_str("2023-10-04T00:00:00", "%Y-%m-%dT%H:%M:%S").unwrap();
+        let mut trades = BTreeMap::new();
+        for i in 0..5 {
+            let solddate = start + Duration::seconds(i);
+            trades.insert(
+                solddate,
+                CoinspotTrade {
+                    amount: i.into(),
+                    solddate,
+                },
+            );
+        }
+        cap_trades(&mut trades, 3);
+        // the oldest go first
+        let amounts: Vec<BigDecimal> = trades.values().map(|t| t.amount.clone()).collect();
+        assert_eq!(amounts, vec![2.into(), 3.into(), 4.into()]);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:27:30Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let secs = |s: u64| Some(std::time::Duration::from_secs(s));
+        assert_eq!(retry_after("120", now), secs(120));
+        assert_eq!(retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now), secs(30));
+        // gone by, or neither
+        assert_eq!(retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now), None);
+        assert_eq!(retry_after("soon", now), None);
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Reply {
+        Ok,
+        // a 500
+        Fail,
+        // "status": "error" with a 200
+        Error,
+    }
+
+    // coinspot stand-in, [open orders, latest price, completed orders]
+    fn serve(replies: Arc<Mutex<[Reply; 3]>>) -> String {
+        let solddate = (Utc::now() - Duration::hours(1))
+            .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+            .to_string();
+        let bodies = [
+            // amounts as strings or numbers, no message
+            serde_json::json!({"status": "ok",
+                "buyorders": [{"amount": "0.5", "rate": 100}, {"amount": 2, "rate": "99.5"}],
+                "sellorders": [{"amount": 1, "rate": "101"}]}),
+            serde_json::json!({"status": "ok", "prices": {"last": "100.5"}}),
+            serde_json::json!({"status": "ok",
+                "buyorders": [{"amount": "0.25", "solddate": solddate}]}),
+        ];
+        let server = HttpServer::new(move || {
+            let mut app = App::new();
+            for (i, path) in [
+                "/pubapi/v2/orders/open/{tail:.*}",
+                "/pubapi/v2/latest/{tail:.*}",
+                "/pubapi/v2/orders/completed/{tail:.*}",
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                let (replies, body) = (replies.clone(), bodies[i].clone());
+                app = app.route(
+                    path,
+                    web::get().to(move || {
+                        let reply = replies.lock().unwrap()[i];
+                        let body = body.clone();
+                        async move {
+                            match reply {
+                                Reply::Ok => HttpResponse::Ok().json(body),
+                                Reply::Fail => HttpResponse::InternalServerError().finish(),
+                                Reply::Error => {
+                                    HttpResponse::Ok().json(serde_json::json!({"status": "error"}))
+                                }
+                            }
+                        }
+                    }),
+                );
+            }
+            app
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let endpoint = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        endpoint
+    }
+
+    #[actix_web::test]
+    async fn test_coinspot_partial_failures() {
+        use Reply::*;
+        let replies = Arc::new(Mutex::new([Ok; 3]));
+        let endpoint = serve(replies.clone());
+        // the book, with its last price and volume as published
+        let poll = async |reply: [Reply; 3]| -> Result<(Orderbook, String, String)> {
+            *replies.lock().unwrap() = reply;
+            let ob = coinspot_book(&endpoint, "BTC/AUD".to_string()).await?;
+            let mut agg = AggregatedOrderbook::new();
+            agg.merge(&ob);
+            ticker::apply(&mut agg, get_unixtime());
+            let (last, volume) = (
+                agg.last_price["coinspot"].to_string(),
+                agg.volume["coinspot"].to_string(),
+            );
+            Result::Ok((ob, last, volume))
+        };
+        let decimal = |s: &str| BigDecimal::from_str(s).unwrap();
+
+        let (ob, last, volume) = poll([Ok; 3]).await.unwrap();
+        assert_eq!(
+            ob.bid,
+            BTreeMap::from([
+                (decimal("99.5"), decimal("2")),
+                (decimal("100"), decimal("0.5"))
+            ])
+        );
+        assert_eq!(ob.ask, BTreeMap::from([(decimal("101"), decimal("1"))]));
+        assert_eq!((last.as_str(), volume.as_str()), ("100.5", "0.25"));
+
+        // the book stays, a failed call leaves its field at 0 and the ticker
+        // publishes the previous value
+        for reply in [[Ok, Error, Ok], [Ok, Ok, Fail], [Ok, Fail, Error]] {
+            let (ob, last, volume) = poll(reply).await.unwrap();
+            assert_eq!(ob.bid.len(), 2);
+            assert_eq!(ob.last_price.is_zero(), reply[1] != Ok);
+            assert_eq!(ob.volume.is_zero(), reply[2] != Ok);
+            assert_eq!((last.as_str(), volume.as_str()), ("100.5", "0.25"));
+        }
+
+        // only the open orders fail the book
+        for reply in [[Fail, Ok, Ok], [Error, Ok, Ok], [Error, Fail, Fail]] {
+            assert!(poll(reply).await.is_err());
+        }
+    }
+}
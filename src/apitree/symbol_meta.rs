@@ -0,0 +1,498 @@
+// Tick size, lot size and minimum order of the configured pairs, from the
+// exchange info endpoints of the venues that have one. Kept in a file
+// across restarts and fetched again once older than ttl_secs. A venue
+// without an endpoint, or one that failed, gets the configured defaults
+// until a fetch goes through; nothing waits on it.
+use super::ticker::number;
+use crate::config::{ExchangeSetting, MetaDefaults, SymbolMetaSetting};
+use crate::orderbook::get_unixtime;
+use crate::sink::{snapshot::write_atomic, statsd};
+use anyhow::{anyhow, Result};
+use bigdecimal::BigDecimal;
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::io::ErrorKind;
+use std::sync::Mutex;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+// url of the exchange info of the pair
+pub fn endpoint(exchange: &str, pair: &str) -> Option<String> {
+    match exchange {
+        // ex: btcusdt
+        "binance" => Some(format!(
+            "https://api.binance.com/api/v3/exchangeInfo?symbol={}",
+            pair.to_uppercase()
+        )),
+        "binanceus" => Some(format!(
+            "https://api.binance.us/api/v3/exchangeInfo?symbol={}",
+            pair.to_uppercase()
+        )),
+        // ex: BTC-USDT
+        "okx" => Some(format!(
+            "https://www.okx.com/api/v5/public/instruments?instType=SPOT&instId={}",
+            pair.to_uppercase()
+        )),
+        // every market at once, ex: BTC-AUD
+        "btcmarkets" => Some("https://api.btcmarkets.net/v3/markets".to_string()),
+        _ => None,
+    }
+}
+
+// sizes as the venue sent them, trailing zeros dropped
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SymbolMeta {
+    pub tick_size: String,
+    pub lot_size: String,
+    pub min_size: Option<String>,
+    pub min_notional: Option<String>,
+    // unix millis of the fetch, None for the configured defaults
+    pub fetched: Option<u64>,
+}
+
+// exchange -> pair -> metadata, as kept in the cache file
+pub type Cache = BTreeMap<String, BTreeMap<String, SymbolMeta>>;
+
+static CACHE: Lazy<Mutex<Cache>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+static DEFAULTS: Lazy<Mutex<HashMap<String, SymbolMeta>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn size(body: &Value, pointer: &str) -> Result<String> {
+    Ok(number(body, pointer)?.normalized().to_string())
+}
+
+fn power(decimals: &Value) -> Result<String> {
+    let decimals: i64 = match decimals {
+        Value::String(s) => s.parse()?,
+        v => v.as_i64().ok_or_else(|| anyhow!("bad decimals {}", v))?,
+    };
+    Ok(BigDecimal::new(1.into(), decimals).to_string())
+}
+
+pub fn parse(exchange: &str, pair: &str, body: &Value) -> Result<SymbolMeta> {
+    match exchange {
+        "binance" | "binanceus" => {
+            let symbol = body
+                .pointer("/symbols/0")
+                .ok_or_else(|| anyhow!("no symbol {}", pair))?;
+            let filter = |name: &str| {
+                symbol["filters"]
+                    .as_array()
+                    .and_then(|f| f.iter().find(|f| f["filterType"] == name))
+                    .ok_or_else(|| anyhow!("no {} filter", name))
+            };
+            let lot = filter("LOT_SIZE")?;
+            // MIN_NOTIONAL on the older listings
+            let notional = filter("NOTIONAL").or_else(|_| filter("MIN_NOTIONAL")).ok();
+            Ok(SymbolMeta {
+                tick_size: size(filter("PRICE_FILTER")?, "/tickSize")?,
+                lot_size: size(lot, "/stepSize")?,
+                min_size: size(lot, "/minQty").ok(),
+                min_notional: notional.and_then(|f| size(f, "/minNotional").ok()),
+                fetched: None,
+            })
+        }
+        "okx" => {
+            let instrument = body
+                .pointer("/data/0")
+                .ok_or_else(|| anyhow!("no instrument {}", pair))?;
+            Ok(SymbolMeta {
+                tick_size: size(instrument, "/tickSz")?,
+                lot_size: size(instrument, "/lotSz")?,
+                min_size: size(instrument, "/minSz").ok(),
+                min_notional: None,
+                fetched: None,
+            })
+        }
+        "btcmarkets" => {
+            let market = body
+                .as_array()
+                .and_then(|m| {
+                    m.iter()
+                        .find(|m| m["marketId"].as_str() == Some(&pair.to_uppercase()))
+                })
+                .ok_or_else(|| anyhow!("no market {}", pair))?;
+            Ok(SymbolMeta {
+                tick_size: power(&market["priceDecimals"])?,
+                lot_size: power(&market["amountDecimals"])?,
+                min_size: size(market, "/minOrderAmount").ok(),
+                min_notional: None,
+                fetched: None,
+            })
+        }
+        _ => Err(anyhow!("{}: no metadata endpoint", exchange)),
+    }
+}
+
+// a pair of a venue with an endpoint
+#[derive(Debug, PartialEq, Clone)]
+pub struct Target {
+    pub exchange: String,
+    pub pair: String,
+    pub url: String,
+}
+
+pub fn targets(exchange_pair_map: &HashMap<String, Vec<ExchangeSetting>>) -> Vec<Target> {
+    let mut result = vec![];
+    for (exchange, settings) in exchange_pair_map {
+        for setting in settings {
+            let Some(url) = endpoint(exchange, &setting.pair) else {
+                continue;
+            };
+            let target = Target {
+                exchange: exchange.clone(),
+                pair: setting.pair.clone(),
+                url,
+            };
+            // the same pair listed once per depth
+            if !result.contains(&target) {
+                result.push(target);
+            }
+        }
+    }
+    result
+}
+
+pub async fn fetch(client: &reqwest::Client, target: &Target) -> Result<SymbolMeta> {
+    let body: Value = client
+        .get(&target.url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    parse(&target.exchange, &target.pair, &body)
+}
+
+// Fetch the targets missing from the cache or older than ttl_ms. A failed
+// fetch keeps the entry it had. True when an entry was fetched.
+pub async fn refresh(
+    client: &reqwest::Client,
+    targets: &[Target],
+    cache: &mut Cache,
+    ttl_ms: u64,
+    now: u64,
+) -> bool {
+    let mut changed = false;
+    for target in targets {
+        let fresh = cache
+            .get(&target.exchange)
+            .and_then(|pairs| pairs.get(&target.pair))
+            .and_then(|meta| meta.fetched)
+            .is_some_and(|fetched| now.saturating_sub(fetched) < ttl_ms);
+        if fresh {
+            continue;
+        }
+        match fetch(client, target).await {
+            Ok(mut meta) => {
+                meta.fetched = Some(now);
+                cache
+                    .entry(target.exchange.clone())
+                    .or_default()
+                    .insert(target.pair.clone(), meta);
+                changed = true;
+            }
+            Err(e) => {
+                statsd::count("symbol_meta.errors", 1, &[("exchange", &target.exchange)]);
+                warn!("symbol_meta {} {}: {}", target.exchange, target.pair, e);
+            }
+        }
+    }
+    changed
+}
+
+// Empty when there is no file yet
+pub fn load(path: &str) -> Result<Cache> {
+    match std::fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data).map_err(|e| anyhow!("{}: {}", path, e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(anyhow!("{}: {}", path, e)),
+    }
+}
+
+pub fn save(path: &str, cache: &Cache) -> Result<()> {
+    write_atomic(path, &serde_json::to_vec(cache)?)
+}
+
+fn default_meta(defaults: &MetaDefaults) -> SymbolMeta {
+    SymbolMeta {
+        tick_size: defaults.tick_size.to_string(),
+        lot_size: defaults.lot_size.to_string(),
+        min_size: defaults.min_size.map(|s| s.to_string()),
+        min_notional: defaults.min_notional.map(|s| s.to_string()),
+        fetched: None,
+    }
+}
+
+// the fetched metadata of the pair, else the defaults of its exchange
+pub fn get(exchange: &str, pair: &str) -> Option<SymbolMeta> {
+    let fetched = CACHE
+        .lock()
+        .unwrap()
+        .get(exchange)
+        .and_then(|pairs| pairs.get(pair))
+        .cloned();
+    fetched.or_else(|| DEFAULTS.lock().unwrap().get(exchange).cloned())
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Listing {
+    pub pair: String,
+    #[serde(flatten)]
+    pub meta: Option<SymbolMeta>,
+}
+
+// the pairs per exchange, with what get has on each
+pub fn discovery(pairs: &BTreeMap<String, Vec<String>>) -> BTreeMap<String, Vec<Listing>> {
+    pairs
+        .iter()
+        .map(|(exchange, pairs)| {
+            let listings = pairs
+                .iter()
+                .map(|pair| Listing {
+                    pair: pair.clone(),
+                    meta: get(exchange, pair),
+                })
+                .collect();
+            (exchange.clone(), listings)
+        })
+        .collect()
+}
+
+async fn run(setting: SymbolMetaSetting, targets: Vec<Target>, mut cache: Cache) {
+    let client = reqwest::Client::new();
+    let ttl_ms = setting.ttl_secs * 1000;
+    let mut tick = interval(Duration::from_secs(setting.retry_secs));
+    tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tick.tick().await;
+        let now = get_unixtime() as u64;
+        if !refresh(&client, &targets, &mut cache, ttl_ms, now).await {
+            continue;
+        }
+        *CACHE.lock().unwrap() = cache.clone();
+        let Some(path) = setting.cache_path.clone() else {
+            continue;
+        };
+        let data = cache.clone();
+        match tokio::task::spawn_blocking(move || save(&path, &data)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("symbol_meta: {}", e),
+            Err(e) => error!("symbol_meta writer: {}", e),
+        }
+    }
+}
+
+// Serve the defaults and the cache file right away, fetch the rest in the
+// background
+pub fn start(
+    setting: &SymbolMetaSetting,
+    exchange_pair_map: &HashMap<String, Vec<ExchangeSetting>>,
+) {
+    *DEFAULTS.lock().unwrap() = setting
+        .defaults
+        .iter()
+        .map(|(exchange, defaults)| (exchange.clone(), default_meta(defaults)))
+        .collect();
+    let cache = match setting.cache_path.as_deref().map(load) {
+        Some(Ok(cache)) => cache,
+        Some(Err(e)) => {
+            warn!("symbol_meta cache skipped, {}", e);
+            BTreeMap::new()
+        }
+        None => BTreeMap::new(),
+    };
+    *CACHE.lock().unwrap() = cache.clone();
+    let targets = targets(exchange_pair_map);
+    info!(
+        "symbol_meta of {} pairs fetched in the background",
+        targets.len()
+    );
+    tokio::spawn(run(setting.clone(), targets, cache));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(exchange: &str, pair: &str, host: &str, server: &mockito::Server) -> Target {
+        Target {
+            exchange: exchange.to_string(),
+            pair: pair.to_string(),
+            url: endpoint(exchange, pair)
+                .unwrap()
+                .replace(host, &server.url()),
+        }
+    }
+
+    fn meta(tick_size: &str, lot_size: &str, fetched: u64) -> SymbolMeta {
+        SymbolMeta {
+            tick_size: tick_size.to_string(),
+            lot_size: lot_size.to_string(),
+            min_size: None,
+            min_notional: None,
+            fetched: Some(fetched),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_meta() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/api/v3/exchangeInfo?symbol=BTCUSDT")
+            .with_body(r#"{"timezone":"UTC","symbols":[{"symbol":"BTCUSDT","status":"TRADING","filters":[{"filterType":"PRICE_FILTER","minPrice":"0.01000000","maxPrice":"1000000.00000000","tickSize":"0.01000000"},{"filterType":"LOT_SIZE","minQty":"0.00001000","maxQty":"9000.00000000","stepSize":"0.00001000"},{"filterType":"NOTIONAL","minNotional":"5.00000000","applyMinToMarket":true}]}]}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/v3/markets")
+            .with_body(r#"[{"marketId":"ETH-AUD","baseAssetName":"ETH","quoteAssetName":"AUD","minOrderAmount":"0.001","amountDecimals":"6","priceDecimals":"2"},{"marketId":"BTC-AUD","baseAssetName":"BTC","quoteAssetName":"AUD","minOrderAmount":"0.0001","maxOrderAmount":"1000000","amountDecimals":"8","priceDecimals":"2","status":"Online"}]"#)
+            .create_async()
+            .await;
+        let client = reqwest::Client::new();
+        let binance = target("binance", "btcusdt", "https://api.binance.com", &server);
+        let meta = fetch(&client, &binance).await.unwrap();
+        assert_eq!(meta.tick_size, "0.01");
+        assert_eq!(meta.lot_size, "0.00001");
+        assert_eq!(meta.min_size.as_deref(), Some("0.00001"));
+        assert_eq!(meta.min_notional.as_deref(), Some("5"));
+        let btcmarkets = target(
+            "btcmarkets",
+            "btc-aud",
+            "https://api.btcmarkets.net",
+            &server,
+        );
+        let meta = fetch(&client, &btcmarkets).await.unwrap();
+        assert_eq!(meta.tick_size, "0.01");
+        assert_eq!(meta.lot_size, "0.00000001");
+        assert_eq!(meta.min_size.as_deref(), Some("0.0001"));
+        assert_eq!(meta.min_notional, None);
+        let missing = target(
+            "btcmarkets",
+            "XRP-AUD",
+            "https://api.btcmarkets.net",
+            &server,
+        );
+        assert!(fetch(&client, &missing).await.is_err());
+
+        let body = serde_json::json!({"code":"0","data":[{"instId":"BTC-USDT","tickSz":"0.1","lotSz":"0.00000001","minSz":"0.00001"}]});
+        let meta = parse("okx", "BTC-USDT", &body).unwrap();
+        assert_eq!(
+            (meta.tick_size.as_str(), meta.lot_size.as_str()),
+            ("0.1", "0.00000001")
+        );
+        assert!(endpoint("coinspot", "btc").is_none());
+    }
+
+    #[test]
+    fn test_cache_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("symbol_meta.json");
+        let path = path.to_str().unwrap();
+        assert!(load(path).unwrap().is_empty());
+        let mut cache = Cache::new();
+        let mut full = meta("0.01", "0.00001", 1000);
+        full.min_notional = Some("5".to_string());
+        cache
+            .entry("binance".to_string())
+            .or_default()
+            .insert("btcusdt".to_string(), full);
+        cache
+            .entry("btcmarkets".to_string())
+            .or_default()
+            .insert("BTC-AUD".to_string(), meta("0.01", "0.00000001", 2000));
+        save(path, &cache).unwrap();
+        assert_eq!(load(path).unwrap(), cache);
+        std::fs::write(path, "{not json").unwrap();
+        assert!(load(path).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ttl_refresh() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/api/v3/exchangeInfo?symbol=ETHUSDT")
+            .with_body(r#"{"symbols":[{"symbol":"ETHUSDT","filters":[{"filterType":"PRICE_FILTER","tickSize":"0.01000000"},{"filterType":"LOT_SIZE","minQty":"0.00010000","stepSize":"0.00010000"}]}]}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let client = reqwest::Client::new();
+        let targets = [target(
+            "binance",
+            "ethusdt",
+            "https://api.binance.com",
+            &server,
+        )];
+        // as restored from the file, a day old
+        let mut cache = Cache::new();
+        cache
+            .entry("binance".to_string())
+            .or_default()
+            .insert("ethusdt".to_string(), meta("0.1", "0.001", 1000));
+        let ttl_ms = 3_600_000;
+        assert!(!refresh(&client, &targets, &mut cache, ttl_ms, 1000 + ttl_ms - 1).await);
+        assert_eq!(cache["binance"]["ethusdt"].tick_size, "0.1");
+        let now = 1000 + 24 * ttl_ms;
+        assert!(refresh(&client, &targets, &mut cache, ttl_ms, now).await);
+        assert_eq!(cache["binance"]["ethusdt"], {
+            let mut fetched = meta("0.01", "0.0001", now);
+            fetched.min_size = Some("0.0001".to_string());
+            fetched
+        });
+        // fresh again until the ttl runs out
+        assert!(!refresh(&client, &targets, &mut cache, ttl_ms, now + 1).await);
+        assert!(refresh(&client, &targets, &mut cache, ttl_ms, now + ttl_ms).await);
+        mock.assert_async().await;
+
+        // a failed fetch keeps the stale entry
+        let gone = [Target {
+            url: format!("{}/gone", server.url()),
+            ..targets[0].clone()
+        }];
+        assert!(!refresh(&client, &gone, &mut cache, ttl_ms, now + 3 * ttl_ms).await);
+        assert_eq!(cache["binance"]["ethusdt"].fetched, Some(now + ttl_ms));
+    }
+
+    #[test]
+    fn test_defaults() {
+        let defaults = MetaDefaults {
+            tick_size: 0.01,
+            lot_size: 0.00000001,
+            min_size: None,
+            min_notional: Some(10.),
+        };
+        DEFAULTS
+            .lock()
+            .unwrap()
+            .insert("meta_test_coinspot".to_string(), default_meta(&defaults));
+        CACHE
+            .lock()
+            .unwrap()
+            .entry("meta_test_binance".to_string())
+            .or_default()
+            .insert("btcusdt".to_string(), meta("0.01", "0.00001", 1000));
+        let listed = |exchange: &str, pair: &str| {
+            BTreeMap::from([(exchange.to_string(), vec![pair.to_string()])])
+        };
+        let listing = discovery(&listed("meta_test_coinspot", "btc"));
+        let meta = listing["meta_test_coinspot"][0].meta.clone().unwrap();
+        assert_eq!(
+            (meta.tick_size.as_str(), meta.lot_size.as_str()),
+            ("0.01", "0.00000001")
+        );
+        assert_eq!(meta.min_notional.as_deref(), Some("10"));
+        assert_eq!(meta.fetched, None);
+        let listing = discovery(&listed("meta_test_binance", "btcusdt"));
+        let json = serde_json::to_value(&listing).unwrap();
+        assert_eq!(json["meta_test_binance"][0]["pair"], "btcusdt");
+        assert_eq!(json["meta_test_binance"][0]["tick_size"], "0.01");
+        assert_eq!(json["meta_test_binance"][0]["fetched"], 1000);
+        let listing = discovery(&listed("meta_test_kraken", "xbtusd"));
+        assert_eq!(listing["meta_test_kraken"][0].meta, None);
+        assert_eq!(
+            serde_json::to_string(&listing).unwrap(),
+            r#"{"meta_test_kraken":[{"pair":"xbtusd"}]}"#
+        );
+    }
+}
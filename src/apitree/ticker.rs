@@ -0,0 +1,221 @@
+use super::ws;
+use crate::config::ExchangeSetting;
+use crate::orderbook::{get_unixtime, AggregatedOrderbook};
+use crate::sink::statsd;
+use anyhow::{anyhow, Result};
+use bigdecimal::BigDecimal;
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::time::{interval, Duration};
+
+// REST ticker of the exchanges whose websocket feed has none:
+// (url of the pair, pointer of the last price, pointer of the 24h volume)
+pub fn endpoint(exchange: &str, pair: &str) -> Option<(String, &'static str, &'static str)> {
+    match exchange {
+        "binance" => Some((
+            format!(
+                "https://api.binance.com/api/v3/ticker/24hr?symbol={}",
+                pair.to_uppercase()
+            ),
+            "/lastPrice",
+            "/volume",
+        )),
+        "binanceus" => Some((
+            format!(
+                "https://api.binance.us/api/v3/ticker/24hr?symbol={}",
+                pair.to_uppercase()
+            ),
+            "/lastPrice",
+            "/volume",
+        )),
+        "bitstamp" | "bitstamp_full" => Some((
+            format!(
+                "https://www.bitstamp.net/api/v2/ticker/{}/",
+                pair.to_lowercase()
+            ),
+            "/last",
+            "/volume",
+        )),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Ticker {
+    pub last_price: BigDecimal,
+    pub volume: BigDecimal,
+    // unix millis of the last successful poll
+    pub fetched: u128,
+}
+
+// exchange -> latest polled ticker
+static TICKERS: Lazy<Mutex<HashMap<String, Ticker>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub(super) fn number(body: &Value, pointer: &str) -> Result<BigDecimal> {
+    match body.pointer(pointer) {
+        Some(Value::String(s)) => Ok(BigDecimal::from_str(s)?),
+        Some(Value::Number(n)) => Ok(BigDecimal::from_str(&n.to_string())?),
+        _ => Err(anyhow!("no number at {}", pointer)),
+    }
+}
+
+// (last price, volume) from the ticker at url
+pub async fn fetch(
+    client: &reqwest::Client,
+    url: &str,
+    last: &str,
+    volume: &str,
+) -> Result<(BigDecimal, BigDecimal)> {
+    let body: Value = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok((number(&body, last)?, number(&body, volume)?))
+}
+
+// a failed poll keeps the previous value, aging
+pub fn update(exchange: &str, polled: Result<(BigDecimal, BigDecimal)>, now: u128) {
+    match polled {
+        Ok((last_price, volume)) => {
+            let ticker = Ticker {
+                last_price,
+                volume,
+                fetched: now,
+            };
+            TICKERS.lock().unwrap().insert(exchange.to_string(), ticker);
+        }
+        Err(e) => {
+            statsd::count("ticker.errors", 1, &[("exchange", exchange)]);
+            warn!("ticker {}: {}", exchange, e);
+        }
+    }
+}
+
+async fn poll(exchange: String, url: String, last: &str, volume: &str, poll_secs: u64) {
+    let client = reqwest::Client::new();
+    let mut ticker = interval(Duration::from_secs(poll_secs));
+    loop {
+        ticker.tick().await;
+        let polled = fetch(&client, &url, last, volume).await;
+        update(&exchange, polled, get_unixtime());
+    }
+}
+
+// Spawns a poller for every websocket exchange without a ticker on its feed.
+// Only the first pair of an exchange is aggregated, so only it is polled.
+pub fn start(exchange_pairs: &HashMap<String, Vec<ExchangeSetting>>, poll_secs: u64) {
+    for (exchange, settings) in exchange_pairs {
+        let setting = match settings.first() {
+            Some(setting) if setting.ws_api => setting,
+            _ => continue,
+        };
+        if ws(exchange).is_ok_and(|api| api.has_ticker) {
+            continue;
+        }
+        if let Some((url, last, volume)) = endpoint(exchange, &setting.pair) {
+            info!("ticker of {} polled every {}s", exchange, poll_secs);
+            tokio::spawn(poll(exchange.clone(), url, last, volume, poll_secs));
+        }
+    }
+}
+
+// Fill the last price and volume the books of the aggregate lack with the
+// polled tickers. Only the scalar maps change, the ladders are left as
+// merged. Returns the age in millis of the values used, per exchange.
+pub fn apply(agg: &mut AggregatedOrderbook, now: u128) -> BTreeMap<String, u64> {
+    let tmp = TICKERS.lock().unwrap();
+    let mut ages = BTreeMap::new();
+    for (exchange, ticker) in tmp.iter() {
+        if !agg.timestamp.contains_key(exchange) {
+            continue;
+        }
+        let mut used = false;
+        for (map, value) in [
+            (&mut agg.last_price, &ticker.last_price),
+            (&mut agg.volume, &ticker.volume),
+        ] {
+            used |= map.fill_zero(exchange, value);
+        }
+        if used {
+            ages.insert(exchange.clone(), now.saturating_sub(ticker.fetched) as u64);
+        }
+    }
+    ages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Orderbook, Side};
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/api/v3/ticker/24hr?symbol=TCKUSDT")
+            .with_body(r#"{"symbol":"TCKUSDT","lastPrice":"37000.10","volume":"1234.5"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/v2/ticker/tckusd/")
+            .with_status(500)
+            .create_async()
+            .await;
+        let client = reqwest::Client::new();
+        let (url, last, volume) = endpoint("binance", "tckusdt").unwrap();
+        let url = url.replace("https://api.binance.com", &server.url());
+        let polled = fetch(&client, &url, last, volume).await.unwrap();
+        assert_eq!(polled, (dec("37000.10"), dec("1234.5")));
+
+        let (url, last, volume) = endpoint("bitstamp", "tckusd").unwrap();
+        let url = url.replace("https://www.bitstamp.net", &server.url());
+        assert!(fetch(&client, &url, last, volume).await.is_err());
+        assert!(endpoint("kraken", "XBT/USD").is_none());
+    }
+
+    #[test]
+    fn test_backfill() {
+        let mut ob = Orderbook::new("tck_binance");
+        ob.insert(Side::Bid, dec("100"), dec("1"));
+        ob.insert(Side::Ask, dec("101"), dec("2"));
+        let mut with_ticker = Orderbook::new("tck_kraken");
+        with_ticker.last_price = dec("99");
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&ob);
+        agg.merge(&with_ticker);
+        let (bid, ask) = (agg.bid.clone(), agg.ask.clone());
+
+        // nothing polled yet
+        assert!(apply(&mut agg, 1000).is_empty());
+        update("tck_binance", Ok((dec("100.5"), dec("42"))), 1000);
+        update("tck_kraken", Ok((dec("98"), dec("7"))), 1000);
+        let ages = apply(&mut agg, 1500);
+        assert_eq!(agg.last_price["tck_binance"], dec("100.5"));
+        assert_eq!(agg.volume["tck_binance"], dec("42"));
+        // the feed's own value wins, its missing volume is filled
+        assert_eq!(agg.last_price["tck_kraken"], dec("99"));
+        assert_eq!(agg.volume["tck_kraken"], dec("7"));
+        assert_eq!(ages["tck_binance"], 500);
+        // no re-merge, the ladders are as merged
+        assert_eq!((agg.bid.clone(), agg.ask.clone()), (bid, ask));
+
+        // a failing poll keeps the last known value, older
+        update("tck_binance", Err(anyhow!("http 500")), 5000);
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&ob);
+        let ages = apply(&mut agg, 6000);
+        assert_eq!(agg.last_price["tck_binance"], dec("100.5"));
+        assert_eq!(ages["tck_binance"], 5000);
+    }
+}
@@ -1,17 +1,60 @@
-use crate::orderbook::{Orderbook, Side};
+use super::evict_stale;
+use crate::fixed::decimal;
+use crate::orderbook::{get_unixtime, Derivatives, Orderbook, Side, Trade};
 use anyhow::{anyhow, Result};
-use bigdecimal::BigDecimal;
+use bigdecimal::{BigDecimal, Zero};
+use flate2::read::GzDecoder;
 use formatx::formatx;
-use log::error;
 use once_cell::sync::Lazy;
 use phf::phf_map;
 use serde::Deserialize;
+use serde_json::value::RawValue;
 use serde_json::Value;
-use std::collections::HashMap;
-use std::str::FromStr;
-use std::sync::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// The stateful parsers keep their working books behind an Arc and mutate
+// them with Arc::make_mut, so a snapshot is only deep copied when the
+// previous one is still held downstream.
+// An Err is a frame that could not be read, handled as a ProtocolError.
+pub(crate) type ParseFunc = fn(&str) -> Result<ParseOutcome>;
+// None when the frame is not from a trade channel
+type TradeParseFunc = fn(&str) -> Result<Option<Vec<Trade>>>;
+#[derive(Debug, PartialEq)]
+pub enum ParseOutcome {
+    Book(Arc<Orderbook>),
+    // acks, heartbeats, status and channels we don't model
+    Ignore,
+    // the venue announced maintenance, or the end of it
+    Status(VenueStatus),
+    // a frame we can't use. Logged and counted, the connection stays up
+    ProtocolError(String),
+    // the local book can't be trusted anymore: reconnect and resubscribe
+    FatalDesync(String),
+}
+
+// What a venue says of itself on its feed. Any restricted mode, where the
+// book no longer trades, counts as maintenance.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VenueStatus {
+    Online,
+    // as the venue names it, ex: maintenance, cancel_only
+    Maintenance(String),
+}
+
+impl VenueStatus {
+    // "Post Only" -> post_only
+    fn of(status: &str) -> VenueStatus {
+        let status = status.trim().to_lowercase().replace([' ', '-'], "_");
+        match status.as_str() {
+            "online" => VenueStatus::Online,
+            _ => VenueStatus::Maintenance(status),
+        }
+    }
+}
 
-type ParseFunc = fn(&str) -> Result<Option<Orderbook>>;
 #[derive(Clone)]
 pub struct Api {
     pub endpoint: &'static str,
@@ -19,29 +62,457 @@ pub struct Api {
     pub subscribe_template: &'static [&'static str],
     // raw String as input
     pub parse: ParseFunc,
+    // (pair, level), only sent for the pairs with trades enabled
+    pub trade_subscribe_template: &'static [&'static str],
+    // tried before parse on the connections subscribed to trades
+    pub parse_trades: Option<TradeParseFunc>,
     // render url with data
     pub render_url: bool,
+    // every message comes wrapped as {"stream": .., "data": ..}. With
+    // render_url, the url is rendered from the stream names of all pairs:
+    // the subscribe templates joined by "/".
+    pub combined_stream: bool,
+    // the feed carries last price and 24h volume. Without, they can be
+    // polled from the rest ticker, see ticker::start
+    pub has_ticker: bool,
     // wait second, heartbeat message. None means no need to send heartbeat
-    pub heartbeat: Option<(u64, &'static str)>,
+    pub heartbeat: Option<(u64, Heartbeat)>,
     // cleanup function when error
     pub clear: fn() -> (),
     // reconnect to the endpoint every {value} seconds
     pub reconnect_sec: Option<u64>,
+    // the channel name of a configured pair, when the venue names it
+    // differently. Fails on a pair the venue can't have.
+    pub normalize_pair: Option<fn(&str) -> Result<String>>,
+    // the depths and update intervals a pair can be subscribed at. Only
+    // on combined streams, where the stream name tells the books apart.
+    pub depth_streams: Option<DepthStreams>,
+    // pairs can be added to and removed from a live connection. None when
+    // the subscriptions only change with a reconnect.
+    pub live_pairs: Option<LivePairs>,
+    // binary frames come compressed, inflated before parse
+    pub compression: Option<Compression>,
+    // the venue pings in its own frames: the reply to send for a frame,
+    // None for the other frames
+    pub pong: Option<fn(&str) -> Option<String>>,
+    // (key id, secret), sent before anything else with the credentials of
+    // the first pair setting
+    pub login: Option<&'static str>,
+    // the endpoint is a rest call handing out the websocket url, POSTed
+    // before every connect: (response body) -> the url to dial
+    pub bootstrap: Option<fn(&str) -> Result<Bootstrap>>,
+    // the feed only sends changes, the books start from a rest snapshot
+    pub seed: Option<Seed>,
+    // pairs a connection streams, None => any
+    pub max_pairs: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+}
+
+impl Compression {
+    pub fn inflate(&self, frame: &[u8]) -> Result<String> {
+        let mut text = String::new();
+        match self {
+            Compression::Gzip => GzDecoder::new(frame).read_to_string(&mut text)?,
+        };
+        Ok(text)
+    }
+}
+
+// the frame a heartbeat is sent in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Heartbeat {
+    Binary(&'static str),
+    // ex: the literal ping okx wants, answered with a pong text frame
+    Text(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bootstrap {
+    pub url: String,
+    // the heartbeat interval the venue asks for, over the one of the api
+    pub heartbeat: Option<Duration>,
+}
+
+#[derive(Clone)]
+pub struct DepthStreams {
+    // (pair, depth, interval_ms)
+    pub template: &'static str,
+    pub depths: &'static [u32],
+    // the first one is the default
+    pub intervals_ms: &'static [u64],
+}
+
+#[derive(Clone)]
+pub struct LivePairs {
+    // (pair, level), for the pairs after the first one
+    pub subscribe: &'static [&'static str],
+    // (pair, level)
+    pub unsubscribe: &'static [&'static str],
+    // drops the parser state of a pair
+    pub forget: fn(&str),
+}
+
+#[derive(Clone)]
+pub struct Seed {
+    // the pairs waiting for a snapshot, their changes are buffered meanwhile
+    pub pending: fn() -> Vec<String>,
+    // (pair), the rest snapshot
+    pub url: &'static str,
+    // (pair, body) the snapshot into the parser state, with the buffered
+    // changes newer than it on top
+    pub apply: fn(&str, &str) -> Result<()>,
 }
 
 impl Api {
+    pub fn channel(&self, pair: &str) -> Result<String> {
+        match self.normalize_pair {
+            Some(normalize) => normalize(pair),
+            None => Ok(pair.to_string()),
+        }
+    }
+
     // utility to render the subscription text
     pub fn subscribe_text(&self, pair: &str, level: u32) -> Result<Vec<String>> {
-        let mut result = vec![];
-        for template in self.subscribe_template.iter() {
-            result
-                .push(formatx!(template.to_string(), pair, level).map_err(|e| anyhow!("{:?}", e))?);
+        render(self.subscribe_template, pair, level)
+    }
+
+    pub fn trade_subscribe_text(&self, pair: &str, level: u32) -> Result<Vec<String>> {
+        render(self.trade_subscribe_template, pair, level)
+    }
+
+    // the subscription of a pair added to a live connection
+    pub fn add_text(&self, pair: &str, level: u32) -> Result<Vec<String>> {
+        match &self.live_pairs {
+            Some(live) => render(live.subscribe, pair, level),
+            None => Err(anyhow!("pairs can't be added to a live connection")),
+        }
+    }
+
+    pub fn remove_text(&self, pair: &str, level: u32) -> Result<Vec<String>> {
+        match &self.live_pairs {
+            Some(live) => render(live.unsubscribe, pair, level),
+            None => Err(anyhow!("pairs can't be removed from a live connection")),
+        }
+    }
+
+    // the level a pair keeping `level` levels is subscribed at: the
+    // shallowest depth the venue offers that covers it, or the deepest one.
+    // SUBSCRIBE_LEVEL where the venue takes no depth.
+    pub fn subscribe_level(&self, level: Option<u32>) -> u32 {
+        match (level, &self.depth_streams) {
+            (Some(level), Some(streams)) => streams
+                .depths
+                .iter()
+                .copied()
+                .find(|d| *d >= level)
+                .or(streams.depths.last().copied())
+                .unwrap_or(SUBSCRIBE_LEVEL),
+            _ => SUBSCRIBE_LEVEL,
+        }
+    }
+
+    // the subscription of a pair at a given depth and interval, the
+    // defaults for the ones not set
+    pub fn depth_text(
+        &self,
+        pair: &str,
+        depth: Option<u32>,
+        interval_ms: Option<u64>,
+    ) -> Result<String> {
+        let streams = self
+            .depth_streams
+            .as_ref()
+            .ok_or_else(|| anyhow!("depth and interval_ms can't be set on this exchange"))?;
+        let depth = depth.unwrap_or(SUBSCRIBE_LEVEL);
+        if !streams.depths.contains(&depth) {
+            return Err(anyhow!("depth {} not in {:?}", depth, streams.depths));
+        }
+        let interval_ms = interval_ms.unwrap_or(streams.intervals_ms[0]);
+        if !streams.intervals_ms.contains(&interval_ms) {
+            return Err(anyhow!(
+                "interval_ms {} not in {:?}",
+                interval_ms,
+                streams.intervals_ms
+            ));
+        }
+        formatx!(streams.template.to_string(), pair, depth, interval_ms)
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+
+    // parse a book payload, tagged with the pair of its stream if known
+    pub fn parse_book(&self, pair: Option<String>, payload: &str) -> ParseOutcome {
+        match (self.parse)(payload) {
+            Ok(ParseOutcome::Book(mut ob)) => {
+                if pair.is_some() {
+                    Arc::make_mut(&mut ob).pair = pair;
+                }
+                ParseOutcome::Book(ob)
+            }
+            Ok(outcome) => outcome,
+            Err(e) => ParseOutcome::ProtocolError(e.to_string()),
+        }
+    }
+}
+
+// the level the pairs are subscribed at, unless configured
+pub const SUBSCRIBE_LEVEL: u32 = 20;
+
+// the pair of a combined stream is its name up to the first '@'
+pub fn stream_pair(stream: &str) -> String {
+    stream.split('@').next().unwrap_or_default().to_string()
+}
+
+// Strip the envelope of a combined stream message, with the stream name,
+// ex: "btcusdt@depth20@100ms". Messages without the envelope are passed
+// through untouched.
+pub fn unwrap_stream(raw: &str) -> (Option<String>, &str) {
+    #[derive(Deserialize)]
+    struct Envelope<'a> {
+        stream: String,
+        #[serde(borrow)]
+        data: &'a RawValue,
+    }
+    match serde_json::from_str::<Envelope>(raw) {
+        Ok(envelope) => (Some(envelope.stream), envelope.data.get()),
+        Err(_) => (None, raw),
+    }
+}
+
+// deltas kept per pair while its snapshot is fetched
+const SEED_BUFFER: usize = 1000;
+
+enum SeedSync<D> {
+    // deltas received before the snapshot, oldest first
+    Buffering(Vec<D>),
+    // the sequence or time the book is at, 0 on the venues with neither
+    Live(u64),
+}
+
+// a delta against the sequence of its book
+enum Step {
+    // the book has it already
+    Stale,
+    // the next one, the sequence of the book after it
+    Next(u64),
+    // the ones up to this sequence went missing
+    Gap(u64),
+    // the book can't take it, fetched again
+    Resync(&'static str),
+}
+
+// the step of the venues whose deltas carry no sequence: each is the next
+fn unsequenced<D>(_: u64, _: &D) -> Step {
+    Step::Next(0)
+}
+
+// The books of a venue seeded from a rest snapshot, see [`Seed`]. The
+// deltas of a pair are buffered until its snapshot comes in, the ones newer
+// than it then go on top. A book dropped as stale, or one a delta doesn't
+// fit, is fetched again.
+struct SeededBooks<D> {
+    name: &'static str,
+    books: Mutex<HashMap<String, Arc<Orderbook>>>,
+    sync: Mutex<HashMap<String, SeedSync<D>>>,
+}
+
+impl<D> SeededBooks<D> {
+    fn new(name: &'static str) -> SeededBooks<D> {
+        SeededBooks {
+            name,
+            books: Mutex::new(HashMap::new()),
+            sync: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn clear(&self) {
+        self.books.lock().unwrap().clear();
+        self.sync.lock().unwrap().clear();
+    }
+
+    fn forget(&self, pair: &str) {
+        self.books.lock().unwrap().remove(pair);
+        self.sync.lock().unwrap().remove(pair);
+    }
+
+    fn pending(&self) -> Vec<String> {
+        self.sync
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, sync)| matches!(sync, SeedSync::Buffering(_)))
+            .map(|(pair, _)| pair.clone())
+            .collect()
+    }
+
+    fn buffering(&self, pair: &str) -> bool {
+        matches!(
+            self.sync.lock().unwrap().get(pair),
+            Some(SeedSync::Buffering(_))
+        )
+    }
+
+    // the book of a whole snapshot at `at`, the deltas buffered before it
+    // dropped
+    fn reset(&self, pair: &str, ob: Orderbook, at: u64) -> Arc<Orderbook> {
+        let ob = Arc::new(ob);
+        self.books
+            .lock()
+            .unwrap()
+            .insert(pair.to_string(), ob.clone());
+        self.sync
+            .lock()
+            .unwrap()
+            .insert(pair.to_string(), SeedSync::Live(at));
+        ob
+    }
+
+    // The rest snapshot of `pair` at `at`, the buffered deltas `step` tells
+    // newer applied on top. An error leaves the pair pending, ex: a
+    // snapshot older than the buffered deltas. No-op unless buffering.
+    fn seed(
+        &self,
+        pair: &str,
+        mut ob: Orderbook,
+        at: u64,
+        step: impl Fn(u64, &D) -> Step,
+        mut apply: impl FnMut(&mut Orderbook, &D) -> Result<bool>,
+    ) -> Result<()> {
+        let mut sync = self.sync.lock().unwrap();
+        let Some(SeedSync::Buffering(buffered)) = sync.get(pair) else {
+            return Ok(());
+        };
+        let mut last = at;
+        for delta in buffered {
+            match step(last, delta) {
+                Step::Stale => continue,
+                Step::Next(next) => {
+                    if !apply(&mut ob, delta)? {
+                        return Err(anyhow!(
+                            "{}: buffered diffs of {} don't fit its snapshot",
+                            self.name,
+                            pair
+                        ));
+                    }
+                    last = next;
+                }
+                Step::Gap(next) => {
+                    return Err(anyhow!(
+                        "{}: buffered diffs of {} jump from {} to {}",
+                        self.name,
+                        pair,
+                        last,
+                        next
+                    ))
+                }
+                Step::Resync(reason) => {
+                    return Err(anyhow!(
+                        "{}: {} in the buffered diffs of {}",
+                        self.name,
+                        reason,
+                        pair
+                    ))
+                }
+            }
+        }
+        sync.insert(pair.to_string(), SeedSync::Live(last));
+        self.books
+            .lock()
+            .unwrap()
+            .insert(pair.to_string(), Arc::new(ob));
+        Ok(())
+    }
+
+    // A delta of `pair`, buffered until its snapshot, else applied when
+    // `step` tells it's the next one. `apply` false means the book missed
+    // a delta.
+    fn live(
+        &self,
+        pair: &str,
+        delta: D,
+        step: impl FnOnce(u64, &D) -> Step,
+        apply: impl FnOnce(&mut Orderbook, &D) -> Result<bool>,
+    ) -> Result<ParseOutcome> {
+        let mut sync = self.sync.lock().unwrap();
+        let last = match sync
+            .entry(pair.to_string())
+            .or_insert_with(|| SeedSync::Buffering(vec![]))
+        {
+            SeedSync::Buffering(buffered) => {
+                if buffered.len() == SEED_BUFFER {
+                    return Ok(ParseOutcome::FatalDesync(format!(
+                        "{}: no snapshot for {} after {} diffs",
+                        self.name, pair, SEED_BUFFER
+                    )));
+                }
+                buffered.push(delta);
+                return Ok(ParseOutcome::Ignore);
+            }
+            SeedSync::Live(last) => last,
+        };
+        let next = match step(*last, &delta) {
+            Step::Stale => return Ok(ParseOutcome::Ignore),
+            Step::Next(next) => next,
+            Step::Gap(next) => {
+                return Ok(ParseOutcome::FatalDesync(format!(
+                    "{}: {} jumped from {} to {}",
+                    self.name, pair, last, next
+                )))
+            }
+            Step::Resync(reason) => {
+                self.books.lock().unwrap().remove(pair);
+                sync.insert(pair.to_string(), SeedSync::Buffering(vec![]));
+                return Ok(ParseOutcome::ProtocolError(format!(
+                    "{}: {} on {}, resync",
+                    self.name, reason, pair
+                )));
+            }
+        };
+        let mut books = self.books.lock().unwrap();
+        evict_stale(&mut books, pair, get_unixtime());
+        let Some(entry) = books.get_mut(pair) else {
+            // buffered until the next snapshot
+            sync.insert(pair.to_string(), SeedSync::Buffering(vec![delta]));
+            return Ok(ParseOutcome::ProtocolError(format!(
+                "{}: book evicted on {}, resync",
+                self.name, pair
+            )));
+        };
+        if !apply(Arc::make_mut(entry), &delta)? {
+            books.remove(pair);
+            sync.insert(pair.to_string(), SeedSync::Buffering(vec![]));
+            return Ok(ParseOutcome::ProtocolError(format!(
+                "{}: missed diff on {}, resync",
+                self.name, pair
+            )));
         }
-        Ok(result)
+        *last = next;
+        Ok(ParseOutcome::Book(entry.clone()))
+    }
+}
+
+fn render(templates: &[&str], pair: &str, level: u32) -> Result<Vec<String>> {
+    let mut result = vec![];
+    for template in templates.iter() {
+        result.push(formatx!(template.to_string(), pair, level).map_err(|e| anyhow!("{:?}", e))?);
     }
+    Ok(result)
+}
+
+fn binance_parser(raw: &str) -> Result<ParseOutcome> {
+    binance_depth("binance", raw)
 }
 
-fn binance_parser(raw: &str) -> Result<Option<Orderbook>> {
+fn binanceus_parser(raw: &str) -> Result<ParseOutcome> {
+    binance_depth("binanceus", raw)
+}
+
+// the partial book depth stream of binance and its variants, the book
+// named after the variant
+fn binance_depth(exchange: &str, raw: &str) -> Result<ParseOutcome> {
     #[derive(Default, Deserialize, Debug)]
     #[serde(rename_all = "camelCase", default)]
     struct PartialBookDepth {
@@ -50,34 +521,122 @@ fn binance_parser(raw: &str) -> Result<Option<Orderbook>> {
         asks: Vec<[String; 2]>,
         result: Value,
         id: u64,
+        // error notices: {"e": "error", "m": ...}
+        e: String,
+        m: String,
+        // request errors: {"code": 2, "msg": ...}
+        code: i64,
+        msg: String,
     }
     // PartialBookDepth is the only subscription type
     // others should be categorized as error
     let result: PartialBookDepth = serde_json::from_str(raw).map_err(|e| anyhow!("{:?}", e))?;
-    // this is a subscription response
-    if result.last_update_id == 0 && result.bids.is_empty() && result.asks.is_empty() {
-        return Ok(None);
+    if result.e == "error" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "{}: {}",
+            exchange, result.m
+        )));
+    }
+    if result.code != 0 {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "{}: {} {}",
+            exchange, result.code, result.msg
+        )));
+    }
+    // a subscription response, nothing to publish anyway without levels
+    if result.bids.is_empty() && result.asks.is_empty() {
+        return Ok(ParseOutcome::Ignore);
     }
     if result.result != Value::Null {
-        return Err(anyhow!("result not empty"));
+        return Ok(ParseOutcome::ProtocolError("result not empty".to_string()));
     }
 
-    let mut ob = Orderbook::new("binance");
+    let mut ob = Orderbook::new(exchange);
     for [price_str, quantity_str] in result.bids {
-        let price = BigDecimal::from_str(&price_str).map_err(|e| anyhow!("{:?}", e))?;
-        let quantity = BigDecimal::from_str(&quantity_str).map_err(|e| anyhow!("{:?}", e))?;
+        let price = decimal(&price_str).map_err(|e| anyhow!("{:?}", e))?;
+        let quantity = decimal(&quantity_str).map_err(|e| anyhow!("{:?}", e))?;
         ob.insert(Side::Bid, price, quantity);
     }
     for [price_str, quantity_str] in result.asks {
-        let price = BigDecimal::from_str(&price_str).map_err(|e| anyhow!("{:?}", e))?;
-        let quantity = BigDecimal::from_str(&quantity_str).map_err(|e| anyhow!("{:?}", e))?;
+        let price = decimal(&price_str).map_err(|e| anyhow!("{:?}", e))?;
+        let quantity = decimal(&quantity_str).map_err(|e| anyhow!("{:?}", e))?;
+        ob.insert(Side::Ask, price, quantity);
+    }
+    ob.trim(20);
+    Ok(ParseOutcome::Book(Arc::new(ob)))
+}
+
+static BINANCE_FUTURES: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn binance_futures_clear() {
+    let mut tmp = BINANCE_FUTURES.lock().unwrap();
+    tmp.clear();
+}
+
+// depth snapshots and mark price updates end up in the same book
+fn binance_futures_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        #[serde(rename = "e")]
+        event: String,
+        #[serde(rename = "s")]
+        symbol: String,
+        // depthUpdate
+        b: Vec<[String; 2]>,
+        a: Vec<[String; 2]>,
+        // markPriceUpdate
+        #[serde(rename = "p")]
+        mark_price: String,
+        #[serde(rename = "i")]
+        index_price: String,
+        #[serde(rename = "r")]
+        funding_rate: String,
+        // next funding time in markPriceUpdate
+        #[serde(rename = "T")]
+        time: u64,
+    }
+    let result: WsEvent = serde_json::from_str(raw).map_err(|e| anyhow!("{:?}", e))?;
+    if result.event != "depthUpdate" && result.event != "markPriceUpdate" {
+        // subscription response
+        return Ok(ParseOutcome::Ignore);
+    }
+    let mut tmp = BINANCE_FUTURES.lock().unwrap();
+    evict_stale(&mut tmp, &result.symbol, get_unixtime());
+    let entry = tmp.entry(result.symbol.clone()).or_insert_with(|| {
+        let mut ob = Orderbook::new("binance_futures");
+        ob.derivatives = Some(Derivatives::default());
+        Arc::new(ob)
+    });
+    let ob = Arc::make_mut(entry);
+    if result.event == "markPriceUpdate" {
+        ob.derivatives = Some(Derivatives {
+            mark_price: decimal(&result.mark_price)?,
+            index_price: decimal(&result.index_price)?,
+            funding_rate: decimal(&result.funding_rate)?,
+            next_funding_time: result.time,
+        });
+        return Ok(ParseOutcome::Book(entry.clone()));
+    }
+    // partial depth streams send the whole top of book every time
+    ob.bid.clear();
+    ob.ask.clear();
+    for [price_str, quantity_str] in result.b {
+        let price = decimal(&price_str)?;
+        let quantity = decimal(&quantity_str)?;
+        ob.insert(Side::Bid, price, quantity);
+    }
+    for [price_str, quantity_str] in result.a {
+        let price = decimal(&price_str)?;
+        let quantity = decimal(&quantity_str)?;
         ob.insert(Side::Ask, price, quantity);
     }
     ob.trim(20);
-    Ok(Some(ob))
+    Ok(ParseOutcome::Book(entry.clone()))
 }
 
-fn bitstamp_parser(raw: &str) -> Result<Option<Orderbook>> {
+fn bitstamp_parser(raw: &str) -> Result<ParseOutcome> {
     #[derive(Deserialize, Debug)]
     struct LiveDetailOrderbook {
         bids: Vec<[String; 2]>,
@@ -94,13 +653,20 @@ fn bitstamp_parser(raw: &str) -> Result<Option<Orderbook>> {
         channel: String,
     }
     let result: WsEvent = serde_json::from_str(raw).map_err(|e| anyhow!("{:?}", e))?;
-    if result.event != "data" {
-        // return an empty Orderbook. This might be a response or reconnect request
-        // we'll ignore reconnection handling at this moment
-        return Ok(None);
+    if result.event == "bts:request_reconnect" {
+        return Ok(ParseOutcome::FatalDesync(
+            "bitstamp requested a reconnect".to_string(),
+        ));
     }
-    if !result.channel.starts_with("order_book_") {
-        return Err(anyhow!("non-orderbook signal passed it"));
+    if result.event == "bts:error" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "bitstamp: {}",
+            result.data
+        )));
+    }
+    // subscription responses, other channels
+    if result.event != "data" || !result.channel.starts_with("order_book_") {
+        return Ok(ParseOutcome::Ignore);
     }
     // LiveDetailOrderbook is the only subscription type
     // others should be categorized as error
@@ -108,27 +674,137 @@ fn bitstamp_parser(raw: &str) -> Result<Option<Orderbook>> {
         serde_json::from_value(result.data).map_err(|e| anyhow!("{:?}", e))?;
     let mut ob = Orderbook::new("bitstamp");
     for [price_str, quantity_str] in result.bids {
-        let price = BigDecimal::from_str(&price_str).map_err(|e| anyhow!("{:?}", e))?;
-        let quantity = BigDecimal::from_str(&quantity_str).map_err(|e| anyhow!("{:?}", e))?;
+        let price = decimal(&price_str).map_err(|e| anyhow!("{:?}", e))?;
+        let quantity = decimal(&quantity_str).map_err(|e| anyhow!("{:?}", e))?;
         ob.insert(Side::Bid, price, quantity);
     }
     for [price_str, quantity_str] in result.asks {
-        let price = BigDecimal::from_str(&price_str).map_err(|e| anyhow!("{:?}", e))?;
-        let quantity = BigDecimal::from_str(&quantity_str).map_err(|e| anyhow!("{:?}", e))?;
+        let price = decimal(&price_str).map_err(|e| anyhow!("{:?}", e))?;
+        let quantity = decimal(&quantity_str).map_err(|e| anyhow!("{:?}", e))?;
         ob.insert(Side::Ask, price, quantity);
     }
-    Ok(Some(ob))
+    Ok(ParseOutcome::Book(Arc::new(ob)))
+}
+
+// a diff_order_book event, or the rest snapshot it starts from
+#[derive(Deserialize, Debug)]
+struct BitstampBook {
+    microtimestamp: String,
+    bids: Vec<[String; 2]>,
+    asks: Vec<[String; 2]>,
+}
+
+// diffs of a pair with their microtimestamp
+static BITSTAMP_FULL: Lazy<SeededBooks<(u64, BitstampBook)>> =
+    Lazy::new(|| SeededBooks::new("bitstamp_full"));
+
+fn bitstamp_full_clear() {
+    BITSTAMP_FULL.clear();
+}
+
+fn bitstamp_full_forget(pair: &str) {
+    BITSTAMP_FULL.forget(pair);
+}
+
+fn bitstamp_full_pending() -> Vec<String> {
+    BITSTAMP_FULL.pending()
+}
+
+// Apply a diff, 0 amounts remove the level. false when the diff removes a
+// level the book doesn't have or leaves it crossed: a diff went missing.
+fn bitstamp_apply(ob: &mut Orderbook, diff: &BitstampBook) -> Result<bool> {
+    let mut consistent = true;
+    for (side, levels) in [(Side::Bid, &diff.bids), (Side::Ask, &diff.asks)] {
+        for [price_str, amount_str] in levels {
+            let price = decimal(price_str)?;
+            let amount = decimal(amount_str)?;
+            let book = match side {
+                Side::Bid => &ob.bid,
+                Side::Ask => &ob.ask,
+            };
+            if amount.is_zero() && !book.contains_key(&price) {
+                consistent = false;
+            }
+            ob.insert(side, price, amount);
+        }
+    }
+    Ok(consistent && !ob.crossed())
+}
+
+fn bitstamp_full_seed(pair: &str, body: &str) -> Result<()> {
+    let snapshot: BitstampBook = serde_json::from_str(body)?;
+    let mut ob = Orderbook::new("bitstamp_full");
+    bitstamp_apply(&mut ob, &snapshot)?;
+    BITSTAMP_FULL.seed(
+        pair,
+        ob,
+        snapshot.microtimestamp.parse()?,
+        // the snapshot already has the older ones
+        |last, (micros, _)| match *micros > last {
+            true => Step::Next(*micros),
+            false => Step::Stale,
+        },
+        |ob, (_, diff)| bitstamp_apply(ob, diff).map(|_| true),
+    )
+}
+
+// the diff_order_book_{pair} channel, applied onto the rest snapshot
+fn bitstamp_full_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Deserialize, Debug)]
+    struct WsEvent {
+        data: Value,
+        event: String,
+        channel: String,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.event == "bts:request_reconnect" {
+        return Ok(ParseOutcome::FatalDesync(
+            "bitstamp requested a reconnect".to_string(),
+        ));
+    }
+    if result.event == "bts:error" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "bitstamp: {}",
+            result.data
+        )));
+    }
+    let pair = match result.channel.strip_prefix("diff_order_book_") {
+        Some(pair) if result.event == "data" => pair.to_string(),
+        _ => return Ok(ParseOutcome::Ignore),
+    };
+    let diff: BitstampBook = serde_json::from_value(result.data)?;
+    let micros: u64 = diff.microtimestamp.parse()?;
+    BITSTAMP_FULL.live(
+        &pair,
+        (micros, diff),
+        |last, (micros, _)| match *micros > last {
+            true => Step::Next(*micros),
+            false => Step::Resync("out of order diff"),
+        },
+        |ob, (_, diff)| bitstamp_apply(ob, diff),
+    )
 }
 
-static INDRESERVE: Lazy<Mutex<HashMap<String, Orderbook>>> =
+static INDRESERVE: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
+// XBT-AUD -> orderbook/20/btc/aud
+fn indreserve_channel(pair: &str) -> Result<String> {
+    let (primary, secondary) = super::indreserve_currencies(pair)?;
+    let primary = if primary == "XBT" { "BTC" } else { &primary };
+    Ok(format!(
+        "orderbook/20/{}/{}",
+        primary.to_lowercase(),
+        secondary.to_lowercase()
+    ))
+}
+
 fn indreserve_clear() {
     let mut tmp = INDRESERVE.lock().unwrap();
     tmp.clear();
 }
 
-fn indreserve_parser(raw: &str) -> Result<Option<Orderbook>> {
+fn indreserve_parser(raw: &str) -> Result<ParseOutcome> {
     #[derive(Deserialize, Debug)]
     #[serde(rename_all = "PascalCase")]
     struct Unit {
@@ -158,42 +834,54 @@ fn indreserve_parser(raw: &str) -> Result<Option<Orderbook>> {
         let mut tmp = INDRESERVE.lock().unwrap();
         let result: Vec<String> = serde_json::from_value(result.data)?;
         for channel in result {
-            tmp.insert(channel, Orderbook::new("independentreserve"));
+            // one connection carries every channel, mapped back to the
+            // pair by Exchange::next
+            let mut ob = Orderbook::new("independentreserve");
+            ob.pair = Some(channel.to_lowercase());
+            tmp.insert(channel.to_lowercase(), Arc::new(ob));
         }
-        return Ok(None);
+        return Ok(ParseOutcome::Ignore);
     } else if result.event != "OrderBookSnapshot" && result.event != "OrderBookChange" {
-        return Ok(None);
+        // Heartbeat
+        return Ok(ParseOutcome::Ignore);
     }
+    let channel = result.channel.to_lowercase();
     let mut tmp = INDRESERVE.lock().unwrap();
-    if let Some(ob) = tmp.get_mut(&result.channel) {
+    evict_stale(&mut tmp, &channel, get_unixtime());
+    if let Some(entry) = tmp.get_mut(&channel) {
+        let ob = Arc::make_mut(entry);
         if result.event == "OrderBookSnapshot" {
             ob.ask.clear();
             ob.bid.clear();
         }
         let result: Snapshot = serde_json::from_value(result.data)?;
         for Unit { price, volume } in result.bids {
-            let p = BigDecimal::from_str(&format!("{}", price))
+            let p = decimal(&format!("{}", price))
                 .map_err(|e| anyhow!("parse price fail: {} {:?}", price, e))?;
-            let v = BigDecimal::from_str(&format!("{}", volume))
+            let v = decimal(&format!("{}", volume))
                 .map_err(|e| anyhow!("parse volume fail: {} {:?}", volume, e))?;
             ob.insert(Side::Bid, p, v);
         }
         for Unit { price, volume } in result.asks {
-            let p = BigDecimal::from_str(&format!("{}", price))
+            let p = decimal(&format!("{}", price))
                 .map_err(|e| anyhow!("parse price fail: {} {:?}", price, e))?;
-            let v = BigDecimal::from_str(&format!("{}", volume))
+            let v = decimal(&format!("{}", volume))
                 .map_err(|e| anyhow!("parse volume fail: {} {:?}", volume, e))?;
             ob.insert(Side::Ask, p, v);
         }
         // since we subscribe the first 20
         ob.trim(20);
-        Ok(Some(ob.clone()))
+        Ok(ParseOutcome::Book(entry.clone()))
     } else {
-        Err(anyhow!("orderbook not exist for {}", result.channel))
+        // changes for a book we never got the subscription of
+        Ok(ParseOutcome::FatalDesync(format!(
+            "orderbook not exist for {}",
+            result.channel
+        )))
     }
 }
 
-static BTCMARKETS: Lazy<Mutex<HashMap<String, Orderbook>>> =
+static BTCMARKETS: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
 fn btcmarkets_clear() {
@@ -203,7 +891,11 @@ fn btcmarkets_clear() {
     std::thread::sleep(std::time::Duration::from_secs(4));
 }
 
-fn btcmarkets_parser(raw: &str) -> Result<Option<Orderbook>> {
+fn btcmarkets_forget(market_id: &str) {
+    BTCMARKETS.lock().unwrap().remove(market_id);
+}
+
+fn btcmarkets_parser(raw: &str) -> Result<ParseOutcome> {
     #[derive(Deserialize, Debug)]
     struct WsEvent {
         #[serde(default)]
@@ -218,50 +910,78 @@ fn btcmarkets_parser(raw: &str) -> Result<Option<Orderbook>> {
         message_type: String,
         #[serde(default, rename = "marketId")]
         market_id: String,
+        #[serde(default)]
+        message: String,
+        #[serde(default)]
+        status: String,
     }
     let result: WsEvent = serde_json::from_str(raw)?;
+    if result.message_type == "error" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "btcmarkets: {}",
+            result.message
+        )));
+    }
+    // the trading status of the markets, ex: Online, Post Only, Offline
+    if result.message_type == "status" {
+        if result.status.is_empty() {
+            return Err(anyhow!("btcmarkets: status without a status"));
+        }
+        return Ok(ParseOutcome::Status(VenueStatus::of(&result.status)));
+    }
+    // heartbeat, subscribe acks
+    if result.message_type != "orderbook" && result.message_type != "tick" {
+        return Ok(ParseOutcome::Ignore);
+    }
     let mut tmp = BTCMARKETS.lock().unwrap();
-    let key = &result.market_id;
-    let ob = if let Some(ob) = tmp.get_mut(key) {
-        ob
-    } else {
-        tmp.insert(key.clone(), Orderbook::new("btcmarkets"));
-        tmp.get_mut(key).unwrap()
-    };
+    evict_stale(&mut tmp, &result.market_id, get_unixtime());
+    let entry = tmp.entry(result.market_id.clone()).or_insert_with(|| {
+        // one connection carries every market
+        let mut ob = Orderbook::new("btcmarkets");
+        ob.pair = Some(result.market_id.clone());
+        Arc::new(ob)
+    });
     if result.message_type == "orderbook" {
+        let ob = Arc::make_mut(entry);
         ob.ask.clear();
         ob.bid.clear();
         for [price_str, quantity_str] in result.bids {
-            let price = BigDecimal::from_str(&price_str)?;
-            let quantity = BigDecimal::from_str(&quantity_str)?;
+            let price = decimal(&price_str)?;
+            let quantity = decimal(&quantity_str)?;
             ob.insert(Side::Bid, price, quantity);
         }
         for [price_str, quantity_str] in result.asks {
-            let price = BigDecimal::from_str(&price_str)?;
-            let quantity = BigDecimal::from_str(&quantity_str)?;
+            let price = decimal(&price_str)?;
+            let quantity = decimal(&quantity_str)?;
             ob.insert(Side::Ask, price, quantity);
         }
         // btcmarkets sends orderbook of 50 levels
         ob.trim(50);
-        return Ok(Some(ob.clone()));
-    } else if result.message_type == "tick" {
-        ob.last_price = BigDecimal::from_str(&result.last_price)?;
-        ob.volume = BigDecimal::from_str(&result.volume)?;
-        return Ok(Some(ob.clone()));
     } else {
-        error!("btcmarket error dump: {}", raw);
+        let ob = Arc::make_mut(entry);
+        ob.last_price = decimal(&result.last_price)?;
+        ob.volume = decimal(&result.volume)?;
     }
-    Ok(None)
+    Ok(ParseOutcome::Book(entry.clone()))
 }
 
-static COINJAR: Lazy<Mutex<HashMap<String, Orderbook>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static COINJAR: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 
 fn coinjar_clear() {
     let mut tmp = COINJAR.lock().unwrap();
     tmp.clear();
 }
 
-fn coinjar_parser(raw: &str) -> Result<Option<Orderbook>> {
+// one connection carries every pair, the book and ticker topics of one
+// share its book
+fn coinjar_book(pair: String) -> Arc<Orderbook> {
+    let mut ob = Orderbook::new("coinjar");
+    ob.pair = Some(pair);
+    Arc::new(ob)
+}
+
+fn coinjar_parser(raw: &str) -> Result<ParseOutcome> {
     #[derive(Deserialize, Debug)]
     struct WsEvent {
         event: String,
@@ -269,19 +989,28 @@ fn coinjar_parser(raw: &str) -> Result<Option<Orderbook>> {
         topic: String,
     }
     let result: WsEvent = serde_json::from_str(raw)?;
+    // the channel of the topic crashed or closed, it won't send updates anymore
+    if result.event == "phx_error" || result.event == "phx_close" {
+        return Ok(ParseOutcome::FatalDesync(format!(
+            "coinjar: {} on {}",
+            result.event, result.topic
+        )));
+    }
+    if result.event == "phx_reply" && result.payload["status"] == "error" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "coinjar: {} on {}",
+            result.payload, result.topic
+        )));
+    }
     if result.event != "init" && result.event != "update" {
-        return Ok(None);
+        return Ok(ParseOutcome::Ignore);
     }
 
     let mut tmp = COINJAR.lock().unwrap();
     if result.topic.starts_with("ticker") {
         let key = result.topic.replace("ticker:", "");
-        let ob = if let Some(ob) = tmp.get_mut(&key) {
-            ob
-        } else {
-            tmp.insert(key.clone(), Orderbook::new("coinjar"));
-            tmp.get_mut(&key).unwrap()
-        };
+        evict_stale(&mut tmp, &key, get_unixtime());
+        let entry = tmp.entry(key.clone()).or_insert_with(|| coinjar_book(key));
         #[derive(Deserialize, Debug)]
         struct Payload {
             #[serde(default)]
@@ -290,17 +1019,15 @@ fn coinjar_parser(raw: &str) -> Result<Option<Orderbook>> {
             last: String,
         }
         let result: Payload = serde_json::from_value(result.payload)?;
-        ob.volume = BigDecimal::from_str(&result.volume_24h)?;
-        ob.last_price = BigDecimal::from_str(&result.last)?;
-        return Ok(Some(ob.clone()));
+        let ob = Arc::make_mut(entry);
+        ob.volume = decimal(&result.volume_24h)?;
+        ob.last_price = decimal(&result.last)?;
+        return Ok(ParseOutcome::Book(entry.clone()));
     } else if result.topic.starts_with("book") {
         let key = result.topic.replace("book:", "");
-        let ob = if let Some(ob) = tmp.get_mut(&key) {
-            ob
-        } else {
-            tmp.insert(key.clone(), Orderbook::new("coinjar"));
-            tmp.get_mut(&key).unwrap()
-        };
+        evict_stale(&mut tmp, &key, get_unixtime());
+        let entry = tmp.entry(key.clone()).or_insert_with(|| coinjar_book(key));
+        let ob = Arc::make_mut(entry);
         if result.event == "init" {
             ob.ask.clear();
             ob.bid.clear();
@@ -314,171 +1041,2577 @@ fn coinjar_parser(raw: &str) -> Result<Option<Orderbook>> {
         }
         let result: Payload = serde_json::from_value(result.payload)?;
         for [price_str, quantity_str] in result.bids {
-            let price = BigDecimal::from_str(&price_str)?;
-            let quantity = BigDecimal::from_str(&quantity_str)?;
+            let price = decimal(&price_str)?;
+            let quantity = decimal(&quantity_str)?;
             ob.insert(Side::Bid, price, quantity);
         }
         for [price_str, quantity_str] in result.asks {
-            let price = BigDecimal::from_str(&price_str)?;
-            let quantity = BigDecimal::from_str(&quantity_str)?;
+            let price = decimal(&price_str)?;
+            let quantity = decimal(&quantity_str)?;
             ob.insert(Side::Ask, price, quantity);
         }
-        return Ok(Some(ob.clone()));
+        return Ok(ParseOutcome::Book(entry.clone()));
     }
-    Ok(None)
+    Ok(ParseOutcome::Ignore)
 }
 
-static KRAKEN: Lazy<Mutex<HashMap<String, Orderbook>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static KRAKEN: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// levels per side of the book subscription, see the templates
+const KRAKEN_DEPTH: u32 = 25;
+
+// pairs whose snapshot came since they were subscribed. Later as / bs rows
+// are republished levels, merged like updates.
+static KRAKEN_SNAPSHOT: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+// pair -> levels the ticker put on top of the book, with the volume each
+// one replaced (None for a new level), in the order they were applied
+pub(super) type Touched = Vec<(Side, BigDecimal, Option<BigDecimal>)>;
+static KRAKEN_TOP: Lazy<Mutex<HashMap<String, Touched>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
 fn kraken_clear() {
     let mut tmp = KRAKEN.lock().unwrap();
     tmp.clear();
+    KRAKEN_TOP.lock().unwrap().clear();
+    KRAKEN_SNAPSHOT.lock().unwrap().clear();
+}
+
+pub(super) fn kraken_forget(pair: &str) {
+    KRAKEN.lock().unwrap().remove(pair);
+    KRAKEN_TOP.lock().unwrap().remove(pair);
+    KRAKEN_SNAPSHOT.lock().unwrap().remove(pair);
+}
+
+// The best bid / ask of the ticker onto the book, when at or better than
+// the best of its side and not crossing the other one.
+pub(super) fn kraken_top(
+    ob: &mut Orderbook,
+    side: Side,
+    price: BigDecimal,
+    volume: BigDecimal,
+) -> Option<(Side, BigDecimal, Option<BigDecimal>)> {
+    if volume.is_zero() {
+        return None;
+    }
+    let (levels, opposite) = match side {
+        Side::Bid => (&mut ob.bid, ob.ask.keys().next()),
+        Side::Ask => (&mut ob.ask, ob.bid.keys().next_back()),
+    };
+    let (improves, crosses) = match side {
+        Side::Bid => (
+            levels.keys().next_back().is_none_or(|best| price >= *best),
+            opposite.is_some_and(|ask| price >= *ask),
+        ),
+        Side::Ask => (
+            levels.keys().next().is_none_or(|best| price <= *best),
+            opposite.is_some_and(|bid| price <= *bid),
+        ),
+    };
+    // rejected quietly, the next book update has the venue's view anyway
+    if !improves || crosses {
+        return None;
+    }
+    let previous = levels.insert(price.clone(), volume);
+    ob.timestamp = get_unixtime();
+    Some((side, price, previous))
+}
+
+// Takes the ticker levels off the book again, so the book updates and
+// their checksums apply to the venue's book only.
+pub(super) fn kraken_untop(ob: &mut Orderbook, touched: Touched) {
+    for (side, price, previous) in touched.into_iter().rev() {
+        let levels = match side {
+            Side::Bid => &mut ob.bid,
+            Side::Ask => &mut ob.ask,
+        };
+        match previous {
+            Some(volume) => levels.insert(price, volume),
+            None => levels.remove(&price),
+        };
+    }
+}
+
+// crc32 of the top 10 asks then the top 10 bids, prices and volumes as sent
+// with the '.' removed and the leading zeros trimmed
+pub(super) fn kraken_checksum(ob: &Orderbook) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    let levels = ob.ask.iter().take(10).chain(ob.bid.iter().rev().take(10));
+    for (price, volume) in levels {
+        for value in [price, volume] {
+            let digits = value.to_string().replace('.', "");
+            hasher.update(digits.trim_start_matches('0').as_bytes());
+        }
+    }
+    hasher.finalize()
 }
 
-fn kraken_parser(raw: &str) -> Result<Option<Orderbook>> {
-    if raw.as_bytes()[0] as char == '{' {
+fn kraken_parser(raw: &str) -> Result<ParseOutcome> {
+    if raw.starts_with('{') {
+        // systemStatus, heartbeat, subscriptionStatus
         let result: Value = serde_json::from_str(raw)?;
         if let Some(e) = result["errorMessage"].as_str() {
-            error!("kraken: {}", e);
+            return Ok(ParseOutcome::ProtocolError(format!("kraken: {}", e)));
         }
-        return Ok(None);
+        // online, maintenance, cancel_only, limit_only or post_only
+        if result["event"] == "systemStatus" {
+            if let Some(status) = result["status"].as_str() {
+                return Ok(ParseOutcome::Status(VenueStatus::of(status)));
+            }
+        }
+        return Ok(ParseOutcome::Ignore);
     }
     let result: Vec<Value> = serde_json::from_str(raw)?;
+    if result.len() < 4 {
+        return Err(anyhow!("kraken: short message"));
+    }
     let channel_name: String = serde_json::from_value(result[result.len() - 2].clone())?;
+    if !channel_name.starts_with("book") && channel_name != "ticker" {
+        return Ok(ParseOutcome::Ignore);
+    }
     let pair: String = serde_json::from_value(result[result.len() - 1].clone())?;
-    let key = &pair;
     let mut tmp = KRAKEN.lock().unwrap();
-    let ob = if let Some(ob) = tmp.get_mut(key) {
-        ob
-    } else {
-        tmp.insert(key.clone(), Orderbook::new("kraken"));
-        tmp.get_mut(key).unwrap()
-    };
+    evict_stale(&mut tmp, &pair, get_unixtime());
+    let mut top = KRAKEN_TOP.lock().unwrap();
+    let entry = tmp.entry(pair.clone()).or_insert_with(|| {
+        // one connection carries every pair
+        let mut ob = Orderbook::new("kraken");
+        ob.pair = Some(pair.clone());
+        Arc::new(ob)
+    });
     if channel_name.starts_with("book") {
+        // a row is [price, volume, timestamp], a republished one has an
+        // "r" after them
         #[derive(Deserialize, Debug)]
         struct Data {
             #[serde(default)]
-            r#as: Vec<[String; 3]>,
+            r#as: Vec<Vec<String>>,
             #[serde(default)]
-            bs: Vec<[String; 3]>,
+            bs: Vec<Vec<String>>,
             #[serde(default)]
             a: Vec<Vec<String>>,
             #[serde(default)]
             b: Vec<Vec<String>>,
+            // checksum of the book after the update
+            #[serde(default)]
+            c: String,
         }
         // channel_id: u64
-        // data: object
-        // - as: Vec<[String; 3]>
-        // - bs: Vec<[String; 3]>
+        // data: object, one or two of them
+        // - as / bs: the snapshot
+        // - a / b: the updates, a volume of 0 removes the level
+        // - c: checksum
         // channel_name: String
         // pair: String
 
+        let ob = Arc::make_mut(entry);
+        if let Some(touched) = top.remove(&pair) {
+            kraken_untop(ob, touched);
+        }
+        let mut checksum = None;
         for r in result[1..result.len() - 2].iter() {
             let data: Data = serde_json::from_value(r.clone())?;
+            if !data.c.is_empty() {
+                checksum = Some(data.c.parse::<u32>()?);
+            }
 
-            if !data.bs.is_empty() || !data.r#as.is_empty() {
+            // only the first as / bs of a subscription replace the book
+            if (!data.bs.is_empty() || !data.r#as.is_empty())
+                && KRAKEN_SNAPSHOT.lock().unwrap().insert(pair.clone())
+            {
                 ob.bid.clear();
                 ob.ask.clear();
             }
-            for [price_str, quantity_str, _timestamp] in data.bs {
-                let price = BigDecimal::from_str(&price_str)?;
-                let quantity = BigDecimal::from_str(&quantity_str)?;
-                ob.insert(Side::Bid, price, quantity);
+            let rows = [
+                (Side::Bid, data.bs),
+                (Side::Bid, data.b),
+                (Side::Ask, data.r#as),
+                (Side::Ask, data.a),
+            ];
+            for (side, rows) in rows {
+                for row in rows {
+                    if row.len() < 2 {
+                        return Err(anyhow!("kraken: short level {:?}", row));
+                    }
+                    ob.insert(side, decimal(&row[0])?, decimal(&row[1])?);
+                }
             }
-            for v in data.b {
-                let price_str: &str = &v[0];
-                let quantity_str: &str = &v[1];
-                let price = BigDecimal::from_str(price_str)?;
-                let quantity = BigDecimal::from_str(quantity_str)?;
-                ob.insert(Side::Bid, price, quantity);
-            }
-            for [price_str, quantity_str, _timestamp] in data.r#as {
-                let price = BigDecimal::from_str(&price_str)?;
-                let quantity = BigDecimal::from_str(&quantity_str)?;
-                ob.insert(Side::Ask, price, quantity);
-            }
-            for v in data.a {
-                let price_str: &str = &v[0];
-                let quantity_str: &str = &v[1];
-                let price = BigDecimal::from_str(price_str)?;
-                let quantity = BigDecimal::from_str(quantity_str)?;
-                ob.insert(Side::Ask, price, quantity);
+        }
+        // A new level pushes the worst one out of the subscribed depth
+        // without a removal for it, so the book is trimmed after every
+        // update, before the checksum. The exchange/mod.rs side only gets
+        // the shared snapshot, it doesn't trim the book of the parser.
+        ob.trim(KRAKEN_DEPTH);
+        if let Some(expected) = checksum {
+            let actual = kraken_checksum(ob);
+            if actual != expected {
+                return Ok(ParseOutcome::FatalDesync(format!(
+                    "kraken: checksum {} != {}",
+                    actual, expected
+                )));
             }
         }
-        // we're subscribing to book-25, so do cleanup here
-        // the exchange/mod.rs side could only get the cloned item,
-        // so the orderbook didn't explicitly trim the orderbook.
-        ob.trim(25);
-        return Ok(Some(ob.clone()));
-    } else if channel_name == *"ticker" {
+        return Ok(ParseOutcome::Book(entry.clone()));
+    } else {
         // data:
-        // - a: best ask [3]
+        // - a: best ask [3] (price, whole lot volume, lot volume)
         // - b: best bid [3]
         // - c: close [2]
         // - v: volume [2] (today, last24hr)
         #[derive(Deserialize, Debug)]
         struct Data {
+            #[serde(default)]
+            a: Option<(String, Value, String)>,
+            #[serde(default)]
+            b: Option<(String, Value, String)>,
             #[serde(default)]
             c: [String; 2],
             #[serde(default)]
             v: [String; 2],
         }
+        let ob = Arc::make_mut(entry);
+        let touched = top.entry(pair).or_default();
         for r in result[1..result.len() - 2].iter() {
             let data: Data = serde_json::from_value(r.clone())?;
-            ob.volume = BigDecimal::from_str(&data.v[1])?;
-            ob.last_price = BigDecimal::from_str(&data.c[0])?;
+            ob.volume = decimal(&data.v[1])?;
+            ob.last_price = decimal(&data.c[0])?;
+            for (side, level) in [(Side::Bid, data.b), (Side::Ask, data.a)] {
+                if let Some((price, _, volume)) = level {
+                    touched.extend(kraken_top(ob, side, decimal(&price)?, decimal(&volume)?));
+                }
+            }
         }
-        return Ok(Some(ob.clone()));
     }
-    Ok(None)
+    Ok(ParseOutcome::Book(entry.clone()))
 }
 
-// The API Map compile-time static map that handles depth orderbook subscription and parsing
-pub static WS_APIMAP: phf::Map<&'static str, Api> = phf_map! {
-    "binance" => Api {
-        endpoint: "wss://stream.binance.com:9443/ws",
-        subscribe_template: &[r#"{{"id": 1, "method": "SUBSCRIBE", "params": ["{}@depth{}@100ms"]}}"#],
-        parse: (binance_parser as ParseFunc),
-        render_url: false,
-        heartbeat: None,
-        reconnect_sec: None,
-        clear: || {},
-    },
-    "binance_futures" => Api {
-        endpoint: "wss://fstream.binance.com:9443/ws",
-        subscribe_template: &[r#"{{"id":1, "method":"SUBSCRIBE", "params": ["{}@depth{}@100ms"]}}"#],
-        parse: (binance_parser as ParseFunc),
-        render_url: false,
-        heartbeat: None,
-        reconnect_sec: None,
-        clear: || {},
-    },
-    "bitstamp" => Api {
-        endpoint: "wss://ws.bitstamp.net",
-        subscribe_template: &[r#"{{"event":"bts:subscribe","data":{{"channel":"order_book_{}"}}}}"#],
-        parse: (bitstamp_parser as ParseFunc),
-        render_url: false,
-        heartbeat: None,
-        reconnect_sec: None,
-        clear: || {},
-    },
-    "independentreserve" => Api {
-        endpoint: "wss://websockets.independentreserve.com/orderbook/20?subscribe={}",
-        subscribe_template: &[r#"{{"Event": "Subscribe", "Data": ["{}"]}}"#],
-        parse: (indreserve_parser as ParseFunc),
-        render_url: true,
-        heartbeat: None,
-        reconnect_sec: None,
-        clear: indreserve_clear,
+static OKX: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// instId -> seqId of the last books message
+static OKX_SEQ: Lazy<Mutex<HashMap<String, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn okx_clear() {
+    OKX.lock().unwrap().clear();
+    OKX_SEQ.lock().unwrap().clear();
+}
+
+fn okx_forget(pair: &str) {
+    OKX.lock().unwrap().remove(pair);
+    OKX_SEQ.lock().unwrap().remove(pair);
+}
+
+// books5 pushes its top 5 whole every time. books sends a snapshot, then
+// updates where a size of 0 removes the level, each carrying the seqId of
+// the one before as prevSeqId. A level is [price, size, liquidated orders
+// (always "0"), orders].
+fn okx_parser(raw: &str) -> Result<ParseOutcome> {
+    // the reply to the text heartbeat
+    if raw == "pong" {
+        return Ok(ParseOutcome::Ignore);
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(rename_all = "camelCase", default)]
+    struct Arg {
+        channel: String,
+        inst_id: String,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(rename_all = "camelCase", default)]
+    struct Data {
+        asks: Vec<Vec<String>>,
+        bids: Vec<Vec<String>>,
+        seq_id: Option<i64>,
+        prev_seq_id: Option<i64>,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        // subscribe, unsubscribe and error replies, empty on a push
+        event: String,
+        code: String,
+        msg: String,
+        arg: Arg,
+        // snapshot or update on books, none on books5
+        action: String,
+        data: Vec<Data>,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.event == "error" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "okx: {} {}",
+            result.code, result.msg
+        )));
+    }
+    if !result.event.is_empty() || !result.arg.channel.starts_with("books") {
+        return Ok(ParseOutcome::Ignore);
+    }
+    if result.data.is_empty() {
+        return Err(anyhow!("okx: no data on {}", result.arg.inst_id));
+    }
+    let pair = result.arg.inst_id;
+    let mut seqs = OKX_SEQ.lock().unwrap();
+    let mut tmp = OKX.lock().unwrap();
+    evict_stale(&mut tmp, &pair, get_unixtime());
+    // its seqId is still known, the levels are gone
+    if result.action == "update" && !tmp.contains_key(&pair) {
+        return Ok(ParseOutcome::FatalDesync(format!(
+            "okx: update on {} whose book was evicted",
+            pair
+        )));
+    }
+    let entry = tmp.entry(pair.clone()).or_insert_with(|| {
+        // one connection carries every instId
+        let mut ob = Orderbook::new("okx");
+        ob.pair = Some(pair.clone());
+        Arc::new(ob)
+    });
+    let ob = Arc::make_mut(entry);
+    for data in result.data {
+        if result.action == "update" {
+            let last = seqs.get(&pair);
+            if data.prev_seq_id.is_none() || last != data.prev_seq_id.as_ref() {
+                return Ok(ParseOutcome::FatalDesync(format!(
+                    "okx: update after {:?} on {}, expected after {:?}",
+                    data.prev_seq_id, pair, last
+                )));
+            }
+        } else {
+            ob.bid.clear();
+            ob.ask.clear();
+        }
+        if let Some(seq) = data.seq_id {
+            seqs.insert(pair.clone(), seq);
+        }
+        for (side, levels) in [(Side::Bid, data.bids), (Side::Ask, data.asks)] {
+            for level in levels {
+                if level.len() < 2 {
+                    return Err(anyhow!("okx: short level {:?}", level));
+                }
+                ob.insert(side, decimal(&level[0])?, decimal(&level[1])?);
+            }
+        }
+    }
+    Ok(ParseOutcome::Book(entry.clone()))
+}
+
+static POLONIEX: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// symbol -> id of the last book_lv2 message
+static POLONIEX_ID: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn poloniex_clear() {
+    POLONIEX.lock().unwrap().clear();
+    POLONIEX_ID.lock().unwrap().clear();
+}
+
+fn poloniex_forget(symbol: &str) {
+    POLONIEX.lock().unwrap().remove(symbol);
+    POLONIEX_ID.lock().unwrap().remove(symbol);
+}
+
+// book_lv2: a snapshot, then updates where a size of 0 removes the level,
+// each carrying the id of the one before as lastId
+fn poloniex_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Data {
+        symbol: String,
+        #[serde(default)]
+        asks: Vec<[String; 2]>,
+        #[serde(default)]
+        bids: Vec<[String; 2]>,
+        id: u64,
+        last_id: u64,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        // subscribe, pong and error replies, empty on a push
+        event: String,
+        message: String,
+        channel: Value,
+        action: String,
+        data: Vec<Data>,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.event == "error" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "poloniex: {}",
+            result.message
+        )));
+    }
+    if !result.event.is_empty() || result.channel != "book_lv2" {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let mut ids = POLONIEX_ID.lock().unwrap();
+    let mut tmp = POLONIEX.lock().unwrap();
+    let mut outcome = ParseOutcome::Ignore;
+    for data in result.data {
+        let symbol = data.symbol;
+        if result.action == "update" && ids.get(&symbol) != Some(&data.last_id) {
+            return Ok(ParseOutcome::FatalDesync(format!(
+                "poloniex: update after {} on {}, expected after {:?}",
+                data.last_id,
+                symbol,
+                ids.get(&symbol)
+            )));
+        }
+        evict_stale(&mut tmp, &symbol, get_unixtime());
+        // its id is still known, the levels are gone
+        if result.action == "update" && !tmp.contains_key(&symbol) {
+            return Ok(ParseOutcome::FatalDesync(format!(
+                "poloniex: update on {} whose book was evicted",
+                symbol
+            )));
+        }
+        ids.insert(symbol.clone(), data.id);
+        let entry = tmp.entry(symbol.clone()).or_insert_with(|| {
+            // one connection carries every symbol
+            let mut ob = Orderbook::new("poloniex");
+            ob.pair = Some(symbol.clone());
+            Arc::new(ob)
+        });
+        let ob = Arc::make_mut(entry);
+        if result.action == "snapshot" {
+            ob.bid.clear();
+            ob.ask.clear();
+        }
+        for (side, levels) in [(Side::Bid, data.bids), (Side::Ask, data.asks)] {
+            for [price, size] in levels {
+                ob.insert(side, decimal(&price)?, decimal(&size)?);
+            }
+        }
+        outcome = ParseOutcome::Book(entry.clone());
+    }
+    Ok(outcome)
+}
+
+static BYBIT: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// symbol -> (u, seq) of the last message
+static BYBIT_SEQ: Lazy<Mutex<HashMap<String, (u64, u64)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn bybit_clear() {
+    BYBIT.lock().unwrap().clear();
+    BYBIT_SEQ.lock().unwrap().clear();
+}
+
+fn bybit_forget(pair: &str) {
+    BYBIT.lock().unwrap().remove(pair);
+    BYBIT_SEQ.lock().unwrap().remove(pair);
+}
+
+// orderbook.50.{symbol}: a snapshot, then deltas where a size of 0 removes
+// the level. u goes up by one per message of the symbol and seq, the cross
+// sequence, only goes up. A snapshot can come again at any time (u = 1
+// after a restart of the venue's service), it replaces the book.
+fn bybit_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct Data {
+        s: String,
+        b: Vec<[String; 2]>,
+        a: Vec<[String; 2]>,
+        u: u64,
+        seq: u64,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        topic: String,
+        r#type: String,
+        data: Data,
+        // replies to subscribe and ping
+        op: String,
+        success: Option<bool>,
+        ret_msg: String,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if !result.op.is_empty() {
+        if result.success == Some(false) {
+            return Ok(ParseOutcome::ProtocolError(format!(
+                "bybit: {} {}",
+                result.op, result.ret_msg
+            )));
+        }
+        return Ok(ParseOutcome::Ignore);
+    }
+    if !result.topic.starts_with("orderbook.") {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let data = result.data;
+    let pair = data.s.clone();
+    if pair.is_empty() {
+        return Err(anyhow!("bybit: no symbol on {}", result.topic));
+    }
+    let mut seqs = BYBIT_SEQ.lock().unwrap();
+    let mut tmp = BYBIT.lock().unwrap();
+    evict_stale(&mut tmp, &pair, get_unixtime());
+    // its u is still known, the levels are gone
+    if result.r#type == "delta" && !tmp.contains_key(&pair) {
+        return Ok(ParseOutcome::FatalDesync(format!(
+            "bybit: delta on {} whose book was evicted",
+            pair
+        )));
+    }
+    let entry = tmp.entry(pair.clone()).or_insert_with(|| {
+        // one connection carries every symbol
+        let mut ob = Orderbook::new("bybit");
+        ob.pair = Some(pair.clone());
+        Arc::new(ob)
+    });
+    let ob = Arc::make_mut(entry);
+    match result.r#type.as_str() {
+        "snapshot" => {
+            ob.bid.clear();
+            ob.ask.clear();
+        }
+        "delta" => match seqs.get(&pair) {
+            Some((u, seq)) if data.u == u + 1 && data.seq > *seq => {}
+            last => {
+                return Ok(ParseOutcome::FatalDesync(format!(
+                    "bybit: delta u {} seq {} on {}, after {:?}",
+                    data.u, data.seq, pair, last
+                )))
+            }
+        },
+        other => return Err(anyhow!("bybit: {} on {}", other, result.topic)),
+    }
+    seqs.insert(pair, (data.u, data.seq));
+    for (side, levels) in [(Side::Bid, data.b), (Side::Ask, data.a)] {
+        for [price, amount] in levels {
+            ob.insert(side, decimal(&price)?, decimal(&amount)?);
+        }
+    }
+    Ok(ParseOutcome::Book(entry.clone()))
+}
+
+static COINBASE: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn coinbase_clear() {
+    COINBASE.lock().unwrap().clear();
+}
+
+fn coinbase_forget(pair: &str) {
+    COINBASE.lock().unwrap().remove(pair);
+}
+
+// level2_batch: the whole book in a snapshot per product, then l2update
+// changes of ["buy" | "sell", price, size], a size of "0" removes the level
+fn coinbase_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        r#type: String,
+        product_id: String,
+        bids: Vec<[String; 2]>,
+        asks: Vec<[String; 2]>,
+        changes: Vec<[String; 3]>,
+        message: String,
+        reason: String,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    match result.r#type.as_str() {
+        "snapshot" | "l2update" => {}
+        "error" => {
+            return Ok(ParseOutcome::ProtocolError(format!(
+                "coinbase: {} {}",
+                result.message, result.reason
+            )))
+        }
+        // heartbeat, subscriptions
+        _ => return Ok(ParseOutcome::Ignore),
+    }
+    let pair = result.product_id;
+    let mut tmp = COINBASE.lock().unwrap();
+    evict_stale(&mut tmp, &pair, get_unixtime());
+    if result.r#type == "snapshot" {
+        // one connection carries every product
+        let mut ob = Orderbook::new("coinbase");
+        ob.pair = Some(pair.clone());
+        for (side, levels) in [(Side::Bid, result.bids), (Side::Ask, result.asks)] {
+            for [price, size] in levels {
+                ob.insert(side, decimal(&price)?, decimal(&size)?);
+            }
+        }
+        let ob = Arc::new(ob);
+        tmp.insert(pair, ob.clone());
+        return Ok(ParseOutcome::Book(ob));
+    }
+    let Some(entry) = tmp.get_mut(&pair) else {
+        return Ok(ParseOutcome::FatalDesync(format!(
+            "coinbase: l2update before the snapshot of {}",
+            pair
+        )));
+    };
+    let ob = Arc::make_mut(entry);
+    for [side, price, size] in result.changes {
+        let side = match side.as_str() {
+            "buy" => Side::Bid,
+            "sell" => Side::Ask,
+            _ => return Err(anyhow!("coinbase: side {} on {}", side, pair)),
+        };
+        ob.insert(side, decimal(&price)?, decimal(&size)?);
+    }
+    Ok(ParseOutcome::Book(entry.clone()))
+}
+
+static GEMINI: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// symbols whose full book came since they were subscribed, the first
+// l2_updates of a symbol carries it
+static GEMINI_SNAPSHOT: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+fn gemini_clear() {
+    GEMINI.lock().unwrap().clear();
+    GEMINI_SNAPSHOT.lock().unwrap().clear();
+}
+
+fn gemini_forget(symbol: &str) {
+    GEMINI.lock().unwrap().remove(symbol);
+    GEMINI_SNAPSHOT.lock().unwrap().remove(symbol);
+}
+
+// the l2 subscription of the v2 marketdata: l2_updates, the first one the
+// whole book, and the trades, their price the last price
+fn gemini_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        r#type: String,
+        symbol: String,
+        changes: Vec<[String; 3]>,
+        price: String,
+        // errors
+        result: String,
+        reason: String,
+        message: String,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.result == "error" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "gemini: {} {}",
+            result.reason, result.message
+        )));
+    }
+    let symbol = result.symbol;
+    let mut tmp = GEMINI.lock().unwrap();
+    match result.r#type.as_str() {
+        "l2_updates" => {}
+        "trade" => {
+            // a trade before the book has nowhere to go
+            let Some(entry) = tmp.get_mut(&symbol) else {
+                return Ok(ParseOutcome::Ignore);
+            };
+            Arc::make_mut(entry).last_price = decimal(&result.price)?;
+            return Ok(ParseOutcome::Book(entry.clone()));
+        }
+        // heartbeat
+        _ => return Ok(ParseOutcome::Ignore),
+    }
+    evict_stale(&mut tmp, &symbol, get_unixtime());
+    if GEMINI_SNAPSHOT.lock().unwrap().insert(symbol.clone()) {
+        let mut ob = Orderbook::new("gemini");
+        ob.pair = Some(symbol.clone());
+        tmp.insert(symbol.clone(), Arc::new(ob));
+    }
+    let Some(entry) = tmp.get_mut(&symbol) else {
+        return Ok(ParseOutcome::FatalDesync(format!(
+            "gemini: the book of {} is gone",
+            symbol
+        )));
+    };
+    let ob = Arc::make_mut(entry);
+    for [side, price, quantity] in result.changes {
+        let side = match side.as_str() {
+            "buy" => Side::Bid,
+            "sell" => Side::Ask,
+            _ => return Err(anyhow!("gemini: side {} on {}", side, symbol)),
+        };
+        // 0 removes the level
+        ob.insert(side, decimal(&price)?, decimal(&quantity)?);
+    }
+    Ok(ParseOutcome::Book(entry.clone()))
+}
+
+// BTC/KRW -> KRW-BTC, the quote first. KRW-BTC stays.
+fn upbit_code(pair: &str) -> Result<String> {
+    let pair = pair.to_uppercase();
+    if pair.contains('-') {
+        return Ok(pair);
+    }
+    match pair.split_once('/') {
+        Some((base, quote)) if !base.is_empty() && !quote.is_empty() => {
+            Ok(format!("{}-{}", quote, base))
+        }
+        _ => Err(anyhow!("upbit: {} is not BASE/QUOTE or QUOTE-BASE", pair)),
+    }
+}
+
+// the orderbook type, in binary frames: the whole book on every message,
+// a bid and an ask per unit
+fn upbit_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Deserialize, Debug)]
+    struct Unit {
+        bid_price: serde_json::Number,
+        bid_size: serde_json::Number,
+        ask_price: serde_json::Number,
+        ask_size: serde_json::Number,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        r#type: String,
+        code: String,
+        orderbook_units: Vec<Unit>,
+        error: Option<Value>,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if let Some(error) = result.error {
+        return Ok(ParseOutcome::ProtocolError(format!("upbit: {}", error)));
+    }
+    // the {"status":"UP"} replies to the heartbeats
+    if result.r#type != "orderbook" {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let mut ob = Orderbook::new("upbit");
+    // the market code, one connection carries every market
+    ob.pair = Some(result.code);
+    for unit in result.orderbook_units {
+        ob.insert(
+            Side::Bid,
+            decimal(&unit.bid_price.to_string())?,
+            decimal(&unit.bid_size.to_string())?,
+        );
+        ob.insert(
+            Side::Ask,
+            decimal(&unit.ask_price.to_string())?,
+            decimal(&unit.ask_size.to_string())?,
+        );
+    }
+    Ok(ParseOutcome::Book(Arc::new(ob)))
+}
+
+// a level of an orderbookdepth delta, or of the rest snapshot
+#[derive(Deserialize, Debug)]
+struct BithumbLevel {
+    #[serde(default)]
+    symbol: String,
+    #[serde(default, rename = "orderType")]
+    order_type: String,
+    price: String,
+    quantity: String,
+}
+
+// (datetime, levels) of the delta of a symbol, in micros
+static BITHUMB: Lazy<SeededBooks<(u64, Vec<BithumbLevel>)>> =
+    Lazy::new(|| SeededBooks::new("bithumb"));
+
+fn bithumb_clear() {
+    BITHUMB.clear();
+}
+
+fn bithumb_pending() -> Vec<String> {
+    BITHUMB.pending()
+}
+
+// 0 quantities remove the level
+fn bithumb_apply(ob: &mut Orderbook, levels: &[BithumbLevel]) -> Result<()> {
+    for level in levels {
+        let side = match level.order_type.as_str() {
+            "bid" => Side::Bid,
+            "ask" => Side::Ask,
+            _ => return Err(anyhow!("bithumb: order type {}", level.order_type)),
+        };
+        ob.insert(side, decimal(&level.price)?, decimal(&level.quantity)?);
+    }
+    Ok(())
+}
+
+// Several deltas can share a datetime and quantities are absolute: only the
+// older ones are stale.
+fn bithumb_step(last: u64, (datetime, _): &(u64, Vec<BithumbLevel>)) -> Step {
+    match *datetime < last {
+        true => Step::Stale,
+        false => Step::Next(*datetime),
+    }
+}
+
+// GET /public/orderbook/{symbol}, the deltas buffered since on top
+fn bithumb_seed(symbol: &str, body: &str) -> Result<()> {
+    #[derive(Deserialize, Debug)]
+    struct Data {
+        timestamp: String,
+        bids: Vec<BithumbLevel>,
+        asks: Vec<BithumbLevel>,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Snapshot {
+        status: String,
+        data: Option<Data>,
+        #[serde(default)]
+        message: String,
+    }
+    let snapshot: Snapshot = serde_json::from_str(body)?;
+    let data = snapshot
+        .data
+        .filter(|_| snapshot.status == "0000")
+        .ok_or_else(|| anyhow!("bithumb: {} {}", snapshot.status, snapshot.message))?;
+    let mut ob = Orderbook::new("bithumb");
+    ob.pair = Some(symbol.to_string());
+    for (side, levels) in [(Side::Bid, data.bids), (Side::Ask, data.asks)] {
+        for level in levels {
+            ob.insert(side, decimal(&level.price)?, decimal(&level.quantity)?);
+        }
+    }
+    // millis, the deltas are in micros
+    let at = data.timestamp.parse::<u64>()? * 1000;
+    BITHUMB.seed(symbol, ob, at, bithumb_step, |ob, (_, levels)| {
+        bithumb_apply(ob, levels).map(|_| true)
+    })
+}
+
+// the orderbookdepth channel, applied onto the rest snapshot. A message
+// can carry the levels of several symbols.
+fn bithumb_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct Content {
+        list: Vec<BithumbLevel>,
+        datetime: String,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        r#type: String,
+        content: Content,
+        status: String,
+        resmsg: String,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if !result.status.is_empty() && result.status != "0000" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "bithumb: {} {}",
+            result.status, result.resmsg
+        )));
+    }
+    // connect and subscribe acks
+    if result.r#type != "orderbookdepth" {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let datetime: u64 = result.content.datetime.parse()?;
+    let mut deltas: Vec<(String, Vec<BithumbLevel>)> = vec![];
+    for level in result.content.list {
+        match deltas
+            .iter_mut()
+            .find(|(symbol, _)| *symbol == level.symbol)
+        {
+            Some((_, levels)) => levels.push(level),
+            None => deltas.push((level.symbol.clone(), vec![level])),
+        }
+    }
+    let mut outcome = ParseOutcome::Ignore;
+    let mut resyncs = vec![];
+    for (symbol, levels) in deltas {
+        let delta = (datetime, levels);
+        match BITHUMB.live(&symbol, delta, bithumb_step, |ob, (_, levels)| {
+            bithumb_apply(ob, levels).map(|_| true)
+        })? {
+            ParseOutcome::Ignore => {}
+            // the other symbols of the message go on
+            ParseOutcome::ProtocolError(e) => resyncs.push(e),
+            book @ ParseOutcome::Book(_) => outcome = book,
+            desync => return Ok(desync),
+        }
+    }
+    if !resyncs.is_empty() {
+        return Ok(ParseOutcome::ProtocolError(resyncs.join("; ")));
+    }
+    Ok(outcome)
+}
+
+struct LunoState {
+    sequence: u64,
+    // order id -> side, price and volume of the resting orders
+    orders: HashMap<String, (Side, BigDecimal, BigDecimal)>,
+    book: Arc<Orderbook>,
+}
+
+// one pair per connection, its levels summed up from the orders
+static LUNO: Lazy<Mutex<Option<LunoState>>> = Lazy::new(|| Mutex::new(None));
+
+fn luno_clear() {
+    *LUNO.lock().unwrap() = None;
+}
+
+// the volume of the level moves by `delta`, the level goes with its last
+// order
+fn move_level(ob: &mut Orderbook, side: Side, price: &BigDecimal, delta: &BigDecimal) {
+    let levels = match side {
+        Side::Bid => &ob.bid,
+        Side::Ask => &ob.ask,
+    };
+    let volume = levels.get(price).cloned().unwrap_or_default() + delta;
+    let volume = if volume > BigDecimal::zero() {
+        volume
+    } else {
+        BigDecimal::zero()
+    };
+    ob.insert(side, price.clone(), volume);
+}
+
+// ACTIVE, POSTONLY, DISABLED
+fn luno_status(status: &str) -> VenueStatus {
+    match status {
+        "ACTIVE" => VenueStatus::Online,
+        "POSTONLY" => VenueStatus::of("post only"),
+        _ => VenueStatus::of(status),
+    }
+}
+
+// the market stream: the orders of the book, then one change a message,
+// each a sequence number up. Keep alive messages are empty.
+fn luno_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Deserialize, Debug)]
+    struct Resting {
+        id: String,
+        price: String,
+        volume: String,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Create {
+        order_id: String,
+        r#type: String,
+        price: String,
+        volume: String,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Delete {
+        order_id: String,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Fill {
+        base: String,
+        maker_order_id: String,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Status {
+        status: String,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        sequence: String,
+        // the snapshot
+        asks: Option<Vec<Resting>>,
+        bids: Option<Vec<Resting>>,
+        // the changes
+        trade_updates: Option<Vec<Fill>>,
+        create_update: Option<Create>,
+        delete_update: Option<Delete>,
+        status_update: Option<Status>,
+    }
+    if raw.trim().is_empty() {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    let sequence: u64 = result.sequence.parse()?;
+    let mut state = LUNO.lock().unwrap();
+    if let (Some(asks), Some(bids)) = (result.asks, result.bids) {
+        let mut orders = HashMap::new();
+        let mut ob = Orderbook::new("luno");
+        for (side, resting) in [(Side::Bid, bids), (Side::Ask, asks)] {
+            for order in resting {
+                let price = decimal(&order.price)?;
+                let volume = decimal(&order.volume)?;
+                move_level(&mut ob, side, &price, &volume);
+                orders.insert(order.id, (side, price, volume));
+            }
+        }
+        let book = Arc::new(ob);
+        *state = Some(LunoState {
+            sequence,
+            orders,
+            book: book.clone(),
+        });
+        return Ok(ParseOutcome::Book(book));
+    }
+    let Some(LunoState {
+        sequence: last,
+        orders,
+        book,
+    }) = state.as_mut()
+    else {
+        return Ok(ParseOutcome::FatalDesync(
+            "luno: a change before the snapshot".to_string(),
+        ));
+    };
+    if sequence != *last + 1 {
+        return Ok(ParseOutcome::FatalDesync(format!(
+            "luno: sequence jumped from {} to {}",
+            last, sequence
+        )));
+    }
+    *last = sequence;
+    let ob = Arc::make_mut(book);
+    // the makers lose what was traded, the filled ones leave the book
+    for fill in result.trade_updates.unwrap_or_default() {
+        let Some((side, price, volume)) = orders.get_mut(&fill.maker_order_id) else {
+            continue;
+        };
+        let base = decimal(&fill.base)?;
+        *volume -= &base;
+        move_level(ob, *side, price, &-base);
+        if *volume <= BigDecimal::zero() {
+            orders.remove(&fill.maker_order_id);
+        }
+    }
+    if let Some(create) = result.create_update {
+        let side = match create.r#type.as_str() {
+            "BID" => Side::Bid,
+            "ASK" => Side::Ask,
+            _ => return Err(anyhow!("luno: order type {}", create.r#type)),
+        };
+        let price = decimal(&create.price)?;
+        let volume = decimal(&create.volume)?;
+        move_level(ob, side, &price, &volume);
+        orders.insert(create.order_id, (side, price, volume));
+    }
+    // a filled order may be gone already
+    if let Some(delete) = result.delete_update {
+        if let Some((side, price, volume)) = orders.remove(&delete.order_id) {
+            move_level(ob, side, &price, &-volume);
+        }
+    }
+    if let Some(status) = result.status_update {
+        return Ok(ParseOutcome::Status(luno_status(&status.status)));
+    }
+    Ok(ParseOutcome::Book(book.clone()))
+}
+
+// an order of a diff-orders message: t 0 is a buy, 1 a sell. Cancelled
+// and completed orders come without an amount.
+#[derive(Deserialize, Debug)]
+struct BitsoOrder {
+    #[serde(rename = "o")]
+    oid: String,
+    #[serde(rename = "r")]
+    rate: String,
+    #[serde(rename = "t")]
+    side: u8,
+    #[serde(rename = "a", default)]
+    amount: Option<String>,
+    #[serde(rename = "s", default)]
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitsoDiff {
+    sequence: u64,
+    payload: Vec<BitsoOrder>,
+}
+
+static BITSO: Lazy<SeededBooks<BitsoDiff>> = Lazy::new(|| SeededBooks::new("bitso"));
+// oid -> side, price and amount of an open order
+type BitsoOrders = HashMap<String, (Side, BigDecimal, BigDecimal)>;
+
+// book -> its open orders, their levels summed up in BITSO
+static BITSO_ORDERS: Lazy<Mutex<HashMap<String, BitsoOrders>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn bitso_clear() {
+    BITSO.clear();
+    BITSO_ORDERS.lock().unwrap().clear();
+}
+
+fn bitso_pending() -> Vec<String> {
+    BITSO.pending()
+}
+
+// the order as it was leaves its level, as it is now joins one
+fn bitso_apply(ob: &mut Orderbook, orders: &mut BitsoOrders, order: &BitsoOrder) -> Result<()> {
+    if let Some((side, price, amount)) = orders.remove(&order.oid) {
+        move_level(ob, side, &price, &-amount);
+    }
+    let Some(amount) = order.amount.as_ref().filter(|_| order.status == "open") else {
+        return Ok(());
+    };
+    let side = match order.side {
+        0 => Side::Bid,
+        1 => Side::Ask,
+        _ => return Err(anyhow!("bitso: order side {}", order.side)),
+    };
+    let price = decimal(&order.rate)?;
+    let amount = decimal(amount)?;
+    move_level(ob, side, &price, &amount);
+    orders.insert(order.oid.clone(), (side, price, amount));
+    Ok(())
+}
+
+fn bitso_step(last: u64, diff: &BitsoDiff) -> Step {
+    if diff.sequence <= last {
+        Step::Stale
+    } else if diff.sequence != last + 1 {
+        Step::Gap(diff.sequence)
+    } else {
+        Step::Next(diff.sequence)
+    }
+}
+
+// GET /v3/order_book/?aggregate=false: the open orders with their oid, the
+// buffered diffs past its sequence replayed on top. A snapshot older than
+// the first buffered diff is an error, fetched again after a backoff.
+fn bitso_seed(book: &str, body: &str) -> Result<()> {
+    #[derive(Deserialize, Debug)]
+    struct Resting {
+        price: String,
+        amount: String,
+        oid: String,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Payload {
+        bids: Vec<Resting>,
+        asks: Vec<Resting>,
+        sequence: String,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Snapshot {
+        success: bool,
+        payload: Option<Payload>,
+        #[serde(default)]
+        error: Value,
+    }
+    let snapshot: Snapshot = serde_json::from_str(body)?;
+    let payload = snapshot
+        .payload
+        .filter(|_| snapshot.success)
+        .ok_or_else(|| anyhow!("bitso: snapshot of {}: {}", book, snapshot.error))?;
+    // the orders of a book already seeded are kept
+    if !BITSO.buffering(book) {
+        return Ok(());
+    }
+    let mut ob = Orderbook::new("bitso");
+    ob.pair = Some(book.to_string());
+    let mut orders = HashMap::new();
+    for (side, resting) in [(Side::Bid, payload.bids), (Side::Ask, payload.asks)] {
+        for order in resting {
+            let price = decimal(&order.price)?;
+            let amount = decimal(&order.amount)?;
+            move_level(&mut ob, side, &price, &amount);
+            orders.insert(order.oid, (side, price, amount));
+        }
+    }
+    BITSO.seed(
+        book,
+        ob,
+        payload.sequence.parse()?,
+        bitso_step,
+        |ob, diff| {
+            for order in diff.payload.iter() {
+                bitso_apply(ob, &mut orders, order)?;
+            }
+            Ok(true)
+        },
+    )?;
+    BITSO_ORDERS
+        .lock()
+        .unwrap()
+        .insert(book.to_string(), orders);
+    Ok(())
+}
+
+// the diff-orders channel, applied onto the rest snapshot
+fn bitso_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        r#type: String,
+        book: String,
+        action: String,
+        response: String,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.action == "subscribe" && result.response != "ok" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "bitso: subscribe {}: {}",
+            result.r#type, result.response
+        )));
+    }
+    // subscribe acks, the "ka" keep alives
+    if result.r#type != "diff-orders" || !result.action.is_empty() {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let book = result.book;
+    let diff: BitsoDiff = serde_json::from_str(raw)?;
+    BITSO.live(&book, diff, bitso_step, |ob, diff| {
+        let mut all_orders = BITSO_ORDERS.lock().unwrap();
+        let orders = all_orders.entry(book.clone()).or_default();
+        for order in diff.payload.iter() {
+            bitso_apply(ob, orders, order)?;
+        }
+        Ok(true)
+    })
+}
+
+// the levels of a lightning_board message, or of the rest board, a size of
+// 0 removing one
+#[derive(Deserialize, Debug)]
+struct BitflyerBoard {
+    bids: Vec<BitflyerLevel>,
+    asks: Vec<BitflyerLevel>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitflyerLevel {
+    price: serde_json::Number,
+    size: serde_json::Number,
+}
+
+static BITFLYER: Lazy<SeededBooks<BitflyerBoard>> = Lazy::new(|| SeededBooks::new("bitflyer"));
+
+fn bitflyer_clear() {
+    BITFLYER.clear();
+}
+
+fn bitflyer_forget(product: &str) {
+    BITFLYER.forget(product);
+}
+
+fn bitflyer_pending() -> Vec<String> {
+    BITFLYER.pending()
+}
+
+fn bitflyer_apply(ob: &mut Orderbook, board: &BitflyerBoard) -> Result<()> {
+    for (side, levels) in [(Side::Bid, &board.bids), (Side::Ask, &board.asks)] {
+        for level in levels {
+            ob.insert(
+                side,
+                decimal(&level.price.to_string())?,
+                decimal(&level.size.to_string())?,
+            );
+        }
+    }
+    Ok(())
+}
+
+// the book of a board, from the rest snapshot or the snapshot channel
+fn bitflyer_book(product: &str, board: &BitflyerBoard) -> Result<Orderbook> {
+    let mut ob = Orderbook::new("bitflyer");
+    ob.pair = Some(product.to_string());
+    bitflyer_apply(&mut ob, board)?;
+    Ok(ob)
+}
+
+// GET /v1/board. The diffs carry no sequence to tell the ones the snapshot
+// has from the newer ones: all the buffered ones go on top, their sizes
+// being absolute, and the snapshot channel rebuilds the book every time it
+// fires anyway. A no-op when the snapshot channel was first.
+fn bitflyer_seed(product: &str, body: &str) -> Result<()> {
+    let board: BitflyerBoard = serde_json::from_str(body)?;
+    BITFLYER.seed(
+        product,
+        bitflyer_book(product, &board)?,
+        0,
+        unsequenced,
+        |ob, diff| bitflyer_apply(ob, diff).map(|_| true),
+    )
+}
+
+// json-rpc channelMessage of lightning_board_snapshot_{product}, the whole
+// board, and lightning_board_{product}, the changed levels
+fn bitflyer_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Deserialize, Debug)]
+    struct Params {
+        channel: String,
+        message: BitflyerBoard,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        method: String,
+        params: Option<Params>,
+        error: Option<Value>,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if let Some(error) = result.error {
+        let message = error.get("message").unwrap_or(&error);
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "bitflyer: {}",
+            message
+                .as_str()
+                .map_or_else(|| message.to_string(), str::to_string)
+        )));
+    }
+    // subscribe acks
+    let Some(params) = result.params.filter(|_| result.method == "channelMessage") else {
+        return Ok(ParseOutcome::Ignore);
+    };
+    // the diffs buffered before it are dropped
+    if let Some(product) = params.channel.strip_prefix("lightning_board_snapshot_") {
+        let ob = bitflyer_book(product, &params.message)?;
+        return Ok(ParseOutcome::Book(BITFLYER.reset(product, ob, 0)));
+    }
+    let Some(product) = params.channel.strip_prefix("lightning_board_") else {
+        return Ok(ParseOutcome::Ignore);
+    };
+    BITFLYER.live(product, params.message, unsequenced, |ob, diff| {
+        bitflyer_apply(ob, diff).map(|_| true)
+    })
+}
+
+// the changed levels of an orderbook message, an amount of "0" removing one
+#[derive(Deserialize, Debug)]
+struct CoincheckDiff {
+    bids: Vec<[String; 2]>,
+    asks: Vec<[String; 2]>,
+    // unix seconds
+    last_update_at: String,
+}
+
+static COINCHECK: Lazy<SeededBooks<CoincheckDiff>> = Lazy::new(|| SeededBooks::new("coincheck"));
+
+fn coincheck_clear() {
+    COINCHECK.clear();
+}
+
+fn coincheck_pending() -> Vec<String> {
+    COINCHECK.pending()
+}
+
+// the levels of the diff, the book stamped with its time
+fn coincheck_apply(ob: &mut Orderbook, diff: &CoincheckDiff) -> Result<()> {
+    for (side, levels) in [(Side::Bid, &diff.bids), (Side::Ask, &diff.asks)] {
+        for [price, amount] in levels {
+            ob.insert(side, decimal(price)?, decimal(amount)?);
+        }
+    }
+    let seconds: u128 = diff.last_update_at.parse()?;
+    ob.set_timestamp(seconds * 1000);
+    Ok(())
+}
+
+// GET /api/order_books. It has no sequence nor time to tell the diffs it
+// has from the newer ones: all the buffered ones go on top, their amounts
+// being absolute.
+fn coincheck_seed(pair: &str, body: &str) -> Result<()> {
+    #[derive(Deserialize, Debug)]
+    struct Snapshot {
+        bids: Vec<[String; 2]>,
+        asks: Vec<[String; 2]>,
+    }
+    let snapshot: Snapshot = serde_json::from_str(body)?;
+    let mut ob = Orderbook::new("coincheck");
+    ob.pair = Some(pair.to_string());
+    for (side, levels) in [(Side::Bid, snapshot.bids), (Side::Ask, snapshot.asks)] {
+        for [price, amount] in levels {
+            ob.insert(side, decimal(&price)?, decimal(&amount)?);
+        }
+    }
+    COINCHECK.seed(pair, ob, 0, unsequenced, |ob, diff| {
+        coincheck_apply(ob, diff).map(|_| true)
+    })
+}
+
+// [pair, diff] on the {pair}-orderbook channel, applied onto the rest
+// snapshot
+fn coincheck_parser(raw: &str) -> Result<ParseOutcome> {
+    let result: Value = serde_json::from_str(raw)?;
+    // the orderbook channel is the only one subscribed
+    if !result.is_array() {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let (pair, diff): (String, CoincheckDiff) = serde_json::from_value(result)?;
+    COINCHECK.live(&pair, diff, unsequenced, |ob, diff| {
+        coincheck_apply(ob, diff).map(|_| true)
+    })
+}
+
+static COINEX: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn coinex_clear() {
+    COINEX.lock().unwrap().clear();
+}
+
+// depth.update: the whole top when is_full, else the changed levels, an
+// amount of 0 removing one. Carries the last price.
+fn coinex_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct Depth {
+        asks: Vec<[String; 2]>,
+        bids: Vec<[String; 2]>,
+        last: String,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct Data {
+        market: String,
+        is_full: bool,
+        depth: Depth,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        method: String,
+        data: Option<Data>,
+        // the replies to subscribe and server.ping
+        code: i64,
+        message: String,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.code != 0 {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "coinex: {} {}",
+            result.code, result.message
+        )));
+    }
+    let Some(data) = result.data.filter(|_| result.method == "depth.update") else {
+        return Ok(ParseOutcome::Ignore);
+    };
+    let market = data.market;
+    let mut tmp = COINEX.lock().unwrap();
+    evict_stale(&mut tmp, &market, get_unixtime());
+    if data.is_full {
+        let mut ob = Orderbook::new("coinex");
+        ob.pair = Some(market.clone());
+        tmp.insert(market.clone(), Arc::new(ob));
+    }
+    let Some(entry) = tmp.get_mut(&market) else {
+        return Ok(ParseOutcome::FatalDesync(format!(
+            "coinex: a diff before the full depth of {}",
+            market
+        )));
+    };
+    let ob = Arc::make_mut(entry);
+    for (side, levels) in [(Side::Bid, data.depth.bids), (Side::Ask, data.depth.asks)] {
+        for [price, amount] in levels {
+            ob.insert(side, decimal(&price)?, decimal(&amount)?);
+        }
+    }
+    if !data.depth.last.is_empty() {
+        ob.last_price = decimal(&data.depth.last)?;
+    }
+    Ok(ParseOutcome::Book(entry.clone()))
+}
+
+static DERIBIT: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// instrument -> change_id of the last book message
+static DERIBIT_CHANGE: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn deribit_clear() {
+    DERIBIT.lock().unwrap().clear();
+    DERIBIT_CHANGE.lock().unwrap().clear();
+}
+
+fn deribit_forget(instrument: &str) {
+    DERIBIT.lock().unwrap().remove(instrument);
+    DERIBIT_CHANGE.lock().unwrap().remove(instrument);
+}
+
+// a test_request heartbeat is answered with public/test, or the venue
+// closes the connection
+fn deribit_pong(raw: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Params {
+        r#type: String,
+    }
+    #[derive(Deserialize)]
+    struct Heartbeat {
+        method: String,
+        params: Params,
+    }
+    let heartbeat: Heartbeat = serde_json::from_str(raw).ok()?;
+    (heartbeat.method == "heartbeat" && heartbeat.params.r#type == "test_request")
+        .then(|| r#"{"jsonrpc":"2.0","id":3,"method":"public/test","params":{}}"#.to_string())
+}
+
+// book.{instrument}.100ms notifications: a snapshot, then changes each
+// carrying the change_id of the one before as prev_change_id. A level is
+// ["new" | "change" | "delete", price, amount].
+fn deribit_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Deserialize, Debug)]
+    struct Data {
+        r#type: String,
+        instrument_name: String,
+        change_id: u64,
+        prev_change_id: Option<u64>,
+        bids: Vec<(String, serde_json::Number, serde_json::Number)>,
+        asks: Vec<(String, serde_json::Number, serde_json::Number)>,
+    }
+    // heartbeats carry {"type": ..} params
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct Params {
+        channel: String,
+        data: Value,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        method: String,
+        params: Option<Params>,
+        error: Option<Value>,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if let Some(error) = result.error {
+        return Ok(ParseOutcome::ProtocolError(format!("deribit: {}", error)));
+    }
+    // subscribe and public/test results, heartbeats
+    let Some(params) = result
+        .params
+        .filter(|p| result.method == "subscription" && p.channel.starts_with("book."))
+    else {
+        return Ok(ParseOutcome::Ignore);
+    };
+    let data: Data = serde_json::from_value(params.data)?;
+    let instrument = data.instrument_name;
+    let mut changes = DERIBIT_CHANGE.lock().unwrap();
+    if data.r#type != "snapshot" {
+        let last = changes.get(&instrument);
+        if data.prev_change_id.is_none() || last != data.prev_change_id.as_ref() {
+            return Ok(ParseOutcome::FatalDesync(format!(
+                "deribit: change after {:?} on {}, expected after {:?}",
+                data.prev_change_id, instrument, last
+            )));
+        }
+    }
+    let mut tmp = DERIBIT.lock().unwrap();
+    evict_stale(&mut tmp, &instrument, get_unixtime());
+    // its change_id is still known, the levels are gone
+    if data.r#type != "snapshot" && !tmp.contains_key(&instrument) {
+        return Ok(ParseOutcome::FatalDesync(format!(
+            "deribit: change on {} whose book was evicted",
+            instrument
+        )));
+    }
+    changes.insert(instrument.clone(), data.change_id);
+    let entry = tmp.entry(instrument.clone()).or_insert_with(|| {
+        // one connection carries every instrument
+        let mut ob = Orderbook::new("deribit");
+        ob.pair = Some(instrument.clone());
+        Arc::new(ob)
+    });
+    let ob = Arc::make_mut(entry);
+    if data.r#type == "snapshot" {
+        ob.bid.clear();
+        ob.ask.clear();
+    }
+    for (side, levels) in [(Side::Bid, data.bids), (Side::Ask, data.asks)] {
+        for (action, price, amount) in levels {
+            let price = decimal(&price.to_string())?;
+            let amount = match action.as_str() {
+                "new" | "change" => decimal(&amount.to_string())?,
+                "delete" => BigDecimal::zero(),
+                _ => return Err(anyhow!("deribit: level action {}", action)),
+            };
+            ob.insert(side, price, amount);
+        }
+    }
+    Ok(ParseOutcome::Book(entry.clone()))
+}
+
+static BITMEX: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// symbol -> level id -> the price of the level
+static BITMEX_IDS: Lazy<Mutex<HashMap<String, HashMap<u64, BigDecimal>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn bitmex_clear() {
+    BITMEX.lock().unwrap().clear();
+    BITMEX_IDS.lock().unwrap().clear();
+}
+
+fn bitmex_forget(symbol: &str) {
+    BITMEX.lock().unwrap().remove(symbol);
+    BITMEX_IDS.lock().unwrap().remove(symbol);
+}
+
+// the price of a legacy level id, (100000000 * index - id) * tick, for the
+// contracts whose index is known
+fn bitmex_id_price(symbol: &str, id: u64) -> Option<BigDecimal> {
+    let (index, scale) = match symbol {
+        // ticks of 0.01, whatever the tick size of the contract
+        "XBTUSD" => (88, 2),
+        _ => return None,
+    };
+    let ticks = (100_000_000u64 * index).checked_sub(id)?;
+    Some(BigDecimal::new(ticks.into(), scale))
+}
+
+// orderBookL2 levels are keyed by id: partial is the whole book, insert adds
+// ids, update changes the size of one and delete removes it, neither
+// carrying the price. Pushes before the partial are dropped.
+fn bitmex_parser(raw: &str) -> Result<ParseOutcome> {
+    // the reply to the text heartbeat
+    if raw == "pong" {
+        return Ok(ParseOutcome::Ignore);
+    }
+    #[derive(Deserialize, Debug)]
+    struct Row {
+        symbol: String,
+        id: u64,
+        side: String,
+        size: Option<serde_json::Number>,
+        price: Option<serde_json::Number>,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        table: String,
+        action: String,
+        data: Vec<Row>,
+        error: Option<String>,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if let Some(error) = result.error {
+        return Ok(ParseOutcome::ProtocolError(format!("bitmex: {}", error)));
+    }
+    // welcome and subscribe replies
+    if result.table != "orderBookL2" {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let partial = result.action == "partial";
+    let mut ids = BITMEX_IDS.lock().unwrap();
+    let mut tmp = BITMEX.lock().unwrap();
+    let mut outcome = ParseOutcome::Ignore;
+    let mut cleared = HashSet::new();
+    for row in result.data {
+        let symbol = row.symbol;
+        evict_stale(&mut tmp, &symbol, get_unixtime());
+        if partial && cleared.insert(symbol.clone()) {
+            ids.insert(symbol.clone(), HashMap::new());
+            tmp.remove(&symbol);
+        }
+        let Some(levels) = ids.get_mut(&symbol) else {
+            continue;
+        };
+        if !partial && !tmp.contains_key(&symbol) {
+            return Ok(ParseOutcome::FatalDesync(format!(
+                "bitmex: the book of {} was dropped",
+                symbol
+            )));
+        }
+        let side = match row.side.as_str() {
+            "Buy" => Side::Bid,
+            "Sell" => Side::Ask,
+            _ => return Err(anyhow!("bitmex: side {}", row.side)),
+        };
+        let price = match (row.price, levels.get(&row.id)) {
+            (Some(price), _) => decimal(&price.to_string())?,
+            (None, Some(price)) => price.clone(),
+            (None, None) if result.action == "insert" => bitmex_id_price(&symbol, row.id)
+                .ok_or_else(|| anyhow!("bitmex: no price for {} on {}", row.id, symbol))?,
+            (None, None) => {
+                return Ok(ParseOutcome::FatalDesync(format!(
+                    "bitmex: {} of unknown id {} on {}",
+                    result.action, row.id, symbol
+                )))
+            }
+        };
+        let amount = match result.action.as_str() {
+            "delete" => {
+                levels.remove(&row.id);
+                BigDecimal::zero()
+            }
+            "partial" | "insert" | "update" => {
+                let size = row
+                    .size
+                    .ok_or_else(|| anyhow!("bitmex: no size for {} on {}", row.id, symbol))?;
+                levels.insert(row.id, price.clone());
+                decimal(&size.to_string())?
+            }
+            _ => return Err(anyhow!("bitmex: action {}", result.action)),
+        };
+        let entry = tmp.entry(symbol.clone()).or_insert_with(|| {
+            // one connection carries every symbol
+            let mut ob = Orderbook::new("bitmex");
+            ob.pair = Some(symbol.clone());
+            Arc::new(ob)
+        });
+        Arc::make_mut(entry).insert(side, price, amount);
+        outcome = ParseOutcome::Book(entry.clone());
+    }
+    Ok(outcome)
+}
+
+// every l2Book message is the whole book, levels being [bids, asks]
+fn hyperliquid_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Deserialize, Debug)]
+    struct Level {
+        px: String,
+        sz: String,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Data {
+        coin: String,
+        time: u128,
+        levels: (Vec<Level>, Vec<Level>),
+    }
+    #[derive(Deserialize, Debug)]
+    struct WsEvent {
+        channel: String,
+        #[serde(default)]
+        data: Value,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.channel == "error" {
+        let error = match result.data {
+            Value::String(error) => error,
+            data => data.to_string(),
+        };
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "hyperliquid: {}",
+            error
+        )));
+    }
+    // subscription replies and pongs
+    if result.channel != "l2Book" {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let data: Data = serde_json::from_value(result.data)?;
+    let mut ob = Orderbook::new("hyperliquid");
+    // one connection carries every coin
+    ob.pair = Some(data.coin);
+    let (bids, asks) = data.levels;
+    for (side, levels) in [(Side::Bid, bids), (Side::Ask, asks)] {
+        for level in levels {
+            ob.insert(side, decimal(&level.px)?, decimal(&level.sz)?);
+        }
+    }
+    ob.set_timestamp(data.time);
+    Ok(ParseOutcome::Book(Arc::new(ob)))
+}
+
+static KRAKEN_FUTURES: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// product id -> seq of the last book message
+static KRAKEN_FUTURES_SEQ: Lazy<Mutex<HashMap<String, u64>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn kraken_futures_clear() {
+    KRAKEN_FUTURES.lock().unwrap().clear();
+    KRAKEN_FUTURES_SEQ.lock().unwrap().clear();
+}
+
+fn kraken_futures_forget(product: &str) {
+    KRAKEN_FUTURES.lock().unwrap().remove(product);
+    KRAKEN_FUTURES_SEQ.lock().unwrap().remove(product);
+}
+
+// the book feed: a book_snapshot, then one level a book message, a qty of 0
+// removing it. seq goes up by one a message of the product. Deltas of a
+// product without a snapshot, ex: after its unsubscribe, are dropped.
+fn kraken_futures_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Deserialize, Debug)]
+    struct Level {
+        price: serde_json::Number,
+        qty: serde_json::Number,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        // info, subscribed, unsubscribed, error and alert, empty on a push
+        event: String,
+        message: String,
+        feed: String,
+        product_id: String,
+        seq: u64,
+        // book_snapshot
+        bids: Vec<Level>,
+        asks: Vec<Level>,
+        // book
+        side: String,
+        price: Option<serde_json::Number>,
+        qty: Option<serde_json::Number>,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.event == "error" || result.event == "alert" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "kraken_futures: {}",
+            result.message
+        )));
+    }
+    // control messages and heartbeats
+    if !result.event.is_empty() || (result.feed != "book_snapshot" && result.feed != "book") {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let product = result.product_id;
+    let mut seqs = KRAKEN_FUTURES_SEQ.lock().unwrap();
+    if result.feed == "book" {
+        let Some(last) = seqs.get(&product) else {
+            return Ok(ParseOutcome::Ignore);
+        };
+        if result.seq != last + 1 {
+            return Ok(ParseOutcome::FatalDesync(format!(
+                "kraken_futures: seq {} on {}, expected {}",
+                result.seq,
+                product,
+                last + 1
+            )));
+        }
+    }
+    let mut tmp = KRAKEN_FUTURES.lock().unwrap();
+    evict_stale(&mut tmp, &product, get_unixtime());
+    // its seq is still known, the levels are gone
+    if result.feed == "book" && !tmp.contains_key(&product) {
+        return Ok(ParseOutcome::FatalDesync(format!(
+            "kraken_futures: delta on {} whose book was evicted",
+            product
+        )));
+    }
+    seqs.insert(product.clone(), result.seq);
+    let entry = tmp.entry(product.clone()).or_insert_with(|| {
+        // one connection carries every product
+        let mut ob = Orderbook::new("kraken_futures");
+        ob.pair = Some(product.clone());
+        Arc::new(ob)
+    });
+    let ob = Arc::make_mut(entry);
+    if result.feed == "book_snapshot" {
+        ob.bid.clear();
+        ob.ask.clear();
+        for (side, levels) in [(Side::Bid, result.bids), (Side::Ask, result.asks)] {
+            for level in levels {
+                ob.insert(
+                    side,
+                    decimal(&level.price.to_string())?,
+                    decimal(&level.qty.to_string())?,
+                );
+            }
+        }
+    } else {
+        let side = match result.side.as_str() {
+            "buy" => Side::Bid,
+            "sell" => Side::Ask,
+            _ => return Err(anyhow!("kraken_futures: side {}", result.side)),
+        };
+        let (Some(price), Some(qty)) = (result.price, result.qty) else {
+            return Err(anyhow!("kraken_futures: no price or qty on {}", product));
+        };
+        ob.insert(
+            side,
+            decimal(&price.to_string())?,
+            decimal(&qty.to_string())?,
+        );
+    }
+    Ok(ParseOutcome::Book(entry.clone()))
+}
+
+// {"action":"ping","ping":id} -> {"action":"pong","pong":id}
+fn lbank_pong(raw: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Ping {
+        action: String,
+        ping: String,
+    }
+    let ping: Ping = serde_json::from_str(raw).ok()?;
+    (ping.action == "ping")
+        .then(|| serde_json::json!({"action": "pong", "pong": ping.ping}).to_string())
+}
+
+// every depth message is the top 50 of the pair whole
+fn lbank_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct Depth {
+        asks: Vec<(serde_json::Number, serde_json::Number)>,
+        bids: Vec<(serde_json::Number, serde_json::Number)>,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        r#type: String,
+        pair: String,
+        depth: Depth,
+        status: String,
+        message: String,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.status == "error" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "lbank: {}",
+            result.message
+        )));
+    }
+    // pings, answered by lbank_pong
+    if result.r#type != "depth" {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let mut ob = Orderbook::new("lbank");
+    // one connection carries every pair
+    ob.pair = Some(result.pair);
+    for (side, levels) in [
+        (Side::Bid, result.depth.bids),
+        (Side::Ask, result.depth.asks),
+    ] {
+        for (price, amount) in levels {
+            ob.insert(
+                side,
+                decimal(&price.to_string())?,
+                decimal(&amount.to_string())?,
+            );
+        }
+    }
+    Ok(ParseOutcome::Book(Arc::new(ob)))
+}
+
+// {"ping": ts} -> {"pong": ts}
+fn huobi_pong(raw: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Ping {
+        ping: u64,
+    }
+    let ping: Ping = serde_json::from_str(raw).ok()?;
+    Some(format!(r#"{{"pong":{}}}"#, ping.ping))
+}
+
+// market.{symbol}.mbp.refresh.20: the top 20 whole on every push, the
+// prices and amounts as json numbers
+fn huobi_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct Tick {
+        bids: Vec<[serde_json::Number; 2]>,
+        asks: Vec<[serde_json::Number; 2]>,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        ch: String,
+        tick: Option<Tick>,
+        // sub replies
+        status: String,
+        #[serde(rename = "err-code")]
+        err_code: String,
+        #[serde(rename = "err-msg")]
+        err_msg: String,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.status == "error" {
+        return Ok(ParseOutcome::ProtocolError(format!(
+            "huobi: {} {}",
+            result.err_code, result.err_msg
+        )));
+    }
+    // pings, sub acks
+    let Some(tick) = result.tick.filter(|_| result.ch.contains(".mbp.")) else {
+        return Ok(ParseOutcome::Ignore);
+    };
+    let mut ob = Orderbook::new("huobi");
+    // the channel names the symbol, one connection carries every one
+    ob.pair = result.ch.split('.').nth(1).map(str::to_string);
+    for (side, levels) in [(Side::Bid, tick.bids), (Side::Ask, tick.asks)] {
+        for [price, amount] in levels {
+            ob.insert(
+                side,
+                decimal(&price.to_string())?,
+                decimal(&amount.to_string())?,
+            );
+        }
+    }
+    Ok(ParseOutcome::Book(Arc::new(ob)))
+}
+
+// POST /api/v1/bullet-public: a token for the public channels, with the
+// servers to dial and how often they want a ping
+fn kucoin_bootstrap(raw: &str) -> Result<Bootstrap> {
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Server {
+        endpoint: String,
+        ping_interval: u64,
+    }
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Data {
+        token: String,
+        instance_servers: Vec<Server>,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Bullet {
+        code: String,
+        data: Option<Data>,
+        #[serde(default)]
+        msg: String,
+    }
+    let result: Bullet = serde_json::from_str(raw)?;
+    let data = result
+        .data
+        .filter(|_| result.code == "200000")
+        .ok_or_else(|| anyhow!("kucoin bullet: {} {}", result.code, result.msg))?;
+    let server = data
+        .instance_servers
+        .first()
+        .ok_or_else(|| anyhow!("kucoin bullet: no instance servers"))?;
+    Ok(Bootstrap {
+        url: format!(
+            "{}?token={}&connectId={}",
+            server.endpoint,
+            data.token,
+            get_unixtime()
+        ),
+        heartbeat: Some(Duration::from_millis(server.ping_interval)),
+    })
+}
+
+// /spotMarket/level2Depth50:{symbol}: the top 50 whole on every push
+fn kucoin_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct Depth {
+        bids: Vec<[String; 2]>,
+        asks: Vec<[String; 2]>,
+    }
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        #[serde(rename = "type")]
+        kind: String,
+        topic: String,
+        // the depth of a message, the reason of an error
+        data: Value,
+        code: Value,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    match result.kind.as_str() {
+        "message" if result.topic.starts_with("/spotMarket/level2Depth50:") => {}
+        "error" => {
+            return Ok(ParseOutcome::ProtocolError(format!(
+                "kucoin: {} {}",
+                result.code, result.data
+            )))
+        }
+        // welcome, ack, pong
+        _ => return Ok(ParseOutcome::Ignore),
+    }
+    let depth: Depth = serde_json::from_value(result.data)?;
+    let mut ob = Orderbook::new("kucoin");
+    // the topic names the symbol, one connection carries every one
+    ob.pair = result
+        .topic
+        .split_once(':')
+        .map(|(_, symbol)| symbol.to_string());
+    for (side, levels) in [(Side::Bid, depth.bids), (Side::Ask, depth.asks)] {
+        for [price, amount] in levels {
+            ob.insert(side, decimal(&price)?, decimal(&amount)?);
+        }
+    }
+    Ok(ParseOutcome::Book(Arc::new(ob)))
+}
+
+// a spot.order_book_update diff, covering the update ids U to u
+#[derive(Deserialize, Debug)]
+struct GateioDiff {
+    #[serde(rename = "s")]
+    pair: String,
+    #[serde(rename = "U")]
+    first: u64,
+    #[serde(rename = "u")]
+    last: u64,
+    #[serde(rename = "b", default)]
+    bids: Vec<[String; 2]>,
+    #[serde(rename = "a", default)]
+    asks: Vec<[String; 2]>,
+}
+
+static GATEIO: Lazy<SeededBooks<GateioDiff>> = Lazy::new(|| SeededBooks::new("gateio"));
+
+fn gateio_clear() {
+    GATEIO.clear();
+}
+
+fn gateio_forget(pair: &str) {
+    GATEIO.forget(pair);
+}
+
+fn gateio_pending() -> Vec<String> {
+    GATEIO.pending()
+}
+
+// 0 amounts remove the level
+fn gateio_apply(ob: &mut Orderbook, bids: &[[String; 2]], asks: &[[String; 2]]) -> Result<()> {
+    for (side, levels) in [(Side::Bid, bids), (Side::Ask, asks)] {
+        for [price, amount] in levels {
+            ob.insert(side, decimal(price)?, decimal(amount)?);
+        }
+    }
+    Ok(())
+}
+
+// the update ids of a diff against the one the book is at
+fn gateio_step(last: u64, diff: &GateioDiff) -> Step {
+    if diff.last <= last {
+        Step::Stale
+    } else if diff.first > last + 1 {
+        Step::Gap(diff.first)
+    } else {
+        Step::Next(diff.last)
+    }
+}
+
+// GET /api/v4/spot/order_book?with_id=true, the buffered diffs past its id
+// on top. A snapshot older than the first buffered diff is an error,
+// fetched again after a backoff.
+fn gateio_seed(pair: &str, body: &str) -> Result<()> {
+    #[derive(Deserialize, Debug)]
+    struct Snapshot {
+        id: u64,
+        bids: Vec<[String; 2]>,
+        asks: Vec<[String; 2]>,
+    }
+    let snapshot: Snapshot = serde_json::from_str(body)?;
+    let mut ob = Orderbook::new("gateio");
+    ob.pair = Some(pair.to_string());
+    gateio_apply(&mut ob, &snapshot.bids, &snapshot.asks)?;
+    GATEIO.seed(pair, ob, snapshot.id, gateio_step, |ob, diff| {
+        gateio_apply(ob, &diff.bids, &diff.asks).map(|_| true)
+    })
+}
+
+// the spot.order_book_update channel, applied onto the rest snapshot
+fn gateio_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        channel: String,
+        event: String,
+        result: Value,
+        error: Option<Value>,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if let Some(error) = result.error.filter(|e| !e.is_null()) {
+        return Ok(ParseOutcome::ProtocolError(format!("gateio: {}", error)));
+    }
+    // subscribe acks, pongs
+    if result.channel != "spot.order_book_update" || result.event != "update" {
+        return Ok(ParseOutcome::Ignore);
+    }
+    let diff: GateioDiff = serde_json::from_value(result.result)?;
+    let pair = diff.pair.clone();
+    GATEIO.live(&pair, diff, gateio_step, |ob, diff| {
+        gateio_apply(ob, &diff.bids, &diff.asks).map(|_| true)
+    })
+}
+
+static BITFINEX: Lazy<Mutex<HashMap<String, Arc<Orderbook>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// chanId -> symbol, from the subscribed events
+static BITFINEX_CHANNELS: Lazy<Mutex<HashMap<u64, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn bitfinex_clear() {
+    BITFINEX.lock().unwrap().clear();
+    BITFINEX_CHANNELS.lock().unwrap().clear();
+}
+
+// a [price, count, amount] level: count 0 removes it, the sign of the
+// amount tells a bid from an ask
+fn bitfinex_apply(ob: &mut Orderbook, level: &[Value]) -> Result<()> {
+    let [price, count, amount] = level else {
+        return Err(anyhow!("bitfinex: not a level: {:?}", level));
+    };
+    let price = decimal(&price.to_string())?;
+    let amount = decimal(&amount.to_string())?;
+    let side = if amount > BigDecimal::zero() {
+        Side::Bid
+    } else {
+        Side::Ask
+    };
+    let amount = if count.as_u64() == Some(0) {
+        BigDecimal::zero()
+    } else {
+        amount.abs()
+    };
+    ob.insert(side, price, amount);
+    Ok(())
+}
+
+// the book channel: json events, then [chanId, levels] snapshots and
+// [chanId, level] updates
+fn bitfinex_parser(raw: &str) -> Result<ParseOutcome> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsEvent {
+        event: String,
+        #[serde(rename = "chanId")]
+        chan_id: u64,
+        symbol: String,
+        code: u64,
+        msg: String,
+    }
+    let frame: Value = serde_json::from_str(raw)?;
+    let Value::Array(frame) = frame else {
+        let result: WsEvent = serde_json::from_value(frame)?;
+        return Ok(match result.event.as_str() {
+            "subscribed" => {
+                BITFINEX_CHANNELS
+                    .lock()
+                    .unwrap()
+                    .insert(result.chan_id, result.symbol);
+                ParseOutcome::Ignore
+            }
+            "error" => {
+                ParseOutcome::ProtocolError(format!("bitfinex: {} {}", result.code, result.msg))
+            }
+            // the venue restarts its websocket servers
+            "info" if result.code == 20051 => {
+                ParseOutcome::FatalDesync("bitfinex requested a reconnect".to_string())
+            }
+            _ => ParseOutcome::Ignore,
+        });
+    };
+    let (Some(chan_id), Some(payload)) = (frame.first().and_then(Value::as_u64), frame.get(1))
+    else {
+        return Err(anyhow!("bitfinex: not a channel frame"));
+    };
+    let levels = match payload {
+        // the "hb" heartbeats
+        Value::String(_) => return Ok(ParseOutcome::Ignore),
+        Value::Array(levels) => levels,
+        _ => return Err(anyhow!("bitfinex: not a book: {}", payload)),
+    };
+    let Some(symbol) = BITFINEX_CHANNELS.lock().unwrap().get(&chan_id).cloned() else {
+        return Ok(ParseOutcome::FatalDesync(format!(
+            "bitfinex: no subscription for channel {}",
+            chan_id
+        )));
+    };
+    let mut tmp = BITFINEX.lock().unwrap();
+    evict_stale(&mut tmp, &symbol, get_unixtime());
+    let entry = tmp.entry(symbol.clone()).or_insert_with(|| {
+        // one connection carries every symbol
+        let mut ob = Orderbook::new("bitfinex");
+        ob.pair = Some(symbol.clone());
+        Arc::new(ob)
+    });
+    let ob = Arc::make_mut(entry);
+    if levels.first().is_none_or(Value::is_array) {
+        ob.bid.clear();
+        ob.ask.clear();
+        for level in levels {
+            let level = level
+                .as_array()
+                .ok_or_else(|| anyhow!("bitfinex: not a level: {}", level))?;
+            bitfinex_apply(ob, level)?;
+        }
+    } else {
+        bitfinex_apply(ob, levels)?;
+    }
+    Ok(ParseOutcome::Book(entry.clone()))
+}
+
+// the "{pair}@trade" stream
+fn binance_trade_parser(raw: &str) -> Result<Option<Vec<Trade>>> {
+    #[derive(Default, Deserialize, Debug)]
+    #[serde(default)]
+    struct WsTrade {
+        #[serde(rename = "e")]
+        event: String,
+        #[serde(rename = "s")]
+        symbol: String,
+        #[serde(rename = "q")]
+        quantity: String,
+        #[serde(rename = "T")]
+        time: u64,
+    }
+    let result: WsTrade = serde_json::from_str(raw)?;
+    if result.event != "trade" {
+        return Ok(None);
+    }
+    Ok(Some(vec![Trade {
+        pair: result.symbol,
+        timestamp: result.time as u128,
+        quantity: decimal(&result.quantity)?,
+    }]))
+}
+
+// the "live_trades_{pair}" channel
+fn bitstamp_trade_parser(raw: &str) -> Result<Option<Vec<Trade>>> {
+    #[derive(Deserialize, Debug)]
+    struct LiveTrade {
+        amount_str: String,
+        microtimestamp: String,
+    }
+    #[derive(Deserialize, Debug)]
+    struct WsEvent {
+        data: Value,
+        event: String,
+        channel: String,
+    }
+    let result: WsEvent = serde_json::from_str(raw)?;
+    if result.event != "trade" {
+        return Ok(None);
+    }
+    let pair = match result.channel.strip_prefix("live_trades_") {
+        Some(pair) => pair.to_string(),
+        None => return Ok(None),
+    };
+    let trade: LiveTrade = serde_json::from_value(result.data)?;
+    let micros: u128 = trade.microtimestamp.parse()?;
+    Ok(Some(vec![Trade {
+        pair,
+        timestamp: micros / 1000,
+        quantity: decimal(&trade.amount_str)?,
+    }]))
+}
+
+// [channel_id, [[price, volume, time, side, type, misc], ...], "trade", pair]
+fn kraken_trade_parser(raw: &str) -> Result<Option<Vec<Trade>>> {
+    if !raw.starts_with('[') {
+        return Ok(None);
+    }
+    let result: Vec<Value> = serde_json::from_str(raw)?;
+    if result.len() < 4 || result[result.len() - 2] != "trade" {
+        return Ok(None);
+    }
+    let pair: String = serde_json::from_value(result[result.len() - 1].clone())?;
+    let prints: Vec<Vec<String>> = serde_json::from_value(result[1].clone())?;
+    let mut trades = vec![];
+    for print in prints {
+        if print.len() < 3 {
+            return Err(anyhow!("kraken: short trade {:?}", print));
+        }
+        // seconds with microsecond decimals
+        let secs: f64 = print[2].parse()?;
+        trades.push(Trade {
+            pair: pair.clone(),
+            timestamp: (secs * 1000.) as u128,
+            quantity: decimal(&print[1])?,
+        });
+    }
+    Ok(Some(trades))
+}
+
+// (map name, books) of the parser state maps
+pub fn state_sizes() -> Vec<(&'static str, usize)> {
+    vec![
+        ("binance_futures", BINANCE_FUTURES.lock().unwrap().len()),
+        ("bitstamp_full", BITSTAMP_FULL.books.lock().unwrap().len()),
+        ("independentreserve", INDRESERVE.lock().unwrap().len()),
+        ("btcmarkets", BTCMARKETS.lock().unwrap().len()),
+        ("coinjar", COINJAR.lock().unwrap().len()),
+        ("kraken", KRAKEN.lock().unwrap().len()),
+        ("okx", OKX.lock().unwrap().len()),
+        ("bybit", BYBIT.lock().unwrap().len()),
+        ("coinbase", COINBASE.lock().unwrap().len()),
+        ("gateio", GATEIO.books.lock().unwrap().len()),
+        ("bitfinex", BITFINEX.lock().unwrap().len()),
+        ("gemini", GEMINI.lock().unwrap().len()),
+        ("bithumb", BITHUMB.books.lock().unwrap().len()),
+        ("poloniex", POLONIEX.lock().unwrap().len()),
+        ("bitso", BITSO.books.lock().unwrap().len()),
+        ("coinex", COINEX.lock().unwrap().len()),
+        ("deribit", DERIBIT.lock().unwrap().len()),
+        ("bitmex", BITMEX.lock().unwrap().len()),
+        ("kraken_futures", KRAKEN_FUTURES.lock().unwrap().len()),
+        ("bitflyer", BITFLYER.books.lock().unwrap().len()),
+        ("coincheck", COINCHECK.books.lock().unwrap().len()),
+        (
+            "bitmex_ids",
+            BITMEX_IDS
+                .lock()
+                .unwrap()
+                .values()
+                .map(|ids| ids.len())
+                .sum(),
+        ),
+        (
+            "luno_orders",
+            LUNO.lock().unwrap().as_ref().map_or(0, |s| s.orders.len()),
+        ),
+    ]
+}
+
+// The API Map compile-time static map that handles depth orderbook subscription and parsing
+pub static WS_APIMAP: phf::Map<&'static str, Api> = phf_map! {
+    "binance" => Api {
+        // one connection for every pair, the streams are in the url
+        endpoint: "wss://stream.binance.com:9443/stream?streams={}",
+        subscribe_template: &["{}@depth{}@100ms"],
+        parse: (binance_parser as ParseFunc),
+        trade_subscribe_template: &["{}@trade"],
+        parse_trades: Some(binance_trade_parser as TradeParseFunc),
+        render_url: true,
+        combined_stream: true,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: || {},
+        normalize_pair: None,
+        depth_streams: Some(DepthStreams {
+            template: "{}@depth{}@{}ms",
+            depths: &[5, 10, 20],
+            intervals_ms: &[100, 1000],
+        }),
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    // binance for the US, stream.binance.com being geo-blocked there
+    "binanceus" => Api {
+        endpoint: "wss://stream.binance.us:9443/stream?streams={}",
+        subscribe_template: &["{}@depth{}@100ms"],
+        parse: (binanceus_parser as ParseFunc),
+        trade_subscribe_template: &["{}@trade"],
+        parse_trades: Some(binance_trade_parser as TradeParseFunc),
+        render_url: true,
+        combined_stream: true,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: || {},
+        normalize_pair: None,
+        depth_streams: Some(DepthStreams {
+            template: "{}@depth{}@{}ms",
+            depths: &[5, 10, 20],
+            intervals_ms: &[100, 1000],
+        }),
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "binance_futures" => Api {
+        endpoint: "wss://fstream.binance.com/ws",
+        subscribe_template: &[
+            r#"{{"id":1, "method":"SUBSCRIBE", "params": ["{}@depth{}@100ms"]}}"#,
+            r#"{{"id":2, "method":"SUBSCRIBE", "params": ["{}@markPrice@1s"]}}"#,
+        ],
+        parse: (binance_futures_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: binance_futures_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "bitstamp" => Api {
+        endpoint: "wss://ws.bitstamp.net",
+        subscribe_template: &[r#"{{"event":"bts:subscribe","data":{{"channel":"order_book_{}"}}}}"#],
+        parse: (bitstamp_parser as ParseFunc),
+        trade_subscribe_template: &[r#"{{"event":"bts:subscribe","data":{{"channel":"live_trades_{}"}}}}"#],
+        parse_trades: Some(bitstamp_trade_parser as TradeParseFunc),
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: || {},
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"event":"bts:subscribe","data":{{"channel":"order_book_{}"}}}}"#],
+            unsubscribe: &[r#"{{"event":"bts:unsubscribe","data":{{"channel":"order_book_{}"}}}}"#],
+            forget: |_| {},
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    // the full book, kept from the changes instead of the top 100 snapshots
+    "bitstamp_full" => Api {
+        endpoint: "wss://ws.bitstamp.net",
+        subscribe_template: &[r#"{{"event":"bts:subscribe","data":{{"channel":"diff_order_book_{}"}}}}"#],
+        parse: (bitstamp_full_parser as ParseFunc),
+        trade_subscribe_template: &[r#"{{"event":"bts:subscribe","data":{{"channel":"live_trades_{}"}}}}"#],
+        parse_trades: Some(bitstamp_trade_parser as TradeParseFunc),
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: bitstamp_full_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"event":"bts:subscribe","data":{{"channel":"diff_order_book_{}"}}}}"#],
+            unsubscribe: &[r#"{{"event":"bts:unsubscribe","data":{{"channel":"diff_order_book_{}"}}}}"#],
+            forget: bitstamp_full_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: Some(Seed {
+            pending: bitstamp_full_pending,
+            url: "https://www.bitstamp.net/api/v2/order_book/{}/?group=1",
+            apply: bitstamp_full_seed,
+        }),
+        max_pairs: None,
+    },
+    "independentreserve" => Api {
+        endpoint: "wss://websockets.independentreserve.com/orderbook/20?subscribe={}",
+        subscribe_template: &[r#"{{"Event": "Subscribe", "Data": ["{}"]}}"#],
+        parse: (indreserve_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: true,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: indreserve_clear,
+        normalize_pair: Some(indreserve_channel),
+        depth_streams: None,
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
     },
     "btcmarkets" => Api {
         endpoint: "wss://socket.btcmarkets.net/v2",
         subscribe_template: &[r#"{{"marketIds": ["{}"], "channels": ["orderbook", "tick"], "messageType": "subscribe"}}"#],
         parse: (btcmarkets_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
         render_url: false,
+        combined_stream: false,
+        has_ticker: true,
         heartbeat: None,
         reconnect_sec: None,
         clear: btcmarkets_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        // a second subscribe message replaces the subscriptions of the first
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"marketIds": ["{}"], "channels": ["orderbook", "tick"], "messageType": "addSubscription"}}"#],
+            unsubscribe: &[r#"{{"marketIds": ["{}"], "channels": ["orderbook", "tick"], "messageType": "removeSubscription"}}"#],
+            forget: btcmarkets_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
     },
     "coinjar" => Api {
         endpoint: "wss://feed.exchange.coinjar.com/socket/websocket",
@@ -487,12 +3620,25 @@ pub static WS_APIMAP: phf::Map<&'static str, Api> = phf_map! {
             r#"{{"topic": "ticker:{}", "event": "phx_join", "payload": {{}}, "ref": 0}}"#,
         ],
         parse: (coinjar_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
         render_url: false,
+        combined_stream: false,
+        has_ticker: true,
         // this will disconnect the websocket
-        //heartbeat: Some((10, r#"{{"topic": "phoenix", "event": "heartbeat", "payload": {{}}, "ref": null}}"#)),
+        //heartbeat: Some((10, Heartbeat::Binary(r#"{{"topic": "phoenix", "event": "heartbeat", "payload": {{}}, "ref": null}}"#))),
         heartbeat: None,
         reconnect_sec: Some(30),
         clear: coinjar_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
     },
     "kraken" => Api {
         endpoint: "wss://ws.kraken.com",
@@ -500,17 +3646,636 @@ pub static WS_APIMAP: phf::Map<&'static str, Api> = phf_map! {
             r#"{{"event":"subscribe","pair":["{}"], "subscription": {{"name":"book","depth":25}}}}"#,
             r#"{{"event":"subscribe","pair":["{}"], "subscription": {{"name":"ticker"}}}}"#],
         parse: (kraken_parser as ParseFunc),
+        trade_subscribe_template: &[r#"{{"event":"subscribe","pair":["{}"], "subscription": {{"name":"trade"}}}}"#],
+        parse_trades: Some(kraken_trade_parser as TradeParseFunc),
         render_url: false,
+        combined_stream: false,
+        has_ticker: true,
         heartbeat: None,
         reconnect_sec: None,
         clear: kraken_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[
+                r#"{{"event":"subscribe","pair":["{}"], "subscription": {{"name":"book","depth":25}}}}"#,
+                r#"{{"event":"subscribe","pair":["{}"], "subscription": {{"name":"ticker"}}}}"#],
+            unsubscribe: &[
+                r#"{{"event":"unsubscribe","pair":["{}"], "subscription": {{"name":"book","depth":25}}}}"#,
+                r#"{{"event":"unsubscribe","pair":["{}"], "subscription": {{"name":"ticker"}}}}"#],
+            forget: kraken_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "okx" => Api {
+        endpoint: "wss://ws.okx.com:8443/ws/v5/public",
+        subscribe_template: &[r#"{{"op":"subscribe","args":[{{"channel":"books5","instId":"{}"}}]}}"#],
+        parse: (okx_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        // the connection is dropped after 30 seconds without a message
+        heartbeat: Some((20, Heartbeat::Text("ping"))),
+        reconnect_sec: None,
+        clear: okx_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"op":"subscribe","args":[{{"channel":"books5","instId":"{}"}}]}}"#],
+            unsubscribe: &[r#"{{"op":"unsubscribe","args":[{{"channel":"books5","instId":"{}"}}]}}"#],
+            forget: okx_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "bybit" => Api {
+        endpoint: "wss://stream.bybit.com/v5/public/spot",
+        subscribe_template: &[r#"{{"op":"subscribe","args":["orderbook.50.{}"]}}"#],
+        parse: (bybit_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: Some((20, Heartbeat::Text(r#"{"op":"ping"}"#))),
+        reconnect_sec: None,
+        clear: bybit_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"op":"subscribe","args":["orderbook.50.{}"]}}"#],
+            unsubscribe: &[r#"{{"op":"unsubscribe","args":["orderbook.50.{}"]}}"#],
+            forget: bybit_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "coinbase" => Api {
+        endpoint: "wss://ws-feed.exchange.coinbase.com",
+        // the product id as configured, ex: BTC-USD
+        subscribe_template: &[r#"{{"type":"subscribe","product_ids":["{}"],"channels":["level2_batch"]}}"#],
+        parse: (coinbase_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: coinbase_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"type":"subscribe","product_ids":["{}"],"channels":["level2_batch"]}}"#],
+            unsubscribe: &[r#"{{"type":"unsubscribe","product_ids":["{}"],"channels":["level2_batch"]}}"#],
+            forget: coinbase_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "huobi" => Api {
+        endpoint: "wss://api.huobi.pro/ws",
+        // the symbol in lower case, ex: btcusdt
+        subscribe_template: &[r#"{{"sub":"market.{}.mbp.refresh.20","id":"arb_monitor"}}"#],
+        parse: (huobi_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: || {},
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"sub":"market.{}.mbp.refresh.20","id":"arb_monitor"}}"#],
+            unsubscribe: &[r#"{{"unsub":"market.{}.mbp.refresh.20","id":"arb_monitor"}}"#],
+            forget: |_| {},
+        }),
+        compression: Some(Compression::Gzip),
+        pong: Some(huobi_pong),
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "kucoin" => Api {
+        // POSTed for the websocket url, see kucoin_bootstrap
+        endpoint: "https://api.kucoin.com/api/v1/bullet-public",
+        // ex: BTC-USDT
+        subscribe_template: &[r#"{{"id":"arb_monitor","type":"subscribe","topic":"/spotMarket/level2Depth50:{}","response":true}}"#],
+        parse: (kucoin_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        // every pingInterval of the bullet
+        heartbeat: Some((18, Heartbeat::Text(r#"{"id":"arb_monitor","type":"ping"}"#))),
+        reconnect_sec: None,
+        clear: || {},
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"id":"arb_monitor","type":"subscribe","topic":"/spotMarket/level2Depth50:{}","response":true}}"#],
+            unsubscribe: &[r#"{{"id":"arb_monitor","type":"unsubscribe","topic":"/spotMarket/level2Depth50:{}","response":true}}"#],
+            forget: |_| {},
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: Some(kucoin_bootstrap),
+        seed: None,
+        max_pairs: None,
+    },
+    "gateio" => Api {
+        endpoint: "wss://api.gateio.ws/ws/v4/",
+        // ex: BTC_USDT
+        subscribe_template: &[r#"{{"channel":"spot.order_book_update","event":"subscribe","payload":["{}","100ms"]}}"#],
+        parse: (gateio_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: Some((10, Heartbeat::Text(r#"{"channel":"spot.ping"}"#))),
+        reconnect_sec: None,
+        clear: gateio_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"channel":"spot.order_book_update","event":"subscribe","payload":["{}","100ms"]}}"#],
+            unsubscribe: &[r#"{{"channel":"spot.order_book_update","event":"unsubscribe","payload":["{}","100ms"]}}"#],
+            forget: gateio_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: Some(Seed {
+            pending: gateio_pending,
+            url: "https://api.gateio.ws/api/v4/spot/order_book?currency_pair={}&limit=100&with_id=true",
+            apply: gateio_seed,
+        }),
+        max_pairs: None,
+    },
+    "bitfinex" => Api {
+        endpoint: "wss://api-pub.bitfinex.com/ws/2",
+        // the trading symbol, ex: tBTCUSD
+        subscribe_template: &[r#"{{"event":"subscribe","channel":"book","symbol":"{}","prec":"P0","len":"25"}}"#],
+        parse: (bitfinex_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: bitfinex_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        // unsubscribing takes the chanId, not the symbol
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "gemini" => Api {
+        endpoint: "wss://api.gemini.com/v2/marketdata",
+        // ex: BTCUSD
+        subscribe_template: &[r#"{{"type":"subscribe","subscriptions":[{{"name":"l2","symbols":["{}"]}}]}}"#],
+        parse: (gemini_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: gemini_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"type":"subscribe","subscriptions":[{{"name":"l2","symbols":["{}"]}}]}}"#],
+            unsubscribe: &[r#"{{"type":"unsubscribe","subscriptions":[{{"name":"l2","symbols":["{}"]}}]}}"#],
+            forget: gemini_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "upbit" => Api {
+        endpoint: "wss://api.upbit.com/websocket/v1",
+        // ex: KRW-BTC, see upbit_code
+        subscribe_template: &[r#"[{{"ticket":"arb_monitor"}},{{"type":"orderbook","codes":["{}"]}}]"#],
+        parse: (upbit_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        // idle connections are closed after 120s
+        heartbeat: Some((60, Heartbeat::Text("PING"))),
+        reconnect_sec: None,
+        clear: || {},
+        normalize_pair: Some(upbit_code),
+        depth_streams: None,
+        // a subscription replaces the ones before it
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "bithumb" => Api {
+        endpoint: "wss://pubwss.bithumb.com/pub/ws",
+        // ex: BTC_KRW
+        subscribe_template: &[r#"{{"type":"orderbookdepth","symbols":["{}"]}}"#],
+        parse: (bithumb_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: bithumb_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: Some(Seed {
+            pending: bithumb_pending,
+            url: "https://api.bithumb.com/public/orderbook/{}",
+            apply: bithumb_seed,
+        }),
+        max_pairs: None,
+    },
+    "luno" => Api {
+        // one pair a connection, ex: XBTZAR
+        endpoint: "wss://ws.luno.com/api/1/stream/{}",
+        subscribe_template: &[],
+        parse: (luno_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: true,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: luno_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: Some(r#"{{"api_key_id":"{}","api_key_secret":"{}"}}"#),
+        bootstrap: None,
+        seed: None,
+        max_pairs: Some(1),
+    },
+    "poloniex" => Api {
+        endpoint: "wss://ws.poloniex.com/ws/public",
+        // ex: BTC_USDT
+        subscribe_template: &[r#"{{"event":"subscribe","channel":["book_lv2"],"symbols":["{}"]}}"#],
+        parse: (poloniex_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: Some((30, Heartbeat::Text(r#"{"event":"ping"}"#))),
+        reconnect_sec: None,
+        clear: poloniex_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"event":"subscribe","channel":["book_lv2"],"symbols":["{}"]}}"#],
+            unsubscribe: &[r#"{{"event":"unsubscribe","channel":["book_lv2"],"symbols":["{}"]}}"#],
+            forget: poloniex_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "bitso" => Api {
+        endpoint: "wss://ws.bitso.com",
+        // ex: btc_mxn
+        subscribe_template: &[r#"{{"action":"subscribe","book":"{}","type":"diff-orders"}}"#],
+        parse: (bitso_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: bitso_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: Some(Seed {
+            pending: bitso_pending,
+            url: "https://api.bitso.com/v3/order_book/?book={}&aggregate=false",
+            apply: bitso_seed,
+        }),
+        max_pairs: None,
+    },
+    "coinex" => Api {
+        endpoint: "wss://socket.coinex.com/v2/spot",
+        // [market, limit, interval, full or diff], ex: BTCUSDT
+        subscribe_template: &[r#"{{"method":"depth.subscribe","params":{{"market_list":[["{}",20,"0",false]]}},"id":1}}"#],
+        parse: (coinex_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        // idle connections are closed after a minute
+        heartbeat: Some((30, Heartbeat::Text(r#"{"method":"server.ping","params":{},"id":2}"#))),
+        reconnect_sec: None,
+        clear: coinex_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: None,
+        compression: Some(Compression::Gzip),
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "deribit" => Api {
+        endpoint: "wss://www.deribit.com/ws/api/v2",
+        // the instrument, ex: BTC-PERPETUAL. The heartbeats come every 30s
+        // once set.
+        subscribe_template: &[
+            r#"{{"jsonrpc":"2.0","id":1,"method":"public/subscribe","params":{{"channels":["book.{}.100ms"]}}}}"#,
+            r#"{{"jsonrpc":"2.0","id":2,"method":"public/set_heartbeat","params":{{"interval":30}}}}"#,
+        ],
+        parse: (deribit_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: deribit_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"jsonrpc":"2.0","id":1,"method":"public/subscribe","params":{{"channels":["book.{}.100ms"]}}}}"#],
+            unsubscribe: &[r#"{{"jsonrpc":"2.0","id":4,"method":"public/unsubscribe","params":{{"channels":["book.{}.100ms"]}}}}"#],
+            forget: deribit_forget,
+        }),
+        compression: None,
+        pong: Some(deribit_pong),
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "bitmex" => Api {
+        endpoint: "wss://ws.bitmex.com/realtime",
+        // ex: XBTUSD
+        subscribe_template: &[r#"{{"op":"subscribe","args":["orderBookL2:{}"]}}"#],
+        parse: (bitmex_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        // a quiet connection is dropped
+        heartbeat: Some((5, Heartbeat::Text("ping"))),
+        reconnect_sec: None,
+        clear: bitmex_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"op":"subscribe","args":["orderBookL2:{}"]}}"#],
+            unsubscribe: &[r#"{{"op":"unsubscribe","args":["orderBookL2:{}"]}}"#],
+            forget: bitmex_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "hyperliquid" => Api {
+        endpoint: "wss://api.hyperliquid.xyz/ws",
+        // the coin, ex: BTC
+        subscribe_template: &[r#"{{"method":"subscribe","subscription":{{"type":"l2Book","coin":"{}"}}}}"#],
+        parse: (hyperliquid_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        // connections quiet for 60s are closed
+        heartbeat: Some((50, Heartbeat::Text(r#"{"method":"ping"}"#))),
+        reconnect_sec: None,
+        clear: || {},
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"method":"subscribe","subscription":{{"type":"l2Book","coin":"{}"}}}}"#],
+            unsubscribe: &[r#"{{"method":"unsubscribe","subscription":{{"type":"l2Book","coin":"{}"}}}}"#],
+            forget: |_| {},
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "kraken_futures" => Api {
+        endpoint: "wss://futures.kraken.com/ws/v1",
+        // the product id, as is, ex: PI_XBTUSD. The heartbeat feed keeps the
+        // connection busy when the books are quiet.
+        subscribe_template: &[
+            r#"{{"event":"subscribe","feed":"book","product_ids":["{}"]}}"#,
+            r#"{{"event":"subscribe","feed":"heartbeat"}}"#,
+        ],
+        parse: (kraken_futures_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: kraken_futures_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"event":"subscribe","feed":"book","product_ids":["{}"]}}"#],
+            unsubscribe: &[r#"{{"event":"unsubscribe","feed":"book","product_ids":["{}"]}}"#],
+            forget: kraken_futures_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
+    },
+    "bitflyer" => Api {
+        endpoint: "wss://ws.lightstream.bitflyer.com/json-rpc",
+        // the product code, ex: BTC_JPY
+        subscribe_template: &[
+            r#"{{"jsonrpc":"2.0","method":"subscribe","params":{{"channel":"lightning_board_snapshot_{}"}},"id":1}}"#,
+            r#"{{"jsonrpc":"2.0","method":"subscribe","params":{{"channel":"lightning_board_{}"}},"id":2}}"#,
+        ],
+        parse: (bitflyer_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: bitflyer_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[
+                r#"{{"jsonrpc":"2.0","method":"subscribe","params":{{"channel":"lightning_board_snapshot_{}"}},"id":1}}"#,
+                r#"{{"jsonrpc":"2.0","method":"subscribe","params":{{"channel":"lightning_board_{}"}},"id":2}}"#,
+            ],
+            unsubscribe: &[
+                r#"{{"jsonrpc":"2.0","method":"unsubscribe","params":{{"channel":"lightning_board_snapshot_{}"}},"id":3}}"#,
+                r#"{{"jsonrpc":"2.0","method":"unsubscribe","params":{{"channel":"lightning_board_{}"}},"id":4}}"#,
+            ],
+            forget: bitflyer_forget,
+        }),
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: Some(Seed {
+            pending: bitflyer_pending,
+            url: "https://api.bitflyer.com/v1/board?product_code={}",
+            apply: bitflyer_seed,
+        }),
+        max_pairs: None,
+    },
+    "coincheck" => Api {
+        endpoint: "wss://ws-api.coincheck.com/",
+        // ex: btc_jpy
+        subscribe_template: &[r#"{{"type":"subscribe","channel":"{}-orderbook"}}"#],
+        parse: (coincheck_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: coincheck_clear,
+        normalize_pair: None,
+        depth_streams: None,
+        // no unsubscribe
+        live_pairs: None,
+        compression: None,
+        pong: None,
+        login: None,
+        bootstrap: None,
+        seed: Some(Seed {
+            pending: coincheck_pending,
+            url: "https://coincheck.com/api/order_books?pair={}",
+            apply: coincheck_seed,
+        }),
+        max_pairs: None,
+    },
+    "lbank" => Api {
+        endpoint: "wss://www.lbkex.net/ws/V2/",
+        // ex: btc_usdt
+        subscribe_template: &[r#"{{"action":"subscribe","subscribe":"depth","depth":"50","pair":"{}"}}"#],
+        parse: (lbank_parser as ParseFunc),
+        trade_subscribe_template: &[],
+        parse_trades: None,
+        render_url: false,
+        combined_stream: false,
+        has_ticker: false,
+        heartbeat: None,
+        reconnect_sec: None,
+        clear: || {},
+        normalize_pair: None,
+        depth_streams: None,
+        live_pairs: Some(LivePairs {
+            subscribe: &[r#"{{"action":"subscribe","subscribe":"depth","depth":"50","pair":"{}"}}"#],
+            unsubscribe: &[r#"{{"action":"unsubscribe","subscribe":"depth","depth":"50","pair":"{}"}}"#],
+            forget: |_| {},
+        }),
+        compression: None,
+        // the server pings, and disconnects when its ping goes unanswered
+        pong: Some(lbank_pong),
+        login: None,
+        bootstrap: None,
+        seed: None,
+        max_pairs: None,
     }
 };
 
 #[cfg(test)]
 mod tests {
+    use super::ParseOutcome;
     use bigdecimal::BigDecimal;
     use std::str::FromStr;
+    use std::sync::Arc;
+
+    impl ParseOutcome {
+        // the book, like Option::unwrap
+        fn unwrap(self) -> Arc<super::Orderbook> {
+            match self {
+                ParseOutcome::Book(ob) => ob,
+                outcome => panic!("not a book: {:?}", outcome),
+            }
+        }
+    }
+
     #[test]
     fn test_subscribe_text() {
         let rendered = super::WS_APIMAP
@@ -518,75 +4283,1538 @@ mod tests {
             .unwrap()
             .subscribe_text("BTCUSDT", 20)
             .unwrap();
+        assert_eq!(rendered, vec!["BTCUSDT@depth20@100ms"]);
+        let rendered = super::WS_APIMAP
+            .get("kraken")
+            .unwrap()
+            .subscribe_text("XBT/AUD", 20)
+            .unwrap();
+        assert_eq!(
+            rendered[0],
+            r#"{"event":"subscribe","pair":["XBT/AUD"], "subscription": {"name":"book","depth":25}}"#
+        );
+    }
+    #[test]
+    fn test_combined_stream() {
+        let api = super::WS_APIMAP.get("binance").unwrap();
+        let frames = [
+            r#"{"stream":"btcusdt@depth20@100ms","data":{"lastUpdateId":160,"bids":[["37000.1","0.5"]],"asks":[["37000.2","1"]]}}"#,
+            r#"{"stream":"ethusdt@depth20@100ms","data":{"lastUpdateId":161,"bids":[["2000.1","3"]],"asks":[]}}"#,
+        ];
+        let mut books = vec![];
+        for raw in frames {
+            let (stream, payload) = super::unwrap_stream(raw);
+            let pair = stream.as_deref().map(super::stream_pair);
+            books.push(api.parse_book(pair, payload).unwrap());
+        }
+        assert_eq!(books[0].name, "binance");
+        assert_eq!(books[0].pair.as_deref(), Some("btcusdt"));
+        assert_eq!(books[0].ask.len(), 1);
+        assert_eq!(
+            books[0].mid(),
+            Some(BigDecimal::from_str("37000.15").unwrap())
+        );
+        assert_eq!(books[1].pair.as_deref(), Some("ethusdt"));
+        assert_eq!(books[1].bid.len(), 1);
+        assert!(books[1].ask.is_empty());
+
+        // a bare message is passed through as is
+        let (pair, payload) = super::unwrap_stream(r#"{"id": 1, "result": null}"#);
+        assert_eq!(pair, None);
+        assert_eq!(api.parse_book(pair, payload), ParseOutcome::Ignore);
+    }
+    #[test]
+    fn test_binance_parse() {
+        // subscription response, return empty Orderbook
+        let out = (super::WS_APIMAP.get("binance").unwrap().parse)(r#"{"id": 1, "result": null}"#)
+            .unwrap();
+        assert_eq!(out, ParseOutcome::Ignore);
+
+        // normal event
+        let out = (super::WS_APIMAP.get("binance").unwrap().parse)(
+            r#"{"lastUpdateId": 160, "bids":[["0.01", "0.2"]], "asks": []}"#,
+        )
+        .unwrap();
+        let mut ob = super::Orderbook::new("binance");
+        ob.insert(
+            super::Side::Bid,
+            BigDecimal::from_str("0.01").unwrap(),
+            BigDecimal::from_str("0.2").unwrap(),
+        );
+        if let ParseOutcome::Book(o) = &out {
+            ob.timestamp = o.timestamp;
+        }
+        assert_eq!(out, ParseOutcome::Book(Arc::new(ob)));
+    }
+    #[test]
+    fn test_binance_variants() {
+        let raw = r#"{"stream":"btcusdt@depth20@100ms","data":{"lastUpdateId":160,"bids":[["37000.1","0.5"]],"asks":[["37000.2","1"]]}}"#;
+        let (stream, payload) = super::unwrap_stream(raw);
+        let pair = stream.as_deref().map(super::stream_pair);
+        let books: Vec<Arc<super::Orderbook>> = ["binance", "binanceus"]
+            .iter()
+            .map(|exchange| {
+                let api = super::WS_APIMAP.get(exchange).unwrap();
+                api.parse_book(pair.clone(), payload).unwrap()
+            })
+            .collect();
+        assert_eq!(books[0].name, "binance");
+        assert_eq!(books[1].name, "binanceus");
+        assert_eq!(books[1].pair.as_deref(), Some("btcusdt"));
+        // the same levels, told apart by name only
+        assert!(!books[0].same_book(&books[1]));
+        assert_eq!(
+            (&books[0].bid, &books[0].ask),
+            (&books[1].bid, &books[1].ask)
+        );
+        let api = super::WS_APIMAP.get("binanceus").unwrap();
+        assert_eq!(
+            api.parse_book(
+                None,
+                r#"{"code": 2, "msg": "Invalid request: unknown variable"}"#
+            ),
+            ParseOutcome::ProtocolError(
+                "binanceus: 2 Invalid request: unknown variable".to_string()
+            )
+        );
+    }
+    #[test]
+    fn test_binance_futures_parse() {
+        let parse = super::WS_APIMAP.get("binance_futures").unwrap().parse;
+        assert_eq!(
+            parse(r#"{"result":null,"id":2}"#).unwrap(),
+            ParseOutcome::Ignore
+        );
+        let out = parse(
+            r#"{"e":"depthUpdate","E":1696405428703,"T":1696405428701,"s":"ETHUSDT","U":3416401,"u":3416450,"pu":3416400,
+                "b":[["1650.10","12.5"],["1650.00","3"]],"a":[["1650.30","7.25"]]}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(out.name, "binance_futures");
+        assert_eq!(out.bid.len(), 2);
+        assert_eq!(out.derivatives, Some(super::Derivatives::default()));
+        let out = parse(
+            r#"{"e":"markPriceUpdate","E":1696405429000,"s":"ETHUSDT","p":"1650.21000000",
+                "i":"1649.87510000","P":"1650.01000000","r":"0.00010000","T":1696406400000}"#,
+        )
+        .unwrap()
+        .unwrap();
+        // the book is kept
+        assert_eq!(out.ask.len(), 1);
+        assert_eq!(
+            out.derivatives,
+            Some(super::Derivatives {
+                mark_price: BigDecimal::from_str("1650.21").unwrap(),
+                index_price: BigDecimal::from_str("1649.8751").unwrap(),
+                funding_rate: BigDecimal::from_str("0.0001").unwrap(),
+                next_funding_time: 1696406400000,
+            })
+        );
+        assert_eq!(out.mid(), Some(BigDecimal::from_str("1650.2").unwrap()));
+    }
+    #[test]
+    fn test_bitstamp_parse() {
+        // subscription response
+        let out = (super::WS_APIMAP.get("bitstamp").unwrap().parse)(
+            r#"{"event": "bts:subscription_succeeded", "channel": "order_book_btcusd", "data": {}}"#,
+        )
+        .unwrap();
+        assert_eq!(out, ParseOutcome::Ignore);
+
+        // normal event
+        let out = (super::WS_APIMAP.get("bitstamp").unwrap().parse)(
+            r#"{"data":{
+                "timestamp":"1691595437",
+                "microtimestamp":"1691595437334962",
+                "bids":[],
+                "asks":[["29737","0.67548438"],["29738","0.67255217"]]
+            },"channel":"order_book_btcusd","event":"data"}"#,
+        )
+        .unwrap();
+        let mut ob = super::Orderbook::new("bitstamp");
+        ob.insert(
+            super::Side::Ask,
+            BigDecimal::from_str("29737").unwrap(),
+            BigDecimal::from_str("0.67548438").unwrap(),
+        );
+        ob.insert(
+            super::Side::Ask,
+            BigDecimal::from_str("29738").unwrap(),
+            BigDecimal::from_str("0.67255217").unwrap(),
+        );
+        if let ParseOutcome::Book(b) = &out {
+            ob.timestamp = b.timestamp;
+        }
+        assert_eq!(out, ParseOutcome::Book(Arc::new(ob)));
+    }
+    #[test]
+    fn test_bitstamp_full() {
+        let api = super::WS_APIMAP.get("bitstamp_full").unwrap();
+        let diff = |micros: u64, bids: &str, asks: &str| {
+            format!(
+                r#"{{"data":{{"timestamp":"1691595437","microtimestamp":"{}","bids":[{}],"asks":[{}]}},"channel":"diff_order_book_fullusd","event":"data"}}"#,
+                micros, bids, asks
+            )
+        };
+        let levels = |ob: &super::Orderbook| {
+            let side = |levels: &std::collections::BTreeMap<BigDecimal, BigDecimal>| {
+                levels
+                    .iter()
+                    .map(|(p, v)| format!("{}:{}", p, v))
+                    .collect::<Vec<_>>()
+            };
+            (side(&ob.bid), side(&ob.ask))
+        };
+        let pending = || (api.seed.as_ref().unwrap().pending)().contains(&"fullusd".to_string());
+        let seed = |body: &str| (api.seed.as_ref().unwrap().apply)("fullusd", body).unwrap();
+
+        // diffs before the snapshot are buffered
+        let out = (api.parse)(&diff(100, r#"["100","1"]"#, "")).unwrap();
+        assert_eq!(out, ParseOutcome::Ignore);
+        assert!(pending());
+        let out = (api.parse)(&diff(300, r#"["99","2"]"#, r#"["101","0"]"#)).unwrap();
+        assert_eq!(out, ParseOutcome::Ignore);
+        // the snapshot already has the diff of 100, the one of 300 goes on top
+        seed(
+            r#"{"timestamp":"1691595437","microtimestamp":"200","bids":[["100","1"]],"asks":[["101","3"],["102","4"]]}"#,
+        );
+        assert!(!pending());
+
+        // in order
+        let ob = (api.parse)(&diff(400, r#"["100","0"]"#, r#"["102","5"]"#))
+            .unwrap()
+            .unwrap();
+        assert_eq!(ob.name, "bitstamp_full");
+        assert_eq!(
+            levels(&ob),
+            (vec!["99:2".to_string()], vec!["102:5".to_string()])
+        );
+
+        // a diff older than the last one: the book is dropped and refetched
+        let out = (api.parse)(&diff(350, r#"["98","1"]"#, "")).unwrap();
+        assert!(matches!(out, ParseOutcome::ProtocolError(e) if e.contains("out of order")));
+        assert!(pending());
+        seed(
+            r#"{"timestamp":"1691595437","microtimestamp":"500","bids":[["99","2"]],"asks":[["102","5"]]}"#,
+        );
+        assert!(
+            (api.parse)(&diff(600, r#"["99","3"]"#, ""))
+                .unwrap()
+                .unwrap()
+                .bid
+                .len()
+                == 1
+        );
+
+        // removing a level the book doesn't have means a diff went missing
+        let out = (api.parse)(&diff(700, "", r#"["105","0"]"#)).unwrap();
+        assert!(matches!(out, ParseOutcome::ProtocolError(e) if e.contains("missed diff")));
+        assert!(pending());
+
+        // the venue asks for a reconnect before maintenance
+        let out =
+            (api.parse)(r#"{"event":"bts:request_reconnect","channel":"","data":""}"#).unwrap();
+        assert!(matches!(out, ParseOutcome::FatalDesync(_)));
+        (api.clear)();
+        assert!(!pending());
+    }
+    #[test]
+    fn test_kraken_parse() {
+        // this is the special case that array has dynamic length
+        (super::WS_APIMAP.get("kraken").unwrap().parse)(
+            r#"[384,{"a":[["43468.00000","0.12661008","1696405428.703749"]]},{"b":[["43468.00000","0.00000000","1696405428.703785"],["43196.60000","0.00115748","1696396431.709973","r"]],"c":"2556304438"},"book-25","XBT/AUD"]"#,
+        ).unwrap();
+    }
+    #[test]
+    fn test_okx_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("okx").unwrap();
+        assert_eq!(api.heartbeat, Some((20, super::Heartbeat::Text("ping"))));
+        assert_eq!(
+            api.subscribe_text("BTC-USDT", 20).unwrap(),
+            [r#"{"op":"subscribe","args":[{"channel":"books5","instId":"BTC-USDT"}]}"#]
+        );
+        for raw in [
+            "pong",
+            r#"{"event":"subscribe","arg":{"channel":"books5","instId":"BTC-USDT"},"connId":"a4d3ae55"}"#,
+        ] {
+            assert_eq!(api.parse_book(None, raw), ParseOutcome::Ignore, "{}", raw);
+        }
+        assert_eq!(
+            api.parse_book(
+                None,
+                r#"{"event":"error","code":"60018","msg":"Wrong URL or channel:books5,instId:FOO-BAR doesn't exist","connId":"a4d3ae55"}"#
+            ),
+            ParseOutcome::ProtocolError(
+                "okx: 60018 Wrong URL or channel:books5,instId:FOO-BAR doesn't exist".to_string()
+            )
+        );
+
+        // books5, the top 5 whole every time
+        let top = |asks: &str| {
+            format!(
+                r#"{{"arg":{{"channel":"books5","instId":"BTC-USDT"}},"data":[{{"asks":{},"bids":[["26990.1","0.3","0","1"],["26989","1","0","4"]],"instId":"BTC-USDT","ts":"1696405428703","seqId":17218790452}}]}}"#,
+                asks
+            )
+        };
+        let ob = api
+            .parse_book(
+                None,
+                &top(r#"[["26990.2","0.1","0","3"],["26991","0.25","0","2"]]"#),
+            )
+            .unwrap();
+        assert_eq!(ob.name, "okx");
+        assert_eq!(ob.pair.as_deref(), Some("BTC-USDT"));
+        assert_eq!(ob.best_bid(), Some((&dec("26990.1"), &dec("0.3"))));
+        assert_eq!(ob.ask.len(), 2);
+        let ob = api
+            .parse_book(None, &top(r#"[["26990.5","2","0","1"]]"#))
+            .unwrap();
+        assert_eq!(ob.ask.len(), 1);
+        assert_eq!(ob.best_ask(), Some((&dec("26990.5"), &dec("2"))));
+
+        // books, a snapshot then the updates onto it
+        let books = |action: &str, data: &str| {
+            format!(
+                r#"{{"arg":{{"channel":"books","instId":"ETH-USDT"}},"action":"{}","data":[{}]}}"#,
+                action, data
+            )
+        };
+        let snapshot = books(
+            "snapshot",
+            r#"{"asks":[["1650.3","2","0","1"],["1650.4","5","0","2"]],"bids":[["1650.1","3","0","2"],["1650","1.5","0","1"]],"ts":"1696405428703","checksum":-855196043,"prevSeqId":-1,"seqId":100}"#,
+        );
+        let ob = api.parse_book(None, &snapshot).unwrap();
+        // each instId's book tells its pair
+        assert_eq!(ob.pair.as_deref(), Some("ETH-USDT"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        // a size of 0 removes the level
+        let ob = api
+            .parse_book(
+                None,
+                &books(
+                    "update",
+                    r#"{"asks":[["1650.3","0","0","0"]],"bids":[["1650.2","0.5","0","1"]],"ts":"1696405428803","checksum":1312538214,"prevSeqId":100,"seqId":101}"#,
+                ),
+            )
+            .unwrap();
+        assert_eq!(ob.best_ask(), Some((&dec("1650.4"), &dec("5"))));
+        assert_eq!(ob.best_bid(), Some((&dec("1650.2"), &dec("0.5"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (3, 1));
+        // an update went missing
+        assert!(matches!(
+            api.parse_book(
+                None,
+                &books(
+                    "update",
+                    r#"{"asks":[],"bids":[["1650.2","0","0","0"]],"ts":"1696405429003","checksum":0,"prevSeqId":103,"seqId":104}"#,
+                ),
+            ),
+            ParseOutcome::FatalDesync(_)
+        ));
+        // a new snapshot starts over
+        let ob = api.parse_book(None, &snapshot).unwrap();
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        // the next update in sequence onto a book dropped as stale
+        super::OKX.lock().unwrap().remove("ETH-USDT");
+        assert_eq!(
+            api.parse_book(
+                None,
+                &books(
+                    "update",
+                    r#"{"asks":[],"bids":[["1650.2","0.5","0","1"]],"ts":"1696405428803","checksum":0,"prevSeqId":100,"seqId":101}"#,
+                ),
+            ),
+            ParseOutcome::FatalDesync("okx: update on ETH-USDT whose book was evicted".to_string())
+        );
+    }
+    #[test]
+    fn test_bybit_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("bybit").unwrap();
+        assert_eq!(
+            api.heartbeat,
+            Some((20, super::Heartbeat::Text(r#"{"op":"ping"}"#)))
+        );
+        let replies = [
+            (
+                r#"{"success":true,"ret_msg":"pong","conn_id":"0970e817-426e-429a-a679-ff7f55e0b16a","op":"ping"}"#,
+                ParseOutcome::Ignore,
+            ),
+            (
+                r#"{"success":true,"ret_msg":"subscribe","conn_id":"2324d924-aa4d-45b0-a858-7b8be29ab52b","req_id":"","op":"subscribe"}"#,
+                ParseOutcome::Ignore,
+            ),
+            (
+                r#"{"success":false,"ret_msg":"Invalid symbol :[orderbook.50.FOOBAR]","conn_id":"2324d924","op":"subscribe"}"#,
+                ParseOutcome::ProtocolError(
+                    "bybit: subscribe Invalid symbol :[orderbook.50.FOOBAR]".to_string(),
+                ),
+            ),
+        ];
+        for (raw, expected) in replies {
+            assert_eq!(api.parse_book(None, raw), expected, "{}", raw);
+        }
+        let frame = |kind: &str, data: &str| {
+            format!(
+                r#"{{"topic":"orderbook.50.BTCUSDT","ts":1696405428703,"type":"{}","data":{{"s":"BTCUSDT",{}}},"cts":1696405428701}}"#,
+                kind, data
+            )
+        };
+        let snapshot = frame(
+            "snapshot",
+            r#""b":[["27990.50","0.006"],["27990.00","1.2"]],"a":[["27991.00","0.029"],["27992.50","0.5"]],"u":18521288,"seq":7961638724"#,
+        );
+        let ob = api.parse_book(None, &snapshot).unwrap();
+        assert_eq!(ob.name, "bybit");
+        assert_eq!(ob.pair.as_deref(), Some("BTCUSDT"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        // a size of 0 removes the level
+        let ob = api
+            .parse_book(
+                None,
+                &frame(
+                    "delta",
+                    r#""b":[["27990.50","0"],["27989.00","3"]],"a":[["27991.00","0.1"]],"u":18521289,"seq":7961638730"#,
+                ),
+            )
+            .unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("27990.00"), &dec("1.2"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27991.00"), &dec("0.1"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        // u 18521290 went missing
+        assert!(matches!(
+            api.parse_book(
+                None,
+                &frame(
+                    "delta",
+                    r#""b":[],"a":[["27992.50","0"]],"u":18521291,"seq":7961638790"#
+                ),
+            ),
+            ParseOutcome::FatalDesync(_)
+        ));
+        // a seq going back is out of order too
+        api.parse_book(None, &snapshot).unwrap();
+        assert!(matches!(
+            api.parse_book(
+                None,
+                &frame(
+                    "delta",
+                    r#""b":[],"a":[["27992.50","0"]],"u":18521289,"seq":7961638700"#
+                ),
+            ),
+            ParseOutcome::FatalDesync(_)
+        ));
+        // a new snapshot rebuilds the book
+        let ob = api
+            .parse_book(
+                None,
+                &frame(
+                    "snapshot",
+                    r#""b":[["28000","1"]],"a":[["28001","2"]],"u":1,"seq":7961639000"#,
+                ),
+            )
+            .unwrap();
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 1));
+        // the next delta in sequence onto a book dropped as stale
+        super::BYBIT.lock().unwrap().remove("BTCUSDT");
+        assert_eq!(
+            api.parse_book(
+                None,
+                &frame(
+                    "delta",
+                    r#""b":[],"a":[["28001","0"]],"u":2,"seq":7961639010"#
+                ),
+            ),
+            ParseOutcome::FatalDesync("bybit: delta on BTCUSDT whose book was evicted".to_string())
+        );
+    }
+    #[test]
+    fn test_coinbase_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("coinbase").unwrap();
+        assert_eq!(
+            api.subscribe_text("BTC-USD", 20).unwrap(),
+            [r#"{"type":"subscribe","product_ids":["BTC-USD"],"channels":["level2_batch"]}"#]
+        );
+        let noise = [
+            r#"{"type":"subscriptions","channels":[{"name":"level2_batch","product_ids":["BTC-USD","ETH-USD"]}]}"#,
+            r#"{"type":"heartbeat","last_trade_id":20,"product_id":"BTC-USD","sequence":10,"time":"2014-11-07T08:19:28.464459Z"}"#,
+        ];
+        for raw in noise {
+            assert_eq!(api.parse_book(None, raw), ParseOutcome::Ignore, "{}", raw);
+        }
+        assert_eq!(
+            api.parse_book(
+                None,
+                r#"{"type":"error","message":"Failed to subscribe","reason":"FOO-BAR is not a valid product"}"#
+            ),
+            ParseOutcome::ProtocolError(
+                "coinbase: Failed to subscribe FOO-BAR is not a valid product".to_string()
+            )
+        );
+        assert!(matches!(
+            api.parse_book(
+                None,
+                r#"{"type":"l2update","product_id":"SOL-USD","time":"2023-10-04T07:43:48.703Z","changes":[["buy","21.5","3"]]}"#
+            ),
+            ParseOutcome::FatalDesync(_)
+        ));
+
+        // two products on one connection, a book each
+        let btc = r#"{"type":"snapshot","product_id":"BTC-USD","bids":[["27990.10","0.45054140"],["27990.00","1"]],"asks":[["27991.55","0.57753524"]]}"#;
+        let eth = r#"{"type":"snapshot","product_id":"ETH-USD","bids":[["1650.10","3"]],"asks":[["1650.30","2"],["1650.40","5"]]}"#;
+        let ob = api.parse_book(None, btc).unwrap();
+        assert_eq!((ob.pair.as_deref(), ob.bid.len()), (Some("BTC-USD"), 2));
+        let ob = api.parse_book(None, eth).unwrap();
+        assert_eq!((ob.pair.as_deref(), ob.ask.len()), (Some("ETH-USD"), 2));
+        let ob = api
+            .parse_book(
+                None,
+                r#"{"type":"l2update","product_id":"BTC-USD","time":"2023-10-04T07:43:48.703Z","changes":[["buy","27990.10","0"],["sell","27991.00","0.2"]]}"#,
+            )
+            .unwrap();
+        assert_eq!(ob.name, "coinbase");
+        assert_eq!(ob.pair.as_deref(), Some("BTC-USD"));
+        assert_eq!(ob.best_bid(), Some((&dec("27990.00"), &dec("1"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27991.00"), &dec("0.2"))));
+        let ob = api
+            .parse_book(
+                None,
+                r#"{"type":"l2update","product_id":"ETH-USD","time":"2023-10-04T07:43:48.803Z","changes":[["sell","1650.30","0"]]}"#,
+            )
+            .unwrap();
+        assert_eq!(ob.pair.as_deref(), Some("ETH-USD"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 1));
+        assert_eq!(ob.best_ask(), Some((&dec("1650.40"), &dec("5"))));
+        // a new snapshot replaces the book
+        let ob = api.parse_book(None, btc).unwrap();
+        assert_eq!(ob.best_ask(), Some((&dec("27991.55"), &dec("0.57753524"))));
+    }
+    #[test]
+    fn test_huobi_parse() {
+        use flate2::{write::GzEncoder, Compression as Level};
+        use std::io::Write;
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("huobi").unwrap();
+        assert_eq!(api.compression, Some(super::Compression::Gzip));
+        // the frames as they come, gzipped
+        let frames: Vec<Vec<u8>> = include_str!("../test_resource/huobi_mbp.jsonl")
+            .lines()
+            .map(|line| {
+                let mut encoder = GzEncoder::new(vec![], Level::default());
+                encoder.write_all(line.as_bytes()).unwrap();
+                encoder.finish().unwrap()
+            })
+            .collect();
+        let raw: Vec<String> = frames
+            .iter()
+            .map(|frame| super::Compression::Gzip.inflate(frame).unwrap())
+            .collect();
+        assert!(super::Compression::Gzip.inflate(b"{}").is_err());
+
+        let pong = api.pong.unwrap();
+        assert_eq!(pong(&raw[1]), Some(r#"{"pong":1696405428701}"#.to_string()));
+        assert_eq!(pong(&raw[2]), None);
+        assert_eq!(api.parse_book(None, &raw[0]), ParseOutcome::Ignore);
+        assert_eq!(api.parse_book(None, &raw[1]), ParseOutcome::Ignore);
+        let ob = api.parse_book(None, &raw[2]).unwrap();
+        assert_eq!(ob.name, "huobi");
+        assert_eq!(ob.pair.as_deref(), Some("btcusdt"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (3, 2));
+        assert_eq!(ob.best_bid(), Some((&dec("27990.1"), &dec("0.5941"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27990.11"), &dec("0.307"))));
+        // every push is the whole top 20
+        let ob = api.parse_book(None, &raw[3]).unwrap();
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 2));
+        assert_eq!(ob.best_bid(), Some((&dec("27990"), &dec("1.1"))));
+        assert_eq!(
+            api.parse_book(
+                None,
+                r#"{"status":"error","ts":1696405428703,"id":"arb_monitor","err-code":"bad-request","err-msg":"invalid topic market.foobar.mbp.refresh.20"}"#
+            ),
+            ParseOutcome::ProtocolError(
+                "huobi: bad-request invalid topic market.foobar.mbp.refresh.20".to_string()
+            )
+        );
+    }
+    #[test]
+    fn test_kucoin_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("kucoin").unwrap();
+        let bootstrap = api.bootstrap.unwrap();
+        let resolved = bootstrap(
+            r#"{"code":"200000","data":{"token":"2neAiuYvAU61ZD","instanceServers":[{"endpoint":"wss://ws-api-spot.kucoin.com/","encrypt":true,"protocol":"websocket","pingInterval":18000,"pingTimeout":10000}]}}"#,
+        )
+        .unwrap();
+        assert!(resolved
+            .url
+            .starts_with("wss://ws-api-spot.kucoin.com/?token=2neAiuYvAU61ZD&connectId="));
+        assert_eq!(resolved.heartbeat, Some(std::time::Duration::from_secs(18)));
+        assert!(bootstrap(r#"{"code":"429000","msg":"Too Many Requests"}"#).is_err());
+
+        for raw in [
+            r#"{"id":"hQvf8jkno","type":"welcome"}"#,
+            r#"{"id":"arb_monitor","type":"ack"}"#,
+            r#"{"id":"arb_monitor","type":"pong"}"#,
+        ] {
+            assert_eq!(api.parse_book(None, raw), ParseOutcome::Ignore);
+        }
+        let ob = api
+            .parse_book(
+                None,
+                r#"{"type":"message","topic":"/spotMarket/level2Depth50:BTC-USDT","subject":"level2","data":{"asks":[["27990.2","0.31"],["27990.3","1.2"]],"bids":[["27990.1","0.59"],["27989.9","2"],["27989","0.1"]],"timestamp":1696405428703}}"#,
+            )
+            .unwrap();
+        assert_eq!(ob.name, "kucoin");
+        assert_eq!(ob.pair.as_deref(), Some("BTC-USDT"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (3, 2));
+        assert_eq!(ob.best_bid(), Some((&dec("27990.1"), &dec("0.59"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27990.2"), &dec("0.31"))));
         assert_eq!(
-            rendered,
-            vec![r#"{"id": 1, "method": "SUBSCRIBE", "params": ["BTCUSDT@depth20@100ms"]}"#]
+            api.parse_book(
+                None,
+                r#"{"id":"arb_monitor","type":"error","code":404,"data":"topic /spotMarket/level2Depth50:FOO-BAR is not found"}"#
+            ),
+            ParseOutcome::ProtocolError(
+                r#"kucoin: 404 "topic /spotMarket/level2Depth50:FOO-BAR is not found""#.to_string()
+            )
         );
     }
     #[test]
-    fn test_binance_parse() {
-        // subscription response, return empty Orderbook
-        let out = (super::WS_APIMAP.get("binance").unwrap().parse)(r#"{"id": 1, "result": null}"#)
-            .unwrap();
-        assert_eq!(out, None);
+    fn test_gateio_parse() {
+        let api = super::WS_APIMAP.get("gateio").unwrap();
+        let diff = |first: u64, last: u64, bids: &str, asks: &str| {
+            format!(
+                r#"{{"time":1696405428,"time_ms":1696405428703,"channel":"spot.order_book_update","event":"update","result":{{"t":1696405428703,"e":"depthUpdate","E":1696405428,"s":"GT_USDT","U":{},"u":{},"b":[{}],"a":[{}]}}}}"#,
+                first, last, bids, asks
+            )
+        };
+        let parse = |raw: &str| api.parse_book(None, raw);
+        let pending = || (api.seed.as_ref().unwrap().pending)().contains(&"GT_USDT".to_string());
+        let seed = |body: &str| (api.seed.as_ref().unwrap().apply)("GT_USDT", body);
+        let snapshot = |id: u64| {
+            format!(
+                r#"{{"id":{},"current":1696405428703,"update":1696405428700,"asks":[["4.01","10"],["4.02","20"]],"bids":[["4.00","5"],["3.99","6"]]}}"#,
+                id
+            )
+        };
+        let ack = r#"{"time":1696405428,"channel":"spot.order_book_update","event":"subscribe","result":{"status":"success"}}"#;
+        assert_eq!(parse(ack), ParseOutcome::Ignore);
 
-        // normal event
-        let out = (super::WS_APIMAP.get("binance").unwrap().parse)(
-            r#"{"lastUpdateId": 160, "bids":[["0.01", "0.2"]], "asks": []}"#,
+        // buffered until the snapshot
+        assert_eq!(
+            parse(&diff(95, 99, r#"["3.98","1"]"#, "")),
+            ParseOutcome::Ignore
+        );
+        assert_eq!(
+            parse(&diff(100, 103, r#"["4.00","0"]"#, r#"["4.01","11"]"#)),
+            ParseOutcome::Ignore
+        );
+        assert_eq!(
+            parse(&diff(104, 105, "", r#"["4.02","0"]"#)),
+            ParseOutcome::Ignore
+        );
+        assert!(pending());
+        // behind the diffs: kept pending, fetched again
+        assert!(seed(&snapshot(90)).is_err());
+        assert!(pending());
+        // the diff of 95..99 is in it, the one straddling 101 goes on top
+        seed(&snapshot(100)).unwrap();
+        assert!(!pending());
+        let ob = parse(&diff(106, 106, r#"["4.00","7"]"#, "")).unwrap();
+        assert_eq!(ob.name, "gateio");
+        assert_eq!(ob.pair.as_deref(), Some("GT_USDT"));
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("4.00"), &dec("7"))));
+        assert_eq!(ob.best_ask(), Some((&dec("4.01"), &dec("11"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 1));
+        assert!(!ob.bid.contains_key(&dec("3.98")));
+        // an old diff, then a gap: reconnect
+        assert_eq!(parse(&diff(104, 105, "", "")), ParseOutcome::Ignore);
+        assert!(matches!(
+            parse(&diff(108, 110, "", "")),
+            ParseOutcome::FatalDesync(e) if e.contains("jumped from 106 to 108")
+        ));
+        // the next diff onto a book dropped as stale: buffered, the
+        // snapshot fetched again
+        super::GATEIO.books.lock().unwrap().remove("GT_USDT");
+        assert_eq!(
+            parse(&diff(107, 107, r#"["4.00","8"]"#, "")),
+            ParseOutcome::ProtocolError("gateio: book evicted on GT_USDT, resync".to_string())
+        );
+        assert!(pending());
+        seed(&snapshot(106)).unwrap();
+        let ob = parse(&diff(108, 108, "", "")).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("4.00"), &dec("8"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+
+        // a gap among the buffered diffs
+        (api.clear)();
+        parse(&diff(101, 102, "", ""));
+        parse(&diff(104, 105, "", ""));
+        assert!(seed(&snapshot(100)).is_err());
+        (api.clear)();
+        assert!(!pending());
+        assert_eq!(
+            parse(
+                r#"{"time":1696405428,"channel":"spot.order_book_update","event":"subscribe","error":{"code":2,"message":"unknown currency pair FOO_BAR"},"result":null}"#
+            ),
+            ParseOutcome::ProtocolError(
+                r#"gateio: {"code":2,"message":"unknown currency pair FOO_BAR"}"#.to_string()
+            )
+        );
+    }
+    #[test]
+    fn test_bitfinex_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("bitfinex").unwrap();
+        (api.clear)();
+        let parse = |raw: &str| api.parse_book(None, raw);
+        assert_eq!(
+            parse(r#"{"event":"info","version":2,"serverId":"0c7a9f4c","platform":{"status":1}}"#),
+            ParseOutcome::Ignore
+        );
+        // changes before the subscribed event can't be told apart
+        assert!(matches!(
+            parse(r#"[17082,[27990,1,0.5]]"#),
+            ParseOutcome::FatalDesync(e) if e.contains("17082")
+        ));
+        assert_eq!(
+            parse(
+                r#"{"event":"subscribed","channel":"book","chanId":17082,"symbol":"tBTCUSD","prec":"P0","freq":"F0","len":"25","pair":"BTCUSD"}"#
+            ),
+            ParseOutcome::Ignore
+        );
+        let ob =
+            parse(r#"[17082,[[27990,2,0.5],[27989,1,1.25],[27991,1,-0.3],[27992,3,-2]]]"#).unwrap();
+        assert_eq!(ob.name, "bitfinex");
+        assert_eq!(ob.pair.as_deref(), Some("tBTCUSD"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        assert_eq!(ob.best_bid(), Some((&dec("27990"), &dec("0.5"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27991"), &dec("0.3"))));
+        // an update, then deletes: count 0, the amount 1 for a bid, -1 an ask
+        let ob = parse(r#"[17082,[27990,3,0.75]]"#).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("27990"), &dec("0.75"))));
+        parse(r#"[17082,[27990,0,1]]"#);
+        let ob = parse(r#"[17082,[27991,0,-1]]"#).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("27989"), &dec("1.25"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27992"), &dec("2"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 1));
+        assert_eq!(parse(r#"[17082,"hb"]"#), ParseOutcome::Ignore);
+        // a new snapshot replaces the book
+        let ob = parse(r#"[17082,[[27980,1,1]]]"#).unwrap();
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 0));
+
+        assert_eq!(
+            parse(r#"{"event":"error","msg":"symbol: invalid","code":10300,"symbol":"tFOOBAR"}"#),
+            ParseOutcome::ProtocolError("bitfinex: 10300 symbol: invalid".to_string())
+        );
+        assert!(matches!(
+            parse(r#"{"event":"info","code":20051,"msg":"Stopping. Please try to reconnect"}"#),
+            ParseOutcome::FatalDesync(_)
+        ));
+        // the channel ids don't outlive the connection
+        (api.clear)();
+        assert!(matches!(
+            parse(r#"[17082,[27990,1,0.5]]"#),
+            ParseOutcome::FatalDesync(_)
+        ));
+    }
+    #[test]
+    fn test_gemini_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("gemini").unwrap();
+        (api.clear)();
+        let parse = |raw: &str| api.parse_book(None, raw);
+        let trade = r#"{"type":"trade","symbol":"BTCUSD","event_id":3575573053,"timestamp":1696405428703,"price":"27990.5","quantity":"0.0073173","side":"sell","tid":3575573053}"#;
+        assert_eq!(parse(trade), ParseOutcome::Ignore);
+        // the whole book first
+        let ob = parse(
+            r#"{"type":"l2_updates","symbol":"BTCUSD","changes":[["buy","27990.01","0.5"],["buy","27989","1.2"],["sell","27991.5","0.25"],["sell","27992","3"]],"trades":[],"auction_events":[]}"#,
         )
         .unwrap();
-        let mut ob = super::Orderbook::new("binance");
-        ob.insert(
-            super::Side::Bid,
-            BigDecimal::from_str("0.01").unwrap(),
-            BigDecimal::from_str("0.2").unwrap(),
+        assert_eq!(ob.name, "gemini");
+        assert_eq!(ob.pair.as_deref(), Some("BTCUSD"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        assert_eq!(ob.best_bid(), Some((&dec("27990.01"), &dec("0.5"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27991.5"), &dec("0.25"))));
+        // then changes, "0" removes a level
+        let ob = parse(
+            r#"{"type":"l2_updates","symbol":"BTCUSD","changes":[["buy","27990.01","0"],["sell","27991","0.1"]]}"#,
+        )
+        .unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("27989"), &dec("1.2"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27991"), &dec("0.1"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 3));
+        assert_eq!(ob.last_price, dec("0"));
+        let ob = parse(trade).unwrap();
+        assert_eq!(ob.last_price, dec("27990.5"));
+        assert_eq!(ob.ask.len(), 3);
+        // one socket, a book per symbol
+        let ob = parse(r#"{"type":"l2_updates","symbol":"ETHUSD","changes":[["buy","1650","2"]]}"#)
+            .unwrap();
+        assert_eq!(ob.pair.as_deref(), Some("ETHUSD"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 0));
+        assert_eq!(
+            parse(r#"{"type":"heartbeat","timestamp":1696405428703}"#),
+            ParseOutcome::Ignore
         );
-        if let Some(o) = out.as_ref() {
-            ob.timestamp = o.timestamp;
+        assert_eq!(
+            parse(
+                r#"{"result":"error","reason":"InvalidJson","message":"Failed to parse subscription"}"#
+            ),
+            ParseOutcome::ProtocolError(
+                "gemini: InvalidJson Failed to parse subscription".to_string()
+            )
+        );
+        (api.clear)();
+    }
+    #[test]
+    fn test_upbit_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("upbit").unwrap();
+        for pair in ["BTC/KRW", "btc/krw", "KRW-BTC"] {
+            assert_eq!(api.channel(pair).unwrap(), "KRW-BTC");
         }
-        assert_eq!(out, Some(ob));
+        assert!(api.channel("BTCKRW").is_err());
+        assert_eq!(
+            api.subscribe_text(&api.channel("BTC/KRW").unwrap(), 20)
+                .unwrap(),
+            vec![r#"[{"ticket":"arb_monitor"},{"type":"orderbook","codes":["KRW-BTC"]}]"#]
+        );
+
+        let raw = r#"{"type":"orderbook","code":"KRW-BTC","timestamp":1696405428703,"total_ask_size":1.6,"total_bid_size":2.85,"orderbook_units":[{"ask_price":37850000.0,"bid_price":37845000.0,"ask_size":0.4,"bid_size":0.85},{"ask_price":37855000.0,"bid_price":37840000.0,"ask_size":1.2,"bid_size":2.0}],"stream_type":"REALTIME"}"#;
+        let ob = api.parse_book(None, raw).unwrap();
+        assert_eq!(ob.name, "upbit");
+        assert_eq!(ob.pair.as_deref(), Some("KRW-BTC"));
+        // every unit is a level of each side
+        let levels = |side: &std::collections::BTreeMap<BigDecimal, BigDecimal>| {
+            side.iter()
+                .map(|(p, v)| (p.clone(), v.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            levels(&ob.bid),
+            vec![(dec("37840000"), dec("2")), (dec("37845000"), dec("0.85"))]
+        );
+        assert_eq!(
+            levels(&ob.ask),
+            vec![(dec("37850000"), dec("0.4")), (dec("37855000"), dec("1.2"))]
+        );
+        assert_eq!(
+            api.parse_book(None, r#"{"status":"UP"}"#),
+            ParseOutcome::Ignore
+        );
+        assert_eq!(
+            api.parse_book(
+                None,
+                r#"{"error":{"name":"INVALID_PARAM","message":"codes"}}"#
+            ),
+            ParseOutcome::ProtocolError(
+                r#"upbit: {"message":"codes","name":"INVALID_PARAM"}"#.to_string()
+            )
+        );
     }
     #[test]
-    fn test_bitstamp_parse() {
-        // subscription response
-        let out = (super::WS_APIMAP.get("bitstamp").unwrap().parse)(
-            r#"{"event": "bts:subscription_succeeded", "channel": "order_book_btcusd", "data": {}}"#,
+    fn test_bithumb_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("bithumb").unwrap();
+        (api.clear)();
+        let delta = |datetime: u64, levels: &str| {
+            format!(
+                r#"{{"type":"orderbookdepth","content":{{"list":[{}],"datetime":"{}"}}}}"#,
+                levels, datetime
+            )
+        };
+        let level = |symbol: &str, side: &str, price: &str, quantity: &str| {
+            format!(
+                r#"{{"symbol":"{}","orderType":"{}","price":"{}","quantity":"{}","total":"1"}}"#,
+                symbol, side, price, quantity
+            )
+        };
+        let parse = |raw: &str| api.parse_book(None, raw);
+        let pending = || (api.seed.as_ref().unwrap().pending)();
+        let seed = |symbol: &str, body: &str| (api.seed.as_ref().unwrap().apply)(symbol, body);
+        assert_eq!(
+            parse(r#"{"status":"0000","resmsg":"Filter Registered Successfully"}"#),
+            ParseOutcome::Ignore
+        );
+
+        // buffered until the snapshot
+        let raw = delta(1696405428000100, &level("BTC_KRW", "bid", "37840000", "5"));
+        assert_eq!(parse(&raw), ParseOutcome::Ignore);
+        let raw = delta(
+            1696405428900000,
+            &[
+                level("BTC_KRW", "ask", "37850000", "0"),
+                level("BTC_KRW", "ask", "37860000", "0.7"),
+            ]
+            .join(","),
+        );
+        assert_eq!(parse(&raw), ParseOutcome::Ignore);
+        assert_eq!(pending(), vec!["BTC_KRW".to_string()]);
+        // taken at 1696405428500 ms: the first delta is in it already
+        seed(
+            "BTC_KRW",
+            r#"{"status":"0000","data":{"timestamp":"1696405428500","order_currency":"BTC","payment_currency":"KRW","bids":[{"quantity":"0.85","price":"37845000"},{"quantity":"2","price":"37840000"}],"asks":[{"quantity":"0.4","price":"37850000"},{"quantity":"1.2","price":"37855000"}]}}"#,
         )
         .unwrap();
-        assert_eq!(out, None);
+        assert!(pending().is_empty());
 
-        // normal event
-        let out = (super::WS_APIMAP.get("bitstamp").unwrap().parse)(
-            r#"{"data":{
-                "timestamp":"1691595437",
-                "microtimestamp":"1691595437334962",
-                "bids":[],
-                "asks":[["29737","0.67548438"],["29738","0.67255217"]]
-            },"channel":"order_book_btcusd","event":"data"}"#,
+        // an incremental delete on top
+        let raw = delta(1696405429000000, &level("BTC_KRW", "bid", "37845000", "0"));
+        let ob = parse(&raw).unwrap();
+        assert_eq!(ob.name, "bithumb");
+        assert_eq!(ob.pair.as_deref(), Some("BTC_KRW"));
+        assert_eq!(ob.best_bid(), Some((&dec("37840000"), &dec("2"))));
+        assert_eq!(ob.best_ask(), Some((&dec("37855000"), &dec("1.2"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 2));
+        // older than the book
+        let raw = delta(1696405428950000, &level("BTC_KRW", "bid", "37830000", "1"));
+        assert_eq!(parse(&raw), ParseOutcome::Ignore);
+        // same datetime as the last one applied
+        let raw = delta(1696405429000000, &level("BTC_KRW", "bid", "37835000", "1"));
+        let ob = parse(&raw).unwrap();
+        assert_eq!(ob.bid.get(&dec("37835000")), Some(&dec("1")));
+        let raw = delta(1696405429000000, &level("BTC_KRW", "bid", "37835000", "0"));
+        let ob = parse(&raw).unwrap();
+        assert_eq!(ob.bid.get(&dec("37835000")), None);
+
+        // another symbol in the same message waits for its own snapshot
+        let raw = delta(
+            1696405429100000,
+            &[
+                level("ETH_KRW", "bid", "2200000", "3"),
+                level("BTC_KRW", "ask", "37855000", "1.5"),
+            ]
+            .join(","),
+        );
+        let ob = parse(&raw).unwrap();
+        assert_eq!(ob.best_ask(), Some((&dec("37855000"), &dec("1.5"))));
+        assert_eq!(pending(), vec!["ETH_KRW".to_string()]);
+        assert!(seed("ETH_KRW", r#"{"status":"5600","message":"invalid"}"#).is_err());
+
+        // the book went stale: buffered and fetched again
+        super::BITHUMB.books.lock().unwrap().remove("BTC_KRW");
+        let raw = delta(1696405429200000, &level("BTC_KRW", "bid", "37840000", "4"));
+        assert_eq!(
+            parse(&raw),
+            ParseOutcome::ProtocolError("bithumb: book evicted on BTC_KRW, resync".to_string())
+        );
+        let mut pending_now = pending();
+        pending_now.sort();
+        assert_eq!(
+            pending_now,
+            vec!["BTC_KRW".to_string(), "ETH_KRW".to_string()]
+        );
+        seed(
+            "BTC_KRW",
+            r#"{"status":"0000","data":{"timestamp":"1696405429200","order_currency":"BTC","payment_currency":"KRW","bids":[{"quantity":"2","price":"37840000"}],"asks":[{"quantity":"1.5","price":"37855000"}]}}"#,
         )
         .unwrap();
-        let mut ob = super::Orderbook::new("bitstamp");
-        ob.insert(
-            super::Side::Ask,
-            BigDecimal::from_str("29737").unwrap(),
-            BigDecimal::from_str("0.67548438").unwrap(),
+        let raw = delta(1696405429300000, &level("BTC_KRW", "ask", "37850000", "1"));
+        let ob = parse(&raw).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("37840000"), &dec("4"))));
+        assert_eq!(ob.best_ask(), Some((&dec("37850000"), &dec("1"))));
+        assert_eq!(
+            parse(r#"{"status":"5100","resmsg":"Invalid Filter Syntax"}"#),
+            ParseOutcome::ProtocolError("bithumb: 5100 Invalid Filter Syntax".to_string())
         );
-        ob.insert(
-            super::Side::Ask,
-            BigDecimal::from_str("29738").unwrap(),
-            BigDecimal::from_str("0.67255217").unwrap(),
+        (api.clear)();
+        assert!(pending().is_empty());
+    }
+    #[test]
+    fn test_luno_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("luno").unwrap();
+        (api.clear)();
+        let raw: Vec<&str> = include_str!("../test_resource/luno_stream.jsonl")
+            .lines()
+            .collect();
+        let parse = |raw: &str| api.parse_book(None, raw);
+        let levels = |side: &std::collections::BTreeMap<BigDecimal, BigDecimal>| {
+            side.iter()
+                .map(|(p, v)| (p.to_string(), v.normalized().to_string()))
+                .collect::<Vec<_>>()
+        };
+        let level = |p: &str, v: &str| (p.to_string(), v.to_string());
+        // changes before the snapshot have no orders to go on
+        assert!(matches!(parse(raw[1]), ParseOutcome::FatalDesync(_)));
+        assert_eq!(parse(""), ParseOutcome::Ignore);
+
+        // the orders summed up by price
+        let ob = parse(raw[0]).unwrap();
+        assert_eq!(ob.name, "luno");
+        assert_eq!(
+            levels(&ob.bid),
+            vec![level("999.00", "2"), level("1000.00", "0.4")]
         );
-        if let Some(b) = out.as_ref() {
-            ob.timestamp = b.timestamp;
+        assert_eq!(
+            levels(&ob.ask),
+            vec![level("1001.00", "0.75"), level("1002.00", "1")]
+        );
+        // create
+        let ob = parse(raw[1]).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("1000.50"), &dec("0.30"))));
+        // delete: the order's volume leaves its level
+        let ob = parse(raw[2]).unwrap();
+        assert_eq!(ob.best_ask(), Some((&dec("1001.00"), &dec("0.5"))));
+        // trades: the maker keeps the rest, the filled one leaves
+        let ob = parse(raw[3]).unwrap();
+        assert_eq!(ob.best_ask(), Some((&dec("1001.00"), &dec("0.3"))));
+        assert_eq!(ob.best_bid(), Some((&dec("1000.00"), &dec("0.40"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        assert_eq!(
+            parse(raw[4]),
+            ParseOutcome::Status(super::VenueStatus::Maintenance("post_only".to_string()))
+        );
+        // 24357 went missing
+        assert_eq!(
+            parse(raw[5]),
+            ParseOutcome::FatalDesync("luno: sequence jumped from 24356 to 24358".to_string())
+        );
+        (api.clear)();
+    }
+    #[test]
+    fn test_poloniex_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("poloniex").unwrap();
+        (api.clear)();
+        let setting: crate::config::ExchangeSetting =
+            serde_yaml::from_str("pair: BTC_USDT").unwrap();
+        assert_eq!(
+            api.subscribe_text(&api.channel(&setting.pair).unwrap(), 20)
+                .unwrap(),
+            vec![r#"{"event":"subscribe","channel":["book_lv2"],"symbols":["BTC_USDT"]}"#]
+        );
+        let frame = |action: &str, id: u64, last_id: u64, bids: &str, asks: &str| {
+            format!(
+                r#"{{"channel":"book_lv2","action":"{}","data":[{{"symbol":"BTC_USDT","createTime":1696405428703,"asks":[{}],"bids":[{}],"lastId":{},"id":{},"ts":1696405428710}}]}}"#,
+                action, asks, bids, last_id, id
+            )
+        };
+        let parse = |raw: &str| api.parse_book(None, raw);
+        assert_eq!(
+            parse(r#"{"event":"subscribe","channel":"book_lv2","symbols":["BTC_USDT"]}"#),
+            ParseOutcome::Ignore
+        );
+        let ob = parse(&frame(
+            "snapshot",
+            165,
+            164,
+            r#"["27990.1","0.5"],["27989","1.2"]"#,
+            r#"["27990.5","0.3"],["27991","2"]"#,
+        ))
+        .unwrap();
+        assert_eq!(ob.name, "poloniex");
+        assert_eq!(ob.pair.as_deref(), Some("BTC_USDT"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        // chained on the snapshot: a level removed, one changed
+        let ob = parse(&frame(
+            "update",
+            166,
+            165,
+            r#"["27990.1","0"]"#,
+            r#"["27990.5","0.1"]"#,
+        ))
+        .unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("27989"), &dec("1.2"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27990.5"), &dec("0.1"))));
+        assert_eq!(parse(r#"{"event":"pong"}"#), ParseOutcome::Ignore);
+        // the next update in sequence onto a book dropped as stale
+        let book = super::POLONIEX.lock().unwrap().remove("BTC_USDT").unwrap();
+        assert_eq!(
+            parse(&frame("update", 167, 166, "", r#"["27991","0"]"#)),
+            ParseOutcome::FatalDesync(
+                "poloniex: update on BTC_USDT whose book was evicted".to_string()
+            )
+        );
+        super::POLONIEX
+            .lock()
+            .unwrap()
+            .insert("BTC_USDT".to_string(), book);
+        // 167 went missing
+        assert_eq!(
+            parse(&frame("update", 168, 167, "", r#"["27991","0"]"#)),
+            ParseOutcome::FatalDesync(
+                "poloniex: update after 167 on BTC_USDT, expected after Some(166)".to_string()
+            )
+        );
+        assert_eq!(
+            parse(r#"{"event":"error","message":"Subscription failed: invalid symbol FOO_BAR"}"#),
+            ParseOutcome::ProtocolError(
+                "poloniex: Subscription failed: invalid symbol FOO_BAR".to_string()
+            )
+        );
+        (api.clear)();
+        // an update without its snapshot
+        assert!(matches!(
+            parse(&frame("update", 170, 169, "", "")),
+            ParseOutcome::FatalDesync(_)
+        ));
+    }
+    #[test]
+    fn test_bitso_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("bitso").unwrap();
+        (api.clear)();
+        let order = |oid: &str, side: u8, rate: &str, amount: Option<&str>| match amount {
+            Some(amount) => format!(
+                r#"{{"d":1696405428703,"r":"{}","t":{},"a":"{}","v":"1","s":"open","o":"{}"}}"#,
+                rate, side, amount, oid
+            ),
+            None => format!(
+                r#"{{"d":1696405428703,"r":"{}","t":{},"s":"cancelled","o":"{}"}}"#,
+                rate, side, oid
+            ),
+        };
+        let diff = |sequence: u64, orders: &[String]| {
+            format!(
+                r#"{{"type":"diff-orders","book":"btc_mxn","payload":[{}],"sequence":{}}}"#,
+                orders.join(","),
+                sequence
+            )
+        };
+        let parse = |raw: &str| api.parse_book(None, raw);
+        let pending = || (api.seed.as_ref().unwrap().pending)();
+        let seed = |body: &str| (api.seed.as_ref().unwrap().apply)("btc_mxn", body);
+        let snapshot = |sequence: u64| {
+            format!(
+                r#"{{"success":true,"payload":{{"asks":[{{"book":"btc_mxn","price":"480100","amount":"0.5","oid":"a1"}},{{"book":"btc_mxn","price":"480100","amount":"0.25","oid":"a2"}}],"bids":[{{"book":"btc_mxn","price":"480000","amount":"1","oid":"b1"}}],"updated_at":"2023-10-04T07:43:48+00:00","sequence":"{}"}}}}"#,
+                sequence
+            )
+        };
+        assert_eq!(
+            parse(
+                r#"{"action":"subscribe","response":"ok","time":1696405428703,"type":"diff-orders"}"#
+            ),
+            ParseOutcome::Ignore
+        );
+
+        // buffered until the snapshot
+        // a0 is out of the snapshot already
+        let d1 = diff(100, &[order("a0", 1, "480200", None)]);
+        let d2 = diff(101, &[order("b2", 0, "480050", Some("0.3"))]);
+        let d3 = diff(102, &[order("a1", 1, "480100", Some("0.2"))]);
+        for raw in [&d1, &d2, &d3] {
+            assert_eq!(parse(raw), ParseOutcome::Ignore);
         }
-        assert_eq!(out, Some(ob));
+        assert_eq!(pending(), vec!["btc_mxn".to_string()]);
+        // older than the diffs: fetched again
+        assert!(seed(&snapshot(90)).is_err());
+        assert_eq!(pending().len(), 1);
+        // has the diff of 100 in it: 101 and 102 replayed in order
+        seed(&snapshot(100)).unwrap();
+        assert!(pending().is_empty());
+        let ob = parse(&diff(103, &[order("b3", 0, "479900", Some("2"))])).unwrap();
+        assert_eq!(ob.name, "bitso");
+        assert_eq!(ob.pair.as_deref(), Some("btc_mxn"));
+        assert_eq!(ob.best_bid(), Some((&dec("480050"), &dec("0.3"))));
+        // a1 went down to 0.2, next to a2
+        assert_eq!(ob.best_ask(), Some((&dec("480100"), &dec("0.45"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (3, 1));
+        // a diff the snapshot had, then a gap
+        assert_eq!(parse(&d3), ParseOutcome::Ignore);
+        assert_eq!(
+            parse(&diff(105, &[])),
+            ParseOutcome::FatalDesync("bitso: btc_mxn jumped from 103 to 105".to_string())
+        );
+
+        // a gap among the buffered diffs fails the seed
+        (api.clear)();
+        parse(&diff(101, &[]));
+        parse(&diff(103, &[]));
+        assert!(seed(&snapshot(100)).is_err());
+        (api.clear)();
+        assert_eq!(parse(r#"{"type":"ka"}"#), ParseOutcome::Ignore);
+        assert!(matches!(
+            parse(r#"{"action":"subscribe","response":"error","type":"diff-orders"}"#),
+            ParseOutcome::ProtocolError(_)
+        ));
     }
     #[test]
-    fn test_kraken_parse() {
-        // this is the special case that array has dynamic length
-        (super::WS_APIMAP.get("kraken").unwrap().parse)(
-            r#"[384,{"a":[["43468.00000","0.12661008","1696405428.703749"]]},{"b":[["43468.00000","0.00000000","1696405428.703785"],["43196.60000","0.00115748","1696396431.709973","r"]],"c":"2556304438"},"book-25","XBT/AUD"]"#,
-        ).unwrap();
+    fn test_coinex_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("coinex").unwrap();
+        (api.clear)();
+        assert_eq!(api.compression, Some(super::Compression::Gzip));
+        let update = |is_full: bool, bids: &str, asks: &str| {
+            format!(
+                r#"{{"method":"depth.update","data":{{"market":"BTCUSDT","is_full":{},"depth":{{"asks":[{}],"bids":[{}],"last":"27990.28","updated_at":1696405428703,"checksum":2578768879}}}},"id":null}}"#,
+                is_full, asks, bids
+            )
+        };
+        let parse = |raw: &str| api.parse_book(None, raw);
+        assert_eq!(
+            parse(r#"{"id":1,"code":0,"message":"OK"}"#),
+            ParseOutcome::Ignore
+        );
+        assert!(matches!(
+            parse(&update(false, r#"["27990","1"]"#, "")),
+            ParseOutcome::FatalDesync(_)
+        ));
+        // clean: the whole book
+        let ob = parse(&update(
+            true,
+            r#"["27990.1","0.5"],["27989","1.2"]"#,
+            r#"["27990.5","0.3"],["27991","2"]"#,
+        ))
+        .unwrap();
+        assert_eq!(ob.name, "coinex");
+        assert_eq!(ob.pair.as_deref(), Some("BTCUSDT"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        assert_eq!(ob.last_price, dec("27990.28"));
+        // a diff: "0" deletes the level
+        let ob = parse(&update(false, r#"["27990.1","0"]"#, r#"["27990.4","0.2"]"#)).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("27989"), &dec("1.2"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27990.4"), &dec("0.2"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 3));
+        // a new full one replaces it
+        let ob = parse(&update(true, r#"["27980","1"]"#, "")).unwrap();
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 0));
+        assert_eq!(
+            parse(r#"{"id":2,"code":0,"message":"OK","data":{"result":"pong"}}"#),
+            ParseOutcome::Ignore
+        );
+        assert_eq!(
+            parse(r#"{"id":1,"code":20001,"message":"invalid argument"}"#),
+            ParseOutcome::ProtocolError("coinex: 20001 invalid argument".to_string())
+        );
+        (api.clear)();
+    }
+    #[test]
+    fn test_deribit_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("deribit").unwrap();
+        (api.clear)();
+        let raw: Vec<&str> = include_str!("../test_resource/deribit_book.jsonl")
+            .lines()
+            .collect();
+        let parse = |raw: &str| api.parse_book(None, raw);
+        assert_eq!(parse(raw[0]), ParseOutcome::Ignore);
+        // a change before the snapshot
+        assert!(matches!(parse(raw[2]), ParseOutcome::FatalDesync(_)));
+        let ob = parse(raw[1]).unwrap();
+        assert_eq!(ob.name, "deribit");
+        assert_eq!(ob.pair.as_deref(), Some("BTC-PERPETUAL"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        assert_eq!(ob.best_bid(), Some((&dec("27990.5"), &dec("12000"))));
+        // new and change
+        let ob = parse(raw[2]).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("27990.8"), &dec("1500"))));
+        assert_eq!(ob.bid.get(&dec("27990.5")), Some(&dec("9000")));
+        // delete
+        let ob = parse(raw[3]).unwrap();
+        assert_eq!(ob.best_ask(), Some((&dec("27991.5"), &dec("20000"))));
+        assert_eq!(ob.ask.len(), 1);
+        // the heartbeat is answered, nothing to parse
+        let pong = api.pong.unwrap();
+        assert_eq!(
+            pong(raw[4]),
+            Some(r#"{"jsonrpc":"2.0","id":3,"method":"public/test","params":{}}"#.to_string())
+        );
+        assert_eq!(
+            pong(r#"{"jsonrpc":"2.0","method":"heartbeat","params":{"type":"heartbeat"}}"#),
+            None
+        );
+        assert_eq!(pong(raw[3]), None);
+        assert_eq!(parse(raw[4]), ParseOutcome::Ignore);
+        // the next change in sequence onto a book dropped as stale
+        super::DERIBIT.lock().unwrap().remove("BTC-PERPETUAL");
+        assert_eq!(
+            parse(
+                r#"{"jsonrpc":"2.0","method":"subscription","params":{"channel":"book.BTC-PERPETUAL.100ms","data":{"type":"change","timestamp":1696405429003,"instrument_name":"BTC-PERPETUAL","prev_change_id":297219,"change_id":297220,"bids":[],"asks":[["new",27992.0,100]]}}}"#
+            ),
+            ParseOutcome::FatalDesync(
+                "deribit: change on BTC-PERPETUAL whose book was evicted".to_string()
+            )
+        );
+        // 297220 and 297221 went missing
+        assert_eq!(
+            parse(raw[5]),
+            ParseOutcome::FatalDesync(
+                "deribit: change after Some(297221) on BTC-PERPETUAL, expected after Some(297219)"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","id":1,"error":{"message":"Invalid params","code":-32602}}"#),
+            ParseOutcome::ProtocolError(
+                r#"deribit: {"code":-32602,"message":"Invalid params"}"#.to_string()
+            )
+        );
+        (api.clear)();
+    }
+    #[test]
+    fn test_bitmex_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("bitmex").unwrap();
+        (api.clear)();
+        let raw: Vec<&str> = include_str!("../test_resource/bitmex_book.jsonl")
+            .lines()
+            .collect();
+        let parse = |raw: &str| api.parse_book(None, raw);
+        assert_eq!(parse(raw[0]), ParseOutcome::Ignore);
+        assert_eq!(parse(raw[1]), ParseOutcome::Ignore);
+        assert_eq!(parse("pong"), ParseOutcome::Ignore);
+        // before the partial
+        assert_eq!(parse(raw[3]), ParseOutcome::Ignore);
+        let ob = parse(raw[2]).unwrap();
+        assert_eq!(ob.name, "bitmex");
+        assert_eq!(ob.pair.as_deref(), Some("XBTUSD"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        assert_eq!(ob.best_ask(), Some((&dec("27999"), &dec("15000"))));
+        // the price comes from the id
+        let ob = parse(raw[3]).unwrap();
+        assert_eq!(ob.best_ask(), Some((&dec("27999"), &dec("12000"))));
+        // one priced from its legacy id, one priced
+        let ob = parse(raw[4]).unwrap();
+        assert_eq!(ob.bid.get(&dec("27997.5")), Some(&dec("7000")));
+        assert_eq!(ob.ask.get(&dec("28000")), Some(&dec("1000")));
+        let ob = parse(raw[5]).unwrap();
+        assert_eq!(ob.ask.get(&dec("27999.5")), None);
+        assert_eq!((ob.bid.len(), ob.ask.len()), (3, 2));
+        assert_eq!(
+            parse(raw[6]),
+            ParseOutcome::FatalDesync(
+                "bitmex: update of unknown id 8797199000 on XBTUSD".to_string()
+            )
+        );
+        // a new partial starts over
+        let ob = parse(raw[2]).unwrap();
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        assert_eq!(
+            super::bitmex_id_price("XBTUSD", 8797200250),
+            Some(dec("27997.5"))
+        );
+        assert_eq!(super::bitmex_id_price("ETHUSD", 8797200250), None);
+        assert_eq!(
+            parse(
+                r#"{"status":400,"error":"Unknown table: orderBookL3","meta":{},"request":{"op":"subscribe","args":["orderBookL3:XBTUSD"]}}"#
+            ),
+            ParseOutcome::ProtocolError("bitmex: Unknown table: orderBookL3".to_string())
+        );
+        (api.clear)();
+    }
+    #[test]
+    fn test_hyperliquid_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("hyperliquid").unwrap();
+        let raw: Vec<&str> = include_str!("../test_resource/hyperliquid_l2book.jsonl")
+            .lines()
+            .collect();
+        let parse = |raw: &str| api.parse_book(None, raw);
+        assert_eq!(parse(raw[0]), ParseOutcome::Ignore);
+        let ob = parse(raw[1]).unwrap();
+        assert_eq!(ob.name, "hyperliquid");
+        assert_eq!(ob.pair.as_deref(), Some("BTC"));
+        assert_eq!(ob.timestamp, 1696405428703);
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        assert_eq!(ob.best_bid(), Some((&dec("27990"), &dec("1.25"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27991"), &dec("0.8"))));
+        // rebuilt, nothing left of the one before
+        let ob = parse(raw[2]).unwrap();
+        assert_eq!(ob.timestamp, 1696405429203);
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 2));
+        assert_eq!(ob.best_bid(), Some((&dec("27989"), &dec("0.7"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27990"), &dec("0.3"))));
+        assert_eq!(parse(raw[3]), ParseOutcome::Ignore);
+        assert_eq!(
+            parse(r#"{"channel":"error","data":"Already subscribed: BTC"}"#),
+            ParseOutcome::ProtocolError("hyperliquid: Already subscribed: BTC".to_string())
+        );
+    }
+    #[test]
+    fn test_kraken_futures_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("kraken_futures").unwrap();
+        (api.clear)();
+        let raw: Vec<&str> = include_str!("../test_resource/kraken_futures_book.jsonl")
+            .lines()
+            .collect();
+        let parse = |raw: &str| api.parse_book(None, raw);
+        assert_eq!(parse(raw[0]), ParseOutcome::Ignore);
+        assert_eq!(parse(raw[1]), ParseOutcome::Ignore);
+        // before the snapshot
+        assert_eq!(parse(raw[3]), ParseOutcome::Ignore);
+        let ob = parse(raw[2]).unwrap();
+        assert_eq!(ob.name, "kraken_futures");
+        assert_eq!(ob.pair.as_deref(), Some("PI_XBTUSD"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        assert_eq!(ob.best_ask(), Some((&dec("27991"), &dec("4500"))));
+        let ob = parse(raw[3]).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("27990.5"), &dec("5000"))));
+        // qty 0 deletes
+        let ob = parse(raw[4]).unwrap();
+        assert_eq!(ob.best_ask(), Some((&dec("27991.5"), &dec("12000"))));
+        assert_eq!(ob.ask.len(), 1);
+        assert_eq!(parse(raw[5]), ParseOutcome::Ignore);
+        // the next delta in sequence onto a book dropped as stale
+        let book = super::KRAKEN_FUTURES
+            .lock()
+            .unwrap()
+            .remove("PI_XBTUSD")
+            .unwrap();
+        assert_eq!(
+            parse(
+                r#"{"feed":"book","product_id":"PI_XBTUSD","side":"buy","seq":326072252,"price":27990,"qty":0,"timestamp":1696405429053}"#
+            ),
+            ParseOutcome::FatalDesync(
+                "kraken_futures: delta on PI_XBTUSD whose book was evicted".to_string()
+            )
+        );
+        super::KRAKEN_FUTURES
+            .lock()
+            .unwrap()
+            .insert("PI_XBTUSD".to_string(), book);
+        // 326072252 went missing
+        assert_eq!(
+            parse(raw[6]),
+            ParseOutcome::FatalDesync(
+                "kraken_futures: seq 326072253 on PI_XBTUSD, expected 326072252".to_string()
+            )
+        );
+        assert_eq!(
+            parse(r#"{"event":"error","message":"Invalid product id"}"#),
+            ParseOutcome::ProtocolError("kraken_futures: Invalid product id".to_string())
+        );
+        (api.clear)();
+    }
+    #[test]
+    fn test_bitflyer_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("bitflyer").unwrap();
+        (api.clear)();
+        let raw: Vec<&str> = include_str!("../test_resource/bitflyer_board.jsonl")
+            .lines()
+            .collect();
+        let parse = |raw: &str| api.parse_book(None, raw);
+        let pending = || (api.seed.as_ref().unwrap().pending)();
+        let seed = |body: &str| (api.seed.as_ref().unwrap().apply)("BTC_JPY", body);
+        let board = r#"{"mid_price":4200050.0,"bids":[{"price":4200000.0,"size":1.0},{"price":4199900.0,"size":2.0}],"asks":[{"price":4200100.0,"size":0.5},{"price":4200200.0,"size":0.4}]}"#;
+        assert_eq!(parse(raw[0]), ParseOutcome::Ignore);
+        // buffered until the rest snapshot
+        assert_eq!(parse(raw[1]), ParseOutcome::Ignore);
+        assert_eq!(parse(raw[2]), ParseOutcome::Ignore);
+        assert_eq!(pending(), vec!["BTC_JPY".to_string()]);
+        seed(board).unwrap();
+        assert!(pending().is_empty());
+        let ob = parse(raw[4]).unwrap();
+        assert_eq!(ob.name, "bitflyer");
+        assert_eq!(ob.pair.as_deref(), Some("BTC_JPY"));
+        // the buffered diffs on top of the board
+        assert_eq!(ob.best_bid(), Some((&dec("4200000"), &dec("0.3"))));
+        assert_eq!(ob.best_ask(), Some((&dec("4200150"), &dec("0.05"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        // the snapshot channel rebuilds it
+        let ob = parse(raw[3]).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("4200050"), &dec("0.1"))));
+        assert_eq!(ob.best_ask(), Some((&dec("4200100"), &dec("0.2"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+
+        // the snapshot channel before the rest snapshot
+        (api.clear)();
+        assert_eq!(parse(raw[1]), ParseOutcome::Ignore);
+        let ob = parse(raw[3]).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("4200050"), &dec("0.1"))));
+        assert!(pending().is_empty());
+        seed(board).unwrap();
+        let ob = parse(raw[4]).unwrap();
+        assert_eq!(ob.best_bid(), Some((&dec("4200000"), &dec("1.5"))));
+        assert_eq!(
+            parse(
+                r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"invalid channel"}}"#
+            ),
+            ParseOutcome::ProtocolError("bitflyer: invalid channel".to_string())
+        );
+        (api.clear)();
+    }
+    #[test]
+    fn test_coincheck_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("coincheck").unwrap();
+        (api.clear)();
+        let raw: Vec<&str> = include_str!("../test_resource/coincheck_orderbook.jsonl")
+            .lines()
+            .collect();
+        let parse = |raw: &str| api.parse_book(None, raw);
+        let pending = || (api.seed.as_ref().unwrap().pending)();
+        let seed = |body: &str| (api.seed.as_ref().unwrap().apply)("btc_jpy", body);
+        let snapshot = r#"{"asks":[["4200100.0","0.5"],["4200200.0","0.4"]],"bids":[["4200000.0","1.0"],["4199900.0","2.0"]]}"#;
+        // buffered until the snapshot
+        assert_eq!(parse(raw[0]), ParseOutcome::Ignore);
+        assert_eq!(parse(raw[1]), ParseOutcome::Ignore);
+        assert_eq!(pending(), vec!["btc_jpy".to_string()]);
+        seed(snapshot).unwrap();
+        assert!(pending().is_empty());
+        let ob = parse(raw[2]).unwrap();
+        assert_eq!(ob.name, "coincheck");
+        assert_eq!(ob.pair.as_deref(), Some("btc_jpy"));
+        assert_eq!(ob.timestamp, 1696405430000);
+        assert_eq!(ob.best_bid(), Some((&dec("4200050"), &dec("0.1"))));
+        assert_eq!(ob.bid.get(&dec("4200000")), Some(&dec("0.3")));
+        // 4200100 went with the second diff
+        assert_eq!(ob.best_ask(), Some((&dec("4200150"), &dec("0.05"))));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (3, 2));
+        // a delete
+        let ob = parse(raw[3]).unwrap();
+        assert_eq!(ob.timestamp, 1696405431000);
+        assert_eq!(ob.bid.get(&dec("4200000")), None);
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        // a seed of a live book is dropped
+        seed(snapshot).unwrap();
+        assert_eq!(parse(raw[3]).unwrap().bid.len(), 2);
+        assert_eq!(parse(r#"{"type":"subscribe"}"#), ParseOutcome::Ignore);
+        (api.clear)();
+    }
+    #[test]
+    fn test_lbank_parse() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let api = super::WS_APIMAP.get("lbank").unwrap();
+        let raw: Vec<&str> = include_str!("../test_resource/lbank_depth.jsonl")
+            .lines()
+            .collect();
+        let parse = |raw: &str| api.parse_book(None, raw);
+        let ob = parse(raw[0]).unwrap();
+        assert_eq!(ob.name, "lbank");
+        assert_eq!(ob.pair.as_deref(), Some("btc_usdt"));
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 2));
+        assert_eq!(ob.best_bid(), Some((&dec("27990.5"), &dec("0.8"))));
+        assert_eq!(ob.best_ask(), Some((&dec("27991.12"), &dec("0.5"))));
+        // answered with its id, nothing to parse
+        let pong = api.pong.unwrap();
+        assert_eq!(
+            pong(raw[1]),
+            Some(r#"{"action":"pong","pong":"0ca8f854-7ba7-4341-9d86-d3327e52804e"}"#.to_string())
+        );
+        assert_eq!(pong(raw[0]), None);
+        assert_eq!(parse(raw[1]), ParseOutcome::Ignore);
+        // rebuilt, nothing left of the one before
+        let ob = parse(raw[2]).unwrap();
+        assert_eq!((ob.bid.len(), ob.ask.len()), (2, 1));
+        assert_eq!(ob.best_bid(), Some((&dec("27990.8"), &dec("1.1"))));
+        assert_eq!(ob.bid.get(&dec("27990.5")), Some(&dec("0.4")));
+        assert_eq!(
+            parse(
+                r#"{"SERVER":"V2","message":"Invalid pair","status":"error","TS":"2023-10-04T15:43:48.903"}"#
+            ),
+            ParseOutcome::ProtocolError("lbank: Invalid pair".to_string())
+        );
+    }
+    #[test]
+    fn test_indreserve_channel_case() {
+        let parse = super::WS_APIMAP.get("independentreserve").unwrap().parse;
+        parse(r#"{"Data": ["OrderBook/20/BTC/CASE"], "Event": "Subscriptions", "Time": 1660895883834}"#)
+            .unwrap();
+        let out = parse(
+            r#"{"Channel": "orderbook/20/btc/case","Data": {"Bids": [{"Price": 100,"Volume": 1}],
+            "Offers": [{"Price": 101,"Volume": 2}],"Crc32": 1},"Time": 1660895883834,"Event": "OrderBookSnapshot"}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(out.bid.len(), 1);
     }
     #[test]
     fn test_indreserve_parse() {
@@ -614,6 +5842,7 @@ mod tests {
         )
         .unwrap();
         let mut ob = super::Orderbook::new("independentreserve");
+        ob.pair = Some("orderbook/5/btc/aud".to_string());
         ob.insert(
             super::Side::Bid,
             BigDecimal::from_str("31802.46").unwrap(),
@@ -634,9 +5863,345 @@ mod tests {
             BigDecimal::from_str("31845").unwrap(),
             BigDecimal::from_str("1.5").unwrap(),
         );
-        if let Some(o) = out.as_ref() {
+        if let ParseOutcome::Book(o) = &out {
             ob.timestamp = o.timestamp;
         }
-        assert_eq!(out, Some(ob));
+        assert_eq!(out, ParseOutcome::Book(Arc::new(ob)));
+    }
+    #[test]
+    fn test_noise_frames() {
+        use ParseOutcome::{Ignore, ProtocolError};
+        let frames = [
+            ("binance", r#"{"id": 1, "result": null}"#, Ignore),
+            ("binance", r#"{"e":"error","m":"Max subscriptions exceeded"}"#, ProtocolError("binance: Max subscriptions exceeded".to_string())),
+            ("binance", r#"{"code": 2, "msg": "Invalid request: unknown variable"}"#, ProtocolError("binance: 2 Invalid request: unknown variable".to_string())),
+            ("binance_futures", r#"{"e":"listenKeyExpired","E":1696405428703}"#, Ignore),
+            ("bitstamp", r#"{"event":"bts:heartbeat","channel":"","data":{"status":"success"}}"#, Ignore),
+            ("bitstamp", r#"{"event":"data","channel":"live_orders_btcusd","data":{}}"#, Ignore),
+            ("bitstamp", r#"{"event":"bts:error","channel":"","data":{"code":null,"message":"Bad subscription string."}}"#, ProtocolError(r#"bitstamp: {"code":null,"message":"Bad subscription string."}"#.to_string())),
+            ("independentreserve", r#"{"Event":"Heartbeat","Time":1660895883834}"#, Ignore),
+            ("btcmarkets", r#"{"messageType":"heartbeat"}"#, Ignore),
+            ("btcmarkets", r#"{"messageType":"subscribe","marketIds":["BTC-AUD"],"channels":["orderbook","tick"]}"#, Ignore),
+            ("btcmarkets", r#"{"messageType":"error","code":3,"message":"invalid channel names"}"#, ProtocolError("btcmarkets: invalid channel names".to_string())),
+            ("coinjar", r#"{"event":"phx_reply","payload":{"status":"ok","response":{}},"ref":0,"topic":"book:BTCAUD"}"#, Ignore),
+            ("coinjar", r#"{"event":"phx_reply","payload":{"status":"error","response":{"reason":"unmatched topic"}},"ref":0,"topic":"book:FOO"}"#, ProtocolError(r#"coinjar: {"response":{"reason":"unmatched topic"},"status":"error"} on book:FOO"#.to_string())),
+            ("kraken", r#"{"event":"heartbeat"}"#, Ignore),
+            ("kraken", r#"{"errorMessage":"Currency pair not supported","event":"subscriptionStatus","status":"error"}"#, ProtocolError("kraken: Currency pair not supported".to_string())),
+            ("kraken", r#"[321,["5698.40000","5700.00000","1542057299.545897","1.01234567","0.98765432"],"spread","XBT/AUD"]"#, Ignore),
+        ];
+        for (exchange, raw, expected) in frames {
+            let api = super::WS_APIMAP.get(exchange).unwrap();
+            assert_eq!(api.parse_book(None, raw), expected, "{}: {}", exchange, raw);
+        }
+        // unreadable frames are protocol errors too, never a reconnect
+        for exchange in super::WS_APIMAP.keys() {
+            let api = super::WS_APIMAP.get(exchange).unwrap();
+            for raw in ["not json", "[]", "{}", "[1]"] {
+                assert!(
+                    !matches!(
+                        api.parse_book(None, raw),
+                        ParseOutcome::FatalDesync(_) | ParseOutcome::Book(_)
+                    ),
+                    "{}: {}",
+                    exchange,
+                    raw
+                );
+            }
+        }
+    }
+    #[test]
+    fn test_venue_status() {
+        use super::VenueStatus::{Maintenance, Online};
+        use ParseOutcome::Status;
+        let frames = [
+            (
+                "kraken",
+                r#"{"connectionID":123,"event":"systemStatus","status":"online","version":"1.9.0"}"#,
+                Status(Online),
+            ),
+            (
+                "kraken",
+                r#"{"connectionID":123,"event":"systemStatus","status":"maintenance","version":"1.9.0"}"#,
+                Status(Maintenance("maintenance".to_string())),
+            ),
+            (
+                "kraken",
+                r#"{"connectionID":123,"event":"systemStatus","status":"cancel_only","version":"1.9.0"}"#,
+                Status(Maintenance("cancel_only".to_string())),
+            ),
+            (
+                "btcmarkets",
+                r#"{"messageType":"status","marketId":"BTC-AUD","status":"Post Only"}"#,
+                Status(Maintenance("post_only".to_string())),
+            ),
+            (
+                "btcmarkets",
+                r#"{"messageType":"status","marketId":"BTC-AUD","status":"Online"}"#,
+                Status(Online),
+            ),
+        ];
+        for (exchange, raw, expected) in frames {
+            let api = super::WS_APIMAP.get(exchange).unwrap();
+            assert_eq!(api.parse_book(None, raw), expected, "{}: {}", exchange, raw);
+        }
+        let api = super::WS_APIMAP.get("btcmarkets").unwrap();
+        assert!(matches!(
+            api.parse_book(None, r#"{"messageType":"status","marketId":"BTC-AUD"}"#),
+            ParseOutcome::ProtocolError(_)
+        ));
+    }
+    #[test]
+    fn test_fatal_desync() {
+        let desync = |exchange: &str, raw: &str| {
+            let api = super::WS_APIMAP.get(exchange).unwrap();
+            matches!(api.parse_book(None, raw), ParseOutcome::FatalDesync(_))
+        };
+        assert!(desync(
+            "independentreserve",
+            r#"{"Channel":"orderbook/5/foo/aud","Data":{"Bids":[],"Offers":[],"Crc32":1},"Time":1660895883834,"Event":"OrderBookChange"}"#
+        ));
+        assert!(desync(
+            "coinjar",
+            r#"{"event":"phx_error","payload":{},"ref":0,"topic":"book:BTCAUD"}"#
+        ));
+        assert!(desync(
+            "bitstamp",
+            r#"{"event":"bts:request_reconnect","channel":"","data":""}"#
+        ));
+    }
+    #[test]
+    fn test_kraken_checksum() {
+        let parse = super::WS_APIMAP.get("kraken").unwrap().parse;
+        let snapshot = r#"[336,{"as":[["43470.00000","0.50000000","1696405428.1"],["43471.10000","1.25000000","1696405428.2"]],
+            "bs":[["43468.00000","0.12661008","1696405428.3"],["43467.50000","2.00000000","1696405428.4"]]},"book-25","XBT/CRC"]"#;
+        parse(snapshot).unwrap().unwrap();
+        // the checksum of the book once 43470 is gone
+        let out = parse(
+            r#"[336,{"a":[["43470.00000","0.00000000","1696405429.1"]],"c":"1029277595"},"book-25","XBT/CRC"]"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(out.ask.len(), 1);
+        // a missed update leaves the book off the exchange's
+        let out = parse(
+            r#"[336,{"b":[["43468.00000","0.20000000","1696405429.2"]],"c":"1029277595"},"book-25","XBT/CRC"]"#,
+        )
+        .unwrap();
+        assert!(matches!(out, ParseOutcome::FatalDesync(_)), "{:?}", out);
+    }
+    #[test]
+    fn test_kraken_republish() {
+        // a snapshot, then removals with their republished levels, new
+        // levels pushing others out, and a republished row under "as"
+        let parse = super::WS_APIMAP.get("kraken").unwrap().parse;
+        let mut book = None;
+        for (i, line) in include_str!("../test_resource/kraken_book.jsonl")
+            .lines()
+            .enumerate()
+        {
+            match parse(line).unwrap() {
+                ParseOutcome::Book(ob) => {
+                    assert_eq!((ob.bid.len(), ob.ask.len()), (25, 25), "line {}", i + 1);
+                    book = Some(ob);
+                }
+                out => panic!("line {}: {:?}", i + 1, out),
+            }
+        }
+        let book = book.unwrap();
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let bid = |price: &str| book.bid.get(&dec(price)).map(|v| v.to_string());
+        let ask = |price: &str| book.ask.get(&dec(price)).map(|v| v.to_string());
+        assert_eq!(book.bid.keys().next_back(), Some(&dec("43000")));
+        assert_eq!(book.bid.keys().next(), Some(&dec("42988")));
+        assert_eq!(book.ask.keys().next(), Some(&dec("43000.6")));
+        assert_eq!(book.ask.keys().next_back(), Some(&dec("43013.5")));
+        // removed, republished, resized
+        assert_eq!(bid("43000.2"), None);
+        assert_eq!(bid("42988"), Some("1.50000000".to_string()));
+        assert_eq!(bid("42999.5"), Some("4.00000000".to_string()));
+        assert_eq!(ask("43003"), None);
+        assert_eq!(ask("43005"), None);
+        assert_eq!(ask("43001"), Some("0.30000000".to_string()));
+        assert_eq!(ask("43013.5"), Some("0.80000000".to_string()));
+        // a resubscribed pair takes its snapshot again
+        super::kraken_forget("XBT/USD");
+        let snapshot = r#"[336,{"as":[["43001.00000","1.0","1696405440.1"]],
+            "bs":[["43000.00000","1.0","1696405440.2"]]},"book-25","XBT/USD"]"#;
+        let ob = parse(snapshot).unwrap().unwrap();
+        assert_eq!((ob.bid.len(), ob.ask.len()), (1, 1));
+    }
+    #[test]
+    fn test_kraken_ticker_top() {
+        let parse = super::WS_APIMAP.get("kraken").unwrap().parse;
+        let top = |raw: &str| {
+            let ob = parse(raw).unwrap().unwrap();
+            let (bid, bid_volume) = ob.bid.last_key_value().unwrap();
+            let (ask, ask_volume) = ob.ask.first_key_value().unwrap();
+            format!("{} {} / {} {}", bid, bid_volume, ask, ask_volume)
+        };
+        // "price volume" of the best bid and ask
+        let ticker = |bid: &str, ask: &str| {
+            let (bid, bid_volume) = bid.split_once(' ').unwrap();
+            let (ask, ask_volume) = ask.split_once(' ').unwrap();
+            format!(
+                r#"[340,{{"a":["{}",1,"{}"],"b":["{}",1,"{}"],"c":["100.5","0.1"],"v":["10","20"]}},"ticker","XBT/BBO"]"#,
+                ask, ask_volume, bid, bid_volume
+            )
+        };
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let snapshot = r#"[340,{"as":[["102.0","1.0","1696405428.1"],["103.0","1.0","1696405428.2"]],
+            "bs":[["100.0","1.0","1696405428.3"],["99.0","2.0","1696405428.4"]]},"book-25","XBT/BBO"]"#;
+        assert_eq!(top(snapshot), "100.0 1.0 / 102.0 1.0");
+        // better bid, same ask with a fresher size
+        assert_eq!(
+            top(&ticker("101.0 0.5", "102.0 3.0")),
+            "101.0 0.5 / 102.0 3.0"
+        );
+        // a crossing bid and a wider ask are left out
+        assert_eq!(
+            top(&ticker("102.5 1.0", "102.5 1.0")),
+            "101.0 0.5 / 102.0 3.0"
+        );
+
+        // the book update applies to the venue's book, its checksum matches
+        let mut venue = super::Orderbook::new("kraken");
+        for (price, volume) in [("100.0", "1.5"), ("99.0", "2.0")] {
+            venue.insert(super::Side::Bid, dec(price), dec(volume));
+        }
+        for (price, volume) in [("102.0", "1.0"), ("103.0", "1.0")] {
+            venue.insert(super::Side::Ask, dec(price), dec(volume));
+        }
+        let update = format!(
+            r#"[340,{{"b":[["100.0","1.5","1696405429.1"]],"c":"{}"}},"book-25","XBT/BBO"]"#,
+            super::kraken_checksum(&venue)
+        );
+        assert_eq!(top(&update), "100.0 1.5 / 102.0 1.0");
+
+        // ticker again between two updates
+        assert_eq!(
+            top(&ticker("99.5 1.0", "101.5 2.0")),
+            "100.0 1.5 / 101.5 2.0"
+        );
+        venue.insert(super::Side::Ask, dec("103.0"), dec("0"));
+        let update = format!(
+            r#"[340,{{"a":[["103.0","0.0","1696405429.2"]],"c":"{}"}},"book-25","XBT/BBO"]"#,
+            super::kraken_checksum(&venue)
+        );
+        let out = parse(&update).unwrap();
+        assert!(matches!(out, ParseOutcome::Book(_)), "{:?}", out);
+        assert_eq!(
+            top(&ticker("100.0 1.5", "102.0 1.0")),
+            "100.0 1.5 / 102.0 1.0"
+        );
+    }
+    #[test]
+    fn test_snapshot_copy_on_write() {
+        let parse = super::WS_APIMAP.get("coinjar").unwrap().parse;
+        let first = parse(
+            r#"{"event":"init","payload":{"bids":[["100","1"]],"asks":[["101","1"]]},"topic":"book:COWAUD"}"#,
+        )
+        .unwrap()
+        .unwrap();
+        let second = parse(
+            r#"{"event":"update","payload":{"bids":[["100.5","2"]],"asks":[]},"topic":"book:COWAUD"}"#,
+        )
+        .unwrap()
+        .unwrap();
+        // the snapshot handed out before is left as it was
+        assert_eq!(first.bid.len(), 1);
+        assert_eq!(second.bid.len(), 2);
+        // once released, the next update reuses the working book in place
+        drop(first);
+        let ptr = std::sync::Arc::as_ptr(&second);
+        drop(second);
+        let third = parse(
+            r#"{"event":"update","payload":{"bids":[],"asks":[["101","0"]]},"topic":"book:COWAUD"}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(std::sync::Arc::as_ptr(&third), ptr);
+        assert!(third.ask.is_empty());
+    }
+    #[test]
+    fn test_fixed_point_differential() {
+        let fixtures = [
+            (
+                "binance",
+                r#"{"lastUpdateId": 160, "bids":[["0.01", "0.2"],["0.0100", "1.50"]], "asks": [["0.011", "3"]]}"#,
+            ),
+            (
+                "bitstamp",
+                r#"{"data":{"timestamp":"1691595437","microtimestamp":"1691595437334962",
+                "bids":[["29736.5","0.1"]],"asks":[["29737","0.67548438"],["29738","0.67255217"]]},
+                "channel":"order_book_btcusd","event":"data"}"#,
+            ),
+            (
+                "binance_futures",
+                r#"{"e":"depthUpdate","s":"DIFFUSDT","b":[["1650.10","12.5"]],"a":[["1650.30","7.25"]]}"#,
+            ),
+            (
+                "kraken",
+                r#"[384,{"as":[["43468.00000","0.12661008","1696405428.703749"]],"bs":[["43196.60000","0.00115748","1696396431.709973"]]},"book-25","XBT/DIFF"]"#,
+            ),
+        ];
+        let summary = |fixed_point: bool| {
+            crate::fixed::enable(fixed_point);
+            let mut agg = crate::orderbook::AggregatedOrderbook::new();
+            for (exchange, raw) in fixtures {
+                let parse = super::WS_APIMAP.get(exchange).unwrap().parse;
+                let mut ob = (*parse(raw).unwrap().unwrap()).clone();
+                ob.timestamp = 0;
+                agg.merge(&ob);
+            }
+            crate::fixed::enable(false);
+            serde_json::to_string(&agg.finalize().unwrap()).unwrap()
+        };
+        // every price and size of the fixtures takes the fixed point path
+        for s in [
+            "0.01",
+            "0.0100",
+            "0.2",
+            "1.50",
+            "0.011",
+            "3",
+            "29736.5",
+            "0.1",
+            "29737",
+            "0.67548438",
+            "1650.10",
+            "12.5",
+            "7.25",
+            "43468.00000",
+            "0.12661008",
+            "43196.60000",
+        ] {
+            assert!(s.parse::<crate::fixed::Fixed>().is_ok(), "{}", s);
+        }
+        // byte for byte, the trailing zeros of the wire strings included
+        let fixed = summary(true);
+        assert_eq!(fixed, summary(false));
+        assert!(fixed.contains(r#""price":"43468.00000""#), "{}", fixed);
+        assert!(fixed.contains(r#""amount":"1.50""#), "{}", fixed);
+    }
+    #[test]
+    fn test_stale_books_evicted() {
+        let book = |timestamp| {
+            let mut ob = super::Orderbook::new("kraken");
+            ob.timestamp = timestamp;
+            std::sync::Arc::new(ob)
+        };
+        {
+            let mut tmp = super::KRAKEN.lock().unwrap();
+            tmp.insert("DEAD/TTL".to_string(), book(0));
+            tmp.insert("LIVE/TTL".to_string(), book(super::get_unixtime()));
+        }
+        (super::WS_APIMAP.get("kraken").unwrap().parse)(
+            r#"[384,{"a":[["43468.00000","0.12661008","1696405428.703749"]]},"book-25","XBT/TTL"]"#,
+        )
+        .unwrap();
+        let tmp = super::KRAKEN.lock().unwrap();
+        assert!(!tmp.contains_key("DEAD/TTL"));
+        assert!(tmp.contains_key("LIVE/TTL"));
+        assert!(tmp.contains_key("XBT/TTL"));
     }
 }
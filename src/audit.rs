@@ -0,0 +1,284 @@
+use crate::apitree;
+use crate::config::{AuditSetting, Sides};
+use crate::orderbook::{get_unixtime, Orderbook};
+use crate::sink::statsd;
+use crate::status;
+use bigdecimal::{BigDecimal, ToPrimitive};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::time::{interval, Duration};
+
+// exchange -> audit setting
+static SETTINGS: Lazy<Mutex<HashMap<String, AuditSetting>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn init(settings: HashMap<String, AuditSetting>) {
+    *SETTINGS.lock().unwrap() = settings;
+}
+
+// outcome of one comparison of the websocket book against the rest one
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AuditResult {
+    // unix millis of the rest snapshot
+    pub timestamp: u128,
+    // age of the websocket book at that time
+    pub book_age_ms: u64,
+    // levels off by more than tolerance_bps, or missing on one side
+    pub mismatched_levels: usize,
+    // largest gap between the prices of the same level
+    pub max_deviation_bps: f64,
+    pub resync: bool,
+}
+
+impl AuditResult {
+    pub fn matched(&self) -> bool {
+        self.mismatched_levels == 0
+    }
+}
+
+// prices of a side, best first
+fn prices(levels: &BTreeMap<BigDecimal, BigDecimal>, bid: bool, n: usize) -> Vec<f64> {
+    let price = |(p, _): (&BigDecimal, &BigDecimal)| p.to_f64().unwrap_or(f64::NAN);
+    if bid {
+        levels.iter().rev().take(n).map(price).collect()
+    } else {
+        levels.iter().take(n).map(price).collect()
+    }
+}
+
+// Level by level over the top_n of each side: a price further than
+// tolerance_bps from its rest counterpart, or a level only one book has,
+// is a mismatch. Quantities move too fast to compare.
+pub fn compare(
+    live: &Orderbook,
+    rest: &Orderbook,
+    setting: &AuditSetting,
+    now: u128,
+) -> AuditResult {
+    let mut mismatched_levels = 0;
+    let mut max_deviation_bps = 0f64;
+    for (ours, theirs, bid) in [(&live.bid, &rest.bid, true), (&live.ask, &rest.ask, false)] {
+        let ours = prices(ours, bid, setting.top_n);
+        let theirs = prices(theirs, bid, setting.top_n);
+        for i in 0..ours.len().max(theirs.len()) {
+            match (ours.get(i), theirs.get(i)) {
+                (Some(a), Some(b)) => {
+                    let deviation = (a - b).abs() / b * 1e4;
+                    max_deviation_bps = max_deviation_bps.max(deviation);
+                    if deviation > setting.tolerance_bps {
+                        mismatched_levels += 1;
+                    }
+                }
+                _ => mismatched_levels += 1,
+            }
+        }
+    }
+    AuditResult {
+        timestamp: now,
+        book_age_ms: now.saturating_sub(live.timestamp) as u64,
+        mismatched_levels,
+        max_deviation_bps,
+        resync: setting
+            .resync_bps
+            .is_some_and(|bps| max_deviation_bps > bps),
+    }
+}
+
+// Shared by an executor and its audit task: the latest websocket book one
+// way, a request to resync the other.
+pub struct Auditor {
+    exchange: String,
+    // the pair compared, the other ones of the connection are left out
+    pair: String,
+    setting: AuditSetting,
+    sides: Sides,
+    latest: Mutex<Option<Arc<Orderbook>>>,
+    resync: AtomicBool,
+}
+
+impl Auditor {
+    // a book without a pair is of the audited one
+    pub fn observe(&self, book: Arc<Orderbook>) {
+        if book.pair.as_ref().is_some_and(|pair| *pair != self.pair) {
+            return;
+        }
+        *self.latest.lock().unwrap() = Some(book);
+    }
+
+    // true once after an audit asked for a resync
+    pub fn take_resync(&self) -> bool {
+        self.resync.swap(false, Ordering::Relaxed)
+    }
+
+    // None before the first websocket book
+    pub fn check(&self, mut rest: Orderbook, now: u128) -> Option<AuditResult> {
+        let live = self.latest.lock().unwrap().clone()?;
+        rest.keep(self.sides);
+        let result = compare(&live, &rest, &self.setting, now);
+        if !result.matched() {
+            let tags = [("exchange", self.exchange.as_str())];
+            statsd::count("audit.mismatches", 1, &tags);
+            warn!(
+                "audit {}: {} levels off the rest book, up to {:.2}bps",
+                self.exchange, result.mismatched_levels, result.max_deviation_bps
+            );
+        }
+        if result.resync {
+            self.resync.store(true, Ordering::Relaxed);
+        }
+        status::audited(&self.exchange, result.clone());
+        Some(result)
+    }
+}
+
+async fn run(auditor: Arc<Auditor>) {
+    let Ok(api) = apitree::rest(&auditor.exchange) else {
+        return;
+    };
+    let mut ticker = interval(Duration::from_secs(auditor.setting.interval_secs));
+    loop {
+        ticker.tick().await;
+        match (api.orderbook)(auditor.pair.clone()).await {
+            Ok(rest) => {
+                auditor.check(rest, get_unixtime());
+            }
+            Err(e) => warn!("audit {}: {}", auditor.exchange, e),
+        }
+    }
+}
+
+// Spawns the audit task of an exchange, when configured. On the runtime of
+// the caller, the executor of the exchange.
+pub fn start(exchange: &str, pair: &str, sides: Sides) -> Option<Arc<Auditor>> {
+    let setting = SETTINGS.lock().unwrap().get(exchange).cloned()?;
+    info!(
+        "audit of {} against its rest book every {}s",
+        exchange, setting.interval_secs
+    );
+    let auditor = Arc::new(Auditor {
+        exchange: exchange.to_string(),
+        pair: pair.to_string(),
+        setting,
+        sides,
+        latest: Mutex::new(None),
+        resync: AtomicBool::new(false),
+    });
+    actix::spawn(run(auditor.clone()));
+    Some(auditor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Side;
+    use std::str::FromStr;
+
+    fn book(bids: &[&str], asks: &[&str], timestamp: u128) -> Orderbook {
+        let mut ob = Orderbook::new("audit_test");
+        for price in bids {
+            ob.insert(Side::Bid, BigDecimal::from_str(price).unwrap(), 1.into());
+        }
+        for price in asks {
+            ob.insert(Side::Ask, BigDecimal::from_str(price).unwrap(), 1.into());
+        }
+        ob.timestamp = timestamp;
+        ob
+    }
+
+    fn new_auditor(resync_bps: Option<f64>) -> Auditor {
+        Auditor {
+            exchange: "audit_test".to_string(),
+            pair: "BTC-AUD".to_string(),
+            setting: AuditSetting {
+                interval_secs: 60,
+                tolerance_bps: 1.,
+                top_n: 3,
+                resync_bps,
+            },
+            sides: Sides::Both,
+            latest: Mutex::new(None),
+            resync: AtomicBool::new(false),
+        }
+    }
+
+    #[test]
+    fn test_audit() {
+        let auditor = new_auditor(Some(200.));
+        let rest = || book(&["100", "99", "98", "97"], &["101", "102", "103"], 2000);
+        // nothing to compare yet
+        assert!(auditor.check(rest(), 2000).is_none());
+
+        // the same top 3, a deeper level differs
+        auditor.observe(Arc::new(book(
+            &["100", "99", "98", "90"],
+            &["101", "102", "103"],
+            1500,
+        )));
+        let result = auditor.check(rest(), 2000).unwrap();
+        assert!(result.matched());
+        assert_eq!(result.book_age_ms, 500);
+        assert!(!auditor.take_resync());
+
+        // within the tolerance: 100.005 is 0.5bps off
+        auditor.observe(Arc::new(book(
+            &["100.005", "99", "98"],
+            &["101", "102", "103"],
+            1500,
+        )));
+        assert!(auditor.check(rest(), 2000).unwrap().matched());
+
+        // drifted: a stale level and a missing one
+        auditor.observe(Arc::new(book(
+            &["100", "99.5", "99"],
+            &["101", "102"],
+            1500,
+        )));
+        let result = auditor.check(rest(), 2000).unwrap();
+        assert!(!result.matched());
+        // 99.5 vs 99, 99 vs 98, and the third ask
+        assert_eq!(result.mismatched_levels, 3);
+        assert!((result.max_deviation_bps - 1e4 / 98.).abs() < 1e-6);
+        assert!(!result.resync);
+        assert_eq!(status::snapshot()["audit_test"].audit, Some(result));
+
+        // past resync_bps: the executor is asked once to reconnect
+        auditor.observe(Arc::new(book(
+            &["95", "94", "93"],
+            &["101", "102", "103"],
+            1500,
+        )));
+        assert!(auditor.check(rest(), 2000).unwrap().resync);
+        assert!(auditor.take_resync());
+        assert!(!auditor.take_resync());
+
+        // without resync_bps, never
+        let auditor = new_auditor(None);
+        auditor.observe(Arc::new(book(&["50"], &["150"], 1500)));
+        let result = auditor.check(rest(), 2000).unwrap();
+        assert!(!result.matched() && !result.resync);
+        assert!(!auditor.take_resync());
+    }
+
+    #[test]
+    fn test_audited_pair() {
+        let mut auditor = new_auditor(Some(200.));
+        // its own status, test_audit checks the one of audit_test
+        auditor.exchange = "audit_pair_test".to_string();
+        let rest = || book(&["100", "99", "98"], &["101", "102", "103"], 2000);
+        let mut live = book(&["100", "99", "98"], &["101", "102", "103"], 1500);
+        live.set_pair("BTC-AUD");
+        auditor.observe(Arc::new(live));
+        // another pair of the connection, far off the rest book
+        let mut other = book(&["50", "49"], &["51", "52"], 1600);
+        other.set_pair("ETH-AUD");
+        auditor.observe(Arc::new(other));
+        let result = auditor.check(rest(), 2000).unwrap();
+        assert!(result.matched());
+        assert_eq!(result.book_age_ms, 500);
+        assert!(!auditor.take_resync());
+    }
+}
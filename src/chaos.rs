@@ -0,0 +1,234 @@
+use crate::config::{ChaosRule, Fault};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+// faults are only injected with this set to 1, so a config carried over
+// to production stays harmless
+const ENV: &str = "ARB_MONITOR_CHAOS";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// exchange -> configured rules
+static RULES: Lazy<Mutex<HashMap<String, Vec<ChaosRule>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// exchange -> faults asked for through POST /admin/chaos, not fired yet
+static TRIGGERED: Lazy<Mutex<HashMap<String, Vec<Fault>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// arm the rules, when the environment allows it
+pub fn init(rules: HashMap<String, Vec<ChaosRule>>) {
+    if std::env::var(ENV).is_ok_and(|v| v == "1") {
+        warn!("chaos enabled, faults are injected into the exchange feeds");
+        *RULES.lock().unwrap() = rules;
+        ENABLED.store(true, Ordering::Relaxed);
+    } else if !rules.is_empty() {
+        warn!("chaos is configured but {} is not 1, ignored", ENV);
+    }
+}
+
+// fire the fault on the next frame of the exchange. false when disabled
+pub fn trigger(exchange: &str, fault: Fault) -> bool {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return false;
+    }
+    info!("chaos: {:?} on {} requested", fault, exchange);
+    TRIGGERED
+        .lock()
+        .unwrap()
+        .entry(exchange.to_string())
+        .or_default()
+        .push(fault);
+    true
+}
+
+struct Armed {
+    rule: ChaosRule,
+    // last time a scheduled rule fired
+    fired: Instant,
+}
+
+// Sits between the frame stream of an exchange and its parser. The frames
+// it passes on are the only ones the rest of the code sees.
+pub struct Injector {
+    exchange: String,
+    rules: Vec<Armed>,
+    frozen_until: Option<Instant>,
+    // duplicated frame, handed over before the next one is read
+    replay: Option<String>,
+}
+
+impl Injector {
+    pub fn new(exchange: &str) -> Injector {
+        let rules = if ENABLED.load(Ordering::Relaxed) {
+            RULES
+                .lock()
+                .unwrap()
+                .get(exchange)
+                .cloned()
+                .unwrap_or_default()
+        } else {
+            vec![]
+        };
+        Injector::with_rules(exchange, rules, Instant::now())
+    }
+
+    fn with_rules(exchange: &str, rules: Vec<ChaosRule>, now: Instant) -> Injector {
+        Injector {
+            exchange: exchange.to_string(),
+            rules: rules
+                .into_iter()
+                .map(|rule| Armed { rule, fired: now })
+                .collect(),
+            frozen_until: None,
+            replay: None,
+        }
+    }
+
+    pub fn replay(&mut self) -> Option<String> {
+        self.replay.take()
+    }
+
+    fn due(&mut self, now: Instant) -> Vec<Fault> {
+        let mut faults = TRIGGERED
+            .lock()
+            .unwrap()
+            .remove(&self.exchange)
+            .unwrap_or_default();
+        for armed in self.rules.iter_mut() {
+            let fire = match (armed.rule.probability, armed.rule.every_secs) {
+                (Some(p), _) => fastrand::f64() < p,
+                (None, Some(every)) => {
+                    now.saturating_duration_since(armed.fired) >= Duration::from_secs(every)
+                }
+                (None, None) => false,
+            };
+            if fire {
+                armed.fired = now;
+                faults.push(armed.rule.fault.clone());
+            }
+        }
+        faults
+    }
+
+    // The frame to parse and how long to hold it, None when swallowed.
+    // Err when the connection is dropped.
+    fn apply(&mut self, mut raw: String, now: Instant) -> Result<Option<(String, Duration)>> {
+        if self.frozen_until.is_some_and(|t| now < t) {
+            return Ok(None);
+        }
+        self.frozen_until = None;
+        let mut delay = Duration::ZERO;
+        for fault in self.due(now) {
+            info!("chaos: {:?} on {}", fault, self.exchange);
+            match fault {
+                Fault::Drop => return Err(anyhow!("chaos: dropped {}", self.exchange)),
+                Fault::Delay { delay_ms } => delay += Duration::from_millis(delay_ms),
+                Fault::Duplicate => self.replay = Some(raw.clone()),
+                Fault::Corrupt => {
+                    let mut half = raw.len() / 2;
+                    while !raw.is_char_boundary(half) {
+                        half -= 1;
+                    }
+                    raw.truncate(half);
+                }
+                Fault::Freeze { freeze_secs } => {
+                    self.frozen_until = Some(now + Duration::from_secs(freeze_secs));
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some((raw, delay)))
+    }
+
+    pub async fn frame(&mut self, raw: String) -> Result<Option<String>> {
+        if self.rules.is_empty() && !ENABLED.load(Ordering::Relaxed) {
+            return Ok(Some(raw));
+        }
+        match self.apply(raw, Instant::now())? {
+            Some((raw, delay)) => {
+                if !delay.is_zero() {
+                    sleep(delay).await;
+                }
+                Ok(Some(raw))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apitree;
+    use crate::apitree::wsapi::ParseOutcome;
+
+    const FRAME: &str = r#"{"lastUpdateId":1,"bids":[["100.0","1.0"]],"asks":[["101.0","2.0"]]}"#;
+
+    fn every(fault: Fault, every_secs: u64) -> ChaosRule {
+        ChaosRule {
+            fault,
+            probability: None,
+            every_secs: Some(every_secs),
+        }
+    }
+
+    fn parse(raw: &str) -> ParseOutcome {
+        apitree::ws("binance").unwrap().parse_book(None, raw)
+    }
+
+    #[test]
+    fn test_faults() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(matches!(parse(FRAME), ParseOutcome::Book(_)));
+
+        // a dropped connection surfaces as an error, the executor reconnects
+        let mut inj = Injector::with_rules("chaos_drop", vec![every(Fault::Drop, 10)], start);
+        assert!(inj.apply(FRAME.to_string(), at(5)).unwrap().is_some());
+        assert!(inj.apply(FRAME.to_string(), at(10)).is_err());
+        assert!(inj.apply(FRAME.to_string(), at(11)).unwrap().is_some());
+
+        // a delayed frame is held, not changed
+        let delay = Fault::Delay { delay_ms: 250 };
+        let mut inj = Injector::with_rules("chaos_delay", vec![every(delay, 1)], start);
+        let (raw, held) = inj.apply(FRAME.to_string(), at(1)).unwrap().unwrap();
+        assert_eq!((raw.as_str(), held), (FRAME, Duration::from_millis(250)));
+
+        // a duplicate is handed over once more
+        let mut inj = Injector::with_rules("chaos_dup", vec![every(Fault::Duplicate, 1)], start);
+        assert!(inj.apply(FRAME.to_string(), at(1)).unwrap().is_some());
+        assert_eq!(inj.replay().as_deref(), Some(FRAME));
+        assert_eq!(inj.replay(), None);
+
+        // a corrupted frame is a protocol error, the connection is kept
+        let mut inj = Injector::with_rules("chaos_corrupt", vec![every(Fault::Corrupt, 1)], start);
+        let (raw, _) = inj.apply(FRAME.to_string(), at(1)).unwrap().unwrap();
+        assert!(matches!(parse(&raw), ParseOutcome::ProtocolError(_)));
+
+        // a frozen feed goes quiet, then resumes
+        let freeze = Fault::Freeze { freeze_secs: 30 };
+        let mut inj = Injector::with_rules("chaos_freeze", vec![every(freeze, 100)], start);
+        assert!(inj.apply(FRAME.to_string(), at(100)).unwrap().is_none());
+        assert!(inj.apply(FRAME.to_string(), at(129)).unwrap().is_none());
+        assert!(inj.apply(FRAME.to_string(), at(130)).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_trigger() {
+        let mut inj = Injector::with_rules("chaos_trigger", vec![], Instant::now());
+        assert!(!trigger("chaos_trigger", Fault::Drop));
+        ENABLED.store(true, Ordering::Relaxed);
+        assert!(trigger("chaos_trigger", Fault::Drop));
+        ENABLED.store(false, Ordering::Relaxed);
+        // fired on the next frame only
+        assert!(inj.apply(FRAME.to_string(), Instant::now()).is_err());
+        assert!(inj
+            .apply(FRAME.to_string(), Instant::now())
+            .unwrap()
+            .is_some());
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::{anyhow, Result};
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use clap::{Parser, Subcommand};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Eq)]
 pub enum LogLevel {
@@ -28,10 +31,96 @@ fn default_true() -> bool {
     true
 }
 
+// secrets come out as "***" wherever the config is serialized, GET /config
+// and --print-config
+fn redact<T, S: Serializer>(_: &T, s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_str("***")
+}
+
+fn redact_some<T, S: Serializer>(v: &Option<T>, s: S) -> std::result::Result<S::Ok, S::Error> {
+    match v {
+        Some(_) => s.serialize_str("***"),
+        None => s.serialize_none(),
+    }
+}
+
+fn default_degraded_factor() -> f64 {
+    5.
+}
+
+fn default_deep_snapshot_max_depth() -> usize {
+    100
+}
+
+fn default_rest_timeout_secs() -> u64 {
+    10
+}
+
+fn default_dead_letter_size() -> usize {
+    100
+}
+
 fn default_three() -> u64 {
     3u64
 }
 
+/// Sides of an exchange book that reach the aggregate.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Sides {
+    #[default]
+    Both,
+    BidOnly,
+    AskOnly,
+}
+
+impl Sides {
+    // the same sides of the reversed pair
+    pub fn flipped(self) -> Sides {
+        match self {
+            Sides::Both => Sides::Both,
+            Sides::BidOnly => Sides::AskOnly,
+            Sides::AskOnly => Sides::BidOnly,
+        }
+    }
+}
+
+/// Whether the terms of an exchange allow redistributing its data, see
+/// the `redistributable_only` outputs.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Redistribution {
+    #[default]
+    Allow,
+    Deny,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    // half away from zero
+    #[default]
+    Round,
+    Truncate,
+}
+
+fn default_dp() -> i64 {
+    8
+}
+
+// decimals of the prices and amounts in the published summaries. The
+// books and the aggregation keep every digit.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct OutputPrecision {
+    #[serde(default = "default_dp")]
+    pub price_dp: i64,
+    #[serde(default = "default_dp")]
+    pub amount_dp: i64,
+    #[serde(default)]
+    pub mode: Rounding,
+}
+
+/// A pair to subscribe to on an exchange, an entry of `exchange_pair_map`.
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ExchangeSetting {
     pub pair: String,
@@ -39,56 +128,1888 @@ pub struct ExchangeSetting {
     pub ws_api: bool,
     #[serde(default = "default_three")]
     pub wait_secs: u64,
+    // also subscribe to the trade channel, for the traded volume
+    #[serde(default)]
+    pub trades: bool,
+    // the other side is dropped right after parsing. last_price and volume
+    // are kept.
+    #[serde(default)]
+    pub sides: Sides,
+    // subscribe at this depth / update interval instead of the venue
+    // default. The same pair can be listed once per depth, the books are
+    // merged into one.
+    #[serde(default)]
+    pub depth: Option<u32>,
+    #[serde(default)]
+    pub interval_ms: Option<u64>,
+    // send a ws ping this often and time the pong. Stops after a few
+    // pings without an answer.
+    #[serde(default)]
+    pub ping_probe_secs: Option<u64>,
+    // levels per side kept of this pair's book, and subscribed at where
+    // the venue takes a depth. None => 10.
+    #[serde(default)]
+    pub level: Option<u32>,
+    // websocket url to dial instead of the venue's, ex: a mock venue or a
+    // local relay. Read from the first pair setting.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    // deny => left out of the redistributable_only outputs. The exchange
+    // is denied when any of its pair settings is.
+    #[serde(default)]
+    pub redistribution: Redistribution,
+    // the venue quotes the pair the other way round, ex: AUD/BTC for
+    // BTC-AUD: its books are inverted before the aggregate. Set on its own
+    // when the symbol reads as the logical pair reversed.
+    #[serde(default)]
+    pub invert: bool,
+    // significant digits of the inverted prices
+    #[serde(default = "default_invert_precision")]
+    pub invert_precision: u64,
+    // api key of a venue that wants one for its market data too, ex:
+    // luno. Read from the first pair setting.
+    #[serde(default)]
+    pub credentials: Option<Credentials>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct Credentials {
+    pub key_id: String,
+    #[serde(serialize_with = "redact")]
+    pub secret: String,
+}
+
+// redacted like the serialized config, the pair settings are logged
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("key_id", &self.key_id)
+            .field("secret", &"***")
+            .finish()
+    }
+}
+
+fn default_invert_precision() -> u64 {
+    12
+}
+
+// base and quote of a logical pair, lowercase, ex: BTC-AUD, btc/aud
+fn base_quote(pair: &str) -> Option<(String, String)> {
+    let (base, quote) = pair.split_once(['-', '/', '_'])?;
+    Some((normalize_symbol(base), normalize_symbol(quote)))
+}
+
+// lowercase without separators, xbt as btc
+fn normalize_symbol(symbol: &str) -> String {
+    symbol
+        .to_lowercase()
+        .replace(['-', '/', '_', ':'], "")
+        .replace("xbt", "btc")
+}
+
+// Some(true) when `symbol` reads as `logical` reversed, Some(false) as
+// `logical` itself, None when it reads as neither
+pub fn reversed(logical: &str, symbol: &str) -> Option<bool> {
+    let (base, quote) = base_quote(logical)?;
+    let symbol = normalize_symbol(symbol);
+    if symbol == format!("{}{}", base, quote) {
+        Some(false)
+    } else if symbol == format!("{}{}", quote, base) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+impl ExchangeSetting {
+    /// `pair` as the venue names it, ex: btcusdt on binance, with the
+    /// defaults of the config file.
+    pub fn new(pair: &str) -> ExchangeSetting {
+        ExchangeSetting {
+            pair: pair.to_string(),
+            ws_api: default_true(),
+            wait_secs: default_three(),
+            trades: false,
+            sides: Sides::default(),
+            depth: None,
+            interval_ms: None,
+            ping_probe_secs: None,
+            level: None,
+            endpoint: None,
+            redistribution: Redistribution::Allow,
+            invert: false,
+            invert_precision: default_invert_precision(),
+            credentials: None,
+        }
+    }
+}
+
+fn default_cooldown_secs() -> u64 {
+    60u64
+}
+
+fn default_max_retries() -> u32 {
+    3u32
+}
+
+// conditions that trigger an alert. Tagged by `type` in the config file.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertRuleKind {
+    // best bid on one exchange exceeds best ask on another one
+    ArbOpportunity {
+        min_profit_bps: f64,
+        #[serde(default)]
+        min_quantity: f64,
+        // match on the profit / quantity of this detector size bucket instead
+        // of the top of book. Must be one of detector.sizes.
+        #[serde(default)]
+        size: Option<f64>,
+        // opportunities scored below this are suppressed, in [0, 1]
+        #[serde(default)]
+        min_confidence: f64,
+    },
+    // exchange connection has been down for more than after_secs
+    ExchangeDown {
+        after_secs: u64,
+    },
+    // best bid >= best ask within the same exchange
+    CrossedBook {
+        exchange: String,
+    },
+    // no orderbook update from an exchange for more than after_secs
+    StaleFeed {
+        after_secs: u64,
+    },
+    // an exchange announced maintenance on its feed at least after_secs ago
+    Maintenance {
+        #[serde(default)]
+        after_secs: u64,
+    },
+    // |perp/spot basis| of a derivatives exchange reaches min_abs_bps
+    Basis {
+        min_abs_bps: f64,
+    },
+    // |funding rate| of a derivatives exchange reaches min_abs_rate, ex: 0.0005
+    Funding {
+        min_abs_rate: f64,
+    },
+    // |spot mid vs an index of index_sources| reaches min_abs_bps
+    IndexDeviation {
+        min_abs_bps: f64,
+    },
+    // best bid of `sell` converted into the quote currency of `buy` with a
+    // bridge rate, above best ask of `buy`. ex: buy "binance:BTC-USDT",
+    // sell "btcmarkets:BTC-AUD", bridge "rate:AUDUSD"
+    BridgedOpportunity {
+        buy: String,
+        sell: String,
+        bridge: String,
+        // cost of moving through the bridge, taken off the profit
+        #[serde(default)]
+        bridge_cost_bps: f64,
+        min_profit_bps: f64,
+        #[serde(default)]
+        min_quantity: f64,
+    },
+}
+
+// source of a bridge rate: buy quote units per sell quote unit.
+// Tagged by `type` in the config file.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RateSource {
+    Static {
+        rate: f64,
+    },
+    // number (or numeric string) at the json pointer of a GET response
+    Rest {
+        url: String,
+        pointer: String,
+        #[serde(default = "default_refresh_secs")]
+        refresh_secs: u64,
+        // use 1 / rate, for sources quoted the other way around
+        #[serde(default)]
+        invert: bool,
+    },
+    // mid of a monitored book. The exchange is left out of the aggregated
+    // ladder, so it should be dedicated to the bridge pair.
+    Book {
+        exchange: String,
+        #[serde(default)]
+        invert: bool,
+    },
+}
+
+fn default_refresh_secs() -> u64 {
+    60u64
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AlertRule {
+    #[serde(flatten)]
+    pub kind: AlertRuleKind,
+    // the same rule won't fire again on the same subject within this period
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct WebhookSetting {
+    // chat webhooks carry their token in the url
+    #[serde(serialize_with = "redact")]
+    pub url: String,
+    // when set, the body is signed with HMAC-SHA256 in the X-Signature header
+    #[serde(serialize_with = "redact_some")]
+    pub secret: Option<String>,
+    // number of retries after the first failed delivery
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_telegram_api() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+fn default_digest_threshold() -> usize {
+    3usize
+}
+
+fn default_digest_window_ms() -> u64 {
+    2000u64
+}
+
+fn default_min_interval_ms() -> u64 {
+    1000u64
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TelegramSetting {
+    #[serde(serialize_with = "redact")]
+    pub bot_token: String,
+    pub chat_id: String,
+    // Bot API base url, only overridden in tests
+    #[serde(default = "default_telegram_api")]
+    pub api_url: String,
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    // alerts are buffered for digest_window_ms. More than digest_threshold
+    // alerts in one window are collapsed into a single digest message.
+    #[serde(default = "default_digest_threshold")]
+    pub digest_threshold: usize,
+    #[serde(default = "default_digest_window_ms")]
+    pub digest_window_ms: u64,
+    // minimum gap between two messages to the chat
+    #[serde(default = "default_min_interval_ms")]
+    pub min_interval_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AlertConfig {
+    pub rules: Vec<AlertRule>,
+    pub webhook: Option<WebhookSetting>,
+    pub telegram: Option<TelegramSetting>,
+}
+
+// wire format of the published summaries
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ZmqSetting {
+    // ex: tcp://127.0.0.1:5556 or ipc:///tmp/arb.sock
+    pub bind: String,
+    // prepended to the pair name to form the topic frame
+    #[serde(default)]
+    pub topic_prefix: String,
+    #[serde(default)]
+    pub encoding: Encoding,
+    // leave out the exchanges denying redistribution
+    #[serde(default)]
+    pub redistributable_only: bool,
+}
+
+fn default_nats_subject() -> String {
+    "md.arb.{pair}".to_string()
+}
+
+fn default_stream_name() -> String {
+    "ARB".to_string()
+}
+
+fn default_max_age_secs() -> u64 {
+    3600u64
+}
+
+fn default_max_messages() -> i64 {
+    -1i64
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct JetStreamSetting {
+    // created with the retention below when missing
+    #[serde(default = "default_stream_name")]
+    pub stream: String,
+    #[serde(default = "default_max_age_secs")]
+    pub max_age_secs: u64,
+    // -1 => unlimited
+    #[serde(default = "default_max_messages")]
+    pub max_messages: i64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct NatsSetting {
+    pub servers: Vec<String>,
+    pub credentials_file: Option<String>,
+    pub user: Option<String>,
+    #[serde(serialize_with = "redact_some")]
+    pub password: Option<String>,
+    // {pair} is replaced by the pair name
+    #[serde(default = "default_nats_subject")]
+    pub subject: String,
+    #[serde(default)]
+    pub encoding: Encoding,
+    // publish through JetStream for persistence. None => core nats.
+    pub jetstream: Option<JetStreamSetting>,
+    // leave out the exchanges denying redistribution
+    #[serde(default)]
+    pub redistributable_only: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+fn default_sample_interval_secs() -> u64 {
+    1u64
+}
+
+fn default_max_depth() -> usize {
+    20usize
+}
+
+fn default_flush_interval_secs() -> u64 {
+    60u64
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ArchiveSetting {
+    // one file per pair and hour is created inside
+    pub directory: String,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    // the latest summary is sampled at this interval
+    #[serde(default = "default_sample_interval_secs")]
+    pub sample_interval_secs: u64,
+    // levels kept per side
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+    // parquet only: a row group is written to disk at this interval
+    #[serde(default = "default_flush_interval_secs")]
+    pub flush_interval_secs: u64,
+}
+
+fn default_statsd_prefix() -> String {
+    "arb_monitor".to_string()
+}
+
+fn default_sample_rate() -> f64 {
+    1.0f64
+}
+
+fn default_gauge_interval_ms() -> u64 {
+    1000u64
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct StatsdSetting {
+    // ex: 127.0.0.1:8125
+    pub address: String,
+    // prepended to every metric name, ex: arb_monitor.reconnects
+    #[serde(default = "default_statsd_prefix")]
+    pub prefix: String,
+    // DogStatsD tags added to every metric, ex: env:prod
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // sampling rate of the high-frequency counters and timers, in (0, 1]
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: f64,
+    // how often the gauges are reported
+    #[serde(default = "default_gauge_interval_ms")]
+    pub gauge_interval_ms: u64,
+}
+
+fn default_state_ttl_secs() -> u64 {
+    3600u64
+}
+
+fn default_max_trades() -> usize {
+    10000usize
+}
+
+fn default_max_books() -> usize {
+    1000usize
+}
+
+fn default_ingest_capacity() -> usize {
+    10000usize
+}
+
+fn default_report_interval_secs() -> u64 {
+    60u64
+}
+
+// bounds on the queues and the memory, and the self report watching them
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct GuardrailSetting {
+    // frames queued for ingest, the oldest is dropped beyond it
+    #[serde(default = "default_ingest_capacity")]
+    pub ingest_capacity: usize,
+    // how often the queue depths, cache sizes and memory are reported
+    #[serde(default = "default_report_interval_secs")]
+    pub report_interval_secs: u64,
+    // resident memory logged as an error above this. None => no limit.
+    pub memory_soft_limit_mb: Option<u64>,
+    // above memory_soft_limit_mb, publish at half the rate
+    #[serde(default)]
+    pub shed_load: bool,
+}
+
+impl Default for GuardrailSetting {
+    fn default() -> Self {
+        GuardrailSetting {
+            ingest_capacity: default_ingest_capacity(),
+            report_interval_secs: default_report_interval_secs(),
+            memory_soft_limit_mb: None,
+            shed_load: false,
+        }
+    }
+}
+
+// retention of the per-exchange parser state
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ParserStateSetting {
+    // books not updated for this long are dropped when their map is accessed
+    #[serde(default = "default_state_ttl_secs")]
+    pub ttl_secs: u64,
+    // trades kept for the coinspot 24h volume, on top of the 24h age limit
+    #[serde(default = "default_max_trades")]
+    pub max_trades: usize,
+    // books per parser state map, the least recently updated are dropped
+    // beyond it
+    #[serde(default = "default_max_books")]
+    pub max_books: usize,
+}
+
+impl Default for ParserStateSetting {
+    fn default() -> Self {
+        ParserStateSetting {
+            ttl_secs: default_state_ttl_secs(),
+            max_trades: default_max_trades(),
+            max_books: default_max_books(),
+        }
+    }
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_cool_off_secs() -> u64 {
+    5u64
+}
+
+fn default_max_cool_off_secs() -> u64 {
+    300u64
+}
+
+// circuit breaker of the rest pollers, see exchange::breaker
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct BreakerSetting {
+    // failed polls in a row that open the circuit, a 429 opens it at once
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    // polls skipped for this long, doubled with every open in a row
+    #[serde(default = "default_cool_off_secs")]
+    pub cool_off_secs: u64,
+    #[serde(default = "default_max_cool_off_secs")]
+    pub max_cool_off_secs: u64,
+}
+
+impl Default for BreakerSetting {
+    fn default() -> Self {
+        BreakerSetting {
+            failure_threshold: default_failure_threshold(),
+            cool_off_secs: default_cool_off_secs(),
+            max_cool_off_secs: default_max_cool_off_secs(),
+        }
+    }
+}
+
+/// What happens to the book of a venue that announced maintenance on its
+/// feed, until it says it is back online.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenancePolicy {
+    // left out of the aggregate, its frozen book isn't served as live
+    #[default]
+    Evict,
+    // kept in the aggregate, only listed in stats.maintenance
+    FlagOnly,
+}
+
+// quorum of the warm-up gate: a number of exchanges, or a percentage of
+// the configured ones, ex: 3 or "60%"
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum MinExchanges {
+    Count(usize),
+    Percent(String),
+}
+
+impl MinExchanges {
+    // exchanges needed out of `configured`, at least one
+    pub fn of(&self, configured: usize) -> Result<usize> {
+        let needed = match self {
+            MinExchanges::Count(count) => *count,
+            MinExchanges::Percent(s) => {
+                let percent: f64 = s
+                    .strip_suffix('%')
+                    .and_then(|p| p.trim().parse().ok())
+                    .filter(|p| (0. ..=100.).contains(p))
+                    .ok_or_else(|| anyhow!("{} is not a percentage", s))?;
+                (percent * configured as f64 / 100.).ceil() as usize
+            }
+        };
+        Ok(needed.clamp(1, configured.max(1)))
+    }
+}
+
+// hold the summaries back at startup until enough exchanges reported
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct WarmupSetting {
+    pub min_exchanges: MinExchanges,
+    // publish anyway once this long after startup
+    #[serde(default = "default_warmup_timeout_secs")]
+    pub warmup_timeout_secs: u64,
+}
+
+fn default_warmup_timeout_secs() -> u64 {
+    10u64
+}
+
+fn default_snapshot_max_age_secs() -> u64 {
+    300u64
+}
+
+fn default_snapshot_write_interval_secs() -> u64 {
+    5u64
+}
+
+fn default_max_offset_ms() -> u64 {
+    1000u64
+}
+
+fn default_time_sync_interval_secs() -> u64 {
+    3600u64
+}
+
+fn default_index_poll_secs() -> u64 {
+    10
+}
+
+// an index / reference price a venue publishes, see apitree::index
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct IndexSource {
+    // okx, kraken or coinbase_international
+    pub exchange: String,
+    // the index as the venue names it, ex: BTC-USD on okx
+    pub symbol: String,
+    #[serde(default = "default_index_poll_secs")]
+    pub poll_secs: u64,
+}
+
+fn default_symbol_meta_ttl_secs() -> u64 {
+    86400u64
+}
+
+fn default_symbol_meta_retry_secs() -> u64 {
+    60u64
+}
+
+// tick / lot size of the pairs, see apitree::symbol_meta
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SymbolMetaSetting {
+    // the metadata is kept there across restarts. None => memory only.
+    pub cache_path: Option<String>,
+    // an entry older than this is fetched again
+    #[serde(default = "default_symbol_meta_ttl_secs")]
+    pub ttl_secs: u64,
+    // the stale or failed entries are retried this often
+    #[serde(default = "default_symbol_meta_retry_secs")]
+    pub retry_secs: u64,
+    // exchange -> what a pair without fetched metadata gets
+    #[serde(default)]
+    pub defaults: HashMap<String, MetaDefaults>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MetaDefaults {
+    pub tick_size: f64,
+    pub lot_size: f64,
+    pub min_size: Option<f64>,
+    pub min_notional: Option<f64>,
+}
+
+// clock offset probes against the venue server time endpoints
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TimeSyncSetting {
+    // a larger |offset| is flagged on /health
+    #[serde(default = "default_max_offset_ms")]
+    pub max_offset_ms: u64,
+    #[serde(default = "default_time_sync_interval_secs")]
+    pub interval_secs: u64,
+}
+
+const MIN_AUDIT_INTERVAL_SECS: u64 = 10;
+
+fn default_audit_interval_secs() -> u64 {
+    300u64
+}
+
+fn default_tolerance_bps() -> f64 {
+    1.
+}
+
+fn default_top_n() -> usize {
+    5
+}
+
+// the rest orderbook polled to check the websocket book against
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AuditSetting {
+    #[serde(default = "default_audit_interval_secs")]
+    pub interval_secs: u64,
+    // a level further off its rest counterpart is a mismatch
+    #[serde(default = "default_tolerance_bps")]
+    pub tolerance_bps: f64,
+    // levels compared per side
+    #[serde(default = "default_top_n")]
+    pub top_n: usize,
+    // reconnect when a level is this far off. None => never.
+    #[serde(default)]
+    pub resync_bps: Option<f64>,
+}
+
+// a fault injected into the frames of an exchange. Tagged by `fault`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "fault", rename_all = "snake_case")]
+pub enum Fault {
+    // close the connection, as the venue would
+    Drop,
+    // hold the frame back
+    Delay { delay_ms: u64 },
+    // hand the frame over twice
+    Duplicate,
+    // cut the json of the frame in half
+    Corrupt,
+    // swallow every frame for freeze_secs
+    Freeze { freeze_secs: u64 },
+}
+
+// when a fault fires: with a chance per frame, or once every every_secs
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ChaosRule {
+    #[serde(flatten)]
+    pub fault: Fault,
+    // in (0, 1]
+    #[serde(default)]
+    pub probability: Option<f64>,
+    #[serde(default)]
+    pub every_secs: Option<u64>,
+}
+
+// the latest summary kept on disk, served until the first live one
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PersistSetting {
+    pub snapshot_path: String,
+    // an older file is not restored
+    #[serde(default = "default_snapshot_max_age_secs")]
+    pub max_age_secs: u64,
+    // the file is rewritten at most this often
+    #[serde(default = "default_snapshot_write_interval_secs")]
+    pub write_interval_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct VenueSetting {
+    // starting balances
+    pub base: f64,
+    pub quote: f64,
+    #[serde(default)]
+    pub taker_fee_bps: f64,
+}
+
+fn default_log_interval_secs() -> u64 {
+    60u64
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SimulationSetting {
+    // opportunities below this are not traded
+    #[serde(default)]
+    pub min_profit_bps: f64,
+    // quote spent on the buy leg of a single trade, at most
+    pub max_notional: f64,
+    // trades are priced against the first summary at least latency_ms after detection
+    #[serde(default)]
+    pub latency_ms: u64,
+    #[serde(default = "default_log_interval_secs")]
+    pub log_interval_secs: u64,
+    // exchanges not listed here are never traded
+    pub venues: HashMap<String, VenueSetting>,
+}
+
+fn default_close_grace_ms() -> u64 {
+    500u64
+}
+
+fn default_window_secs() -> u64 {
+    3600u64
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct OpportunitySetting {
+    // an opportunity opens at open_bps and stays open down to close_bps
+    pub open_bps: f64,
+    pub close_bps: f64,
+    // below close_bps (or gone) for this long before it is closed
+    #[serde(default = "default_close_grace_ms")]
+    pub close_grace_ms: u64,
+    // closed opportunities are kept, and the stats computed, over this window
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+}
+
+// moving one asset between venues
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct TransferSetting {
+    // in units of the asset
+    #[serde(default)]
+    pub withdrawal_fee: f64,
+    // indicative time until the funds are usable on the other venue
+    #[serde(default)]
+    pub transfer_mins: f64,
+}
+
+// balances and transfer costs used to turn gross profit into net profit
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PortfolioSetting {
+    // starting balances and taker fees. Opportunities on other venues get no net figure.
+    pub venues: HashMap<String, VenueSetting>,
+    #[serde(default)]
+    pub base: TransferSetting,
+    #[serde(default)]
+    pub quote: TransferSetting,
+    // amortize a rebalancing transfer over the trades left when a balance
+    // runs out within this many trades. 0 => never amortized.
+    #[serde(default)]
+    pub rebalance_trades: u64,
+    // route frequency is observed over this window
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+}
+
+// executable profit of the detected opportunities
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct DetectorSetting {
+    // notional sizes in quote units, ex: [1000, 5000, 25000]. Each crossed
+    // route is priced by walking both books for every size.
+    #[serde(default)]
+    pub sizes: Vec<f64>,
+    // the largest size still at or above this profit is reported
+    #[serde(default)]
+    pub min_profit_bps: f64,
+    #[serde(default)]
+    pub confidence: ConfidenceSetting,
+    // in a volatile regime (see volatility): detect nothing at all
+    #[serde(default)]
+    pub suppress_volatile: bool,
+    // in a volatile regime: drop opportunities under min_profit_bps times this
+    #[serde(default)]
+    pub volatile_multiplier: Option<f64>,
+    // an opportunity is emitted once it existed for this long without a gap
+    #[serde(default)]
+    pub min_persistence_ms: u64,
+    // updates of an emitted opportunity are sent at most this often
+    #[serde(default)]
+    pub debounce_ms: u64,
+    // an emitted opportunity only closes after staying under min_profit_bps
+    // (or absent) for this long
+    #[serde(default)]
+    pub grace_ms: u64,
+}
+
+// rolling realized volatility of the aggregate mid
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct VolatilitySetting {
+    // the regime is classified on the first window
+    #[serde(default = "default_volatility_windows_secs")]
+    pub windows_secs: Vec<u64>,
+    // volatility below quiet_bps is quiet, above volatile_bps volatile
+    pub quiet_bps: f64,
+    pub volatile_bps: f64,
+}
+
+fn default_volatility_windows_secs() -> Vec<u64> {
+    vec![60, 300]
+}
+
+// An aggregate of its own over some of the configured exchange pairs, with
+// its own channel and cached summary. The connections are shared with the
+// main aggregate.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct GroupSetting {
+    // exchange -> pair, each one listed in exchange_pair_map
+    pub exchanges: HashMap<String, String>,
+    // None => the top level detector
+    pub detector: Option<DetectorSetting>,
+    pub consolidated_levels: Option<usize>,
+    pub output_precision: Option<OutputPrecision>,
+}
+
+// which exchange moves first
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct LeadLagSetting {
+    // a mid change of at least this much is a move
+    pub threshold_bps: f64,
+    // a move leads the same direction moves of other exchanges within this window
+    #[serde(default = "default_lead_window_ms")]
+    pub window_ms: u64,
+    // moves kept per exchange, and lead events kept per exchange pair
+    #[serde(default = "default_lead_capacity")]
+    pub capacity: usize,
+}
+
+// share of each exchange in the best prices and the top of book depth
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ContributionSetting {
+    // rolling window of the shares
+    #[serde(default = "default_contribution_window_secs")]
+    pub window_secs: u64,
+    // the window is kept and dropped in buckets this long
+    #[serde(default = "default_contribution_bucket_secs")]
+    pub bucket_secs: u64,
+    // price levels per side the depth share is taken over
+    #[serde(default = "default_contribution_levels")]
+    pub levels: usize,
+    // the books with fewer levels on a side, see ShallowBooks
+    #[serde(default)]
+    pub shallow_books: ShallowBooks,
+}
+
+/// What a depth metric does with a venue whose book has fewer levels than
+/// the metric is taken over: a top-5 book would count for less than it
+/// holds next to the full ones.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ShallowBooks {
+    // counted with the levels it has, the result flagged partial
+    #[default]
+    Annotate,
+    // left out of the metric while its book is shallower
+    Exclude,
+}
+
+fn default_contribution_window_secs() -> u64 {
+    3600u64
+}
+
+fn default_contribution_bucket_secs() -> u64 {
+    60u64
+}
+
+fn default_contribution_levels() -> usize {
+    10usize
+}
+
+fn default_lead_window_ms() -> u64 {
+    1000u64
+}
+
+fn default_lead_capacity() -> usize {
+    256usize
+}
+
+// weights of the opportunity confidence score, see opportunity::confidence
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ConfidenceSetting {
+    // per leg, times the book age over its typical update interval
+    #[serde(default = "default_age_weight")]
+    pub age_weight: f64,
+    // per reconnect of either venue within window_secs
+    #[serde(default = "default_reconnect_weight")]
+    pub reconnect_weight: f64,
+    // per leg whose book was crossed or invalid within window_secs
+    #[serde(default = "default_fault_weight")]
+    pub fault_weight: f64,
+    #[serde(default = "default_confidence_window_secs")]
+    pub window_secs: u64,
+    // floor of the typical update interval
+    #[serde(default = "default_min_update_interval_ms")]
+    pub min_interval_ms: u64,
+}
+
+fn default_age_weight() -> f64 {
+    0.5f64
+}
+
+fn default_reconnect_weight() -> f64 {
+    0.3f64
+}
+
+fn default_fault_weight() -> f64 {
+    1.0f64
+}
+
+fn default_confidence_window_secs() -> u64 {
+    300u64
+}
+
+fn default_min_update_interval_ms() -> u64 {
+    100u64
+}
+
+impl Default for ConfidenceSetting {
+    fn default() -> Self {
+        Self {
+            age_weight: default_age_weight(),
+            reconnect_weight: default_reconnect_weight(),
+            fault_weight: default_fault_weight(),
+            window_secs: default_confidence_window_secs(),
+            min_interval_ms: default_min_update_interval_ms(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct OutputConfig {
+    // stream summaries as newline delimited json to clients of this unix socket
+    pub unix_socket_path: Option<String>,
+    // permission bits of the socket file, ex: 0o660
+    pub unix_socket_mode: Option<u32>,
+}
+
+// routes of the http server, served per listener
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteGroup {
+    // the feed and the read only reports
+    Public,
+    // endpoints that change state, ex: POST /simulation/reset
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TlsSetting {
+    // pem files
+    pub cert_path: String,
+    pub key_path: String,
 }
 
-// This is the real configuration structure.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ListenerSetting {
+    pub addr: String,
+    pub port: u16,
+    // None => plain http
+    pub tls: Option<TlsSetting>,
+    // None => every group
+    pub routes: Option<Vec<RouteGroup>>,
+    // the summaries, bbo ticks and deep snapshots served leave out the
+    // exchanges denying redistribution
+    #[serde(default)]
+    pub redistributable_only: bool,
+}
+
+impl ListenerSetting {
+    pub fn serves(&self, group: RouteGroup) -> bool {
+        self.routes.as_ref().is_none_or(|r| r.contains(&group))
+    }
+
+    // same port, and one of the addresses covers the other
+    fn conflicts(&self, other: &ListenerSetting) -> bool {
+        let wildcard = |addr: &str| addr == "0.0.0.0" || addr == "::";
+        self.port != 0
+            && self.port == other.port
+            && (self.addr == other.addr || wildcard(&self.addr) || wildcard(&other.addr))
+    }
+}
+
+/// The config file, see the README for every setting.
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct InnerConfig {
     // trading pair: btcusdt
-    // exchange: binance, bitstamp, independentreserve
+    // exchange: binance, bitstamp, bitstamp_full, independentreserve
     pub exchange_pair_map: HashMap<String, Vec<ExchangeSetting>>,
+    // logical name of the aggregated pair, ex: BTC-AUD. Used to tag the outputs.
+    // None => the first configured pair.
+    pub pair: Option<String>,
     // client only. server address to connect to.
     pub server_addr: Option<String>,
-    // server only. address on server to bind.
+    // server only. address on server to bind. Conflicts with listeners.
     pub bind_addr: Option<String>,
     // both the client and the server will refer to this server port setting.
+    // The server ignores it when listeners is set.
     pub server_port: u16,
     // output log path. None => the log won't be output to a file.
     pub log_path: Option<String>,
     // output log level. ex: Error, Warning, Info, Debug
     pub log_level: LogLevel,
+    // alert rules and delivery targets. None => alerting disabled.
+    pub alerts: Option<AlertConfig>,
+    // ZeroMQ PUB socket output. None => disabled.
+    pub zmq: Option<ZmqSetting>,
+    // local output streams
+    #[serde(default)]
+    pub output: OutputConfig,
+    // NATS publisher. None => disabled.
+    pub nats: Option<NatsSetting>,
+    // sampled ladder written to csv / parquet files. None => disabled.
+    pub archive: Option<ArchiveSetting>,
+    // statsd / DogStatsD metrics. None => disabled.
+    pub statsd: Option<StatsdSetting>,
+    // paper trading of the detected opportunities. None => disabled.
+    pub simulation: Option<SimulationSetting>,
+    // lifecycle tracking of the detected opportunities. None => disabled.
+    pub opportunities: Option<OpportunitySetting>,
+    // size buckets of the opportunity detector
+    #[serde(default)]
+    pub detector: DetectorSetting,
+    // bridge rates by name, referred to as rate:{name} by the alert rules
+    #[serde(default)]
+    pub rates: HashMap<String, RateSource>,
+    // net profit model of the detected opportunities. None => gross only.
+    pub portfolio: Option<PortfolioSetting>,
+    // volatility regime in the summary stats. None => disabled.
+    pub volatility: Option<VolatilitySetting>,
+    // lead / lag statistics between exchanges. None => disabled.
+    pub leadlag: Option<LeadLagSetting>,
+    // share of the best prices and the depth per exchange. None => disabled.
+    pub contribution: Option<ContributionSetting>,
+    // parse wire prices and sizes through an i64 fixed point fast path.
+    // Parsing only: the books keep BigDecimal, so the output is identical.
+    #[serde(default)]
+    pub fixed_point: bool,
+    #[serde(default)]
+    pub parser_state: ParserStateSetting,
+    // startup quorum before the first summary. None => publish right away.
+    pub warmup: Option<WarmupSetting>,
+    // summary snapshot restored across restarts. None => disabled.
+    pub persist: Option<PersistSetting>,
+    // server only. addresses to serve on. Empty => bind_addr:server_port,
+    // the public routes only.
+    #[serde(default)]
+    pub listeners: Vec<ListenerSetting>,
+    // server only. bearer token of the admin routes. None => they answer
+    // 401.
+    #[serde(serialize_with = "redact_some")]
+    pub admin_token: Option<String>,
+    // add stats.pipeline_ms to every summary
+    #[serde(default)]
+    pub pipeline_ms: bool,
+    // poll the rest ticker of the exchanges whose websocket feed has no
+    // last price / volume this often. None => disabled.
+    pub ticker_poll_secs: Option<u64>,
+    // index prices polled next to the books, in index_prices of the Summary
+    #[serde(default)]
+    pub index_sources: Vec<IndexSource>,
+    // venue clock offsets, applied to the venue timestamps. None => disabled.
+    pub time_sync: Option<TimeSyncSetting>,
+    // tick size, lot size and minimum order of the pairs, GET /exchanges.
+    // None => disabled.
+    pub symbol_meta: Option<SymbolMetaSetting>,
+    // exchange -> faults injected into its frames, only armed with
+    // ARB_MONITOR_CHAOS=1 in the environment. None => disabled.
+    pub chaos: Option<HashMap<String, Vec<ChaosRule>>>,
+    // exchange -> periodic check of its book against the rest snapshot.
+    // None => disabled.
+    pub audit: Option<HashMap<String, AuditSetting>>,
+    // an exchange without an update for this many average update
+    // intervals is flagged degraded
+    #[serde(default = "default_degraded_factor")]
+    pub degraded_factor: f64,
+    // frames the parsers failed on kept for GET /debug/parse_errors
+    #[serde(default = "default_dead_letter_size")]
+    pub dead_letter_size: usize,
+    // exit the process when an exchange fails for good, instead of
+    // running without it
+    #[serde(default)]
+    pub fail_fast: bool,
+    // best prices per side in the consolidated section of the Summary.
+    // None => no section.
+    pub consolidated_levels: Option<usize>,
+    // most levels per side a ws client gets with the deep_snapshot op
+    #[serde(default = "default_deep_snapshot_max_depth")]
+    pub deep_snapshot_max_depth: usize,
+    // None => prices and amounts as the venues sent them
+    pub output_precision: Option<OutputPrecision>,
+    // a bbo tick per change of a venue's top of book, to the ws clients
+    // that subscribe and on the zmq topic
+    #[serde(default)]
+    pub bbo_ticks: bool,
+    // age_ms on every level of the Summary, see analytics::ages
+    #[serde(default)]
+    pub level_ages: bool,
+    // timeout of every call of the rest pollers
+    #[serde(default = "default_rest_timeout_secs")]
+    pub rest_timeout_secs: u64,
+    #[serde(default)]
+    pub rest_breaker: BreakerSetting,
+    #[serde(default)]
+    pub maintenance: MaintenancePolicy,
+    // name -> pair group, served on /ws/group/{name}
+    #[serde(default)]
+    pub groups: HashMap<String, GroupSetting>,
+    #[serde(default)]
+    pub guardrails: GuardrailSetting,
+}
+
+impl InnerConfig {
+    // sides kept of an exchange, Both when not configured
+    pub fn sides(&self, exchange: &str) -> Sides {
+        self.exchange_pair_map
+            .get(exchange)
+            .and_then(|settings| settings.first())
+            .map(|s| s.sides)
+            .unwrap_or_default()
+    }
+
+    // catch settings that can never work before anything starts
+    pub fn validate(&self) -> Result<()> {
+        for (exchange, settings) in self.exchange_pair_map.iter() {
+            let pairs: HashSet<&str> = settings.iter().map(|s| s.pair.as_str()).collect();
+            crate::apitree::check_pairs(exchange, pairs.len())?;
+            for (i, setting) in settings.iter().enumerate() {
+                crate::apitree::check_pair(exchange, &setting.pair)?;
+                if i == 0 && setting.ws_api {
+                    crate::apitree::check_login(exchange, setting.credentials.as_ref())?;
+                }
+                crate::apitree::check_depth(
+                    exchange,
+                    &setting.pair,
+                    setting.depth,
+                    setting.interval_ms,
+                )?;
+                if setting.level == Some(0) {
+                    return Err(anyhow!("{}: level should be positive", exchange));
+                }
+                if setting.invert_precision == 0 {
+                    return Err(anyhow!("{}: invert_precision should be positive", exchange));
+                }
+                let native = self
+                    .pair
+                    .as_ref()
+                    .and_then(|logical| reversed(logical, &setting.pair))
+                    == Some(false);
+                if setting.invert && native {
+                    return Err(anyhow!(
+                        "{}: {} is quoted the way of the pair, invert would reverse it",
+                        exchange,
+                        setting.pair
+                    ));
+                }
+                if !setting.ws_api && (setting.depth.is_some() || setting.interval_ms.is_some()) {
+                    return Err(anyhow!(
+                        "{}: depth and interval_ms need the websocket api",
+                        exchange
+                    ));
+                }
+                if settings[..i].iter().any(|s| {
+                    s.pair == setting.pair
+                        && s.depth == setting.depth
+                        && s.interval_ms == setting.interval_ms
+                }) {
+                    return Err(anyhow!(
+                        "{}: {} listed twice with the same depth and interval_ms",
+                        exchange,
+                        setting.pair
+                    ));
+                }
+            }
+        }
+        if cfg!(not(unix)) && self.output.unix_socket_path.is_some() {
+            return Err(anyhow!(
+                "output.unix_socket_path is only supported on unix platforms"
+            ));
+        }
+        if let Some(nats) = &self.nats {
+            if nats.servers.is_empty() {
+                return Err(anyhow!("nats.servers should have at least one server"));
+            }
+        }
+        if let Some(archive) = &self.archive {
+            if archive.sample_interval_secs == 0 {
+                return Err(anyhow!("archive.sample_interval_secs should be positive"));
+            }
+            if archive.flush_interval_secs == 0 {
+                return Err(anyhow!("archive.flush_interval_secs should be positive"));
+            }
+        }
+        if let Some(statsd) = &self.statsd {
+            if !(statsd.sample_rate > 0. && statsd.sample_rate <= 1.) {
+                return Err(anyhow!("statsd.sample_rate should be in (0, 1]"));
+            }
+            if statsd.gauge_interval_ms == 0 {
+                return Err(anyhow!("statsd.gauge_interval_ms should be positive"));
+            }
+        }
+        if let Some(simulation) = &self.simulation {
+            if simulation.log_interval_secs == 0 {
+                return Err(anyhow!("simulation.log_interval_secs should be positive"));
+            }
+        }
+        if let Some(o) = &self.opportunities {
+            if o.close_bps > o.open_bps {
+                return Err(anyhow!(
+                    "opportunities.close_bps should not be above open_bps"
+                ));
+            }
+        }
+        if self
+            .detector
+            .sizes
+            .iter()
+            .any(|s| !s.is_finite() || *s <= 0.)
+        {
+            return Err(anyhow!("detector.sizes should be positive"));
+        }
+        for rule in self.alerts.iter().flat_map(|a| a.rules.iter()) {
+            match &rule.kind {
+                AlertRuleKind::ArbOpportunity {
+                    size: Some(size), ..
+                } if !self.detector.sizes.contains(size) => {
+                    return Err(anyhow!("alert size {} is not one of detector.sizes", size));
+                }
+                AlertRuleKind::CrossedBook { exchange } if self.sides(exchange) != Sides::Both => {
+                    return Err(anyhow!(
+                        "{} can't cross, only one side of its book is kept",
+                        exchange
+                    ));
+                }
+                AlertRuleKind::BridgedOpportunity {
+                    bridge, buy, sell, ..
+                } => {
+                    // bought off the asks of buy, sold into the bids of sell
+                    for (leg, missing) in [(buy, Sides::BidOnly), (sell, Sides::AskOnly)] {
+                        let exchange = leg.split(':').next().unwrap_or_default();
+                        if self.sides(exchange) == missing {
+                            return Err(anyhow!(
+                                "{} is {:?}, it can't be the {} leg",
+                                exchange,
+                                missing,
+                                if leg == buy { "buy" } else { "sell" }
+                            ));
+                        }
+                    }
+                    let known = bridge
+                        .strip_prefix("rate:")
+                        .is_some_and(|name| self.rates.contains_key(name));
+                    if !known {
+                        return Err(anyhow!(
+                            "bridge {} should be rate:{{name of rates}}",
+                            bridge
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(v) = &self.volatility {
+            if v.windows_secs.is_empty() || v.windows_secs.contains(&0) {
+                return Err(anyhow!("volatility.windows_secs should be positive"));
+            }
+            if v.quiet_bps > v.volatile_bps {
+                return Err(anyhow!(
+                    "volatility.quiet_bps should not be above volatile_bps"
+                ));
+            }
+        }
+        if let Some(l) = &self.leadlag {
+            if l.capacity == 0 || l.window_ms == 0 {
+                return Err(anyhow!("leadlag.capacity and window_ms should be positive"));
+            }
+        }
+        if let Some(c) = &self.contribution {
+            if c.window_secs == 0 || c.bucket_secs == 0 || c.levels == 0 {
+                return Err(anyhow!(
+                    "contribution.window_secs, bucket_secs and levels should be positive"
+                ));
+            }
+            if c.bucket_secs > c.window_secs {
+                return Err(anyhow!(
+                    "contribution.bucket_secs should be at most window_secs"
+                ));
+            }
+        }
+        if let Some(portfolio) = &self.portfolio {
+            if portfolio.window_secs == 0 {
+                return Err(anyhow!("portfolio.window_secs should be positive"));
+            }
+        }
+        if self.parser_state.ttl_secs == 0
+            || self.parser_state.max_trades == 0
+            || self.parser_state.max_books == 0
+        {
+            return Err(anyhow!(
+                "parser_state.ttl_secs, max_trades and max_books should be positive"
+            ));
+        }
+        if self.guardrails.ingest_capacity == 0 || self.guardrails.report_interval_secs == 0 {
+            return Err(anyhow!(
+                "guardrails.ingest_capacity and report_interval_secs should be positive"
+            ));
+        }
+        if self.guardrails.memory_soft_limit_mb == Some(0) {
+            return Err(anyhow!(
+                "guardrails.memory_soft_limit_mb should be positive"
+            ));
+        }
+        if let Some(warmup) = &self.warmup {
+            warmup.min_exchanges.of(self.exchange_pair_map.len())?;
+        }
+        if let Some(persist) = &self.persist {
+            if persist.write_interval_secs == 0 {
+                return Err(anyhow!("persist.write_interval_secs should be positive"));
+            }
+        }
+        if self
+            .time_sync
+            .as_ref()
+            .is_some_and(|t| t.interval_secs == 0)
+        {
+            return Err(anyhow!("time_sync.interval_secs should be positive"));
+        }
+        if let Some(meta) = &self.symbol_meta {
+            if meta.ttl_secs == 0 {
+                return Err(anyhow!("symbol_meta.ttl_secs should be positive"));
+            }
+            if meta.retry_secs == 0 {
+                return Err(anyhow!("symbol_meta.retry_secs should be positive"));
+            }
+            for (exchange, defaults) in &meta.defaults {
+                if defaults.tick_size <= 0. || defaults.lot_size <= 0. {
+                    return Err(anyhow!(
+                        "symbol_meta.defaults.{}: tick_size and lot_size should be positive",
+                        exchange
+                    ));
+                }
+            }
+        }
+        for (exchange, rule) in self
+            .chaos
+            .iter()
+            .flat_map(|c| c.iter())
+            .flat_map(|(e, rules)| rules.iter().map(move |r| (e, r)))
+        {
+            let scheduled = match (rule.probability, rule.every_secs) {
+                (Some(p), None) => p > 0. && p <= 1.,
+                (None, Some(every)) => every > 0,
+                _ => false,
+            };
+            if !scheduled {
+                return Err(anyhow!(
+                    "chaos.{}: a rule needs either a probability in (0, 1] or a positive every_secs",
+                    exchange
+                ));
+            }
+            if matches!(
+                rule.fault,
+                Fault::Delay { delay_ms: 0 } | Fault::Freeze { freeze_secs: 0 }
+            ) {
+                return Err(anyhow!("chaos.{}: {:?} should last", exchange, rule.fault));
+            }
+        }
+        if self.degraded_factor.is_nan() || self.degraded_factor <= 1. {
+            return Err(anyhow!("degraded_factor should be above 1"));
+        }
+        for (exchange, audit) in self.audit.iter().flat_map(|a| a.iter()) {
+            if !self
+                .exchange_pair_map
+                .get(exchange)
+                .and_then(|settings| settings.first())
+                .is_some_and(|s| s.ws_api)
+            {
+                return Err(anyhow!(
+                    "audit.{}: only websocket exchanges are audited",
+                    exchange
+                ));
+            }
+            if crate::apitree::rest(exchange).is_err() {
+                return Err(anyhow!("audit.{}: no rest orderbook", exchange));
+            }
+            // the rest endpoints are rate limited
+            if audit.interval_secs < MIN_AUDIT_INTERVAL_SECS {
+                return Err(anyhow!(
+                    "audit.{}: interval_secs should be at least {}",
+                    exchange,
+                    MIN_AUDIT_INTERVAL_SECS
+                ));
+            }
+            if audit.top_n == 0 || audit.tolerance_bps.is_nan() || audit.tolerance_bps < 0. {
+                return Err(anyhow!(
+                    "audit.{}: top_n should be positive, tolerance_bps not negative",
+                    exchange
+                ));
+            }
+            if audit
+                .resync_bps
+                .is_some_and(|bps| bps.is_nan() || bps < audit.tolerance_bps)
+            {
+                return Err(anyhow!(
+                    "audit.{}: resync_bps should be at least tolerance_bps",
+                    exchange
+                ));
+            }
+        }
+        if self
+            .output_precision
+            .is_some_and(|p| p.price_dp < 0 || p.amount_dp < 0)
+        {
+            return Err(anyhow!("output_precision decimals should not be negative"));
+        }
+        if self.deep_snapshot_max_depth == 0 {
+            return Err(anyhow!("deep_snapshot_max_depth should be positive"));
+        }
+        if self.rest_timeout_secs == 0 {
+            return Err(anyhow!("rest_timeout_secs should be positive"));
+        }
+        let breaker = &self.rest_breaker;
+        if breaker.failure_threshold == 0 || breaker.cool_off_secs == 0 {
+            return Err(anyhow!(
+                "rest_breaker.failure_threshold and cool_off_secs should be positive"
+            ));
+        }
+        if breaker.max_cool_off_secs < breaker.cool_off_secs {
+            return Err(anyhow!(
+                "rest_breaker.max_cool_off_secs should be at least cool_off_secs"
+            ));
+        }
+        if self.consolidated_levels == Some(0) {
+            return Err(anyhow!("consolidated_levels should be positive"));
+        }
+        for (name, group) in self.groups.iter() {
+            if group.exchanges.is_empty() {
+                return Err(anyhow!("groups.{}: exchanges should not be empty", name));
+            }
+            for (exchange, pair) in group.exchanges.iter() {
+                let configured = self
+                    .exchange_pair_map
+                    .get(exchange)
+                    .is_some_and(|settings| settings.iter().any(|s| &s.pair == pair));
+                if !configured {
+                    return Err(anyhow!(
+                        "groups.{}: {} {} is not in exchange_pair_map",
+                        name,
+                        exchange,
+                        pair
+                    ));
+                }
+            }
+            if group.consolidated_levels == Some(0) {
+                return Err(anyhow!(
+                    "groups.{}: consolidated_levels should be positive",
+                    name
+                ));
+            }
+            if group
+                .detector
+                .as_ref()
+                .is_some_and(|d| d.sizes.iter().any(|s| !s.is_finite() || *s <= 0.))
+            {
+                return Err(anyhow!(
+                    "groups.{}: detector.sizes should be positive",
+                    name
+                ));
+            }
+            if group
+                .output_precision
+                .is_some_and(|p| p.price_dp < 0 || p.amount_dp < 0)
+            {
+                return Err(anyhow!(
+                    "groups.{}: output_precision decimals should not be negative",
+                    name
+                ));
+            }
+        }
+        if self.ticker_poll_secs == Some(0) {
+            return Err(anyhow!("ticker_poll_secs should be positive"));
+        }
+        for (i, source) in self.index_sources.iter().enumerate() {
+            if crate::apitree::index::endpoint(&source.exchange, &source.symbol).is_none() {
+                return Err(anyhow!(
+                    "index_sources.{}: unknown exchange {}",
+                    i,
+                    source.exchange
+                ));
+            }
+            if source.poll_secs == 0 {
+                return Err(anyhow!("index_sources.{}: poll_secs should be positive", i));
+            }
+            if self.index_sources[..i]
+                .iter()
+                .any(|other| other.exchange == source.exchange)
+            {
+                return Err(anyhow!(
+                    "index_sources.{}: {} listed twice",
+                    i,
+                    source.exchange
+                ));
+            }
+        }
+        // one or the other says where the server binds, never both
+        if self.bind_addr.is_some() && !self.listeners.is_empty() {
+            return Err(anyhow!(
+                "bind_addr conflicts with listeners, give each listener its addr"
+            ));
+        }
+        for (i, listener) in self.listeners.iter().enumerate() {
+            if listener.routes.as_ref().is_some_and(|r| r.is_empty()) {
+                return Err(anyhow!(
+                    "listener {}:{} serves no routes",
+                    listener.addr,
+                    listener.port
+                ));
+            }
+            if let Some(other) = self.listeners[..i].iter().find(|o| o.conflicts(listener)) {
+                return Err(anyhow!(
+                    "listener {}:{} conflicts with {}:{}",
+                    listener.addr,
+                    listener.port,
+                    other.addr,
+                    other.port
+                ));
+            }
+        }
+        for (exchange, settings) in self.exchange_pair_map.iter() {
+            if settings.iter().any(|s| s.sides != settings[0].sides) {
+                return Err(anyhow!(
+                    "{}: all pairs should have the same sides",
+                    exchange
+                ));
+            }
+            if settings
+                .iter()
+                .any(|s| s.ping_probe_secs != settings[0].ping_probe_secs)
+            {
+                return Err(anyhow!(
+                    "{}: all pairs should have the same ping_probe_secs",
+                    exchange
+                ));
+            }
+            if settings[0].ping_probe_secs == Some(0) {
+                return Err(anyhow!("{}: ping_probe_secs should be > 0", exchange));
+            }
+        }
+        for (name, source) in self.rates.iter() {
+            if let RateSource::Book { exchange, .. } = source {
+                if self.sides(exchange) != Sides::Both {
+                    return Err(anyhow!(
+                        "rates.{}: the mid of {} needs both sides of its book",
+                        name,
+                        exchange
+                    ));
+                }
+            }
+            if let RateSource::Rest { refresh_secs, .. } = source {
+                if *refresh_secs == 0 {
+                    return Err(anyhow!("rates.{}.refresh_secs should be positive", name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // invert the pair settings whose symbol reads as the logical pair
+    // reversed, ex: audbtc for BTC-AUD
+    pub fn orient(&mut self) {
+        let Some(logical) = self.pair.clone() else {
+            return;
+        };
+        for setting in self.exchange_pair_map.values_mut().flatten() {
+            if reversed(&logical, &setting.pair) == Some(true) {
+                setting.invert = true;
+            }
+        }
+    }
+
+    // the listeners, or the single public one of bind_addr / server_port
+    pub fn listeners(&self) -> Vec<ListenerSetting> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+        vec![ListenerSetting {
+            addr: self
+                .bind_addr
+                .clone()
+                .unwrap_or_else(|| "0.0.0.0".to_string()),
+            port: self.server_port,
+            tls: None,
+            routes: Some(vec![RouteGroup::Public]),
+            redistributable_only: false,
+        }]
+    }
+
+    // the exchanges left out of the redistributable_only outputs
+    pub fn denied_exchanges(&self) -> HashSet<String> {
+        self.exchange_pair_map
+            .iter()
+            .filter(|(_, settings)| {
+                settings
+                    .iter()
+                    .any(|s| s.redistribution == Redistribution::Deny)
+            })
+            .map(|(exchange, _)| exchange.clone())
+            .collect()
+    }
+
+    pub fn pair_name(&self) -> String {
+        if let Some(pair) = &self.pair {
+            return pair.clone();
+        }
+        let mut exchanges: Vec<&String> = self.exchange_pair_map.keys().collect();
+        exchanges.sort();
+        exchanges
+            .first()
+            .and_then(|e| self.exchange_pair_map[*e].first())
+            .map(|s| s.pair.clone())
+            .unwrap_or_default()
+    }
 }
 
 impl Default for InnerConfig {
     fn default() -> Self {
         Self {
             exchange_pair_map: HashMap::new(),
+            pair: None,
             server_addr: Some("127.0.0.1".to_string()),
             bind_addr: Some("0.0.0.0".to_string()),
             server_port: 50051,
             log_path: Some("./test.log".to_string()),
             log_level: LogLevel::Info,
+            alerts: None,
+            zmq: None,
+            output: OutputConfig::default(),
+            nats: None,
+            archive: None,
+            statsd: None,
+            simulation: None,
+            opportunities: None,
+            detector: DetectorSetting::default(),
+            rates: HashMap::new(),
+            portfolio: None,
+            volatility: None,
+            leadlag: None,
+            contribution: None,
+            fixed_point: false,
+            parser_state: ParserStateSetting::default(),
+            warmup: None,
+            persist: None,
+            listeners: vec![],
+            admin_token: None,
+            pipeline_ms: false,
+            ticker_poll_secs: None,
+            index_sources: vec![],
+            time_sync: None,
+            symbol_meta: None,
+            chaos: None,
+            audit: None,
+            degraded_factor: default_degraded_factor(),
+            dead_letter_size: default_dead_letter_size(),
+            fail_fast: false,
+            consolidated_levels: None,
+            deep_snapshot_max_depth: default_deep_snapshot_max_depth(),
+            output_precision: None,
+            bbo_ticks: false,
+            level_ages: false,
+            rest_timeout_secs: default_rest_timeout_secs(),
+            rest_breaker: BreakerSetting::default(),
+            maintenance: MaintenancePolicy::default(),
+            groups: HashMap::new(),
+            guardrails: GuardrailSetting::default(),
         }
     }
 }
 
-// outer config structure. Used to define the parameter input / env input of the whole program.
+/// Command line of the monitor, with the config file it points at once
+/// loaded.
 #[derive(Serialize, Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Config {
     #[arg(short, long, default_value_t=String::from("./config/config.yaml"))]
     pub config_path: String,
+    // print the loaded config, secrets redacted, before starting
+    #[arg(long)]
+    pub print_config: bool,
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+    // soak test: synthetic feeds instead of the exchanges, ex:
+    // generators=4,rate=2000,secs=60,seed=7
+    #[arg(long, value_name = "SPEC")]
+    #[serde(skip)]
+    pub synthetic: Option<SyntheticSpec>,
     #[arg(skip)]
     pub inner: InnerConfig,
 }
 
+/// Dev tools, run instead of the monitor.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compare the baseline and the current parser of an exchange
+    ///
+    /// Replays a capture, one raw frame per line, through both and reports
+    /// the first frame they disagree on. Exits 0 when they agree, 1 when
+    /// they don't, 2 on error.
+    CompareParsers {
+        #[arg(long)]
+        exchange: String,
+        #[arg(long)]
+        capture: String,
+        // the parsers of the legacy-parsers feature
+        #[arg(long, default_value_t = String::from("legacy"))]
+        baseline: String,
+    },
+}
+
+/// Synthetic feeds of a soak test, see soak. Comma separated key=value,
+/// every key optional.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticSpec {
+    // exchanges simulated
+    pub generators: usize,
+    // books per second of each generator
+    pub rate: u64,
+    // exits after this long
+    pub secs: u64,
+    // None => a random one, printed with the reports
+    pub seed: Option<u64>,
+    // a whole new ladder every this many books, else a few levels change
+    pub snapshot_every: u64,
+    // per side
+    pub levels: usize,
+    // how often the throughput report is printed
+    pub report_secs: u64,
+}
+
+impl Default for SyntheticSpec {
+    fn default() -> Self {
+        SyntheticSpec {
+            generators: 4,
+            rate: 1000,
+            secs: 60,
+            seed: None,
+            snapshot_every: 100,
+            levels: 20,
+            report_secs: 5,
+        }
+    }
+}
+
+impl std::str::FromStr for SyntheticSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut spec = SyntheticSpec::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("{} is not key=value", pair))?;
+            let number = || -> Result<u64> {
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("{}: {} is not a number", key, value))
+            };
+            match key.trim() {
+                "generators" => spec.generators = number()? as usize,
+                "rate" => spec.rate = number()?,
+                "secs" => spec.secs = number()?,
+                "seed" => spec.seed = Some(number()?),
+                "snapshot_every" => spec.snapshot_every = number()?,
+                "levels" => spec.levels = number()? as usize,
+                "report_secs" => spec.report_secs = number()?,
+                key => return Err(anyhow!("unknown key {}", key)),
+            }
+        }
+        for (key, value) in [
+            ("generators", spec.generators as u64),
+            ("rate", spec.rate),
+            ("secs", spec.secs),
+            ("snapshot_every", spec.snapshot_every),
+            ("levels", spec.levels as u64),
+            ("report_secs", spec.report_secs),
+        ] {
+            if value == 0 {
+                return Err(anyhow!("{} should be positive", key));
+            }
+        }
+        Ok(spec)
+    }
+}
+
 impl Config {
-    // load real config from the path given by parameter input / env input.
+    /// Load and validate the config file at `config_path`.
     pub fn load(&mut self) -> Result<()> {
-        let f = File::open(&self.config_path).map_err(|e| anyhow!("{:?}", e))?;
-        self.inner = serde_yaml::from_reader(f).map_err(|e| anyhow!("{:?}", e))?;
+        let f =
+            File::open(&self.config_path).map_err(|e| anyhow!("{}: {}", self.config_path, e))?;
+        self.inner =
+            serde_yaml::from_reader(f).map_err(|e| anyhow!("{}: {}", self.config_path, e))?;
+        self.inner.validate()?;
+        self.inner.orient();
         Ok(())
     }
+
+    // register the loaded config as the one in use. Every call after the
+    // first counts as a reload.
+    pub fn publish(&self) -> Result<Effective> {
+        let config = serde_json::to_value(&self.inner)?;
+        let mut tmp = EFFECTIVE.lock().unwrap();
+        let effective = Effective {
+            config_path: self.config_path.clone(),
+            loaded_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_millis(),
+            reloads: tmp.as_ref().map_or(0, |e| e.reloads + 1),
+            config,
+        };
+        *tmp = Some(effective.clone());
+        Ok(effective)
+    }
+}
+
+// the config an instance runs with, secrets redacted
+#[derive(Serialize, Clone, Debug)]
+pub struct Effective {
+    pub config_path: String,
+    // unix millis
+    pub loaded_at: u128,
+    pub reloads: u64,
+    pub config: serde_json::Value,
+}
+
+static EFFECTIVE: Lazy<Mutex<Option<Effective>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn effective() -> Option<Effective> {
+    EFFECTIVE.lock().unwrap().clone()
 }
 
 #[cfg(test)]
@@ -98,6 +2019,9 @@ mod tests {
     fn test_load() {
         let mut config = Config {
             config_path: "src/test_resource/config.yaml".to_string(),
+            print_config: false,
+            command: None,
+            synthetic: None,
             inner: InnerConfig::default(),
         };
         let result = config.load();
@@ -113,6 +2037,17 @@ mod tests {
                             pair: "btcusdt".to_string(),
                             ws_api: false,
                             wait_secs: 3,
+                            trades: false,
+                            sides: Sides::Both,
+                            depth: None,
+                            interval_ms: None,
+                            ping_probe_secs: None,
+                            level: None,
+                            endpoint: None,
+                            redistribution: Redistribution::Allow,
+                            invert: false,
+                            invert_precision: default_invert_precision(),
+                            credentials: None,
                         }]
                     ),
                     (
@@ -121,15 +2056,729 @@ mod tests {
                             pair: "btcusd".to_string(),
                             ws_api: true,
                             wait_secs: 3,
+                            trades: true,
+                            sides: Sides::BidOnly,
+                            depth: None,
+                            interval_ms: None,
+                            ping_probe_secs: None,
+                            level: None,
+                            endpoint: None,
+                            redistribution: Redistribution::Deny,
+                            invert: false,
+                            invert_precision: default_invert_precision(),
+                            credentials: None,
                         }]
                     ),
                 ]),
+                pair: None,
                 server_addr: Some("127.0.0.1".to_string()),
                 bind_addr: None,
                 server_port: 50051,
                 log_path: Some("test.log".to_string()),
                 log_level: LogLevel::Debug,
+                alerts: Some(AlertConfig {
+                    rules: vec![
+                        AlertRule {
+                            kind: AlertRuleKind::ArbOpportunity {
+                                min_profit_bps: 10.0,
+                                min_quantity: 0.01,
+                                size: None,
+                                min_confidence: 0.,
+                            },
+                            cooldown_secs: 30,
+                        },
+                        AlertRule {
+                            kind: AlertRuleKind::StaleFeed { after_secs: 10 },
+                            cooldown_secs: 60,
+                        },
+                    ],
+                    webhook: Some(WebhookSetting {
+                        url: "http://127.0.0.1:8080/alert".to_string(),
+                        secret: Some("secret".to_string()),
+                        max_retries: 3,
+                    }),
+                    telegram: Some(TelegramSetting {
+                        bot_token: "123:abc".to_string(),
+                        chat_id: "-100123".to_string(),
+                        api_url: "https://api.telegram.org".to_string(),
+                        max_retries: 3,
+                        digest_threshold: 5,
+                        digest_window_ms: 2000,
+                        min_interval_ms: 1000,
+                    }),
+                }),
+                zmq: Some(ZmqSetting {
+                    bind: "tcp://127.0.0.1:5556".to_string(),
+                    topic_prefix: "md.".to_string(),
+                    encoding: Encoding::Msgpack,
+                    redistributable_only: true,
+                }),
+                output: OutputConfig {
+                    unix_socket_path: Some("/tmp/arb_monitor.sock".to_string()),
+                    unix_socket_mode: Some(0o660),
+                },
+                nats: Some(NatsSetting {
+                    servers: vec!["nats://127.0.0.1:4222".to_string()],
+                    credentials_file: None,
+                    user: None,
+                    password: None,
+                    subject: "md.arb.{pair}".to_string(),
+                    encoding: Encoding::Json,
+                    jetstream: Some(JetStreamSetting {
+                        stream: "ARB".to_string(),
+                        max_age_secs: 600,
+                        max_messages: -1,
+                    }),
+                    redistributable_only: false,
+                }),
+                archive: Some(ArchiveSetting {
+                    directory: "/tmp/arb_archive".to_string(),
+                    format: ArchiveFormat::Parquet,
+                    sample_interval_secs: 1,
+                    max_depth: 10,
+                    flush_interval_secs: 60,
+                }),
+                statsd: Some(StatsdSetting {
+                    address: "127.0.0.1:8125".to_string(),
+                    prefix: "arb".to_string(),
+                    tags: vec!["env:test".to_string()],
+                    sample_rate: 0.1,
+                    gauge_interval_ms: 1000,
+                }),
+                simulation: Some(SimulationSetting {
+                    min_profit_bps: 5.,
+                    max_notional: 1000.,
+                    latency_ms: 200,
+                    log_interval_secs: 60,
+                    venues: HashMap::from([
+                        (
+                            "kraken".to_string(),
+                            VenueSetting {
+                                base: 0.5,
+                                quote: 20000.,
+                                taker_fee_bps: 26.,
+                            },
+                        ),
+                        (
+                            "binance".to_string(),
+                            VenueSetting {
+                                base: 0.5,
+                                quote: 20000.,
+                                taker_fee_bps: 0.,
+                            },
+                        ),
+                    ]),
+                }),
+                opportunities: Some(OpportunitySetting {
+                    open_bps: 10.,
+                    close_bps: 5.,
+                    close_grace_ms: 500,
+                    window_secs: 3600,
+                }),
+                detector: DetectorSetting {
+                    sizes: vec![1000., 5000., 25000.],
+                    min_profit_bps: 5.,
+                    confidence: ConfidenceSetting {
+                        age_weight: 1.,
+                        ..ConfidenceSetting::default()
+                    },
+                    suppress_volatile: false,
+                    volatile_multiplier: Some(2.),
+                    min_persistence_ms: 300,
+                    debounce_ms: 1000,
+                    grace_ms: 500,
+                },
+                rates: HashMap::from([
+                    (
+                        "AUDUSD".to_string(),
+                        RateSource::Rest {
+                            url: "https://api.example.com/latest?base=AUD".to_string(),
+                            pointer: "/rates/USD".to_string(),
+                            refresh_secs: 60,
+                            invert: false,
+                        },
+                    ),
+                    (
+                        "AUDUSDT".to_string(),
+                        RateSource::Book {
+                            exchange: "kraken".to_string(),
+                            invert: true,
+                        },
+                    ),
+                ]),
+                portfolio: Some(PortfolioSetting {
+                    venues: HashMap::from([(
+                        "kraken".to_string(),
+                        VenueSetting {
+                            base: 1.,
+                            quote: 50000.,
+                            taker_fee_bps: 26.,
+                        },
+                    )]),
+                    base: TransferSetting {
+                        withdrawal_fee: 0.0005,
+                        transfer_mins: 30.,
+                    },
+                    quote: TransferSetting::default(),
+                    rebalance_trades: 10,
+                    window_secs: 3600,
+                }),
+                volatility: Some(VolatilitySetting {
+                    windows_secs: vec![60, 300],
+                    quiet_bps: 5.,
+                    volatile_bps: 30.,
+                }),
+                leadlag: Some(LeadLagSetting {
+                    threshold_bps: 2.,
+                    window_ms: 500,
+                    capacity: 256,
+                }),
+                contribution: None,
+                fixed_point: true,
+                parser_state: ParserStateSetting {
+                    ttl_secs: 600,
+                    max_trades: 10000,
+                    max_books: 1000,
+                },
+                warmup: Some(WarmupSetting {
+                    min_exchanges: MinExchanges::Percent("50%".to_string()),
+                    warmup_timeout_secs: 10,
+                }),
+                persist: Some(PersistSetting {
+                    snapshot_path: "/tmp/arb_monitor.snapshot.json".to_string(),
+                    max_age_secs: 60,
+                    write_interval_secs: 5,
+                }),
+                listeners: vec![
+                    ListenerSetting {
+                        addr: "127.0.0.1".to_string(),
+                        port: 50051,
+                        tls: None,
+                        routes: None,
+                        redistributable_only: false,
+                    },
+                    ListenerSetting {
+                        addr: "10.8.0.1".to_string(),
+                        port: 50443,
+                        tls: Some(TlsSetting {
+                            cert_path: "/etc/arb_monitor/cert.pem".to_string(),
+                            key_path: "/etc/arb_monitor/key.pem".to_string(),
+                        }),
+                        routes: Some(vec![RouteGroup::Public]),
+                        redistributable_only: true,
+                    },
+                ],
+                admin_token: Some("t0ken".to_string()),
+                pipeline_ms: true,
+                ticker_poll_secs: Some(30),
+                index_sources: vec![],
+                time_sync: Some(TimeSyncSetting {
+                    max_offset_ms: 500,
+                    interval_secs: 3600,
+                }),
+                symbol_meta: None,
+                chaos: Some(HashMap::from([(
+                    "binance".to_string(),
+                    vec![
+                        ChaosRule {
+                            fault: Fault::Freeze { freeze_secs: 30 },
+                            probability: None,
+                            every_secs: Some(300),
+                        },
+                        ChaosRule {
+                            fault: Fault::Corrupt,
+                            probability: Some(0.01),
+                            every_secs: None,
+                        },
+                    ],
+                )])),
+                audit: None,
+                degraded_factor: 8.,
+                dead_letter_size: 100,
+                fail_fast: false,
+                consolidated_levels: None,
+                deep_snapshot_max_depth: 100,
+                output_precision: None,
+                bbo_ticks: false,
+                level_ages: false,
+                rest_timeout_secs: 10,
+                rest_breaker: BreakerSetting::default(),
+                maintenance: MaintenancePolicy::default(),
+                groups: HashMap::new(),
+                guardrails: GuardrailSetting::default(),
             }
-        )
+        );
+        assert_eq!(config.inner.pair_name(), "btcusdt");
+    }
+    #[test]
+    fn test_validate() {
+        let mut inner = InnerConfig::default();
+        assert!(inner.validate().is_ok());
+        inner.nats = Some(NatsSetting {
+            servers: vec![],
+            credentials_file: None,
+            user: None,
+            password: None,
+            subject: default_nats_subject(),
+            encoding: Encoding::Json,
+            jetstream: None,
+            redistributable_only: false,
+        });
+        assert!(inner.validate().is_err());
+        inner.nats = None;
+        inner.archive = Some(ArchiveSetting {
+            directory: "/tmp".to_string(),
+            format: ArchiveFormat::Csv,
+            sample_interval_secs: 0,
+            max_depth: default_max_depth(),
+            flush_interval_secs: default_flush_interval_secs(),
+        });
+        assert!(inner.validate().is_err());
+        inner.archive = Some(ArchiveSetting {
+            directory: "/tmp".to_string(),
+            format: ArchiveFormat::Csv,
+            sample_interval_secs: 1,
+            max_depth: default_max_depth(),
+            flush_interval_secs: 0,
+        });
+        assert!(inner.validate().is_err());
+        inner.archive = None;
+        inner.statsd = Some(StatsdSetting {
+            address: "127.0.0.1:8125".to_string(),
+            prefix: default_statsd_prefix(),
+            tags: vec![],
+            sample_rate: 0.,
+            gauge_interval_ms: default_gauge_interval_ms(),
+        });
+        assert!(inner.validate().is_err());
+        inner.statsd = None;
+        inner.alerts = Some(AlertConfig {
+            rules: vec![AlertRule {
+                kind: AlertRuleKind::ArbOpportunity {
+                    min_profit_bps: 10.,
+                    min_quantity: 0.,
+                    size: Some(5000.),
+                    min_confidence: 0.,
+                },
+                cooldown_secs: 60,
+            }],
+            webhook: None,
+            telegram: None,
+        });
+        assert!(inner.validate().is_err());
+        inner.detector.sizes = vec![1000., 5000.];
+        assert!(inner.validate().is_ok());
+        inner.alerts.as_mut().unwrap().rules[0].kind = AlertRuleKind::BridgedOpportunity {
+            buy: "binance:BTC-USDT".to_string(),
+            sell: "btcmarkets:BTC-AUD".to_string(),
+            bridge: "rate:AUDUSD".to_string(),
+            bridge_cost_bps: 0.,
+            min_profit_bps: 10.,
+            min_quantity: 0.,
+        };
+        assert!(inner.validate().is_err());
+        inner
+            .rates
+            .insert("AUDUSD".to_string(), RateSource::Static { rate: 0.66 });
+        assert!(inner.validate().is_ok());
+        // a leg needs the side it trades against
+        let one_side = |sides| {
+            vec![ExchangeSetting {
+                pair: "btcusdt".to_string(),
+                ws_api: true,
+                wait_secs: 3,
+                trades: false,
+                sides,
+                depth: None,
+                interval_ms: None,
+                ping_probe_secs: None,
+                level: None,
+                endpoint: None,
+                redistribution: Redistribution::Allow,
+                invert: false,
+                invert_precision: default_invert_precision(),
+                credentials: None,
+            }]
+        };
+        for (exchange, sides, ok) in [
+            ("binance", Sides::AskOnly, true),
+            ("binance", Sides::BidOnly, false),
+            ("btcmarkets", Sides::BidOnly, true),
+            ("btcmarkets", Sides::AskOnly, false),
+        ] {
+            inner
+                .exchange_pair_map
+                .insert(exchange.to_string(), one_side(sides));
+            assert_eq!(inner.validate().is_ok(), ok, "{} {:?}", exchange, sides);
+            inner.exchange_pair_map.clear();
+        }
+        inner
+            .exchange_pair_map
+            .insert("kraken".to_string(), one_side(Sides::BidOnly));
+        let crossed = AlertRule {
+            kind: AlertRuleKind::CrossedBook {
+                exchange: "kraken".to_string(),
+            },
+            cooldown_secs: 60,
+        };
+        inner.alerts.as_mut().unwrap().rules.push(crossed);
+        assert!(inner.validate().is_err());
+        inner.alerts.as_mut().unwrap().rules.pop();
+        inner.exchange_pair_map.clear();
+        inner.parser_state.ttl_secs = 0;
+        assert!(inner.validate().is_err());
+        inner.parser_state.ttl_secs = 600;
+        inner.rest_breaker.max_cool_off_secs = 1;
+        assert!(inner.validate().is_err());
+        inner.rest_breaker = BreakerSetting::default();
+        inner.guardrails.ingest_capacity = 0;
+        assert!(inner.validate().is_err());
+        inner.guardrails.ingest_capacity = 100;
+        inner.warmup = Some(WarmupSetting {
+            min_exchanges: MinExchanges::Percent("150%".to_string()),
+            warmup_timeout_secs: 10,
+        });
+        assert!(inner.validate().is_err());
+        inner.warmup = None;
+        inner.persist = Some(PersistSetting {
+            snapshot_path: "/tmp/snapshot.json".to_string(),
+            max_age_secs: 60,
+            write_interval_secs: 0,
+        });
+        assert!(inner.validate().is_err());
+        inner.persist = None;
+        let rule = |probability, every_secs| ChaosRule {
+            fault: Fault::Drop,
+            probability,
+            every_secs,
+        };
+        for (rules, ok) in [
+            (vec![rule(Some(0.5), None), rule(None, Some(60))], true),
+            (vec![rule(Some(0.5), Some(60))], false),
+            (vec![rule(None, None)], false),
+            (vec![rule(Some(1.5), None)], false),
+        ] {
+            inner.chaos = Some(HashMap::from([("binance".to_string(), rules)]));
+            assert_eq!(inner.validate().is_ok(), ok);
+        }
+        inner.chaos = None;
+        let pair = |pair: &str| ExchangeSetting {
+            pair: pair.to_string(),
+            ws_api: true,
+            wait_secs: 3,
+            trades: false,
+            sides: Sides::Both,
+            depth: None,
+            interval_ms: None,
+            ping_probe_secs: None,
+            level: None,
+            endpoint: None,
+            redistribution: Redistribution::Allow,
+            invert: false,
+            invert_precision: default_invert_precision(),
+            credentials: None,
+        };
+        inner
+            .exchange_pair_map
+            .insert("independentreserve".to_string(), vec![pair("Xbt/Aud")]);
+        assert!(inner.validate().is_err());
+        inner
+            .exchange_pair_map
+            .insert("independentreserve".to_string(), vec![pair("XBT-AUD")]);
+        assert!(inner.validate().is_ok());
+        let mut seller = pair("XBT-AUD");
+        seller.sides = Sides::BidOnly;
+        inner
+            .exchange_pair_map
+            .insert("independentreserve".to_string(), vec![seller.clone()]);
+        assert!(inner.validate().is_ok());
+        inner.exchange_pair_map.insert(
+            "independentreserve".to_string(),
+            vec![seller, pair("USDT-AUD")],
+        );
+        assert!(inner.validate().is_err());
+        inner.exchange_pair_map.clear();
+        // one pair at two depths
+        let depth = |depth, interval_ms| ExchangeSetting {
+            depth,
+            interval_ms,
+            ..pair("btcusdt")
+        };
+        for (settings, ok) in [
+            (
+                vec![depth(Some(5), None), depth(Some(20), Some(1000))],
+                true,
+            ),
+            (vec![depth(Some(5), None), depth(Some(5), Some(1000))], true),
+            (vec![depth(Some(5), None), depth(Some(5), None)], false),
+            (vec![depth(Some(15), None)], false),
+            (vec![depth(None, Some(250))], false),
+        ] {
+            inner
+                .exchange_pair_map
+                .insert("binance".to_string(), settings);
+            assert_eq!(inner.validate().is_ok(), ok);
+        }
+        inner.exchange_pair_map.clear();
+        inner
+            .exchange_pair_map
+            .insert("kraken".to_string(), vec![depth(Some(5), None)]);
+        assert!(inner.validate().is_err());
+        inner.exchange_pair_map.clear();
+        let probe = |name: &str, ping_probe_secs| ExchangeSetting {
+            ping_probe_secs,
+            ..pair(name)
+        };
+        for (settings, ok) in [
+            (
+                vec![probe("BTC-AUD", Some(30)), probe("ETH-AUD", Some(30))],
+                true,
+            ),
+            (
+                vec![probe("BTC-AUD", Some(30)), probe("ETH-AUD", None)],
+                false,
+            ),
+            (vec![probe("BTC-AUD", Some(0))], false),
+        ] {
+            inner
+                .exchange_pair_map
+                .insert("btcmarkets".to_string(), settings);
+            assert_eq!(inner.validate().is_ok(), ok);
+        }
+        inner.exchange_pair_map.clear();
+        let audit = |interval_secs, resync_bps| AuditSetting {
+            interval_secs,
+            tolerance_bps: 1.,
+            top_n: 5,
+            resync_bps,
+        };
+        inner
+            .exchange_pair_map
+            .insert("btcmarkets".to_string(), vec![pair("BTC-AUD")]);
+        inner
+            .exchange_pair_map
+            .insert("coinjar".to_string(), vec![pair("BTCAUD")]);
+        for (exchange, setting, ok) in [
+            ("btcmarkets", audit(60, Some(20.)), true),
+            ("btcmarkets", audit(5, None), false),
+            ("btcmarkets", audit(60, Some(0.5)), false),
+            // no rest orderbook
+            ("coinjar", audit(60, None), false),
+            ("kraken", audit(60, None), false),
+        ] {
+            inner.audit = Some(HashMap::from([(exchange.to_string(), setting)]));
+            assert_eq!(inner.validate().is_ok(), ok, "{}", exchange);
+        }
+        inner.audit = None;
+        inner.exchange_pair_map.clear();
+        inner.degraded_factor = 1.;
+        assert!(inner.validate().is_err());
+        inner.degraded_factor = 5.;
+        assert_eq!(inner.listeners().len(), 1);
+        assert_eq!(inner.listeners()[0].addr, "0.0.0.0");
+        // the admin routes on a listener configured for them only
+        assert!(!inner.listeners()[0].serves(RouteGroup::Admin));
+        let listener = |addr: &str, port| ListenerSetting {
+            addr: addr.to_string(),
+            port,
+            tls: None,
+            routes: None,
+            redistributable_only: false,
+        };
+        inner.listeners = vec![listener("127.0.0.1", 8080), listener("10.8.0.1", 8080)];
+        assert!(inner.validate().is_err());
+        inner.bind_addr = None;
+        assert!(inner.validate().is_ok());
+        inner.listeners.push(listener("0.0.0.0", 8080));
+        assert!(inner.validate().is_err());
+        inner.listeners[2].port = 8443;
+        inner.listeners[2].routes = Some(vec![]);
+        assert!(inner.validate().is_err());
+        inner.listeners[2].routes = Some(vec![RouteGroup::Admin]);
+        assert!(inner.validate().is_ok());
+        // ephemeral ports never clash
+        inner.listeners = vec![listener("127.0.0.1", 0), listener("127.0.0.1", 0)];
+        assert!(inner.validate().is_ok());
+        inner.exchange_pair_map.insert(
+            "binance".to_string(),
+            vec![serde_yaml::from_str("{pair: btcusdt}").unwrap()],
+        );
+        let group = |exchange: &str, pair: &str| GroupSetting {
+            exchanges: HashMap::from([(exchange.to_string(), pair.to_string())]),
+            detector: None,
+            consolidated_levels: None,
+            output_precision: None,
+        };
+        inner.groups = HashMap::from([("usd".to_string(), group("binance", "btcusdt"))]);
+        assert!(inner.validate().is_ok());
+        // only the configured pairs can be grouped
+        inner.groups = HashMap::from([("usd".to_string(), group("binance", "ethusdt"))]);
+        assert!(inner.validate().is_err());
+        inner.groups = HashMap::from([("usd".to_string(), group("kraken", "btcusdt"))]);
+        assert!(inner.validate().is_err());
+        inner.groups.get_mut("usd").unwrap().exchanges.clear();
+        assert!(inner.validate().is_err());
+        inner.groups.clear();
+
+        let index = |exchange: &str, poll_secs| IndexSource {
+            exchange: exchange.to_string(),
+            symbol: "BTC-USD".to_string(),
+            poll_secs,
+        };
+        inner.index_sources = vec![index("okx", 10), index("kraken", 10)];
+        assert!(inner.validate().is_ok());
+        inner.index_sources.push(index("okx", 5));
+        assert!(inner.validate().is_err());
+        inner.index_sources = vec![index("bitstamp", 10)];
+        assert!(inner.validate().is_err());
+        inner.index_sources = vec![index("okx", 0)];
+        assert!(inner.validate().is_err());
+        inner.index_sources.clear();
+
+        let mut meta: SymbolMetaSetting =
+            serde_yaml::from_str("defaults: {coinspot: {tick_size: 0.01, lot_size: 0.00000001}}")
+                .unwrap();
+        assert_eq!(meta.ttl_secs, 86400);
+        inner.symbol_meta = Some(meta.clone());
+        assert!(inner.validate().is_ok());
+        meta.defaults.get_mut("coinspot").unwrap().lot_size = 0.;
+        inner.symbol_meta = Some(meta.clone());
+        assert!(inner.validate().is_err());
+        meta.defaults.clear();
+        meta.retry_secs = 0;
+        inner.symbol_meta = Some(meta);
+        assert!(inner.validate().is_err());
+    }
+    #[test]
+    fn test_redacted() {
+        let alerts = r#"
+rules: []
+webhook:
+  url: "https://hooks.example.com/services/webhook-token"
+  secret: "hmac-secret"
+telegram:
+  bot_token: "123:bot-token"
+  chat_id: "42"
+"#;
+        let nats =
+            r#"{servers: ["nats://127.0.0.1:4222"], user: "arb", password: "nats-password"}"#;
+        let luno = r#"{pair: XBTZAR, credentials: {key_id: "luno-id", secret: "luno-secret"}}"#;
+        let mut inner = InnerConfig {
+            alerts: Some(serde_yaml::from_str(alerts).unwrap()),
+            nats: Some(serde_yaml::from_str(nats).unwrap()),
+            admin_token: Some("admin-token".to_string()),
+            ..InnerConfig::default()
+        };
+        inner.exchange_pair_map.insert(
+            "luno".to_string(),
+            vec![serde_yaml::from_str(luno).unwrap()],
+        );
+        let config = serde_json::to_value(&inner).unwrap();
+        let text = config.to_string();
+        for secret in [
+            "webhook-token",
+            "hmac-secret",
+            "bot-token",
+            "nats-password",
+            "luno-secret",
+            "admin-token",
+        ] {
+            assert!(!text.contains(secret), "{} in {}", secret, text);
+        }
+        assert_eq!(config["alerts"]["webhook"]["url"], "***");
+        assert_eq!(config["alerts"]["webhook"]["secret"], "***");
+        assert_eq!(config["alerts"]["telegram"]["bot_token"], "***");
+        assert_eq!(config["nats"]["password"], "***");
+        assert_eq!(config["admin_token"], "***");
+        // the rest as loaded
+        assert_eq!(config["alerts"]["telegram"]["chat_id"], "42");
+        assert_eq!(config["nats"]["user"], "arb");
+        let credentials = &config["exchange_pair_map"]["luno"][0]["credentials"];
+        assert_eq!(credentials["key_id"], "luno-id");
+        assert_eq!(credentials["secret"], "***");
+        // and as the settings are logged at startup
+        let logged = format!("{:?}", inner.exchange_pair_map["luno"]);
+        assert!(logged.contains("luno-id") && !logged.contains("luno-secret"));
+        assert!(inner.validate().is_ok());
+        // one pair a connection
+        let mut second: ExchangeSetting = serde_yaml::from_str(luno).unwrap();
+        second.pair = "ETHZAR".to_string();
+        inner
+            .exchange_pair_map
+            .get_mut("luno")
+            .unwrap()
+            .push(second);
+        let e = inner.validate().unwrap_err();
+        assert!(
+            e.to_string().contains("a connection streams 1 at most"),
+            "{}",
+            e
+        );
+        inner.exchange_pair_map.get_mut("luno").unwrap().pop();
+        // luno streams nothing without them
+        inner.exchange_pair_map.get_mut("luno").unwrap()[0].credentials = None;
+        assert!(inner.validate().is_err());
+        // an unset secret stays unset
+        inner.nats.as_mut().unwrap().password = None;
+        assert!(serde_json::to_value(&inner).unwrap()["nats"]["password"].is_null());
+    }
+    #[test]
+    fn test_orient() {
+        assert_eq!(reversed("BTC-AUD", "audbtc"), Some(true));
+        assert_eq!(reversed("BTC-AUD", "AUD/BTC"), Some(true));
+        assert_eq!(reversed("BTC-USD", "USD/XBT"), Some(true));
+        assert_eq!(reversed("BTC-AUD", "btcaud"), Some(false));
+        assert_eq!(reversed("BTC-AUD", "ethaud"), None);
+        assert_eq!(reversed("BTCAUD", "audbtc"), None);
+
+        let mut inner = InnerConfig {
+            pair: Some("BTC-AUD".to_string()),
+            ..Default::default()
+        };
+        inner.exchange_pair_map.insert(
+            "coinspot".to_string(),
+            vec![ExchangeSetting::new("AUD/BTC")],
+        );
+        inner.exchange_pair_map.insert(
+            "independentreserve".to_string(),
+            vec![ExchangeSetting::new("XBT-AUD")],
+        );
+        assert!(inner.validate().is_ok());
+        inner.orient();
+        assert!(inner.exchange_pair_map["coinspot"][0].invert);
+        assert!(!inner.exchange_pair_map["independentreserve"][0].invert);
+        // a native symbol set to invert is a mistake
+        inner
+            .exchange_pair_map
+            .get_mut("independentreserve")
+            .unwrap()[0]
+            .invert = true;
+        assert!(inner.validate().is_err());
+        inner
+            .exchange_pair_map
+            .get_mut("independentreserve")
+            .unwrap()[0]
+            .invert = false;
+        inner.exchange_pair_map.get_mut("coinspot").unwrap()[0].invert_precision = 0;
+        assert!(inner.validate().is_err());
+        // no logical pair, nothing to tell
+        inner.exchange_pair_map.get_mut("coinspot").unwrap()[0].invert_precision = 12;
+        inner.pair = None;
+        inner
+            .exchange_pair_map
+            .get_mut("independentreserve")
+            .unwrap()[0]
+            .invert = true;
+        assert!(inner.validate().is_ok());
+    }
+    #[test]
+    fn test_min_exchanges() {
+        assert_eq!(MinExchanges::Count(2).of(5).unwrap(), 2);
+        // never more than configured, never none
+        assert_eq!(MinExchanges::Count(7).of(5).unwrap(), 5);
+        assert_eq!(MinExchanges::Count(0).of(5).unwrap(), 1);
+        assert_eq!(MinExchanges::Percent("60%".to_string()).of(5).unwrap(), 3);
+        assert_eq!(MinExchanges::Percent("50%".to_string()).of(5).unwrap(), 3);
+        assert!(MinExchanges::Percent("60".to_string()).of(5).is_err());
     }
 }
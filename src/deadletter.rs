@@ -0,0 +1,144 @@
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+
+// a frame the parsers failed on, kept whole
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub exchange: String,
+    // unix millis of the receipt
+    pub timestamp: u128,
+    pub error: String,
+    pub raw: String,
+}
+
+struct Buffer {
+    capacity: usize,
+    // oldest first
+    entries: VecDeque<DeadLetter>,
+    // failures per exchange since start, evicted ones included
+    totals: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub capacity: usize,
+    pub totals: BTreeMap<String, u64>,
+    // oldest first
+    pub entries: Vec<DeadLetter>,
+}
+
+impl Buffer {
+    fn new(capacity: usize) -> Buffer {
+        Buffer {
+            capacity,
+            entries: VecDeque::new(),
+            totals: BTreeMap::new(),
+        }
+    }
+
+    // 0 keeps no frame, the failures are still counted
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    fn push(&mut self, letter: DeadLetter) {
+        *self.totals.entry(letter.exchange.clone()).or_default() += 1;
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(letter);
+    }
+
+    // the kept frames, of one exchange when given
+    fn report(&self, exchange: Option<&str>) -> Report {
+        Report {
+            capacity: self.capacity,
+            totals: self.totals.clone(),
+            entries: self
+                .entries
+                .iter()
+                .filter(|e| exchange.is_none_or(|name| e.exchange == name))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+static BUFFER: Lazy<Mutex<Buffer>> = Lazy::new(|| Mutex::new(Buffer::new(100)));
+
+pub fn set_capacity(capacity: usize) {
+    BUFFER.lock().unwrap().set_capacity(capacity);
+}
+
+pub fn push(exchange: &str, error: String, raw: &str, now: u128) {
+    BUFFER.lock().unwrap().push(DeadLetter {
+        exchange: exchange.to_string(),
+        timestamp: now,
+        error,
+        raw: raw.to_string(),
+    });
+}
+
+pub fn report(exchange: Option<&str>) -> Report {
+    BUFFER.lock().unwrap().report(exchange)
+}
+
+// prometheus text exposition of the failure counters
+pub fn prometheus() -> String {
+    let mut out = String::new();
+    let name = "arb_monitor_parse_errors_total";
+    let _ = writeln!(out, "# HELP {} frames the parsers failed on", name);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (exchange, count) in BUFFER.lock().unwrap().totals.iter() {
+        let _ = writeln!(out, "{}{{exchange=\"{}\"}} {}", name, exchange, count);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_letters() {
+        let mut buffer = Buffer::new(3);
+        for i in 0..5u128 {
+            buffer.push(DeadLetter {
+                exchange: if i % 2 == 0 { "even" } else { "odd" }.to_string(),
+                timestamp: i,
+                error: format!("error {}", i),
+                raw: format!("{{\"frame\":{}", i),
+            });
+        }
+        let all = buffer.report(None);
+        assert_eq!(all.capacity, 3);
+        // the oldest are gone, the counts keep them
+        let kept: Vec<u128> = all.entries.iter().map(|e| e.timestamp).collect();
+        assert_eq!(kept, vec![2, 3, 4]);
+        assert_eq!(all.totals["even"], 3);
+        assert_eq!(all.totals["odd"], 2);
+        assert_eq!(all.entries[2].raw, "{\"frame\":4");
+        assert_eq!(all.entries[2].error, "error 4");
+
+        let odd = buffer.report(Some("odd"));
+        assert_eq!(odd.entries.len(), 1);
+        assert_eq!(odd.entries[0].timestamp, 3);
+        assert!(buffer.report(Some("none")).entries.is_empty());
+
+        buffer.set_capacity(1);
+        assert_eq!(buffer.report(None).entries.len(), 1);
+        buffer.set_capacity(0);
+        buffer.push(all.entries[1].clone());
+        assert!(buffer.report(None).entries.is_empty());
+        assert_eq!(buffer.report(None).totals["odd"], 3);
+    }
+}
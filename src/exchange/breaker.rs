@@ -0,0 +1,211 @@
+// Circuit breaker of a polled venue. failure_threshold failed polls in a
+// row, or a single 429, open it: the polls are skipped for a cool-off that
+// doubles with every open in a row, up to max_cool_off_secs, and lasts at
+// least the Retry-After of the 429. The first poll past it is a probe (half
+// open), its success closes the circuit and its failure opens it again.
+use crate::apitree::restapi::HttpError;
+use crate::config::BreakerSetting;
+use crate::orderbook::get_unixtime;
+use serde::Serialize;
+use tokio::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    #[default]
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+// what the status registry shows of a breaker
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BreakerStatus {
+    pub state: BreakerState,
+    // unix millis the cool-off ends, while open
+    pub until: Option<u128>,
+    // times opened since process start
+    pub opened: u64,
+}
+
+#[derive(Debug)]
+pub struct Breaker {
+    setting: BreakerSetting,
+    state: BreakerState,
+    // failed polls in a row while closed
+    failures: u32,
+    // opens in a row, the exponent of the cool-off
+    opens: u32,
+    opened: u64,
+    until: Instant,
+}
+
+impl Breaker {
+    pub fn new(setting: BreakerSetting) -> Self {
+        Self {
+            setting,
+            state: BreakerState::Closed,
+            failures: 0,
+            opens: 0,
+            opened: 0,
+            until: Instant::now(),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.state
+    }
+
+    pub fn status(&self, now: Instant) -> BreakerStatus {
+        let until = (self.state == BreakerState::Open)
+            .then(|| get_unixtime() + self.until.saturating_duration_since(now).as_millis());
+        BreakerStatus {
+            state: self.state,
+            until,
+            opened: self.opened,
+        }
+    }
+
+    // whether to poll at `now`, the first poll past the cool-off is the probe
+    pub fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            BreakerState::Open if now < self.until => false,
+            BreakerState::Open => {
+                self.state = BreakerState::HalfOpen;
+                true
+            }
+            _ => true,
+        }
+    }
+
+    // a poll went through. True when that closed the circuit.
+    pub fn success(&mut self) -> bool {
+        let closed = self.state != BreakerState::Closed;
+        self.state = BreakerState::Closed;
+        self.failures = 0;
+        self.opens = 0;
+        closed
+    }
+
+    // a poll failed with `e`. The cool-off when that opened the circuit.
+    pub fn failure(&mut self, now: Instant, e: &anyhow::Error) -> Option<Duration> {
+        let throttled = e.downcast_ref::<HttpError>().filter(|e| e.status == 429);
+        self.failures += 1;
+        let open = throttled.is_some()
+            || self.state == BreakerState::HalfOpen
+            || self.failures >= self.setting.failure_threshold;
+        if !open {
+            return None;
+        }
+        let max = Duration::from_secs(self.setting.max_cool_off_secs);
+        let mut cool_off = Duration::from_secs(self.setting.cool_off_secs)
+            .saturating_mul(1 << self.opens.min(16))
+            .min(max);
+        // the venue knows best when it takes calls again
+        if let Some(after) = throttled.and_then(|e| e.retry_after) {
+            cool_off = cool_off.max(after);
+        }
+        self.state = BreakerState::Open;
+        self.failures = 0;
+        self.opens += 1;
+        self.opened += 1;
+        self.until = now + cool_off;
+        Some(cool_off)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn http(status: u16, retry_after: Option<u64>) -> anyhow::Error {
+        anyhow::Error::new(HttpError {
+            url: "http://venue/book".to_string(),
+            status,
+            retry_after: retry_after.map(Duration::from_secs),
+        })
+    }
+
+    #[test]
+    fn test_transitions() {
+        let setting = BreakerSetting {
+            failure_threshold: 3,
+            cool_off_secs: 5,
+            max_cool_off_secs: 12,
+        };
+        let mut breaker = Breaker::new(setting);
+        let t0 = Instant::now();
+        let secs = |s: u64| t0 + Duration::from_secs(s);
+        // a success in between starts the count over
+        assert_eq!(breaker.failure(t0, &http(502, None)), None);
+        assert_eq!(breaker.failure(t0, &anyhow!("timed out")), None);
+        assert!(!breaker.success());
+        assert_eq!(breaker.failure(t0, &http(502, None)), None);
+        assert_eq!(breaker.failure(t0, &http(502, None)), None);
+        assert_eq!(
+            breaker.failure(t0, &http(503, None)),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.allow(secs(4)));
+        assert!(breaker.status(secs(4)).until.is_some());
+        // the probe fails, twice as long
+        assert!(breaker.allow(secs(5)));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert_eq!(
+            breaker.failure(secs(5), &http(502, None)),
+            Some(Duration::from_secs(10))
+        );
+        assert!(!breaker.allow(secs(14)));
+        // capped
+        assert!(breaker.allow(secs(15)));
+        assert_eq!(
+            breaker.failure(secs(15), &http(502, None)),
+            Some(Duration::from_secs(12))
+        );
+        // the probe goes through
+        assert!(breaker.allow(secs(27)));
+        assert!(breaker.success());
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        let status = breaker.status(secs(27));
+        assert_eq!((status.until, status.opened), (None, 3));
+        // the cool-off starts over from cool_off_secs
+        for _ in 0..2 {
+            assert_eq!(breaker.failure(secs(28), &http(500, None)), None);
+        }
+        assert_eq!(
+            breaker.failure(secs(28), &http(500, None)),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn test_rate_limited() {
+        let setting = BreakerSetting {
+            failure_threshold: 5,
+            cool_off_secs: 5,
+            max_cool_off_secs: 60,
+        };
+        let mut breaker = Breaker::new(setting);
+        let t0 = Instant::now();
+        // opens on the first 429, for its Retry-After even past the cap
+        assert_eq!(
+            breaker.failure(t0, &http(429, Some(90))),
+            Some(Duration::from_secs(90))
+        );
+        assert!(!breaker.allow(t0 + Duration::from_secs(89)));
+        assert!(breaker.allow(t0 + Duration::from_secs(90)));
+        assert!(breaker.success());
+        // a shorter one than the cool-off, or none, leaves the cool-off
+        assert_eq!(
+            breaker.failure(t0, &http(429, Some(1))),
+            Some(Duration::from_secs(5))
+        );
+        assert!(breaker.success());
+        assert_eq!(
+            breaker.failure(t0, &http(429, None)),
+            Some(Duration::from_secs(5))
+        );
+    }
+}
@@ -1,68 +1,429 @@
-use crate::apitree;
-use crate::config::ExchangeSetting;
-use crate::orderbook::Orderbook;
+use crate::analytics::traded;
+use crate::apitree::{self, wsapi, wsapi::Heartbeat, wsapi::ParseOutcome};
+use crate::chaos::Injector;
+use crate::config::{ExchangeSetting, Sides};
+use crate::deadletter;
+use crate::orderbook::{get_unixtime, Orderbook, Side};
+use crate::sink::statsd;
+use crate::status;
+use crate::timings::{self, Stage};
 use actix_http::ws::Item::*;
 use anyhow::{anyhow, Result};
 use awc::ws::Frame::*;
+use bigdecimal::BigDecimal;
 use formatx::formatx;
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, error, info};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
-use tokio::time::{sleep, Duration, Instant};
+use tokio::time::{Duration, Instant};
 
+pub mod breaker;
+pub mod poller;
+
+// pings left without a pong before the probe gives up on a venue
+const PING_ATTEMPTS: u32 = 3;
+// levels per side forwarded of a book
+const LEVEL: u32 = 10;
+// wait after a failed reconnect before the next attempt
+pub const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+// wait after a failed rest snapshot, doubled per failure in a row
+const SEED_BACKOFF: Duration = Duration::from_secs(1);
+const SEED_BACKOFF_MAX: Duration = Duration::from_secs(60);
+// the longest a read blocks while snapshots are outstanding
+const SEED_POLL: Duration = Duration::from_millis(100);
+
+/// An error no reconnect fixes, like an exchange or a pair the venue
+/// doesn't know. The executor gives up on the exchange instead of retrying.
+#[derive(Debug)]
+pub struct Permanent(pub String);
+
+impl std::fmt::Display for Permanent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Permanent {}
+
+fn permanent(e: anyhow::Error) -> anyhow::Error {
+    anyhow::Error::new(Permanent(e.to_string()))
+}
+
+/// The error is a [`Permanent`] one.
+pub fn is_permanent(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<Permanent>().is_some()
+}
+
+// a pair added to or removed from a running exchange
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PairChange {
+    Add(ExchangeSetting),
+    Remove(String),
+}
+
+// exchange -> pair changes not applied yet
+static CHANGES: Lazy<Mutex<HashMap<String, Vec<PairChange>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Queue a pair change, applied by the executor before its next frame. Err
+// when the venue only changes its subscriptions with a reconnect.
+pub fn change_pairs(exchange: &str, change: PairChange) -> Result<()> {
+    let api = apitree::ws(exchange)?;
+    if api.live_pairs.is_none() {
+        return Err(anyhow!(
+            "{}: pairs can't change on a live connection",
+            exchange
+        ));
+    }
+    if let PairChange::Add(setting) = &change {
+        apitree::check_pair(exchange, &setting.pair)?;
+    }
+    info!("{}: {:?} requested", exchange, change);
+    CHANGES
+        .lock()
+        .unwrap()
+        .entry(exchange.to_string())
+        .or_default()
+        .push(change);
+    Ok(())
+}
+
+// Round trip probe: a ws ping carrying its send time in unix micros, timed
+// when the pong echoes it back.
+struct PingProbe {
+    every: Duration,
+    // payload and send time of the ping waiting for its pong
+    pending: Option<(String, Instant)>,
+    last: Option<Instant>,
+    // pings in a row without a pong
+    misses: u32,
+    disabled: bool,
+}
+
+impl PingProbe {
+    fn new(every: Duration) -> PingProbe {
+        PingProbe {
+            every,
+            pending: None,
+            last: None,
+            misses: 0,
+            disabled: false,
+        }
+    }
+
+    // the payload of the ping to send now, if one is due
+    fn due(&mut self, exchange: &str, now: Instant) -> Option<String> {
+        if self.disabled
+            || self
+                .last
+                .is_some_and(|t| now.duration_since(t) < self.every)
+        {
+            return None;
+        }
+        if self.pending.is_some() {
+            self.misses += 1;
+            if self.misses >= PING_ATTEMPTS {
+                info!(
+                    "{}: no pong to {} pings, round trip probe stopped",
+                    exchange, self.misses
+                );
+                self.disabled = true;
+                self.pending = None;
+                return None;
+            }
+        }
+        let sent = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros()
+            .to_string();
+        self.pending = Some((sent.clone(), now));
+        self.last = Some(now);
+        Some(sent)
+    }
+
+    // the round trip, when the pong answers the pending ping
+    fn pong(&mut self, payload: &[u8], now: Instant) -> Option<Duration> {
+        let (sent, at) = self.pending.as_ref()?;
+        if sent.as_bytes() != payload {
+            return None;
+        }
+        let rtt = now.duration_since(*at);
+        self.pending = None;
+        self.misses = 0;
+        Some(rtt)
+    }
+}
+
+// the rest snapshot of one pair: in flight, or waiting for its next attempt
+#[derive(Default)]
+struct SeedFetch {
+    task: Option<tokio::task::JoinHandle<Result<String>>>,
+    // failures in a row
+    failures: u32,
+    retry: Option<Instant>,
+}
+
+// The rest snapshots of the pairs the parser buffers changes for, fetched
+// in the background. A failed one is logged and retried after a backoff,
+// the connection stays up meanwhile.
+#[derive(Default)]
+struct Seeding {
+    fetches: HashMap<String, SeedFetch>,
+}
+
+impl Seeding {
+    fn idle(&self) -> bool {
+        self.fetches.is_empty()
+    }
+
+    // applies the snapshots fetched since, starts the ones due
+    async fn poll(&mut self, exchange: &str, seed: &wsapi::Seed, now: Instant) {
+        let pending = (seed.pending)();
+        // seeded, cleared or removed meanwhile
+        self.fetches.retain(|pair, fetch| {
+            let keep = pending.contains(pair);
+            if let Some(task) = fetch.task.as_ref().filter(|_| !keep) {
+                task.abort();
+            }
+            keep
+        });
+        for pair in pending {
+            let fetch = self.fetches.entry(pair.clone()).or_default();
+            match fetch.task.take() {
+                Some(task) if !task.is_finished() => fetch.task = Some(task),
+                Some(task) => {
+                    let applied = match task.await {
+                        Ok(body) => body.and_then(|body| (seed.apply)(&pair, &body)),
+                        Err(e) => Err(anyhow!("{}", e)),
+                    };
+                    match applied {
+                        // a pair left pending, ex: by a snapshot older than
+                        // its buffered changes, waits too
+                        Ok(()) => {
+                            fetch.failures = 0;
+                            fetch.retry = Some(now + SEED_BACKOFF);
+                        }
+                        Err(e) => {
+                            let wait = SEED_BACKOFF
+                                .saturating_mul(1 << fetch.failures.min(6))
+                                .min(SEED_BACKOFF_MAX);
+                            fetch.failures += 1;
+                            fetch.retry = Some(now + wait);
+                            error!(
+                                "{}: snapshot of {}, retry in {:?}: {}",
+                                exchange, pair, wait, e
+                            );
+                        }
+                    }
+                }
+                None if fetch.retry.is_some_and(|retry| now < retry) => (),
+                None => {
+                    let url = match formatx!(seed.url.to_string(), pair.clone()) {
+                        Ok(url) => url,
+                        Err(e) => {
+                            error!("{}: snapshot url of {}: {:?}", exchange, pair, e);
+                            fetch.retry = Some(now + SEED_BACKOFF_MAX);
+                            continue;
+                        }
+                    };
+                    info!("{}: seeding {} from {}", exchange, pair, url);
+                    fetch.task = Some(tokio::spawn(async move {
+                        let response = reqwest::get(&url).await?.error_for_status()?;
+                        Ok(response.text().await?)
+                    }));
+                }
+            }
+        }
+    }
+}
+
+/// A websocket connection to one exchange, yielding its books:
+/// `new` -> `connect` -> `next` until an error, then `clear` and a new one.
 pub struct Exchange {
     name: String,
+    // the endpoint of the api, unless overridden
+    endpoint: Option<String>,
     client: awc::Client,
-    level: u32,
+    // pair -> levels per side kept of its books
+    levels: HashMap<String, u32>,
     connection: Option<actix_codec::Framed<awc::BoxedSocket, awc::ws::Codec>>,
     cache: String,
-    ws_api: bool,
+    sides: Sides,
     pairs: Vec<String>,
-    wait_secs: u64,
+    settings: Vec<ExchangeSetting>,
+    // pairs subscribed to the trade channel
+    trade_pairs: Vec<String>,
+    // stream -> depth, of the pairs subscribed at several depths
+    depths: HashMap<String, u32>,
+    // pair -> books of each depth
+    ladders: HashMap<String, Ladder>,
     heartbeat_ts: Option<Instant>,
+    // the interval the venue asked for at bootstrap
+    heartbeat_interval: Option<Duration>,
     reconnect_ts: Option<Instant>,
+    // receipt of the frame behind the latest book
+    received: Option<Instant>,
+    chaos: Injector,
+    probe: Option<PingProbe>,
+    seeding: Seeding,
 }
 
 impl Exchange {
+    /// A connector of the exchange `name`, ex: binance, see
+    /// [`apitree::ws`] for the supported ones.
     pub fn new(name: &str) -> Exchange {
         let client = awc::Client::builder()
             .max_http_version(awc::http::Version::HTTP_11)
             .finish();
         Exchange {
             name: name.to_string(),
+            endpoint: None,
             client,
-            level: 10,
+            levels: HashMap::new(),
             connection: None,
             cache: "".to_string(),
-            ws_api: true,
+            sides: Sides::Both,
             pairs: vec![],
-            wait_secs: 0,
+            settings: vec![],
+            trade_pairs: vec![],
+            depths: HashMap::new(),
+            ladders: HashMap::new(),
             heartbeat_ts: None,
+            heartbeat_interval: None,
             reconnect_ts: None,
+            received: None,
+            chaos: Injector::new(name),
+            probe: None,
+            seeding: Seeding::default(),
         }
     }
+
+    // levels kept of a book of `pair`, the first pair's for a book that
+    // doesn't tell its pair
+    fn level(&self, pair: Option<&str>) -> u32 {
+        pair.or(self.pairs.first().map(String::as_str))
+            .and_then(|p| self.levels.get(p))
+            .copied()
+            .unwrap_or(LEVEL)
+    }
+    // The configured pair of a book. The venues naming a pair their own way
+    // tag their books with the channel, see Api::channel.
+    fn configured_pair(&self, api: &wsapi::Api, pair: Option<&str>) -> Option<String> {
+        let pair = pair?;
+        let configured = api.normalize_pair.and_then(|_| {
+            self.pairs
+                .iter()
+                .find(|p| api.channel(p).is_ok_and(|c| c == pair))
+        });
+        Some(configured.map_or(pair, String::as_str).to_string())
+    }
+    // significant digits of the inverted books of `pair`, None when its
+    // books aren't inverted. The first pair's for a book that doesn't tell
+    // its pair.
+    fn inversion(&self, pair: Option<&str>) -> Option<u64> {
+        let pair = pair.or(self.pairs.first().map(String::as_str))?;
+        self.settings
+            .iter()
+            .find(|s| s.pair == pair && s.invert)
+            .map(|s| s.invert_precision)
+    }
+    /// Receipt of the frame behind the latest book.
+    pub fn received(&self) -> Option<Instant> {
+        self.received
+    }
+    /// The pairs subscribed to, with the ones changed since connect.
+    pub fn settings(&self) -> Vec<ExchangeSetting> {
+        self.settings.clone()
+    }
+    /// Dial the venue and subscribe to `pairs`. [`Permanent`] when no
+    /// retry helps, ex: an unknown pair.
     pub async fn connect(&mut self, pairs: Vec<ExchangeSetting>) -> Result<()> {
-        self.pairs = pairs.iter().map(|e| e.pair.clone()).collect();
+        self.pairs = vec![];
+        for setting in pairs.iter() {
+            if !self.pairs.contains(&setting.pair) {
+                self.pairs.push(setting.pair.clone());
+            }
+        }
+        self.settings = pairs.clone();
+        // the deepest of a pair listed at several depths
+        self.levels = HashMap::new();
+        for setting in pairs.iter() {
+            let level = self.levels.entry(setting.pair.clone()).or_insert(0);
+            *level = (*level).max(setting.level.unwrap_or(LEVEL));
+        }
+        self.trade_pairs = pairs
+            .iter()
+            .filter(|e| e.trades)
+            .map(|e| e.pair.clone())
+            .collect();
         let default_setup = pairs
-            .get(0)
+            .first()
             .ok_or_else(|| anyhow!("should have at least one pair setting"))?;
-        // wait_secs here is only used in rest api
-        self.wait_secs = if default_setup.wait_secs > 0 {
-            default_setup.wait_secs
-        } else {
-            1_u64
-        };
-        self.ws_api = default_setup.ws_api;
-        if !self.ws_api {
-            return Ok(());
+        // the rest venues go to the poller
+        if !default_setup.ws_api {
+            return Err(permanent(anyhow!("{} is polled over rest", self.name)));
+        }
+        self.sides = default_setup.sides;
+        if default_setup.endpoint.is_some() {
+            self.endpoint = default_setup.endpoint.clone();
         }
+        self.probe = default_setup
+            .ping_probe_secs
+            .map(|secs| PingProbe::new(Duration::from_secs(secs)));
         info!("start connect, {}", self.name);
-        let api = apitree::ws(&self.name)?;
+        let api = apitree::ws(&self.name).map_err(permanent)?;
+        // checked before dialing, a retry won't make the venue list them
+        for setting in pairs.iter() {
+            self.book_streams(api, setting).map_err(permanent)?;
+        }
+        apitree::check_login(&self.name, default_setup.credentials.as_ref()).map_err(permanent)?;
+        for pair in self.pairs.iter() {
+            let settings: Vec<&ExchangeSetting> =
+                pairs.iter().filter(|s| &s.pair == pair).collect();
+            if settings.len() < 2 {
+                continue;
+            }
+            for setting in settings {
+                let (streams, depth) = self.book_streams(api, setting)?;
+                for stream in streams {
+                    self.depths.insert(stream, depth);
+                }
+            }
+        }
 
-        let mut url = api.endpoint.to_string();
+        let mut url = self.endpoint.as_deref().unwrap_or(api.endpoint).to_string();
+        // a fresh token on every connect, the reconnects included
+        if let Some(bootstrap) = api.bootstrap {
+            info!("{}: bootstrap from {}", self.name, url);
+            let response = reqwest::Client::new()
+                .post(&url)
+                .send()
+                .await?
+                .error_for_status()?;
+            let resolved = bootstrap(&response.text().await?)?;
+            url = resolved.url;
+            self.heartbeat_interval = resolved.heartbeat;
+        }
         let render_url = api.render_url;
         if render_url {
-            let p = self.pairs.join(",");
+            let p = if api.combined_stream {
+                let mut streams = vec![];
+                for pair in self.pairs.iter() {
+                    streams.extend(self.subscriptions(api, pair)?);
+                }
+                streams.join("/")
+            } else {
+                let channels: Result<Vec<String>> =
+                    self.pairs.iter().map(|p| api.channel(p)).collect();
+                channels?.join(",")
+            };
 
             info!("render Url: {}", p);
             url = formatx!(url, p).map_err(|e| anyhow!("{:?}", e))?;
@@ -76,9 +437,21 @@ impl Exchange {
             .await
             .map_err(|e| anyhow!("connection error: {:?}", e))?;
         info!("{:?}", result);
+        // not logged, it carries the secret
+        if let (Some(login), Some(credentials)) = (api.login, &default_setup.credentials) {
+            let text = formatx!(
+                login.to_string(),
+                credentials.key_id.clone(),
+                credentials.secret.clone()
+            )
+            .map_err(|e| anyhow!("{:?}", e))?;
+            conn.send(awc::ws::Message::Text(text.into()))
+                .await
+                .map_err(|e| anyhow!("{:?}", e))?;
+        }
         if !render_url {
             for pair in self.pairs.iter() {
-                let requests = api.subscribe_text(pair, 20)?;
+                let requests = self.subscriptions(api, pair)?;
                 info!("{:?}", requests);
                 for request in requests {
                     conn.send(awc::ws::Message::Text(request.into()))
@@ -92,55 +465,174 @@ impl Exchange {
         self.connection = Some(conn);
         Ok(())
     }
-    pub fn clear(&self) -> Result<()> {
+    // subscription messages, or stream names of a combined stream, of a pair
+    fn subscriptions(&self, api: &wsapi::Api, pair: &str) -> Result<Vec<String>> {
+        let mut result: Vec<String> = vec![];
+        if api.live_pairs.is_some() && self.pairs.first().is_some_and(|p| p != pair) {
+            // the pairs after the first one are added to its subscription
+            let level = api.subscribe_level(self.levels.get(pair).copied());
+            result = api.add_text(&api.channel(pair)?, level)?;
+        } else {
+            for setting in self.settings.iter().filter(|s| s.pair == pair) {
+                for text in self.book_streams(api, setting)?.0 {
+                    if !result.contains(&text) {
+                        result.push(text);
+                    }
+                }
+            }
+        }
+        if self.trade_pairs.iter().any(|p| p == pair) {
+            let level = wsapi::SUBSCRIBE_LEVEL;
+            result.extend(api.trade_subscribe_text(&api.channel(pair)?, level)?);
+        }
+        Ok(result)
+    }
+    // the book subscriptions of one pair setting, with their depth
+    fn book_streams(
+        &self,
+        api: &wsapi::Api,
+        setting: &ExchangeSetting,
+    ) -> Result<(Vec<String>, u32)> {
+        let channel = api.channel(&setting.pair)?;
+        if setting.depth.is_none() && setting.interval_ms.is_none() {
+            let level = api.subscribe_level(setting.level);
+            return Ok((api.subscribe_text(&channel, level)?, level));
+        }
+        let text = api.depth_text(&channel, setting.depth, setting.interval_ms)?;
+        Ok((vec![text], setting.depth.unwrap_or(wsapi::SUBSCRIBE_LEVEL)))
+    }
+    /// Subscribe to one more pair on the live connection.
+    pub async fn add_pair(&mut self, setting: ExchangeSetting) -> Result<()> {
         let api = apitree::ws(&self.name)?;
-        (api.clear)();
-        Ok(())
+        if self.pairs.contains(&setting.pair) {
+            return Err(anyhow!(
+                "{}: {} is subscribed already",
+                self.name,
+                setting.pair
+            ));
+        }
+        if setting.trades || setting.depth.is_some() || setting.interval_ms.is_some() {
+            return Err(anyhow!(
+                "{}: trades, depth and interval_ms need a reconnect",
+                self.name
+            ));
+        }
+        let level = api.subscribe_level(setting.level);
+        let requests = api.add_text(&api.channel(&setting.pair)?, level)?;
+        // kept on a failed send, the reconnect subscribes to it
+        self.pairs.push(setting.pair.clone());
+        self.levels
+            .insert(setting.pair.clone(), setting.level.unwrap_or(LEVEL));
+        self.settings.push(setting);
+        self.send(requests).await
     }
-    pub async fn next(&mut self) -> Result<Option<Orderbook>> {
-        if !self.ws_api {
-            let level = self.level;
-            sleep(Duration::from_secs(self.wait_secs)).await;
-            // only able to handle one pair
-            if let Some(pair) = self.pairs.first() {
-                return (apitree::rest(&self.name)?.orderbook)(pair.clone())
-                    .await
-                    .map(move |mut e| {
-                        e.trim(level);
-                        Some(e)
-                    });
-            }
-            return Err(anyhow!("no pair assigned to the exchange"));
+    /// Unsubscribe from a pair, the others stay up.
+    pub async fn remove_pair(&mut self, pair: &str) -> Result<()> {
+        let api = apitree::ws(&self.name)?;
+        let live = api.live_pairs.as_ref().ok_or_else(|| {
+            anyhow!(
+                "{}: pairs can't be removed from a live connection",
+                self.name
+            )
+        })?;
+        if !self.pairs.iter().any(|p| p == pair) {
+            return Err(anyhow!("{}: {} is not subscribed", self.name, pair));
         }
-        let result = &mut self
+        let channel = api.channel(pair)?;
+        let requests = api.remove_text(&channel, wsapi::SUBSCRIBE_LEVEL)?;
+        self.pairs.retain(|p| p != pair);
+        self.levels.remove(pair);
+        self.settings.retain(|s| s.pair != pair);
+        self.trade_pairs.retain(|p| p != pair);
+        self.send(requests).await?;
+        (live.forget)(&channel);
+        Ok(())
+    }
+    async fn send(&mut self, requests: Vec<String>) -> Result<()> {
+        let conn = self
             .connection
             .as_mut()
             .ok_or_else(|| anyhow!("Not connect yet. Please run connect first"))?;
+        info!("{:?}", requests);
+        for request in requests {
+            conn.send(awc::ws::Message::Text(request.into()))
+                .await
+                .map_err(|e| anyhow!("{:?}", e))?;
+        }
+        Ok(())
+    }
+    /// Drop the parser state of the exchange, before a reconnect.
+    pub fn clear(&self) -> Result<()> {
+        let api = apitree::ws(&self.name)?;
+        (api.clear)();
+        Ok(())
+    }
+    /// The next book, trimmed to the levels of its pair. None when the
+    /// frame carried no book update. An error means the connection is
+    /// lost or out of sync, reconnect.
+    pub async fn next(&mut self) -> Result<Option<Arc<Orderbook>>> {
         let api = apitree::ws(&self.name)?;
-        let (wait_secs, msg) = api.heartbeat.unwrap_or((0, ""));
+        let (wait_secs, msg) = api.heartbeat.unwrap_or((0, Heartbeat::Binary("")));
+        let wait = self
+            .heartbeat_interval
+            .unwrap_or(Duration::from_secs(wait_secs));
         let reconn_secs = api.reconnect_sec.unwrap_or(0);
         info!("reconn_secs: {}", reconn_secs);
-        if self.heartbeat_ts.is_none() && wait_secs > 0 {
+        if self.heartbeat_ts.is_none() && !wait.is_zero() {
             self.heartbeat_ts = Some(Instant::now());
         }
         if self.reconnect_ts.is_none() && reconn_secs > 0 {
             self.reconnect_ts = Some(Instant::now());
         }
+        let changes = CHANGES
+            .lock()
+            .unwrap()
+            .remove(&self.name)
+            .unwrap_or_default();
+        for change in changes {
+            info!("{}: {:?}", self.name, change);
+            // a broken connection fails the next read anyway
+            if let Err(e) = match change {
+                PairChange::Add(setting) => self.add_pair(setting).await,
+                PairChange::Remove(pair) => self.remove_pair(&pair).await,
+            } {
+                error!("{}", e);
+            }
+        }
+        let result = &mut self
+            .connection
+            .as_mut()
+            .ok_or_else(|| anyhow!("Not connect yet. Please run connect first"))?;
         loop {
             // sending heartbeats
             if let Some(now) = self.heartbeat_ts {
-                if wait_secs < now.elapsed().as_secs() {
+                if wait < now.elapsed() {
                     info!("send heartbeat to {}", self.name);
                     self.heartbeat_ts = Some(Instant::now());
-                    if let Err(e) = result
-                        .send(awc::ws::Message::Binary(msg.into()))
-                        .await
-                        .map(|e| info!("{:?}", e))
-                    {
+                    let frame = match msg {
+                        Heartbeat::Binary(msg) => awc::ws::Message::Binary(msg.into()),
+                        Heartbeat::Text(msg) => awc::ws::Message::Text(msg.into()),
+                    };
+                    if let Err(e) = result.send(frame).await.map(|e| info!("{:?}", e)) {
                         error!("heartbeat: {}", e);
                     }
                 }
             }
+            // timed on the way back, in the same loop as the frames
+            if let Some(payload) = self
+                .probe
+                .as_mut()
+                .and_then(|probe| probe.due(&self.name, Instant::now()))
+            {
+                if let Err(e) = result.send(awc::ws::Message::Ping(payload.into())).await {
+                    error!("ping: {:?}", e);
+                }
+            }
+            // books waiting for their rest snapshot, the parser buffers
+            // their changes meanwhile
+            if let Some(seed) = &api.seed {
+                self.seeding.poll(&self.name, seed, Instant::now()).await;
+            }
             if let Some(now) = self.reconnect_ts {
                 if reconn_secs < now.elapsed().as_secs() {
                     // force close the connection
@@ -148,40 +640,858 @@ impl Exchange {
                     return Err(anyhow!("close {}", self.name));
                 }
             }
-            if let Some(result) = result.next().await {
-                let raw = match result? {
-                    Text(msg) => std::str::from_utf8(&msg)?.to_string(),
-                    Binary(msg) => std::str::from_utf8(&msg)?.to_string(),
-                    Continuation(item) => match item {
-                        FirstText(b) | FirstBinary(b) | Continue(b) => {
-                            self.cache += std::str::from_utf8(&b)?;
-                            return Ok(None);
+            let raw = match self.chaos.replay() {
+                Some(raw) => raw,
+                None => {
+                    let frame = if self.seeding.idle() {
+                        Ok(result.next().await)
+                    } else {
+                        tokio::time::timeout(SEED_POLL, result.next()).await
+                    };
+                    match frame {
+                        // a snapshot came in or is due meanwhile
+                        Err(_) => continue,
+                        Ok(Some(result)) => match result? {
+                            Text(msg) => std::str::from_utf8(&msg)?.to_string(),
+                            Binary(msg) => match api.compression {
+                                Some(compression) => compression.inflate(&msg)?,
+                                None => std::str::from_utf8(&msg)?.to_string(),
+                            },
+                            Continuation(item) => match item {
+                                FirstText(b) | FirstBinary(b) | Continue(b) => {
+                                    self.cache += std::str::from_utf8(&b)?;
+                                    return Ok(None);
+                                }
+                                Last(b) => {
+                                    let output = self.cache.clone() + std::str::from_utf8(&b)?;
+                                    self.cache = "".to_string();
+                                    output
+                                }
+                            },
+                            Pong(payload) => {
+                                let rtt = self
+                                    .probe
+                                    .as_mut()
+                                    .and_then(|probe| probe.pong(&payload, Instant::now()));
+                                if let Some(rtt) = rtt {
+                                    status::ping_returned(&self.name, rtt.as_secs_f64() * 1e3);
+                                }
+                                continue;
+                            }
+                            Ping(_) => return Ok(None),
+                            Close(_) => {
+                                error!("stream gets closed: {}", self.name);
+                                return Err(anyhow!("close {}", self.name));
+                            }
+                        },
+                        Ok(None) => return Ok(None),
+                    }
+                }
+            };
+            // faults injected for testing, a no-op unless chaos is enabled
+            let Some(raw) = self.chaos.frame(raw).await? else {
+                continue;
+            };
+            // the venue's own ping, answered before anything else
+            if let Some(pong) = api.pong.and_then(|pong| pong(&raw)) {
+                if let Err(e) = result.send(awc::ws::Message::Text(pong.into())).await {
+                    error!("pong: {:?}", e);
+                }
+                continue;
+            }
+            let received = Instant::now();
+
+            debug!("{}: {}", self.name, raw);
+
+            let tags = [("exchange", self.name.as_str())];
+            let (stream, payload) = if api.combined_stream {
+                wsapi::unwrap_stream(&raw)
+            } else {
+                (None, raw.as_str())
+            };
+            let pair = stream.as_deref().map(wsapi::stream_pair);
+            if let Some(parse_trades) = api.parse_trades.filter(|_| !self.trade_pairs.is_empty()) {
+                match parse_trades(payload) {
+                    Ok(Some(mut trades)) => {
+                        statsd::count_sampled("messages.parsed", &tags);
+                        let now = get_unixtime();
+                        // venue time to ours, once the venue clock was probed
+                        for trade in trades.iter_mut() {
+                            trade.timestamp = status::local_time(&self.name, trade.timestamp);
                         }
-                        Last(b) => {
-                            let output = self.cache.clone() + std::str::from_utf8(&b)?;
-                            self.cache = "".to_string();
-                            output
+                        if let Some(last) = trades.iter().map(|t| t.timestamp).max() {
+                            status::venue_message(&self.name, last, now);
                         }
-                    },
-                    Ping(_) | Pong(_) => return Ok(None),
-                    Close(_) => {
-                        error!("stream gets closed: {}", self.name);
-                        return Err(anyhow!("close {}", self.name));
+                        traded::record(&self.name, &trades, now);
+                        return Ok(None);
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        statsd::count("parse.errors", 1, &tags);
+                        error!("{}: {}: raw msg: {}", self.name, e, raw);
+                        deadletter::push(&self.name, e.to_string(), &raw, get_unixtime());
+                        return Ok(None);
+                    }
+                }
+            }
+            let outcome = api.parse_book(pair.clone(), payload);
+            timings::record(Stage::Parse, received);
+            match outcome {
+                ParseOutcome::Book(mut e) => {
+                    statsd::count_sampled("messages.parsed", &tags);
+                    self.received = Some(received);
+                    // one book out of the depths a pair is subscribed at
+                    if let (Some(&depth), Some(pair)) =
+                        (stream.as_ref().and_then(|s| self.depths.get(s)), pair)
+                    {
+                        let ladder = self.ladders.entry(pair).or_default();
+                        e = Arc::new(ladder.update(depth, e));
+                    }
+                    // copy only the sides and levels we publish out of the
+                    // parser's book
+                    let pair = self.configured_pair(api, e.pair.as_deref());
+                    let level = self.level(pair.as_deref());
+                    let n = level as usize;
+                    // once per book, after the trim. The venue's asks are
+                    // the bids of the pair.
+                    if let Some(prec) = self.inversion(pair.as_deref()) {
+                        let mut top = e.top(level, self.sides.flipped()).inverted(prec);
+                        top.pair = pair;
+                        return Ok(Some(Arc::new(top)));
+                    }
+                    if self.sides != Sides::Both || e.bid.len() > n || e.ask.len() > n {
+                        let mut top = e.top(level, self.sides);
+                        top.pair = pair;
+                        return Ok(Some(Arc::new(top)));
                     }
+                    if e.pair != pair {
+                        Arc::make_mut(&mut e).pair = pair;
+                    }
+                    return Ok(Some(e));
+                }
+                ParseOutcome::Ignore => {
+                    statsd::count_sampled("messages.parsed", &tags);
+                }
+                ParseOutcome::Status(venue) => {
+                    statsd::count_sampled("messages.parsed", &tags);
+                    info!("{}: venue status {:?}", self.name, venue);
+                    status::venue_status(&self.name, venue, get_unixtime());
+                }
+                // the connection is still good, keep reading
+                ParseOutcome::ProtocolError(e) => {
+                    statsd::count("parse.errors", 1, &tags);
+                    error!("{}: {}: raw msg: {}", self.name, e, raw);
+                    deadletter::push(&self.name, e, &raw, get_unixtime());
+                }
+                ParseOutcome::FatalDesync(e) => {
+                    statsd::count("parse.desyncs", 1, &tags);
+                    return Err(anyhow!("{}: raw msg: {}", e, raw));
+                }
+            }
+        }
+    }
+}
+
+// The books of a pair subscribed at several depths. Each range of levels,
+// from the top down to a depth, comes from the freshest book covering it:
+// a shallow update refreshes the top, a deep one the levels under it, and
+// the whole ladder when it is the newest.
+#[derive(Default)]
+struct Ladder {
+    // depth -> (arrival, book)
+    books: BTreeMap<u32, (u64, Arc<Orderbook>)>,
+    arrivals: u64,
+}
+
+impl Ladder {
+    fn update(&mut self, depth: u32, book: Arc<Orderbook>) -> Orderbook {
+        self.arrivals += 1;
+        self.books.insert(depth, (self.arrivals, book));
+        // freshest of the books at least `depth` deep, per depth
+        let sources: Vec<(u32, &Arc<Orderbook>)> = self
+            .books
+            .keys()
+            .filter_map(|&depth| {
+                self.books
+                    .range(depth..)
+                    .map(|(_, (arrival, book))| (book.timestamp, *arrival, book))
+                    .max_by_key(|(timestamp, arrival, _)| (*timestamp, *arrival))
+                    .map(|(_, _, book)| (depth, book))
+            })
+            .collect();
+        // the shallowest range is covered by every book
+        let newest = sources[0].1;
+        Orderbook {
+            name: newest.name.clone(),
+            pair: newest.pair.clone(),
+            timestamp: newest.timestamp,
+            volume: newest.volume.clone(),
+            last_price: newest.last_price.clone(),
+            bid: merge_side(&sources, Side::Bid),
+            ask: merge_side(&sources, Side::Ask),
+            derivatives: newest.derivatives.clone(),
+        }
+    }
+}
+
+// levels of each source under the ones already taken, down to its depth
+fn merge_side(sources: &[(u32, &Arc<Orderbook>)], side: Side) -> BTreeMap<BigDecimal, BigDecimal> {
+    let mut merged = BTreeMap::new();
+    for &(depth, book) in sources {
+        let (levels, worst) = match side {
+            Side::Bid => (&book.bid, merged.keys().next()),
+            Side::Ask => (&book.ask, merged.keys().next_back()),
+        };
+        let below = |price: &BigDecimal| match (side, worst) {
+            (_, None) => true,
+            (Side::Bid, Some(worst)) => price < worst,
+            (Side::Ask, Some(worst)) => price > worst,
+        };
+        let best_first: Vec<(&BigDecimal, &BigDecimal)> = match side {
+            Side::Bid => levels.iter().rev().take(depth as usize).collect(),
+            Side::Ask => levels.iter().take(depth as usize).collect(),
+        };
+        let taken: Vec<(BigDecimal, BigDecimal)> = best_first
+            .into_iter()
+            .filter(|(price, _)| below(price))
+            .map(|(price, quantity)| (price.clone(), quantity.clone()))
+            .collect();
+        merged.extend(taken);
+        // a side shorter than its depth is the whole side
+        if levels.len() < depth as usize {
+            break;
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::{Actor, StreamHandler};
+    use actix_web::{web, App, HttpRequest, HttpServer};
+    use actix_web_actors::ws;
+    use serde_json::Value;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // a binance combined stream frame, every level with the same quantity
+    fn frame(stream: &str, bids: &[u32], asks: &[u32], quantity: &str) -> String {
+        let levels = |prices: &[u32]| -> Vec<[String; 2]> {
+            prices
+                .iter()
+                .map(|p| [p.to_string(), quantity.to_string()])
+                .collect()
+        };
+        serde_json::json!({
+            "stream": stream,
+            "data": {"lastUpdateId": 1, "bids": levels(bids), "asks": levels(asks)},
+        })
+        .to_string()
+    }
+
+    fn ladder(levels: &BTreeMap<BigDecimal, BigDecimal>) -> Vec<(u32, String)> {
+        levels
+            .iter()
+            .map(|(p, q)| (p.to_string().parse().unwrap(), q.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_depth_ladder() {
+        let api = apitree::ws("binance").unwrap();
+        let shallow = api.depth_text("btcusdt", Some(5), None).unwrap();
+        let deep = api.depth_text("btcusdt", Some(20), Some(1000)).unwrap();
+        assert_eq!(shallow, "btcusdt@depth5@100ms");
+        assert_eq!(deep, "btcusdt@depth20@1000ms");
+        assert!(api.depth_text("btcusdt", Some(7), None).is_err());
+        let depths = HashMap::from([(shallow.clone(), 5), (deep.clone(), 20)]);
+
+        let mut books = Ladder::default();
+        let mut feed = |raw: String, timestamp: u128| {
+            let (stream, payload) = wsapi::unwrap_stream(&raw);
+            let stream = stream.unwrap();
+            let pair = Some(wsapi::stream_pair(&stream));
+            let ParseOutcome::Book(mut book) = api.parse_book(pair, payload) else {
+                panic!("no book in {}", raw);
+            };
+            Arc::make_mut(&mut book).timestamp = timestamp;
+            books.update(depths[&stream], book)
+        };
+        let range = |from: u32, to: u32| (from..=to).collect::<Vec<u32>>();
+        let rev = |from: u32, to: u32| (to..=from).rev().collect::<Vec<u32>>();
+
+        let merged = feed(frame(&deep, &rev(100, 81), &range(201, 220), "1"), 1000);
+        assert_eq!(merged.pair.as_deref(), Some("btcusdt"));
+        assert_eq!((merged.bid.len(), merged.ask.len()), (20, 20));
+
+        // the top from the shallow stream, the rest from the deep one
+        let merged = feed(frame(&shallow, &rev(99, 95), &range(202, 206), "2"), 1100);
+        assert_eq!(merged.timestamp, 1100);
+        let bids = ladder(&merged.bid);
+        assert_eq!(bids.len(), 19);
+        assert_eq!(bids[0], (81, "1".to_string()));
+        assert_eq!(bids[13], (94, "1".to_string()));
+        assert_eq!(bids[14], (95, "2".to_string()));
+        assert_eq!(bids[18], (99, "2".to_string()));
+        let asks = ladder(&merged.ask);
+        assert_eq!(asks.len(), 19);
+        assert_eq!(asks[0], (202, "2".to_string()));
+        assert_eq!(asks[4], (206, "2".to_string()));
+        assert_eq!(asks[5], (207, "1".to_string()));
+        assert_eq!(merged.mid(), Some(BigDecimal::from_str("150.5").unwrap()));
+
+        // a newer deep book replaces the whole ladder
+        let merged = feed(frame(&deep, &rev(98, 79), &range(203, 222), "3"), 2000);
+        assert_eq!(ladder(&merged.bid)[19], (98, "3".to_string()));
+        assert_eq!(ladder(&merged.ask)[0], (203, "3".to_string()));
+        assert!(merged
+            .bid
+            .values()
+            .chain(merged.ask.values())
+            .all(|q| q.to_string() == "3"));
+
+        // a shallow side shorter than its depth is the whole side
+        let merged = feed(frame(&shallow, &rev(97, 96), &range(204, 208), "4"), 2100);
+        assert_eq!(
+            ladder(&merged.bid),
+            vec![(96, "4".to_string()), (97, "4".to_string())]
+        );
+        assert_eq!(merged.ask.len(), 19);
+        assert_eq!(ladder(&merged.ask)[5], (209, "3".to_string()));
+
+        // the freshest wins, whatever the order of arrival
+        let merged = feed(frame(&deep, &rev(90, 71), &range(210, 229), "5"), 1500);
+        assert_eq!(
+            ladder(&merged.bid),
+            vec![(96, "4".to_string()), (97, "4".to_string())]
+        );
+        assert_eq!(ladder(&merged.ask)[5], (210, "5".to_string()));
+    }
+
+    const PRICES: [(&str, u32); 3] = [("LIVEA-AUD", 100), ("LIVEB-AUD", 10), ("LIVEC-AUD", 1)];
+
+    // A btcmarkets stand-in: every subscription is answered with a book of
+    // its markets, bid at the price of the market. Every ping with a book
+    // of the first market, after the pong when it answers them. Answers the
+    // book subscriptions of coinjar and kraken alike.
+    struct Venue {
+        received: Arc<Mutex<Vec<String>>>,
+        pings: Arc<AtomicUsize>,
+        pong: bool,
+    }
+
+    // 8 levels a side, a cent apart: (bids, asks)
+    fn levels(market: &str) -> (Vec<[String; 2]>, Vec<[String; 2]>) {
+        let price = HashMap::from(PRICES)[market] as f64;
+        let side = |top: f64, step: f64| -> Vec<[String; 2]> {
+            (0..8)
+                .map(|i| [format!("{:.2}", top + step * i as f64), "1".to_string()])
+                .collect()
+        };
+        (side(price, -0.01), side(price + 1., 0.01))
+    }
+
+    fn book(market: &str) -> String {
+        let (bids, asks) = levels(market);
+        serde_json::json!({
+            "messageType": "orderbook",
+            "marketId": market,
+            "bids": bids,
+            "asks": asks,
+        })
+        .to_string()
+    }
+
+    // the answers of the other venues to a book subscription
+    fn venue_books(request: &Value) -> Vec<String> {
+        if let Some(market) = request["topic"]
+            .as_str()
+            .and_then(|topic| topic.strip_prefix("book:"))
+        {
+            let (bids, asks) = levels(market);
+            let payload = serde_json::json!({"bids": bids, "asks": asks});
+            let init = serde_json::json!({
+                "event": "init",
+                "topic": format!("book:{}", market),
+                "payload": payload,
+                "ref": null,
+            });
+            return vec![init.to_string()];
+        }
+        if request["subscription"]["name"] != "book" {
+            return vec![];
+        }
+        let Some(markets) = request["pair"].as_array() else {
+            return vec![];
+        };
+        markets
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|market| {
+                let (bids, asks) = levels(market);
+                let rows = |levels: Vec<[String; 2]>| -> Vec<[String; 3]> {
+                    levels
+                        .into_iter()
+                        .map(|[p, v]| [p, v, "1696405428.703749".to_string()])
+                        .collect()
                 };
+                let snapshot = serde_json::json!({"bs": rows(bids), "as": rows(asks)});
+                serde_json::json!([1, snapshot, "book-25", market]).to_string()
+            })
+            .collect()
+    }
 
-                debug!("{}: {}", self.name, raw);
+    impl Actor for Venue {
+        type Context = ws::WebsocketContext<Self>;
+    }
 
-                if let Some(mut e) = (apitree::ws(&self.name)?.parse)(&raw)
-                    .map_err(|e| anyhow!("{}: raw msg: {}", e, raw))?
-                {
-                    e.trim(self.level);
-                    return Ok(Some(e));
+    impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Venue {
+        fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+            let text = match msg {
+                Ok(ws::Message::Text(text)) => text,
+                Ok(ws::Message::Ping(payload)) => {
+                    self.pings.fetch_add(1, Ordering::Relaxed);
+                    if self.pong {
+                        ctx.pong(&payload);
+                    }
+                    ctx.text(book(PRICES[0].0));
+                    return;
                 }
-                // skip none
-            } else {
-                return Ok(None);
+                _ => return,
+            };
+            self.received.lock().unwrap().push(text.to_string());
+            let request: Value = serde_json::from_str(&text).unwrap();
+            if request["messageType"] == "removeSubscription" {
+                return;
+            }
+            let Some(markets) = request["marketIds"].as_array() else {
+                for book in venue_books(&request) {
+                    ctx.text(book);
+                }
+                return;
+            };
+            for market in markets {
+                ctx.text(book(market.as_str().unwrap()));
             }
         }
     }
+
+    struct Mock {
+        addr: std::net::SocketAddr,
+        connections: Arc<AtomicUsize>,
+        received: Arc<Mutex<Vec<String>>>,
+        pings: Arc<AtomicUsize>,
+    }
+
+    fn serve(pong: bool) -> Mock {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let received = Arc::new(Mutex::new(vec![]));
+        let pings = Arc::new(AtomicUsize::new(0));
+        let (count, log, pinged) = (connections.clone(), received.clone(), pings.clone());
+        let server = HttpServer::new(move || {
+            let (count, log, pinged) = (count.clone(), log.clone(), pinged.clone());
+            App::new().route(
+                "/",
+                web::get().to(move |req: HttpRequest, stream: web::Payload| {
+                    count.fetch_add(1, Ordering::Relaxed);
+                    let venue = Venue {
+                        received: log.clone(),
+                        pings: pinged.clone(),
+                        pong,
+                    };
+                    async move { ws::start(venue, &req, stream) }
+                }),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        tokio::spawn(server.run());
+        Mock {
+            addr,
+            connections,
+            received,
+            pings,
+        }
+    }
+
+    async fn connect(mock: &Mock, setting: ExchangeSetting) -> Exchange {
+        let mut exchange = Exchange::new("btcmarkets");
+        exchange.endpoint = Some(format!("ws://{}/", mock.addr));
+        exchange.connect(vec![setting]).await.unwrap();
+        exchange
+    }
+
+    fn setting(pair: &str) -> ExchangeSetting {
+        serde_yaml::from_str(&format!("pair: {}", pair)).unwrap()
+    }
+
+    async fn best_bid(exchange: &mut Exchange) -> String {
+        loop {
+            if let Some(book) = exchange.next().await.unwrap() {
+                return book
+                    .bid
+                    .keys()
+                    .next_back()
+                    .unwrap()
+                    .normalized()
+                    .to_string();
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_pair_levels() {
+        let mock = serve(true);
+        // venues carrying both pairs on one connection
+        for venue in ["btcmarkets", "coinjar", "kraken"] {
+            let mut exchange = Exchange::new(venue);
+            exchange.endpoint = Some(format!("ws://{}/", mock.addr));
+            let mut deep = setting("LIVEA-AUD");
+            deep.level = Some(3);
+            let mut shallow = setting("LIVEC-AUD");
+            shallow.level = Some(5);
+            exchange.connect(vec![deep, shallow]).await.unwrap();
+
+            // parsed and trimmed by the client, merged like the publisher
+            let mut books = HashMap::new();
+            while books.len() < 2 {
+                if let Some(book) = exchange.next().await.unwrap() {
+                    books.insert(book.pair.clone().unwrap(), book);
+                }
+            }
+            let mut agg = crate::orderbook::AggregatedOrderbook::new();
+            agg.remerge(books.values().map(|book| book.as_ref()));
+            let summary = agg.finalize().unwrap();
+            // LIVEA is quoted around 100, LIVEC around 1
+            let count = |levels: &[crate::orderbook::Level], high: bool| {
+                levels
+                    .iter()
+                    .filter(|l| (f64::from_str(&l.price).unwrap() > 50.) == high)
+                    .count()
+            };
+            assert_eq!(count(&summary.bids, true), 3, "{}", venue);
+            assert_eq!(count(&summary.asks, true), 3, "{}", venue);
+            assert_eq!(count(&summary.bids, false), 5, "{}", venue);
+            assert_eq!(count(&summary.asks, false), 5, "{}", venue);
+        }
+
+        // subscribed at the depth covering the level, where there are depths
+        let binance = apitree::ws("binance").unwrap();
+        assert_eq!(binance.subscribe_level(Some(6)), 10);
+        assert_eq!(binance.subscribe_level(Some(25)), 20);
+        assert_eq!(binance.subscribe_level(None), wsapi::SUBSCRIBE_LEVEL);
+        let btcmarkets = apitree::ws("btcmarkets").unwrap();
+        assert_eq!(btcmarkets.subscribe_level(Some(5)), wsapi::SUBSCRIBE_LEVEL);
+    }
+
+    #[test]
+    fn test_channel_pairs() {
+        let mut exchange = Exchange::new("upbit");
+        exchange.pairs = vec!["BTC/KRW".to_string(), "eth/krw".to_string()];
+        let upbit = apitree::ws("upbit").unwrap();
+        // the market code of a book back to the pair as configured
+        let pair = |code| exchange.configured_pair(upbit, code);
+        assert_eq!(pair(Some("KRW-BTC")).as_deref(), Some("BTC/KRW"));
+        assert_eq!(pair(Some("KRW-ETH")).as_deref(), Some("eth/krw"));
+        assert_eq!(pair(Some("KRW-XRP")).as_deref(), Some("KRW-XRP"));
+        assert_eq!(pair(None), None);
+        // the others name their pairs as configured
+        let btcmarkets = apitree::ws("btcmarkets").unwrap();
+        assert_eq!(
+            exchange
+                .configured_pair(btcmarkets, Some("KRW-BTC"))
+                .as_deref(),
+            Some("KRW-BTC")
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_inverted_pair() {
+        let mock = serve(true);
+        // LIVEB-AUD bid at 10.00 down, ask at 11.00 up, 1 each
+        let mut inverted = setting("LIVEB-AUD");
+        inverted.invert = true;
+        inverted.invert_precision = 6;
+        inverted.level = Some(2);
+        inverted.sides = Sides::BidOnly;
+        let mut exchange = connect(&mock, inverted).await;
+        let book = loop {
+            if let Some(book) = exchange.next().await.unwrap() {
+                break book;
+            }
+        };
+        // the two best asks as the bids of AUD per LIVEB, in AUD
+        let bids: Vec<(String, String)> = book
+            .bid
+            .iter()
+            .rev()
+            .map(|(p, a)| (p.to_string(), a.to_string()))
+            .collect();
+        assert_eq!(
+            bids,
+            [
+                ("0.0909091".to_string(), "11".to_string()),
+                ("0.0908265".to_string(), "11.01".to_string()),
+            ]
+        );
+        assert!(book.ask.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_live_pairs() {
+        let mock = serve(true);
+        let mut exchange = connect(&mock, setting("LIVEA-AUD")).await;
+        assert_eq!(best_bid(&mut exchange).await, "100");
+
+        // the second pair goes out on the same connection
+        exchange.add_pair(setting("LIVEB-AUD")).await.unwrap();
+        assert_eq!(best_bid(&mut exchange).await, "10");
+        assert!(exchange.add_pair(setting("LIVEB-AUD")).await.is_err());
+        exchange.remove_pair("LIVEB-AUD").await.unwrap();
+        assert!(exchange.remove_pair("LIVEB-AUD").await.is_err());
+        // queued changes are applied before the next frame
+        change_pairs("btcmarkets", PairChange::Add(setting("LIVEC-AUD"))).unwrap();
+        assert_eq!(best_bid(&mut exchange).await, "1");
+        assert!(change_pairs("binance", PairChange::Remove("btcusdt".to_string())).is_err());
+
+        assert_eq!(mock.connections.load(Ordering::Relaxed), 1);
+        let types: Vec<(String, String)> = mock
+            .received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|text| {
+                let request: Value = serde_json::from_str(text).unwrap();
+                let market = request["marketIds"][0].as_str().unwrap().to_string();
+                (request["messageType"].as_str().unwrap().to_string(), market)
+            })
+            .collect();
+        let expected = [
+            ("subscribe", "LIVEA-AUD"),
+            ("addSubscription", "LIVEB-AUD"),
+            ("removeSubscription", "LIVEB-AUD"),
+            ("addSubscription", "LIVEC-AUD"),
+        ];
+        assert_eq!(types, expected.map(|(t, m)| (t.to_string(), m.to_string())));
+        // a reconnect subscribes to the pairs as they are now
+        let pairs: Vec<String> = exchange.settings().into_iter().map(|s| s.pair).collect();
+        assert_eq!(pairs, vec!["LIVEA-AUD", "LIVEC-AUD"]);
+        let api = apitree::ws("btcmarkets").unwrap();
+        assert!(exchange.subscriptions(api, "LIVEC-AUD").unwrap()[0].contains("addSubscription"));
+    }
+
+    #[actix_web::test]
+    async fn test_permanent_errors() {
+        let connect_err = |name: &str, setting: ExchangeSetting, endpoint: Option<String>| {
+            let mut exchange = Exchange::new(name);
+            exchange.endpoint = endpoint;
+            async move { exchange.connect(vec![setting]).await.unwrap_err() }
+        };
+        // nothing a reconnect fixes
+        let e = connect_err("nosuch", setting("BTC-AUD"), None).await;
+        assert!(is_permanent(&e));
+        let mut deep = setting("BTC-AUD");
+        deep.depth = Some(7);
+        assert!(is_permanent(&connect_err("btcmarkets", deep, None).await));
+
+        // an unreachable venue is retried
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("ws://{}/", closed.local_addr().unwrap());
+        drop(closed);
+        let e = connect_err("btcmarkets", setting("BTC-AUD"), Some(endpoint)).await;
+        assert!(!is_permanent(&e));
+    }
+
+    #[actix_web::test]
+    async fn test_ping_probe() {
+        let mock = serve(true);
+        let mut probed = setting("LIVEA-AUD");
+        probed.ping_probe_secs = Some(60);
+        let mut exchange = connect(&mock, probed).await;
+        // the first ping goes out right away, its pong is timed
+        assert_eq!(best_bid(&mut exchange).await, "100");
+        assert_eq!(best_bid(&mut exchange).await, "100");
+        assert_eq!(mock.pings.load(Ordering::Relaxed), 1);
+        let rtt = status::snapshot()["btcmarkets"].ping_rtt().unwrap();
+        assert_eq!(rtt.count, 1);
+        assert!(rtt.p50_ms > 0. && rtt.p50_ms < 5000.);
+        let probe = exchange.probe.as_ref().unwrap();
+        assert!(probe.pending.is_none() && !probe.disabled);
+
+        // a venue that never answers: the probe gives up
+        let mock = serve(false);
+        let mut exchange = connect(&mock, setting("LIVEA-AUD")).await;
+        exchange.probe = Some(PingProbe::new(Duration::ZERO));
+        assert_eq!(best_bid(&mut exchange).await, "100");
+        for _ in 0..PING_ATTEMPTS {
+            assert_eq!(best_bid(&mut exchange).await, "100");
+        }
+        assert!(exchange.probe.as_ref().unwrap().disabled);
+        assert_eq!(mock.pings.load(Ordering::Relaxed), PING_ATTEMPTS as usize);
+    }
+
+    #[actix_web::test]
+    async fn test_login() {
+        let mock = serve(true);
+        let mut luno: ExchangeSetting =
+            serde_yaml::from_str("{pair: XBTZAR, credentials: {key_id: key, secret: s3cret}}")
+                .unwrap();
+        let mut exchange = Exchange::new("luno");
+        exchange.endpoint = Some(format!("ws://{}/?pair={{}}", mock.addr));
+        exchange.connect(vec![luno.clone()]).await.unwrap();
+        while mock.received.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // the credentials first, the pair is in the url
+        assert_eq!(
+            *mock.received.lock().unwrap(),
+            vec![r#"{"api_key_id":"key","api_key_secret":"s3cret"}"#]
+        );
+
+        luno.credentials = None;
+        let mut exchange = Exchange::new("luno");
+        exchange.endpoint = Some(format!("ws://{}/?pair={{}}", mock.addr));
+        let e = exchange.connect(vec![luno]).await.unwrap_err();
+        assert!(is_permanent(&e));
+        assert_eq!(mock.connections.load(Ordering::Relaxed), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_bootstrap() {
+        let mock = serve(true);
+        let mut bullet = mockito::Server::new_async().await;
+        let body = serde_json::json!({
+            "code": "200000",
+            "data": {
+                "token": "t0ken",
+                "instanceServers": [{
+                    "endpoint": format!("ws://{}/", mock.addr),
+                    "pingInterval": 1000,
+                    "pingTimeout": 10000,
+                }],
+            },
+        });
+        let handshake = bullet
+            .mock("POST", "/api/v1/bullet-public")
+            .with_body(body.to_string())
+            .expect(2)
+            .create_async()
+            .await;
+        let endpoint = format!("{}/api/v1/bullet-public", bullet.url());
+        // a new token for the reconnect too
+        for _ in 0..2 {
+            let mut exchange = Exchange::new("kucoin");
+            exchange.endpoint = Some(endpoint.clone());
+            exchange.connect(vec![setting("BTC-USDT")]).await.unwrap();
+            assert_eq!(exchange.heartbeat_interval, Some(Duration::from_secs(1)));
+        }
+        handshake.assert_async().await;
+        assert_eq!(mock.connections.load(Ordering::Relaxed), 2);
+        while mock.received.lock().unwrap().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let topics: Vec<String> = mock
+            .received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|text| {
+                let request: Value = serde_json::from_str(text).unwrap();
+                request["topic"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(topics, vec!["/spotMarket/level2Depth50:BTC-USDT"; 2]);
+
+        // a failed handshake is retried
+        bullet
+            .mock("POST", "/api/v1/bullet-public")
+            .with_status(503)
+            .create_async()
+            .await;
+        let mut exchange = Exchange::new("kucoin");
+        exchange.endpoint = Some(endpoint);
+        let e = exchange
+            .connect(vec![setting("BTC-USDT")])
+            .await
+            .unwrap_err();
+        assert!(!is_permanent(&e));
+    }
+
+    // pairs waiting for a snapshot, of the seed in test_seeding
+    static WAITING: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(vec![]));
+
+    fn waiting() -> Vec<String> {
+        WAITING.lock().unwrap().clone()
+    }
+
+    fn seeded(pair: &str, body: &str) -> Result<()> {
+        if body != "book" {
+            return Err(anyhow!("snapshot {}", body));
+        }
+        WAITING.lock().unwrap().retain(|p| p != pair);
+        Ok(())
+    }
+
+    // polls until no snapshot is in flight
+    async fn settle(seeding: &mut Seeding, seed: &wsapi::Seed, now: Instant) {
+        seeding.poll("test", seed, now).await;
+        while seeding.fetches.values().any(|f| f.task.is_some()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            seeding.poll("test", seed, now).await;
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_seeding() {
+        let mut server = mockito::Server::new_async().await;
+        let seed = wsapi::Seed {
+            pending: waiting,
+            url: format!("{}/book/{{}}", server.url()).leak(),
+            apply: seeded,
+        };
+        *WAITING.lock().unwrap() = vec!["BTC".to_string()];
+        let mut seeding = Seeding::default();
+        let start = Instant::now();
+
+        // an http error backs off, the pair stays pending
+        let down = server
+            .mock("GET", "/book/BTC")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        settle(&mut seeding, &seed, start).await;
+        let fetch = &seeding.fetches["BTC"];
+        assert_eq!(fetch.failures, 1);
+        assert_eq!(fetch.retry, Some(start + SEED_BACKOFF));
+        // nothing fetched before the retry is due
+        settle(&mut seeding, &seed, start + SEED_BACKOFF / 2).await;
+        down.assert_async().await;
+        down.remove_async().await;
+
+        // a snapshot the parser rejects backs off longer
+        let bad = server
+            .mock("GET", "/book/BTC")
+            .with_body("truncated")
+            .create_async()
+            .await;
+        settle(&mut seeding, &seed, start + SEED_BACKOFF).await;
+        let fetch = &seeding.fetches["BTC"];
+        assert_eq!(fetch.failures, 2);
+        assert_eq!(fetch.retry, Some(start + SEED_BACKOFF * 3));
+        bad.remove_async().await;
+
+        server
+            .mock("GET", "/book/BTC")
+            .with_body("book")
+            .create_async()
+            .await;
+        settle(&mut seeding, &seed, start + SEED_BACKOFF * 3).await;
+        assert!(waiting().is_empty());
+        settle(&mut seeding, &seed, start + SEED_BACKOFF * 3).await;
+        assert!(seeding.idle());
+    }
 }
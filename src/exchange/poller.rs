@@ -0,0 +1,261 @@
+use super::breaker::{Breaker, BreakerState};
+use super::{permanent, LEVEL};
+use crate::apitree;
+use crate::config::{BreakerSetting, ExchangeSetting};
+use crate::orderbook::Orderbook;
+use crate::pipeline::IngestSender;
+use crate::sink::statsd;
+use crate::status;
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+
+// rest_breaker, read by the pollers spawned after set_breaker
+static BREAKER: Lazy<Mutex<BreakerSetting>> = Lazy::new(|| Mutex::new(BreakerSetting::default()));
+
+pub fn set_breaker(setting: BreakerSetting) {
+    *BREAKER.lock().unwrap() = setting;
+}
+
+// The venues without a websocket feed are polled on tasks of the shared
+// runtime, instead of a thread and an actix System each like the websocket
+// ones. Only the first pair is polled, the one that is aggregated.
+pub fn spawn(
+    exchange: String,
+    settings: Vec<ExchangeSetting>,
+    tx: IngestSender,
+) -> Result<JoinHandle<()>> {
+    let api = apitree::rest(&exchange).map_err(permanent)?;
+    let setting = settings
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("should have at least one pair setting"))?;
+    info!("start poller: {} {}", exchange, setting.pair);
+    let breaker = Breaker::new(BREAKER.lock().unwrap().clone());
+    Ok(tokio::spawn(poll(
+        exchange,
+        setting,
+        move |pair| (api.orderbook)(pair),
+        tx,
+        breaker,
+    )))
+}
+
+// `fetch` the book every wait_secs, until the pipeline is gone. A failed
+// fetch marks the exchange down, and the polls are skipped while the
+// breaker is open.
+pub async fn poll<F, Fut>(
+    exchange: String,
+    setting: ExchangeSetting,
+    fetch: F,
+    tx: IngestSender,
+    mut breaker: Breaker,
+) where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<Orderbook>>,
+{
+    let wait = Duration::from_secs(setting.wait_secs.max(1));
+    let mut ticker = interval(wait);
+    // a slow fetch pushes the next one back instead of bunching them up
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut up = false;
+    loop {
+        ticker.tick().await;
+        if !breaker.allow(Instant::now()) {
+            statsd::count("polls_skipped", 1, &[("exchange", &exchange)]);
+            continue;
+        }
+        // past the cool-off, this poll is the probe
+        if breaker.state() == BreakerState::HalfOpen {
+            status::breaker_changed(&exchange, breaker.status(Instant::now()));
+        }
+        match fetch(setting.pair.clone()).await {
+            Ok(mut ob) => {
+                let received = Instant::now();
+                if breaker.success() {
+                    status::breaker_changed(&exchange, breaker.status(received));
+                }
+                if !up {
+                    up = true;
+                    status::connected(&exchange);
+                }
+                // sides of the pair, the venue's the other way round when inverted
+                let sides = match setting.invert {
+                    true => setting.sides.flipped(),
+                    false => setting.sides,
+                };
+                ob.keep(sides);
+                ob.trim(setting.level.unwrap_or(LEVEL));
+                if setting.invert {
+                    ob = ob.inverted(setting.invert_precision);
+                }
+                if tx.send((exchange.clone(), Arc::new(ob), received)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                error!("{}, poll error {}", e, exchange);
+                if up {
+                    up = false;
+                    status::disconnected(&exchange);
+                }
+                statsd::count("reconnects", 1, &[("exchange", &exchange)]);
+                let now = Instant::now();
+                match breaker.failure(now, &e) {
+                    Some(cool_off) => {
+                        warn!("{} circuit open for {:?}", exchange, cool_off);
+                        statsd::count("breaker_opened", 1, &[("exchange", &exchange)]);
+                        status::breaker_changed(&exchange, breaker.status(now));
+                        status::reconnect_scheduled(&exchange, cool_off);
+                    }
+                    None => status::reconnect_scheduled(&exchange, wait),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Side;
+    use crate::pipeline::ingest_channel;
+    use tokio::time::sleep;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrent_polling() {
+        let (tx, mut rx) = ingest_channel(100);
+        let fetches = Arc::new(Mutex::new(vec![]));
+        let start = Instant::now();
+        for (venue, latency) in [("slow_rest", 600), ("fast_rest", 10)] {
+            let setting: ExchangeSetting =
+                serde_yaml::from_str("{pair: BTC-AUD, wait_secs: 1}").unwrap();
+            let fetches = fetches.clone();
+            let fetch = move |_pair: String| {
+                let fetches = fetches.clone();
+                async move {
+                    fetches.lock().unwrap().push((venue, start.elapsed()));
+                    sleep(Duration::from_millis(latency)).await;
+                    let mut ob = Orderbook::new(venue);
+                    for i in 0..20 {
+                        ob.insert(Side::Bid, (100 - i).into(), 1.into());
+                    }
+                    Ok(ob)
+                }
+            };
+            let breaker = Breaker::new(BreakerSetting::default());
+            tokio::spawn(poll(venue.to_string(), setting, fetch, tx.clone(), breaker));
+        }
+        drop(tx);
+        sleep(Duration::from_millis(2300)).await;
+
+        let fetches = fetches.lock().unwrap();
+        for venue in ["slow_rest", "fast_rest"] {
+            let at: Vec<u128> = fetches
+                .iter()
+                .filter(|(v, _)| *v == venue)
+                .map(|(_, t)| t.as_millis())
+                .collect();
+            // at 0, 1s and 2s, the slow venue holding up neither
+            assert_eq!(at.len(), 3, "{} polled at {:?}", venue, at);
+            for (i, t) in at.iter().enumerate() {
+                assert!(
+                    t.abs_diff(i as u128 * 1000) < 200,
+                    "{} polled at {:?}",
+                    venue,
+                    at
+                );
+            }
+        }
+        let mut books = vec![];
+        while let Some((venue, ob, _)) = rx.try_recv() {
+            assert_eq!(ob.bid.len(), LEVEL as usize);
+            books.push(venue);
+        }
+        // the fast one delivered its book of 2s, the slow one not yet
+        assert_eq!(books.iter().filter(|v| *v == "fast_rest").count(), 3);
+        assert_eq!(books.iter().filter(|v| *v == "slow_rest").count(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker() {
+        let mut server = mockito::Server::new_async().await;
+        // two 502 open the circuit, the probe gets a 429, the next one a book
+        for (status, retry_after) in [(502, None), (502, None), (429, Some("10"))] {
+            let mut mock = server.mock("GET", "/book").with_status(status);
+            if let Some(secs) = retry_after {
+                mock = mock.with_header("retry-after", secs);
+            }
+            mock.expect(1).create_async().await;
+        }
+        server
+            .mock("GET", "/book")
+            .with_body("{}")
+            .create_async()
+            .await;
+        let url = format!("{}/book", server.url());
+        let calls = Arc::new(Mutex::new(vec![]));
+        let start = Instant::now();
+        let fetch = {
+            let calls = calls.clone();
+            move |_pair: String| {
+                let (url, calls) = (url.clone(), calls.clone());
+                async move {
+                    calls.lock().unwrap().push(start.elapsed().as_secs());
+                    apitree::restapi::checked(reqwest::get(&url).await?)?;
+                    let mut ob = Orderbook::new("breaker_rest");
+                    ob.insert(Side::Bid, 100.into(), 1.into());
+                    Ok(ob)
+                }
+            }
+        };
+        let setting: ExchangeSetting =
+            serde_yaml::from_str("{pair: BTC-AUD, wait_secs: 1}").unwrap();
+        let breaker = Breaker::new(BreakerSetting {
+            failure_threshold: 2,
+            cool_off_secs: 3,
+            max_cool_off_secs: 60,
+        });
+        let (tx, mut rx) = ingest_channel(10);
+        let mut events = status::events().subscribe();
+        tokio::spawn(poll(
+            "breaker_rest".to_string(),
+            setting,
+            fetch,
+            tx,
+            breaker,
+        ));
+        let (venue, _, _) = rx.recv().await.unwrap();
+        assert_eq!(venue, "breaker_rest");
+
+        // open at 1s for 3s, the probe at 4s is throttled for 10s (not the
+        // 6s of the second open), and the one at 14s closes it
+        assert_eq!(*calls.lock().unwrap(), [0, 1, 4, 14]);
+        let mut states = vec![];
+        while let Ok(event) = events.try_recv() {
+            let event: serde_json::Value = serde_json::from_str(&event).unwrap();
+            if event["exchange"] == "breaker_rest" && event["state"] != "reconnect_scheduled" {
+                states.push(event["state"].as_str().unwrap().to_string());
+            }
+        }
+        assert_eq!(
+            states,
+            [
+                "breaker_open",
+                "breaker_half_open",
+                "breaker_open",
+                "breaker_half_open",
+                "breaker_closed",
+                "connected"
+            ]
+        );
+        let breaker = status::snapshot()["breaker_rest"].breaker.clone().unwrap();
+        assert_eq!((breaker.state, breaker.opened), (BreakerState::Closed, 2));
+        assert!(status::prometheus()
+            .contains("arb_monitor_breaker_opened_total{exchange=\"breaker_rest\"} 2"));
+    }
+}
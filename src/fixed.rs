@@ -0,0 +1,125 @@
+use anyhow::{anyhow, Result};
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Decimal from the wire as mantissa * 10^-scale. Every venue quotes prices
+// and sizes that fit in an i64 mantissa, so parsing them this way skips the
+// BigInt parsing of BigDecimal::from_str entirely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixed {
+    mantissa: i64,
+    scale: i8,
+}
+
+impl FromStr for Fixed {
+    type Err = anyhow::Error;
+
+    // plain decimal notation only, ex: "-0.0150"
+    fn from_str(s: &str) -> Result<Fixed> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let mut mantissa: i64 = 0;
+        let mut scale: Option<i8> = None;
+        let mut count = 0;
+        for c in digits.bytes() {
+            match c {
+                b'0'..=b'9' => {
+                    mantissa = mantissa
+                        .checked_mul(10)
+                        .and_then(|m| m.checked_add((c - b'0') as i64))
+                        .ok_or_else(|| anyhow!("{} overflows a fixed point mantissa", s))?;
+                    count += 1;
+                    if let Some(scale) = scale.as_mut() {
+                        *scale = scale
+                            .checked_add(1)
+                            .ok_or_else(|| anyhow!("{} has too many decimals", s))?;
+                    }
+                }
+                b'.' if scale.is_none() => scale = Some(0),
+                _ => return Err(anyhow!("{} is not a plain decimal", s)),
+            }
+        }
+        if count == 0 {
+            return Err(anyhow!("{} has no digits", s));
+        }
+        Ok(Fixed {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale: scale.unwrap_or(0),
+        })
+    }
+}
+
+impl From<Fixed> for BigDecimal {
+    // same digits and scale as BigDecimal::from_str of the same string
+    fn from(f: Fixed) -> BigDecimal {
+        BigDecimal::new(BigInt::from(f.mantissa), f.scale as i64)
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// Wire string to BigDecimal. With the fixed point path enabled, strings
+// that fit go through Fixed, anything else falls back to BigDecimal.
+pub fn decimal(s: &str) -> Result<BigDecimal> {
+    if ENABLED.load(Ordering::Relaxed) {
+        if let Ok(f) = Fixed::from_str(s) {
+            return Ok(f.into());
+        }
+    }
+    BigDecimal::from_str(s).map_err(|e| anyhow!("{}: {:?}", s, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_parse() {
+        let cases = [
+            "0",
+            "1",
+            "-1",
+            "+2",
+            "0.01",
+            "29737",
+            "0.67548438",
+            "43468.00000",
+            "1650.10",
+            "-0.0150",
+            ".5",
+            "5.",
+            "31802.46",
+            "0.00000001",
+        ];
+        for s in cases {
+            let f = Fixed::from_str(s).unwrap();
+            let exact = BigDecimal::from_str(s).unwrap();
+            let converted: BigDecimal = f.into();
+            // same value, same scale, so the same string in the summary
+            assert_eq!(converted, exact, "{}", s);
+            assert_eq!(converted.to_string(), exact.to_string(), "{}", s);
+        }
+        for s in ["", "-", "1.2.3", "1e5", "abc", "99999999999999999999"] {
+            assert!(Fixed::from_str(s).is_err(), "{}", s);
+        }
+        // out of range for Fixed is still parsed, exactly
+        enable(true);
+        assert_eq!(
+            decimal("99999999999999999999").unwrap(),
+            BigDecimal::from_str("99999999999999999999").unwrap()
+        );
+        assert_eq!(
+            decimal("1e5").unwrap(),
+            BigDecimal::from_str("1e5").unwrap()
+        );
+        enable(false);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::config::GuardrailSetting;
+use crate::sink::statsd;
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+// over memory_soft_limit_mb with shed_load set
+static SHEDDING: AtomicBool = AtomicBool::new(false);
+
+// the publisher runs at half the rate while set
+pub fn shedding() -> bool {
+    SHEDDING.load(Ordering::Relaxed)
+}
+
+// the queues and caches between the venues and the clients, at one report
+#[derive(Debug, Clone)]
+pub struct Report {
+    // frames waiting for ingest, and the ones dropped from its queue so far
+    pub ingest_depth: usize,
+    pub ingest_dropped: u64,
+    // summaries queued for the slowest ws session, and for the slowest sink
+    pub summary_queue: usize,
+    pub sink_queue: usize,
+    // latest books of the aggregate
+    pub books: usize,
+    // parser state map -> books kept
+    pub parser_state: BTreeMap<&'static str, usize>,
+    // None where /proc is not there
+    pub resident_mb: Option<f64>,
+}
+
+static LAST: Lazy<Mutex<Option<Report>>> = Lazy::new(|| Mutex::new(None));
+
+// resident set size, from VmRSS of /proc/self/status. Linux only.
+pub fn resident_mb() -> Option<f64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb: f64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb / 1024.)
+}
+
+// An error every report while over the soft limit. Returns whether load is
+// shed from now on.
+fn check_memory(setting: &GuardrailSetting, resident_mb: Option<f64>) -> bool {
+    let over = match (setting.memory_soft_limit_mb, resident_mb) {
+        (Some(limit), Some(mb)) if mb > limit as f64 => {
+            error!(
+                "resident memory {:.0}MB is over memory_soft_limit_mb {}",
+                mb, limit
+            );
+            true
+        }
+        _ => false,
+    };
+    let shed = over && setting.shed_load;
+    if SHEDDING.swap(shed, Ordering::Relaxed) != shed {
+        if shed {
+            warn!("shedding load, summaries at half the rate");
+        } else {
+            info!("memory back under the soft limit, summaries at the full rate");
+        }
+    }
+    shed
+}
+
+fn export(report: &Report) {
+    statsd::gauge("ingest.depth", report.ingest_depth as f64, &[]);
+    statsd::gauge("ingest.dropped", report.ingest_dropped as f64, &[]);
+    statsd::gauge("summary.queue", report.summary_queue as f64, &[]);
+    statsd::gauge("sink.queue", report.sink_queue as f64, &[]);
+    statsd::gauge("books", report.books as f64, &[]);
+    if let Some(mb) = report.resident_mb {
+        statsd::gauge("memory.resident_mb", mb, &[]);
+    }
+}
+
+// Log and export what `probe` reports every report_interval_secs, and hold
+// the memory against the soft limit.
+pub async fn run<F>(setting: GuardrailSetting, probe: F)
+where
+    F: Fn() -> Report,
+{
+    let mut ticker = interval(Duration::from_secs(setting.report_interval_secs));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        ticker.tick().await;
+        let report = probe();
+        info!("self report {:?}", report);
+        export(&report);
+        check_memory(&setting, report.resident_mb);
+        *LAST.lock().unwrap() = Some(report);
+    }
+}
+
+// prometheus text exposition of the latest report
+pub fn prometheus() -> String {
+    let mut out = String::new();
+    let Some(report) = LAST.lock().unwrap().clone() else {
+        return out;
+    };
+    let mut gauge = |name: &str, help: &str, value: f64| {
+        let _ = writeln!(out, "# HELP arb_monitor_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE arb_monitor_{} gauge", name);
+        let _ = writeln!(out, "arb_monitor_{} {}", name, value);
+    };
+    gauge(
+        "ingest_depth",
+        "frames waiting for ingest",
+        report.ingest_depth as f64,
+    );
+    gauge(
+        "ingest_dropped",
+        "frames dropped from the full ingest queue",
+        report.ingest_dropped as f64,
+    );
+    gauge(
+        "summary_queue",
+        "summaries queued for the slowest ws session",
+        report.summary_queue as f64,
+    );
+    if let Some(mb) = report.resident_mb {
+        gauge("resident_mb", "resident memory", mb);
+    }
+    gauge(
+        "shedding",
+        "summaries at half the rate",
+        shedding() as u8 as f64,
+    );
+    let name = "arb_monitor_parser_state_books";
+    let _ = writeln!(out, "# HELP {} books kept per parser state map", name);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for (map, size) in report.parser_state.iter() {
+        let _ = writeln!(out, "{}{{map=\"{}\"}} {}", name, map, size);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_limit() {
+        let mut setting: GuardrailSetting =
+            serde_yaml::from_str("{memory_soft_limit_mb: 512}").unwrap();
+        // over the limit, logged but not shed
+        assert!(!check_memory(&setting, Some(600.)));
+        setting.shed_load = true;
+        assert!(check_memory(&setting, Some(600.)));
+        assert!(shedding());
+        // unknown is never over
+        assert!(!check_memory(&setting, None));
+        assert!(!check_memory(&setting, Some(500.)));
+        assert!(!shedding());
+        #[cfg(target_os = "linux")]
+        assert!(resident_mb().is_some_and(|mb| mb > 0.));
+    }
+}
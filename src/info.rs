@@ -0,0 +1,185 @@
+// What a client can learn of the server before relying on it: the
+// versions, the configured pairs, the optional features and the limits.
+// Built from the config by server_info only, and the value kept is what
+// every surface serves, GET /info and the reply to the ws hello op, so
+// they can't tell different stories.
+use crate::config::{Encoding, InnerConfig, Redistribution};
+use crate::schema::SCHEMA_VERSION;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct ServerInfo {
+    pub server_version: String,
+    // of the published Summary, see schema
+    pub schema_version: u32,
+    // logical name of the aggregated pair
+    pub pair: String,
+    // exchange -> its configured pairs
+    pub pairs: BTreeMap<String, Vec<String>>,
+    // pair groups, served on /ws/group/{name}
+    pub groups: Vec<String>,
+    pub features: Features,
+    pub limits: Limits,
+}
+
+// the optional parts of the outputs, on when configured
+#[derive(Debug, Serialize, PartialEq, Clone, Default)]
+pub struct Features {
+    // the subscribe_bbo op and the zmq bbo topic
+    pub bbo_ticks: bool,
+    // the consolidated section of the Summary
+    pub consolidated: bool,
+    // opportunity lifecycle tracking, GET /opportunities
+    pub opportunities: bool,
+    // the net section of the opportunities
+    pub portfolio: bool,
+    // paper trading, GET /simulation
+    pub simulation: bool,
+    // stats.volatility
+    pub volatility: bool,
+    // stats.leader and GET /analytics/leadlag
+    pub leadlag: bool,
+    // GET /analytics/contribution
+    pub contribution: bool,
+    // no summary until the warm-up quorum
+    pub warmup: bool,
+    // prices and amounts rounded on output
+    pub output_precision: bool,
+    // age_ms on the levels
+    pub level_ages: bool,
+    // index_prices and stats.mid_vs_index_bps
+    pub index_prices: bool,
+    // a zmq or nats sink encodes msgpack
+    pub msgpack: bool,
+    // an exchange is left out of the redistributable_only outputs
+    pub redistribution_filter: bool,
+    // tick / lot sizes on GET /exchanges
+    pub symbol_meta: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct Limits {
+    // most levels per side of a deep_snapshot
+    pub deep_snapshot_max_depth: usize,
+    pub consolidated_levels: Option<usize>,
+    // window of the closed opportunities kept
+    pub opportunity_window_secs: Option<u64>,
+    pub contribution_window_secs: Option<u64>,
+}
+
+pub fn server_info(config: &InnerConfig) -> ServerInfo {
+    let msgpack = config
+        .zmq
+        .as_ref()
+        .map(|s| s.encoding)
+        .into_iter()
+        .chain(config.nats.as_ref().map(|s| s.encoding))
+        .any(|e| e == Encoding::Msgpack);
+    let mut groups: Vec<String> = config.groups.keys().cloned().collect();
+    groups.sort();
+    ServerInfo {
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version: SCHEMA_VERSION,
+        pair: config.pair_name(),
+        pairs: config
+            .exchange_pair_map
+            .iter()
+            .map(|(exchange, settings)| {
+                let mut pairs: Vec<String> = settings.iter().map(|s| s.pair.clone()).collect();
+                // the same pair listed once per depth
+                pairs.dedup();
+                (exchange.clone(), pairs)
+            })
+            .collect(),
+        groups,
+        features: Features {
+            bbo_ticks: config.bbo_ticks,
+            consolidated: config.consolidated_levels.is_some(),
+            opportunities: config.opportunities.is_some(),
+            portfolio: config.portfolio.is_some(),
+            simulation: config.simulation.is_some(),
+            volatility: config.volatility.is_some(),
+            leadlag: config.leadlag.is_some(),
+            contribution: config.contribution.is_some(),
+            warmup: config.warmup.is_some(),
+            output_precision: config.output_precision.is_some(),
+            level_ages: config.level_ages,
+            index_prices: !config.index_sources.is_empty(),
+            msgpack,
+            redistribution_filter: config
+                .exchange_pair_map
+                .values()
+                .flatten()
+                .any(|s| s.redistribution == Redistribution::Deny),
+            symbol_meta: config.symbol_meta.is_some(),
+        },
+        limits: Limits {
+            deep_snapshot_max_depth: config.deep_snapshot_max_depth,
+            consolidated_levels: config.consolidated_levels,
+            opportunity_window_secs: config.opportunities.as_ref().map(|o| o.window_secs),
+            contribution_window_secs: config.contribution.as_ref().map(|c| c.window_secs),
+        },
+    }
+}
+
+static INFO: Lazy<Mutex<Option<ServerInfo>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn init(info: ServerInfo) {
+    *INFO.lock().unwrap() = Some(info);
+}
+
+// None before init
+pub fn get() -> Option<ServerInfo> {
+    INFO.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ExchangeSetting, OpportunitySetting, ZmqSetting};
+
+    #[test]
+    fn test_server_info() {
+        let mut config = InnerConfig::default();
+        let info = server_info(&config);
+        assert_eq!(info.features, Features::default());
+        assert_eq!(info.schema_version, SCHEMA_VERSION);
+        assert_eq!(info.limits.opportunity_window_secs, None);
+
+        let mut deep = ExchangeSetting::new("btcusdt");
+        deep.depth = Some(20);
+        deep.redistribution = Redistribution::Deny;
+        config.exchange_pair_map.insert(
+            "binance".to_string(),
+            vec![ExchangeSetting::new("btcusdt"), deep],
+        );
+        config.bbo_ticks = true;
+        config.zmq = Some(ZmqSetting {
+            bind: "tcp://127.0.0.1:5556".to_string(),
+            topic_prefix: String::new(),
+            encoding: Encoding::Msgpack,
+            redistributable_only: false,
+        });
+        config.opportunities = Some(OpportunitySetting {
+            open_bps: 10.,
+            close_bps: 5.,
+            close_grace_ms: 500,
+            window_secs: 3600,
+        });
+        let info = server_info(&config);
+        assert_eq!(info.pair, "btcusdt");
+        assert_eq!(info.pairs["binance"], ["btcusdt"]);
+        let on = Features {
+            bbo_ticks: true,
+            opportunities: true,
+            msgpack: true,
+            redistribution_filter: true,
+            ..Default::default()
+        };
+        assert_eq!(info.features, on);
+        assert_eq!(info.limits.opportunity_window_secs, Some(3600));
+    }
+}
@@ -0,0 +1,55 @@
+//! Order book aggregation across crypto exchanges.
+//!
+//! The exchange connectors and the aggregation are usable on their own:
+//! - [`exchange`]: websocket connectors, one [`exchange::Exchange`] per venue
+//!   yielding its [`orderbook::Orderbook`]s
+//! - [`apitree`]: the venue apis, their endpoints and parsers
+//! - [`orderbook`]: the books, and their merge into an
+//!   [`orderbook::AggregatedOrderbook`] and its published [`orderbook::Summary`]
+//! - [`config`]: the settings the above are built from
+//!
+//! See `examples/binance_books.rs`. The other modules make up the monitor
+//! binary, they are public for it only and may change at any time.
+pub mod apitree;
+pub mod config;
+pub mod exchange;
+pub mod orderbook;
+
+#[doc(hidden)]
+pub mod alert;
+#[doc(hidden)]
+pub mod analytics;
+#[doc(hidden)]
+pub mod audit;
+#[doc(hidden)]
+pub mod chaos;
+#[doc(hidden)]
+pub mod deadletter;
+#[doc(hidden)]
+pub mod fixed;
+#[doc(hidden)]
+pub mod guardrails;
+#[doc(hidden)]
+pub mod info;
+#[doc(hidden)]
+pub mod opportunity;
+#[doc(hidden)]
+pub mod pipeline;
+#[doc(hidden)]
+pub mod schema;
+#[doc(hidden)]
+pub mod server;
+#[doc(hidden)]
+pub mod simulation;
+#[doc(hidden)]
+pub mod sink;
+#[doc(hidden)]
+pub mod snapshots;
+#[doc(hidden)]
+pub mod soak;
+#[doc(hidden)]
+pub mod status;
+#[doc(hidden)]
+pub mod timings;
+#[doc(hidden)]
+pub mod vwap;
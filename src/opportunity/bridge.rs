@@ -0,0 +1,350 @@
+use super::best_levels;
+use crate::config::RateSource;
+use crate::orderbook::{get_unixtime, Summary};
+use anyhow::{anyhow, Result};
+use bigdecimal::{BigDecimal, One, ToPrimitive, Zero};
+use log::{error, info};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::time::{interval, Duration};
+
+// latest value of a bridge rate: buy quote units per sell quote unit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rate {
+    pub value: BigDecimal,
+    // static, rest or book
+    pub source: &'static str,
+    // unix millis of the last refresh
+    pub updated: u128,
+}
+
+static RATES: Lazy<Mutex<HashMap<String, Rate>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn set(name: &str, value: BigDecimal, source: &'static str, now: u128) {
+    RATES.lock().unwrap().insert(
+        name.to_string(),
+        Rate {
+            value,
+            source,
+            updated: now,
+        },
+    );
+}
+
+pub fn get(name: &str) -> Option<Rate> {
+    RATES.lock().unwrap().get(name).cloned()
+}
+
+fn oriented(value: BigDecimal, invert: bool) -> Option<BigDecimal> {
+    if value <= BigDecimal::zero() {
+        return None;
+    }
+    Some(if invert {
+        BigDecimal::one() / value
+    } else {
+        value
+    })
+}
+
+// rate at `pointer` of the json returned by `url`
+pub async fn fetch(client: &reqwest::Client, url: &str, pointer: &str) -> Result<BigDecimal> {
+    let body: Value = client.get(url).send().await?.json().await?;
+    let value = body
+        .pointer(pointer)
+        .ok_or_else(|| anyhow!("{} not found in {}", pointer, url))?;
+    let rate = match value {
+        Value::Number(n) => BigDecimal::from_str(&n.to_string())?,
+        Value::String(s) => BigDecimal::from_str(s)?,
+        _ => return Err(anyhow!("{} of {} is not a number", pointer, url)),
+    };
+    Ok(rate)
+}
+
+async fn refresh(name: String, url: String, pointer: String, refresh_secs: u64, invert: bool) {
+    let client = reqwest::Client::new();
+    let mut ticker = interval(Duration::from_secs(refresh_secs));
+    loop {
+        ticker.tick().await;
+        match fetch(&client, &url, &pointer).await {
+            Ok(rate) => match oriented(rate, invert) {
+                Some(rate) => set(&name, rate, "rest", get_unixtime()),
+                None => error!("rate {}: non positive value from {}", name, url),
+            },
+            Err(e) => error!("rate {}: {}", name, e),
+        }
+    }
+}
+
+// Sets the static rates and spawns the rest refreshers. Returns the book
+// sources as exchange -> (rate name, invert), to be fed by on_book.
+pub fn start(rates: HashMap<String, RateSource>) -> HashMap<String, (String, bool)> {
+    let mut books = HashMap::new();
+    for (name, source) in rates {
+        match source {
+            RateSource::Static { rate } => {
+                match BigDecimal::from_str(&rate.to_string())
+                    .ok()
+                    .and_then(|r| oriented(r, false))
+                {
+                    Some(rate) => set(&name, rate, "static", get_unixtime()),
+                    None => error!("rate {}: invalid static rate {}", name, rate),
+                }
+            }
+            RateSource::Rest {
+                url,
+                pointer,
+                refresh_secs,
+                invert,
+            } => {
+                info!(
+                    "rate {} refreshed from {} every {}s",
+                    name, url, refresh_secs
+                );
+                tokio::spawn(refresh(name, url, pointer, refresh_secs, invert));
+            }
+            RateSource::Book { exchange, invert } => {
+                info!("rate {} from the mid of {}", name, exchange);
+                books.insert(exchange, (name, invert));
+            }
+        }
+    }
+    books
+}
+
+// mid of a bridge book, None while one of its sides is empty
+pub fn on_book(name: &str, invert: bool, mid: Option<BigDecimal>, now: u128) {
+    if let Some(rate) = mid.and_then(|m| oriented(m, invert)) {
+        set(name, rate, "book", now);
+    }
+}
+
+// "binance:BTC-USDT" -> "binance"
+fn exchange_of(venue: &str) -> &str {
+    venue.split(':').next().unwrap_or(venue)
+}
+
+// best ask of `buy` against best bid of `sell` converted with the bridge rate
+#[derive(Debug, Clone, PartialEq)]
+pub struct BridgedOpportunity {
+    pub buy: String,
+    pub sell: String,
+    pub bridge: String,
+    pub rate: Rate,
+    pub rate_age_ms: u64,
+    // in the quote of buy
+    pub buy_price: BigDecimal,
+    // in the quote of sell
+    pub sell_price: BigDecimal,
+    // sell_price in the quote of buy
+    pub converted_price: BigDecimal,
+    // after the bridge cost
+    pub profit_bps: f64,
+    pub quantity: BigDecimal,
+}
+
+// None without a crossing, or while the bridge rate is unknown
+pub fn detect(
+    summary: &Summary,
+    buy: &str,
+    sell: &str,
+    bridge: &str,
+    bridge_cost_bps: f64,
+    now: u128,
+) -> Option<BridgedOpportunity> {
+    let rate = get(bridge.strip_prefix("rate:")?)?;
+    let asks = best_levels(&summary.asks);
+    let bids = best_levels(&summary.bids);
+    let (ask, ask_amount) = asks.get(exchange_of(buy))?;
+    let (bid, bid_amount) = bids.get(exchange_of(sell))?;
+    let converted = bid * &rate.value;
+    let gross = ((&converted - ask) / ask * BigDecimal::from(10000)).to_f64()?;
+    let profit_bps = gross - bridge_cost_bps;
+    if profit_bps <= 0. {
+        return None;
+    }
+    Some(BridgedOpportunity {
+        buy: buy.to_string(),
+        sell: sell.to_string(),
+        bridge: bridge.to_string(),
+        rate_age_ms: now.saturating_sub(rate.updated) as u64,
+        rate,
+        buy_price: ask.clone(),
+        sell_price: bid.clone(),
+        converted_price: converted,
+        profit_bps,
+        quantity: bid_amount.min(ask_amount).clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+    use crate::orderbook::{Orderbook, Side};
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    // binance in USDT, btcmarkets in AUD
+    fn mixed(now: u128) -> Summary {
+        summary(
+            vec![
+                level("btcmarkets", "150000", "0.5"),
+                level("binance", "99000", "2"),
+            ],
+            vec![
+                level("binance", "99500", "1"),
+                level("btcmarkets", "151000", "3"),
+            ],
+            now,
+        )
+    }
+
+    #[test]
+    fn test_static_rate() {
+        start(HashMap::from([(
+            "static_audusd".to_string(),
+            RateSource::Static { rate: 0.67 },
+        )]));
+        let now = get_unixtime() + 5000;
+        let s = mixed(now);
+        let o = detect(
+            &s,
+            "binance:BTC-USDT",
+            "btcmarkets:BTC-AUD",
+            "rate:static_audusd",
+            2.,
+            now,
+        )
+        .unwrap();
+        // 150000 AUD * 0.67 = 100500 USDT against 99500: 100.50 bps, less 2
+        assert_eq!(o.converted_price, dec("100500"));
+        assert_eq!(o.buy_price, dec("99500"));
+        assert_eq!(o.profit_bps, 98.50251256281403);
+        assert_eq!(o.quantity, dec("0.5"));
+        assert_eq!(o.rate.source, "static");
+        assert!(o.rate_age_ms >= 5000);
+
+        // no crossing the other way, and unknown rates never match
+        assert!(detect(
+            &s,
+            "btcmarkets:BTC-AUD",
+            "binance:BTC-USDT",
+            "rate:static_audusd",
+            0.,
+            now
+        )
+        .is_none());
+        assert!(detect(
+            &s,
+            "binance:BTC-USDT",
+            "btcmarkets:BTC-AUD",
+            "rate:missing",
+            0.,
+            now
+        )
+        .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rest_rate() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/latest")
+            .with_body(r#"{"base":"AUD","rates":{"USD":0.66,"JPY":"97.5"}}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let client = reqwest::Client::new();
+        let url = format!("{}/latest", server.url());
+        assert_eq!(
+            fetch(&client, &url, "/rates/USD").await.unwrap(),
+            dec("0.66")
+        );
+        // numeric strings are accepted too
+        assert_eq!(
+            fetch(&client, &url, "/rates/JPY").await.unwrap(),
+            dec("97.5")
+        );
+        mock.assert_async().await;
+        assert!(fetch(&client, &url, "/rates/EUR").await.is_err());
+
+        start(HashMap::from([(
+            "rest_audusd".to_string(),
+            RateSource::Rest {
+                url,
+                pointer: "/rates/USD".to_string(),
+                refresh_secs: 60,
+                invert: false,
+            },
+        )]));
+        // the first refresh happens right away
+        for _ in 0..100 {
+            if get("rest_audusd").is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let now = get_unixtime();
+        let o = detect(
+            &mixed(now),
+            "binance:BTC-USDT",
+            "btcmarkets:BTC-AUD",
+            "rate:rest_audusd",
+            0.,
+            now,
+        );
+        // 150000 * 0.66 = 99000 < 99500
+        assert!(o.is_none());
+        assert_eq!(get("rest_audusd").unwrap().value, dec("0.66"));
+        assert_eq!(get("rest_audusd").unwrap().source, "rest");
+    }
+
+    #[test]
+    fn test_book_rate() {
+        let books = start(HashMap::from([(
+            "book_audusdt".to_string(),
+            RateSource::Book {
+                exchange: "kraken".to_string(),
+                invert: true,
+            },
+        )]));
+        assert_eq!(
+            books,
+            HashMap::from([("kraken".to_string(), ("book_audusdt".to_string(), true))])
+        );
+        // USDT/AUD, 1.49 / 1.51: 1.5 AUD per USDT
+        let mut ob = Orderbook::new("kraken");
+        ob.insert(Side::Bid, dec("1.49"), dec("1000"));
+        ob.insert(Side::Ask, dec("1.51"), dec("1000"));
+        on_book("book_audusdt", true, ob.mid(), 1000);
+        let rate = get("book_audusdt").unwrap();
+        assert_eq!(
+            (rate.value * BigDecimal::from(3)).round(8),
+            BigDecimal::from(2)
+        );
+        assert_eq!(rate.updated, 1000);
+
+        let o = detect(
+            &mixed(4000),
+            "binance:BTC-USDT",
+            "btcmarkets:BTC-AUD",
+            "rate:book_audusdt",
+            0.,
+            4000,
+        )
+        .unwrap();
+        // 150000 / 1.5 = 100000 against 99500
+        assert_eq!(o.converted_price.round(8), dec("100000"));
+        assert_eq!(o.rate_age_ms, 3000);
+        assert_eq!(o.rate.source, "book");
+
+        // an empty side leaves the last rate
+        on_book("book_audusdt", true, Orderbook::new("kraken").mid(), 5000);
+        assert_eq!(get("book_audusdt").unwrap().updated, 1000);
+    }
+}
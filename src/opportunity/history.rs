@@ -0,0 +1,342 @@
+use super::Opportunity;
+use crate::config::{DetectorSetting, OpportunitySetting};
+use crate::opportunity;
+use crate::orderbook::{get_unixtime, Summary};
+use crate::status;
+use bigdecimal::ToPrimitive;
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+// lifecycle of one opportunity, identified by (pair, buy venue, sell venue)
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Record {
+    pub pair: String,
+    pub buy_exchange: String,
+    pub sell_exchange: String,
+    // unix millis
+    pub opened: u128,
+    // None while still open
+    pub closed: Option<u128>,
+    pub peak_profit_bps: f64,
+    // largest top of book quantity seen while open
+    pub peak_quantity: f64,
+    // largest profitable detector size seen while open
+    pub peak_size: Option<f64>,
+    // first time it fell under close_bps (or disappeared) since last seen
+    #[serde(skip)]
+    below_since: Option<u128>,
+}
+
+impl Record {
+    pub fn route(&self) -> String {
+        format!("{}->{}", self.buy_exchange, self.sell_exchange)
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RouteStats {
+    pub count: usize,
+    pub median_duration_ms: f64,
+    pub median_peak_bps: f64,
+}
+
+// over the opportunities closed within the window
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Stats {
+    pub window_secs: u64,
+    pub count: usize,
+    pub median_duration_ms: Option<f64>,
+    pub routes: BTreeMap<String, RouteStats>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Report {
+    pub open: Vec<Record>,
+    pub closed: Vec<Record>,
+    pub stats: Stats,
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let n = values.len();
+    Some(if n % 2 == 1 {
+        values[n / 2]
+    } else {
+        (values[n / 2 - 1] + values[n / 2]) / 2.
+    })
+}
+
+// Tracks open opportunities with hysteresis: a route opens at open_bps, and
+// only closes once it stayed under close_bps (or absent) for close_grace_ms.
+// Its close time is when it first fell under, so flickering around the
+// threshold extends one record instead of creating new ones.
+pub struct History {
+    setting: OpportunitySetting,
+    pair: String,
+    open: BTreeMap<(String, String), Record>,
+    // oldest first
+    closed: VecDeque<Record>,
+}
+
+impl History {
+    pub fn new(setting: OpportunitySetting, pair: &str) -> History {
+        History {
+            setting,
+            pair: pair.to_string(),
+            open: BTreeMap::new(),
+            closed: VecDeque::new(),
+        }
+    }
+
+    // feed the opportunities detected at `now`
+    pub fn update(&mut self, opportunities: &[Opportunity], now: u128) {
+        let mut seen = vec![];
+        for o in opportunities {
+            let key = (o.buy_exchange.clone(), o.sell_exchange.clone());
+            let quantity = o.quantity.to_f64().unwrap_or(0.);
+            match self.open.get_mut(&key) {
+                Some(record) if o.profit_bps >= self.setting.close_bps => {
+                    record.below_since = None;
+                    record.peak_profit_bps = record.peak_profit_bps.max(o.profit_bps);
+                    record.peak_quantity = record.peak_quantity.max(quantity);
+                    record.peak_size = match (record.peak_size, o.max_size) {
+                        (Some(a), Some(b)) => Some(a.max(b)),
+                        (a, b) => a.or(b),
+                    };
+                }
+                Some(_) => continue,
+                None if o.profit_bps >= self.setting.open_bps => {
+                    self.open.insert(
+                        key.clone(),
+                        Record {
+                            pair: self.pair.clone(),
+                            buy_exchange: o.buy_exchange.clone(),
+                            sell_exchange: o.sell_exchange.clone(),
+                            opened: now,
+                            closed: None,
+                            peak_profit_bps: o.profit_bps,
+                            peak_quantity: quantity,
+                            peak_size: o.max_size,
+                            below_since: None,
+                        },
+                    );
+                }
+                None => continue,
+            }
+            seen.push(key);
+        }
+
+        let grace = self.setting.close_grace_ms as u128;
+        let mut closing = vec![];
+        for (key, record) in self.open.iter_mut() {
+            if seen.contains(key) {
+                continue;
+            }
+            let since = *record.below_since.get_or_insert(now);
+            if now - since >= grace {
+                closing.push(key.clone());
+            }
+        }
+        for key in closing {
+            let mut record = self.open.remove(&key).unwrap();
+            record.closed = record.below_since;
+            info!(
+                "opportunity {} closed after {} ms, peak {:.2} bps",
+                record.route(),
+                record.closed.unwrap_or(now) - record.opened,
+                record.peak_profit_bps
+            );
+            self.closed.push_back(record);
+        }
+
+        let window = self.setting.window_secs as u128 * 1000;
+        while let Some(record) = self.closed.front() {
+            if record.closed.unwrap_or(now) + window >= now {
+                break;
+            }
+            self.closed.pop_front();
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut routes: BTreeMap<String, (Vec<f64>, Vec<f64>)> = BTreeMap::new();
+        let mut durations = vec![];
+        for record in self.closed.iter() {
+            let duration = (record.closed.unwrap_or(record.opened) - record.opened) as f64;
+            durations.push(duration);
+            let entry = routes.entry(record.route()).or_default();
+            entry.0.push(duration);
+            entry.1.push(record.peak_profit_bps);
+        }
+        Stats {
+            window_secs: self.setting.window_secs,
+            count: durations.len(),
+            median_duration_ms: median(durations),
+            routes: routes
+                .into_iter()
+                .map(|(route, (durations, peaks))| {
+                    let stats = RouteStats {
+                        count: durations.len(),
+                        median_duration_ms: median(durations).unwrap_or(0.),
+                        median_peak_bps: median(peaks).unwrap_or(0.),
+                    };
+                    (route, stats)
+                })
+                .collect(),
+        }
+    }
+
+    // open opportunities, and the ones closed at or after `since`
+    pub fn report(&self, since: u128) -> Report {
+        Report {
+            open: self.open.values().cloned().collect(),
+            closed: self
+                .closed
+                .iter()
+                .filter(|r| r.closed.unwrap_or(0) >= since)
+                .cloned()
+                .collect(),
+            stats: self.stats(),
+        }
+    }
+}
+
+static HISTORY: Lazy<Mutex<Option<History>>> = Lazy::new(|| Mutex::new(None));
+
+// None when the tracking is disabled
+pub fn snapshot(since: u128) -> Option<Report> {
+    HISTORY.lock().unwrap().as_ref().map(|h| h.report(since))
+}
+
+pub async fn run(
+    setting: OpportunitySetting,
+    detector: DetectorSetting,
+    pair: String,
+    mut rx: broadcast::Receiver<Arc<Summary>>,
+) {
+    *HISTORY.lock().unwrap() = Some(History::new(setting, &pair));
+    loop {
+        match rx.recv().await {
+            Ok(summary) => {
+                let now = get_unixtime();
+                let opportunities =
+                    opportunity::detect(&summary, &detector, &status::snapshot(), now);
+                if let Some(h) = HISTORY.lock().unwrap().as_mut() {
+                    h.update(&opportunities, now);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("opportunity history lagged {} summaries", n);
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigdecimal::BigDecimal;
+
+    fn route(buy: &str, sell: &str, profit_bps: f64) -> Opportunity {
+        Opportunity {
+            buy_exchange: buy.to_string(),
+            sell_exchange: sell.to_string(),
+            buy_price: BigDecimal::from(100),
+            sell_price: BigDecimal::from(101),
+            profit_bps,
+            quantity: BigDecimal::from(1),
+            sizes: vec![],
+            max_size: None,
+            confidence: 1.,
+            net: None,
+        }
+    }
+
+    fn history() -> History {
+        History::new(
+            OpportunitySetting {
+                open_bps: 10.,
+                close_bps: 5.,
+                close_grace_ms: 500,
+                window_secs: 60,
+            },
+            "BTC-AUD",
+        )
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let mut h = history();
+        // (time, profit of coinjar->kraken, profit of kraken->coinjar)
+        let path = [
+            (0, Some(12.), Some(9.)),
+            (100, Some(8.), Some(9.5)),
+            (200, Some(4.), None),
+            (300, Some(15.), Some(9.)),
+            (400, None, None),
+            (800, None, None),
+            (1000, None, None),
+        ];
+        for (now, a, b) in path {
+            let mut opportunities = vec![];
+            if let Some(p) = a {
+                opportunities.push(route("coinjar", "kraken", p));
+            }
+            if let Some(p) = b {
+                opportunities.push(route("kraken", "coinjar", p));
+            }
+            h.update(&opportunities, now);
+            if now == 300 {
+                let report = h.report(0);
+                assert_eq!(report.open.len(), 1);
+                assert!(report.closed.is_empty());
+            }
+        }
+        let report = h.report(0);
+        assert!(report.open.is_empty());
+        // kraken->coinjar stayed under open_bps, coinjar->kraken dipped under
+        // close_bps at 200 but came back within the grace period
+        assert_eq!(
+            report.closed,
+            vec![Record {
+                pair: "BTC-AUD".to_string(),
+                buy_exchange: "coinjar".to_string(),
+                sell_exchange: "kraken".to_string(),
+                opened: 0,
+                closed: Some(400),
+                peak_profit_bps: 15.,
+                peak_quantity: 1.,
+                peak_size: None,
+                below_since: Some(400),
+            }]
+        );
+        assert!(h.report(401).closed.is_empty());
+
+        h.update(&[route("coinjar", "kraken", 20.)], 2000);
+        h.update(&[], 3000);
+        h.update(&[], 3500);
+        let stats = h.stats();
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.median_duration_ms, Some(700.));
+        assert_eq!(
+            stats.routes["coinjar->kraken"],
+            RouteStats {
+                count: 2,
+                median_duration_ms: 700.,
+                median_peak_bps: 17.5,
+            }
+        );
+
+        // out of the window
+        h.update(&[], 63_001);
+        assert_eq!(h.stats().count, 0);
+    }
+}
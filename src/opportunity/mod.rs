@@ -0,0 +1,420 @@
+pub mod bridge;
+pub mod history;
+pub mod portfolio;
+pub mod tracker;
+
+use crate::config::{ConfidenceSetting, DetectorSetting};
+use crate::orderbook::{Level, Regime, Summary};
+use crate::status::ExchangeStatus;
+use crate::vwap;
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// best bid on one exchange above the best ask on another
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opportunity {
+    pub buy_exchange: String,
+    pub sell_exchange: String,
+    // best ask of the buy exchange
+    pub buy_price: BigDecimal,
+    // best bid of the sell exchange
+    pub sell_price: BigDecimal,
+    pub profit_bps: f64,
+    // smaller of the two top of book amounts
+    pub quantity: BigDecimal,
+    // executable profit per configured size, in the order of detector.sizes
+    pub sizes: Vec<SizedProfit>,
+    // largest fully filled size at or above detector.min_profit_bps
+    pub max_size: Option<f64>,
+    // in [0, 1], see confidence
+    pub confidence: f64,
+    // None unless a portfolio is configured, see portfolio::annotate
+    pub net: Option<portfolio::NetProfit>,
+}
+
+// both legs walked for one notional size: buy on the asks of the buy
+// exchange, sell the same base quantity on the bids of the sell exchange
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizedProfit {
+    // requested notional, in quote units
+    pub size: f64,
+    // quote spent on the buy leg. Below size when partial.
+    pub notional: BigDecimal,
+    pub quantity: BigDecimal,
+    pub buy_vwap: BigDecimal,
+    pub sell_vwap: BigDecimal,
+    pub profit_bps: f64,
+    // one of the books was too thin for the requested size
+    pub partial: bool,
+}
+
+impl Opportunity {
+    // buy->sell, stable name of the route
+    pub fn route(&self) -> String {
+        format!("{}->{}", self.buy_exchange, self.sell_exchange)
+    }
+}
+
+// How likely the opportunity is still there by the time anyone acts on it:
+//
+//   confidence = exp(-(age_weight * (age_buy / interval_buy + age_sell / interval_sell)
+//                      + reconnect_weight * reconnects of both venues in the window
+//                      + fault_weight * legs with a crossed / invalid book in the window))
+//
+// age is the time since the last update of the leg's book and interval its
+// typical update interval (moving average, at least min_interval_ms), so a
+// book is judged against its own cadence: a polled venue that skipped a
+// couple of polls scores like a streaming one that went quiet. 1 means fresh
+// books on stable venues; every penalty scales it down.
+pub fn confidence(
+    summary: &Summary,
+    exchanges: [&str; 2],
+    status: &HashMap<String, ExchangeStatus>,
+    setting: &ConfidenceSetting,
+    now: u128,
+) -> f64 {
+    let since = now.saturating_sub(setting.window_secs as u128 * 1000);
+    let min_interval = setting.min_interval_ms.max(1) as f64;
+    let mut penalty = 0.;
+    for exchange in exchanges {
+        let st = status.get(exchange);
+        let interval = st
+            .and_then(|st| st.update_interval_ms)
+            .unwrap_or(min_interval)
+            .max(min_interval);
+        // unknown age counts as one interval old
+        let age = summary
+            .timestamp
+            .get(exchange)
+            .and_then(|ts| ts.parse::<u128>().ok())
+            .map(|ts| now.saturating_sub(ts) as f64)
+            .unwrap_or(interval);
+        penalty += setting.age_weight * age / interval;
+        if let Some(st) = st {
+            penalty += setting.reconnect_weight * st.reconnects_since(since) as f64;
+            if st.last_fault.is_some_and(|t| t >= since) {
+                penalty += setting.fault_weight;
+            }
+        }
+    }
+    (-penalty).exp()
+}
+
+// the first level of each exchange. The summary levels are sorted best first.
+pub fn best_levels(levels: &[Level]) -> HashMap<&str, (BigDecimal, BigDecimal)> {
+    let mut result = HashMap::new();
+    for level in levels {
+        if result.contains_key(level.exchange.as_str()) {
+            continue;
+        }
+        let price = BigDecimal::from_str(&level.price);
+        let amount = BigDecimal::from_str(&level.amount);
+        if let (Ok(price), Ok(amount)) = (price, amount) {
+            result.insert(level.exchange.as_str(), (price, amount));
+        }
+    }
+    result
+}
+
+// None when one of the ladders is empty
+fn sized_profit(
+    asks: &[(BigDecimal, BigDecimal)],
+    bids: &[(BigDecimal, BigDecimal)],
+    size: f64,
+) -> Option<SizedProfit> {
+    let notional = BigDecimal::try_from(size).ok()?;
+    let mut buy = vwap::fill_notional(asks, &notional);
+    let sell = vwap::fill_quantity(bids, &buy.quantity);
+    // the bids are thinner: buy only what can be sold
+    if sell.quantity < buy.quantity {
+        buy = vwap::fill_quantity(asks, &sell.quantity);
+    }
+    if buy.notional.is_zero() {
+        return None;
+    }
+    let profit_bps = ((&sell.notional - &buy.notional) / &buy.notional * BigDecimal::from(10000))
+        .to_f64()
+        .unwrap_or(0.);
+    Some(SizedProfit {
+        size,
+        partial: buy.notional < notional,
+        buy_vwap: buy.vwap()?,
+        sell_vwap: sell.vwap()?,
+        notional: buy.notional,
+        quantity: sell.quantity,
+        profit_bps,
+    })
+}
+
+// Every crossed exchange pair of the summary, most profitable first. In a
+// volatile regime crossings are often latency mirages: they are either all
+// dropped, or need a profit widened by volatile_multiplier.
+pub fn detect(
+    summary: &Summary,
+    setting: &DetectorSetting,
+    status: &HashMap<String, ExchangeStatus>,
+    now: u128,
+) -> Vec<Opportunity> {
+    let volatile = summary
+        .stats
+        .volatility
+        .as_ref()
+        .is_some_and(|v| v.regime == Regime::Volatile);
+    if volatile && setting.suppress_volatile {
+        return vec![];
+    }
+    let min_profit_bps = match setting.volatile_multiplier {
+        Some(multiplier) if volatile => setting.min_profit_bps * multiplier,
+        _ => setting.min_profit_bps,
+    };
+    let bids = best_levels(&summary.bids);
+    let asks = best_levels(&summary.asks);
+    let mut result = vec![];
+    for (sell, (bid, bid_amount)) in bids.iter() {
+        for (buy, (ask, ask_amount)) in asks.iter() {
+            if sell == buy || bid <= ask {
+                continue;
+            }
+            let profit_bps = ((bid - ask) / ask * BigDecimal::from(10000))
+                .to_f64()
+                .unwrap_or(0.);
+            if volatile && profit_bps < min_profit_bps {
+                continue;
+            }
+            let sizes: Vec<SizedProfit> = if setting.sizes.is_empty() {
+                vec![]
+            } else {
+                let ask_ladder = vwap::ladder(&summary.asks, buy);
+                let bid_ladder = vwap::ladder(&summary.bids, sell);
+                setting
+                    .sizes
+                    .iter()
+                    .filter_map(|size| sized_profit(&ask_ladder, &bid_ladder, *size))
+                    .collect()
+            };
+            let max_size = sizes
+                .iter()
+                .filter(|s| !s.partial && s.profit_bps >= min_profit_bps)
+                .map(|s| s.size)
+                .reduce(f64::max);
+            result.push(Opportunity {
+                buy_exchange: buy.to_string(),
+                sell_exchange: sell.to_string(),
+                buy_price: ask.clone(),
+                sell_price: bid.clone(),
+                profit_bps,
+                quantity: bid_amount.min(ask_amount).clone(),
+                sizes,
+                max_size,
+                confidence: confidence(summary, [buy, sell], status, &setting.confidence, now),
+                net: None,
+            });
+        }
+    }
+    result.sort_by(|a, b| {
+        b.profit_bps
+            .total_cmp(&a.profit_bps)
+            .then_with(|| a.route().cmp(&b.route()))
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+    use crate::orderbook::Volatility;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_detect() {
+        let s = summary(
+            vec![
+                level("kraken", "102", "1"),
+                level("coinjar", "101", "2"),
+                level("btcmarkets", "99", "1"),
+            ],
+            vec![
+                level("btcmarkets", "100", "0.5"),
+                level("coinjar", "101.5", "1"),
+                level("kraken", "103", "1"),
+            ],
+            0,
+        );
+        let result = detect(&s, &DetectorSetting::default(), &HashMap::new(), 0);
+        let routes: Vec<String> = result.iter().map(|o| o.route()).collect();
+        assert_eq!(
+            routes,
+            vec![
+                "btcmarkets->kraken",
+                "btcmarkets->coinjar",
+                "coinjar->kraken"
+            ]
+        );
+        assert_eq!(result[0].profit_bps, 200.);
+        assert_eq!(result[0].quantity, BigDecimal::from_str("0.5").unwrap());
+        assert_eq!(result[0].buy_price, BigDecimal::from(100));
+        assert_eq!(result[0].sell_price, BigDecimal::from(102));
+        assert!(result[0].sizes.is_empty());
+        assert_eq!(result[0].max_size, None);
+    }
+
+    #[test]
+    fn test_detect_volatile() {
+        let mut s = summary(
+            vec![level("kraken", "101", "1"), level("coinjar", "100.2", "1")],
+            vec![level("btcmarkets", "100", "1")],
+            0,
+        );
+        s.stats.volatility = Some(Volatility {
+            windows: vec![],
+            regime: Regime::Volatile,
+        });
+        let mut setting = DetectorSetting {
+            min_profit_bps: 10.,
+            ..DetectorSetting::default()
+        };
+        // no policy: volatile is just reported
+        assert_eq!(detect(&s, &setting, &HashMap::new(), 0).len(), 2);
+        // 100 bps passes 10 * 5, 20 bps does not
+        setting.volatile_multiplier = Some(5.);
+        let result = detect(&s, &setting, &HashMap::new(), 0);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].route(), "btcmarkets->kraken");
+        setting.suppress_volatile = true;
+        assert!(detect(&s, &setting, &HashMap::new(), 0).is_empty());
+        // back to normal
+        s.stats.volatility.as_mut().unwrap().regime = Regime::Normal;
+        assert_eq!(detect(&s, &setting, &HashMap::new(), 0).len(), 2);
+    }
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_detect_sized() {
+        // thin on top, deep below on the asks; thinner bids
+        let s = summary(
+            vec![level("kraken", "102", "5"), level("kraken", "100.5", "10")],
+            vec![
+                level("coinspot", "100", "2"),
+                level("coinspot", "101", "20"),
+                level("kraken", "103", "1"),
+            ],
+            0,
+        );
+        let setting = DetectorSetting {
+            sizes: vec![100., 1000., 2000.],
+            min_profit_bps: 10.,
+            ..DetectorSetting::default()
+        };
+        let result = detect(&s, &setting, &HashMap::new(), 0);
+        assert_eq!(result.len(), 1);
+        let o = &result[0];
+        assert_eq!(o.route(), "coinspot->kraken");
+        // top of book says 200 bps
+        assert_eq!(o.profit_bps, 200.);
+        let sizes: Vec<(f64, f64, bool)> = o
+            .sizes
+            .iter()
+            .map(|s| (s.size, s.profit_bps, s.partial))
+            .collect();
+        assert_eq!(
+            sizes,
+            vec![
+                // 1 at 100, sold at 102
+                (100., 200., false),
+                // 2 at 100 + 800 / 101 at 101, 5 sold at 102 and the rest at 100.5
+                (1000., 45.396039603960396, false),
+                // 15 bids in total cap the quantity: 2 at 100 + 13 at 101,
+                // 5 sold at 102 + 10 at 100.5
+                (2000., 13.218770654329147, true),
+            ]
+        );
+        assert_eq!(o.sizes[0].quantity, dec("1"));
+        assert_eq!(o.sizes[0].buy_vwap, dec("100"));
+        assert_eq!(o.sizes[0].sell_vwap, dec("102"));
+        let partial = &o.sizes[2];
+        assert_eq!(partial.quantity, dec("15"));
+        assert_eq!(partial.notional, dec("1513"));
+        assert_eq!(partial.sell_vwap, dec("101"));
+        // the capped 2000 bucket is above 10 bps but did not fill
+        assert_eq!(o.max_size, Some(1000.));
+    }
+
+    #[test]
+    fn test_confidence() {
+        let now = 10_000;
+        let mut s = summary(
+            vec![level("kraken", "101", "1"), level("coinspot", "101", "1")],
+            vec![level("btcmarkets", "100", "1")],
+            now,
+        );
+        let steady = |interval: f64| ExchangeStatus {
+            connected: true,
+            update_interval_ms: Some(interval),
+            ..Default::default()
+        };
+        let mut status = HashMap::from([
+            ("btcmarkets".to_string(), steady(200.)),
+            ("kraken".to_string(), steady(200.)),
+            ("coinspot".to_string(), steady(3000.)),
+        ]);
+        let setting = ConfidenceSetting {
+            window_secs: 5,
+            ..ConfidenceSetting::default()
+        };
+        let score = |s: &Summary, status: &HashMap<String, ExchangeStatus>, sell: &str| {
+            confidence(s, ["btcmarkets", sell], status, &setting, now)
+        };
+
+        // fresh/fresh
+        s.timestamp
+            .insert("btcmarkets".to_string(), "9900".to_string());
+        s.timestamp.insert("kraken".to_string(), "9900".to_string());
+        let fresh = score(&s, &status, "kraken");
+        // 0.5 * (100 / 200) * 2
+        assert_eq!(fresh, (-0.5f64).exp());
+
+        // fresh/stale: kraken is 10 intervals old
+        s.timestamp.insert("kraken".to_string(), "8000".to_string());
+        let stale = score(&s, &status, "kraken");
+        assert!(stale < fresh);
+
+        // a polled venue is judged against its own cadence: 2s old coinspot
+        // still beats 2s old kraken
+        s.timestamp
+            .insert("coinspot".to_string(), "8000".to_string());
+        let polled = score(&s, &status, "coinspot");
+        assert!(polled > stale);
+        assert!(polled < fresh);
+
+        // flapping venue: fresh books, but reconnecting and crossed
+        s.timestamp.insert("kraken".to_string(), "9900".to_string());
+        let kraken = status.get_mut("kraken").unwrap();
+        kraken.reconnect_times.extend([9000, 9500, 9800]);
+        let reconnecting = score(&s, &status, "kraken");
+        assert!(reconnecting < fresh);
+        status.get_mut("kraken").unwrap().last_fault = Some(9900);
+        let flapping = score(&s, &status, "kraken");
+        assert!(flapping < reconnecting);
+        assert!(flapping > 0.);
+
+        // faults out of the window are forgotten
+        let kraken = status.get_mut("kraken").unwrap();
+        kraken.last_fault = Some(4000);
+        kraken.reconnect_times = VecDeque::from([1000, 4999]);
+        assert_eq!(score(&s, &status, "kraken"), fresh);
+
+        // and detect carries the score
+        let detector = DetectorSetting {
+            confidence: setting.clone(),
+            ..DetectorSetting::default()
+        };
+        let o = detect(&s, &detector, &status, now);
+        assert_eq!(o[0].route(), "btcmarkets->coinspot");
+        assert_eq!(o[0].confidence, polled);
+    }
+}
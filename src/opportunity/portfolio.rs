@@ -0,0 +1,298 @@
+use super::Opportunity;
+use crate::config::PortfolioSetting;
+use crate::simulation::{decimal, report};
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Execution {
+    // both venues hold enough to trade right away
+    Executable,
+    // funds have to be moved first
+    RequiresRebalancing,
+}
+
+// gross profit turned into net profit with the portfolio balances and costs
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct NetProfit {
+    pub execution: Execution,
+    // top of book quantity capped by the balances. Uncapped when rebalancing is required.
+    pub quantity: String,
+    pub gross_bps: f64,
+    // taker fees of both legs
+    pub fee_bps: f64,
+    // transfer cost per trade, in bps of the buy notional
+    pub rebalance_bps: f64,
+    pub net_bps: f64,
+    // trades of this quantity the balances allow
+    pub trades_left: u64,
+    // trades_left at the observed frequency of the route
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exhausted_in_secs: Option<f64>,
+    // when a rebalancing transfer is accounted for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_mins: Option<f64>,
+}
+
+// Pure model: the balances never move, no order is ever sent.
+pub struct Portfolio {
+    setting: PortfolioSetting,
+    // route -> unix millis it started crossing, within the window
+    seen: HashMap<String, VecDeque<u128>>,
+    // routes crossed in the last observed summary
+    crossed: HashSet<String>,
+}
+
+impl Portfolio {
+    pub fn new(setting: PortfolioSetting) -> Portfolio {
+        Portfolio {
+            setting,
+            seen: HashMap::new(),
+            crossed: HashSet::new(),
+        }
+    }
+
+    // the opportunities of one summary, at `now`
+    pub fn observe(&mut self, opportunities: &[Opportunity], now: u128) {
+        let crossed: HashSet<String> = opportunities.iter().map(|o| o.route()).collect();
+        for route in crossed.difference(&self.crossed) {
+            self.seen.entry(route.clone()).or_default().push_back(now);
+        }
+        self.crossed = crossed;
+        let since = now.saturating_sub(self.setting.window_secs as u128 * 1000);
+        self.seen.retain(|_, times| {
+            while times.front().is_some_and(|t| *t < since) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+    }
+
+    // appearances per second of the route over the window
+    fn frequency(&self, route: &str) -> Option<f64> {
+        let count = self.seen.get(route)?.len();
+        Some(count as f64 / self.setting.window_secs as f64)
+    }
+
+    // None when one of the venues is not part of the portfolio
+    pub fn evaluate(&self, o: &Opportunity) -> Option<NetProfit> {
+        let buy = self.setting.venues.get(&o.buy_exchange)?;
+        let sell = self.setting.venues.get(&o.sell_exchange)?;
+        if o.buy_price <= BigDecimal::zero() {
+            return None;
+        }
+        let affordable = decimal(buy.quote) / &o.buy_price;
+        let base = decimal(sell.base);
+        let capped = o.quantity.clone().min(affordable.clone()).min(base.clone());
+        let fee_bps = buy.taker_fee_bps + sell.taker_fee_bps;
+        // base goes back from the buy venue, quote from the sell venue
+        let transfer_cost = decimal(self.setting.base.withdrawal_fee) * &o.sell_price
+            + decimal(self.setting.quote.withdrawal_fee);
+        let transfer_mins = self
+            .setting
+            .base
+            .transfer_mins
+            .max(self.setting.quote.transfer_mins);
+        let bps = |cost: BigDecimal, quantity: &BigDecimal| {
+            let notional = quantity * &o.buy_price;
+            if notional.is_zero() {
+                return 0.;
+            }
+            (cost / notional * BigDecimal::from(10000))
+                .normalized()
+                .to_f64()
+                .unwrap_or(0.)
+        };
+
+        let (execution, quantity, trades_left, rebalance_bps, transfer) =
+            if capped <= BigDecimal::zero() {
+                // a full transfer lands on this single trade
+                let rebalance_bps = bps(transfer_cost, &o.quantity);
+                (
+                    Execution::RequiresRebalancing,
+                    o.quantity.clone(),
+                    0,
+                    rebalance_bps,
+                    Some(transfer_mins),
+                )
+            } else {
+                let trades_left = (&affordable / &capped)
+                    .min(&base / &capped)
+                    .to_f64()
+                    .unwrap_or(0.)
+                    .floor() as u64;
+                if trades_left < self.setting.rebalance_trades {
+                    let per_trade = transfer_cost / BigDecimal::from(trades_left.max(1));
+                    let rebalance_bps = bps(per_trade, &capped);
+                    (
+                        Execution::Executable,
+                        capped,
+                        trades_left,
+                        rebalance_bps,
+                        Some(transfer_mins),
+                    )
+                } else {
+                    (Execution::Executable, capped, trades_left, 0., None)
+                }
+            };
+        Some(NetProfit {
+            execution,
+            quantity: report(&quantity),
+            gross_bps: o.profit_bps,
+            fee_bps,
+            rebalance_bps,
+            net_bps: o.profit_bps - fee_bps - rebalance_bps,
+            trades_left,
+            exhausted_in_secs: self.frequency(&o.route()).map(|f| trades_left as f64 / f),
+            transfer_mins: transfer,
+        })
+    }
+}
+
+static PORTFOLIO: Lazy<Mutex<Option<Portfolio>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn init(setting: PortfolioSetting) {
+    *PORTFOLIO.lock().unwrap() = Some(Portfolio::new(setting));
+}
+
+// feed the opportunities of every published summary. No-op until init.
+pub fn observe(opportunities: &[Opportunity], now: u128) {
+    if let Some(p) = PORTFOLIO.lock().unwrap().as_mut() {
+        p.observe(opportunities, now);
+    }
+}
+
+// fill in the net profit of the opportunities. No-op until init.
+pub fn annotate(opportunities: &mut [Opportunity]) {
+    if let Some(p) = PORTFOLIO.lock().unwrap().as_ref() {
+        for o in opportunities.iter_mut() {
+            o.net = p.evaluate(o);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{TransferSetting, VenueSetting};
+    use std::str::FromStr;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    // buy coinjar at 100, sell kraken at 101, 1 on top of both books
+    fn opportunity() -> Opportunity {
+        Opportunity {
+            buy_exchange: "coinjar".to_string(),
+            sell_exchange: "kraken".to_string(),
+            buy_price: dec("100"),
+            sell_price: dec("101"),
+            profit_bps: 100.,
+            quantity: dec("1"),
+            sizes: vec![],
+            max_size: None,
+            confidence: 1.,
+            net: None,
+        }
+    }
+
+    // kraken holds `base`, coinjar holds 20000 quote
+    fn portfolio(base: f64) -> Portfolio {
+        Portfolio::new(PortfolioSetting {
+            venues: HashMap::from([
+                (
+                    "coinjar".to_string(),
+                    VenueSetting {
+                        base: 0.,
+                        quote: 20000.,
+                        taker_fee_bps: 10.,
+                    },
+                ),
+                (
+                    "kraken".to_string(),
+                    VenueSetting {
+                        base,
+                        quote: 0.,
+                        taker_fee_bps: 0.,
+                    },
+                ),
+            ]),
+            // 0.0001 * 101 + 1 = 1.0101 quote per rebalance
+            base: TransferSetting {
+                withdrawal_fee: 0.0001,
+                transfer_mins: 30.,
+            },
+            quote: TransferSetting {
+                withdrawal_fee: 1.,
+                transfer_mins: 60.,
+            },
+            rebalance_trades: 10,
+            window_secs: 100,
+        })
+    }
+
+    #[test]
+    fn test_net_profit() {
+        let o = opportunity();
+
+        // plenty of both: only the fees
+        let net = portfolio(50.).evaluate(&o).unwrap();
+        assert_eq!(net.execution, Execution::Executable);
+        assert_eq!(net.quantity, "1");
+        assert_eq!(net.trades_left, 50);
+        assert_eq!(net.fee_bps, 10.);
+        assert_eq!(net.rebalance_bps, 0.);
+        assert_eq!(net.net_bps, 90.);
+        assert_eq!(net.transfer_mins, None);
+
+        // runs out of base in 5 trades: 1.0101 / 5 on a notional of 100
+        let net = portfolio(5.).evaluate(&o).unwrap();
+        assert_eq!(net.trades_left, 5);
+        assert_eq!(net.rebalance_bps, 20.202);
+        assert_eq!(net.net_bps, 100. - 10. - 20.202);
+        assert_eq!(net.transfer_mins, Some(60.));
+
+        // capped by the balance: 0.5 on a notional of 50, last trade
+        let net = portfolio(0.5).evaluate(&o).unwrap();
+        assert_eq!(net.execution, Execution::Executable);
+        assert_eq!(net.quantity, "0.5");
+        assert_eq!(net.trades_left, 1);
+        assert_eq!(net.rebalance_bps, 202.02);
+        assert!(net.net_bps < 0.);
+
+        // nothing to sell on kraken
+        let net = portfolio(0.).evaluate(&o).unwrap();
+        assert_eq!(net.execution, Execution::RequiresRebalancing);
+        assert_eq!(net.quantity, "1");
+        assert_eq!(net.trades_left, 0);
+        assert_eq!(net.rebalance_bps, 101.01);
+
+        // venue out of the portfolio
+        let mut other = opportunity();
+        other.sell_exchange = "btcmarkets".to_string();
+        assert_eq!(portfolio(1.).evaluate(&other), None);
+    }
+
+    #[test]
+    fn test_frequency() {
+        let mut p = portfolio(5.);
+        let o = opportunity();
+        // starts crossing at 0 and 2000, still crossed at 3000
+        for (now, crossed) in [(0, true), (500, false), (2000, true), (3000, true)] {
+            let opportunities = if crossed { vec![o.clone()] } else { vec![] };
+            p.observe(&opportunities, now);
+        }
+        // 2 appearances over 100 secs: 5 trades last 250 secs
+        assert_eq!(p.evaluate(&o).unwrap().exhausted_in_secs, Some(250.));
+        // out of the window
+        p.observe(&[], 100_001);
+        assert_eq!(p.evaluate(&o).unwrap().exhausted_in_secs, Some(500.));
+        p.observe(&[], 102_001);
+        assert_eq!(p.evaluate(&o).unwrap().exhausted_in_secs, None);
+    }
+}
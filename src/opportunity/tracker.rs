@@ -0,0 +1,243 @@
+use super::Opportunity;
+use crate::config::DetectorSetting;
+use crate::orderbook::OpportunitySummary;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    // first emission, after min_persistence_ms
+    New,
+    // still there, at most every debounce_ms
+    Update,
+    // gone (or under min_profit_bps) for longer than grace_ms
+    Closed,
+}
+
+// one emission of a tracked opportunity. A closed event carries the last
+// seen values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub state: State,
+    pub pair: String,
+    // unix millis it was first seen
+    pub opened: u128,
+    pub opportunity: Opportunity,
+}
+
+impl Event {
+    pub fn to_summary(&self) -> OpportunitySummary {
+        let o = &self.opportunity;
+        OpportunitySummary {
+            state: self.state,
+            buy_exchange: o.buy_exchange.clone(),
+            sell_exchange: o.sell_exchange.clone(),
+            opened: self.opened,
+            profit_bps: o.profit_bps,
+            quantity: o.quantity.to_string(),
+            max_size: o.max_size,
+            confidence: o.confidence,
+            net: o.net.clone(),
+        }
+    }
+}
+
+struct Tracked {
+    first_seen: u128,
+    // None until emitted as new
+    last_emitted: Option<u128>,
+    // first time it went missing since last seen
+    below_since: Option<u128>,
+    last: Opportunity,
+}
+
+// Turns the opportunities detected on every summary into new / update /
+// closed events keyed by (buy venue, sell venue, pair). Flickers shorter
+// than min_persistence_ms are never emitted, and an emitted opportunity
+// survives dips of up to grace_ms without closing and reopening.
+pub struct Tracker {
+    setting: DetectorSetting,
+    pair: String,
+    tracked: BTreeMap<(String, String, String), Tracked>,
+}
+
+impl Tracker {
+    pub fn new(setting: DetectorSetting, pair: &str) -> Tracker {
+        Tracker {
+            setting,
+            pair: pair.to_string(),
+            tracked: BTreeMap::new(),
+        }
+    }
+
+    // feed the opportunities detected at `now`, get the events to send
+    pub fn update(&mut self, opportunities: &[Opportunity], now: u128) -> Vec<Event> {
+        let mut result = vec![];
+        let mut seen = vec![];
+        for o in opportunities {
+            if o.profit_bps < self.setting.min_profit_bps {
+                continue;
+            }
+            let key = (
+                o.buy_exchange.clone(),
+                o.sell_exchange.clone(),
+                self.pair.clone(),
+            );
+            let tracked = self.tracked.entry(key.clone()).or_insert(Tracked {
+                first_seen: now,
+                last_emitted: None,
+                below_since: None,
+                last: o.clone(),
+            });
+            tracked.below_since = None;
+            tracked.last = o.clone();
+            let state = match tracked.last_emitted {
+                None if now - tracked.first_seen >= self.setting.min_persistence_ms as u128 => {
+                    Some(State::New)
+                }
+                Some(last) if now - last >= self.setting.debounce_ms as u128 => Some(State::Update),
+                _ => None,
+            };
+            if let Some(state) = state {
+                tracked.last_emitted = Some(now);
+                result.push(Event {
+                    state,
+                    pair: self.pair.clone(),
+                    opened: tracked.first_seen,
+                    opportunity: o.clone(),
+                });
+            }
+            seen.push(key);
+        }
+
+        let grace = self.setting.grace_ms as u128;
+        let mut closing = vec![];
+        for (key, tracked) in self.tracked.iter_mut() {
+            if seen.contains(key) {
+                continue;
+            }
+            // never emitted: the continuous run is broken, start over
+            if tracked.last_emitted.is_none() {
+                closing.push(key.clone());
+                continue;
+            }
+            let since = *tracked.below_since.get_or_insert(now);
+            if now - since >= grace {
+                closing.push(key.clone());
+            }
+        }
+        for key in closing {
+            let tracked = self.tracked.remove(&key).unwrap();
+            if tracked.last_emitted.is_some() {
+                result.push(Event {
+                    state: State::Closed,
+                    pair: self.pair.clone(),
+                    opened: tracked.first_seen,
+                    opportunity: tracked.last,
+                });
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigdecimal::BigDecimal;
+
+    fn route(profit_bps: f64) -> Opportunity {
+        Opportunity {
+            buy_exchange: "coinjar".to_string(),
+            sell_exchange: "kraken".to_string(),
+            buy_price: BigDecimal::from(100),
+            sell_price: BigDecimal::from(101),
+            profit_bps,
+            quantity: BigDecimal::from(1),
+            sizes: vec![],
+            max_size: None,
+            confidence: 1.,
+            net: None,
+        }
+    }
+
+    fn tracker() -> Tracker {
+        Tracker::new(
+            DetectorSetting {
+                min_profit_bps: 5.,
+                min_persistence_ms: 300,
+                debounce_ms: 1000,
+                grace_ms: 500,
+                ..DetectorSetting::default()
+            },
+            "BTC-AUD",
+        )
+    }
+
+    // (time, profit of coinjar->kraken) -> states emitted at each step
+    fn run(t: &mut Tracker, path: &[(u128, Option<f64>)]) -> Vec<(u128, State)> {
+        let mut result = vec![];
+        for (now, profit) in path {
+            let opportunities: Vec<Opportunity> = profit.iter().map(|p| route(*p)).collect();
+            for e in t.update(&opportunities, *now) {
+                assert_eq!(e.pair, "BTC-AUD");
+                result.push((*now, e.state));
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_flicker_suppressed() {
+        let mut t = tracker();
+        // never there for 300ms in a row, or under the threshold
+        let path = [
+            (0, Some(10.)),
+            (200, None),
+            (300, Some(10.)),
+            (500, Some(4.)),
+            (600, Some(10.)),
+            (800, Some(10.)),
+            (850, None),
+        ];
+        assert!(run(&mut t, &path).is_empty());
+    }
+
+    #[test]
+    fn test_grace_window() {
+        let mut t = tracker();
+        let path = [
+            (0, Some(10.)),
+            (300, Some(10.)),
+            // debounced
+            (400, Some(12.)),
+            // dips within the grace window
+            (600, None),
+            (900, Some(3.)),
+            (1000, Some(10.)),
+            (1300, Some(11.)),
+        ];
+        assert_eq!(
+            run(&mut t, &path),
+            vec![(300, State::New), (1300, State::Update)]
+        );
+    }
+
+    #[test]
+    fn test_closed_event() {
+        let mut t = tracker();
+        run(&mut t, &[(0, Some(10.)), (300, Some(20.))]);
+        assert!(t.update(&[], 400).is_empty());
+        let events = t.update(&[route(2.)], 900);
+        assert_eq!(events.len(), 1);
+        let e = &events[0];
+        assert_eq!(e.state, State::Closed);
+        assert_eq!(e.opened, 0);
+        // the last values seen above the threshold
+        assert_eq!(e.opportunity.profit_bps, 20.);
+        // and it starts over
+        assert!(t.update(&[route(10.)], 1000).is_empty());
+        assert_eq!(t.update(&[route(10.)], 1300)[0].state, State::New);
+    }
+}
@@ -1,35 +1,88 @@
+use crate::config::{OutputPrecision, Rounding, Sides};
+use crate::opportunity::portfolio::NetProfit;
+use crate::opportunity::tracker::State;
+use crate::schema::SCHEMA_VERSION;
 use anyhow::Result;
-use bigdecimal::{BigDecimal, Zero};
+use bigdecimal::{BigDecimal, One, RoundingMode, Signed, ToPrimitive, Zero};
 use log::error;
+use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
-use std::collections::{BTreeMap, HashMap};
-use std::ops::Bound;
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ops::Index;
 use std::time::SystemTime;
 
+/// Side of a book level.
 #[derive(Clone, Copy)]
 pub enum Side {
     Bid,
     Ask,
 }
 
-fn get_unixtime() -> u128 {
+/// Now, in unix millis. The time the books are stamped with.
+pub fn get_unixtime() -> u128 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_millis()
 }
 
+/// Perpetual futures data, from the mark price stream.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Derivatives {
+    pub(crate) mark_price: BigDecimal,
+    pub(crate) index_price: BigDecimal,
+    pub(crate) funding_rate: BigDecimal,
+    // unix millis
+    pub(crate) next_funding_time: u64,
+}
+
+impl Derivatives {
+    pub fn mark_price(&self) -> &BigDecimal {
+        &self.mark_price
+    }
+    pub fn index_price(&self) -> &BigDecimal {
+        &self.index_price
+    }
+    pub fn funding_rate(&self) -> &BigDecimal {
+        &self.funding_rate
+    }
+    /// Unix millis.
+    pub fn next_funding_time(&self) -> u64 {
+        self.next_funding_time
+    }
+}
+
+// one print from a trade channel
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trade {
+    // as the venue names it in the frame
+    pub(crate) pair: String,
+    // unix millis, venue time
+    pub(crate) timestamp: u128,
+    pub(crate) quantity: BigDecimal,
+}
+
+/// The book of one exchange: price -> amount per side, and what came with
+/// it. The exchanges keep theirs trimmed to the levels of the pair.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Orderbook {
     pub(crate) name: String,
+    // set by the connections carrying several pairs, ex: combined streams
+    pub(crate) pair: Option<String>,
     pub(crate) timestamp: u128,
     pub(crate) volume: BigDecimal,
     pub(crate) last_price: BigDecimal,
     pub(crate) bid: BTreeMap<BigDecimal, BigDecimal>,
     pub(crate) ask: BTreeMap<BigDecimal, BigDecimal>,
+    // Some for derivatives books. They are left out of the spot mid.
+    pub(crate) derivatives: Option<Derivatives>,
 }
 
 impl Orderbook {
+    /// Set the amount of a price level, a zero amount removes it. Stamps
+    /// the book with the local time.
     pub fn insert(&mut self, side: Side, price: BigDecimal, volume: BigDecimal) {
         match side {
             Side::Bid => {
@@ -57,17 +110,144 @@ impl Orderbook {
             }
         }
     }
+    /// An empty book of the exchange `name`.
     pub fn new(name: &str) -> Orderbook {
         Orderbook {
             name: name.to_string(),
+            pair: None,
             bid: BTreeMap::new(),
             ask: BTreeMap::new(),
             timestamp: get_unixtime(),
             last_price: BigDecimal::zero(),
             volume: BigDecimal::zero(),
+            derivatives: None,
         }
     }
-    // used to trim bid/ask to level numbers of price bars
+    /// Exchange the book is of.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Pair, as the venue names it, on the connections carrying several.
+    pub fn pair(&self) -> Option<&str> {
+        self.pair.as_deref()
+    }
+    /// Unix millis of the last update.
+    pub fn timestamp(&self) -> u128 {
+        self.timestamp
+    }
+    /// Stamp the book with the venue's time instead of the local one.
+    pub fn set_timestamp(&mut self, timestamp: u128) {
+        self.timestamp = timestamp;
+    }
+    /// Equal to `other` but for the time of the last update, which two
+    /// parsers of the same frames stamp differently.
+    pub fn same_book(&self, other: &Orderbook) -> bool {
+        let Orderbook {
+            name,
+            pair,
+            timestamp: _,
+            volume,
+            last_price,
+            bid,
+            ask,
+            derivatives,
+        } = self;
+        (name, pair, volume, last_price, bid, ask, derivatives)
+            == (
+                &other.name,
+                &other.pair,
+                &other.volume,
+                &other.last_price,
+                &other.bid,
+                &other.ask,
+                &other.derivatives,
+            )
+    }
+    /// Tag the book with its pair, for a connection carrying several.
+    pub fn set_pair(&mut self, pair: &str) {
+        self.pair = Some(pair.to_string());
+    }
+    /// Traded volume, where the venue reports it.
+    pub fn volume(&self) -> &BigDecimal {
+        &self.volume
+    }
+    /// Last traded price, where the venue reports it.
+    pub fn last_price(&self) -> &BigDecimal {
+        &self.last_price
+    }
+    /// Bids, price -> amount, the best last.
+    pub fn bid(&self) -> &BTreeMap<BigDecimal, BigDecimal> {
+        &self.bid
+    }
+    /// Asks, price -> amount, the best first.
+    pub fn ask(&self) -> &BTreeMap<BigDecimal, BigDecimal> {
+        &self.ask
+    }
+    /// Some for the books of derivatives exchanges.
+    pub fn derivatives(&self) -> Option<&Derivatives> {
+        self.derivatives.as_ref()
+    }
+    /// (price, amount) of the best bid.
+    pub fn best_bid(&self) -> Option<(&BigDecimal, &BigDecimal)> {
+        self.bid.last_key_value()
+    }
+    /// (price, amount) of the best ask.
+    pub fn best_ask(&self) -> Option<(&BigDecimal, &BigDecimal)> {
+        self.ask.first_key_value()
+    }
+    /// Mid of the top of book, if both sides have levels.
+    pub fn mid(&self) -> Option<BigDecimal> {
+        let best_bid = self.best_bid()?.0;
+        let best_ask = self.best_ask()?.0;
+        Some((best_bid + best_ask) / BigDecimal::from(2))
+    }
+    /// Best bid at or above best ask.
+    pub fn crossed(&self) -> bool {
+        match (self.best_bid(), self.best_ask()) {
+            (Some((bid, _)), Some((ask, _))) => bid >= ask,
+            _ => false,
+        }
+    }
+    /// Copy of the book limited to the best `level` bars of each side, with
+    /// the side left out by `sides` empty.
+    pub fn top(&self, level: u32, sides: Sides) -> Orderbook {
+        let level = level as usize;
+        let (bid_level, ask_level) = match sides {
+            Sides::Both => (level, level),
+            Sides::BidOnly => (level, 0),
+            Sides::AskOnly => (0, level),
+        };
+        Orderbook {
+            name: self.name.clone(),
+            pair: self.pair.clone(),
+            timestamp: self.timestamp,
+            volume: self.volume.clone(),
+            last_price: self.last_price.clone(),
+            bid: self
+                .bid
+                .iter()
+                .rev()
+                .take(bid_level)
+                .map(|(p, v)| (p.clone(), v.clone()))
+                .collect(),
+            ask: self
+                .ask
+                .iter()
+                .take(ask_level)
+                .map(|(p, v)| (p.clone(), v.clone()))
+                .collect(),
+            derivatives: self.derivatives.clone(),
+        }
+    }
+    /// Clear the side left out by `sides`.
+    pub fn keep(&mut self, sides: Sides) {
+        match sides {
+            Sides::Both => {}
+            Sides::BidOnly => self.ask.clear(),
+            Sides::AskOnly => self.bid.clear(),
+        }
+    }
+    /// Trim bid/ask to `level` price bars each.
     pub fn trim(&mut self, level: u32) {
         let l = self.bid.len();
         for _ in (level as usize)..l {
@@ -78,152 +258,785 @@ impl Orderbook {
             self.ask.pop_last();
         }
     }
+    /// The book of the reversed pair, ex: BTC/AUD -> AUD/BTC. Prices become
+    /// 1/price, to `prec` significant digits, the asks become the bids and
+    /// the other way round, and the amounts move to the other currency,
+    /// amount * price. Levels meeting at one rounded price are summed.
+    pub fn inverted(&self, prec: u64) -> Orderbook {
+        let invert = |price: &BigDecimal| (BigDecimal::one() / price).with_prec(prec);
+        let flip = |levels: &BTreeMap<BigDecimal, BigDecimal>| {
+            let mut flipped: BTreeMap<BigDecimal, BigDecimal> = BTreeMap::new();
+            for (price, amount) in levels.iter().filter(|(p, _)| !p.is_zero()) {
+                *flipped.entry(invert(price)).or_default() += amount * price;
+            }
+            flipped
+        };
+        let reciprocal = |value: &BigDecimal| match value.is_zero() {
+            true => BigDecimal::zero(),
+            false => invert(value),
+        };
+        Orderbook {
+            name: self.name.clone(),
+            pair: self.pair.clone(),
+            timestamp: self.timestamp,
+            // the base volume in the quote, at the last price
+            volume: &self.volume * &self.last_price,
+            last_price: reciprocal(&self.last_price),
+            bid: flip(&self.ask),
+            ask: flip(&self.bid),
+            derivatives: self.derivatives.as_ref().map(|d| Derivatives {
+                mark_price: reciprocal(&d.mark_price),
+                index_price: reciprocal(&d.index_price),
+                funding_rate: d.funding_rate.clone(),
+                next_funding_time: d.next_funding_time,
+            }),
+        }
+    }
 }
 
-// AggregatedOrderbook works like this:
-// new() -> merge(ob1) -> merge(ob2) -> ... -> merge(obN) -> finalize(max_level)
-// max_level here is used to limit the depth of orderbook to reach in this call
+// (perp mid - spot aggregate mid) / spot aggregate mid, in bps
+fn basis_bps(mid: &Option<BigDecimal>, spot_mid: &Option<BigDecimal>) -> Option<f64> {
+    match (mid, spot_mid) {
+        (Some(m), Some(s)) if !s.is_zero() => ((m - s) / s * BigDecimal::from(10000)).to_f64(),
+        _ => None,
+    }
+}
+
+/// The books of several exchanges merged into one:
+/// `new()` -> `merge(ob1)` -> `merge(ob2)` -> ... -> `merge(obN)` -> `finalize()`.
+/// Kept from one publish to the next, `remerge` takes the books in place of
+/// the previous ones.
 #[derive(Debug)]
 pub struct AggregatedOrderbook {
     pub spread: f64,
     pub bid: BTreeMap<BigDecimal, Vec<(String, BigDecimal)>>,
     pub ask: BTreeMap<BigDecimal, Vec<(String, BigDecimal)>>,
-    pub timestamp: HashMap<String, u128>,
-    pub volume: HashMap<String, BigDecimal>,
-    pub last_price: HashMap<String, BigDecimal>,
+    // sorted by exchange, so the output is the same whatever the merge order
+    pub timestamp: ScalarMap<u128>,
+    pub volume: ScalarMap<BigDecimal>,
+    pub last_price: ScalarMap<BigDecimal>,
+    // source -> polled index price, see apitree::index
+    pub index_prices: BTreeMap<String, BigDecimal>,
+    // exchange -> (derivatives data, mid of the derivatives book)
+    pub derivatives: BTreeMap<String, (Derivatives, Option<BigDecimal>)>,
+    // best bid / ask over the spot books only
+    pub spot_bid: Option<BigDecimal>,
+    pub spot_ask: Option<BigDecimal>,
+    // distinct prices per side in the consolidated section. None => no section.
+    pub consolidated_levels: Option<usize>,
+    // applied to the serialized prices and amounts only
+    pub output_precision: Option<OutputPrecision>,
+    // age_ms of the levels, None => left out
+    pub level_ages: Option<LevelAges>,
+    // exchange -> the levels its book came with
+    pub depth: BTreeMap<String, Depth>,
+    // written by finalize, for SummaryView
+    formatted: Formatted,
+}
+
+/// The price levels a merged book has per side. Some venues only publish
+/// the top few, see `shallower_than`.
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Depth {
+    pub bid: usize,
+    pub ask: usize,
+}
+
+impl Depth {
+    /// One side has fewer than `levels` levels: a metric over that many
+    /// only sees part of the book.
+    pub fn shallower_than(&self, levels: usize) -> bool {
+        self.bid < levels || self.ask < levels
+    }
+}
+
+// the decimals a price or an amount is written with, None => as it is
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Dp(Option<(i64, RoundingMode)>);
+
+impl Dp {
+    fn apply(self, value: &BigDecimal) -> Cow<'_, BigDecimal> {
+        match self.0 {
+            // trailing zeros dropped, 0.30000000 is written 0.3
+            Some((dp, mode)) => Cow::Owned(value.with_scale_round(dp, mode).normalized()),
+            None => Cow::Borrowed(value),
+        }
+    }
+}
+
+/// exchange -> one of the scalars of an aggregate, with its output form.
+/// The scalars change far less often than the ladders: `finalize` only
+/// writes again the values set since its last run, and the serializer
+/// reuses the json of the map until one is.
+#[derive(Debug)]
+pub struct ScalarMap<T> {
+    values: BTreeMap<String, T>,
+    // the values as published, written with `dp`
+    text: BTreeMap<String, String>,
+    // None before the first write
+    dp: Option<Dp>,
+    // set since the last write
+    dirty: BTreeSet<String>,
+    // the text as json, None after the text changed until the next write
+    json: Option<Box<RawValue>>,
+    // bumped by every change of the text
+    version: u64,
+    format: fn(&T, Dp) -> String,
+}
+
+impl<T: PartialEq> ScalarMap<T> {
+    fn new(format: fn(&T, Dp) -> String) -> Self {
+        ScalarMap {
+            values: BTreeMap::new(),
+            text: BTreeMap::new(),
+            dp: None,
+            dirty: BTreeSet::new(),
+            json: None,
+            version: 0,
+            format,
+        }
+    }
+
+    pub fn get(&self, exchange: &str) -> Option<&T> {
+        self.values.get(exchange)
+    }
+
+    pub fn contains_key(&self, exchange: &str) -> bool {
+        self.values.contains_key(exchange)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &T)> {
+        self.values.iter()
+    }
+
+    // the number of changes of the text so far
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // a no-op when the value is the same
+    pub fn insert(&mut self, exchange: &str, value: T) {
+        if self.values.get(exchange) == Some(&value) {
+            return;
+        }
+        self.values.insert(exchange.to_string(), value);
+        self.dirty.insert(exchange.to_string());
+    }
+
+    // sets the value of `exchange` when it is zero, true when it did
+    pub fn fill_zero(&mut self, exchange: &str, value: &T) -> bool
+    where
+        T: Zero + Clone,
+    {
+        if self.values.get(exchange).is_some_and(|v| v.is_zero()) {
+            self.insert(exchange, value.clone());
+            return true;
+        }
+        false
+    }
+
+    fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        let before = self.values.len();
+        self.values.retain(|exchange, _| keep(exchange));
+        if self.values.len() != before {
+            self.text.retain(|exchange, _| keep(exchange));
+            self.dirty.retain(|exchange| keep(exchange));
+            self.changed();
+        }
+    }
+
+    fn changed(&mut self) {
+        self.version += 1;
+        self.json = None;
+    }
+
+    // brings the text up to date, with the decimals `dp`
+    fn write(&mut self, dp: Dp) {
+        if self.dp != Some(dp) {
+            self.dp = Some(dp);
+            self.dirty = self.values.keys().cloned().collect();
+        }
+        // a value set back and forth within a tick writes the same text
+        let mut changed = false;
+        for exchange in std::mem::take(&mut self.dirty) {
+            let text = (self.format)(&self.values[&exchange], dp);
+            if self.text.get(&exchange) != Some(&text) {
+                self.text.insert(exchange, text);
+                changed = true;
+            }
+        }
+        if changed {
+            self.changed();
+        }
+        if self.json.is_none() {
+            self.json = serde_json::value::to_raw_value(&self.text).ok();
+        }
+    }
+
+    // the text, when it is up to date for `dp`
+    fn written(&self, dp: Dp) -> Option<&BTreeMap<String, String>> {
+        (self.dp == Some(dp) && self.dirty.is_empty()).then_some(&self.text)
+    }
 }
 
-#[derive(Debug, Serialize, PartialEq)]
+impl<T: PartialEq> Index<&str> for ScalarMap<T> {
+    type Output = T;
+
+    fn index(&self, exchange: &str) -> &T {
+        &self.values[exchange]
+    }
+}
+
+// a scalar map as published, from the text written by finalize when it is
+// up to date
+struct ScalarView<'a, T>(&'a ScalarMap<T>, Dp);
+
+impl<T: PartialEq> Serialize for ScalarView<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let ScalarView(map, dp) = *self;
+        match (map.written(dp), map.json.as_ref()) {
+            // the raw json only goes out as it is to a json serializer
+            (Some(_), Some(json)) if serializer.is_human_readable() => json.serialize(serializer),
+            (Some(text), _) => text.serialize(serializer),
+            (None, _) => {
+                serializer.collect_map(map.values.iter().map(|(k, v)| (k, (map.format)(v, dp))))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
 pub struct Level {
-    exchange: String,
-    price: String,
-    amount: String,
+    pub exchange: String,
+    pub price: String,
+    pub amount: String,
+    // ms since the amount last changed, with level_ages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_ms: Option<u64>,
 }
 
+/// Milliseconds since the amount of each level last changed, per side:
+/// exchange -> price -> age. See `analytics::ages`.
+#[derive(Debug, Default, PartialEq)]
+pub struct LevelAges {
+    pub bid: BTreeMap<String, BTreeMap<BigDecimal, u64>>,
+    pub ask: BTreeMap<String, BTreeMap<BigDecimal, u64>>,
+}
+
+impl LevelAges {
+    fn get(&self, side: Side, exchange: &str, price: &BigDecimal) -> Option<u64> {
+        let ages = match side {
+            Side::Bid => &self.bid,
+            Side::Ask => &self.ask,
+        };
+        ages.get(exchange)?.get(price).copied()
+    }
+}
+
+// one price of the ladder, all the venues quoting it together
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct ConsolidatedLevel {
+    pub price: String,
+    pub amount: String,
+    pub venues: usize,
+    // the largest amount at the price, the first by name on a tie
+    pub best_venue: String,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct Consolidated {
+    pub bids: Vec<ConsolidatedLevel>,
+    pub asks: Vec<ConsolidatedLevel>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct DerivativesSummary {
+    pub mark_price: String,
+    pub index_price: String,
+    pub funding_rate: String,
+    pub next_funding_time: u64,
+    // (perp mid - spot aggregate mid) / spot aggregate mid. None without both mids.
+    pub basis_bps: Option<f64>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct OpportunitySummary {
+    pub state: State,
+    pub buy_exchange: String,
+    pub sell_exchange: String,
+    // unix millis it was first seen
+    pub opened: u128,
+    pub profit_bps: f64,
+    pub quantity: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<f64>,
+    pub confidence: f64,
+    // net of fees and transfers, when a portfolio is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net: Option<NetProfit>,
+}
+
+// analytics derived from the published summaries, see crate::analytics
+#[derive(Debug, Serialize, PartialEq, Clone, Default)]
+pub struct Stats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volatility: Option<Volatility>,
+    // exchange that most often moves first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leader: Option<String>,
+    // volume traded over the short windows, from the trade channels
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub traded: Vec<TradedVolume>,
+    // only on the first summary after the warm-up gate opened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup: Option<Warmup>,
+    // receipt of the newest frame to serialization, when enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pipeline_ms: Option<f64>,
+    // millis since the rest ticker behind a backfilled last price / volume
+    // was polled, per exchange
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub ticker_age_ms: BTreeMap<String, u64>,
+    // average update interval and degraded flag, per exchange
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub update_rate: BTreeMap<String, UpdateRate>,
+    // (spot mid - index) / index in bps, per index source
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub mid_vs_index_bps: BTreeMap<String, f64>,
+    // exchanges whose venue announced maintenance, see MaintenancePolicy
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub maintenance: Vec<String>,
+}
+
+impl Stats {
+    pub fn is_empty(&self) -> bool {
+        self.volatility.is_none()
+            && self.leader.is_none()
+            && self.traded.is_empty()
+            && self.warmup.is_none()
+            && self.pipeline_ms.is_none()
+            && self.ticker_age_ms.is_empty()
+            && self.update_rate.is_empty()
+            && self.mid_vs_index_bps.is_empty()
+            && self.maintenance.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct UpdateRate {
+    // moving average of the gaps between book updates
+    pub interval_ms: f64,
+    // no update for degraded_factor times interval_ms
+    pub degraded: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct Warmup {
+    // false when the gate opened on the timeout, short of the quorum
+    pub warmup_complete: bool,
+    // configured exchanges without a book yet
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct TradedVolume {
+    pub window_secs: u64,
+    // exchange -> volume traded in the window
+    pub volume: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct Volatility {
+    pub windows: Vec<WindowVolatility>,
+    pub regime: Regime,
+}
+
+// realized volatility of the aggregate mid over one window
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct WindowVolatility {
+    pub window_secs: u64,
+    // square root of the summed squared log returns, in bps
+    pub bps: f64,
+    pub samples: usize,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Regime {
+    Quiet,
+    Normal,
+    Volatile,
+}
+
+/// What is published of an [`AggregatedOrderbook`], see `finalize`.
 #[derive(Debug, Serialize)]
 pub struct Summary {
+    // see schema, for the consumers to tell the layouts apart
+    pub schema_version: u32,
+    // position in the broadcast it is published on, 0 (left out) before
+    #[serde(skip_serializing_if = "is_unpublished")]
+    pub seq: u64,
     pub spread: String,
+    // the best bid of one exchange is over the best ask of another, the
+    // spread is negative. Left out when it isn't.
+    #[serde(skip_serializing_if = "is_uncrossed")]
+    pub crossed: bool,
     pub bids: Vec<Level>,
     pub asks: Vec<Level>,
-    pub timestamp: HashMap<String, String>,
-    pub volume: HashMap<String, String>,
-    pub last_price: HashMap<String, String>,
+    pub timestamp: BTreeMap<String, String>,
+    pub volume: BTreeMap<String, String>,
+    pub last_price: BTreeMap<String, String>,
+    // exchange -> the levels of its book per side, a book of fewer levels
+    // than the others is only its top
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub depth: BTreeMap<String, Depth>,
+    // source -> index price, with index_sources
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub index_prices: BTreeMap<String, String>,
+    // the best prices with the venues summed, when configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consolidated: Option<Consolidated>,
+    // per derivatives exchange, only present when one is configured
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub derivatives: BTreeMap<String, DerivativesSummary>,
+    // events of the tracked opportunities, see opportunity::tracker. Filled
+    // in after finalize.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub opportunities: Vec<OpportunitySummary>,
+    #[serde(skip_serializing_if = "Stats::is_empty")]
+    pub stats: Stats,
+}
+
+fn is_unpublished(seq: &u64) -> bool {
+    *seq == 0
+}
+
+fn is_uncrossed(crossed: &bool) -> bool {
+    !crossed
+}
+
+impl Default for AggregatedOrderbook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The exchanges quoting the same price are kept sorted by name, so the
+// rows of a price come out in the same order whatever the merge order.
+fn insert_level(levels: &mut Vec<(String, BigDecimal)>, name: &str, volume: &BigDecimal) {
+    let at = levels.partition_point(|(e, _)| e.as_str() < name);
+    levels.insert(at, (name.to_string(), volume.clone()));
 }
 
 impl AggregatedOrderbook {
-    // merge the content from one orderbook
+    /// Merge the content of one exchange's book.
     pub fn merge(&mut self, orderbook: &Orderbook) {
         let name = &orderbook.name;
-        let mut counter = 0;
+        // the book comes trimmed to the levels of its pair, all of them go in
         for (price, volume) in orderbook.bid.iter() {
-            counter += 1;
             self.bid
                 .entry(price.clone())
-                .and_modify(|e| e.push((name.clone(), volume.clone())))
+                .and_modify(|e| insert_level(e, name, volume))
                 .or_insert_with(|| vec![(name.clone(), volume.clone())]);
-
-            if counter == 10 {
-                break;
-            }
         }
-        let mut counter = 0;
         for (price, volume) in orderbook.ask.iter() {
-            counter += 1;
             self.ask
                 .entry(price.clone())
-                .and_modify(|e| e.push((name.clone(), volume.clone())))
+                .and_modify(|e| insert_level(e, name, volume))
                 .or_insert_with(|| vec![(name.clone(), volume.clone())]);
-            if counter == 10 {
-                break;
-            }
         }
         self.spread = 0.0;
-        self.timestamp.remove(name);
-        self.timestamp.insert(name.clone(), orderbook.timestamp);
-        self.volume.remove(name);
-        self.volume.insert(name.clone(), orderbook.volume.clone());
-        self.last_price.remove(name);
-        self.last_price
-            .insert(name.clone(), orderbook.last_price.clone());
+        self.timestamp.insert(name, orderbook.timestamp);
+        self.volume.insert(name, orderbook.volume.clone());
+        self.last_price.insert(name, orderbook.last_price.clone());
+        self.depth.insert(
+            name.clone(),
+            Depth {
+                bid: orderbook.bid.len(),
+                ask: orderbook.ask.len(),
+            },
+        );
+        match &orderbook.derivatives {
+            Some(d) => {
+                self.derivatives
+                    .insert(name.clone(), (d.clone(), orderbook.mid()));
+            }
+            None => {
+                if let Some((p, _)) = orderbook.bid.last_key_value() {
+                    if self.spot_bid.as_ref().is_none_or(|b| p > b) {
+                        self.spot_bid = Some(p.clone());
+                    }
+                }
+                if let Some((p, _)) = orderbook.ask.first_key_value() {
+                    if self.spot_ask.as_ref().is_none_or(|a| p < a) {
+                        self.spot_ask = Some(p.clone());
+                    }
+                }
+            }
+        }
     }
+    /// Merge `books` in place of the books merged so far. The scalars of
+    /// the exchanges still there are only written again when they change.
+    pub fn remerge<'a>(&mut self, books: impl IntoIterator<Item = &'a Orderbook>) {
+        self.spread = f64::NAN;
+        self.bid.clear();
+        self.ask.clear();
+        self.index_prices.clear();
+        self.derivatives.clear();
+        self.spot_bid = None;
+        self.spot_ask = None;
+        self.depth.clear();
+        let mut merged = HashSet::new();
+        for book in books {
+            self.merge(book);
+            merged.insert(book.name.clone());
+        }
+        self.timestamp.retain(|e| merged.contains(e));
+        self.volume.retain(|e| merged.contains(e));
+        self.last_price.retain(|e| merged.contains(e));
+    }
+    /// An empty aggregate, no consolidated section, prices as they are.
     pub fn new() -> AggregatedOrderbook {
         AggregatedOrderbook {
-            spread: std::f64::NAN,
+            spread: f64::NAN,
             bid: BTreeMap::new(),
             ask: BTreeMap::new(),
-            timestamp: HashMap::new(),
-            last_price: HashMap::new(),
-            volume: HashMap::new(),
+            timestamp: ScalarMap::new(|t, _| t.to_string()),
+            last_price: ScalarMap::new(|p, dp| dp.apply(p).to_string()),
+            volume: ScalarMap::new(|v, dp| dp.apply(v).to_string()),
+            index_prices: BTreeMap::new(),
+            derivatives: BTreeMap::new(),
+            spot_bid: None,
+            spot_ask: None,
+            consolidated_levels: None,
+            output_precision: None,
+            level_ages: None,
+            depth: BTreeMap::new(),
+            formatted: Formatted::default(),
         }
     }
-    // calculate the spread, output the stored price and volume data to Summary
+    fn dp(&self, of: fn(&OutputPrecision) -> i64) -> Dp {
+        Dp(self.output_precision.as_ref().map(|p| {
+            let mode = match p.mode {
+                Rounding::Round => RoundingMode::HalfUp,
+                Rounding::Truncate => RoundingMode::Down,
+            };
+            (of(p), mode)
+        }))
+    }
+    fn price_dp(&self) -> Dp {
+        self.dp(|p| p.price_dp)
+    }
+    fn amount_dp(&self) -> Dp {
+        self.dp(|p| p.amount_dp)
+    }
+    /// The top `consolidated_levels` prices of each side, fewer when the
+    /// side has fewer.
+    pub fn consolidated(&self) -> Option<Consolidated> {
+        let n = self.consolidated_levels?;
+        let (price_dp, amount_dp) = (self.price_dp(), self.amount_dp());
+        let level = |(price, v): (&BigDecimal, &Vec<(String, BigDecimal)>)| {
+            let mut best = &v[0];
+            for entry in v.iter().skip(1) {
+                if entry.1 > best.1 {
+                    best = entry;
+                }
+            }
+            let amount = v.iter().map(|(_, volume)| volume).sum::<BigDecimal>();
+            ConsolidatedLevel {
+                price: price_dp.apply(price).to_string(),
+                amount: amount_dp.apply(&amount).to_string(),
+                venues: v.len(),
+                best_venue: best.0.clone(),
+            }
+        };
+        Some(Consolidated {
+            bids: self.bid.iter().rev().take(n).map(level).collect(),
+            asks: self.ask.iter().take(n).map(level).collect(),
+        })
+    }
+    // best ask - best bid, None while a side is empty
+    fn spread_value(&self) -> Option<BigDecimal> {
+        let best_bid = self.bid.last_key_value()?.0;
+        let best_ask = self.ask.first_key_value()?.0;
+        Some(best_ask - best_bid)
+    }
+    fn age_ms(&self, side: Side, exchange: &str, price: &BigDecimal) -> Option<u64> {
+        self.level_ages.as_ref()?.get(side, exchange, price)
+    }
+    pub(crate) fn spot_mid(&self) -> Option<BigDecimal> {
+        match (&self.spot_bid, &self.spot_ask) {
+            (Some(b), Some(a)) => Some((b + a) / BigDecimal::from(2)),
+            _ => None,
+        }
+    }
+    /// Calculate the spread, output the stored price and volume data to a
+    /// [`Summary`].
     pub fn finalize(&mut self) -> Result<Summary> {
-        let mut cursor = self.bid.upper_bound(Bound::Unbounded);
-        let last_price = self
-            .last_price
+        let (price_dp, amount_dp) = (self.price_dp(), self.amount_dp());
+        // the timestamps are written without decimals, whatever the precision
+        self.timestamp.write(Dp(None));
+        self.volume.write(amount_dp);
+        self.last_price.write(price_dp);
+        let index_prices = self
+            .index_prices
             .iter()
-            .map(|(e, t)| (e.clone(), t.to_string()))
-            .collect();
-        let volume = self
-            .volume
-            .iter()
-            .map(|(e, t)| (e.clone(), t.to_string()))
-            .collect();
-        let timestamp = self
-            .timestamp
-            .iter()
-            .map(|(e, t)| (e.clone(), t.to_string()))
+            .map(|(s, p)| (s.clone(), price_dp.apply(p).to_string()))
             .collect();
         let mut bids = vec![];
-        while let Some((price, v)) = cursor.key_value() {
+        for (price, v) in self.bid.iter().rev() {
             for (exchange, volume) in v.iter() {
                 bids.push(Level {
                     exchange: exchange.clone(),
-                    price: price.to_string(),
-                    amount: volume.to_string(),
+                    price: price_dp.apply(price).to_string(),
+                    amount: amount_dp.apply(volume).to_string(),
+                    age_ms: self.age_ms(Side::Bid, exchange, price),
                 });
             }
-            // notice move_prev is to move to the previous element in tree,
-            // not the order of upper bound or lower bound.
-            if cursor.peek_prev().is_some() {
-                cursor.move_prev();
-            } else {
-                break;
-            }
         }
-        let mut cursor = self.ask.lower_bound(Bound::Unbounded);
         let mut asks = vec![];
-        while let Some((price, v)) = cursor.key_value() {
+        for (price, v) in self.ask.iter() {
             for (exchange, volume) in v.iter() {
                 asks.push(Level {
                     exchange: exchange.clone(),
-                    price: price.to_string(),
-                    amount: volume.to_string(),
+                    price: price_dp.apply(price).to_string(),
+                    amount: amount_dp.apply(volume).to_string(),
+                    age_ms: self.age_ms(Side::Ask, exchange, price),
                 });
             }
-            if cursor.peek_next().is_some() {
-                cursor.move_next();
-            } else {
-                break;
-            }
         }
-        let best_bid = self.bid.last_key_value().map(|(p, _)| p);
-        let best_ask = self.ask.first_key_value().map(|(p, _)| p);
-        let spread = match (best_bid, best_ask) {
-            (Some(v), Some(w)) => (w - v).to_string(),
-            _ => "0".to_string(),
+        let spread_value = self.spread_value();
+        let crossed = spread_value.as_ref().is_some_and(|s| s.is_negative());
+        let spread = match spread_value {
+            Some(spread) => price_dp.apply(&spread).to_string(),
+            None => "0".to_string(),
         };
-        Ok(Summary {
+        let spot_mid = self.spot_mid();
+        let derivatives = self
+            .derivatives
+            .iter()
+            .map(|(e, (d, mid))| {
+                let summary = DerivativesSummary {
+                    mark_price: price_dp.apply(&d.mark_price).to_string(),
+                    index_price: price_dp.apply(&d.index_price).to_string(),
+                    funding_rate: d.funding_rate.to_string(),
+                    next_funding_time: d.next_funding_time,
+                    basis_bps: basis_bps(mid, &spot_mid),
+                };
+                (e.clone(), summary)
+            })
+            .collect();
+        self.formatted = Formatted {
             spread,
+            crossed,
             bids,
             asks,
-            timestamp,
-            last_price,
-            volume,
+            index_prices,
+            consolidated: self.consolidated(),
+            derivatives,
+        };
+        let f = &self.formatted;
+        Ok(Summary {
+            schema_version: SCHEMA_VERSION,
+            seq: 0,
+            spread: f.spread.clone(),
+            crossed: f.crossed,
+            bids: f.bids.clone(),
+            asks: f.asks.clone(),
+            timestamp: self.timestamp.text.clone(),
+            last_price: self.last_price.text.clone(),
+            volume: self.volume.text.clone(),
+            depth: self.depth.clone(),
+            index_prices: f.index_prices.clone(),
+            consolidated: f.consolidated.clone(),
+            derivatives: f.derivatives.clone(),
+            opportunities: vec![],
+            stats: Stats::default(),
         })
     }
 }
 
+// The numbers of an aggregate as published, written by `finalize`.
+// BigDecimal's Display allocates for every number, so they are formatted
+// once there and the view writes them as they are.
+#[derive(Debug, Default)]
+struct Formatted {
+    spread: String,
+    crossed: bool,
+    bids: Vec<Level>,
+    asks: Vec<Level>,
+    index_prices: BTreeMap<String, String>,
+    consolidated: Option<Consolidated>,
+    derivatives: BTreeMap<String, DerivativesSummary>,
+}
+
+// Serializes like the Summary of `book` as of its last `finalize`, with the
+// given opportunities and stats: the numbers formatted by finalize and the
+// json of the scalar maps go out as they are, so the only allocation is the
+// output buffer. The owned Summary stays for the consumers that keep the data.
+pub struct SummaryView<'a> {
+    pub book: &'a AggregatedOrderbook,
+    // see Summary
+    pub seq: u64,
+    pub opportunities: &'a [OpportunitySummary],
+    pub stats: &'a Stats,
+}
+
+impl Serialize for SummaryView<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let book = self.book;
+        let f = &book.formatted;
+        let mut summary = serializer.serialize_struct("Summary", 15)?;
+        summary.serialize_field("schema_version", &SCHEMA_VERSION)?;
+        if is_unpublished(&self.seq) {
+            summary.skip_field("seq")?;
+        } else {
+            summary.serialize_field("seq", &self.seq)?;
+        }
+        summary.serialize_field("spread", &f.spread)?;
+        if is_uncrossed(&f.crossed) {
+            summary.skip_field("crossed")?;
+        } else {
+            summary.serialize_field("crossed", &f.crossed)?;
+        }
+        summary.serialize_field("bids", &f.bids)?;
+        summary.serialize_field("asks", &f.asks)?;
+        summary.serialize_field("timestamp", &ScalarView(&book.timestamp, Dp(None)))?;
+        summary.serialize_field("volume", &ScalarView(&book.volume, book.amount_dp()))?;
+        summary.serialize_field("last_price", &ScalarView(&book.last_price, book.price_dp()))?;
+        if book.depth.is_empty() {
+            summary.skip_field("depth")?;
+        } else {
+            summary.serialize_field("depth", &book.depth)?;
+        }
+        if f.index_prices.is_empty() {
+            summary.skip_field("index_prices")?;
+        } else {
+            summary.serialize_field("index_prices", &f.index_prices)?;
+        }
+        match &f.consolidated {
+            Some(consolidated) => summary.serialize_field("consolidated", consolidated)?,
+            None => summary.skip_field("consolidated")?,
+        }
+        if f.derivatives.is_empty() {
+            summary.skip_field("derivatives")?;
+        } else {
+            summary.serialize_field("derivatives", &f.derivatives)?;
+        }
+        if self.opportunities.is_empty() {
+            summary.skip_field("opportunities")?;
+        } else {
+            summary.serialize_field("opportunities", self.opportunities)?;
+        }
+        if self.stats.is_empty() {
+            summary.skip_field("stats")?;
+        } else {
+            summary.serialize_field("stats", self.stats)?;
+        }
+        summary.end()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -250,6 +1063,128 @@ mod tests {
         assert_eq!(ob.ask.first_key_value(), Some((&one, &default_quantity)));
     }
     #[test]
+    fn test_orderbook_top() {
+        let mut ob = Orderbook::new("A");
+        for p in ["1", "2", "3"] {
+            let price = BigDecimal::from_str(p).unwrap();
+            ob.insert(Side::Bid, price.clone(), BigDecimal::from(1));
+            ob.insert(Side::Ask, price + BigDecimal::from(3), BigDecimal::from(1));
+        }
+        // same levels as trim, without touching the book
+        let top = ob.top(2, Sides::Both);
+        let mut trimmed = ob.clone();
+        trimmed.trim(2);
+        assert_eq!(top, trimmed);
+        assert_eq!(ob.bid.len(), 3);
+        assert_eq!(ob.top(5, Sides::Both), ob);
+    }
+    #[test]
+    fn test_inverted() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        // BTC/AUD
+        let mut ob = Orderbook::new("A");
+        ob.insert(Side::Bid, dec("40000"), dec("0.5"));
+        ob.insert(Side::Bid, dec("39000"), dec("2"));
+        ob.insert(Side::Ask, dec("40100"), dec("1.5"));
+        ob.last_price = dec("40050");
+        ob.volume = dec("10");
+        let inverted = ob.inverted(8);
+        let levels = |levels: &BTreeMap<BigDecimal, BigDecimal>| -> Vec<(String, String)> {
+            levels
+                .iter()
+                .map(|(p, a)| (p.normalized().to_string(), a.normalized().to_string()))
+                .collect()
+        };
+        // AUD/BTC: the ask is the only bid, 1 / 40100 to 8 digits, 1.5 * 40100 AUD
+        assert_eq!(
+            levels(&inverted.bid),
+            [("0.000024937656".to_string(), "60150".to_string())]
+        );
+        assert_eq!(
+            levels(&inverted.ask),
+            [
+                ("0.000025".to_string(), "20000".to_string()),
+                ("0.000025641026".to_string(), "78000".to_string()),
+            ]
+        );
+        assert_eq!(
+            inverted.last_price.normalized().to_string(),
+            "0.000024968789"
+        );
+        assert_eq!(inverted.volume, dec("400500"));
+        assert!(!inverted.crossed());
+
+        // a native AUD/BTC book lines up with it in the aggregate
+        let mut native = Orderbook::new("B");
+        native.insert(Side::Bid, dec("0.000024937656"), dec("1000"));
+        native.insert(Side::Ask, dec("0.000025"), dec("4000"));
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&native);
+        agg.merge(&inverted);
+        let summary = agg.finalize().unwrap();
+        let top = |levels: &[Level]| -> Vec<(String, String)> {
+            levels
+                .iter()
+                .take(2)
+                .map(|l| (l.exchange.clone(), l.amount.clone()))
+                .collect()
+        };
+        assert_eq!(summary.bids[0].price, summary.bids[1].price);
+        assert_eq!(
+            top(&summary.bids),
+            [
+                // the product keeps the scale of the amount
+                ("A".to_string(), "60150.0".to_string()),
+                ("B".to_string(), "1000".to_string())
+            ]
+        );
+        assert_eq!(summary.asks[0].price, "0.000025");
+        assert_eq!(
+            top(&summary.asks),
+            [
+                ("A".to_string(), "20000.0".to_string()),
+                ("B".to_string(), "4000".to_string())
+            ]
+        );
+    }
+    #[test]
+    fn test_one_side() {
+        let book = |name: &str, bid: &str, ask: &str| {
+            let mut ob = Orderbook::new(name);
+            ob.insert(
+                Side::Bid,
+                BigDecimal::from_str(bid).unwrap(),
+                BigDecimal::from(1),
+            );
+            ob.insert(
+                Side::Ask,
+                BigDecimal::from_str(ask).unwrap(),
+                BigDecimal::from(1),
+            );
+            ob.last_price = BigDecimal::from(100);
+            ob.volume = BigDecimal::from(7);
+            ob
+        };
+        let seller = book("seller", "101", "102");
+        let mut rest = book("rest", "99", "103");
+        rest.keep(Sides::AskOnly);
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&seller.top(10, Sides::BidOnly));
+        agg.merge(&rest);
+        let summary = agg.finalize().unwrap();
+        let on =
+            |levels: &[Level], name: &str| levels.iter().filter(|l| l.exchange == name).count();
+        assert_eq!(on(&summary.bids, "seller"), 1);
+        assert_eq!(on(&summary.asks, "seller"), 0);
+        assert_eq!(on(&summary.bids, "rest"), 0);
+        assert_eq!(on(&summary.asks, "rest"), 1);
+        // the scalars are kept
+        for name in ["seller", "rest"] {
+            assert_eq!(summary.last_price[name], "100");
+            assert_eq!(summary.volume[name], "7");
+        }
+    }
+    #[test]
     fn test_agg_merge() {
         let default_quantity: BigDecimal = BigDecimal::from_str("10").unwrap();
         let mut ob1 = Orderbook::new("A");
@@ -286,24 +1221,376 @@ mod tests {
                     exchange: "A".to_string(),
                     price: 1_f64.to_string(),
                     amount: 10_f64.to_string(),
+                    age_ms: None,
                 },
                 Level {
                     exchange: "B".to_string(),
                     price: 1_f64.to_string(),
                     amount: 10_f64.to_string(),
+                    age_ms: None,
                 },
                 Level {
                     exchange: "A".to_string(),
                     price: 2_f64.to_string(),
-                    amount: 10_f64.to_string()
+                    amount: 10_f64.to_string(),
+                    age_ms: None,
                 },
                 Level {
                     exchange: "B".to_string(),
                     price: 3_f64.to_string(),
                     amount: 10_f64.to_string(),
+                    age_ms: None,
                 },
             ]
         );
         assert_eq!(summary.bids.len(), 0);
     }
+    #[test]
+    fn test_basis() {
+        let one = BigDecimal::from(1);
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let mut spot1 = Orderbook::new("A");
+        spot1.insert(Side::Bid, dec("99"), one.clone());
+        spot1.insert(Side::Ask, dec("101.5"), one.clone());
+        let mut spot2 = Orderbook::new("B");
+        spot2.insert(Side::Bid, dec("98"), one.clone());
+        spot2.insert(Side::Ask, dec("101"), one.clone());
+        let mut perp = Orderbook::new("binance_futures");
+        perp.insert(Side::Bid, dec("100.4"), one.clone());
+        perp.insert(Side::Ask, dec("100.6"), one.clone());
+        perp.derivatives = Some(Derivatives {
+            mark_price: dec("100.5"),
+            index_price: dec("100.1"),
+            funding_rate: dec("0.0001"),
+            next_funding_time: 1696406400000,
+        });
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&spot1);
+        agg.merge(&perp);
+        agg.merge(&spot2);
+        let summary = agg.finalize().unwrap();
+        // spot mid (99 + 101) / 2 = 100, perp mid 100.5
+        assert_eq!(
+            summary.derivatives["binance_futures"],
+            DerivativesSummary {
+                mark_price: "100.5".to_string(),
+                index_price: "100.1".to_string(),
+                funding_rate: "0.0001".to_string(),
+                next_funding_time: 1696406400000,
+                basis_bps: Some(50.),
+            }
+        );
+        // perp levels are still part of the ladder
+        assert_eq!(summary.bids[0].exchange, "binance_futures");
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["derivatives"]["binance_futures"]["basis_bps"], 50.);
+
+        // without a spot book there is no basis
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&perp);
+        let summary = agg.finalize().unwrap();
+        assert_eq!(summary.derivatives["binance_futures"].basis_bps, None);
+        // and no derivatives section without derivatives books
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&spot1);
+        let json = serde_json::to_value(agg.finalize().unwrap()).unwrap();
+        assert!(json.get("derivatives").is_none());
+    }
+    #[test]
+    fn test_merge_order() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let book = |name: &str, ts, levels: &[(Side, &str, &str)], volume, last| {
+            let mut ob = Orderbook::new(name);
+            for (side, price, amount) in levels {
+                ob.insert(*side, dec(price), dec(amount));
+            }
+            ob.timestamp = ts;
+            ob.volume = dec(volume);
+            ob.last_price = dec(last);
+            ob
+        };
+        let books = [
+            book(
+                "kraken",
+                1700000000001,
+                &[(Side::Bid, "100", "1"), (Side::Ask, "101", "2")],
+                "10",
+                "100.5",
+            ),
+            book(
+                "binance",
+                1700000000002,
+                &[
+                    (Side::Bid, "100", "3"),
+                    (Side::Bid, "99", "1"),
+                    (Side::Ask, "101", "1"),
+                ],
+                "20",
+                "100.4",
+            ),
+            book(
+                "bitstamp",
+                1700000000003,
+                &[
+                    (Side::Bid, "100", "0.5"),
+                    (Side::Ask, "101", "0.25"),
+                    (Side::Ask, "102", "1"),
+                ],
+                "5",
+                "100.6",
+            ),
+        ];
+        let golden = include_str!("test_resource/summary_ties.json").trim_end();
+        for order in [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ] {
+            let mut agg = AggregatedOrderbook::new();
+            for i in order {
+                agg.merge(&books[i]);
+            }
+            let summary = agg.finalize().unwrap();
+            let view = SummaryView {
+                book: &agg,
+                seq: 0,
+                opportunities: &summary.opportunities,
+                stats: &summary.stats,
+            };
+            assert_eq!(
+                serde_json::to_string(&summary).unwrap(),
+                golden,
+                "{:?}",
+                order
+            );
+            assert_eq!(serde_json::to_string(&view).unwrap(), golden, "{:?}", order);
+        }
+    }
+    #[test]
+    fn test_consolidated() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let mut a = Orderbook::new("A");
+        a.insert(Side::Bid, dec("100"), dec("1"));
+        a.insert(Side::Bid, dec("99"), dec("2"));
+        a.insert(Side::Ask, dec("101"), dec("0.5"));
+        a.insert(Side::Ask, dec("102"), dec("1"));
+        let mut b = Orderbook::new("B");
+        b.insert(Side::Bid, dec("100"), dec("3"));
+        b.insert(Side::Bid, dec("98"), dec("1"));
+        b.insert(Side::Bid, dec("97"), dec("1"));
+        b.insert(Side::Ask, dec("101"), dec("0.5"));
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&a);
+        agg.merge(&b);
+        assert_eq!(agg.finalize().unwrap().consolidated, None);
+
+        agg.consolidated_levels = Some(3);
+        let summary = agg.finalize().unwrap();
+        let level = |price: &str, amount: &str, venues, best_venue: &str| ConsolidatedLevel {
+            price: price.to_string(),
+            amount: amount.to_string(),
+            venues,
+            best_venue: best_venue.to_string(),
+        };
+        assert_eq!(
+            summary.consolidated,
+            Some(Consolidated {
+                bids: vec![
+                    level("100", "4", 2, "B"),
+                    level("99", "2", 1, "A"),
+                    level("98", "1", 1, "B"),
+                ],
+                // two prices only, a tie goes to the first by name
+                asks: vec![level("101", "1.0", 2, "A"), level("102", "1", 1, "A")],
+            })
+        );
+        // the bids and asks arrays are unchanged
+        assert_eq!(summary.bids.len(), 5);
+        let view = SummaryView {
+            book: &agg,
+            seq: 0,
+            opportunities: &summary.opportunities,
+            stats: &summary.stats,
+        };
+        assert_eq!(
+            serde_json::to_string(&view).unwrap(),
+            serde_json::to_string(&summary).unwrap()
+        );
+    }
+    #[test]
+    fn test_output_precision() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        // an amount as the f64 parsers produce it
+        let noisy = format!("{}", 0.1_f64 + 0.2);
+        assert_eq!(noisy, "0.30000000000000004");
+        let mut ob = Orderbook::new("coinspot");
+        ob.insert(Side::Bid, dec("99.123456789"), dec(&noisy));
+        ob.insert(Side::Ask, dec("100.5"), dec("2"));
+        ob.volume = dec("1234.567890125");
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&ob);
+        agg.output_precision = Some(serde_yaml::from_str("{}").unwrap());
+        let summary = agg.finalize().unwrap();
+        let view = SummaryView {
+            book: &agg,
+            seq: 0,
+            opportunities: &summary.opportunities,
+            stats: &summary.stats,
+        };
+        let json = serde_json::to_string(&view).unwrap();
+        assert_eq!(json, serde_json::to_string(&summary).unwrap());
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["bids"][0]["amount"], "0.3");
+        assert_eq!(json["bids"][0]["price"], "99.12345679");
+        assert_eq!(json["asks"][0]["amount"], "2");
+        assert_eq!(json["spread"], "1.37654321");
+        assert_eq!(json["volume"]["coinspot"], "1234.56789013");
+        // the aggregate keeps every digit
+        assert_eq!(agg.bid[&dec("99.123456789")][0].1, dec(&noisy));
+
+        agg.output_precision = Some(OutputPrecision {
+            price_dp: 2,
+            amount_dp: 0,
+            mode: Rounding::Truncate,
+        });
+        let summary = agg.finalize().unwrap();
+        assert_eq!(summary.bids[0].price, "99.12");
+        assert_eq!(summary.bids[0].amount, "0");
+        assert_eq!(summary.volume["coinspot"], "1234");
+    }
+    #[test]
+    fn test_summary_view() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let mut ob = Orderbook::new("A");
+        ob.insert(Side::Bid, dec("99.50"), dec("1.25"));
+        ob.insert(Side::Bid, dec("99"), dec("0.001"));
+        ob.insert(Side::Ask, dec("100.0"), dec("2"));
+        ob.timestamp = 1696405428703;
+        ob.volume = dec("1234.5");
+        ob.last_price = dec("99.75");
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&ob);
+        let summary = agg.finalize().unwrap();
+        let view = SummaryView {
+            book: &agg,
+            seq: 0,
+            opportunities: &summary.opportunities,
+            stats: &summary.stats,
+        };
+        // byte for byte: the maps of the summary are ordered by exchange
+        let golden = include_str!("test_resource/summary.json").trim_end();
+        assert_eq!(serde_json::to_string(&summary).unwrap(), golden);
+        assert_eq!(serde_json::to_string(&view).unwrap(), golden);
+
+        // several exchanges, derivatives, opportunities and stats
+        let mut perp = Orderbook::new("binance_futures");
+        perp.insert(Side::Bid, dec("100.4"), dec("1"));
+        perp.insert(Side::Ask, dec("100.6"), dec("1"));
+        perp.derivatives = Some(Derivatives {
+            mark_price: dec("100.5"),
+            index_price: dec("100.1"),
+            funding_rate: dec("0.0001"),
+            next_funding_time: 1696406400000,
+        });
+        let mut other = Orderbook::new("B");
+        other.insert(Side::Ask, dec("99.5"), dec("3"));
+        agg.merge(&perp);
+        agg.merge(&other);
+        let mut summary = agg.finalize().unwrap();
+        summary.opportunities = vec![OpportunitySummary {
+            state: State::New,
+            buy_exchange: "B".to_string(),
+            sell_exchange: "binance_futures".to_string(),
+            opened: 1,
+            profit_bps: 90.,
+            quantity: "1".to_string(),
+            max_size: None,
+            confidence: 1.,
+            net: None,
+        }];
+        summary.stats.leader = Some("A".to_string());
+        let view = SummaryView {
+            book: &agg,
+            seq: 0,
+            opportunities: &summary.opportunities,
+            stats: &summary.stats,
+        };
+        let json = serde_json::to_string(&view).unwrap();
+        assert_eq!(json, serde_json::to_string(&summary).unwrap());
+        // the books stamped at merge time come after A, in exchange order
+        assert!(
+            json.contains(r#""timestamp":{"A":"1696405428703","B":""#),
+            "{}",
+            json
+        );
+    }
+    #[test]
+    fn test_remerge() {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let book = |name: &str, bid: &str, volume: &str| {
+            let mut ob = Orderbook::new(name);
+            ob.insert(Side::Bid, dec(bid), dec("1"));
+            ob.insert(Side::Ask, dec("101"), dec("2"));
+            ob.timestamp = 1696405428703;
+            ob.volume = dec(volume);
+            ob.last_price = dec("100.25");
+            ob
+        };
+        let json = |agg: &AggregatedOrderbook, summary: &Summary| {
+            let view = SummaryView {
+                book: agg,
+                seq: 0,
+                opportunities: &[],
+                stats: &summary.stats,
+            };
+            let json = serde_json::to_string(&view).unwrap();
+            assert_eq!(json, serde_json::to_string(summary).unwrap());
+            json
+        };
+        // the same as an aggregate built afresh, tick after tick
+        let mut kept = AggregatedOrderbook::new();
+        let ticks = [
+            vec![book("A", "99", "10"), book("B", "98", "20")],
+            // only the ladder of A changes
+            vec![book("A", "99.5", "10"), book("B", "98", "20")],
+            vec![book("A", "99.5", "11"), book("B", "98", "20")],
+            // B leaves
+            vec![book("A", "99.5", "11")],
+        ];
+        let mut versions = vec![];
+        for books in ticks.iter() {
+            kept.remerge(books);
+            let summary = kept.finalize().unwrap();
+            let mut fresh = AggregatedOrderbook::new();
+            for book in books {
+                fresh.merge(book);
+            }
+            let expected = fresh.finalize().unwrap();
+            assert_eq!(json(&kept, &summary), json(&fresh, &expected));
+            versions.push(kept.volume.version());
+        }
+        assert_eq!(versions, [1, 1, 2, 3]);
+        assert_eq!(kept.last_price.version(), 2);
+        assert!(!kept.timestamp.contains_key("B"));
+
+        // set back and forth within a tick, the text stays
+        kept.volume.insert("A", dec("0"));
+        assert!(kept.volume.fill_zero("A", &dec("11")));
+        kept.finalize().unwrap();
+        assert_eq!(kept.volume.version(), 3);
+
+        // new decimals write every value again
+        kept.output_precision = Some(OutputPrecision {
+            price_dp: 1,
+            amount_dp: 0,
+            mode: Rounding::Truncate,
+        });
+        let summary = kept.finalize().unwrap();
+        assert_eq!(summary.last_price["A"], "100.2");
+        assert_eq!(summary.timestamp["A"], "1696405428703");
+        assert!(json(&kept, &summary).contains(r#""last_price":{"A":"100.2"}"#));
+    }
 }
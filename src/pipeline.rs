@@ -0,0 +1,1027 @@
+use crate::analytics;
+use crate::config::{
+    DetectorSetting, ExchangeSetting, GroupSetting, GuardrailSetting, OutputPrecision,
+    SyntheticSpec, VolatilitySetting, WarmupSetting,
+};
+use crate::exchange::{self, Exchange};
+use crate::guardrails;
+use crate::opportunity;
+use crate::orderbook::{
+    get_unixtime, AggregatedOrderbook, Orderbook, Summary, SummaryView, Warmup,
+};
+use crate::sink::{self, statsd};
+use crate::snapshots::{self, Snapshot, Topic};
+use crate::timings::{self, Stage};
+use crate::{apitree, audit, soak, status};
+use anyhow::{anyhow, Result};
+use bigdecimal::BigDecimal;
+use bytestring::ByteString;
+use log::{debug, error, info};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::sync::{oneshot, Notify};
+use tokio::time::{sleep, Duration, Instant};
+
+// a book from an exchange client, with the receipt of its frame
+pub type Frame = (String, Arc<Orderbook>, Instant);
+
+// The queue between the exchange clients and ingest. Bounded: beyond the
+// capacity the oldest frame is dropped, so a venue flooding us costs
+// freshness, not memory. Closed once every sender is gone.
+struct Queue {
+    frames: Mutex<VecDeque<Frame>>,
+    capacity: usize,
+    ready: Notify,
+    senders: AtomicUsize,
+    receiving: AtomicBool,
+    dropped: AtomicU64,
+}
+
+pub struct IngestSender(Arc<Queue>);
+
+pub struct IngestReceiver(Arc<Queue>);
+
+// depth and drops of the queue, for the self report. Keeps it open for
+// neither side.
+#[derive(Clone)]
+pub struct IngestMonitor(Arc<Queue>);
+
+pub fn ingest_channel(capacity: usize) -> (IngestSender, IngestReceiver) {
+    let queue = Arc::new(Queue {
+        frames: Mutex::new(VecDeque::new()),
+        capacity,
+        ready: Notify::new(),
+        senders: AtomicUsize::new(1),
+        receiving: AtomicBool::new(true),
+        dropped: AtomicU64::new(0),
+    });
+    (IngestSender(queue.clone()), IngestReceiver(queue))
+}
+
+impl IngestSender {
+    // Err once ingest is gone
+    pub fn send(&self, frame: Frame) -> Result<()> {
+        if !self.0.receiving.load(Ordering::Acquire) {
+            return Err(anyhow!("ingest is gone"));
+        }
+        let overflow = {
+            let mut frames = self.0.frames.lock().unwrap();
+            frames.push_back(frame);
+            let overflow = frames.len() > self.0.capacity;
+            if overflow {
+                frames.pop_front();
+            }
+            overflow
+        };
+        if overflow {
+            self.0.dropped.fetch_add(1, Ordering::Relaxed);
+            statsd::count_sampled("ingest.dropped", &[]);
+        }
+        self.0.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl Clone for IngestSender {
+    fn clone(&self) -> Self {
+        self.0.senders.fetch_add(1, Ordering::Relaxed);
+        IngestSender(self.0.clone())
+    }
+}
+
+impl Drop for IngestSender {
+    fn drop(&mut self) {
+        if self.0.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.ready.notify_one();
+        }
+    }
+}
+
+impl IngestReceiver {
+    // None once the queue is drained and every sender is gone
+    pub async fn recv(&mut self) -> Option<Frame> {
+        loop {
+            if let Some(frame) = self.try_recv() {
+                return Some(frame);
+            }
+            if self.0.senders.load(Ordering::Acquire) == 0 {
+                // a frame sent right before the last sender went
+                return self.try_recv();
+            }
+            self.0.ready.notified().await;
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<Frame> {
+        self.0.frames.lock().unwrap().pop_front()
+    }
+
+    pub fn monitor(&self) -> IngestMonitor {
+        IngestMonitor(self.0.clone())
+    }
+}
+
+impl Drop for IngestReceiver {
+    fn drop(&mut self) {
+        self.0.receiving.store(false, Ordering::Release);
+    }
+}
+
+impl IngestMonitor {
+    pub fn depth(&self) -> usize {
+        self.0.frames.lock().unwrap().len()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.0.dropped.load(Ordering::Relaxed)
+    }
+}
+
+// Latest book of every exchange, shared by the two stages of the marketdata
+// pipeline: ingest only swaps a book in and flags the cache dirty, the
+// publisher takes a snapshot whenever it is free. Updates arriving while
+// the publisher is busy are coalesced into its next run.
+#[derive(Clone, Default)]
+pub struct BookCache {
+    books: Arc<Mutex<HashMap<String, Arc<Orderbook>>>>,
+    // receipt of the frame behind the newest book
+    received: Arc<Mutex<Option<Instant>>>,
+    dirty: Arc<Notify>,
+    // bumped by every update, so a wake-up for a change the publisher
+    // already saw is skipped
+    version: Arc<AtomicU64>,
+    closed: Arc<AtomicBool>,
+}
+
+impl BookCache {
+    pub fn update(&self, exchange: String, orderbook: Arc<Orderbook>, received: Instant) {
+        {
+            let mut books = self.books.lock().unwrap();
+            books.insert(exchange, orderbook);
+            self.version.fetch_add(1, Ordering::Relaxed);
+        }
+        *self.received.lock().unwrap() = Some(received);
+        // stores at most one permit, so a burst wakes the publisher once
+        self.dirty.notify_one();
+    }
+
+    // The books are shared, only the map is copied. With the version it
+    // is at.
+    pub fn snapshot(&self) -> (HashMap<String, Arc<Orderbook>>, u64) {
+        let books = self.books.lock().unwrap();
+        (books.clone(), self.version.load(Ordering::Relaxed))
+    }
+
+    // the latest book of one exchange
+    pub fn book(&self, exchange: &str) -> Option<Arc<Orderbook>> {
+        self.books.lock().unwrap().get(exchange).cloned()
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.dirty.notify_one();
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct BboTick {
+    op: &'static str,
+    pub exchange: String,
+    pub pair: String,
+    // None while the side is empty
+    pub bid: Option<String>,
+    pub bid_size: Option<String>,
+    pub ask: Option<String>,
+    pub ask_size: Option<String>,
+    pub ts: u128,
+    pub seq: u64,
+}
+
+type Top = [Option<(BigDecimal, BigDecimal)>; 2];
+
+// Top of book ticks, sent from ingest apart from the Summaries. A book
+// whose best bid and best ask (price and amount) are those of the previous
+// book of its exchange makes no tick.
+pub struct BboTicker {
+    tx: broadcast::Sender<ByteString>,
+    // exchange -> the pair of its book
+    pairs: Arc<HashMap<String, String>>,
+    last: HashMap<String, Top>,
+    seq: u64,
+}
+
+impl BboTicker {
+    pub fn new(tx: broadcast::Sender<ByteString>, pairs: Arc<HashMap<String, String>>) -> Self {
+        BboTicker {
+            tx,
+            pairs,
+            last: HashMap::new(),
+            seq: 0,
+        }
+    }
+
+    fn tick(&mut self, exchange: &str, orderbook: &Orderbook) -> Option<BboTick> {
+        let owned =
+            |level: Option<(&BigDecimal, &BigDecimal)>| level.map(|(p, v)| (p.clone(), v.clone()));
+        let top = [owned(orderbook.best_bid()), owned(orderbook.best_ask())];
+        if self.last.get(exchange) == Some(&top) {
+            return None;
+        }
+        self.seq += 1;
+        let price =
+            |side: &Option<(BigDecimal, BigDecimal)>| side.as_ref().map(|l| l.0.to_string());
+        let size = |side: &Option<(BigDecimal, BigDecimal)>| side.as_ref().map(|l| l.1.to_string());
+        let tick = BboTick {
+            op: "bbo",
+            exchange: exchange.to_string(),
+            pair: self.pairs.get(exchange).cloned().unwrap_or_default(),
+            bid: price(&top[0]),
+            bid_size: size(&top[0]),
+            ask: price(&top[1]),
+            ask_size: size(&top[1]),
+            ts: orderbook.timestamp,
+            seq: self.seq,
+        };
+        self.last.insert(exchange.to_string(), top);
+        Some(tick)
+    }
+
+    fn observe(&mut self, exchange: &str, orderbook: &Orderbook) {
+        let Some(tick) = self.tick(exchange, orderbook) else {
+            return;
+        };
+        match serde_json::to_string(&tick) {
+            // no subscriber is fine, the tick is just not wanted
+            Ok(tick) => {
+                let _ = self.tx.send(tick.into());
+            }
+            Err(e) => error!("bbo tick: {}", e),
+        }
+    }
+}
+
+// Fans the books of the shared connections out to the caches of the pair
+// groups that aggregate them.
+#[derive(Default)]
+pub struct GroupRouter {
+    // exchange -> the pair of its book, for the books that don't tell
+    pairs: Arc<HashMap<String, String>>,
+    // (cache, exchange -> pair) of every group
+    groups: Vec<(BookCache, HashMap<String, String>)>,
+}
+
+impl GroupRouter {
+    pub fn new(pairs: Arc<HashMap<String, String>>) -> Self {
+        GroupRouter {
+            pairs,
+            groups: vec![],
+        }
+    }
+
+    // the cache a group of `exchanges` is fed into
+    pub fn add(&mut self, exchanges: HashMap<String, String>) -> BookCache {
+        let cache = BookCache::default();
+        self.groups.push((cache.clone(), exchanges));
+        cache
+    }
+
+    fn route(&self, exchange: &str, orderbook: &Arc<Orderbook>, received: Instant) {
+        let Some(pair) = orderbook.pair.as_ref().or(self.pairs.get(exchange)) else {
+            return;
+        };
+        for (cache, exchanges) in self.groups.iter() {
+            if exchanges.get(exchange) == Some(pair) {
+                cache.update(exchange.to_string(), orderbook.clone(), received);
+            }
+        }
+    }
+
+    // A book of the exchange's main pair, the one the main aggregate takes.
+    // The other pairs on its connection are there for the groups only.
+    fn is_main(&self, exchange: &str, orderbook: &Orderbook) -> bool {
+        match (orderbook.pair.as_ref(), self.pairs.get(exchange)) {
+            (Some(pair), Some(main)) => pair == main,
+            _ => true,
+        }
+    }
+
+    fn close(&self) {
+        for (cache, _) in self.groups.iter() {
+            cache.close();
+        }
+    }
+}
+
+// Ingest stage: bookkeeping that is cheap per frame, then into the cache.
+pub async fn ingest(
+    mut rx: IngestReceiver,
+    cache: BookCache,
+    // exchange -> (rate name, invert) of the books used as bridge rates
+    bridge_books: HashMap<String, (String, bool)>,
+    mut bbo: Option<BboTicker>,
+    groups: GroupRouter,
+) {
+    while let Some((exchange, orderbook, received)) = rx.recv().await {
+        status::book_updated(&exchange, orderbook.timestamp);
+        if orderbook.crossed() {
+            status::book_fault(&exchange, orderbook.timestamp);
+        }
+        // a bridge book is quoted in other currencies, keep it out of the ladder
+        if let Some((name, invert)) = bridge_books.get(&exchange) {
+            opportunity::bridge::on_book(name, *invert, orderbook.mid(), orderbook.timestamp);
+            continue;
+        }
+        if let Some(bbo) = bbo.as_mut() {
+            bbo.observe(&exchange, &orderbook);
+        }
+        analytics::ages::observe(&orderbook, get_unixtime());
+        groups.route(&exchange, &orderbook, received);
+        if groups.is_main(&exchange, &orderbook) {
+            cache.update(exchange, orderbook, received);
+        }
+    }
+    groups.close();
+    cache.close();
+}
+
+const SHED_WAIT_MAX: Duration = Duration::from_secs(1);
+
+// Publisher stage: `build` gets a snapshot of the books, and the receipt of
+// the newest one, every time the cache changed since its last run. Returns
+// once the cache is closed. While the guardrails shed load, it runs at
+// half the rate at most.
+pub async fn publish<F>(cache: BookCache, mut build: F)
+where
+    F: FnMut(&HashMap<String, Arc<Orderbook>>, Option<Instant>),
+{
+    let mut built = 0;
+    let mut last_build: Option<Instant> = None;
+    loop {
+        cache.dirty.notified().await;
+        if let Some(last) = last_build.filter(|_| guardrails::shedding()) {
+            // as long again as since the last build, a second at most so a
+            // quiet feed isn't held back
+            sleep(last.elapsed().min(SHED_WAIT_MAX)).await;
+        }
+        // read before the snapshot, so the books after close get built once
+        let closed = cache.closed.load(Ordering::Acquire);
+        let (books, version) = cache.snapshot();
+        let received = *cache.received.lock().unwrap();
+        if !books.is_empty() && version != built {
+            built = version;
+            last_build = Some(Instant::now());
+            build(&books, received);
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+// The books into the aggregate kept from the previous publish, but those of
+// the venues evicted for maintenance.
+pub fn merge(agg: &mut AggregatedOrderbook, books: &HashMap<String, Arc<Orderbook>>) {
+    // a venue under maintenance serves a frozen book
+    let evicted = status::evicted();
+    agg.remerge(
+        books
+            .iter()
+            .filter(|(exchange, _)| !evicted.contains(*exchange))
+            .map(|(_, ob)| ob.as_ref()),
+    );
+}
+
+// the exchanges of `books` under maintenance, see MaintenancePolicy
+pub fn maintenance(books: &HashMap<String, Arc<Orderbook>>) -> Vec<String> {
+    status::under_maintenance()
+        .into_iter()
+        .filter(|exchange| books.contains_key(exchange))
+        .collect()
+}
+
+pub enum GateState {
+    // hold the summary back
+    Closed,
+    // publish, with the annotation of the first summary
+    Opening(Warmup),
+    Open,
+}
+
+// Startup gate of the publisher: closed until `needed` of the expected
+// exchanges delivered a book, or until the deadline, whichever comes first.
+pub struct WarmupGate {
+    needed: usize,
+    // unix millis
+    deadline: u128,
+    expected: Vec<String>,
+    open: bool,
+}
+
+impl WarmupGate {
+    pub fn new(needed: usize, timeout_ms: u128, mut expected: Vec<String>, now: u128) -> Self {
+        expected.sort();
+        status::warming_up(Some(expected.clone()));
+        WarmupGate {
+            needed,
+            deadline: now + timeout_ms,
+            expected,
+            open: false,
+        }
+    }
+
+    pub fn check(&mut self, books: &HashMap<String, Arc<Orderbook>>, now: u128) -> GateState {
+        if self.open {
+            return GateState::Open;
+        }
+        let missing: Vec<String> = self
+            .expected
+            .iter()
+            .filter(|e| !books.contains_key(*e))
+            .cloned()
+            .collect();
+        let complete = self.expected.len() - missing.len() >= self.needed;
+        if !complete && now < self.deadline {
+            status::warming_up(Some(missing));
+            return GateState::Closed;
+        }
+        self.open = true;
+        status::warming_up(None);
+        GateState::Opening(Warmup {
+            warmup_complete: complete,
+            missing,
+        })
+    }
+}
+
+// The feeds of the redistributable_only outputs, without the exchanges
+// denying redistribution. None when no exchange does, those outputs get
+// the full feeds then.
+#[derive(Clone)]
+pub struct Redistributable {
+    pub denied: Arc<HashSet<String>>,
+    pub btx: broadcast::Sender<ByteString>,
+    pub stx: broadcast::Sender<Arc<Summary>>,
+    // the bbo ticks, None when bbo_ticks is off
+    pub bbo: Option<broadcast::Sender<ByteString>>,
+}
+
+// summaries nobody was subscribed to when they were published
+pub static SKIPPED_NO_SUBSCRIBERS: AtomicU64 = AtomicU64::new(0);
+
+// keep the summary for new sessions and hand it to the open ones
+pub fn publish_summary(btx: &broadcast::Sender<ByteString>, latest: Snapshot) {
+    let summary = latest.json();
+    debug!("Summary {}", summary);
+    snapshots::store(Topic::Main, latest);
+    // a broadcast send only fails without receivers, which is just an
+    // instance with no dashboard connected
+    if btx.send(summary).is_err() {
+        SKIPPED_NO_SUBSCRIBERS.fetch_add(1, Ordering::Relaxed);
+        sink::statsd::count_sampled("messages_skipped_no_subscribers", &[]);
+    }
+}
+
+pub async fn executor(
+    exchange: String,
+    pairs: Vec<ExchangeSetting>,
+    tx: IngestSender,
+) -> Result<()> {
+    let mut client = Exchange::new(&exchange);
+    info!("start executor: {}", exchange);
+    // only the first pair is aggregated, so only it is audited
+    let auditor = pairs
+        .first()
+        .and_then(|s| audit::start(&exchange, &s.pair, s.sides));
+    // a failed first connect is retried like any other, unless permanent
+    match client.connect(pairs).await {
+        Ok(()) => status::connected(&exchange),
+        Err(e) if exchange::is_permanent(&e) => return Err(e),
+        Err(e) => error!("{}, connect error {}", e, exchange),
+    }
+    info!("connect {}", exchange);
+    // currently we only allow single subscription
+    loop {
+        match client.next().await {
+            Ok(Some(orderbook)) => {
+                let received = client.received().unwrap_or_else(Instant::now);
+                if let Some(auditor) = &auditor {
+                    auditor.observe(orderbook.clone());
+                }
+                tx.send((exchange.clone(), orderbook, received))?;
+                if !auditor.as_ref().is_some_and(|a| a.take_resync()) {
+                    continue;
+                }
+                error!("{}: the book drifted from the rest one, resync", exchange);
+            }
+            Ok(None) => {
+                error!("shutdown {}", exchange);
+            }
+            Err(e) if exchange::is_permanent(&e) => return Err(e),
+            Err(e) => {
+                error!("{}, reconnect...", e);
+            }
+        }
+        status::disconnected(&exchange);
+        sink::statsd::count("reconnects", 1, &[("exchange", &exchange)]);
+        if let Err(e) = client.clear() {
+            error!("{}, clear error", e);
+        }
+        // with the pairs added or removed since
+        let pairs = client.settings();
+        client = Exchange::new(&exchange);
+        match client.connect(pairs).await {
+            Ok(()) => status::connected(&exchange),
+            Err(e) if exchange::is_permanent(&e) => return Err(e),
+            Err(e) => {
+                error!("{}, connect error {}", e, exchange);
+                // the venue is unreachable, don't spin on it
+                status::reconnect_scheduled(&exchange, exchange::RECONNECT_BACKOFF);
+                tokio::time::sleep(exchange::RECONNECT_BACKOFF).await;
+            }
+        }
+        error!("connect {}", exchange);
+    }
+}
+
+// an exchange that failed for good
+fn give_up(exchange: &str, e: &anyhow::Error, fail_fast: bool) {
+    error!("{}: giving up, {}", exchange, e);
+    status::failed(exchange, &e.to_string());
+    if fail_fast {
+        error!("fail_fast is set, exiting");
+        std::process::exit(1);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn setup_marketdata(
+    exchange_pairs: HashMap<String, Vec<ExchangeSetting>>,
+    btx: broadcast::Sender<ByteString>,
+    stx: broadcast::Sender<Arc<Summary>>,
+    // exchange -> (rate name, invert) of the books used as bridge rates
+    bridge_books: HashMap<String, (String, bool)>,
+    detector: DetectorSetting,
+    pair: String,
+    volatility: Option<VolatilitySetting>,
+    warmup: Option<WarmupSetting>,
+    pipeline_ms: bool,
+    fail_fast: bool,
+    consolidated_levels: Option<usize>,
+    output_precision: Option<OutputPrecision>,
+    cache: BookCache,
+    bbo: Option<BboTicker>,
+    groups: GroupRouter,
+    guardrail: GuardrailSetting,
+    redistributable: Option<Redistributable>,
+    // generators in place of the exchanges, and where their final report goes
+    synthetic: Option<(SyntheticSpec, oneshot::Sender<soak::Report>)>,
+) {
+    let mut tracker = opportunity::tracker::Tracker::new(detector.clone(), &pair);
+    let mut volatility = volatility.map(analytics::volatility::Estimator::new);
+    // exchanges that end up in the aggregate
+    let expected: Vec<String> = exchange_pairs
+        .keys()
+        .filter(|e| !bridge_books.contains_key(*e))
+        .cloned()
+        .collect();
+    let mut gate = warmup.map(|setting| {
+        // validated with the config
+        let needed = setting.min_exchanges.of(expected.len()).unwrap_or(1);
+        WarmupGate::new(
+            needed,
+            setting.warmup_timeout_secs as u128 * 1000,
+            expected,
+            get_unixtime(),
+        )
+    });
+    let (itx, irx) = ingest_channel(guardrail.ingest_capacity);
+    let monitor = irx.monitor();
+    let (summaries, sinks, books) = (btx.clone(), stx.clone(), cache.clone());
+    let soaking = synthetic.is_some();
+    if let Some((spec, done)) = synthetic {
+        let soak = soak::run(
+            spec,
+            guardrail.clone(),
+            itx.clone(),
+            irx.monitor(),
+            btx.subscribe(),
+        );
+        tokio::spawn(async move {
+            let _ = done.send(soak.await);
+        });
+    }
+    tokio::spawn(guardrails::run(guardrail, move || guardrails::Report {
+        ingest_depth: monitor.depth(),
+        ingest_dropped: monitor.dropped(),
+        summary_queue: summaries.len(),
+        sink_queue: sinks.len(),
+        books: books.snapshot().0.len(),
+        parser_state: apitree::state_sizes().into_iter().collect(),
+        resident_mb: guardrails::resident_mb(),
+    }));
+    let mut threads = vec![];
+    // the generators stand for the exchanges in a soak test
+    let exchange_pairs = match soaking {
+        true => HashMap::new(),
+        false => exchange_pairs,
+    };
+    for (exchange, settings) in exchange_pairs {
+        info!("loading {}: {:?}", exchange, settings);
+        let ltx = itx.clone();
+        if settings.first().is_some_and(|s| !s.ws_api) {
+            if let Err(e) = exchange::poller::spawn(exchange.clone(), settings, ltx) {
+                give_up(&exchange, &e, fail_fast);
+            }
+            continue;
+        }
+        threads.push(std::thread::spawn(move || {
+            let system = actix::System::new();
+            let runtime = system.runtime();
+            let result = runtime.block_on(executor(exchange.clone(), settings.clone(), ltx));
+            if let Err(e) = result {
+                give_up(&exchange, &e, fail_fast);
+            }
+        }));
+    }
+    // ingest only swaps books into the cache, the publisher below builds
+    // the summary from the latest books whenever it is free
+    tokio::spawn(ingest(irx, cache.clone(), bridge_books, bbo, groups));
+    // counts the published summaries, for the clients to tell the gaps
+    let mut seq = 0u64;
+    // kept across the publishes, the scalars are only written on a change
+    let mut agg = AggregatedOrderbook::new();
+    agg.consolidated_levels = consolidated_levels;
+    agg.output_precision = output_precision;
+    publish(cache, |books, received| {
+        let warmup = match gate.as_mut().map(|g| g.check(books, get_unixtime())) {
+            Some(GateState::Closed) => return,
+            Some(GateState::Opening(warmup)) => Some(warmup),
+            _ => None,
+        };
+        let start = Instant::now();
+        merge(&mut agg, books);
+        timings::record(Stage::Merge, start);
+        let finalizing = Instant::now();
+        let ticker_age_ms = apitree::ticker::apply(&mut agg, get_unixtime());
+        let traded = analytics::traded::apply(&mut agg, get_unixtime());
+        analytics::ages::apply(&mut agg, get_unixtime());
+        let mid_vs_index_bps = apitree::index::apply(&mut agg);
+        match agg.finalize() {
+            Ok(mut result) => {
+                let now = get_unixtime();
+                seq += 1;
+                result.seq = seq;
+                result.stats.traded = traded;
+                result.stats.ticker_age_ms = ticker_age_ms;
+                result.stats.mid_vs_index_bps = mid_vs_index_bps;
+                result.stats.update_rate = status::update_rates(now);
+                result.stats.warmup = warmup;
+                result.stats.maintenance = maintenance(books);
+                if let Some(estimator) = volatility.as_mut() {
+                    if let Some(mid) = analytics::volatility::aggregate_mid(&result) {
+                        result.stats.volatility = Some(estimator.update(mid, now));
+                    }
+                }
+                result.stats.leader = analytics::leadlag::update(&result, now);
+                analytics::contribution::update(&agg, now);
+                let mut detected =
+                    opportunity::detect(&result, &detector, &status::snapshot(), now);
+                opportunity::portfolio::observe(&detected, now);
+                opportunity::portfolio::annotate(&mut detected);
+                result.opportunities = tracker
+                    .update(&detected, now)
+                    .iter()
+                    .map(|e| e.to_summary())
+                    .collect();
+                timings::record(Stage::Finalize, finalizing);
+                if pipeline_ms {
+                    // up to here, serializing and sending are left out
+                    result.stats.pipeline_ms = received.map(|r| r.elapsed().as_secs_f64() * 1e3);
+                }
+                // written straight from the aggregate. String -> ByteString
+                // takes over the buffer without a copy.
+                let view = SummaryView {
+                    book: &agg,
+                    seq,
+                    opportunities: &result.opportunities,
+                    stats: &result.stats,
+                };
+                let serializing = Instant::now();
+                let summary = ByteString::from(serde_json::to_string(&view).unwrap());
+                timings::record(Stage::Serialize, serializing);
+                sink::statsd::timing_sampled("summary.build", start, &[]);
+                let result = Arc::new(result);
+                publish_summary(&btx, Snapshot::new(summary, Some(result.clone())));
+                if let Some(received) = received {
+                    timings::record(Stage::Pipeline, received);
+                }
+                sink::statsd::count_sampled("summaries.published", &[]);
+                if let Some(feeds) = &redistributable {
+                    let filtered = sink::redistribution::redistributable(&result, &feeds.denied);
+                    let filtered = Arc::new(filtered);
+                    match serde_json::to_string(&*filtered) {
+                        Ok(summary) => {
+                            let summary = ByteString::from(summary);
+                            let latest = Snapshot::new(summary.clone(), Some(filtered.clone()));
+                            snapshots::store(Topic::Redistributable, latest);
+                            let _ = feeds.btx.send(summary);
+                        }
+                        Err(e) => error!("redistributable summary: {}", e),
+                    }
+                    let _ = feeds.stx.send(filtered);
+                }
+                // structured copy for the sinks. No receiver just means no sink is enabled.
+                let _ = stx.send(result);
+            }
+            Err(e) => {
+                error!("{:?}", e);
+            }
+        }
+    })
+    .await;
+    threads.clear();
+}
+
+// The aggregate of a pair group, built from the books the ingest stage
+// routes into its cache. The sinks, the warmup gate and the analytics with
+// state of their own stay with the main aggregate.
+pub async fn aggregate_group(
+    name: String,
+    setting: GroupSetting,
+    detector: DetectorSetting,
+    cache: BookCache,
+    btx: broadcast::Sender<ByteString>,
+) {
+    // the pair of the first exchange by name, like pair_name
+    let pair = setting
+        .exchanges
+        .iter()
+        .min_by_key(|(exchange, _)| *exchange)
+        .map(|(_, pair)| pair.clone())
+        .unwrap_or_default();
+    let mut tracker = opportunity::tracker::Tracker::new(detector.clone(), &pair);
+    let mut seq = 0u64;
+    let mut agg = AggregatedOrderbook::new();
+    agg.consolidated_levels = setting.consolidated_levels;
+    agg.output_precision = setting.output_precision;
+    publish(cache, |books, _| {
+        let now = get_unixtime();
+        merge(&mut agg, books);
+        let ticker_age_ms = apitree::ticker::apply(&mut agg, now);
+        let traded = analytics::traded::apply(&mut agg, now);
+        match agg.finalize() {
+            Ok(mut result) => {
+                seq += 1;
+                result.stats.traded = traded;
+                result.stats.ticker_age_ms = ticker_age_ms;
+                result.stats.update_rate = status::update_rates(now);
+                result
+                    .stats
+                    .update_rate
+                    .retain(|exchange, _| books.contains_key(exchange));
+                result.stats.maintenance = maintenance(books);
+                let detected = opportunity::detect(&result, &detector, &status::snapshot(), now);
+                result.opportunities = tracker
+                    .update(&detected, now)
+                    .iter()
+                    .map(|e| e.to_summary())
+                    .collect();
+                let view = SummaryView {
+                    book: &agg,
+                    seq,
+                    opportunities: &result.opportunities,
+                    stats: &result.stats,
+                };
+                let summary = ByteString::from(serde_json::to_string(&view).unwrap());
+                let latest = Snapshot::new(summary.clone(), Some(Arc::new(result)));
+                snapshots::store(Topic::Group(name.clone()), latest);
+                // no receiver is just no client of the group
+                let _ = btx.send(summary);
+            }
+            Err(e) => {
+                error!("group {}: {:?}", name, e);
+            }
+        }
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Side;
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_slow_publisher() {
+        let cache = BookCache::default();
+        let (tx, rx) = ingest_channel(1000);
+        let ingested = tokio::spawn(ingest(
+            rx,
+            cache.clone(),
+            HashMap::new(),
+            None,
+            GroupRouter::default(),
+        ));
+        let builds = Arc::new(Mutex::new(vec![]));
+        let seen = builds.clone();
+        let publisher = tokio::spawn(publish(cache.clone(), move |books, _| {
+            // a serializer far slower than the feed
+            std::thread::sleep(Duration::from_millis(50));
+            seen.lock().unwrap().push(books["pipeline"].timestamp);
+        }));
+
+        let start = Instant::now();
+        for i in 1..=200 {
+            let mut ob = Orderbook::new("pipeline");
+            ob.timestamp = i;
+            tx.send(("pipeline".to_string(), Arc::new(ob), Instant::now()))
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        // ingest kept up with the feed, not with the publisher
+        while cache.snapshot().0.get("pipeline").map(|ob| ob.timestamp) != Some(200) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        drop(tx);
+        ingested.await.unwrap();
+        publisher.await.unwrap();
+
+        let builds = builds.lock().unwrap();
+        // bursts are coalesced, and the last state is always published
+        assert!(builds.len() < 100, "{} builds", builds.len());
+        assert_eq!(builds.last(), Some(&200));
+        assert!(builds.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[tokio::test]
+    async fn test_flooded_ingest() {
+        let (tx, mut rx) = ingest_channel(100);
+        let monitor = rx.monitor();
+        let flood = tx.clone();
+        // a venue far faster than ingest
+        let sender = std::thread::spawn(move || {
+            for i in 1..=10_000 {
+                let mut ob = Orderbook::new("flood");
+                ob.timestamp = i;
+                flood
+                    .send(("flood".to_string(), Arc::new(ob), Instant::now()))
+                    .unwrap();
+            }
+        });
+        sender.join().unwrap();
+        assert_eq!(monitor.depth(), 100);
+        assert_eq!(monitor.dropped(), 9_900);
+        drop(tx);
+        // the newest frames are the ones kept, then closed
+        let mut received = vec![];
+        while let Some((_, ob, _)) = rx.recv().await {
+            received.push(ob.timestamp);
+        }
+        assert_eq!(received, (9_901..=10_000).collect::<Vec<u128>>());
+        assert_eq!(monitor.depth(), 0);
+
+        let (tx, rx) = ingest_channel(1);
+        drop(rx);
+        let ob = Arc::new(Orderbook::new("flood"));
+        assert!(tx.send(("flood".to_string(), ob, Instant::now())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bbo_ticks() {
+        let (btx, mut brx) = broadcast::channel(10);
+        let pairs = Arc::new(HashMap::from([(
+            "kraken".to_string(),
+            "XBT/USD".to_string(),
+        )]));
+        let (tx, rx) = ingest_channel(1000);
+        let cache = BookCache::default();
+        let ingested = tokio::spawn(ingest(
+            rx,
+            cache,
+            HashMap::new(),
+            Some(BboTicker::new(btx, pairs)),
+            GroupRouter::default(),
+        ));
+        let send = |ts: u128, bids: &[(i32, i32)], asks: &[(i32, i32)]| {
+            let mut ob = Orderbook::new("kraken");
+            for (p, v) in bids {
+                ob.insert(Side::Bid, (*p).into(), (*v).into());
+            }
+            for (p, v) in asks {
+                ob.insert(Side::Ask, (*p).into(), (*v).into());
+            }
+            ob.timestamp = ts;
+            tx.send(("kraken".to_string(), Arc::new(ob), Instant::now()))
+                .unwrap();
+        };
+        send(1, &[(99, 1), (100, 2)], &[(101, 3), (102, 4)]);
+        // a level below the top changed
+        send(2, &[(98, 5), (100, 2)], &[(101, 3), (102, 6)]);
+        // the size at the best ask changed
+        send(3, &[(98, 5), (100, 2)], &[(101, 1), (102, 6)]);
+        // the bid side emptied
+        send(4, &[], &[(101, 1)]);
+        drop(tx);
+        ingested.await.unwrap();
+
+        let tick = |raw: ByteString| serde_json::from_str::<serde_json::Value>(&raw).unwrap();
+        assert_eq!(
+            tick(brx.try_recv().unwrap()),
+            serde_json::json!({
+                "op": "bbo", "exchange": "kraken", "pair": "XBT/USD",
+                "bid": "100", "bid_size": "2", "ask": "101", "ask_size": "3",
+                "ts": 1, "seq": 1,
+            })
+        );
+        let second = tick(brx.try_recv().unwrap());
+        assert_eq!((&second["ts"], &second["seq"]), (&3.into(), &2.into()));
+        assert_eq!(second["ask_size"], "1");
+        let third = tick(brx.try_recv().unwrap());
+        assert_eq!(
+            (&third["ts"], &third["bid"]),
+            (&4.into(), &serde_json::Value::Null)
+        );
+        assert!(brx.try_recv().is_err());
+    }
+
+    // runs the gate the way the publisher does, sending what passes it
+    fn gated(
+        gate: &mut WarmupGate,
+        books: &HashMap<String, Arc<Orderbook>>,
+        now: u128,
+        tx: &tokio::sync::broadcast::Sender<Option<Warmup>>,
+    ) {
+        match gate.check(books, now) {
+            GateState::Closed => {}
+            GateState::Opening(warmup) => {
+                tx.send(Some(warmup)).unwrap();
+            }
+            GateState::Open => {
+                tx.send(None).unwrap();
+            }
+        }
+    }
+
+    fn books(exchanges: &[&str]) -> HashMap<String, Arc<Orderbook>> {
+        exchanges
+            .iter()
+            .map(|e| (e.to_string(), Arc::new(Orderbook::new(e))))
+            .collect()
+    }
+
+    #[test]
+    fn test_warmup_quorum() {
+        let (tx, mut rx) = tokio::sync::broadcast::channel(10);
+        let expected = vec!["c".to_string(), "a".to_string(), "b".to_string()];
+        let mut gate = WarmupGate::new(2, 10_000, expected, 0);
+        gated(&mut gate, &books(&["a"]), 1000, &tx);
+        // one venue is not an aggregate, nothing goes out
+        assert!(rx.try_recv().is_err());
+        gated(&mut gate, &books(&["a", "b"]), 2000, &tx);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Some(Warmup {
+                warmup_complete: true,
+                missing: vec!["c".to_string()],
+            })
+        );
+        // annotated once
+        gated(&mut gate, &books(&["a"]), 3000, &tx);
+        assert_eq!(rx.try_recv().unwrap(), None);
+    }
+
+    #[test]
+    fn test_warmup_timeout() {
+        let (tx, mut rx) = tokio::sync::broadcast::channel(10);
+        let expected = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut gate = WarmupGate::new(3, 10_000, expected, 0);
+        gated(&mut gate, &books(&["b"]), 9_999, &tx);
+        assert!(rx.try_recv().is_err());
+        gated(&mut gate, &books(&["b"]), 10_000, &tx);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Some(Warmup {
+                warmup_complete: false,
+                missing: vec!["a".to_string(), "c".to_string()],
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_executor_gives_up() {
+        let (tx, _rx) = ingest_channel(100);
+        let pairs = vec![serde_yaml::from_str("pair: BTC-AUD").unwrap()];
+        let run = executor("nosuch".to_string(), pairs, tx);
+        // returns instead of reconnecting forever
+        let e = tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(exchange::is_permanent(&e));
+    }
+}
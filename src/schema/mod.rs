@@ -0,0 +1,223 @@
+// Version of the published Summary layout, sent as its schema_version.
+//
+// The policy, checked by the tests below against the golden Summary of
+// every version in test_resource/schema:
+// - adding a field, or a key to a map, keeps the version. Consumers ignore
+//   what they don't know.
+// - removing, renaming or retyping a field, optional ones included, bumps
+//   it. The consumer structs of the old version stay in their module, a new
+//   module and golden file are added for the new one.
+use serde_json::{Map, Value};
+
+// only ever deserialized, by the compatibility tests
+#[cfg(test)]
+#[allow(dead_code)]
+pub mod v1;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// the schema_version of a serialized summary. The summaries from before the
+// field was added are version 1.
+pub fn version(summary: &Map<String, Value>) -> u64 {
+    summary
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opportunity::portfolio::{Execution, NetProfit};
+    use crate::opportunity::tracker::State;
+    use crate::orderbook::{
+        AggregatedOrderbook, Derivatives, OpportunitySummary, Orderbook, Regime, Side, Stats,
+        SummaryView, TradedVolume, UpdateRate, Volatility, Warmup, WindowVolatility,
+    };
+    use anyhow::{anyhow, Result};
+    use bigdecimal::BigDecimal;
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    // version -> golden Summary, with every optional section present
+    const GOLDEN: [(u64, &str); 1] = [(1, include_str!("../test_resource/schema/v1.json"))];
+
+    // a summary of the version with the consumer structs of that version
+    fn read(version: u64, raw: &str) -> Result<()> {
+        match version {
+            1 => serde_json::from_str::<v1::Summary>(raw).map(|_| ()),
+            v => return Err(anyhow!("no consumer structs of schema version {}", v)),
+        }
+        .map_err(|e| anyhow!("version {}: {}", version, e))
+    }
+
+    // Every field of `old` is in `new`, with the same json type. Arrays are
+    // compared by their first element, maps by their shared keys.
+    fn compatible(old: &Value, new: &Value, path: &str) -> Result<()> {
+        match (old, new) {
+            (Value::Object(old), Value::Object(new)) => {
+                for (key, value) in old.iter() {
+                    let field = format!("{}.{}", path, key);
+                    match new.get(key) {
+                        Some(next) => compatible(value, next, &field)?,
+                        None => return Err(anyhow!("{} was removed", field)),
+                    }
+                }
+                Ok(())
+            }
+            (Value::Array(old), Value::Array(new)) => match (old.first(), new.first()) {
+                (Some(old), Some(new)) => compatible(old, new, &format!("{}[]", path)),
+                _ => Ok(()),
+            },
+            // an optional value may be null on either side
+            (Value::Null, _) | (_, Value::Null) => Ok(()),
+            (Value::Bool(_), Value::Bool(_))
+            | (Value::Number(_), Value::Number(_))
+            | (Value::String(_), Value::String(_)) => Ok(()),
+            _ => Err(anyhow!("{} changed type, {} -> {}", path, old, new)),
+        }
+    }
+
+    // the Summary published now, with every optional section filled in
+    fn current() -> Value {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let mut a = Orderbook::new("A");
+        a.insert(Side::Bid, dec("99.5"), dec("1.25"));
+        a.insert(Side::Ask, dec("100"), dec("2"));
+        a.timestamp = 1696405428703;
+        a.volume = dec("1234.5");
+        a.last_price = dec("99.75");
+        let mut perp = Orderbook::new("binance_futures");
+        perp.insert(Side::Bid, dec("100.4"), dec("1"));
+        perp.insert(Side::Ask, dec("100.6"), dec("1"));
+        perp.timestamp = 1696405428704;
+        perp.derivatives = Some(Derivatives {
+            mark_price: dec("100.5"),
+            index_price: dec("100.1"),
+            funding_rate: dec("0.0001"),
+            next_funding_time: 1696406400000,
+        });
+        let mut agg = AggregatedOrderbook::new();
+        agg.consolidated_levels = Some(1);
+        agg.merge(&a);
+        agg.merge(&perp);
+        agg.finalize().unwrap();
+        let opportunities = vec![OpportunitySummary {
+            state: State::New,
+            buy_exchange: "A".to_string(),
+            sell_exchange: "binance_futures".to_string(),
+            opened: 1696405428703,
+            profit_bps: 40.,
+            quantity: "1".to_string(),
+            max_size: Some(5000.),
+            confidence: 0.9,
+            net: Some(NetProfit {
+                execution: Execution::Executable,
+                quantity: "1".to_string(),
+                gross_bps: 40.,
+                fee_bps: 20.,
+                rebalance_bps: 1.,
+                net_bps: 19.,
+                trades_left: 3,
+                exhausted_in_secs: Some(60.),
+                transfer_mins: Some(30.),
+            }),
+        }];
+        let stats = Stats {
+            volatility: Some(Volatility {
+                windows: vec![WindowVolatility {
+                    window_secs: 60,
+                    bps: 3.5,
+                    samples: 12,
+                }],
+                regime: Regime::Normal,
+            }),
+            leader: Some("A".to_string()),
+            traded: vec![TradedVolume {
+                window_secs: 60,
+                volume: BTreeMap::from([("A".to_string(), "0.5".to_string())]),
+            }],
+            warmup: Some(Warmup {
+                warmup_complete: true,
+                missing: vec![],
+            }),
+            pipeline_ms: Some(0.25),
+            ticker_age_ms: BTreeMap::from([("A".to_string(), 1500)]),
+            update_rate: BTreeMap::from([(
+                "A".to_string(),
+                UpdateRate {
+                    interval_ms: 120.,
+                    degraded: false,
+                },
+            )]),
+            mid_vs_index_bps: BTreeMap::new(),
+            maintenance: vec!["A".to_string()],
+        };
+        let view = SummaryView {
+            book: &agg,
+            seq: 7,
+            opportunities: &opportunities,
+            stats: &stats,
+        };
+        serde_json::to_value(&view).unwrap()
+    }
+
+    #[test]
+    fn test_golden_files() {
+        for (version, raw) in GOLDEN {
+            let golden: Value = serde_json::from_str(raw).unwrap();
+            assert_eq!(super::version(golden.as_object().unwrap()), version);
+            read(version, raw).unwrap();
+        }
+        // the golden of the current version is what is published now, give
+        // or take the added fields
+        let current = current();
+        assert_eq!(
+            super::version(current.as_object().unwrap()),
+            SCHEMA_VERSION as u64
+        );
+        let (_, golden) = GOLDEN
+            .iter()
+            .find(|(v, _)| *v == SCHEMA_VERSION as u64)
+            .expect("a golden Summary of the current version");
+        compatible(&serde_json::from_str(golden).unwrap(), &current, "").unwrap();
+        read(SCHEMA_VERSION as u64, &current.to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_additive_change() {
+        let golden: Value = serde_json::from_str(GOLDEN[0].1).unwrap();
+        let mut next = current();
+        next["pair"] = "BTC-USD".into();
+        next["stats"]["queue_depth"] = 3.into();
+        next["bids"][0]["orders"] = 2.into();
+        compatible(&golden, &next, "").unwrap();
+        read(1, &next.to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_breaking_change() {
+        let golden: Value = serde_json::from_str(GOLDEN[0].1).unwrap();
+        // renamed
+        let mut next = current();
+        let spread = next.as_object_mut().unwrap().remove("spread").unwrap();
+        next["spread_bps"] = spread;
+        let e = compatible(&golden, &next, "").unwrap_err();
+        assert_eq!(e.to_string(), ".spread was removed");
+        assert!(read(1, &next.to_string()).is_err());
+        // an optional field is caught too
+        let mut next = current();
+        next["stats"].as_object_mut().unwrap().remove("leader");
+        let e = compatible(&golden, &next, "").unwrap_err();
+        assert_eq!(e.to_string(), ".stats.leader was removed");
+        // retyped, prices as numbers
+        let mut next = current();
+        next["bids"][0]["price"] = 100.4.into();
+        let e = compatible(&golden, &next, "").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            ".bids[].price changed type, \"100.4\" -> 100.4"
+        );
+        assert!(read(1, &next.to_string()).is_err());
+    }
+}
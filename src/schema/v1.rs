@@ -0,0 +1,130 @@
+// The Summary of schema version 1 as a consumer reads it. Frozen: a change
+// here is a change of the published schema, see the policy in schema.
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Deserialize)]
+pub struct Summary {
+    // absent before the field was added
+    #[serde(default = "first")]
+    pub schema_version: u32,
+    pub spread: String,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+    pub timestamp: BTreeMap<String, String>,
+    pub volume: BTreeMap<String, String>,
+    pub last_price: BTreeMap<String, String>,
+    pub consolidated: Option<Consolidated>,
+    #[serde(default)]
+    pub derivatives: BTreeMap<String, Derivatives>,
+    #[serde(default)]
+    pub opportunities: Vec<Opportunity>,
+    #[serde(default)]
+    pub stats: Stats,
+}
+
+fn first() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+pub struct Level {
+    pub exchange: String,
+    pub price: String,
+    pub amount: String,
+}
+
+#[derive(Deserialize)]
+pub struct Consolidated {
+    pub bids: Vec<ConsolidatedLevel>,
+    pub asks: Vec<ConsolidatedLevel>,
+}
+
+#[derive(Deserialize)]
+pub struct ConsolidatedLevel {
+    pub price: String,
+    pub amount: String,
+    pub venues: usize,
+    pub best_venue: String,
+}
+
+#[derive(Deserialize)]
+pub struct Derivatives {
+    pub mark_price: String,
+    pub index_price: String,
+    pub funding_rate: String,
+    pub next_funding_time: u64,
+    pub basis_bps: Option<f64>,
+}
+
+#[derive(Deserialize)]
+pub struct Opportunity {
+    // new | update | closed
+    pub state: String,
+    pub buy_exchange: String,
+    pub sell_exchange: String,
+    pub opened: u64,
+    pub profit_bps: f64,
+    pub quantity: String,
+    pub max_size: Option<f64>,
+    pub confidence: f64,
+    pub net: Option<NetProfit>,
+}
+
+#[derive(Deserialize)]
+pub struct NetProfit {
+    // executable | requires_rebalancing
+    pub execution: String,
+    pub quantity: String,
+    pub gross_bps: f64,
+    pub fee_bps: f64,
+    pub rebalance_bps: f64,
+    pub net_bps: f64,
+    pub trades_left: u64,
+    pub exhausted_in_secs: Option<f64>,
+    pub transfer_mins: Option<f64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Stats {
+    pub volatility: Option<Volatility>,
+    pub leader: Option<String>,
+    pub traded: Vec<TradedVolume>,
+    pub warmup: Option<Warmup>,
+    pub pipeline_ms: Option<f64>,
+    pub ticker_age_ms: BTreeMap<String, u64>,
+    pub update_rate: BTreeMap<String, UpdateRate>,
+}
+
+#[derive(Deserialize)]
+pub struct Volatility {
+    pub windows: Vec<WindowVolatility>,
+    // quiet | normal | volatile
+    pub regime: String,
+}
+
+#[derive(Deserialize)]
+pub struct WindowVolatility {
+    pub window_secs: u64,
+    pub bps: f64,
+    pub samples: usize,
+}
+
+#[derive(Deserialize)]
+pub struct TradedVolume {
+    pub window_secs: u64,
+    pub volume: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct Warmup {
+    pub warmup_complete: bool,
+    pub missing: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateRate {
+    pub interval_ms: f64,
+    pub degraded: bool,
+}
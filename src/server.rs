@@ -0,0 +1,2104 @@
+use crate::config::{
+    self, Config, Encoding, Fault, ListenerSetting, PersistSetting, RouteGroup, TlsSetting,
+};
+use crate::orderbook::Summary;
+use crate::pipeline::{self, aggregate_group, setup_marketdata, Redistributable};
+use crate::snapshots::{self, Snapshot, Topic};
+use crate::{
+    alert, analytics, apitree, audit, chaos, deadletter, exchange, fixed, guardrails, info,
+    opportunity, simulation, sink, soak, status, timings,
+};
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix_web::{middleware, web, App, HttpRequest, HttpResponse, HttpServer};
+use actix_web_actors::ws;
+use actix_web_codegen::*;
+use anyhow::{anyhow, Result};
+use bigdecimal::BigDecimal;
+use bytestring::ByteString;
+use futures_util::StreamExt;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::sync::{broadcast, oneshot};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+
+// A summary is serialized once into a ByteString. Every consumer (the
+// cache, the websocket sessions, the unix socket clients) shares its buffer,
+// cloning only bumps a reference count.
+struct Session {
+    tx: broadcast::Sender<ByteString>,
+    books: DeepBooks,
+    bbo: BboFeed,
+    bbo_subscribed: bool,
+    status_subscribed: bool,
+    // what tx carries, the snapshot sent on connect is of the same
+    topic: Topic,
+    // what to do when the client falls behind the summaries
+    on_lag: LagPolicy,
+    // summaries missed since the last one sent, for the gap marker
+    missed: u64,
+}
+
+impl Session {
+    pub fn new(tx: broadcast::Sender<ByteString>, books: DeepBooks, bbo: BboFeed) -> Self {
+        Self {
+            tx,
+            books,
+            bbo,
+            bbo_subscribed: false,
+            status_subscribed: false,
+            topic: Topic::Main,
+            on_lag: LagPolicy::default(),
+            missed: 0,
+        }
+    }
+}
+
+// a pair group: its summary broadcast and the exchanges it aggregates
+struct Group {
+    tx: broadcast::Sender<ByteString>,
+    exchanges: Vec<String>,
+}
+
+// name -> pair group, empty without groups
+#[derive(Clone, Default)]
+struct Groups(Arc<HashMap<String, Group>>);
+
+// the bbo ticks of the ingest stage, None when bbo_ticks is off
+#[derive(Clone)]
+struct BboFeed(Option<broadcast::Sender<ByteString>>);
+
+// the topic of the main aggregate on a listener, filtered on the
+// redistributable_only ones, see serve
+#[derive(Clone)]
+struct MainTopic(Topic);
+
+// the bearer token of the admin routes, see authorized
+#[derive(Clone)]
+struct AdminToken(Option<Arc<str>>);
+
+// The request carries the admin token, compared in constant time. Nothing
+// is authorized without a token configured.
+fn authorized(req: &HttpRequest) -> bool {
+    let Some(AdminToken(Some(token))) = req.app_data::<AdminToken>() else {
+        return false;
+    };
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| given.as_bytes().ct_eq(token.as_bytes()).into())
+}
+
+// The first argument of the admin handlers: 401 unless authorized, before
+// the body is read.
+struct Admin;
+
+impl actix_web::FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Admin, actix_web::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        std::future::ready(match authorized(req) {
+            true => Ok(Admin),
+            false => Err(actix_web::error::ErrorUnauthorized("unauthorized")),
+        })
+    }
+}
+
+// The latest book of every exchange, for the clients that ask for more
+// levels than the broadcast carries.
+#[derive(Clone)]
+struct DeepBooks {
+    cache: pipeline::BookCache,
+    // exchange -> the pair of its book
+    pairs: Arc<HashMap<String, String>>,
+    max_depth: usize,
+}
+
+// requests a ws client can send, tagged by "op"
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ClientOp {
+    DeepSnapshot {
+        exchange: String,
+        pair: Option<String>,
+        depth: Option<usize>,
+    },
+    Hello,
+    SubscribeBbo,
+    SubscribeStatus,
+    Subscribe {
+        on_lag: Option<LagPolicy>,
+    },
+}
+
+// What a session does once its client fell so far behind that the
+// broadcast dropped summaries it hadn't sent yet.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+enum LagPolicy {
+    // go on from the newest summary
+    Skip,
+    // tell the client first, {"op":"gap","missed":n,"resume_seq":m}
+    GapMarker,
+    // close with LAGGED_CLOSE_CODE, the client reconnects and starts over
+    // from the cached summary
+    #[default]
+    Disconnect,
+}
+
+const LAGGED_CLOSE_CODE: u16 = 4000;
+
+// the seq of a summary, read back for the gap marker only
+#[derive(Deserialize)]
+struct SummarySeq {
+    #[serde(default)]
+    seq: u64,
+}
+
+// the reply to subscribe_status: the state the events are transitions of
+#[derive(Serialize)]
+struct StatusDump {
+    op: &'static str,
+    subscribed: bool,
+    health: status::Health,
+    exchanges: BTreeMap<String, status::ExchangeStatus>,
+}
+
+#[derive(Serialize)]
+struct DeepSnapshot<'a> {
+    op: &'static str,
+    exchange: &'a str,
+    pair: &'a str,
+    depth: usize,
+    timestamp: u128,
+    // [price, amount], best first
+    bids: Vec<[String; 2]>,
+    asks: Vec<[String; 2]>,
+}
+
+impl DeepBooks {
+    // the same books, without the exchanges in `denied`
+    fn without(&self, denied: &HashSet<String>) -> DeepBooks {
+        DeepBooks {
+            pairs: Arc::new(
+                self.pairs
+                    .iter()
+                    .filter(|(e, _)| !denied.contains(*e))
+                    .map(|(e, p)| (e.clone(), p.clone()))
+                    .collect(),
+            ),
+            ..self.clone()
+        }
+    }
+
+    // the book of an exchange down to `depth`, max_depth at most
+    fn snapshot(&self, exchange: &str, pair: Option<&str>, depth: Option<usize>) -> Result<String> {
+        let book_pair = self
+            .pairs
+            .get(exchange)
+            .ok_or_else(|| anyhow!("unknown exchange {}", exchange))?;
+        if pair.is_some_and(|p| p != book_pair) {
+            return Err(anyhow!("unknown pair {} on {}", pair.unwrap(), exchange));
+        }
+        let book = self
+            .cache
+            .book(exchange)
+            .ok_or_else(|| anyhow!("no book of {} yet", exchange))?;
+        let depth = depth.unwrap_or(self.max_depth).min(self.max_depth);
+        let level = |(p, v): (&BigDecimal, &BigDecimal)| [p.to_string(), v.to_string()];
+        Ok(serde_json::to_string(&DeepSnapshot {
+            op: "deep_snapshot",
+            exchange,
+            pair: book_pair,
+            depth,
+            timestamp: book.timestamp(),
+            bids: book.bid().iter().rev().take(depth).map(level).collect(),
+            asks: book.ask().iter().take(depth).map(level).collect(),
+        })?)
+    }
+}
+
+// the latest summary of the main aggregate, as the file and the unix
+// socket take it
+fn cached() -> Option<ByteString> {
+    snapshots::get(&Topic::Main).map(|s| s.json())
+}
+
+fn group_cached(group: &str) -> Option<ByteString> {
+    snapshots::get(&Topic::Group(group.to_string())).map(|s| s.json())
+}
+
+// serve the summary saved before the restart until the first live one
+// replaces it, marked stale
+fn restore_summary(setting: &PersistSetting) -> Option<ByteString> {
+    let max_age = std::time::Duration::from_secs(setting.max_age_secs);
+    match sink::snapshot::load(
+        &setting.snapshot_path,
+        max_age,
+        std::time::SystemTime::now(),
+    ) {
+        Ok(Some(summary)) => {
+            info!("restored summary from {}", setting.snapshot_path);
+            snapshots::store(Topic::Main, Snapshot::new(summary.clone(), None));
+            Some(summary)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!("restore summary: {}", e);
+            None
+        }
+    }
+}
+
+// websocket sessions currently open
+static WS_CLIENTS: AtomicI64 = AtomicI64::new(0);
+
+fn ws_clients() -> i64 {
+    WS_CLIENTS.load(Ordering::Relaxed)
+}
+
+// a broadcast as a stream of frames to the client
+fn frames(
+    tx: &broadcast::Sender<ByteString>,
+) -> impl futures_util::Stream<Item = WsResult> + 'static {
+    BroadcastStream::new(tx.subscribe()).map(|e| {
+        e.map(ws::Message::Text)
+            .map_err(|e| ws::ProtocolError::Io(std::io::Error::other(e)))
+    })
+}
+
+impl Actor for Session {
+    type Context = ws::WebsocketContext<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        WS_CLIENTS.fetch_add(1, Ordering::Relaxed);
+        // send previous record on connect, or tell there is none yet
+        match snapshots::get(&self.topic) {
+            Some(latest) => ctx.text(latest.json()),
+            None => ctx.text(r#"{"op":"no_data"}"#),
+        }
+        ctx.add_stream(BroadcastStream::new(self.tx.subscribe()));
+    }
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        WS_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+type WsResult = Result<ws::Message, ws::ProtocolError>;
+
+impl StreamHandler<WsResult> for Session {
+    fn handle(&mut self, msg: WsResult, ctx: &mut Self::Context) {
+        if msg.is_err() {
+            error!("{:?}", msg);
+            ctx.stop();
+            return;
+        }
+
+        match msg.unwrap() {
+            ws::Message::Ping(p) => {
+                info!("ping {:?}", p);
+            }
+            ws::Message::Text(text) => {
+                info!("recv {}", text);
+                match serde_json::from_str::<ClientOp>(&text) {
+                    Ok(ClientOp::DeepSnapshot {
+                        exchange,
+                        pair,
+                        depth,
+                    }) => match self.books.snapshot(&exchange, pair.as_deref(), depth) {
+                        Ok(reply) => ctx.text(reply),
+                        Err(e) => ctx.text(
+                            serde_json::json!({"op": "deep_snapshot", "exchange": exchange, "error": e.to_string()})
+                                .to_string(),
+                        ),
+                    },
+                    Ok(ClientOp::SubscribeBbo) => match &self.bbo.0 {
+                        Some(bbo) => {
+                            // a second subscribe would double every tick
+                            if !self.bbo_subscribed {
+                                self.bbo_subscribed = true;
+                                ctx.add_stream(frames(bbo));
+                            }
+                            ctx.text(r#"{"op":"subscribe_bbo","subscribed":true}"#);
+                        }
+                        None => ctx.text(r#"{"op":"subscribe_bbo","error":"bbo_ticks is off"}"#),
+                    },
+                    Ok(ClientOp::Hello) => {
+                        let hello = serde_json::json!({"op": "hello", "info": info::get()});
+                        ctx.text(hello.to_string());
+                    }
+                    Ok(ClientOp::Subscribe { on_lag }) => {
+                        self.on_lag = on_lag.unwrap_or_default();
+                        ctx.text(
+                            serde_json::json!({"op": "subscribe", "on_lag": self.on_lag})
+                                .to_string(),
+                        );
+                    }
+                    Ok(ClientOp::SubscribeStatus) => {
+                        // subscribed before the dump, so no transition falls
+                        // in between
+                        if !self.status_subscribed {
+                            self.status_subscribed = true;
+                            ctx.add_stream(frames(&status::events()));
+                        }
+                        let dump = StatusDump {
+                            op: "subscribe_status",
+                            subscribed: true,
+                            health: status::health(),
+                            exchanges: status::snapshot().into_iter().collect(),
+                        };
+                        match serde_json::to_string(&dump) {
+                            Ok(text) => ctx.text(text),
+                            Err(e) => error!("status dump: {}", e),
+                        }
+                    }
+                    // anything else is echoed back
+                    Err(_) => ctx.text(text),
+                }
+            }
+            ws::Message::Pong(_) => {
+                info!("pong");
+            }
+            ws::Message::Binary(bin) => {
+                info!("recv bin {:?}", bin);
+                ctx.binary(bin);
+            }
+            ws::Message::Close(reason) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => (),
+        }
+    }
+    // the client went away, or the summaries ended on shutdown. Either way
+    // the session is over, or it holds up a graceful shutdown.
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        info!("finished");
+        ctx.stop();
+    }
+}
+
+// the summaries, apart from the other frames for the lag policy
+impl StreamHandler<Result<ByteString, BroadcastStreamRecvError>> for Session {
+    fn handle(
+        &mut self,
+        summary: Result<ByteString, BroadcastStreamRecvError>,
+        ctx: &mut Self::Context,
+    ) {
+        match summary {
+            Ok(summary) => {
+                if self.missed > 0 {
+                    let resume_seq = serde_json::from_str::<SummarySeq>(&summary)
+                        .map(|s| s.seq)
+                        .unwrap_or_default();
+                    ctx.text(
+                        serde_json::json!({"op": "gap", "missed": self.missed, "resume_seq": resume_seq})
+                            .to_string(),
+                    );
+                    self.missed = 0;
+                }
+                ctx.text(summary);
+            }
+            Err(BroadcastStreamRecvError::Lagged(n)) => {
+                warn!("ws client lagged {} summaries, {:?}", n, self.on_lag);
+                match self.on_lag {
+                    LagPolicy::Skip => {}
+                    LagPolicy::GapMarker => self.missed += n,
+                    LagPolicy::Disconnect => {
+                        ctx.close(Some(ws::CloseReason {
+                            code: ws::CloseCode::Other(LAGGED_CLOSE_CODE),
+                            description: Some(format!("lagged {} summaries", n)),
+                        }));
+                        ctx.stop();
+                    }
+                }
+            }
+        }
+    }
+    // the summaries ended on shutdown
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        ctx.stop();
+    }
+}
+
+#[get("/ws")]
+async fn websocket(
+    req: HttpRequest,
+    stream: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    let tx = req.app_data::<broadcast::Sender<ByteString>>().unwrap();
+    let tx = tx.clone();
+    let books = req.app_data::<DeepBooks>().unwrap().clone();
+    let bbo = req.app_data::<BboFeed>().unwrap().clone();
+    let mut session = Session::new(tx, books, bbo);
+    session.topic = req.app_data::<MainTopic>().unwrap().0.clone();
+    ws::start(session, &req, stream)
+}
+
+// the summaries of one pair group
+#[get("/ws/group/{name}")]
+async fn group_websocket(
+    req: HttpRequest,
+    stream: web::Payload,
+    name: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let groups = req.app_data::<Groups>().unwrap();
+    let Some(group) = groups.0.get(name.as_str()) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let books = req.app_data::<DeepBooks>().unwrap().clone();
+    let bbo = req.app_data::<BboFeed>().unwrap().clone();
+    let mut session = Session::new(group.tx.clone(), books, bbo);
+    session.topic = Topic::Group(name.into_inner());
+    ws::start(session, &req, stream)
+}
+
+#[derive(Deserialize)]
+struct GroupQuery {
+    group: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SummaryQuery {
+    group: Option<String>,
+    #[serde(default)]
+    format: Encoding,
+}
+
+// latest summary, for consumers that need a snapshot before the stream.
+// 204 before the first one, or in msgpack while only the restored one is.
+#[get("/summary")]
+async fn snapshot(req: HttpRequest, query: web::Query<SummaryQuery>) -> HttpResponse {
+    let topic = match &query.group {
+        Some(group) if !req.app_data::<Groups>().unwrap().0.contains_key(group) => {
+            return HttpResponse::NotFound().finish();
+        }
+        Some(group) => Topic::Group(group.clone()),
+        None => req.app_data::<MainTopic>().unwrap().0.clone(),
+    };
+    let content_type = match query.format {
+        Encoding::Json => "application/json",
+        Encoding::Msgpack => "application/msgpack",
+    };
+    match snapshots::get(&topic).and_then(|s| s.encoded(query.format)) {
+        Some(body) => HttpResponse::Ok().content_type(content_type).body(body),
+        None => HttpResponse::NoContent().finish(),
+    }
+}
+
+// warming up (503) until the first summary, then degraded while an
+// exchange is disconnected. Of a group's exchanges only with ?group=.
+#[get("/health")]
+async fn health(req: HttpRequest, query: web::Query<GroupQuery>) -> HttpResponse {
+    let health = match &query.group {
+        Some(name) => match req.app_data::<Groups>().unwrap().0.get(name) {
+            Some(group) => status::health_among(&group.exchanges, group_cached(name).is_none()),
+            None => return HttpResponse::NotFound().finish(),
+        },
+        None => status::health(),
+    };
+    match health.status {
+        status::HealthState::WarmingUp => HttpResponse::ServiceUnavailable().json(health),
+        _ => HttpResponse::Ok().json(health),
+    }
+}
+
+// rolling percentiles of the pipeline stages
+#[get("/debug/timings")]
+async fn debug_timings() -> HttpResponse {
+    HttpResponse::Ok().json(timings::report())
+}
+
+#[get("/metrics")]
+async fn metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(
+            timings::prometheus(&timings::report())
+                + &deadletter::prometheus()
+                + &status::prometheus()
+                + &guardrails::prometheus()
+                + &analytics::contribution::prometheus(),
+        )
+}
+
+#[derive(Deserialize)]
+struct ExchangeQuery {
+    exchange: Option<String>,
+}
+
+// the frames the parsers failed on, whole. Served on admin listeners only.
+#[get("/debug/parse_errors")]
+async fn parse_errors(query: web::Query<ExchangeQuery>) -> HttpResponse {
+    HttpResponse::Ok().json(deadletter::report(query.exchange.as_deref()))
+}
+
+// the config in use, secrets redacted. Served on admin listeners only, to
+// the requests with the admin token.
+#[get("/config")]
+async fn effective_config(_: Admin) -> HttpResponse {
+    match config::effective() {
+        Some(effective) => HttpResponse::Ok().json(effective),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+// paper trading balances and pnl, 404 when the simulation is disabled
+#[get("/simulation")]
+async fn simulation_report() -> HttpResponse {
+    match simulation::snapshot() {
+        Some(report) => HttpResponse::Ok().json(report),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[post("/simulation/reset")]
+async fn simulation_reset(_: Admin) -> HttpResponse {
+    if simulation::reset() {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct ChaosTrigger {
+    exchange: String,
+    #[serde(flatten)]
+    fault: Fault,
+}
+
+// inject a fault into the next frame of an exchange, 404 unless chaos is
+// enabled. ex: {"exchange":"binance","fault":"freeze","freeze_secs":30}
+#[post("/admin/chaos")]
+async fn chaos_trigger(_: Admin, body: web::Json<ChaosTrigger>) -> HttpResponse {
+    let ChaosTrigger { exchange, fault } = body.into_inner();
+    if chaos::trigger(&exchange, fault) {
+        HttpResponse::Accepted().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct PairUpdate {
+    exchange: String,
+    #[serde(flatten)]
+    change: exchange::PairChange,
+}
+
+// add or remove a pair on a running connection, the other pairs stay up.
+// ex: {"exchange":"btcmarkets","add":{"pair":"ETH-AUD"}}. 409 when the
+// venue only changes its subscriptions with a reconnect.
+#[post("/admin/pairs")]
+async fn pair_update(_: Admin, body: web::Json<PairUpdate>) -> HttpResponse {
+    let PairUpdate { exchange, change } = body.into_inner();
+    match exchange::change_pairs(&exchange, change) {
+        Ok(()) => HttpResponse::Accepted().finish(),
+        Err(e) => HttpResponse::Conflict().body(e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct SinceQuery {
+    // unix millis
+    since: Option<u128>,
+}
+
+// versions, pairs, features and limits, as the ws hello op answers
+#[get("/info")]
+async fn server_info() -> HttpResponse {
+    match info::get() {
+        Some(info) => HttpResponse::Ok().json(info),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+// the configured pairs per exchange, with their tick / lot sizes when
+// symbol_meta is on
+#[get("/exchanges")]
+async fn exchange_list() -> HttpResponse {
+    match info::get() {
+        Some(info) => HttpResponse::Ok().json(apitree::symbol_meta::discovery(&info.pairs)),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+// pairwise lead percentages, 404 when the analyzer is disabled
+#[get("/analytics/leadlag")]
+async fn leadlag() -> HttpResponse {
+    match analytics::leadlag::snapshot() {
+        Some(report) => HttpResponse::Ok().json(report),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+// share of the best prices and the depth per exchange, 404 when disabled
+#[get("/analytics/contribution")]
+async fn contribution() -> HttpResponse {
+    match analytics::contribution::snapshot() {
+        Some(report) => HttpResponse::Ok().json(report),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+// opportunity lifecycle records, 404 when the tracking is disabled
+#[get("/opportunities")]
+async fn opportunities(query: web::Query<SinceQuery>) -> HttpResponse {
+    match opportunity::history::snapshot(query.since.unwrap_or(0)) {
+        Some(report) => HttpResponse::Ok().json(report),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+// A running instance: the feeds, the sinks and the servers of the
+// listeners. The servers stop on SIGINT / SIGTERM.
+pub struct Handle {
+    // bound addresses of every listener, port 0 resolved
+    pub addrs: Vec<std::net::SocketAddr>,
+    servers: Vec<actix_web::dev::Server>,
+    // the final report of a soak test, which runs instead of until a signal
+    pub soak: Option<oneshot::Receiver<soak::Report>>,
+}
+
+impl Handle {
+    // until every server stopped
+    pub async fn wait(self) -> Result<()> {
+        futures_util::future::try_join_all(self.servers)
+            .await
+            .map_err(|e| anyhow!("{:?}", e))?;
+        Ok(())
+    }
+}
+
+// Everything main sets up from a loaded config and the bound listeners,
+// the logger aside. Returns once the servers are built.
+pub async fn run(mut config: Config, bound: Listeners) -> Result<Handle> {
+    let pair = config.inner.pair_name();
+    let denied = config.inner.denied_exchanges();
+    let token = AdminToken(config.inner.admin_token.as_deref().map(Arc::from));
+    info::init(info::server_info(&config.inner));
+
+    if let Some(setting) = &config.inner.statsd {
+        sink::statsd::init(setting)?;
+    }
+
+    let (btx, _) = broadcast::channel::<ByteString>(100);
+    if let Some(setting) = config.inner.persist {
+        let restored = restore_summary(&setting);
+        tokio::spawn(sink::snapshot::run(setting, cached, restored));
+    }
+
+    #[cfg(unix)]
+    if let Some(listener) = bound.unix {
+        tokio::spawn(sink::unix::run(listener, btx.clone(), cached));
+    }
+
+    let (stx, _) = broadcast::channel::<Arc<Summary>>(100);
+    // ticks come far more often than summaries
+    let bbo = BboFeed(
+        config
+            .inner
+            .bbo_ticks
+            .then(|| broadcast::channel::<ByteString>(1000).0),
+    );
+    let redistributable = (!denied.is_empty()).then(|| {
+        info!("not redistributed: {:?}", denied);
+        let denied = Arc::new(denied);
+        let bbo = bbo.0.as_ref().map(|tx| {
+            let (ftx, _) = broadcast::channel::<ByteString>(1000);
+            tokio::spawn(sink::redistribution::forward_ticks(
+                tx.subscribe(),
+                ftx.clone(),
+                denied.clone(),
+            ));
+            ftx
+        });
+        Redistributable {
+            denied,
+            btx: broadcast::channel::<ByteString>(100).0,
+            stx: broadcast::channel::<Arc<Summary>>(100).0,
+            bbo,
+        }
+    });
+    // the structured summaries of an output
+    let summaries = |redistributable_only: bool| match &redistributable {
+        Some(feeds) if redistributable_only => feeds.stx.subscribe(),
+        _ => stx.subscribe(),
+    };
+    if let Some(setting) = config.inner.simulation {
+        tokio::spawn(simulation::run(setting, stx.subscribe()));
+    }
+    if let Some(setting) = config.inner.opportunities {
+        tokio::spawn(opportunity::history::run(
+            setting,
+            config.inner.detector.clone(),
+            pair.clone(),
+            stx.subscribe(),
+        ));
+    }
+    if let Some(alerts) = config.inner.alerts {
+        tokio::spawn(alert::run(
+            alerts,
+            config.inner.detector.clone(),
+            pair.clone(),
+            stx.subscribe(),
+        ));
+    }
+    if let Some(setting) = config.inner.zmq {
+        let rx = summaries(setting.redistributable_only);
+        let ticks = match &redistributable {
+            Some(feeds) if setting.redistributable_only => &feeds.bbo,
+            _ => &bbo.0,
+        }
+        .as_ref()
+        .map(|tx| tx.subscribe());
+        let pair = pair.clone();
+        tokio::spawn(async move {
+            if let Err(e) = sink::zmq::run(setting, pair, rx, ticks).await {
+                error!("zmq sink: {}", e);
+            }
+        });
+    }
+    if let Some(setting) = config.inner.nats {
+        let rx = summaries(setting.redistributable_only);
+        let pair = pair.clone();
+        tokio::spawn(async move {
+            if let Err(e) = sink::nats::run(setting, pair, rx).await {
+                error!("nats sink: {}", e);
+            }
+        });
+    }
+    if let Some(setting) = &config.inner.statsd {
+        tokio::spawn(sink::statsd::run(
+            setting.gauge_interval_ms,
+            ws_clients,
+            stx.subscribe(),
+        ));
+    }
+    if let Some(setting) = config.inner.archive {
+        let rx = stx.subscribe();
+        let pair = pair.clone();
+        tokio::spawn(async move {
+            if let Err(e) = sink::archive::run(setting, pair, rx).await {
+                error!("archive sink: {}", e);
+            }
+        });
+    }
+
+    // subscribe to multiple exchanges
+    // TODO: rewrite using tungstenite
+    fixed::enable(config.inner.fixed_point);
+    apitree::set_retention(&config.inner.parser_state);
+    deadletter::set_capacity(config.inner.dead_letter_size);
+    apitree::restapi::set_timeout(config.inner.rest_timeout_secs);
+    exchange::poller::set_breaker(config.inner.rest_breaker.clone());
+    status::set_degraded_factor(config.inner.degraded_factor);
+    status::set_maintenance_policy(config.inner.maintenance);
+    tokio::spawn(status::watch());
+    chaos::init(config.inner.chaos.take().unwrap_or_default());
+    audit::init(config.inner.audit.take().unwrap_or_default());
+    let bridge_books = opportunity::bridge::start(config.inner.rates);
+    if let Some(setting) = &config.inner.time_sync {
+        apitree::clock::start(config.inner.exchange_pair_map.keys(), setting);
+    }
+    apitree::index::start(&config.inner.index_sources);
+    if let Some(setting) = &config.inner.symbol_meta {
+        apitree::symbol_meta::start(setting, &config.inner.exchange_pair_map);
+    }
+    if let Some(poll_secs) = config.inner.ticker_poll_secs {
+        apitree::ticker::start(&config.inner.exchange_pair_map, poll_secs);
+    }
+    if let Some(setting) = config.inner.leadlag {
+        analytics::leadlag::init(setting);
+    }
+    if let Some(setting) = config.inner.contribution {
+        analytics::contribution::init(setting);
+    }
+    if config.inner.level_ages {
+        analytics::ages::init();
+    }
+    if let Some(setting) = config.inner.portfolio {
+        opportunity::portfolio::init(setting);
+    }
+    let (synthetic, soak) = match config.synthetic.take() {
+        Some(spec) => {
+            let (tx, rx) = oneshot::channel();
+            (Some((spec, tx)), Some(rx))
+        }
+        None => (None, None),
+    };
+    let pairs: Arc<HashMap<String, String>> = Arc::new(
+        config
+            .inner
+            .exchange_pair_map
+            .iter()
+            .filter_map(|(e, settings)| Some((e.clone(), settings.first()?.pair.clone())))
+            .collect(),
+    );
+    let mut router = pipeline::GroupRouter::new(pairs.clone());
+    let mut groups = HashMap::new();
+    for (name, setting) in config.inner.groups {
+        let (tx, _) = broadcast::channel::<ByteString>(100);
+        let cache = router.add(setting.exchanges.clone());
+        let detector = setting
+            .detector
+            .clone()
+            .unwrap_or_else(|| config.inner.detector.clone());
+        let mut exchanges: Vec<String> = setting.exchanges.keys().cloned().collect();
+        exchanges.sort();
+        info!("group {}: {:?}", name, exchanges);
+        tokio::spawn(aggregate_group(
+            name.clone(),
+            setting,
+            detector,
+            cache,
+            tx.clone(),
+        ));
+        groups.insert(name, Group { tx, exchanges });
+    }
+    let groups = Groups(Arc::new(groups));
+    let books = DeepBooks {
+        cache: pipeline::BookCache::default(),
+        pairs: pairs.clone(),
+        max_depth: config.inner.deep_snapshot_max_depth,
+    };
+    tokio::spawn(setup_marketdata(
+        config.inner.exchange_pair_map,
+        btx.clone(),
+        stx,
+        bridge_books,
+        config.inner.detector.clone(),
+        pair.clone(),
+        config.inner.volatility,
+        config.inner.warmup,
+        config.inner.pipeline_ms,
+        config.inner.fail_fast,
+        config.inner.consolidated_levels,
+        config.inner.output_precision,
+        books.cache.clone(),
+        bbo.0.clone().map(|tx| pipeline::BboTicker::new(tx, pairs)),
+        router,
+        config.inner.guardrails,
+        redistributable.clone(),
+        synthetic,
+    ));
+
+    // websocket server for broadcasting states
+    let mut handle = Handle {
+        addrs: vec![],
+        servers: vec![],
+        soak,
+    };
+    for mut listener in bound.tcp {
+        let (server, addrs) = match &redistributable {
+            // the groups aren't filtered, they aren't served
+            Some(feeds) if listener.setting.redistributable_only => serve(
+                listener,
+                feeds.btx.clone(),
+                books.without(&feeds.denied),
+                BboFeed(feeds.bbo.clone()),
+                Groups::default(),
+                token.clone(),
+            )?,
+            _ => {
+                listener.setting.redistributable_only = false;
+                serve(
+                    listener,
+                    btx.clone(),
+                    books.clone(),
+                    bbo.clone(),
+                    groups.clone(),
+                    token.clone(),
+                )?
+            }
+        };
+        handle.servers.push(server);
+        handle.addrs.extend(addrs);
+    }
+    Ok(handle)
+}
+
+// the routes of the groups a listener serves. Admin routes are refused
+// with 403 on the others, so a client can tell them from a typo.
+fn routes(cfg: &mut web::ServiceConfig, listener: &ListenerSetting) {
+    if listener.serves(RouteGroup::Public) {
+        cfg.service(websocket)
+            .service(group_websocket)
+            .service(snapshot)
+            .service(health)
+            .service(server_info)
+            .service(exchange_list)
+            .service(simulation_report)
+            .service(opportunities)
+            .service(leadlag)
+            .service(contribution)
+            .service(debug_timings)
+            .service(metrics);
+    }
+    if listener.serves(RouteGroup::Admin) {
+        cfg.service(simulation_reset)
+            .service(chaos_trigger)
+            .service(pair_update)
+            .service(parse_errors)
+            .service(effective_config);
+    } else {
+        for path in ["/simulation/reset", "/admin/chaos", "/admin/pairs"] {
+            cfg.route(
+                path,
+                web::post().to(|| async { HttpResponse::Forbidden().finish() }),
+            );
+        }
+        for path in ["/debug/parse_errors", "/config"] {
+            cfg.route(
+                path,
+                web::get().to(|| async { HttpResponse::Forbidden().finish() }),
+            );
+        }
+    }
+}
+
+fn tls_config(setting: &TlsSetting) -> Result<rustls::ServerConfig> {
+    let open = |path: &str| {
+        std::fs::File::open(path)
+            .map(std::io::BufReader::new)
+            .map_err(|e| anyhow!("{}: {}", path, e))
+    };
+    let certs = rustls_pemfile::certs(&mut open(&setting.cert_path)?)?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    let key = rustls_pemfile::read_all(&mut open(&setting.key_path)?)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("no private key in {}", setting.key_path))?;
+    Ok(rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?)
+}
+
+// A listener's sockets, bound before the pipeline starts, with its tls
+// config loaded.
+struct Bound {
+    setting: ListenerSetting,
+    sockets: Vec<std::net::TcpListener>,
+    tls: Option<rustls::ServerConfig>,
+}
+
+// every socket the instance serves on
+pub struct Listeners {
+    tcp: Vec<Bound>,
+    #[cfg(unix)]
+    unix: Option<tokio::net::UnixListener>,
+}
+
+// every address the listener resolves to, like HttpServer::bind
+fn bind(listener: &ListenerSetting) -> Result<Bound> {
+    use std::net::ToSocketAddrs;
+    let addr = format!("{}:{}", listener.addr, listener.port);
+    let resolved = (listener.addr.as_str(), listener.port)
+        .to_socket_addrs()
+        .map_err(|e| anyhow!("cannot resolve {}: {}", addr, e))?;
+    let mut sockets = vec![];
+    for resolved in resolved {
+        let socket = std::net::TcpListener::bind(resolved)
+            .map_err(|e| anyhow!("cannot bind {}: {}", resolved, e))?;
+        sockets.push(socket);
+    }
+    if sockets.is_empty() {
+        return Err(anyhow!("{} resolves to no address", addr));
+    }
+    let tls = match &listener.tls {
+        Some(tls) => Some(tls_config(tls).map_err(|e| anyhow!("tls of {}: {}", addr, e))?),
+        None => None,
+    };
+    Ok(Bound {
+        setting: listener.clone(),
+        sockets,
+        tls,
+    })
+}
+
+pub fn bind_all(config: &config::InnerConfig) -> Result<Listeners> {
+    let tcp = config
+        .listeners()
+        .iter()
+        .map(bind)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Listeners {
+        tcp,
+        #[cfg(unix)]
+        unix: match &config.output.unix_socket_path {
+            Some(path) => Some(sink::unix::bind(path, config.output.unix_socket_mode)?),
+            None => None,
+        },
+    })
+}
+
+// One server per listener, since the routes differ between them. Returns
+// the bound addresses too, port 0 picks a free one.
+fn serve(
+    listener: Bound,
+    btx: broadcast::Sender<ByteString>,
+    books: DeepBooks,
+    bbo: BboFeed,
+    groups: Groups,
+    token: AdminToken,
+) -> Result<(actix_web::dev::Server, Vec<std::net::SocketAddr>)> {
+    let setting = listener.setting.clone();
+    let latest = match setting.redistributable_only {
+        true => MainTopic(Topic::Redistributable),
+        false => MainTopic(Topic::Main),
+    };
+    let mut server = HttpServer::new(move || {
+        App::new()
+            .app_data(btx.clone())
+            .app_data(latest.clone())
+            .app_data(books.clone())
+            .app_data(bbo.clone())
+            .app_data(groups.clone())
+            .app_data(token.clone())
+            .configure(|cfg| routes(cfg, &setting))
+            .wrap(middleware::Logger::default())
+    });
+    for socket in listener.sockets {
+        server = match &listener.tls {
+            Some(tls) => server.listen_rustls_0_21(socket, tls.clone()),
+            None => server.listen(socket),
+        }?;
+    }
+    let addrs = server.addrs();
+    info!("listening on {:?}", addrs);
+    Ok((server.run(), addrs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bind, cached, group_cached, info, restore_summary, serve, status, AdminToken, BboFeed,
+        DeepBooks, Group, Groups, LAGGED_CLOSE_CODE,
+    };
+    use crate::config::{
+        Config, DetectorSetting, GroupSetting, ListenerSetting, PersistSetting, RouteGroup,
+    };
+    use crate::deadletter;
+    use crate::orderbook::{AggregatedOrderbook, Level, Orderbook, Side};
+    use crate::pipeline::{self, aggregate_group, publish_summary, SKIPPED_NO_SUBSCRIBERS};
+    use crate::snapshots::{self, Snapshot, Topic};
+    use actix_web_actors::ws;
+    use bytestring::ByteString;
+    use futures_util::{SinkExt, StreamExt};
+    use std::cell::Cell;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use tokio::sync::broadcast;
+    use tokio::time::{timeout, Duration, Instant};
+
+    // the admin token of the listeners of the tests
+    const TOKEN: &str = "t0ken";
+
+    // binds and serves at once, as main does in two phases
+    fn listen(
+        listener: &ListenerSetting,
+        btx: broadcast::Sender<ByteString>,
+        books: DeepBooks,
+        bbo: BboFeed,
+        groups: Groups,
+    ) -> anyhow::Result<(actix_web::dev::Server, Vec<std::net::SocketAddr>)> {
+        let token = AdminToken(Some(TOKEN.into()));
+        serve(bind(listener)?, btx, books, bbo, groups, token)
+    }
+
+    thread_local! {
+        static ERRORS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // counts the error records logged by the calling test thread
+    struct ErrorCounter;
+
+    impl log::Log for ErrorCounter {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &log::Record) {
+            if record.level() == log::Level::Error {
+                ERRORS.with(|e| e.set(e.get() + 1));
+            }
+        }
+        fn flush(&self) {}
+    }
+
+    // a summary of json only, like the restored one
+    fn json(summary: &str) -> Snapshot {
+        Snapshot::new(ByteString::from(summary.to_string()), None)
+    }
+
+    // the tests below share the snapshot of the main aggregate
+    static CACHE_TEST: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[test]
+    fn test_publish_without_subscribers() {
+        let _guard = CACHE_TEST.lock().unwrap();
+        static COUNTER: ErrorCounter = ErrorCounter;
+        let _ = log::set_logger(&COUNTER);
+        log::set_max_level(log::LevelFilter::Trace);
+        let (btx, rx) = broadcast::channel::<ByteString>(10);
+        drop(rx);
+        let skipped = SKIPPED_NO_SUBSCRIBERS.load(Ordering::Relaxed);
+        for i in 0..3 {
+            publish_summary(&btx, json(&format!(r#"{{"spread":"{}"}}"#, i)));
+        }
+        assert_eq!(ERRORS.with(|e| e.get()), 0);
+        assert!(SKIPPED_NO_SUBSCRIBERS.load(Ordering::Relaxed) >= skipped + 3);
+        // still cached for the next session
+        assert_eq!(cached().unwrap(), r#"{"spread":"2"}"#);
+
+        let mut rx = btx.subscribe();
+        publish_summary(&btx, json(r#"{"spread":"3"}"#));
+        assert_eq!(rx.try_recv().unwrap(), r#"{"spread":"3"}"#);
+        assert_eq!(ERRORS.with(|e| e.get()), 0);
+    }
+
+    #[test]
+    fn test_restored_summary() {
+        let _guard = CACHE_TEST.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.json");
+        std::fs::write(&path, r#"{"spread":"1"}"#).unwrap();
+        let setting = PersistSetting {
+            snapshot_path: path.to_str().unwrap().to_string(),
+            max_age_secs: 60,
+            write_interval_secs: 5,
+        };
+        let restored = restore_summary(&setting).unwrap();
+        assert_eq!(cached().unwrap(), restored);
+        assert!(restored.contains(r#""stale":true"#));
+        assert!(restored.contains(r#""restored_from":"#));
+
+        // the first live summary clears the marker
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        publish_summary(&btx, json(r#"{"spread":"2"}"#));
+        assert_eq!(cached().unwrap(), r#"{"spread":"2"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_listeners() {
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        let listener = |routes| ListenerSetting {
+            addr: "127.0.0.1".to_string(),
+            port: 0,
+            tls: None,
+            routes,
+            redistributable_only: false,
+        };
+        let (local, local_addrs) = listen(
+            &listener(None),
+            btx.clone(),
+            books(),
+            BboFeed(None),
+            Groups::default(),
+        )
+        .unwrap();
+        let (lan, lan_addrs) = listen(
+            &listener(Some(vec![RouteGroup::Public])),
+            btx,
+            books(),
+            BboFeed(None),
+            Groups::default(),
+        )
+        .unwrap();
+        let (local_handle, lan_handle) = (local.handle(), lan.handle());
+        tokio::spawn(local);
+        tokio::spawn(lan);
+
+        let client = reqwest::Client::new();
+        for addr in [local_addrs[0], lan_addrs[0]] {
+            let response = client
+                .get(format!("http://{}/summary", addr))
+                .send()
+                .await
+                .unwrap();
+            // 204 before the first summary, or the summary of another test
+            assert!(response.status().is_success());
+        }
+        let reset = |addr| {
+            client
+                .post(format!("http://{}/simulation/reset", addr))
+                .bearer_auth(TOKEN)
+                .send()
+        };
+        let timings: serde_json::Value = client
+            .get(format!("http://{}/debug/timings", lan_addrs[0]))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(timings["window_secs"], 60);
+        assert!(timings["stages"]["serialize"]["p99_us"].is_u64());
+        // simulation disabled, but routed
+        assert_eq!(reset(local_addrs[0]).await.unwrap().status(), 404);
+        assert_eq!(reset(lan_addrs[0]).await.unwrap().status(), 403);
+        // the admin routes need the token
+        for path in ["/simulation/reset", "/admin/chaos", "/admin/pairs"] {
+            let response = client
+                .post(format!("http://{}{}", local_addrs[0], path))
+                .json(&serde_json::json!({"exchange": "btcmarkets", "remove": "BTC-AUD"}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 401, "{}", path);
+        }
+
+        deadletter::push("dl_listener", "kraken: short message".to_string(), "[1]", 1);
+        let parse_errors = |addr| {
+            client
+                .get(format!(
+                    "http://{}/debug/parse_errors?exchange=dl_listener",
+                    addr
+                ))
+                .send()
+        };
+        let report: serde_json::Value = parse_errors(local_addrs[0])
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            report["entries"],
+            serde_json::json!([{"exchange": "dl_listener", "timestamp": 1,
+                "error": "kraken: short message", "raw": "[1]"}])
+        );
+        assert_eq!(parse_errors(lan_addrs[0]).await.unwrap().status(), 403);
+        let metrics = client
+            .get(format!("http://{}/metrics", lan_addrs[0]))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(metrics.contains("arb_monitor_parse_errors_total{exchange=\"dl_listener\"} 1"));
+
+        local_handle.stop(false).await;
+        lan_handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_redistributable_listener() {
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        let setting = ListenerSetting {
+            addr: "127.0.0.1".to_string(),
+            port: 0,
+            tls: None,
+            routes: None,
+            redistributable_only: true,
+        };
+        let denied = HashSet::from(["kraken".to_string()]);
+        let (server, addrs) = listen(
+            &setting,
+            btx,
+            books().without(&denied),
+            BboFeed(None),
+            Groups::default(),
+        )
+        .unwrap();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        snapshots::store(
+            Topic::Redistributable,
+            json(r#"{"spread":"1","timestamp":{"bitstamp":"1"}}"#),
+        );
+        let summary: serde_json::Value = reqwest::get(format!("http://{}/summary", addrs[0]))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(summary["timestamp"], serde_json::json!({"bitstamp": "1"}));
+        // the first frame is the filtered summary too
+        let (_, mut conn) = awc::Client::new()
+            .ws(format!("ws://{}/ws", addrs[0]))
+            .connect()
+            .await
+            .unwrap();
+        match conn.next().await.unwrap().unwrap() {
+            awc::ws::Frame::Text(text) => {
+                assert_eq!(
+                    serde_json::from_slice::<serde_json::Value>(&text).unwrap(),
+                    summary
+                )
+            }
+            frame => panic!("{:?}", frame),
+        }
+        // nor the book of a denied exchange
+        assert!(books()
+            .without(&denied)
+            .snapshot("kraken", None, None)
+            .is_err());
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_server_info() {
+        let mut config = Config {
+            config_path: "src/test_resource/config.yaml".to_string(),
+            print_config: false,
+            command: None,
+            synthetic: None,
+            inner: Default::default(),
+        };
+        config.load().unwrap();
+        info::init(info::server_info(&config.inner));
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        let setting = ListenerSetting {
+            addr: "127.0.0.1".to_string(),
+            port: 0,
+            tls: None,
+            routes: None,
+            redistributable_only: false,
+        };
+        let (server, addrs) =
+            listen(&setting, btx, books(), BboFeed(None), Groups::default()).unwrap();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let rest: serde_json::Value = reqwest::get(format!("http://{}/info", addrs[0]))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let (_, mut conn) = awc::Client::new()
+            .ws(format!("ws://{}/ws", addrs[0]))
+            .connect()
+            .await
+            .unwrap();
+        conn.send(awc::ws::Message::Text(r#"{"op":"hello"}"#.into()))
+            .await
+            .unwrap();
+        let hello = next_op(&mut conn, "hello").await;
+        assert_eq!(hello["info"], rest);
+        assert_eq!(rest["features"]["msgpack"], true);
+        assert_eq!(rest["features"]["redistribution_filter"], true);
+        assert_eq!(rest["pairs"]["bitstamp"], serde_json::json!(["btcusd"]));
+        // the same pairs, no metadata without symbol_meta
+        let listed: serde_json::Value = reqwest::get(format!("http://{}/exchanges", addrs[0]))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(listed["bitstamp"], serde_json::json!([{"pair": "btcusd"}]));
+        handle.stop(false).await;
+    }
+
+    fn books() -> DeepBooks {
+        DeepBooks {
+            cache: pipeline::BookCache::default(),
+            pairs: Arc::new(HashMap::from([(
+                "kraken".to_string(),
+                "XBT/USD".to_string(),
+            )])),
+            max_depth: 3,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_deep_snapshot() {
+        let books = books();
+        let mut book = Orderbook::new("kraken");
+        for i in 0..5 {
+            book.insert(Side::Bid, (100 - i).into(), 1.into());
+            book.insert(Side::Ask, (101 + i).into(), 2.into());
+        }
+        book.set_timestamp(42);
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        let setting = ListenerSetting {
+            addr: "127.0.0.1".to_string(),
+            port: 0,
+            tls: None,
+            routes: None,
+            redistributable_only: false,
+        };
+        let (server, addrs) = listen(
+            &setting,
+            btx,
+            books.clone(),
+            BboFeed(None),
+            Groups::default(),
+        )
+        .unwrap();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let (_, mut conn) = awc::Client::new()
+            .ws(format!("ws://{}/ws", addrs[0]))
+            .connect()
+            .await
+            .unwrap();
+        // the reply to a request, past the summary sent on connect
+        let mut ask = async |request: &str| -> serde_json::Value {
+            conn.send(awc::ws::Message::Text(request.to_string().into()))
+                .await
+                .unwrap();
+            loop {
+                if let awc::ws::Frame::Text(text) = conn.next().await.unwrap().unwrap() {
+                    let reply: serde_json::Value = serde_json::from_slice(&text).unwrap();
+                    if reply["op"] == "deep_snapshot" {
+                        return reply;
+                    }
+                }
+            }
+        };
+
+        let request = r#"{"op":"deep_snapshot","exchange":"kraken","pair":"XBT/USD","depth":2}"#;
+        let reply = ask(request).await;
+        assert!(reply["error"].as_str().unwrap().contains("no book"));
+        books.cache.update(
+            "kraken".to_string(),
+            Arc::new(book),
+            tokio::time::Instant::now(),
+        );
+        assert_eq!(
+            ask(request).await,
+            serde_json::json!({"op": "deep_snapshot", "exchange": "kraken", "pair": "XBT/USD",
+                "depth": 2, "timestamp": 42,
+                "bids": [["100", "1"], ["99", "1"]], "asks": [["101", "2"], ["102", "2"]]})
+        );
+        // capped at max_depth
+        let reply = ask(r#"{"op":"deep_snapshot","exchange":"kraken","depth":100}"#).await;
+        assert_eq!(reply["depth"], 3);
+        assert_eq!(reply["bids"].as_array().unwrap().len(), 3);
+        assert_eq!(reply["asks"][2], serde_json::json!(["103", "2"]));
+
+        let reply = ask(r#"{"op":"deep_snapshot","exchange":"nosuch"}"#).await;
+        assert_eq!(reply["error"], "unknown exchange nosuch");
+        let reply = ask(r#"{"op":"deep_snapshot","exchange":"kraken","pair":"ETH/USD"}"#).await;
+        assert_eq!(reply["error"], "unknown pair ETH/USD on kraken");
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_effective_config() {
+        let mut config = Config {
+            config_path: "src/test_resource/config.yaml".to_string(),
+            print_config: false,
+            command: None,
+            synthetic: None,
+            inner: Default::default(),
+        };
+        config.load().unwrap();
+        let first = config.publish().unwrap();
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        let listener = |routes| ListenerSetting {
+            addr: "127.0.0.1".to_string(),
+            port: 0,
+            tls: None,
+            routes,
+            redistributable_only: false,
+        };
+        let (admin, admin_addrs) = listen(
+            &listener(None),
+            btx.clone(),
+            books(),
+            BboFeed(None),
+            Groups::default(),
+        )
+        .unwrap();
+        let (public, public_addrs) = listen(
+            &listener(Some(vec![RouteGroup::Public])),
+            btx,
+            books(),
+            BboFeed(None),
+            Groups::default(),
+        )
+        .unwrap();
+        let (admin_handle, public_handle) = (admin.handle(), public.handle());
+        actix_web::rt::spawn(admin);
+        actix_web::rt::spawn(public);
+        let client = awc::Client::new();
+        let url = format!("http://{}/config", admin_addrs[0]);
+        let get = async || -> serde_json::Value {
+            client
+                .get(&url)
+                .bearer_auth(TOKEN)
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap()
+        };
+
+        let served = get().await;
+        assert_eq!(served["config_path"], "src/test_resource/config.yaml");
+        assert_eq!(served["config"]["alerts"]["telegram"]["bot_token"], "***");
+        assert_eq!(served["config"]["degraded_factor"], 8.);
+        // a reload is what the endpoint shows next
+        config.inner.degraded_factor = 4.;
+        config.publish().unwrap();
+        let served = get().await;
+        assert_eq!(served["config"]["degraded_factor"], 4.);
+        assert_eq!(served["reloads"], first.reloads + 1);
+
+        let response = client
+            .get(format!("http://{}/config", public_addrs[0]))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+
+        // no token, or another one
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client.get(&url).bearer_auth("t0ke").send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        // nothing is authorized without a token configured
+        let (unset, unset_addrs) = serve(
+            bind(&listener(None)).unwrap(),
+            broadcast::channel::<ByteString>(10).0,
+            books(),
+            BboFeed(None),
+            Groups::default(),
+            AdminToken(None),
+        )
+        .unwrap();
+        let unset_handle = unset.handle();
+        actix_web::rt::spawn(unset);
+        let response = client
+            .get(format!("http://{}/config", unset_addrs[0]))
+            .bearer_auth(TOKEN)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+        admin_handle.stop(false).await;
+        public_handle.stop(false).await;
+        unset_handle.stop(false).await;
+    }
+
+    // the next frame of an op, past the summary sent on connect
+    async fn next_op(
+        conn: &mut actix_codec::Framed<awc::BoxedSocket, awc::ws::Codec>,
+        op: &str,
+    ) -> serde_json::Value {
+        loop {
+            if let awc::ws::Frame::Text(text) = conn.next().await.unwrap().unwrap() {
+                let frame: serde_json::Value = serde_json::from_slice(&text).unwrap();
+                if frame["op"] == op {
+                    return frame;
+                }
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_bbo_subscription() {
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        let (ticks, _) = broadcast::channel::<ByteString>(10);
+        let setting = ListenerSetting {
+            addr: "127.0.0.1".to_string(),
+            port: 0,
+            tls: None,
+            routes: None,
+            redistributable_only: false,
+        };
+        let (server, addrs) = listen(
+            &setting,
+            btx,
+            books(),
+            BboFeed(Some(ticks.clone())),
+            Groups::default(),
+        )
+        .unwrap();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let connect = || {
+            awc::Client::new()
+                .ws(format!("ws://{}/ws", addrs[0]))
+                .connect()
+        };
+        let (_, mut subscribed) = connect().await.unwrap();
+        let (_, mut other) = connect().await.unwrap();
+        subscribed
+            .send(awc::ws::Message::Text(r#"{"op":"subscribe_bbo"}"#.into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_op(&mut subscribed, "subscribe_bbo").await["subscribed"],
+            true
+        );
+        let tick = r#"{"op":"bbo","exchange":"kraken","seq":1}"#;
+        ticks.send(ByteString::from_static(tick)).unwrap();
+        assert_eq!(next_op(&mut subscribed, "bbo").await["seq"], 1);
+        // without the subscribe only the summaries come
+        assert!(
+            timeout(Duration::from_millis(300), next_op(&mut other, "bbo"))
+                .await
+                .is_err()
+        );
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_status_subscription() {
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        let setting = ListenerSetting {
+            addr: "127.0.0.1".to_string(),
+            port: 0,
+            tls: None,
+            routes: None,
+            redistributable_only: false,
+        };
+        let (server, addrs) =
+            listen(&setting, btx, books(), BboFeed(None), Groups::default()).unwrap();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let connect = || {
+            awc::Client::new()
+                .ws(format!("ws://{}/ws", addrs[0]))
+                .connect()
+        };
+        status::connected("status_ws");
+        let (_, mut subscribed) = connect().await.unwrap();
+        let (_, mut other) = connect().await.unwrap();
+        subscribed
+            .send(awc::ws::Message::Text(
+                r#"{"op":"subscribe_status"}"#.into(),
+            ))
+            .await
+            .unwrap();
+        // the state the events are transitions of comes first
+        let dump = next_op(&mut subscribed, "subscribe_status").await;
+        assert_eq!(dump["subscribed"], true);
+        assert_eq!(dump["exchanges"]["status_ws"]["connected"], true);
+        assert!(dump["health"]["status"].is_string());
+
+        // the events of the other tests come through too
+        async fn next_event(
+            conn: &mut actix_codec::Framed<awc::BoxedSocket, awc::ws::Codec>,
+        ) -> serde_json::Value {
+            loop {
+                let event = next_op(conn, "status").await;
+                if event["exchange"] == "status_ws" {
+                    return event;
+                }
+            }
+        }
+        status::disconnected("status_ws");
+        // a level, not sent again
+        status::disconnected("status_ws");
+        status::connected("status_ws");
+        assert_eq!(next_event(&mut subscribed).await["state"], "disconnected");
+        let event = next_event(&mut subscribed).await;
+        assert_eq!(event["state"], "connected");
+        assert!(event["ts"].as_u64().unwrap() > 0);
+        // without the subscribe only the summaries come
+        assert!(
+            timeout(Duration::from_millis(300), next_op(&mut other, "status"))
+                .await
+                .is_err()
+        );
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_lag_policies() {
+        let (btx, _) = broadcast::channel::<ByteString>(2);
+        let setting = ListenerSetting {
+            addr: "127.0.0.1".to_string(),
+            port: 0,
+            tls: None,
+            routes: None,
+            redistributable_only: false,
+        };
+        let (server, addrs) = listen(
+            &setting,
+            btx.clone(),
+            books(),
+            BboFeed(None),
+            Groups::default(),
+        )
+        .unwrap();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let mut clients = vec![];
+        for on_lag in ["skip", "gap_marker", "disconnect"] {
+            let (_, mut client) = awc::Client::new()
+                .ws(format!("ws://{}/ws", addrs[0]))
+                .connect()
+                .await
+                .unwrap();
+            client
+                .send(awc::ws::Message::Text(
+                    format!(r#"{{"op":"subscribe","on_lag":"{}"}}"#, on_lag).into(),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(next_op(&mut client, "subscribe").await["on_lag"], on_lag);
+            clients.push(client);
+        }
+        // far faster than the sessions, on their worker threads, can send
+        // them on: they lag, at least once
+        const SENT: u64 = 1000;
+        for seq in 1..=SENT {
+            let _ = btx.send(ByteString::from(format!(r#"{{"seq":{}}}"#, seq)));
+        }
+        async fn next_frame(
+            conn: &mut actix_codec::Framed<awc::BoxedSocket, awc::ws::Codec>,
+        ) -> awc::ws::Frame {
+            timeout(Duration::from_secs(5), conn.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap()
+        }
+        let text = |frame: awc::ws::Frame| match frame {
+            awc::ws::Frame::Text(text) => {
+                serde_json::from_slice::<serde_json::Value>(&text).unwrap()
+            }
+            frame => panic!("{:?}", frame),
+        };
+        let mut disconnect = clients.pop().unwrap();
+        let mut gap_marker = clients.pop().unwrap();
+        let mut skip = clients.pop().unwrap();
+
+        // only summaries, in order, up to the last one
+        let (mut last, mut received) = (0, 0);
+        while last < SENT {
+            let seq = text(next_frame(&mut skip).await)["seq"].as_u64().unwrap();
+            assert!(seq > last, "{} after {}", seq, last);
+            (last, received) = (seq, received + 1);
+        }
+        assert!(received < SENT);
+
+        // every gap is announced, with the seq it resumes at
+        let (mut last, mut gaps) = (0, 0);
+        while last < SENT {
+            let frame = text(next_frame(&mut gap_marker).await);
+            let seq = frame["seq"].as_u64();
+            if frame["op"] == "gap" {
+                let resume_seq = frame["resume_seq"].as_u64().unwrap();
+                assert_eq!(frame["missed"].as_u64().unwrap(), resume_seq - last - 1);
+                let next = text(next_frame(&mut gap_marker).await);
+                assert_eq!(next["seq"].as_u64(), Some(resume_seq));
+                (last, gaps) = (resume_seq, gaps + 1);
+            } else {
+                assert_eq!(seq, Some(last + 1));
+                last += 1;
+            }
+        }
+        assert!(gaps > 0);
+
+        // summaries until the lag, then the close
+        loop {
+            match next_frame(&mut disconnect).await {
+                awc::ws::Frame::Text(_) => continue,
+                awc::ws::Frame::Close(Some(reason)) => {
+                    assert_eq!(reason.code, ws::CloseCode::Other(LAGGED_CLOSE_CODE));
+                    break;
+                }
+                frame => panic!("{:?}", frame),
+            }
+        }
+        handle.stop(false).await;
+    }
+
+    #[test]
+    fn test_shared_payload() {
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        let mut first = btx.subscribe();
+        let mut second = btx.subscribe();
+        let summary = ByteString::from(r#"{"spread":"1"}"#.to_string());
+        let ptr = summary.as_ptr();
+        btx.send(summary).unwrap();
+        // every receiver and the frame built from it point at the one buffer
+        for rx in [&mut first, &mut second] {
+            match ws::Message::Text(rx.try_recv().unwrap()) {
+                ws::Message::Text(s) => assert_eq!(s.as_ptr(), ptr),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_groups_share_feed() {
+        let pairs = Arc::new(HashMap::from([
+            ("kraken".to_string(), "XBT/USD".to_string()),
+            ("gemini".to_string(), "btcusd".to_string()),
+            ("coinjar".to_string(), "BTCAUD".to_string()),
+        ]));
+        let group = |exchanges: &[(&str, &str)]| GroupSetting {
+            exchanges: exchanges
+                .iter()
+                .map(|(e, p)| (e.to_string(), p.to_string()))
+                .collect(),
+            detector: None,
+            consolidated_levels: None,
+            output_precision: None,
+        };
+        let mut router = pipeline::GroupRouter::new(pairs);
+        let mut groups = HashMap::new();
+        let mut tasks = vec![];
+        for (name, setting) in [
+            (
+                "grp_usd",
+                group(&[("kraken", "XBT/USD"), ("gemini", "btcusd")]),
+            ),
+            (
+                "grp_aud",
+                group(&[("kraken", "XBT/USD"), ("coinjar", "BTCAUD")]),
+            ),
+            (
+                "grp_kraken_aud",
+                group(&[("kraken", "XBT/AUD"), ("coinjar", "BTCAUD")]),
+            ),
+        ] {
+            let (tx, _) = broadcast::channel::<ByteString>(10);
+            let cache = router.add(setting.exchanges.clone());
+            let exchanges = setting.exchanges.keys().cloned().collect();
+            tasks.push(tokio::spawn(aggregate_group(
+                name.to_string(),
+                setting,
+                DetectorSetting::default(),
+                cache,
+                tx.clone(),
+            )));
+            groups.insert(name.to_string(), Group { tx, exchanges });
+        }
+        let (tx, rx) = pipeline::ingest_channel(100);
+        let cache = pipeline::BookCache::default();
+        let ingested = tokio::spawn(pipeline::ingest(
+            rx,
+            cache.clone(),
+            HashMap::new(),
+            None,
+            router,
+        ));
+        for (i, exchange) in ["kraken", "gemini", "coinjar"].iter().enumerate() {
+            let mut book = Orderbook::new(exchange);
+            book.insert(Side::Bid, (100 + i as i32).into(), 1.into());
+            book.insert(Side::Ask, (110 + i as i32).into(), 1.into());
+            tx.send((exchange.to_string(), Arc::new(book), Instant::now()))
+                .unwrap();
+        }
+        // a pair no group aggregates
+        let mut book = Orderbook::new("gemini");
+        book.set_pair("ethusd");
+        book.insert(Side::Bid, 3.into(), 1.into());
+        book.insert(Side::Ask, 4.into(), 1.into());
+        tx.send(("gemini".to_string(), Arc::new(book), Instant::now()))
+            .unwrap();
+        // a second pair on kraken's connection, for its group only
+        let mut book = Orderbook::new("kraken");
+        book.set_pair("XBT/AUD");
+        book.insert(Side::Bid, 150.into(), 1.into());
+        book.insert(Side::Ask, 160.into(), 1.into());
+        tx.send(("kraken".to_string(), Arc::new(book), Instant::now()))
+            .unwrap();
+        drop(tx);
+        ingested.await.unwrap();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // the main aggregate has the main pair of every exchange, each
+        // group only its own
+        let (main, _) = cache.snapshot();
+        assert_eq!(main.len(), 3);
+        let mut agg = AggregatedOrderbook::new();
+        pipeline::merge(&mut agg, &main);
+        let summary = agg.finalize().unwrap();
+        let prices = |levels: &[Level]| -> Vec<(String, String)> {
+            levels
+                .iter()
+                .map(|l| (l.exchange.clone(), l.price.clone()))
+                .collect()
+        };
+        assert_eq!(
+            prices(&summary.bids),
+            [("coinjar", "102"), ("gemini", "101"), ("kraken", "100")]
+                .map(|(e, p)| (e.to_string(), p.to_string()))
+        );
+        assert_eq!(
+            prices(&summary.asks),
+            [("kraken", "110"), ("gemini", "111"), ("coinjar", "112")]
+                .map(|(e, p)| (e.to_string(), p.to_string()))
+        );
+        let venues = |group: &str| {
+            let summary: serde_json::Value =
+                serde_json::from_str(&group_cached(group).unwrap()).unwrap();
+            let bids = summary["bids"].clone();
+            let mut venues: Vec<String> = summary["timestamp"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect();
+            venues.sort();
+            (venues, bids)
+        };
+        let (usd, usd_bids) = venues("grp_usd");
+        let (aud, aud_bids) = venues("grp_aud");
+        assert_eq!(usd, ["gemini", "kraken"]);
+        assert_eq!(aud, ["coinjar", "kraken"]);
+        assert_eq!(usd_bids[0]["exchange"], "gemini");
+        assert_eq!(usd_bids[0]["price"], "101");
+        assert_eq!(aud_bids[0]["exchange"], "coinjar");
+        let (kraken_aud, kraken_aud_bids) = venues("grp_kraken_aud");
+        assert_eq!(kraken_aud, ["coinjar", "kraken"]);
+        assert_eq!(kraken_aud_bids[0]["exchange"], "kraken");
+        assert_eq!(kraken_aud_bids[0]["price"], "150");
+
+        let setting = ListenerSetting {
+            addr: "127.0.0.1".to_string(),
+            port: 0,
+            tls: None,
+            routes: None,
+            redistributable_only: false,
+        };
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        let (server, addrs) = listen(
+            &setting,
+            btx,
+            books(),
+            BboFeed(None),
+            Groups(Arc::new(groups)),
+        )
+        .unwrap();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let client = awc::Client::new();
+        let mut res = client
+            .get(format!("http://{}/summary?group=grp_aud", addrs[0]))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.body().await.unwrap(),
+            group_cached("grp_aud").unwrap().as_bytes()
+        );
+        for path in [
+            "/summary?group=nope",
+            "/health?group=nope",
+            "/ws/group/nope",
+        ] {
+            let res = client
+                .get(format!("http://{}{}", addrs[0], path))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), 404, "{}", path);
+        }
+        let (_, mut conn) = client
+            .ws(format!("ws://{}/ws/group/grp_usd", addrs[0]))
+            .connect()
+            .await
+            .unwrap();
+        // the group's cached summary on connect
+        match timeout(Duration::from_secs(5), conn.next()).await {
+            Ok(Some(Ok(ws::Frame::Text(text)))) => {
+                assert_eq!(text, group_cached("grp_usd").unwrap().as_bytes())
+            }
+            other => panic!("{:?}", other),
+        }
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_topic_snapshots() {
+        let mut book = Orderbook::new("kraken");
+        book.insert(Side::Bid, 99.into(), 1.into());
+        book.insert(Side::Ask, 101.into(), 2.into());
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&book);
+        let summary = Arc::new(agg.finalize().unwrap());
+        let text = ByteString::from(serde_json::to_string(&*summary).unwrap());
+        let latest = Snapshot::new(text.clone(), Some(summary));
+        snapshots::store(Topic::Group("topic_eth".to_string()), latest);
+        let mut groups = HashMap::new();
+        for name in ["topic_eth", "topic_sol"] {
+            let (tx, _) = broadcast::channel::<ByteString>(10);
+            let exchanges = vec!["kraken".to_string()];
+            groups.insert(name.to_string(), Group { tx, exchanges });
+        }
+        let setting = ListenerSetting {
+            addr: "127.0.0.1".to_string(),
+            port: 0,
+            tls: None,
+            routes: None,
+            redistributable_only: false,
+        };
+        let (btx, _) = broadcast::channel::<ByteString>(10);
+        let (server, addrs) = listen(
+            &setting,
+            btx,
+            books(),
+            BboFeed(None),
+            Groups(Arc::new(groups)),
+        )
+        .unwrap();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let client = awc::Client::new();
+        let first_frame = async |path: &str| {
+            let (_, mut conn) = client
+                .ws(format!("ws://{}{}", addrs[0], path))
+                .connect()
+                .await
+                .unwrap();
+            match timeout(Duration::from_secs(5), conn.next()).await {
+                Ok(Some(Ok(ws::Frame::Text(text)))) => text,
+                other => panic!("{:?}", other),
+            }
+        };
+        // each topic its own summary, none yet for the other
+        assert_eq!(first_frame("/ws/group/topic_eth").await, text.as_bytes());
+        assert_eq!(
+            first_frame("/ws/group/topic_sol").await,
+            r#"{"op":"no_data"}"#
+        );
+
+        let get = async |query: &str| {
+            let mut res = client
+                .get(format!("http://{}/summary?{}", addrs[0], query))
+                .send()
+                .await
+                .unwrap();
+            let content_type = res
+                .headers()
+                .get("content-type")
+                .map(|v| v.to_str().unwrap().to_string());
+            (res.status(), content_type, res.body().await.unwrap())
+        };
+        let (status, content_type, body) = get("group=topic_eth&format=msgpack").await;
+        assert_eq!(status, 200);
+        assert_eq!(content_type.as_deref(), Some("application/msgpack"));
+        let decoded: serde_json::Value = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(
+            decoded,
+            serde_json::from_str::<serde_json::Value>(&text).unwrap()
+        );
+        let (_, content_type, body) = get("group=topic_eth").await;
+        assert_eq!(content_type.as_deref(), Some("application/json"));
+        assert_eq!(body, text.as_bytes());
+        assert_eq!(get("group=topic_sol&format=msgpack").await.0, 204);
+        assert_eq!(get("group=topic_eth&format=xml").await.0, 400);
+        handle.stop(false).await;
+    }
+}
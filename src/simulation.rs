@@ -0,0 +1,463 @@
+use crate::config::{DetectorSetting, SimulationSetting};
+use crate::opportunity::{self, Opportunity};
+use crate::orderbook::{get_unixtime, Summary};
+use crate::vwap::{depth, fill_notional, fill_quantity, ladder};
+use bigdecimal::{BigDecimal, One, Zero};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::time::{interval, Duration};
+
+// digits kept in the reported numbers
+const REPORT_SCALE: i64 = 8;
+
+pub(crate) fn decimal(v: f64) -> BigDecimal {
+    // through the shortest string form, so 0.1 stays 0.1
+    BigDecimal::from_str(&v.to_string()).unwrap_or_else(|_| BigDecimal::zero())
+}
+
+pub(crate) fn report(v: &BigDecimal) -> String {
+    v.round(REPORT_SCALE).normalized().to_string()
+}
+
+fn min(a: BigDecimal, b: BigDecimal) -> BigDecimal {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+struct Venue {
+    base: BigDecimal,
+    quote: BigDecimal,
+    fee_rate: BigDecimal,
+    fees: BigDecimal,
+    pnl: BigDecimal,
+    trades: u64,
+}
+
+#[derive(Default)]
+struct Route {
+    trades: u64,
+    quantity: BigDecimal,
+    pnl: BigDecimal,
+}
+
+// a detected opportunity waiting for the latency penalty to pass
+struct Pending {
+    opportunity: Opportunity,
+    quantity: BigDecimal,
+    due: u128,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct VenueReport {
+    pub base: String,
+    pub quote: String,
+    pub fees: String,
+    pub pnl: String,
+    pub trades: u64,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RouteReport {
+    pub trades: u64,
+    pub quantity: String,
+    pub pnl: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Report {
+    // unix millis of the start or the last reset
+    pub since: u128,
+    pub trades: u64,
+    pub realized_pnl: String,
+    pub pending: usize,
+    pub venues: BTreeMap<String, VenueReport>,
+    pub routes: BTreeMap<String, RouteReport>,
+}
+
+// Paper trades the opportunities of every summary.
+// A trade buys on the buy venue's asks and sells on the sell venue's bids, the
+// same base quantity on both legs, paying the taker fee of each venue in quote.
+// The realized PnL of a trade is split between both venues by valuing the
+// base at the mid of the two fill prices, so each venue carries its own fee.
+pub struct Simulator {
+    setting: SimulationSetting,
+    max_notional: BigDecimal,
+    venues: HashMap<String, Venue>,
+    routes: HashMap<String, Route>,
+    pending: Vec<Pending>,
+    // route -> book timestamps of both legs when it was last traded. The
+    // simulated trades don't consume the real books, so the same books are
+    // never traded twice.
+    traded: HashMap<String, (String, String)>,
+    pnl: BigDecimal,
+    trades: u64,
+    since: u128,
+}
+
+impl Simulator {
+    pub fn new(setting: SimulationSetting, now: u128) -> Simulator {
+        let mut simulator = Simulator {
+            max_notional: decimal(setting.max_notional),
+            setting,
+            venues: HashMap::new(),
+            routes: HashMap::new(),
+            pending: vec![],
+            traded: HashMap::new(),
+            pnl: BigDecimal::zero(),
+            trades: 0,
+            since: now,
+        };
+        simulator.reset(now);
+        simulator
+    }
+
+    // back to the configured balances, forget all trades
+    pub fn reset(&mut self, now: u128) {
+        self.venues = self
+            .setting
+            .venues
+            .iter()
+            .map(|(name, v)| {
+                let venue = Venue {
+                    base: decimal(v.base),
+                    quote: decimal(v.quote),
+                    fee_rate: decimal(v.taker_fee_bps) / BigDecimal::from(10000),
+                    fees: BigDecimal::zero(),
+                    pnl: BigDecimal::zero(),
+                    trades: 0,
+                };
+                (name.clone(), venue)
+            })
+            .collect();
+        self.routes.clear();
+        self.pending.clear();
+        self.traded.clear();
+        self.pnl = BigDecimal::zero();
+        self.trades = 0;
+        self.since = now;
+    }
+
+    // Largest base quantity the books and the balances allow. When given, the
+    // buy leg spends at most `budget` quote before fees.
+    fn capacity(
+        &self,
+        asks: &[(BigDecimal, BigDecimal)],
+        bids: &[(BigDecimal, BigDecimal)],
+        o: &Opportunity,
+        budget: Option<&BigDecimal>,
+    ) -> BigDecimal {
+        let buy = &self.venues[&o.buy_exchange];
+        let sell = &self.venues[&o.sell_exchange];
+        let mut spend = &buy.quote / (BigDecimal::one() + &buy.fee_rate);
+        if let Some(budget) = budget {
+            spend = min(spend, budget.clone());
+        }
+        let bought = fill_notional(asks, &spend).quantity;
+        min(min(bought, depth(bids)), sell.base.clone())
+    }
+
+    // feed one summary, received at `now`
+    pub fn on_summary(&mut self, summary: &Summary, now: u128) {
+        let (due, waiting): (Vec<Pending>, Vec<Pending>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|p| p.due <= now);
+        self.pending = waiting;
+        for p in due {
+            self.execute(&p.opportunity, p.quantity, summary);
+        }
+
+        // sized below against the venue balances, top of book is enough here
+        for o in opportunity::detect(summary, &DetectorSetting::default(), &HashMap::new(), now) {
+            if o.profit_bps < self.setting.min_profit_bps
+                || !self.venues.contains_key(&o.buy_exchange)
+                || !self.venues.contains_key(&o.sell_exchange)
+            {
+                continue;
+            }
+            let route = o.route();
+            if self.pending.iter().any(|p| p.opportunity.route() == route) {
+                continue;
+            }
+            let stamp = |e: &str| summary.timestamp.get(e).cloned().unwrap_or_default();
+            let books = (stamp(&o.buy_exchange), stamp(&o.sell_exchange));
+            if self.traded.get(&route) == Some(&books) {
+                continue;
+            }
+            // only the crossing part of the books is worth trading
+            let asks: Vec<_> = ladder(&summary.asks, &o.buy_exchange)
+                .into_iter()
+                .filter(|(p, _)| p < &o.sell_price)
+                .collect();
+            let bids: Vec<_> = ladder(&summary.bids, &o.sell_exchange)
+                .into_iter()
+                .filter(|(p, _)| p > &o.buy_price)
+                .collect();
+            let quantity = self.capacity(&asks, &bids, &o, Some(&self.max_notional));
+            if quantity <= BigDecimal::zero() {
+                continue;
+            }
+            self.traded.insert(route, books);
+            if self.setting.latency_ms == 0 {
+                self.execute(&o, quantity, summary);
+            } else {
+                self.pending.push(Pending {
+                    opportunity: o,
+                    quantity,
+                    due: now + self.setting.latency_ms as u128,
+                });
+            }
+        }
+    }
+
+    // trade `quantity` at the prices of `summary`, as far as books and balances allow
+    fn execute(&mut self, o: &Opportunity, quantity: BigDecimal, summary: &Summary) {
+        let asks = ladder(&summary.asks, &o.buy_exchange);
+        let bids = ladder(&summary.bids, &o.sell_exchange);
+        let quantity = min(quantity, self.capacity(&asks, &bids, o, None));
+        if quantity <= BigDecimal::zero() {
+            warn!("simulation: {} gone before execution", o.route());
+            return;
+        }
+        let bought = fill_quantity(&asks, &quantity);
+        let sold = fill_quantity(&bids, &quantity);
+
+        let buy_fee = &bought.notional * &self.venues[&o.buy_exchange].fee_rate;
+        let cost = &bought.notional + &buy_fee;
+        let sell_fee = &sold.notional * &self.venues[&o.sell_exchange].fee_rate;
+        let proceeds = &sold.notional - &sell_fee;
+        let pnl = &proceeds - &cost;
+        let reference = (&bought.notional + &sold.notional) / BigDecimal::from(2);
+
+        let buy = self.venues.get_mut(&o.buy_exchange).unwrap();
+        buy.quote -= &cost;
+        buy.base += &quantity;
+        buy.fees += &buy_fee;
+        buy.pnl += &reference - &cost;
+        buy.trades += 1;
+        let sell = self.venues.get_mut(&o.sell_exchange).unwrap();
+        sell.quote += &proceeds;
+        sell.base -= &quantity;
+        sell.fees += &sell_fee;
+        sell.pnl += &proceeds - &reference;
+        sell.trades += 1;
+
+        let route = self.routes.entry(o.route()).or_default();
+        route.trades += 1;
+        route.quantity += &quantity;
+        route.pnl += &pnl;
+        self.trades += 1;
+        self.pnl += &pnl;
+        info!(
+            "simulation: {} {} @ {} -> {}, pnl {}",
+            o.route(),
+            report(&quantity),
+            report(&bought.vwap().unwrap_or_default()),
+            report(&sold.vwap().unwrap_or_default()),
+            report(&pnl)
+        );
+    }
+
+    pub fn report(&self) -> Report {
+        Report {
+            since: self.since,
+            trades: self.trades,
+            realized_pnl: report(&self.pnl),
+            pending: self.pending.len(),
+            venues: self
+                .venues
+                .iter()
+                .map(|(name, v)| {
+                    let r = VenueReport {
+                        base: report(&v.base),
+                        quote: report(&v.quote),
+                        fees: report(&v.fees),
+                        pnl: report(&v.pnl),
+                        trades: v.trades,
+                    };
+                    (name.clone(), r)
+                })
+                .collect(),
+            routes: self
+                .routes
+                .iter()
+                .map(|(name, r)| {
+                    let r = RouteReport {
+                        trades: r.trades,
+                        quantity: report(&r.quantity),
+                        pnl: report(&r.pnl),
+                    };
+                    (name.clone(), r)
+                })
+                .collect(),
+        }
+    }
+}
+
+static SIMULATOR: Lazy<Mutex<Option<Simulator>>> = Lazy::new(|| Mutex::new(None));
+
+// None when the simulation is disabled
+pub fn snapshot() -> Option<Report> {
+    SIMULATOR.lock().unwrap().as_ref().map(|s| s.report())
+}
+
+// false when the simulation is disabled
+pub fn reset() -> bool {
+    match SIMULATOR.lock().unwrap().as_mut() {
+        Some(s) => {
+            s.reset(get_unixtime());
+            info!("simulation reset");
+            true
+        }
+        None => false,
+    }
+}
+
+pub async fn run(setting: SimulationSetting, mut rx: broadcast::Receiver<Arc<Summary>>) {
+    let mut ticker = interval(Duration::from_secs(setting.log_interval_secs));
+    *SIMULATOR.lock().unwrap() = Some(Simulator::new(setting, get_unixtime()));
+    loop {
+        tokio::select! {
+            result = rx.recv() => match result {
+                Ok(summary) => {
+                    if let Some(s) = SIMULATOR.lock().unwrap().as_mut() {
+                        s.on_summary(&summary, get_unixtime());
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("simulation lagged {} summaries", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            _ = ticker.tick() => {
+                if let Some(r) = snapshot() {
+                    info!("simulation: {} trades, realized pnl {}", r.trades, r.realized_pnl);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+    use crate::config::VenueSetting;
+
+    fn setting(latency_ms: u64) -> SimulationSetting {
+        SimulationSetting {
+            min_profit_bps: 10.,
+            max_notional: 1000.,
+            latency_ms,
+            log_interval_secs: 60,
+            venues: HashMap::from([
+                (
+                    "kraken".to_string(),
+                    VenueSetting {
+                        base: 1.,
+                        quote: 100000.,
+                        taker_fee_bps: 10.,
+                    },
+                ),
+                (
+                    "coinjar".to_string(),
+                    VenueSetting {
+                        base: 1.,
+                        quote: 100000.,
+                        taker_fee_bps: 20.,
+                    },
+                ),
+            ]),
+        }
+    }
+
+    // coinjar asks `ask`, kraken bids `bid`, plus non crossing levels
+    fn book(ask: &str, bid: &str, now: u128) -> Summary {
+        summary(
+            vec![level("kraken", bid, "5"), level("coinjar", "99", "5")],
+            vec![level("coinjar", ask, "5"), level("kraken", "103", "5")],
+            now,
+        )
+    }
+
+    fn cents(v: &str) -> String {
+        BigDecimal::from_str(v).unwrap().round(2).to_string()
+    }
+
+    #[test]
+    fn test_immediate() {
+        let mut sim = Simulator::new(setting(0), 0);
+        sim.on_summary(&book("100", "101", 1), 1);
+        // same books again: not traded twice
+        sim.on_summary(&book("100", "101", 1), 2);
+        let r = sim.report();
+        assert_eq!(r.trades, 1);
+        // buy 1 @ 100 + 0.2 fee, sell 1 @ 101 - 0.101 fee
+        assert_eq!(cents(&r.realized_pnl), "0.70");
+        assert_eq!(r.realized_pnl, "0.699");
+        assert_eq!(r.venues["coinjar"].base, "2");
+        assert_eq!(r.venues["coinjar"].quote, "99899.8");
+        assert_eq!(r.venues["coinjar"].pnl, "0.3");
+        assert_eq!(r.venues["kraken"].base, "0");
+        assert_eq!(r.venues["kraken"].quote, "100100.899");
+        assert_eq!(r.venues["kraken"].pnl, "0.399");
+        assert_eq!(r.routes["coinjar->kraken"].trades, 1);
+
+        // kraken ran out of base, nothing left to sell there
+        sim.on_summary(&book("100", "101", 3), 3);
+        assert_eq!(sim.report().trades, 1);
+
+        sim.reset(4);
+        let r = sim.report();
+        assert_eq!(r.trades, 0);
+        assert_eq!(r.realized_pnl, "0");
+        assert_eq!(r.venues["kraken"].base, "1");
+        assert_eq!(r.since, 4);
+    }
+
+    #[test]
+    fn test_latency() {
+        let mut sim = Simulator::new(setting(100), 0);
+        sim.on_summary(&book("100", "101", 0), 0);
+        assert_eq!(sim.report().pending, 1);
+        // not due yet
+        sim.on_summary(&book("100", "100.8", 50), 50);
+        assert_eq!(sim.report().trades, 0);
+        // executed against the book 150ms later
+        sim.on_summary(&book("100", "100.5", 150), 150);
+        let r = sim.report();
+        assert_eq!(r.trades, 1);
+        assert_eq!(r.pending, 0);
+        // 100.5 * 0.999 - 100.2
+        assert_eq!(cents(&r.realized_pnl), "0.20");
+
+        // the opportunity disappeared before execution
+        let mut sim = Simulator::new(setting(100), 0);
+        sim.on_summary(&book("100", "101", 0), 0);
+        let mut gone = book("100", "101", 200);
+        gone.bids.retain(|l| l.exchange != "kraken");
+        sim.on_summary(&gone, 200);
+        assert_eq!(sim.report().trades, 0);
+    }
+
+    #[test]
+    fn test_caps() {
+        let mut s = setting(0);
+        s.max_notional = 250.;
+        s.venues.get_mut("kraken").unwrap().base = 10.;
+        let mut sim = Simulator::new(s, 0);
+        // only 2.5 bought for 250, though both books hold 5
+        sim.on_summary(&book("100", "101", 1), 1);
+        let r = sim.report();
+        assert_eq!(r.routes["coinjar->kraken"].quantity, "2.5");
+        // 2.5 * (101 * 0.999 - 100 * 1.002)
+        assert_eq!(cents(&r.realized_pnl), "1.75");
+    }
+}
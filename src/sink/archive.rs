@@ -0,0 +1,521 @@
+use crate::config::{ArchiveFormat, ArchiveSetting};
+use crate::orderbook::{get_unixtime, Level, Summary};
+use anyhow::{anyhow, Result};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use chrono::{TimeZone, Utc};
+use log::{error, info, warn};
+use parquet::arrow::arrow_to_parquet_schema;
+use parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use serde::Serialize;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+const HOUR_MS: i64 = 3_600_000;
+// bounds the rows buffered in memory between two flushes
+const MAX_ROW_GROUP_SIZE: usize = 65_536;
+
+// one level of one side of a sampled ladder.
+// prices and amounts stay strings so no precision is lost on the way.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Row {
+    pub ts: i64,
+    pub pair: String,
+    pub side: &'static str,
+    pub level_index: u32,
+    pub exchange: String,
+    pub price: String,
+    pub amount: String,
+}
+
+pub fn flatten(summary: &Summary, pair: &str, ts: i64, max_depth: usize) -> Vec<Row> {
+    let side = |name: &'static str, levels: &[Level]| {
+        levels
+            .iter()
+            .take(max_depth)
+            .enumerate()
+            .map(|(i, l)| Row {
+                ts,
+                pair: pair.to_string(),
+                side: name,
+                level_index: i as u32,
+                exchange: l.exchange.clone(),
+                price: l.price.clone(),
+                amount: l.amount.clone(),
+            })
+            .collect::<Vec<Row>>()
+    };
+    let mut rows = side("bid", &summary.bids);
+    rows.extend(side("ask", &summary.asks));
+    rows
+}
+
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("ts", DataType::Int64, false),
+        Field::new("pair", DataType::Utf8, false),
+        Field::new("side", DataType::Utf8, false),
+        Field::new("level_index", DataType::UInt32, false),
+        Field::new("exchange", DataType::Utf8, false),
+        Field::new("price", DataType::Utf8, false),
+        Field::new("amount", DataType::Utf8, false),
+    ]))
+}
+
+// Rows are buffered until flushed as one row group. The column chunks are
+// written with the low level column writers: closing them flushes the file
+// writer's buffer, which an ArrowWriter never does before the footer.
+struct ParquetWriter {
+    file: SerializedFileWriter<File>,
+    pending: Vec<Row>,
+}
+
+impl ParquetWriter {
+    fn new(file: File) -> Result<ParquetWriter> {
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(MAX_ROW_GROUP_SIZE)
+            .build();
+        let descr = arrow_to_parquet_schema(&schema())?;
+        Ok(ParquetWriter {
+            file: SerializedFileWriter::new(file, descr.root_schema_ptr(), Arc::new(props))?,
+            pending: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, rows: Vec<Row>) -> Result<()> {
+        self.pending.extend(rows);
+        if self.pending.len() >= self.file.properties().max_row_group_size() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.pending);
+        let strings = |f: fn(&Row) -> &str| -> Vec<ByteArray> {
+            rows.iter().map(|r| ByteArray::from(f(r))).collect()
+        };
+        let mut group = self.file.next_row_group()?;
+        // in the order of schema()
+        let mut index = 0;
+        while let Some(mut column) = group.next_column()? {
+            match index {
+                0 => {
+                    let values: Vec<i64> = rows.iter().map(|r| r.ts).collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&values, None, None)?;
+                }
+                3 => {
+                    let values: Vec<i32> = rows.iter().map(|r| r.level_index as i32).collect();
+                    column
+                        .typed::<Int32Type>()
+                        .write_batch(&values, None, None)?;
+                }
+                _ => {
+                    let values = match index {
+                        1 => strings(|r| &r.pair),
+                        2 => strings(|r| r.side),
+                        4 => strings(|r| &r.exchange),
+                        5 => strings(|r| &r.price),
+                        _ => strings(|r| &r.amount),
+                    };
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&values, None, None)?;
+                }
+            }
+            column.close()?;
+            index += 1;
+        }
+        group.close()?;
+        self.file.inner().sync_data()?;
+        Ok(())
+    }
+
+    fn close(mut self) -> Result<File> {
+        self.flush()?;
+        Ok(self.file.into_inner()?)
+    }
+}
+
+enum Writer {
+    Csv(csv::Writer<File>),
+    Parquet(ParquetWriter),
+}
+
+impl Writer {
+    fn create(path: &PathBuf, format: ArchiveFormat) -> Result<Writer> {
+        let file = File::create(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        Ok(match format {
+            ArchiveFormat::Csv => Writer::Csv(csv::Writer::from_writer(file)),
+            ArchiveFormat::Parquet => Writer::Parquet(ParquetWriter::new(file)?),
+        })
+    }
+
+    fn write(&mut self, rows: Vec<Row>) -> Result<()> {
+        match self {
+            Writer::Csv(w) => {
+                for row in &rows {
+                    w.serialize(row)?;
+                }
+                w.flush()?;
+            }
+            Writer::Parquet(w) => w.write(rows)?,
+        }
+        Ok(())
+    }
+
+    // writes the buffered parquet rows as a row group and fsyncs.
+    // csv rows are already flushed on every write.
+    fn flush(&mut self) -> Result<()> {
+        if let Writer::Parquet(w) = self {
+            w.flush()?;
+        }
+        Ok(())
+    }
+
+    // flush everything (the parquet footer included) and fsync
+    fn close(self) -> Result<()> {
+        let file = match self {
+            Writer::Csv(w) => w.into_inner().map_err(|e| anyhow!("{}", e.error()))?,
+            Writer::Parquet(w) => w.close()?,
+        };
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+// Writes sampled summaries into one file per hour:
+// {directory}/{pair}-{YYYYMMDDHH}.{csv|parquet}, hours in UTC.
+pub struct Archiver {
+    directory: PathBuf,
+    format: ArchiveFormat,
+    pair: String,
+    max_depth: usize,
+    flush_interval_ms: i64,
+    // ts of the last flush of the current file
+    flushed: i64,
+    current: Option<(i64, PathBuf, Writer)>,
+}
+
+impl Archiver {
+    pub fn new(setting: &ArchiveSetting, pair: &str) -> Result<Archiver> {
+        std::fs::create_dir_all(&setting.directory)
+            .map_err(|e| anyhow!("{}: {}", setting.directory, e))?;
+        Ok(Archiver {
+            directory: PathBuf::from(&setting.directory),
+            format: setting.format,
+            pair: pair.to_string(),
+            max_depth: setting.max_depth,
+            flush_interval_ms: setting.flush_interval_secs as i64 * 1000,
+            flushed: 0,
+            current: None,
+        })
+    }
+
+    // a file left from a previous run in the same hour is never overwritten
+    fn path(&self, hour: i64) -> PathBuf {
+        let ext = match self.format {
+            ArchiveFormat::Csv => "csv",
+            ArchiveFormat::Parquet => "parquet",
+        };
+        let pair: String = self
+            .pair
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let stamp = Utc
+            .timestamp_millis_opt(hour * HOUR_MS)
+            .unwrap()
+            .format("%Y%m%d%H");
+        let mut path = self.directory.join(format!("{}-{}.{}", pair, stamp, ext));
+        let mut n = 0;
+        while path.exists() {
+            n += 1;
+            path = self
+                .directory
+                .join(format!("{}-{}-{}.{}", pair, stamp, n, ext));
+        }
+        path
+    }
+
+    pub fn write(&mut self, summary: &Summary, ts: i64) -> Result<()> {
+        let hour = ts.div_euclid(HOUR_MS);
+        if !matches!(&self.current, Some((h, _, _)) if *h == hour) {
+            self.close()?;
+            let path = self.path(hour);
+            let writer = Writer::create(&path, self.format)?;
+            info!("archiving to {}", path.display());
+            self.current = Some((hour, path, writer));
+            self.flushed = ts;
+        }
+        let rows = flatten(summary, &self.pair, ts, self.max_depth);
+        let (_, _, writer) = self.current.as_mut().unwrap();
+        writer.write(rows)?;
+        if ts - self.flushed >= self.flush_interval_ms {
+            writer.flush()?;
+            self.flushed = ts;
+        }
+        Ok(())
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        if let Some((_, path, writer)) = self.current.take() {
+            writer
+                .close()
+                .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+            info!("archived {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+// The latest summary is sampled every sample_interval_secs and handed to a
+// blocking task doing the file io. When the writer can't keep up, samples are
+// skipped instead of queued.
+pub async fn run(
+    setting: ArchiveSetting,
+    pair: String,
+    mut rx: broadcast::Receiver<Arc<Summary>>,
+) -> Result<()> {
+    let mut archiver = Archiver::new(&setting, &pair)?;
+    let (wtx, mut wrx) = mpsc::channel::<(i64, Arc<Summary>)>(16);
+    let writer = tokio::task::spawn_blocking(move || {
+        while let Some((ts, summary)) = wrx.blocking_recv() {
+            if let Err(e) = archiver.write(&summary, ts) {
+                error!("archive write: {}", e);
+            }
+        }
+        if let Err(e) = archiver.close() {
+            error!("archive close: {}", e);
+        }
+    });
+    let mut ticker = interval(Duration::from_secs(setting.sample_interval_secs));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut latest: Option<Arc<Summary>> = None;
+    loop {
+        tokio::select! {
+            result = rx.recv() => match result {
+                Ok(summary) => latest = Some(summary),
+                // only the latest one is sampled anyway
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = ticker.tick() => {
+                if let Some(summary) = &latest {
+                    if wtx.try_send((get_unixtime() as i64, summary.clone())).is_err() {
+                        warn!("archive writer is behind, sample skipped");
+                    }
+                }
+            }
+        }
+    }
+    drop(wtx);
+    writer.await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+    use arrow_array::{Int64Array, RecordBatch, StringArray, UInt32Array};
+    use parquet::arrow::arrow_reader::{
+        ArrowReaderMetadata, ArrowReaderOptions, ParquetRecordBatchReaderBuilder,
+    };
+    use parquet::file::metadata::{FileMetaData, ParquetMetaData};
+
+    // 2023-11-14 22:13:20 UTC
+    const TS: i64 = 1_700_000_000_000;
+
+    fn setting(directory: &str, format: ArchiveFormat) -> ArchiveSetting {
+        ArchiveSetting {
+            directory: directory.to_string(),
+            format,
+            sample_interval_secs: 1,
+            max_depth: 2,
+            flush_interval_secs: 60,
+        }
+    }
+
+    fn sample() -> Summary {
+        summary(
+            vec![
+                level("kraken", "101.00000001", "1"),
+                level("coinjar", "100.5", "0.25"),
+                level("btcmarkets", "100", "3"),
+            ],
+            vec![level("coinjar", "102", "0.5")],
+            0,
+        )
+    }
+
+    #[test]
+    fn test_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archiver = Archiver::new(
+            &setting(dir.path().to_str().unwrap(), ArchiveFormat::Csv),
+            "XBT/AUD",
+        )
+        .unwrap();
+        archiver.write(&sample(), TS).unwrap();
+        archiver.write(&sample(), TS + 1000).unwrap();
+        // next hour goes to a new file
+        archiver.write(&sample(), TS + HOUR_MS).unwrap();
+        archiver.close().unwrap();
+
+        let content = std::fs::read_to_string(dir.path().join("XBT_AUD-2023111422.csv")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], "ts,pair,side,level_index,exchange,price,amount");
+        assert_eq!(
+            lines[1],
+            "1700000000000,XBT/AUD,bid,0,kraken,101.00000001,1"
+        );
+        assert_eq!(lines[2], "1700000000000,XBT/AUD,bid,1,coinjar,100.5,0.25");
+        assert_eq!(lines[3], "1700000000000,XBT/AUD,ask,0,coinjar,102,0.5");
+        assert_eq!(
+            lines[4],
+            "1700000001000,XBT/AUD,bid,0,kraken,101.00000001,1"
+        );
+        let next = std::fs::read_to_string(dir.path().join("XBT_AUD-2023111423.csv")).unwrap();
+        assert_eq!(next.lines().count(), 4);
+
+        // a restart within the same hour doesn't overwrite the previous file
+        let mut archiver = Archiver::new(
+            &setting(dir.path().to_str().unwrap(), ArchiveFormat::Csv),
+            "XBT/AUD",
+        )
+        .unwrap();
+        archiver.write(&sample(), TS).unwrap();
+        archiver.close().unwrap();
+        assert!(dir.path().join("XBT_AUD-2023111422-1.csv").exists());
+    }
+
+    #[test]
+    fn test_parquet() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archiver = Archiver::new(
+            &setting(dir.path().to_str().unwrap(), ArchiveFormat::Parquet),
+            "BTC-AUD",
+        )
+        .unwrap();
+        archiver.write(&sample(), TS).unwrap();
+        archiver.write(&sample(), TS + 1000).unwrap();
+        archiver.close().unwrap();
+
+        let file = File::open(dir.path().join("BTC-AUD-2023111422.parquet")).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        assert_eq!(batches[0].schema(), schema());
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 6);
+
+        let b = &batches[0];
+        let column = |i: usize| {
+            b.column(i)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap()
+                .clone()
+        };
+        let ts = b.column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        let index = b.column(3).as_any().downcast_ref::<UInt32Array>().unwrap();
+        assert_eq!(ts.value(0), TS);
+        assert_eq!(column(1).value(0), "BTC-AUD");
+        assert_eq!(column(2).value(0), "bid");
+        assert_eq!(index.value(1), 1);
+        assert_eq!(column(4).value(1), "coinjar");
+        assert_eq!(column(5).value(0), "101.00000001");
+        assert_eq!(column(6).value(1), "0.25");
+        assert_eq!(column(2).value(2), "ask");
+    }
+
+    fn timestamps(batches: &[RecordBatch]) -> Vec<i64> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                b.column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parquet_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archiver = Archiver::new(
+            &setting(dir.path().to_str().unwrap(), ArchiveFormat::Parquet),
+            "BTC-AUD",
+        )
+        .unwrap();
+        archiver.write(&sample(), TS).unwrap();
+        archiver.write(&sample(), TS + 30_000).unwrap();
+        // flush_interval_secs elapsed, the row group goes to disk
+        archiver.write(&sample(), TS + 60_000).unwrap();
+        // still buffered
+        archiver.write(&sample(), TS + 61_000).unwrap();
+
+        // no footer yet: read what reached the disk with the metadata of the
+        // flushed row groups, as a recovery of a crashed run would
+        let path = dir.path().join("BTC-AUD-2023111422.parquet");
+        let row_groups = match &archiver.current {
+            Some((_, _, Writer::Parquet(w))) => w.file.flushed_row_groups().to_vec(),
+            _ => panic!("no parquet writer"),
+        };
+        assert_eq!(row_groups.len(), 1);
+        let num_rows = row_groups.iter().map(|r| r.num_rows()).sum();
+        let file_metadata = FileMetaData::new(
+            1,
+            num_rows,
+            None,
+            None,
+            row_groups[0].schema_descr_ptr(),
+            None,
+        );
+        let metadata = ArrowReaderMetadata::try_new(
+            Arc::new(ParquetMetaData::new(file_metadata, row_groups)),
+            ArrowReaderOptions::new(),
+        )
+        .unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::new_with_metadata(
+            File::open(&path).unwrap(),
+            metadata,
+        )
+        .build()
+        .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        let mut expected = vec![TS; 3];
+        expected.extend([TS + 30_000; 3]);
+        expected.extend([TS + 60_000; 3]);
+        assert_eq!(timestamps(&batches), expected);
+
+        archiver.close().unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let batches: Vec<RecordBatch> = reader.build().unwrap().map(|b| b.unwrap()).collect();
+        expected.extend([TS + 61_000; 3]);
+        assert_eq!(timestamps(&batches), expected);
+    }
+}
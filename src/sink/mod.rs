@@ -0,0 +1,21 @@
+pub mod archive;
+pub mod nats;
+pub mod redistribution;
+pub mod snapshot;
+pub mod statsd;
+#[cfg(unix)]
+pub mod unix;
+pub mod zmq;
+
+use crate::config::Encoding;
+use crate::orderbook::Summary;
+use anyhow::Result;
+
+// serialize a summary in the requested wire format.
+// msgpack keeps the field names so both formats decode to the same document.
+pub fn encode(summary: &Summary, encoding: Encoding) -> Result<Vec<u8>> {
+    Ok(match encoding {
+        Encoding::Json => serde_json::to_vec(summary)?,
+        Encoding::Msgpack => rmp_serde::to_vec_named(summary)?,
+    })
+}
@@ -0,0 +1,259 @@
+use super::encode;
+use crate::config::{Encoding, JetStreamSetting, NatsSetting};
+use crate::orderbook::Summary;
+use anyhow::{anyhow, Result};
+use async_nats::jetstream;
+use async_nats::HeaderMap;
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::{error, info, warn};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::time::Duration;
+
+pub const SEQ_HEADER: &str = "Arb-Seq";
+pub const PAIR_HEADER: &str = "Arb-Pair";
+
+// publish failures and summaries dropped because the publisher fell behind
+static PUBLISH_ERRORS: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// what the sink needs from a nats connection, so tests can run without a server
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    async fn publish(&self, subject: String, headers: HeaderMap, payload: Bytes) -> Result<()>;
+}
+
+struct Core(async_nats::Client);
+
+#[async_trait]
+impl Publisher for Core {
+    async fn publish(&self, subject: String, headers: HeaderMap, payload: Bytes) -> Result<()> {
+        self.0
+            .publish_with_headers(subject, headers, payload)
+            .await
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+struct JetStream(jetstream::Context);
+
+#[async_trait]
+impl Publisher for JetStream {
+    async fn publish(&self, subject: String, headers: HeaderMap, payload: Bytes) -> Result<()> {
+        self.0
+            .publish_with_headers(subject, headers, payload)
+            .await
+            .map_err(|e| anyhow!("{}", e))?
+            .await
+            .map_err(|e| anyhow!("{}", e))?;
+        Ok(())
+    }
+}
+
+// nats subjects can't contain whitespace, and `.`, `*`, `>` are token syntax
+pub fn subject(template: &str, pair: &str) -> String {
+    let pair: String = pair
+        .chars()
+        .map(|c| match c {
+            '.' | '*' | '>' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect();
+    template.replace("{pair}", &pair)
+}
+
+pub fn headers(seq: u64, pair: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(SEQ_HEADER, seq.to_string().as_str());
+    headers.insert(PAIR_HEADER, pair);
+    headers
+}
+
+async fn publish(
+    publisher: Box<dyn Publisher>,
+    subject: String,
+    pair: String,
+    encoding: Encoding,
+    mut rx: broadcast::Receiver<Arc<Summary>>,
+) {
+    let mut seq = 0u64;
+    loop {
+        let summary = match rx.recv().await {
+            Ok(summary) => summary,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                let total = DROPPED.fetch_add(n, Ordering::Relaxed) + n;
+                warn!("nats publisher dropped {} summaries, {} in total", n, total);
+                seq += n;
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        seq += 1;
+        let payload = match encode(&summary, encoding) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("nats encode: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = publisher
+            .publish(subject.clone(), headers(seq, &pair), payload.into())
+            .await
+        {
+            let total = PUBLISH_ERRORS.fetch_add(1, Ordering::Relaxed) + 1;
+            error!("nats publish: {}, {} errors in total", e, total);
+        }
+    }
+}
+
+async fn ensure_stream(
+    context: &jetstream::Context,
+    setting: &JetStreamSetting,
+    subject: &str,
+) -> Result<()> {
+    context
+        .get_or_create_stream(jetstream::stream::Config {
+            name: setting.stream.clone(),
+            subjects: vec![subject.to_string()],
+            max_age: Duration::from_secs(setting.max_age_secs),
+            max_messages: setting.max_messages,
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| anyhow!("jetstream stream {}: {}", setting.stream, e))?;
+    Ok(())
+}
+
+// the client reconnects on its own; publish errors in between are only counted
+pub async fn run(
+    setting: NatsSetting,
+    pair: String,
+    rx: broadcast::Receiver<Arc<Summary>>,
+) -> Result<()> {
+    let mut options = async_nats::ConnectOptions::new().retry_on_initial_connect();
+    if let Some(path) = &setting.credentials_file {
+        options = options.credentials_file(path).await?;
+    }
+    if let (Some(user), Some(password)) = (&setting.user, &setting.password) {
+        options = options.user_and_password(user.clone(), password.clone());
+    }
+    let servers = setting
+        .servers
+        .iter()
+        .map(|s| s.parse())
+        .collect::<std::result::Result<Vec<async_nats::ServerAddr>, _>>()
+        .map_err(|e| anyhow!("nats server address: {}", e))?;
+    let client = options
+        .connect(servers.as_slice())
+        .await
+        .map_err(|e| anyhow!("nats connect: {}", e))?;
+    let subject = subject(&setting.subject, &pair);
+    let publisher: Box<dyn Publisher> = match &setting.jetstream {
+        Some(js) => {
+            let context = jetstream::new(client);
+            ensure_stream(&context, js, &subject).await?;
+            Box::new(JetStream(context))
+        }
+        None => Box::new(Core(client)),
+    };
+    info!("nats publishing to {}", subject);
+    publish(publisher, subject, pair, setting.encoding, rx).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Mock {
+        sent: Arc<Mutex<Vec<(String, HeaderMap, Bytes)>>>,
+    }
+
+    #[async_trait]
+    impl Publisher for Mock {
+        async fn publish(&self, subject: String, headers: HeaderMap, payload: Bytes) -> Result<()> {
+            self.sent.lock().unwrap().push((subject, headers, payload));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_subject() {
+        assert_eq!(subject("md.arb.{pair}", "BTC-AUD"), "md.arb.BTC-AUD");
+        assert_eq!(subject("md.arb.{pair}", "XBT/AUD"), "md.arb.XBT/AUD");
+        assert_eq!(subject("{pair}.book", "btc.aud *>"), "btc_aud___.book");
+    }
+
+    #[tokio::test]
+    async fn test_publish() {
+        let mock = Mock::default();
+        let sent = mock.sent.clone();
+        let (tx, rx) = broadcast::channel(10);
+        let s = summary(
+            vec![level("kraken", "101", "1")],
+            vec![level("coinjar", "102", "0.5")],
+            1,
+        );
+        let expected = serde_json::to_vec(&s).unwrap();
+        let s = Arc::new(s);
+        tx.send(s.clone()).unwrap();
+        tx.send(s).unwrap();
+        drop(tx);
+        publish(
+            Box::new(mock),
+            "md.arb.BTC-AUD".to_string(),
+            "BTC-AUD".to_string(),
+            Encoding::Json,
+            rx,
+        )
+        .await;
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        for (i, (subject, headers, payload)) in sent.iter().enumerate() {
+            assert_eq!(subject, "md.arb.BTC-AUD");
+            assert_eq!(
+                headers.get(SEQ_HEADER).unwrap().as_str(),
+                (i + 1).to_string()
+            );
+            assert_eq!(headers.get(PAIR_HEADER).unwrap().as_str(), "BTC-AUD");
+            assert_eq!(payload.as_ref(), expected.as_slice());
+        }
+    }
+
+    // requires `nats-server -js` listening on 127.0.0.1:4222
+    #[tokio::test]
+    #[ignore]
+    async fn test_jetstream() {
+        let client = async_nats::connect("127.0.0.1:4222").await.unwrap();
+        let context = jetstream::new(client);
+        let setting = JetStreamSetting {
+            stream: "ARB_TEST".to_string(),
+            max_age_secs: 60,
+            max_messages: 10,
+        };
+        ensure_stream(&context, &setting, "md.test.BTC-AUD")
+            .await
+            .unwrap();
+        // second call finds the existing stream
+        ensure_stream(&context, &setting, "md.test.BTC-AUD")
+            .await
+            .unwrap();
+        JetStream(context.clone())
+            .publish(
+                "md.test.BTC-AUD".to_string(),
+                headers(1, "BTC-AUD"),
+                "{}".into(),
+            )
+            .await
+            .unwrap();
+        let mut stream = context.get_stream("ARB_TEST").await.unwrap();
+        assert!(stream.info().await.unwrap().state.messages >= 1);
+        context.delete_stream("ARB_TEST").await.unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+// The venues whose terms forbid redistribution are left out of the outputs
+// set to redistributable_only: their levels, their entries in the per
+// exchange maps and the opportunities with a leg on them. The rest of the
+// Summary stays as it was published, the spread and the consolidated levels
+// are only rebuilt when a denied venue was part of them.
+use crate::orderbook::{Consolidated, ConsolidatedLevel, Level, Summary};
+use bigdecimal::{BigDecimal, Signed};
+use bytestring::ByteString;
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+fn dec(s: &str) -> BigDecimal {
+    BigDecimal::from_str(s).unwrap_or_default()
+}
+
+// the levels of one side summed per price, best first, like
+// AggregatedOrderbook::consolidated
+fn consolidate(levels: &[Level], n: usize) -> Vec<ConsolidatedLevel> {
+    levels
+        .chunk_by(|a, b| a.price == b.price)
+        .take(n)
+        .map(|rows| {
+            let mut best = &rows[0];
+            for row in rows.iter().skip(1) {
+                if dec(&row.amount) > dec(&best.amount) {
+                    best = row;
+                }
+            }
+            ConsolidatedLevel {
+                price: rows[0].price.clone(),
+                amount: rows
+                    .iter()
+                    .map(|l| dec(&l.amount))
+                    .sum::<BigDecimal>()
+                    .to_string(),
+                venues: rows.len(),
+                best_venue: best.exchange.clone(),
+            }
+        })
+        .collect()
+}
+
+pub fn redistributable(summary: &Summary, denied: &HashSet<String>) -> Summary {
+    let allowed = |exchange: &String| !denied.contains(exchange);
+    let side = |levels: &[Level]| -> Vec<Level> {
+        levels
+            .iter()
+            .filter(|l| allowed(&l.exchange))
+            .cloned()
+            .collect()
+    };
+    let (bids, asks) = (side(&summary.bids), side(&summary.asks));
+    let price = |levels: &[Level]| levels.first().map(|l| l.price.clone());
+    let (spread, crossed) =
+        if price(&bids) == price(&summary.bids) && price(&asks) == price(&summary.asks) {
+            (summary.spread.clone(), summary.crossed)
+        } else {
+            match (bids.first(), asks.first()) {
+                (Some(bid), Some(ask)) => {
+                    let spread = dec(&ask.price) - dec(&bid.price);
+                    (spread.to_string(), spread.is_negative())
+                }
+                _ => ("0".to_string(), false),
+            }
+        };
+    let consolidated = summary.consolidated.as_ref().map(|c| {
+        let n = c.bids.len().max(c.asks.len());
+        Consolidated {
+            bids: consolidate(&bids, n),
+            asks: consolidate(&asks, n),
+        }
+    });
+    let mut stats = summary.stats.clone();
+    stats.leader = stats.leader.filter(|e| allowed(e));
+    for traded in stats.traded.iter_mut() {
+        traded.volume.retain(|e, _| allowed(e));
+    }
+    if let Some(warmup) = stats.warmup.as_mut() {
+        warmup.missing.retain(|e| allowed(e));
+    }
+    stats.ticker_age_ms.retain(|e, _| allowed(e));
+    stats.update_rate.retain(|e, _| allowed(e));
+    stats.maintenance.retain(|e| allowed(e));
+    let scalars = |map: &std::collections::BTreeMap<String, String>| {
+        map.iter()
+            .filter(|(e, _)| allowed(e))
+            .map(|(e, v)| (e.clone(), v.clone()))
+            .collect()
+    };
+    Summary {
+        schema_version: summary.schema_version,
+        seq: summary.seq,
+        spread,
+        crossed,
+        bids,
+        asks,
+        timestamp: scalars(&summary.timestamp),
+        volume: scalars(&summary.volume),
+        last_price: scalars(&summary.last_price),
+        depth: summary
+            .depth
+            .iter()
+            .filter(|(e, _)| allowed(e))
+            .map(|(e, d)| (e.clone(), *d))
+            .collect(),
+        index_prices: summary.index_prices.clone(),
+        consolidated,
+        derivatives: summary
+            .derivatives
+            .iter()
+            .filter(|(e, _)| allowed(e))
+            .map(|(e, d)| (e.clone(), d.clone()))
+            .collect(),
+        opportunities: summary
+            .opportunities
+            .iter()
+            .filter(|o| allowed(&o.buy_exchange) && allowed(&o.sell_exchange))
+            .cloned()
+            .collect(),
+        stats,
+    }
+}
+
+#[derive(Deserialize)]
+struct Tick {
+    exchange: String,
+}
+
+// forward the bbo ticks of the allowed exchanges
+pub async fn forward_ticks(
+    mut rx: broadcast::Receiver<ByteString>,
+    tx: broadcast::Sender<ByteString>,
+    denied: Arc<HashSet<String>>,
+) {
+    loop {
+        let tick = match rx.recv().await {
+            Ok(tick) => tick,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("redistributable bbo ticks lagged {}", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        match serde_json::from_str::<Tick>(&tick) {
+            Ok(t) if !denied.contains(&t.exchange) => {
+                // no receiver is just no client of the ticks
+                let _ = tx.send(tick);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opportunity::tracker::State;
+    use crate::orderbook::{AggregatedOrderbook, OpportunitySummary, Orderbook, Side, UpdateRate};
+
+    fn book(exchange: &str, bid: &str, ask: &str, amount: &str) -> Orderbook {
+        let mut ob = Orderbook::new(exchange);
+        ob.insert(Side::Bid, dec(bid), dec(amount));
+        ob.insert(Side::Ask, dec(ask), dec(amount));
+        ob.set_timestamp(1696405428703);
+        ob
+    }
+
+    fn opportunity(buy: &str, sell: &str) -> OpportunitySummary {
+        OpportunitySummary {
+            state: State::New,
+            buy_exchange: buy.to_string(),
+            sell_exchange: sell.to_string(),
+            opened: 1696405428703,
+            profit_bps: 40.,
+            quantity: "1".to_string(),
+            max_size: None,
+            confidence: 1.,
+            net: None,
+        }
+    }
+
+    #[test]
+    fn test_redistributable() {
+        let mut agg = AggregatedOrderbook::new();
+        agg.consolidated_levels = Some(2);
+        agg.merge(&book("allowed", "99", "101", "1"));
+        agg.merge(&book("other", "99", "102", "2"));
+        agg.merge(&book("licensed", "100", "101", "5"));
+        let mut summary = agg.finalize().unwrap();
+        summary.seq = 3;
+        summary.opportunities = vec![
+            opportunity("allowed", "licensed"),
+            opportunity("other", "allowed"),
+        ];
+        summary.stats.leader = Some("licensed".to_string());
+        for exchange in ["allowed", "licensed"] {
+            let rate = UpdateRate {
+                interval_ms: 100.,
+                degraded: false,
+            };
+            summary.stats.update_rate.insert(exchange.to_string(), rate);
+        }
+        let denied = HashSet::from(["licensed".to_string()]);
+
+        let unfiltered = serde_json::to_string(&summary).unwrap();
+        assert!(unfiltered.contains("licensed"));
+        let filtered = redistributable(&summary, &denied);
+        assert!(!serde_json::to_string(&filtered)
+            .unwrap()
+            .contains("licensed"));
+        // the rest is kept
+        assert_eq!(filtered.seq, 3);
+        assert_eq!(filtered.bids.len(), 2);
+        assert_eq!(filtered.asks.len(), 2);
+        assert_eq!(filtered.spread, "2");
+        assert_eq!(
+            filtered.opportunities,
+            vec![opportunity("other", "allowed")]
+        );
+        assert_eq!(
+            filtered.timestamp.keys().collect::<Vec<_>>(),
+            ["allowed", "other"]
+        );
+        assert_eq!(filtered.stats.leader, None);
+        assert!(filtered.stats.update_rate.contains_key("allowed"));
+        let consolidated = filtered.consolidated.unwrap();
+        assert_eq!(consolidated.bids.len(), 1);
+        assert_eq!(consolidated.bids[0].amount, "3");
+        assert_eq!(consolidated.bids[0].venues, 2);
+        assert_eq!(consolidated.bids[0].best_venue, "other");
+        assert_eq!(consolidated.asks.len(), 2);
+
+        // nothing denied, nothing changes
+        let same = redistributable(&summary, &HashSet::new());
+        assert_eq!(serde_json::to_string(&same).unwrap(), unfiltered);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::config::PersistSetting;
+use crate::schema::{self, SCHEMA_VERSION};
+use anyhow::{anyhow, Result};
+use bytestring::ByteString;
+use log::{error, info, warn};
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{ErrorKind, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{interval, MissedTickBehavior};
+
+// Write next to the target and rename over it, so the file on disk is
+// always a whole summary, even when the process dies mid write.
+pub fn write_atomic(path: &str, summary: &[u8]) -> Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let mut file = File::create(&tmp)?;
+    file.write_all(summary)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// The summary saved at path, marked as data from before the restart.
+// None when there is no file or it is older than max_age.
+pub fn load(path: &str, max_age: Duration, now: SystemTime) -> Result<Option<ByteString>> {
+    let modified = match std::fs::metadata(path) {
+        Ok(meta) => meta.modified()?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    // a clock that went backwards makes it brand new
+    if now.duration_since(modified).unwrap_or_default() > max_age {
+        info!("snapshot {} is older than {:?}, skipped", path, max_age);
+        return Ok(None);
+    }
+    let mut summary: Map<String, Value> = serde_json::from_slice(&std::fs::read(path)?)
+        .map_err(|e| anyhow!("snapshot {}: {}", path, e))?;
+    // the clients of this build expect its layout, even from a stale summary
+    let version = schema::version(&summary);
+    if version != SCHEMA_VERSION as u64 {
+        info!(
+            "snapshot {} is of schema version {}, skipped",
+            path, version
+        );
+        return Ok(None);
+    }
+    // unix millis of the last write before the restart
+    let written = modified.duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    summary.insert("stale".to_string(), Value::Bool(true));
+    summary.insert("restored_from".to_string(), Value::from(written));
+    Ok(Some(ByteString::from(serde_json::to_string(&summary)?)))
+}
+
+// Sample the cache every write_interval_secs and write it out when it
+// changed. The file io runs on the blocking pool, the publisher only ever
+// swaps the cache. `restored` is what load returned, never written back.
+pub async fn run(
+    setting: PersistSetting,
+    cached: fn() -> Option<ByteString>,
+    restored: Option<ByteString>,
+) {
+    let mut last = restored;
+    let mut tick = interval(Duration::from_secs(setting.write_interval_secs));
+    tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tick.tick().await;
+        let summary = match cached() {
+            Some(summary) if last.as_ref() != Some(&summary) => summary,
+            _ => continue,
+        };
+        let path = setting.snapshot_path.clone();
+        let data = summary.clone();
+        match tokio::task::spawn_blocking(move || write_atomic(&path, data.as_bytes())).await {
+            Ok(Ok(())) => last = Some(summary),
+            Ok(Err(e)) => warn!("snapshot {}: {}", setting.snapshot_path, e),
+            Err(e) => error!("snapshot writer: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.json");
+        let path = path.to_str().unwrap();
+        // left over by a write that was cut short
+        std::fs::write(format!("{}.tmp", path), "{\"bids\":[").unwrap();
+        write_atomic(path, br#"{"spread":"1"}"#).unwrap();
+        write_atomic(path, br#"{"spread":"2"}"#).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), r#"{"spread":"2"}"#);
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["snapshot.json"]);
+    }
+
+    #[test]
+    fn test_load_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.json");
+        let path = path.to_str().unwrap();
+        let max_age = Duration::from_secs(60);
+        assert_eq!(load(path, max_age, SystemTime::now()).unwrap(), None);
+
+        write_atomic(path, br#"{"spread":"1"}"#).unwrap();
+        let modified = std::fs::metadata(path).unwrap().modified().unwrap();
+        let restored = load(path, max_age, modified + Duration::from_secs(59))
+            .unwrap()
+            .unwrap();
+        let restored: Value = serde_json::from_str(&restored).unwrap();
+        assert_eq!(restored["spread"], "1");
+        assert_eq!(restored["stale"], true);
+        let written = modified.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        assert_eq!(restored["restored_from"], written);
+        // too old to be useful
+        assert_eq!(
+            load(path, max_age, modified + Duration::from_secs(61)).unwrap(),
+            None
+        );
+        // written by a build of another schema
+        write_atomic(path, br#"{"schema_version":2,"spread":"1"}"#).unwrap();
+        assert_eq!(load(path, max_age, SystemTime::now()).unwrap(), None);
+
+        std::fs::write(path, "not json").unwrap();
+        assert!(load(path, max_age, SystemTime::now()).is_err());
+    }
+}
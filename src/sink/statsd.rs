@@ -0,0 +1,305 @@
+use crate::apitree;
+use crate::config::StatsdSetting;
+use crate::orderbook::{get_unixtime, Level, Regime, Summary};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+
+// DogStatsD over UDP. Every send is a single non-blocking datagram; when the
+// socket buffer is full or nobody listens the metric is simply lost.
+pub struct Statsd {
+    socket: UdpSocket,
+    target: SocketAddr,
+    prefix: String,
+    tags: Vec<String>,
+    sample_rate: f64,
+}
+
+impl Statsd {
+    pub fn new(setting: &StatsdSetting) -> Result<Statsd> {
+        let target = setting
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("statsd address {} not resolved", setting.address))?;
+        let bind: SocketAddr = if target.is_ipv4() {
+            "0.0.0.0:0".parse()?
+        } else {
+            "[::]:0".parse()?
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_nonblocking(true)?;
+        Ok(Statsd {
+            socket,
+            target,
+            prefix: setting.prefix.clone(),
+            tags: setting.tags.clone(),
+            sample_rate: setting.sample_rate,
+        })
+    }
+
+    // {prefix}.{name}:{value}|{kind}[|@{rate}][|#{tags}]
+    fn line(
+        &self,
+        name: &str,
+        value: &str,
+        kind: &str,
+        rate: f64,
+        tags: &[(&str, &str)],
+    ) -> String {
+        let mut line = if self.prefix.is_empty() {
+            format!("{}:{}|{}", name, value, kind)
+        } else {
+            format!("{}.{}:{}|{}", self.prefix, name, value, kind)
+        };
+        if rate < 1. {
+            line += &format!("|@{}", rate);
+        }
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .cloned()
+            .chain(tags.iter().map(|(k, v)| format!("{}:{}", k, v)))
+            .collect();
+        if !tags.is_empty() {
+            line += "|#";
+            line += &tags.join(",");
+        }
+        line
+    }
+
+    fn send(&self, line: String) {
+        let _ = self.socket.send_to(line.as_bytes(), self.target);
+    }
+
+    pub fn count(&self, name: &str, value: i64, tags: &[(&str, &str)]) {
+        self.send(self.line(name, &value.to_string(), "c", 1., tags));
+    }
+
+    // for the hot paths: only sample_rate of the calls are sent
+    pub fn count_sampled(&self, name: &str, tags: &[(&str, &str)]) {
+        if self.sample_rate >= 1. || fastrand::f64() < self.sample_rate {
+            self.send(self.line(name, "1", "c", self.sample_rate, tags));
+        }
+    }
+
+    pub fn gauge(&self, name: &str, value: f64, tags: &[(&str, &str)]) {
+        self.send(self.line(name, &value.to_string(), "g", 1., tags));
+    }
+
+    pub fn timing_sampled(&self, name: &str, elapsed: Duration, tags: &[(&str, &str)]) {
+        if self.sample_rate >= 1. || fastrand::f64() < self.sample_rate {
+            let ms = elapsed.as_secs_f64() * 1000.;
+            self.send(self.line(name, &ms.to_string(), "ms", self.sample_rate, tags));
+        }
+    }
+
+    // gauges derived from the latest summary
+    pub fn report(&self, summary: &Summary, now: u128, ws_clients: i64) {
+        for (exchange, ts) in summary.timestamp.iter() {
+            if let Ok(ts) = ts.parse::<u128>() {
+                let age = now.saturating_sub(ts) as f64;
+                self.gauge("book.age_ms", age, &[("exchange", exchange)]);
+            }
+        }
+        for (name, levels) in [("best_bid", &summary.bids), ("best_ask", &summary.asks)] {
+            for (exchange, price) in best(levels) {
+                self.gauge(name, price, &[("exchange", exchange)]);
+            }
+        }
+        let top = |levels: &[Level]| levels.first().and_then(|l| l.price.parse::<f64>().ok());
+        if let (Some(bid), Some(ask)) = (top(&summary.bids), top(&summary.asks)) {
+            let mid = (bid + ask) / 2.;
+            if mid > 0. {
+                self.gauge("spread_bps", (ask - bid) / mid * 10000., &[]);
+            }
+        }
+        if let Some(v) = &summary.stats.volatility {
+            for w in v.windows.iter() {
+                let window = w.window_secs.to_string();
+                self.gauge("volatility_bps", w.bps, &[("window", &window)]);
+            }
+            // 0 quiet, 1 normal, 2 volatile
+            let regime = match v.regime {
+                Regime::Quiet => 0.,
+                Regime::Normal => 1.,
+                Regime::Volatile => 2.,
+            };
+            self.gauge("volatility.regime", regime, &[]);
+        }
+        for (exchange, rate) in summary.stats.update_rate.iter() {
+            let tags = [("exchange", exchange.as_str())];
+            self.gauge("update.interval_ms", rate.interval_ms, &tags);
+            self.gauge("update.degraded", rate.degraded as u8 as f64, &tags);
+        }
+        self.gauge("ws.clients", ws_clients as f64, &[]);
+    }
+}
+
+// top of book of every exchange in an aggregated side
+fn best(levels: &[Level]) -> HashMap<&str, f64> {
+    let mut result = HashMap::new();
+    for l in levels {
+        if !result.contains_key(l.exchange.as_str()) {
+            if let Ok(price) = l.price.parse::<f64>() {
+                result.insert(l.exchange.as_str(), price);
+            }
+        }
+    }
+    result
+}
+
+static CLIENT: OnceCell<Statsd> = OnceCell::new();
+
+pub fn init(setting: &StatsdSetting) -> Result<()> {
+    let client = Statsd::new(setting)?;
+    info!("statsd metrics to {}", client.target);
+    CLIENT
+        .set(client)
+        .map_err(|_| anyhow!("statsd already initialized"))
+}
+
+// The helpers below are no-ops until init is called.
+
+pub fn count(name: &str, value: i64, tags: &[(&str, &str)]) {
+    if let Some(client) = CLIENT.get() {
+        client.count(name, value, tags);
+    }
+}
+
+pub fn count_sampled(name: &str, tags: &[(&str, &str)]) {
+    if let Some(client) = CLIENT.get() {
+        client.count_sampled(name, tags);
+    }
+}
+
+pub fn gauge(name: &str, value: f64, tags: &[(&str, &str)]) {
+    if let Some(client) = CLIENT.get() {
+        client.gauge(name, value, tags);
+    }
+}
+
+pub fn timing_sampled(name: &str, start: Instant, tags: &[(&str, &str)]) {
+    if let Some(client) = CLIENT.get() {
+        client.timing_sampled(name, start.elapsed(), tags);
+    }
+}
+
+// report the gauges of the latest summary every gauge_interval_ms
+pub async fn run(
+    gauge_interval_ms: u64,
+    ws_clients: fn() -> i64,
+    mut rx: broadcast::Receiver<Arc<Summary>>,
+) {
+    let client = match CLIENT.get() {
+        Some(client) => client,
+        None => return,
+    };
+    let mut ticker = interval(Duration::from_millis(gauge_interval_ms));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut latest: Option<Arc<Summary>> = None;
+    loop {
+        tokio::select! {
+            result = rx.recv() => match result {
+                Ok(summary) => latest = Some(summary),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    warn!("statsd: summary channel closed");
+                    return;
+                }
+            },
+            _ = ticker.tick() => {
+                if let Some(summary) = &latest {
+                    client.report(summary, get_unixtime(), ws_clients());
+                }
+                for (map, size) in apitree::state_sizes() {
+                    client.gauge("parser.state_size", size as f64, &[("map", map)]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+
+    fn client(sample_rate: f64) -> (Statsd, UdpSocket) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(std::time::Duration::from_secs(2)))
+            .unwrap();
+        let statsd = Statsd::new(&StatsdSetting {
+            address: server.local_addr().unwrap().to_string(),
+            prefix: "arb".to_string(),
+            tags: vec!["env:test".to_string()],
+            sample_rate,
+            gauge_interval_ms: 1000,
+        })
+        .unwrap();
+        (statsd, server)
+    }
+
+    fn recv(server: &UdpSocket) -> String {
+        let mut buf = [0u8; 1024];
+        let n = server.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_datagrams() {
+        let (statsd, server) = client(1.);
+        statsd.count("reconnects", 1, &[("exchange", "kraken")]);
+        assert_eq!(
+            recv(&server),
+            "arb.reconnects:1|c|#env:test,exchange:kraken"
+        );
+        statsd.count_sampled("messages.parsed", &[("exchange", "coinjar")]);
+        assert_eq!(
+            recv(&server),
+            "arb.messages.parsed:1|c|#env:test,exchange:coinjar"
+        );
+        statsd.timing_sampled("summary.build", Duration::from_micros(1500), &[]);
+        assert_eq!(recv(&server), "arb.summary.build:1.5|ms|#env:test");
+
+        let (statsd, server) = client(0.5);
+        // sampled calls carry the rate so the server can scale them back
+        for _ in 0..100 {
+            statsd.count_sampled("messages.parsed", &[]);
+        }
+        assert_eq!(recv(&server), "arb.messages.parsed:1|c|@0.5|#env:test");
+    }
+
+    #[test]
+    fn test_report() {
+        let (statsd, server) = client(1.);
+        let mut s = summary(
+            vec![level("kraken", "101", "1"), level("coinjar", "100", "1")],
+            vec![level("coinjar", "102", "0.5"), level("kraken", "103", "1")],
+            1000,
+        );
+        s.timestamp.insert("kraken".to_string(), "400".to_string());
+        statsd.report(&s, 1500, 3);
+        let mut lines: Vec<String> = (0..8).map(|_| recv(&server)).collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                "arb.best_ask:102|g|#env:test,exchange:coinjar",
+                "arb.best_ask:103|g|#env:test,exchange:kraken",
+                "arb.best_bid:100|g|#env:test,exchange:coinjar",
+                "arb.best_bid:101|g|#env:test,exchange:kraken",
+                "arb.book.age_ms:1100|g|#env:test,exchange:kraken",
+                "arb.book.age_ms:500|g|#env:test,exchange:coinjar",
+                "arb.spread_bps:98.52216748768473|g|#env:test",
+                "arb.ws.clients:3|g|#env:test",
+            ]
+        );
+    }
+}
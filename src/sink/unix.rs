@@ -0,0 +1,129 @@
+use anyhow::{anyhow, Result};
+use bytestring::ByteString;
+use log::{error, info, warn};
+use std::fs::Permissions;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast;
+
+// Bind the listener, replacing a socket file left behind by a previous run.
+// Anything else at the path is refused rather than deleted.
+pub fn bind(path: &str, mode: Option<u32>) -> Result<UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(anyhow!("{} exists and is not a socket", path));
+        }
+        std::fs::remove_file(path)?;
+        info!("removed stale socket {}", path);
+    }
+    let listener = UnixListener::bind(path).map_err(|e| anyhow!("bind {}: {}", path, e))?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(Path::new(path), Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+async fn write_line(stream: &mut UnixStream, line: &str) -> std::io::Result<()> {
+    stream.write_all(line.as_bytes()).await?;
+    stream.write_all(b"\n").await
+}
+
+// Same semantics as the websocket Session: snapshot first, then every summary.
+// The broadcast receiver is the per-client buffer; a client that lags behind
+// it gets disconnected.
+async fn serve(
+    mut stream: UnixStream,
+    mut rx: broadcast::Receiver<ByteString>,
+    snapshot: Option<ByteString>,
+) {
+    if let Some(s) = snapshot {
+        if write_line(&mut stream, &s).await.is_err() {
+            return;
+        }
+    }
+    loop {
+        match rx.recv().await {
+            Ok(s) => {
+                if let Err(e) = write_line(&mut stream, &s).await {
+                    info!("unix socket client gone: {}", e);
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("unix socket client lagged {} summaries, disconnect", n);
+                return;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+pub async fn run(
+    listener: UnixListener,
+    tx: broadcast::Sender<ByteString>,
+    snapshot: fn() -> Option<ByteString>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                // subscribe before taking the snapshot so nothing falls in between
+                let rx = tx.subscribe();
+                tokio::spawn(serve(stream, rx, snapshot()));
+            }
+            Err(e) => error!("unix socket accept: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    fn snapshot() -> Option<ByteString> {
+        Some(ByteString::from_static(r#"{"spread":"1"}"#))
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("arb.sock");
+        let path = path.to_str().unwrap();
+        // stale socket from a previous run
+        drop(std::os::unix::net::UnixListener::bind(path).unwrap());
+
+        let listener = bind(path, Some(0o600)).unwrap();
+        let mode = std::fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let (tx, _) = broadcast::channel(10);
+        tokio::spawn(run(listener, tx.clone(), snapshot));
+
+        let mut first = BufReader::new(UnixStream::connect(path).await.unwrap()).lines();
+        assert_eq!(
+            first.next_line().await.unwrap().unwrap(),
+            r#"{"spread":"1"}"#
+        );
+        tx.send(ByteString::from_static(r#"{"spread":"2"}"#))
+            .unwrap();
+        assert_eq!(
+            first.next_line().await.unwrap().unwrap(),
+            r#"{"spread":"2"}"#
+        );
+
+        let mut second = BufReader::new(UnixStream::connect(path).await.unwrap()).lines();
+        assert_eq!(
+            second.next_line().await.unwrap().unwrap(),
+            r#"{"spread":"1"}"#
+        );
+    }
+
+    #[test]
+    fn test_refuse_regular_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("arb.sock");
+        std::fs::write(&path, "data").unwrap();
+        assert!(bind(path.to_str().unwrap(), None).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use super::encode;
+use crate::config::{Encoding, ZmqSetting};
+use crate::orderbook::Summary;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use bytestring::ByteString;
+use log::{error, info, warn};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use zeromq::{PubSocket, Socket, SocketSend, ZmqMessage};
+
+// summaries skipped because the publisher fell behind the pipeline
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// [topic, seq, payload]. seq is an ascii decimal number.
+pub fn message(topic: &str, seq: u64, payload: Vec<u8>) -> ZmqMessage {
+    let mut msg = ZmqMessage::from(topic.to_string());
+    msg.push_back(Bytes::from(seq.to_string()));
+    msg.push_back(Bytes::from(payload));
+    msg
+}
+
+// the next bbo tick, None without a bbo stream
+async fn next_tick(
+    bbo: &mut Option<broadcast::Receiver<ByteString>>,
+) -> Option<Result<ByteString, broadcast::error::RecvError>> {
+    Some(bbo.as_mut()?.recv().await)
+}
+
+// Publishing runs on its own task, fed by its own broadcast receiver, so a
+// blocked socket never stalls aggregation. When the receiver falls behind the
+// oldest summaries are dropped, counted, and skipped in seq. bbo ticks go
+// out as json on `{topic}.bbo`, with a seq of their own.
+async fn publish(
+    mut socket: PubSocket,
+    topic: String,
+    encoding: Encoding,
+    mut rx: broadcast::Receiver<Arc<Summary>>,
+    mut bbo: Option<broadcast::Receiver<ByteString>>,
+) {
+    let bbo_topic = format!("{}.bbo", topic);
+    let mut seq = 0u64;
+    let mut bbo_seq = 0u64;
+    loop {
+        let msg = tokio::select! {
+            result = rx.recv() => {
+                let summary = match result {
+                    Ok(summary) => summary,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        let total = DROPPED.fetch_add(n, Ordering::Relaxed) + n;
+                        warn!("zmq publisher dropped {} summaries, {} in total", n, total);
+                        seq += n;
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                seq += 1;
+                match encode(&summary, encoding) {
+                    Ok(payload) => message(&topic, seq, payload),
+                    Err(e) => {
+                        error!("zmq encode: {}", e);
+                        continue;
+                    }
+                }
+            }
+            Some(result) = next_tick(&mut bbo) => match result {
+                Ok(tick) => {
+                    bbo_seq += 1;
+                    message(&bbo_topic, bbo_seq, tick.as_bytes().to_vec())
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("zmq publisher dropped {} bbo ticks", n);
+                    bbo_seq += n;
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    bbo = None;
+                    continue;
+                }
+            },
+        };
+        if let Err(e) = socket.send(msg).await {
+            error!("zmq send: {}", e);
+        }
+    }
+}
+
+pub async fn run(
+    setting: ZmqSetting,
+    pair: String,
+    rx: broadcast::Receiver<Arc<Summary>>,
+    bbo: Option<broadcast::Receiver<ByteString>>,
+) -> Result<()> {
+    let mut socket = PubSocket::new();
+    let endpoint = socket
+        .bind(&setting.bind)
+        .await
+        .map_err(|e| anyhow!("zmq bind {}: {}", setting.bind, e))?;
+    info!("zmq publisher bound to {}", endpoint);
+    publish(
+        socket,
+        format!("{}{}", setting.topic_prefix, pair),
+        setting.encoding,
+        rx,
+        bbo,
+    )
+    .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::{level, summary};
+    use tokio::time::{sleep, timeout, Duration};
+    use zeromq::{SocketRecv, SubSocket};
+
+    #[tokio::test]
+    async fn test_publish() {
+        let mut socket = PubSocket::new();
+        let endpoint = socket.bind("tcp://127.0.0.1:0").await.unwrap();
+        let (tx, rx) = broadcast::channel(10);
+        let (bbo_tx, bbo_rx) = broadcast::channel(10);
+        tokio::spawn(publish(
+            socket,
+            "md.BTC-AUD".to_string(),
+            Encoding::Msgpack,
+            rx,
+            Some(bbo_rx),
+        ));
+
+        let mut sub = SubSocket::new();
+        sub.connect(&endpoint.to_string()).await.unwrap();
+        sub.subscribe("md.BTC").await.unwrap();
+        let mut other = SubSocket::new();
+        other.connect(&endpoint.to_string()).await.unwrap();
+        other.subscribe("md.ETH").await.unwrap();
+        // slow joiner: give the subscriptions time to reach the publisher
+        sleep(Duration::from_millis(300)).await;
+
+        let s = summary(
+            vec![level("kraken", "101", "1")],
+            vec![level("coinjar", "102", "0.5")],
+            1,
+        );
+        let expected = serde_json::to_value(&s).unwrap();
+        tx.send(Arc::new(s)).unwrap();
+
+        let msg = timeout(Duration::from_secs(5), sub.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.len(), 3);
+        assert_eq!(msg.get(0).unwrap().as_ref(), b"md.BTC-AUD");
+        assert_eq!(msg.get(1).unwrap().as_ref(), b"1");
+        let payload: serde_json::Value = rmp_serde::from_slice(msg.get(2).unwrap()).unwrap();
+        assert_eq!(payload, expected);
+
+        let tick = r#"{"op":"bbo","exchange":"kraken","seq":1}"#;
+        bbo_tx.send(ByteString::from_static(tick)).unwrap();
+        let msg = timeout(Duration::from_secs(5), sub.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.get(0).unwrap().as_ref(), b"md.BTC-AUD.bbo");
+        assert_eq!(msg.get(1).unwrap().as_ref(), b"1");
+        assert_eq!(msg.get(2).unwrap().as_ref(), tick.as_bytes());
+
+        assert!(timeout(Duration::from_millis(300), other.recv())
+            .await
+            .is_err());
+    }
+}
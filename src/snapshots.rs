@@ -0,0 +1,129 @@
+// The latest summary of every topic, what a client gets on connect and on
+// GET /summary before the broadcast brings the next one. A snapshot keeps
+// the structured summary next to the json the publisher broadcast, the
+// other formats are serialized from it once, on first use.
+use crate::config::Encoding;
+use crate::orderbook::Summary;
+use crate::sink;
+use bytes::Bytes;
+use bytestring::ByteString;
+use log::error;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Topic {
+    // the main aggregate
+    Main,
+    // the main aggregate without the exchanges denying redistribution
+    Redistributable,
+    // the aggregate of a pair group, by name
+    Group(String),
+}
+
+pub struct Snapshot {
+    // None for the summary restored from disk, only kept as json
+    summary: Option<Arc<Summary>>,
+    json: ByteString,
+    msgpack: OnceLock<Option<Bytes>>,
+}
+
+impl Snapshot {
+    // json is the summary as broadcast, so the two never differ
+    pub fn new(json: ByteString, summary: Option<Arc<Summary>>) -> Self {
+        Self {
+            summary,
+            json,
+            msgpack: OnceLock::new(),
+        }
+    }
+
+    pub fn summary(&self) -> Option<&Arc<Summary>> {
+        self.summary.as_ref()
+    }
+
+    pub fn json(&self) -> ByteString {
+        self.json.clone()
+    }
+
+    // None without a structured summary to encode
+    pub fn encoded(&self, encoding: Encoding) -> Option<Bytes> {
+        match encoding {
+            Encoding::Json => Some(self.json.as_bytes().clone()),
+            Encoding::Msgpack => self
+                .msgpack
+                .get_or_init(|| {
+                    let summary = self.summary.as_ref()?;
+                    sink::encode(summary, Encoding::Msgpack)
+                        .map(Bytes::from)
+                        .map_err(|e| error!("msgpack snapshot: {}", e))
+                        .ok()
+                })
+                .clone(),
+        }
+    }
+}
+
+static SNAPSHOTS: Lazy<RwLock<HashMap<Topic, Arc<Snapshot>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+// swapped in whole, a reader gets the previous snapshot or this one
+pub fn store(topic: Topic, snapshot: Snapshot) {
+    SNAPSHOTS.write().unwrap().insert(topic, Arc::new(snapshot));
+}
+
+// None until the topic published a summary
+pub fn get(topic: &Topic) -> Option<Arc<Snapshot>> {
+    SNAPSHOTS.read().unwrap().get(topic).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{AggregatedOrderbook, Orderbook, Side};
+    use bigdecimal::BigDecimal;
+    use std::str::FromStr;
+
+    fn summary(exchange: &str) -> Summary {
+        let dec = |s: &str| BigDecimal::from_str(s).unwrap();
+        let mut book = Orderbook::new(exchange);
+        book.insert(Side::Bid, dec("99"), dec("1"));
+        book.insert(Side::Ask, dec("101"), dec("2"));
+        let mut agg = AggregatedOrderbook::new();
+        agg.merge(&book);
+        agg.finalize().unwrap()
+    }
+
+    #[test]
+    fn test_topics() {
+        let topic = Topic::Group("snapshots_test".to_string());
+        assert!(get(&topic).is_none());
+        let summary = Arc::new(summary("snapshots_test"));
+        let json = ByteString::from(serde_json::to_string(&*summary).unwrap());
+        store(topic.clone(), Snapshot::new(json.clone(), Some(summary)));
+        let other = Topic::Group("snapshots_other".to_string());
+        store(
+            other.clone(),
+            Snapshot::new(ByteString::from_static(r#"{"spread":"1"}"#), None),
+        );
+
+        let snapshot = get(&topic).unwrap();
+        assert_eq!(snapshot.json(), json);
+        assert_eq!(snapshot.encoded(Encoding::Json).unwrap(), json.as_bytes());
+        let msgpack = snapshot.encoded(Encoding::Msgpack).unwrap();
+        let decoded: serde_json::Value = rmp_serde::from_slice(&msgpack).unwrap();
+        let expected: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, expected);
+        // encoded once
+        assert_eq!(
+            snapshot.encoded(Encoding::Msgpack).unwrap().as_ptr(),
+            msgpack.as_ptr()
+        );
+        // json only, as restored from disk
+        let snapshot = get(&other).unwrap();
+        assert!(snapshot.summary().is_none());
+        assert!(snapshot.encoded(Encoding::Msgpack).is_none());
+        assert_eq!(snapshot.json(), r#"{"spread":"1"}"#);
+    }
+}
@@ -0,0 +1,385 @@
+// Soak test: synthetic exchanges feed the ingest queue at a chosen rate, so
+// the aggregation, the serialization, the broadcast and the sinks can be
+// hammered locally without a venue. A generator random walks its mid around
+// a shared anchor and sends its whole ladder every book: a new one every
+// snapshot_every books, a few changed levels in between. Seeded, a run
+// sends the same books every time, only their timing differs.
+use crate::config::{ExchangeSetting, GuardrailSetting, InnerConfig, RateSource, SyntheticSpec};
+use crate::guardrails;
+use crate::orderbook::Orderbook;
+use crate::pipeline::{IngestMonitor, IngestSender};
+use crate::timings;
+use bigdecimal::BigDecimal;
+use bytestring::ByteString;
+use log::info;
+use num_bigint::BigInt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+
+// prices in cents, amounts in 1e-4
+const PRICE_SCALE: i64 = 2;
+const AMOUNT_SCALE: i64 = 4;
+// 50000.00, where every mid is pulled back to
+const ANCHOR: f64 = 5_000_000.;
+// per book: the pull back to the anchor, and the largest step, of the mid
+const REVERSION: f64 = 0.001;
+const STEP_BPS: f64 = 0.5;
+// how often a generator catches up with its rate
+const BATCH: Duration = Duration::from_millis(10);
+
+pub fn name(i: usize) -> String {
+    format!("synthetic{}", i)
+}
+
+// The generators in place of the exchanges, on the pair of the config. What
+// would reach a venue is left out: the rest rates, tickers, indexes, clock
+// sync and symbol metadata. The rest, the sinks included, runs as configured.
+pub fn isolate(config: &mut InnerConfig, spec: &SyntheticSpec) {
+    let pair = config.pair_name();
+    config.pair = Some(pair.clone());
+    config.exchange_pair_map = (0..spec.generators)
+        .map(|i| (name(i), vec![ExchangeSetting::new(&pair)]))
+        .collect();
+    config
+        .rates
+        .retain(|_, source| matches!(source, RateSource::Static { .. }));
+    config.ticker_poll_secs = None;
+    config.index_sources.clear();
+    config.time_sync = None;
+    config.symbol_meta = None;
+    config.audit = None;
+}
+
+pub struct Generator {
+    name: String,
+    rng: fastrand::Rng,
+    snapshot_every: u64,
+    levels: usize,
+    // in cents
+    mid: f64,
+    // price -> amount, in cents and 1e-4
+    bid: BTreeMap<i64, i64>,
+    ask: BTreeMap<i64, i64>,
+    sent: u64,
+}
+
+impl Generator {
+    pub fn new(name: String, seed: u64, spec: &SyntheticSpec) -> Generator {
+        Generator {
+            name,
+            rng: fastrand::Rng::with_seed(seed),
+            snapshot_every: spec.snapshot_every,
+            levels: spec.levels,
+            mid: ANCHOR,
+            bid: BTreeMap::new(),
+            ask: BTreeMap::new(),
+            sent: 0,
+        }
+    }
+
+    // 0.01 to 5
+    fn amount(&mut self) -> i64 {
+        self.rng.i64(100..50_000)
+    }
+
+    fn snapshot(&mut self, mid: i64) {
+        self.bid.clear();
+        self.ask.clear();
+        for i in 0..self.levels as i64 {
+            let (bid, ask) = (self.amount(), self.amount());
+            self.bid.insert(mid - 1 - i, bid);
+            self.ask.insert(mid + 1 + i, ask);
+        }
+    }
+
+    // the ladder follows the mid, then up to three levels are resized
+    fn update(&mut self, mid: i64) {
+        self.bid.retain(|price, _| *price < mid);
+        self.ask.retain(|price, _| *price > mid);
+        if !self.bid.contains_key(&(mid - 1)) {
+            let amount = self.amount();
+            self.bid.insert(mid - 1, amount);
+        }
+        if !self.ask.contains_key(&(mid + 1)) {
+            let amount = self.amount();
+            self.ask.insert(mid + 1, amount);
+        }
+        while self.bid.len() > self.levels {
+            self.bid.pop_first();
+        }
+        while self.ask.len() > self.levels {
+            self.ask.pop_last();
+        }
+        for _ in 0..self.rng.usize(1..=3) {
+            let amount = self.amount();
+            let side = match self.rng.bool() {
+                true => &mut self.bid,
+                false => &mut self.ask,
+            };
+            let nth = self.rng.usize(..side.len());
+            if let Some((_, kept)) = side.iter_mut().nth(nth) {
+                *kept = amount;
+            }
+        }
+    }
+
+    pub fn next_book(&mut self) -> Orderbook {
+        let step = (self.rng.f64() * 2. - 1.) * STEP_BPS / 1e4;
+        self.mid += (ANCHOR - self.mid) * REVERSION + self.mid * step;
+        let mid = self.mid.round() as i64;
+        match self.sent % self.snapshot_every {
+            0 => self.snapshot(mid),
+            _ => self.update(mid),
+        }
+        self.sent += 1;
+        let decimal = |n: &i64, scale| BigDecimal::new(BigInt::from(*n), scale);
+        let mut ob = Orderbook::new(&self.name);
+        ob.bid = self
+            .bid
+            .iter()
+            .map(|(p, a)| (decimal(p, PRICE_SCALE), decimal(a, AMOUNT_SCALE)))
+            .collect();
+        ob.ask = self
+            .ask
+            .iter()
+            .map(|(p, a)| (decimal(p, PRICE_SCALE), decimal(a, AMOUNT_SCALE)))
+            .collect();
+        ob
+    }
+}
+
+// Sends `rate` books a second, in batches, until ingest is gone. Behind,
+// it catches up as fast as ingest takes them.
+async fn generate(mut generator: Generator, rate: u64, tx: IngestSender, sent: Arc<AtomicU64>) {
+    let start = Instant::now();
+    let mut ticker = interval(BATCH);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut count = 0u64;
+    loop {
+        ticker.tick().await;
+        let due = (start.elapsed().as_secs_f64() * rate as f64) as u64;
+        while count < due {
+            let ob = generator.next_book();
+            if tx
+                .send((ob.name.clone(), Arc::new(ob), Instant::now()))
+                .is_err()
+            {
+                return;
+            }
+            count += 1;
+            sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// throughput since the start of the run, rates over the last interval
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Report {
+    pub seed: u64,
+    pub elapsed_secs: f64,
+    pub generated: u64,
+    pub ingested: u64,
+    pub ingested_per_sec: f64,
+    pub published: u64,
+    pub publishes_per_sec: f64,
+    // receipt of the newest book to broadcast, of the timings window
+    pub p95_pipeline_ms: f64,
+    pub dropped: u64,
+    pub resident_mb: Option<f64>,
+    // the guardrails breached so far: "dropped", "memory"
+    pub breaches: Vec<&'static str>,
+    // the last one, at the end of the run
+    pub done: bool,
+}
+
+// counts the summaries broadcast, the lagged ones included
+async fn count(mut rx: broadcast::Receiver<ByteString>, published: Arc<AtomicU64>) {
+    loop {
+        match rx.recv().await {
+            Ok(_) => published.fetch_add(1, Ordering::Relaxed),
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                published.fetch_add(n, Ordering::Relaxed)
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+    }
+}
+
+// Runs the generators for spec.secs, printing a report every
+// spec.report_secs, and returns the final one.
+pub async fn run(
+    spec: SyntheticSpec,
+    guardrail: GuardrailSetting,
+    tx: IngestSender,
+    monitor: IngestMonitor,
+    summaries: broadcast::Receiver<ByteString>,
+) -> Report {
+    let seed = spec.seed.unwrap_or_else(|| fastrand::u64(..));
+    info!(
+        "soak: {} generators at {}/s for {}s, seed {}",
+        spec.generators, spec.rate, spec.secs, seed
+    );
+    let sent = Arc::new(AtomicU64::new(0));
+    let published = Arc::new(AtomicU64::new(0));
+    tokio::spawn(count(summaries, published.clone()));
+    let start = Instant::now();
+    for i in 0..spec.generators {
+        // seed + i, the generators of a run differ
+        let generator = Generator::new(name(i), seed.wrapping_add(i as u64), &spec);
+        tokio::spawn(generate(generator, spec.rate, tx.clone(), sent.clone()));
+    }
+    drop(tx);
+    let end = start + Duration::from_secs(spec.secs);
+    let mut ticker = interval(Duration::from_secs(spec.report_secs));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // the first tick is right away
+    ticker.tick().await;
+    let mut breaches = vec![];
+    let (mut last_at, mut last_ingested, mut last_published) = (start, 0, 0);
+    loop {
+        let done = tokio::select! {
+            _ = ticker.tick() => Instant::now() >= end,
+            _ = tokio::time::sleep_until(end) => true,
+        };
+        let now = Instant::now();
+        let generated = sent.load(Ordering::Relaxed);
+        let dropped = monitor.dropped();
+        let ingested = generated.saturating_sub(dropped + monitor.depth() as u64);
+        let published = published.load(Ordering::Relaxed);
+        let resident_mb = guardrails::resident_mb();
+        if dropped > 0 && !breaches.contains(&"dropped") {
+            breaches.push("dropped");
+        }
+        let over = matches!(
+            (guardrail.memory_soft_limit_mb, resident_mb),
+            (Some(limit), Some(mb)) if mb > limit as f64
+        );
+        if over && !breaches.contains(&"memory") {
+            breaches.push("memory");
+        }
+        let secs = now.duration_since(last_at).as_secs_f64().max(1e-3);
+        let pipeline = &timings::report().stages["pipeline"];
+        let report = Report {
+            seed,
+            elapsed_secs: (now - start).as_secs_f64(),
+            generated,
+            ingested,
+            ingested_per_sec: ingested.saturating_sub(last_ingested) as f64 / secs,
+            published,
+            publishes_per_sec: published.saturating_sub(last_published) as f64 / secs,
+            p95_pipeline_ms: pipeline.p95_us as f64 / 1e3,
+            dropped,
+            resident_mb,
+            breaches: breaches.clone(),
+            done,
+        };
+        // on stdout whatever the log level, one json per line
+        println!("soak {}", serde_json::to_string(&report).unwrap());
+        if done {
+            return report;
+        }
+        (last_at, last_ingested, last_published) = (now, ingested, published);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::ingest_channel;
+
+    fn parse(s: &str) -> SyntheticSpec {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_generator() {
+        let spec = parse("levels=5,snapshot_every=10");
+        let books = |seed| {
+            let mut generator = Generator::new(name(0), seed, &spec);
+            (0..50)
+                .map(|_| generator.next_book())
+                .map(|ob| (ob.bid, ob.ask))
+                .collect::<Vec<_>>()
+        };
+        // a seed, the same books
+        let seeded = books(7);
+        assert_eq!(seeded, books(7));
+        assert_ne!(seeded, books(8));
+        for (bid, ask) in seeded.iter() {
+            assert!(bid.len() <= 5 && ask.len() <= 5);
+            assert!(bid.keys().next_back() < ask.keys().next());
+        }
+        // a snapshot refills the ladder, an update changes a few levels
+        assert_eq!(seeded[10].0.len(), 5);
+        let changed = |before: &BTreeMap<BigDecimal, BigDecimal>, after: &BTreeMap<_, _>| {
+            before
+                .iter()
+                .filter(|(p, a)| after.get(*p) != Some(*a))
+                .count()
+        };
+        let changed = changed(&seeded[1].0, &seeded[2].0) + changed(&seeded[1].1, &seeded[2].1);
+        assert!((1..10).contains(&changed), "{}", changed);
+
+        assert!("rate=0".parse::<SyntheticSpec>().is_err());
+        assert!("rate".parse::<SyntheticSpec>().is_err());
+        assert!("speed=1".parse::<SyntheticSpec>().is_err());
+        assert_eq!(parse("").generators, 4);
+    }
+
+    #[tokio::test]
+    async fn test_soak() {
+        // ingest and publish stand-ins: drained, one summary every 100 books
+        let (itx, mut irx) = ingest_channel(100_000);
+        let monitor = irx.monitor();
+        let (btx, _) = broadcast::channel::<ByteString>(100);
+        let summaries = btx.subscribe();
+        tokio::spawn(async move {
+            let mut books = 0;
+            while irx.recv().await.is_some() {
+                books += 1;
+                if books % 100 == 0 {
+                    let _ = btx.send(ByteString::from_static("{}"));
+                }
+            }
+        });
+        let spec = parse("generators=2,rate=2000,secs=1,report_secs=1,seed=7");
+        let report = run(spec, GuardrailSetting::default(), itx, monitor, summaries).await;
+        assert_eq!(report.seed, 7);
+        assert!(report.done);
+        assert!(report.elapsed_secs >= 1.);
+        assert!(report.generated > 1000, "{:?}", report);
+        assert!(report.ingested > 0 && report.ingested <= report.generated);
+        assert!(report.published > 0);
+        assert!(report.ingested_per_sec > 0.);
+        assert_eq!(report.dropped, 0);
+        assert!(report.breaches.is_empty());
+        let fields: Vec<String> = serde_json::to_value(&report)
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        assert_eq!(fields.len(), 12);
+
+        // a soft limit no process stays under
+        let (itx, mut irx) = ingest_channel(100_000);
+        let monitor = irx.monitor();
+        tokio::spawn(async move { while irx.recv().await.is_some() {} });
+        let guardrail = GuardrailSetting {
+            memory_soft_limit_mb: Some(0),
+            ..Default::default()
+        };
+        let spec = parse("generators=1,rate=100,secs=1,seed=7");
+        let (_btx, rx) = broadcast::channel::<ByteString>(1);
+        let report = run(spec, guardrail, itx, monitor, rx).await;
+        match report.resident_mb {
+            Some(_) => assert_eq!(report.breaches, ["memory"]),
+            None => assert!(report.breaches.is_empty()),
+        }
+    }
+}
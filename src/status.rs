@@ -0,0 +1,876 @@
+use crate::apitree::wsapi::VenueStatus;
+use crate::audit::AuditResult;
+use crate::config::MaintenancePolicy;
+use crate::exchange::breaker::{BreakerState, BreakerStatus};
+use crate::orderbook::{get_unixtime, UpdateRate};
+use bytestring::ByteString;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tokio::time::{interval, Duration};
+
+// disconnections kept per exchange for the recent reconnect count
+const RECONNECT_HISTORY: usize = 32;
+// weight of the latest gap in the update interval average
+const INTERVAL_ALPHA: f64 = 0.1;
+// ping round trips kept per exchange for the percentiles
+const PING_HISTORY: usize = 100;
+// how often watch looks for the exchanges gone quiet
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// connection state of a single exchange, maintained by the executors
+#[derive(Debug, Clone, Serialize, PartialEq, Default)]
+pub struct ExchangeStatus {
+    pub connected: bool,
+    // unix millis of the last connected/disconnected transition
+    pub since: u128,
+    // number of reconnections since process start
+    pub reconnects: u64,
+    // unix millis of the last disconnections, oldest first
+    #[serde(skip)]
+    pub reconnect_times: VecDeque<u128>,
+    // unix millis of the last book update
+    pub last_update: Option<u128>,
+    // moving average of the gaps between book updates
+    pub update_interval_ms: Option<f64>,
+    // unix millis of the last crossed or otherwise invalid book
+    pub last_fault: Option<u128>,
+    // venue clock minus ours and the round trip of the last time probe
+    pub clock_offset_ms: Option<i64>,
+    pub clock_rtt_ms: Option<u64>,
+    // |clock_offset_ms| above time_sync.max_offset_ms
+    pub clock_skewed: bool,
+    // receipt of the last venue timestamped message minus its timestamp,
+    // with the timestamp moved to our clock
+    pub latency_ms: Option<u64>,
+    // round trips of the last ws pings, oldest first
+    #[serde(skip)]
+    pub ping_rtts_ms: VecDeque<f64>,
+    // last comparison of the book against the rest snapshot
+    pub audit: Option<AuditResult>,
+    // why the executor gave up on the exchange, it won't reconnect
+    pub failed: Option<String>,
+    // circuit breaker of a polled exchange, once it opened
+    pub breaker: Option<BreakerStatus>,
+    // announced by the venue on its feed, until it says it is online again
+    pub maintenance: Option<Maintenance>,
+    // degraded at the last watch, for the events to be transitions
+    #[serde(skip)]
+    pub slowed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Maintenance {
+    // as the venue names it, ex: maintenance, cancel_only
+    pub status: String,
+    // unix millis it was announced
+    pub since: u128,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PingRtt {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub count: usize,
+}
+
+impl ExchangeStatus {
+    fn new(connected: bool, now: u128) -> ExchangeStatus {
+        ExchangeStatus {
+            connected,
+            since: now,
+            reconnects: 0,
+            reconnect_times: VecDeque::new(),
+            last_update: None,
+            update_interval_ms: None,
+            last_fault: None,
+            clock_offset_ms: None,
+            clock_rtt_ms: None,
+            clock_skewed: false,
+            latency_ms: None,
+            ping_rtts_ms: VecDeque::new(),
+            audit: None,
+            failed: None,
+            breaker: None,
+            maintenance: None,
+            slowed: false,
+        }
+    }
+
+    // percentiles of the recent ping round trips, None before the first pong
+    pub fn ping_rtt(&self) -> Option<PingRtt> {
+        if self.ping_rtts_ms.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<f64> = self.ping_rtts_ms.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        // nearest rank
+        let at = |q: f64| sorted[((q * sorted.len() as f64).ceil() as usize).max(1) - 1];
+        Some(PingRtt {
+            p50_ms: at(0.5),
+            p95_ms: at(0.95),
+            count: sorted.len(),
+        })
+    }
+
+    // no update for more than `factor` average intervals. Needs two
+    // updates to have an average.
+    pub fn degraded(&self, now: u128, factor: f64) -> bool {
+        match (self.last_update, self.update_interval_ms) {
+            (Some(last), Some(avg)) => now.saturating_sub(last) as f64 > factor * avg,
+            _ => false,
+        }
+    }
+
+    // disconnections at or after `since`
+    pub fn reconnects_since(&self, since: u128) -> usize {
+        self.reconnect_times.iter().filter(|t| **t >= since).count()
+    }
+}
+
+static STATUS: Lazy<Mutex<HashMap<String, ExchangeStatus>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusState {
+    Connected,
+    Disconnected,
+    // given up on, it won't reconnect
+    Failed,
+    // no update for degraded_factor average intervals, and back to it
+    Degraded,
+    Recovered,
+    ReconnectScheduled,
+    // circuit breaker of a polled exchange
+    BreakerOpen,
+    BreakerHalfOpen,
+    BreakerClosed,
+    // announced by the venue, and the end of it
+    Maintenance,
+    Online,
+    // of the whole process, no exchange
+    WarmupComplete,
+}
+
+// A transition of the registry, as the ws clients that subscribe_status
+// get it. Levels are never repeated.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct StatusEvent {
+    pub op: &'static str,
+    pub exchange: Option<String>,
+    pub state: StatusState,
+    pub detail: Option<String>,
+    pub ts: u128,
+}
+
+// serialized once, shared by every subscribed session
+static EVENTS: Lazy<broadcast::Sender<ByteString>> = Lazy::new(|| broadcast::channel(256).0);
+
+pub fn events() -> broadcast::Sender<ByteString> {
+    EVENTS.clone()
+}
+
+fn emit(exchange: Option<&str>, state: StatusState, detail: Option<String>) {
+    let event = StatusEvent {
+        op: "status",
+        exchange: exchange.map(str::to_string),
+        state,
+        detail,
+        ts: get_unixtime(),
+    };
+    if let Ok(text) = serde_json::to_string(&event) {
+        // no subscriber is fine
+        let _ = EVENTS.send(ByteString::from(text));
+    }
+}
+static DEGRADED_FACTOR: Lazy<Mutex<f64>> = Lazy::new(|| Mutex::new(5.));
+
+pub fn set_degraded_factor(factor: f64) {
+    *DEGRADED_FACTOR.lock().unwrap() = factor;
+}
+
+fn set(exchange: &str, connected: bool) {
+    let mut tmp = STATUS.lock().unwrap();
+    let now = get_unixtime();
+    let entry = tmp
+        .entry(exchange.to_string())
+        .or_insert_with(|| ExchangeStatus::new(!connected, now));
+    if entry.connected != connected {
+        entry.connected = connected;
+        entry.since = now;
+        entry.slowed = false;
+        let state = match connected {
+            true => StatusState::Connected,
+            false => StatusState::Disconnected,
+        };
+        emit(Some(exchange), state, None);
+    }
+}
+
+pub fn connected(exchange: &str) {
+    set(exchange, true);
+}
+
+pub fn disconnected(exchange: &str) {
+    set(exchange, false);
+    let mut tmp = STATUS.lock().unwrap();
+    if let Some(entry) = tmp.get_mut(exchange) {
+        entry.reconnects += 1;
+        if entry.reconnect_times.len() == RECONNECT_HISTORY {
+            entry.reconnect_times.pop_front();
+        }
+        entry.reconnect_times.push_back(get_unixtime());
+    }
+}
+
+// the exchange is dialed again after `after`
+pub fn reconnect_scheduled(exchange: &str, after: Duration) {
+    emit(
+        Some(exchange),
+        StatusState::ReconnectScheduled,
+        Some(format!("in {}ms", after.as_millis())),
+    );
+}
+
+// a book of the exchange was received at `now`
+pub fn book_updated(exchange: &str, now: u128) {
+    let mut tmp = STATUS.lock().unwrap();
+    let entry = tmp
+        .entry(exchange.to_string())
+        .or_insert_with(|| ExchangeStatus::new(true, now));
+    // the average is kept across reconnects, but a gap spanning one says
+    // nothing about the venue's cadence
+    if let Some(last) = entry.last_update.filter(|last| entry.since <= *last) {
+        let gap = now.saturating_sub(last) as f64;
+        entry.update_interval_ms = Some(match entry.update_interval_ms {
+            Some(avg) => avg + INTERVAL_ALPHA * (gap - avg),
+            None => gap,
+        });
+    }
+    entry.last_update = Some(now);
+    if entry.slowed {
+        entry.slowed = false;
+        emit(Some(exchange), StatusState::Recovered, None);
+    }
+}
+
+// Flag the connected exchanges that went quiet since the last check. They
+// are cleared by their next book.
+pub fn check_degraded(now: u128) {
+    let factor = *DEGRADED_FACTOR.lock().unwrap();
+    let mut tmp = STATUS.lock().unwrap();
+    for (exchange, entry) in tmp.iter_mut() {
+        if entry.slowed || !entry.connected || !entry.degraded(now, factor) {
+            continue;
+        }
+        entry.slowed = true;
+        let detail = format!(
+            "no update for {}ms, {:.0}ms on average",
+            now.saturating_sub(entry.last_update.unwrap_or(now)),
+            entry.update_interval_ms.unwrap_or(0.)
+        );
+        emit(Some(exchange), StatusState::Degraded, Some(detail));
+    }
+}
+
+// a quiet exchange has no book to notice it on, so it is looked for
+pub async fn watch() {
+    let mut ticker = interval(WATCH_INTERVAL);
+    loop {
+        ticker.tick().await;
+        check_degraded(get_unixtime());
+    }
+}
+
+// the book of the exchange was crossed or failed validation at `now`
+pub fn book_fault(exchange: &str, now: u128) {
+    let mut tmp = STATUS.lock().unwrap();
+    let entry = tmp
+        .entry(exchange.to_string())
+        .or_insert_with(|| ExchangeStatus::new(true, now));
+    entry.last_fault = Some(now);
+}
+
+pub fn clock_synced(exchange: &str, offset_ms: i64, rtt_ms: u64, skewed: bool) {
+    let mut tmp = STATUS.lock().unwrap();
+    let entry = tmp
+        .entry(exchange.to_string())
+        .or_insert_with(|| ExchangeStatus::new(false, get_unixtime()));
+    entry.clock_offset_ms = Some(offset_ms);
+    entry.clock_rtt_ms = Some(rtt_ms);
+    entry.clock_skewed = skewed;
+}
+
+// a venue timestamp moved to our clock, unchanged until the venue was probed
+pub fn local_time(exchange: &str, venue_ts: u128) -> u128 {
+    let offset = STATUS
+        .lock()
+        .unwrap()
+        .get(exchange)
+        .and_then(|s| s.clock_offset_ms)
+        .unwrap_or(0);
+    (venue_ts as i128 - offset as i128).max(0) as u128
+}
+
+// a message stamped `local_ts` (see local_time) by the venue was received at `now`
+pub fn venue_message(exchange: &str, local_ts: u128, now: u128) {
+    let mut tmp = STATUS.lock().unwrap();
+    if let Some(entry) = tmp.get_mut(exchange) {
+        entry.latency_ms = Some(now.saturating_sub(local_ts) as u64);
+    }
+}
+
+// a ws ping of the exchange came back after `rtt_ms`
+pub fn ping_returned(exchange: &str, rtt_ms: f64) {
+    let mut tmp = STATUS.lock().unwrap();
+    let entry = tmp
+        .entry(exchange.to_string())
+        .or_insert_with(|| ExchangeStatus::new(true, get_unixtime()));
+    if entry.ping_rtts_ms.len() == PING_HISTORY {
+        entry.ping_rtts_ms.pop_front();
+    }
+    entry.ping_rtts_ms.push_back(rtt_ms);
+}
+
+// the circuit breaker of a polled exchange opened, probes or closed
+pub fn breaker_changed(exchange: &str, breaker: BreakerStatus) {
+    let (state, detail) = match breaker.state {
+        BreakerState::Open => {
+            let ms = breaker
+                .until
+                .unwrap_or_default()
+                .saturating_sub(get_unixtime());
+            (StatusState::BreakerOpen, Some(format!("for {}ms", ms)))
+        }
+        BreakerState::HalfOpen => (StatusState::BreakerHalfOpen, None),
+        BreakerState::Closed => (StatusState::BreakerClosed, None),
+    };
+    let mut tmp = STATUS.lock().unwrap();
+    let entry = tmp
+        .entry(exchange.to_string())
+        .or_insert_with(|| ExchangeStatus::new(false, get_unixtime()));
+    entry.breaker = Some(breaker);
+    emit(Some(exchange), state, detail);
+}
+
+static MAINTENANCE_POLICY: Lazy<Mutex<MaintenancePolicy>> =
+    Lazy::new(|| Mutex::new(MaintenancePolicy::default()));
+
+pub fn set_maintenance_policy(policy: MaintenancePolicy) {
+    *MAINTENANCE_POLICY.lock().unwrap() = policy;
+}
+
+// the venue said on its feed it is under maintenance, or online again
+pub fn venue_status(exchange: &str, venue: VenueStatus, now: u128) {
+    let mut tmp = STATUS.lock().unwrap();
+    let entry = tmp
+        .entry(exchange.to_string())
+        .or_insert_with(|| ExchangeStatus::new(true, now));
+    match venue {
+        VenueStatus::Maintenance(status) => {
+            if entry
+                .maintenance
+                .as_ref()
+                .is_some_and(|m| m.status == status)
+            {
+                return;
+            }
+            emit(
+                Some(exchange),
+                StatusState::Maintenance,
+                Some(status.clone()),
+            );
+            entry.maintenance = Some(Maintenance { status, since: now });
+        }
+        VenueStatus::Online => {
+            if entry.maintenance.take().is_some() {
+                emit(Some(exchange), StatusState::Online, None);
+            }
+        }
+    }
+}
+
+// the exchanges under maintenance
+pub fn under_maintenance() -> BTreeSet<String> {
+    STATUS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, s)| s.maintenance.is_some())
+        .map(|(exchange, _)| exchange.clone())
+        .collect()
+}
+
+// the exchanges whose books are left out of the aggregates
+pub fn evicted() -> BTreeSet<String> {
+    match *MAINTENANCE_POLICY.lock().unwrap() {
+        MaintenancePolicy::Evict => under_maintenance(),
+        MaintenancePolicy::FlagOnly => BTreeSet::new(),
+    }
+}
+
+// the exchange is down for good
+pub fn failed(exchange: &str, reason: &str) {
+    set(exchange, false);
+    let mut tmp = STATUS.lock().unwrap();
+    if let Some(entry) = tmp.get_mut(exchange) {
+        if entry.failed.is_none() {
+            emit(
+                Some(exchange),
+                StatusState::Failed,
+                Some(reason.to_string()),
+            );
+        }
+        entry.failed = Some(reason.to_string());
+    }
+}
+
+pub fn audited(exchange: &str, result: AuditResult) {
+    let mut tmp = STATUS.lock().unwrap();
+    let entry = tmp
+        .entry(exchange.to_string())
+        .or_insert_with(|| ExchangeStatus::new(true, get_unixtime()));
+    entry.audit = Some(result);
+}
+
+fn ping_rtts(status: &HashMap<String, ExchangeStatus>) -> BTreeMap<String, PingRtt> {
+    status
+        .iter()
+        .filter_map(|(exchange, s)| Some((exchange.clone(), s.ping_rtt()?)))
+        .collect()
+}
+
+// prometheus text exposition of the ping round trips and the breakers
+pub fn prometheus() -> String {
+    let mut out = String::new();
+    let status = snapshot();
+    let mut breakers: Vec<(&String, &BreakerStatus)> = status
+        .iter()
+        .filter_map(|(exchange, s)| Some((exchange, s.breaker.as_ref()?)))
+        .collect();
+    breakers.sort_by_key(|(exchange, _)| *exchange);
+    let name = "arb_monitor_breaker_state";
+    let _ = writeln!(
+        out,
+        "# HELP {} circuit breaker of the polled exchanges, 0 closed, 1 half open, 2 open",
+        name
+    );
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for (exchange, breaker) in breakers.iter() {
+        let state = match breaker.state {
+            BreakerState::Closed => 0,
+            BreakerState::HalfOpen => 1,
+            BreakerState::Open => 2,
+        };
+        let _ = writeln!(out, "{}{{exchange=\"{}\"}} {}", name, exchange, state);
+    }
+    let name = "arb_monitor_breaker_opened_total";
+    let _ = writeln!(out, "# HELP {} times the circuit breaker opened", name);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (exchange, breaker) in breakers.iter() {
+        let _ = writeln!(
+            out,
+            "{}{{exchange=\"{}\"}} {}",
+            name, exchange, breaker.opened
+        );
+    }
+    let name = "arb_monitor_ping_rtt_seconds";
+    let _ = writeln!(out, "# HELP {} round trip of the ws pings", name);
+    let _ = writeln!(out, "# TYPE {} summary", name);
+    for (exchange, rtt) in ping_rtts(&status) {
+        for (q, ms) in [("0.5", rtt.p50_ms), ("0.95", rtt.p95_ms)] {
+            let _ = writeln!(
+                out,
+                "{}{{exchange=\"{}\",quantile=\"{}\"}} {}",
+                name,
+                exchange,
+                q,
+                ms / 1e3
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_count{{exchange=\"{}\"}} {}",
+            name, exchange, rtt.count
+        );
+    }
+    out
+}
+
+// update interval average and degraded flag of the exchanges with one
+pub fn update_rates(now: u128) -> BTreeMap<String, UpdateRate> {
+    let factor = *DEGRADED_FACTOR.lock().unwrap();
+    STATUS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(exchange, s)| {
+            let rate = UpdateRate {
+                interval_ms: s.update_interval_ms?,
+                degraded: s.degraded(now, factor),
+            };
+            Some((exchange.clone(), rate))
+        })
+        .collect()
+}
+
+// copy of the current registry, so callers don't hold the lock
+pub fn snapshot() -> HashMap<String, ExchangeStatus> {
+    STATUS.lock().unwrap().clone()
+}
+
+// exchanges the warm-up gate still waits for, None when it is open or unset
+static WARMUP: Lazy<Mutex<Option<Vec<String>>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn warming_up(missing: Option<Vec<String>>) {
+    let mut tmp = WARMUP.lock().unwrap();
+    if tmp.is_some() && missing.is_none() {
+        emit(None, StatusState::WarmupComplete, None);
+    }
+    *tmp = missing;
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    // no summary published yet, the startup quorum isn't there
+    WarmingUp,
+    // publishing, with some exchanges disconnected or slowed down
+    Degraded,
+    Ok,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Health {
+    pub status: HealthState,
+    // warming up: the exchanges without a book yet, otherwise the
+    // disconnected ones
+    pub exchanges: Vec<String>,
+    // exchanges whose clock is off ours by more than time_sync.max_offset_ms.
+    // Their latency figures are corrected, but worth a look.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub clock_skewed: Vec<String>,
+    // connected exchanges updating far slower than they used to
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub degraded: Vec<String>,
+    // of the exchanges probed with ws pings
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub ping_rtt: BTreeMap<String, PingRtt>,
+    // last audit of the audited exchanges
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub audit: BTreeMap<String, AuditResult>,
+    // exchange -> why it was given up on. Also in the disconnected ones.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub failed: BTreeMap<String, String>,
+    // of the polled exchanges whose circuit breaker ever opened. Open or
+    // half open is degraded.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub breakers: BTreeMap<String, BreakerStatus>,
+    // exchange -> the maintenance its venue announced. Degraded.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub maintenance: BTreeMap<String, Maintenance>,
+}
+
+fn health_of(
+    warmup: Option<&Vec<String>>,
+    status: &HashMap<String, ExchangeStatus>,
+    now: u128,
+    factor: f64,
+) -> Health {
+    let mut clock_skewed: Vec<String> = status
+        .iter()
+        .filter(|(_, s)| s.clock_skewed)
+        .map(|(exchange, _)| exchange.clone())
+        .collect();
+    clock_skewed.sort();
+    let mut degraded: Vec<String> = status
+        .iter()
+        .filter(|(_, s)| s.connected && s.degraded(now, factor))
+        .map(|(exchange, _)| exchange.clone())
+        .collect();
+    degraded.sort();
+    let ping_rtt = ping_rtts(status);
+    let audit = status
+        .iter()
+        .filter_map(|(exchange, s)| Some((exchange.clone(), s.audit.clone()?)))
+        .collect();
+    let failed: BTreeMap<String, String> = status
+        .iter()
+        .filter_map(|(exchange, s)| Some((exchange.clone(), s.failed.clone()?)))
+        .collect();
+    let breakers: BTreeMap<String, BreakerStatus> = status
+        .iter()
+        .filter_map(|(exchange, s)| Some((exchange.clone(), s.breaker.clone()?)))
+        .collect();
+    let maintenance: BTreeMap<String, Maintenance> = status
+        .iter()
+        .filter_map(|(exchange, s)| Some((exchange.clone(), s.maintenance.clone()?)))
+        .collect();
+    if let Some(missing) = warmup {
+        return Health {
+            status: HealthState::WarmingUp,
+            exchanges: missing.clone(),
+            clock_skewed,
+            degraded,
+            ping_rtt,
+            audit,
+            failed,
+            breakers,
+            maintenance,
+        };
+    }
+    let mut down: Vec<String> = status
+        .iter()
+        .filter(|(_, s)| !s.connected)
+        .map(|(exchange, _)| exchange.clone())
+        .collect();
+    down.sort();
+    let tripped = breakers.values().any(|b| b.state != BreakerState::Closed);
+    Health {
+        status: if down.is_empty() && degraded.is_empty() && !tripped && maintenance.is_empty() {
+            HealthState::Ok
+        } else {
+            HealthState::Degraded
+        },
+        exchanges: down,
+        clock_skewed,
+        degraded,
+        ping_rtt,
+        audit,
+        failed,
+        breakers,
+        maintenance,
+    }
+}
+
+pub fn health() -> Health {
+    let warmup = WARMUP.lock().unwrap().clone();
+    let factor = *DEGRADED_FACTOR.lock().unwrap();
+    health_of(warmup.as_ref(), &snapshot(), get_unixtime(), factor)
+}
+
+// the health of a pair group, from its exchanges only. Warming up lists the
+// ones without a book yet.
+pub fn health_among(exchanges: &[String], warming_up: bool) -> Health {
+    let factor = *DEGRADED_FACTOR.lock().unwrap();
+    let mut status = snapshot();
+    status.retain(|exchange, _| exchanges.contains(exchange));
+    let warmup = warming_up.then(|| {
+        let mut missing: Vec<String> = exchanges
+            .iter()
+            .filter(|e| status.get(*e).is_none_or(|s| s.last_update.is_none()))
+            .cloned()
+            .collect();
+        missing.sort();
+        missing
+    });
+    health_of(warmup.as_ref(), &status, get_unixtime(), factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    // the events of `exchange` sent so far, the other tests emit too
+    fn events_of(rx: &mut broadcast::Receiver<ByteString>, exchange: &str) -> Vec<Value> {
+        let mut result = vec![];
+        while let Ok(text) = rx.try_recv() {
+            let event: Value = serde_json::from_str(&text).unwrap();
+            if event["exchange"] == exchange {
+                result.push(event);
+            }
+        }
+        result
+    }
+
+    fn states(events: &[Value]) -> Vec<&str> {
+        events
+            .iter()
+            .map(|e| e["state"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_transition_events() {
+        let mut rx = events().subscribe();
+        // levels are not repeated
+        connected("events_test");
+        connected("events_test");
+        let events = events_of(&mut rx, "events_test");
+        assert_eq!(states(&events), ["connected"]);
+        assert_eq!(events[0]["op"], "status");
+        disconnected("events_test");
+        reconnect_scheduled("events_test", Duration::from_secs(1));
+        connected("events_test");
+        let events = events_of(&mut rx, "events_test");
+        assert_eq!(
+            states(&events),
+            ["disconnected", "reconnect_scheduled", "connected"]
+        );
+        assert_eq!(events[1]["detail"], "in 1000ms");
+        // quiet for long past the average, flagged once, cleared by a book
+        let now = get_unixtime();
+        book_updated("events_test", now);
+        book_updated("events_test", now + 100);
+        check_degraded(now + 10_000);
+        check_degraded(now + 10_000);
+        assert_eq!(states(&events_of(&mut rx, "events_test")), ["degraded"]);
+        book_updated("events_test", now + 10_000);
+        assert_eq!(states(&events_of(&mut rx, "events_test")), ["recovered"]);
+        failed("events_test", "unknown pair");
+        failed("events_test", "unknown pair");
+        let events = events_of(&mut rx, "events_test");
+        assert_eq!(states(&events), ["disconnected", "failed"]);
+        assert_eq!(events[1]["detail"], "unknown pair");
+        // announced by the venue, a change of mode is sent, a repeat is not
+        for venue in ["maintenance", "maintenance", "cancel_only"] {
+            venue_status(
+                "events_test",
+                VenueStatus::Maintenance(venue.to_string()),
+                now,
+            );
+        }
+        venue_status("events_test", VenueStatus::Online, now);
+        venue_status("events_test", VenueStatus::Online, now);
+        let events = events_of(&mut rx, "events_test");
+        assert_eq!(states(&events), ["maintenance", "maintenance", "online"]);
+        assert_eq!(events[1]["detail"], "cancel_only");
+    }
+
+    #[test]
+    fn test_book_updates() {
+        book_updated("status_test", 1000);
+        book_updated("status_test", 1200);
+        book_updated("status_test", 2200);
+        book_fault("status_test", 2200);
+        disconnected("status_test");
+        let st = snapshot().remove("status_test").unwrap();
+        assert_eq!(st.last_update, Some(2200));
+        // 200, then 200 + 0.1 * (1000 - 200)
+        assert_eq!(st.update_interval_ms, Some(280.));
+        assert_eq!(st.last_fault, Some(2200));
+        assert_eq!(st.reconnects_since(0), 1);
+        assert_eq!(st.reconnects_since(get_unixtime() + 1), 0);
+    }
+
+    #[test]
+    fn test_degraded() {
+        let name = "degraded_test";
+        let start = get_unixtime() - 60_000;
+        // every 200ms
+        for i in 0..20 {
+            book_updated(name, start + i * 200);
+        }
+        let last = start + 19 * 200;
+        let rate = |now| update_rates(now).remove(name).unwrap();
+        assert_eq!(rate(last).interval_ms, 200.);
+        let st = snapshot().remove(name).unwrap();
+        assert!(!st.degraded(last + 1000, 5.));
+        assert!(st.degraded(last + 1001, 5.));
+        let status = HashMap::from([(name.to_string(), st)]);
+        let health = health_of(None, &status, last + 1001, 5.);
+        assert_eq!(health.status, HealthState::Degraded);
+        assert_eq!(health.degraded, vec![name.to_string()]);
+        assert!(health_of(None, &status, last + 1001, 8.)
+            .degraded
+            .is_empty());
+
+        // the venue is back, a single slow gap moves the average a little
+        book_updated(name, last + 1500);
+        let st = snapshot().remove(name).unwrap();
+        assert!(!st.degraded(last + 1600, 5.));
+        assert_eq!(st.update_interval_ms, Some(200. + 0.1 * 1300.));
+
+        // a reconnect keeps the average and leaves its gap out
+        disconnected(name);
+        connected(name);
+        let resumed = get_unixtime() + 5000;
+        book_updated(name, resumed);
+        let st = snapshot().remove(name).unwrap();
+        assert_eq!(st.update_interval_ms, Some(330.));
+        assert!(!st.degraded(resumed + 200, 5.));
+    }
+
+    #[test]
+    fn test_health() {
+        let mut status = HashMap::from([
+            ("a".to_string(), ExchangeStatus::new(true, 0)),
+            ("b".to_string(), ExchangeStatus::new(true, 0)),
+        ]);
+        assert_eq!(health_of(None, &status, 0, 5.).status, HealthState::Ok);
+        status.get_mut("b").unwrap().connected = false;
+        assert_eq!(
+            health_of(None, &status, 0, 5.),
+            Health {
+                status: HealthState::Degraded,
+                exchanges: vec!["b".to_string()],
+                clock_skewed: vec![],
+                degraded: vec![],
+                ping_rtt: BTreeMap::new(),
+                audit: BTreeMap::new(),
+                failed: BTreeMap::new(),
+                breakers: BTreeMap::new(),
+                maintenance: BTreeMap::new(),
+            }
+        );
+        // warming up wins over degraded
+        let missing = vec!["c".to_string()];
+        assert_eq!(
+            health_of(Some(&missing), &status, 0, 5.),
+            Health {
+                status: HealthState::WarmingUp,
+                exchanges: missing.clone(),
+                clock_skewed: vec![],
+                degraded: vec![],
+                ping_rtt: BTreeMap::new(),
+                audit: BTreeMap::new(),
+                failed: BTreeMap::new(),
+                breakers: BTreeMap::new(),
+                maintenance: BTreeMap::new(),
+            }
+        );
+        status.get_mut("a").unwrap().clock_skewed = true;
+        assert_eq!(
+            health_of(None, &status, 0, 5.).clock_skewed,
+            vec!["a".to_string()]
+        );
+        let a = status.get_mut("a").unwrap();
+        for rtt in 1..=20 {
+            a.ping_rtts_ms.push_back(rtt as f64);
+        }
+        let rtt = PingRtt {
+            p50_ms: 10.,
+            p95_ms: 19.,
+            count: 20,
+        };
+        let health = health_of(None, &status, 0, 5.);
+        assert_eq!(health.ping_rtt, BTreeMap::from([("a".to_string(), rtt)]));
+
+        // a failed exchange is down, with the reason
+        status.get_mut("b").unwrap().failed = Some("Exchange not supported".to_string());
+        let health = health_of(None, &status, 0, 5.);
+        assert_eq!(health.exchanges, vec!["b".to_string()]);
+        assert_eq!(health.failed["b"], "Exchange not supported");
+
+        // an open circuit is degraded, closed again it is not
+        status.get_mut("b").unwrap().connected = true;
+        status.get_mut("b").unwrap().failed = None;
+        let mut breaker = BreakerStatus {
+            state: BreakerState::Open,
+            until: Some(5000),
+            opened: 1,
+        };
+        status.get_mut("a").unwrap().breaker = Some(breaker.clone());
+        let health = health_of(None, &status, 0, 5.);
+        assert_eq!(health.status, HealthState::Degraded);
+        assert_eq!(health.breakers["a"], breaker);
+        breaker.state = BreakerState::Closed;
+        status.get_mut("a").unwrap().breaker = Some(breaker);
+        assert_eq!(health_of(None, &status, 0, 5.).status, HealthState::Ok);
+    }
+}
@@ -0,0 +1,287 @@
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// stages of the marketdata pipeline, timed separately
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    // one frame, in Exchange::next
+    Parse,
+    // the books into the aggregate
+    Merge,
+    // finalize and the stats / opportunities on top of it
+    Finalize,
+    Serialize,
+    // receipt of the newest frame to broadcast
+    Pipeline,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::Parse,
+        Stage::Merge,
+        Stage::Finalize,
+        Stage::Serialize,
+        Stage::Pipeline,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Parse => "parse",
+            Stage::Merge => "merge",
+            Stage::Finalize => "finalize",
+            Stage::Serialize => "serialize",
+            Stage::Pipeline => "pipeline",
+        }
+    }
+}
+
+// sub buckets per power of two, so a bucket is within 1/8 of its values
+const SUB_BITS: u32 = 3;
+const SUB: u64 = 1 << SUB_BITS;
+// microseconds up to 2^36, about 19 hours
+const MAX_BITS: u32 = 36;
+const BUCKETS: usize = ((MAX_BITS - SUB_BITS + 1) as u64 * SUB) as usize;
+// the percentiles cover the current and the previous half
+const HALF: Duration = Duration::from_secs(30);
+
+fn index(us: u64) -> usize {
+    if us < SUB {
+        return us as usize;
+    }
+    let exp = 63 - us.leading_zeros();
+    let mantissa = (us >> (exp - SUB_BITS)) & (SUB - 1);
+    (((exp - SUB_BITS + 1) as u64 * SUB + mantissa) as usize).min(BUCKETS - 1)
+}
+
+// smallest value of the bucket
+fn lower(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB {
+        return index;
+    }
+    let exp = (index / SUB) as u32 + SUB_BITS - 1;
+    (SUB + index % SUB) << (exp - SUB_BITS)
+}
+
+// Fixed bucket histogram of microseconds, log scaled like HdrHistogram.
+// Recording is an index and an increment, no allocation.
+#[derive(Clone)]
+pub struct Histogram {
+    counts: [u64; BUCKETS],
+    total: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: [0; BUCKETS],
+            total: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, us: u64) {
+        self.counts[index(us)] += 1;
+        self.total += 1;
+    }
+
+    pub fn add(&mut self, other: &Histogram) {
+        for (a, b) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *a += b;
+        }
+        self.total += other.total;
+    }
+
+    // q in [0, 1]. None when empty
+    pub fn percentile(&self, q: f64) -> Option<u64> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = ((q * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(lower(i));
+            }
+        }
+        None
+    }
+}
+
+struct Rolling {
+    current: Histogram,
+    previous: Histogram,
+    // start of the current half
+    since: Instant,
+}
+
+impl Rolling {
+    fn new(now: Instant) -> Self {
+        Rolling {
+            current: Histogram::default(),
+            previous: Histogram::default(),
+            since: now,
+        }
+    }
+
+    fn rotate(&mut self, now: Instant) {
+        let age = now.saturating_duration_since(self.since);
+        if age >= HALF * 2 {
+            *self = Rolling::new(now);
+        } else if age >= HALF {
+            self.previous = std::mem::take(&mut self.current);
+            self.since += HALF;
+        }
+    }
+
+    fn window(&mut self, now: Instant) -> Histogram {
+        self.rotate(now);
+        let mut window = self.previous.clone();
+        window.add(&self.current);
+        window
+    }
+}
+
+static STAGES: Lazy<[Mutex<Rolling>; 5]> = Lazy::new(|| {
+    let now = Instant::now();
+    Stage::ALL.map(|_| Mutex::new(Rolling::new(now)))
+});
+
+// time of `stage` since start
+pub fn record(stage: Stage, start: Instant) {
+    let now = Instant::now();
+    let mut rolling = STAGES[stage as usize].lock().unwrap();
+    rolling.rotate(now);
+    rolling
+        .current
+        .record(now.saturating_duration_since(start).as_micros() as u64);
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StageTimings {
+    pub count: u64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    // the percentiles cover at most this long
+    pub window_secs: u64,
+    pub stages: BTreeMap<&'static str, StageTimings>,
+}
+
+pub fn report() -> Report {
+    let now = Instant::now();
+    let stages = Stage::ALL
+        .iter()
+        .map(|stage| {
+            let window = STAGES[*stage as usize].lock().unwrap().window(now);
+            let p = |q| window.percentile(q).unwrap_or(0);
+            let timings = StageTimings {
+                count: window.total,
+                p50_us: p(0.5),
+                p95_us: p(0.95),
+                p99_us: p(0.99),
+            };
+            (stage.name(), timings)
+        })
+        .collect();
+    Report {
+        window_secs: (HALF * 2).as_secs(),
+        stages,
+    }
+}
+
+// prometheus text exposition of the report, as a summary per stage
+pub fn prometheus(report: &Report) -> String {
+    let mut out = String::new();
+    let name = "arb_monitor_pipeline_seconds";
+    let _ = writeln!(out, "# HELP {} time spent per pipeline stage", name);
+    let _ = writeln!(out, "# TYPE {} summary", name);
+    for (stage, t) in report.stages.iter() {
+        for (q, us) in [("0.5", t.p50_us), ("0.95", t.p95_us), ("0.99", t.p99_us)] {
+            let _ = writeln!(
+                out,
+                "{}{{stage=\"{}\",quantile=\"{}\"}} {}",
+                name,
+                stage,
+                q,
+                us as f64 / 1e6
+            );
+        }
+        let _ = writeln!(out, "{}_count{{stage=\"{}\"}} {}", name, stage, t.count);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        for us in [0, 1, 7, 8, 15, 16, 17, 100, 1_000, 123_456, 10_000_000] {
+            let i = index(us);
+            assert!(lower(i) <= us, "{}", us);
+            // within 1/8 of the value
+            assert!(us - lower(i) <= us / 8, "{}", us);
+            assert!(i + 1 == BUCKETS || lower(i + 1) > us, "{}", us);
+        }
+        assert_eq!(index(u64::MAX), BUCKETS - 1);
+
+        let mut h = Histogram::default();
+        assert_eq!(h.percentile(0.5), None);
+        for us in 1..=100 {
+            h.record(us);
+        }
+        assert_eq!(h.percentile(0.5), Some(48));
+        assert_eq!(h.percentile(0.99), Some(96));
+        assert_eq!(h.percentile(1.), Some(96));
+    }
+
+    #[test]
+    fn test_rolling() {
+        let start = Instant::now();
+        let mut r = Rolling::new(start);
+        r.current.record(10);
+        r.rotate(start + HALF);
+        r.current.record(20);
+        assert_eq!(r.window(start + HALF).total, 2);
+        // the first half is out
+        assert_eq!(r.window(start + HALF * 2).total, 1);
+        assert_eq!(r.window(start + HALF * 4).total, 0);
+    }
+
+    #[test]
+    fn test_report() {
+        let start = Instant::now() - Duration::from_micros(1500);
+        record(Stage::Merge, start);
+        record(Stage::Merge, start);
+        let report = report();
+        let merge = &report.stages["merge"];
+        assert!(merge.count >= 2);
+        assert!(merge.p50_us >= 1300);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["window_secs"], 60);
+        for stage in ["parse", "merge", "finalize", "serialize", "pipeline"] {
+            for key in ["count", "p50_us", "p95_us", "p99_us"] {
+                assert!(json["stages"][stage][key].is_u64(), "{} {}", stage, key);
+            }
+        }
+        let text = prometheus(&report);
+        assert!(
+            text.contains("arb_monitor_pipeline_seconds{stage=\"merge\",quantile=\"0.5\"} 0.00")
+        );
+        assert!(text.contains("arb_monitor_pipeline_seconds_count{stage=\"parse\"}"));
+    }
+}
@@ -0,0 +1,120 @@
+use crate::orderbook::Level;
+use bigdecimal::{BigDecimal, Zero};
+use std::str::FromStr;
+
+// one exchange's side of a summary, best first, as (price, amount)
+pub fn ladder(levels: &[Level], exchange: &str) -> Vec<(BigDecimal, BigDecimal)> {
+    levels
+        .iter()
+        .filter(|l| l.exchange == exchange)
+        .filter_map(|l| {
+            let price = BigDecimal::from_str(&l.price).ok()?;
+            let amount = BigDecimal::from_str(&l.amount).ok()?;
+            Some((price, amount))
+        })
+        .collect()
+}
+
+// result of walking a ladder. quantity is less than requested when the book
+// is too thin: fills are capped, never extrapolated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub quantity: BigDecimal,
+    pub notional: BigDecimal,
+}
+
+impl Fill {
+    pub fn vwap(&self) -> Option<BigDecimal> {
+        if self.quantity.is_zero() {
+            return None;
+        }
+        Some(&self.notional / &self.quantity)
+    }
+}
+
+// total amount available on the ladder
+pub fn depth(ladder: &[(BigDecimal, BigDecimal)]) -> BigDecimal {
+    ladder.iter().map(|(_, amount)| amount).sum()
+}
+
+// take up to `quantity` base units from the best level on
+pub fn fill_quantity(ladder: &[(BigDecimal, BigDecimal)], quantity: &BigDecimal) -> Fill {
+    let mut fill = Fill {
+        quantity: BigDecimal::zero(),
+        notional: BigDecimal::zero(),
+    };
+    for (price, amount) in ladder {
+        let remain = quantity - &fill.quantity;
+        if remain <= BigDecimal::zero() {
+            break;
+        }
+        let take = if amount < &remain {
+            amount.clone()
+        } else {
+            remain
+        };
+        fill.notional += price * &take;
+        fill.quantity += take;
+    }
+    fill
+}
+
+// spend up to `notional` quote units from the best level on
+pub fn fill_notional(ladder: &[(BigDecimal, BigDecimal)], notional: &BigDecimal) -> Fill {
+    let mut fill = Fill {
+        quantity: BigDecimal::zero(),
+        notional: BigDecimal::zero(),
+    };
+    for (price, amount) in ladder {
+        let remain = notional - &fill.notional;
+        if remain <= BigDecimal::zero() || price <= &BigDecimal::zero() {
+            break;
+        }
+        let cost = price * amount;
+        if cost <= remain {
+            fill.notional += cost;
+            fill.quantity += amount;
+        } else {
+            fill.quantity += &remain / price;
+            fill.notional += remain;
+        }
+    }
+    fill
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::tests::level;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_fill() {
+        let asks = vec![
+            level("kraken", "100", "1"),
+            level("coinjar", "100.5", "5"),
+            level("kraken", "101", "2"),
+        ];
+        let ladder = ladder(&asks, "kraken");
+        assert_eq!(ladder, vec![(dec("100"), dec("1")), (dec("101"), dec("2"))]);
+        assert_eq!(depth(&ladder), dec("3"));
+
+        let fill = fill_quantity(&ladder, &dec("2"));
+        assert_eq!(fill.quantity, dec("2"));
+        assert_eq!(fill.notional, dec("201"));
+        assert_eq!(fill.vwap(), Some(dec("100.5")));
+        // thinner than requested: capped
+        let fill = fill_quantity(&ladder, &dec("10"));
+        assert_eq!(fill.quantity, dec("3"));
+        assert_eq!(fill.notional, dec("302"));
+
+        let fill = fill_notional(&ladder, &dec("150.5"));
+        assert_eq!(fill.quantity, dec("1.5"));
+        assert_eq!(fill.notional, dec("150.5"));
+        assert_eq!(fill_notional(&ladder, &dec("1000")).notional, dec("302"));
+        assert_eq!(fill_notional(&[], &dec("1000")).vwap(), None);
+    }
+}
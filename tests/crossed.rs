@@ -0,0 +1,190 @@
+// Two venues crossing by 30 bps, through the aggregation, the detector and
+// the publisher: fixed books, no network, the same result on every run.
+use arb_monitor::config::DetectorSetting;
+use arb_monitor::opportunity::{self, tracker::State, tracker::Tracker};
+use arb_monitor::orderbook::{
+    AggregatedOrderbook, Level, Orderbook, Side, Stats, Summary, SummaryView,
+};
+use arb_monitor::pipeline::{self, BookCache};
+use bigdecimal::BigDecimal;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+const TS: u128 = 1696405428703;
+
+fn dec(s: &str) -> BigDecimal {
+    BigDecimal::from_str(s).unwrap()
+}
+
+fn book(exchange: &str, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> Orderbook {
+    let mut ob = Orderbook::new(exchange);
+    for (price, amount) in bids {
+        ob.insert(Side::Bid, dec(price), dec(amount));
+    }
+    for (price, amount) in asks {
+        ob.insert(Side::Ask, dec(price), dec(amount));
+    }
+    ob.set_timestamp(TS);
+    ob
+}
+
+// the cheap venue, its asks are what gets bought
+fn coinjar() -> Orderbook {
+    book(
+        "coinjar",
+        &[("9980", "1"), ("9970", "2")],
+        &[("10000", "0.8"), ("10010", "1")],
+    )
+}
+
+// the rich venue, its best bid 30 bps over the best ask of coinjar
+fn kraken(crossed: bool) -> Orderbook {
+    kraken_bids(if crossed {
+        &[("10030", "0.5"), ("10020", "0.8"), ("9990", "2")]
+    } else {
+        &[("9995", "0.5"), ("9990", "2")]
+    })
+}
+
+fn kraken_bids(bids: &[(&str, &str)]) -> Orderbook {
+    book("kraken", bids, &[("10040", "1"), ("10050", "3")])
+}
+
+fn aggregate(books: &[&Orderbook]) -> (AggregatedOrderbook, Summary) {
+    let mut agg = AggregatedOrderbook::new();
+    for book in books {
+        agg.merge(book);
+    }
+    let summary = agg.finalize().unwrap();
+    (agg, summary)
+}
+
+fn setting() -> DetectorSetting {
+    DetectorSetting {
+        sizes: vec![5000., 13005., 50000.],
+        min_profit_bps: 10.,
+        ..DetectorSetting::default()
+    }
+}
+
+#[test]
+fn test_crossed_summary() {
+    let (coinjar, kraken) = (coinjar(), kraken(true));
+    let (agg, summary) = aggregate(&[&coinjar, &kraken]);
+    assert_eq!(summary.spread, "-30");
+    assert!(summary.crossed);
+    // still best first on both sides, across the venues
+    let prices =
+        |levels: &[Level]| -> Vec<BigDecimal> { levels.iter().map(|l| dec(&l.price)).collect() };
+    let bids = prices(&summary.bids);
+    let asks = prices(&summary.asks);
+    assert!(bids.windows(2).all(|w| w[0] >= w[1]));
+    assert!(asks.windows(2).all(|w| w[0] <= w[1]));
+    assert_eq!(
+        (
+            summary.bids[0].exchange.as_str(),
+            summary.asks[0].exchange.as_str()
+        ),
+        ("kraken", "coinjar")
+    );
+    // the view writes what the summary holds
+    let stats = Stats::default();
+    let view = SummaryView {
+        book: &agg,
+        seq: 0,
+        opportunities: &[],
+        stats: &stats,
+    };
+    let json = serde_json::to_string(&view).unwrap();
+    assert_eq!(json, serde_json::to_string(&summary).unwrap());
+    assert!(json.contains(r#""spread":"-30","crossed":true"#));
+
+    let result = opportunity::detect(&summary, &setting(), &HashMap::new(), TS);
+    assert_eq!(result.len(), 1);
+    let o = &result[0];
+    assert_eq!(o.route(), "coinjar->kraken");
+    assert_eq!(o.profit_bps, 30.);
+    assert_eq!(
+        (&o.buy_price, &o.sell_price),
+        (&dec("10000"), &dec("10030"))
+    );
+    assert_eq!(o.quantity, dec("0.5"));
+    let sizes: Vec<(f64, BigDecimal, BigDecimal, bool)> = o
+        .sizes
+        .iter()
+        .map(|s| (s.size, s.notional.clone(), s.quantity.clone(), s.partial))
+        .collect();
+    assert_eq!(
+        sizes,
+        vec![
+            // 0.5 at 10000, sold at 10030
+            (5000., dec("5000"), dec("0.5"), false),
+            // 0.8 at 10000 + 0.5 at 10010, sold 0.5 at 10030 + 0.8 at 10020
+            (13005., dec("13005"), dec("1.3"), false),
+            // both asks, 1.8, sold down to 9990
+            (50000., dec("18010"), dec("1.8"), true),
+        ]
+    );
+    assert_eq!(o.sizes[0].profit_bps, 30.);
+    assert!((o.sizes[1].profit_bps - 26. / 13005. * 1e4).abs() < 1e-9);
+    // the partial walk doesn't count
+    assert_eq!(o.max_size, Some(13005.));
+
+    // touching is not crossed
+    let touching = kraken_bids(&[("10000", "0.5"), ("9990", "2")]);
+    let (_, summary) = aggregate(&[&coinjar, &touching]);
+    assert_eq!(summary.spread, "0");
+    assert!(!summary.crossed);
+    assert!(!serde_json::to_string(&summary).unwrap().contains("crossed"));
+    assert!(opportunity::detect(&summary, &setting(), &HashMap::new(), TS).is_empty());
+}
+
+#[tokio::test]
+async fn test_publish_on_cross() {
+    let cache = BookCache::default();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    // nothing to say while the cross holds
+    let setting = DetectorSetting {
+        debounce_ms: 60_000,
+        ..setting()
+    };
+    let mut tracker = Tracker::new(setting.clone(), "BTC-AUD");
+    let mut now = TS;
+    let publisher = tokio::spawn(pipeline::publish(cache.clone(), move |books, _| {
+        let books: Vec<&Orderbook> = books.values().map(|b| b.as_ref()).collect();
+        let (_, summary) = aggregate(&books);
+        let detected = opportunity::detect(&summary, &setting, &HashMap::new(), now);
+        let events = tracker.update(&detected, now);
+        now += 1000;
+        tx.send((summary.crossed, events)).unwrap();
+    }));
+
+    let mut published = vec![];
+    cache.update("coinjar".to_string(), Arc::new(coinjar()), Instant::now());
+    for crossed in [false, true, true, false] {
+        cache.update(
+            "kraken".to_string(),
+            Arc::new(kraken(crossed)),
+            Instant::now(),
+        );
+        let (crossed, events) = rx.recv().await.unwrap();
+        let states: Vec<State> = events.iter().map(|e| e.state).collect();
+        published.push((crossed, states));
+    }
+    cache.close();
+    publisher.await.unwrap();
+    // one event when the cross appears, and one when it closes
+    assert_eq!(
+        published,
+        vec![
+            (false, vec![]),
+            (true, vec![State::New]),
+            (true, vec![]),
+            (false, vec![State::Closed]),
+        ]
+    );
+    assert!(rx.try_recv().is_err());
+}
@@ -0,0 +1,131 @@
+// A top-5 book next to a 25 level one: the depth each book came with in the
+// summary, the depth share flagged or left out for the shallow venue, and the
+// sized opportunities capped at what the shallow book holds.
+use arb_monitor::analytics::contribution::Accumulator;
+use arb_monitor::config::{ContributionSetting, DetectorSetting, ShallowBooks};
+use arb_monitor::opportunity;
+use arb_monitor::orderbook::{
+    AggregatedOrderbook, Depth, Orderbook, Side, Stats, Summary, SummaryView,
+};
+use bigdecimal::BigDecimal;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+const TS: u128 = 1696405428703;
+
+fn dec(s: &str) -> BigDecimal {
+    BigDecimal::from_str(s).unwrap()
+}
+
+// `levels` levels a side, a dollar apart from the best ones
+fn book(exchange: &str, bid: u32, ask: u32, levels: u32, amount: &str) -> Orderbook {
+    let mut ob = Orderbook::new(exchange);
+    for i in 0..levels {
+        ob.insert(Side::Bid, (bid - i).into(), dec(amount));
+        ob.insert(Side::Ask, (ask + i).into(), dec(amount));
+    }
+    ob.set_timestamp(TS);
+    ob
+}
+
+// a broker quoting its top 5 only, its bids 30 bps over the asks of deep
+fn aggregate() -> (AggregatedOrderbook, Summary) {
+    let mut agg = AggregatedOrderbook::new();
+    agg.merge(&book("broker", 10030, 10040, 5, "0.2"));
+    agg.merge(&book("deep", 9999, 10000, 25, "1"));
+    let summary = agg.finalize().unwrap();
+    (agg, summary)
+}
+
+fn contribution(shallow_books: ShallowBooks) -> BTreeMap<String, (f64, bool)> {
+    let (agg, _) = aggregate();
+    let mut accumulator = Accumulator::new(ContributionSetting {
+        window_secs: 3600,
+        bucket_secs: 60,
+        levels: 10,
+        shallow_books,
+    });
+    accumulator.update(&agg, TS);
+    accumulator.update(&agg, TS + 1000);
+    accumulator
+        .report()
+        .exchanges
+        .into_iter()
+        .map(|(exchange, c)| (exchange, ((c.depth * 1000.).round() / 1000., c.partial)))
+        .collect()
+}
+
+#[test]
+fn test_depth_in_summary() {
+    let (agg, summary) = aggregate();
+    assert_eq!(
+        summary.depth,
+        BTreeMap::from([
+            ("broker".to_string(), Depth { bid: 5, ask: 5 }),
+            ("deep".to_string(), Depth { bid: 25, ask: 25 }),
+        ])
+    );
+    let stats = Stats::default();
+    let view = SummaryView {
+        book: &agg,
+        seq: 0,
+        opportunities: &[],
+        stats: &stats,
+    };
+    let json = serde_json::to_string(&view).unwrap();
+    assert_eq!(json, serde_json::to_string(&summary).unwrap());
+    assert!(json.contains(r#""depth":{"broker":{"bid":5,"ask":5},"deep":{"bid":25,"ask":25}}"#));
+}
+
+#[test]
+fn test_shallow_contribution() {
+    // the top 10 levels of each side hold 16: 1 on the 5 bids of broker, 5
+    // on the next bids and 10 on the asks of deep
+    let share = 1. / 16.;
+    let round = |v: f64| (v * 1000.).round() / 1000.;
+    assert_eq!(
+        contribution(ShallowBooks::Annotate),
+        BTreeMap::from([
+            ("broker".to_string(), (round(share), true)),
+            ("deep".to_string(), (round(1. - share), false)),
+        ])
+    );
+    // broker still holds the best bid, but no depth share
+    assert_eq!(
+        contribution(ShallowBooks::Exclude),
+        BTreeMap::from([
+            ("broker".to_string(), (0., false)),
+            ("deep".to_string(), (1., false)),
+        ])
+    );
+}
+
+#[test]
+fn test_capped_opportunity() {
+    let (_, summary) = aggregate();
+    let setting = DetectorSetting {
+        sizes: vec![1000., 5000., 50000.],
+        min_profit_bps: 10.,
+        ..DetectorSetting::default()
+    };
+    let result = opportunity::detect(&summary, &setting, &HashMap::new(), TS);
+    assert_eq!(result.len(), 1);
+    let o = &result[0];
+    assert_eq!(o.route(), "deep->broker");
+    let sizes: Vec<(BigDecimal, BigDecimal, bool)> = o
+        .sizes
+        .iter()
+        .map(|s| (s.notional.clone(), s.quantity.clone(), s.partial))
+        .collect();
+    assert_eq!(
+        sizes,
+        vec![
+            (dec("1000"), dec("0.1"), false),
+            (dec("5000"), dec("0.5"), false),
+            // deep has 5 BTC for it, the 5 levels of broker take 1 only
+            (dec("10000"), dec("1.0"), true),
+        ]
+    );
+    assert_eq!(o.sizes[2].sell_vwap, dec("10028"));
+    assert_eq!(o.max_size, Some(5000.));
+}
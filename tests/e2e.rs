@@ -0,0 +1,413 @@
+// The whole pipeline, from the venue feeds to the clients: the binary runs
+// against in-process mock venues replaying recorded frames, found through
+// the endpoint override of the config.
+use actix::{Actor, AsyncContext, StreamHandler};
+use actix_web::{web, App, HttpRequest, HttpServer};
+use actix_web_actors::ws;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::{sleep, timeout, Instant};
+use tokio_stream::wrappers::BroadcastStream;
+
+const BINANCE: &str = include_str!("fixtures/binance.jsonl");
+const BITSTAMP: &str = include_str!("fixtures/bitstamp.jsonl");
+const DEADLINE: Duration = Duration::from_secs(10);
+
+// plays the first frames of its fixture on connect, then whatever is pushed
+struct Venue {
+    frames: Vec<String>,
+    push: Option<broadcast::Receiver<String>>,
+}
+
+impl Actor for Venue {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        for frame in self.frames.iter() {
+            ctx.text(frame.clone());
+        }
+        if let Some(rx) = self.push.take() {
+            ctx.add_stream(BroadcastStream::new(rx));
+        }
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Venue {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        // subscriptions are taken as granted
+        if let Ok(ws::Message::Ping(msg)) = msg {
+            ctx.pong(&msg);
+        }
+    }
+}
+
+impl StreamHandler<Result<String, tokio_stream::wrappers::errors::BroadcastStreamRecvError>>
+    for Venue
+{
+    fn handle(
+        &mut self,
+        frame: Result<String, tokio_stream::wrappers::errors::BroadcastStreamRecvError>,
+        ctx: &mut Self::Context,
+    ) {
+        if let Ok(frame) = frame {
+            ctx.text(frame);
+        }
+    }
+}
+
+struct Mock {
+    addr: SocketAddr,
+    push: broadcast::Sender<String>,
+    handle: actix_web::dev::ServerHandle,
+    // connections made to the venue so far
+    connections: Arc<AtomicUsize>,
+}
+
+fn mock(fixture: &'static str, replayed: usize) -> Mock {
+    let (push, _) = broadcast::channel::<String>(16);
+    let tx = push.clone();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+    let server = HttpServer::new(move || {
+        let tx = tx.clone();
+        let counter = counter.clone();
+        App::new().default_service(web::to(move |req: HttpRequest, stream: web::Payload| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let venue = Venue {
+                frames: fixture.lines().take(replayed).map(str::to_string).collect(),
+                push: Some(tx.subscribe()),
+            };
+            async move { ws::start(venue, &req, stream) }
+        }))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let addr = server.addrs()[0];
+    let server = server.run();
+    let handle = server.handle();
+    tokio::spawn(server);
+    Mock {
+        addr,
+        push,
+        handle,
+        connections,
+    }
+}
+
+// killed on drop, so a failed assert doesn't leave it running
+struct Monitor(Child);
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+    }
+}
+
+// runs the binary, returns once it printed the address it listens on
+fn start(config: &std::path::Path) -> (Monitor, SocketAddr) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_arb_monitor"))
+        .arg("--config-path")
+        .arg(config)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let monitor = Monitor(child);
+    let (tx, rx) = std::sync::mpsc::channel();
+    // drained to the end, the monitor fails on a closed stdout
+    std::thread::spawn(move || {
+        for line in stdout.split(b'\n').map_while(Result::ok) {
+            let line = String::from_utf8_lossy(&line);
+            if let Some(addr) = line.strip_prefix("listening on ") {
+                if let Ok(addr) = addr.trim().parse::<SocketAddr>() {
+                    let _ = tx.send(addr);
+                }
+            }
+        }
+    });
+    let addr = rx
+        .recv_timeout(DEADLINE)
+        .expect("the monitor to listen, see e2e.log");
+    (monitor, addr)
+}
+
+async fn get(url: &str) -> (u16, Value) {
+    let mut response = awc::Client::new().get(url).send().await.unwrap();
+    let body = response.body().await.unwrap();
+    (
+        response.status().as_u16(),
+        serde_json::from_slice(&body).unwrap(),
+    )
+}
+
+fn exchanges(summary: &Value) -> Vec<String> {
+    summary["timestamp"]
+        .as_object()
+        .map(|m| m.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+// polls `url` until `done` holds of its body
+async fn until<F>(url: &str, done: F) -> Value
+where
+    F: Fn(u16, &Value) -> bool,
+{
+    let deadline = Instant::now() + DEADLINE;
+    loop {
+        let (status, body) = get(url).await;
+        if done(status, &body) {
+            return body;
+        }
+        assert!(Instant::now() < deadline, "{} is still {}", url, body);
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+async fn next_summary(client: &mut actix_codec::Framed<awc::BoxedSocket, awc::ws::Codec>) -> Value {
+    loop {
+        let frame = timeout(DEADLINE, client.next())
+            .await
+            .expect("a summary")
+            .unwrap()
+            .unwrap();
+        if let awc::ws::Frame::Text(text) = frame {
+            return serde_json::from_slice(&text).unwrap();
+        }
+    }
+}
+
+#[actix_web::test]
+async fn test_pipeline() {
+    let binance = mock(BINANCE, 1);
+    let bitstamp = mock(BITSTAMP, 2);
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.yaml");
+    std::fs::write(
+        &config,
+        format!(
+            r#"
+exchange_pair_map:
+  binance:
+    - pair: btcusdt
+      endpoint: "ws://{}/stream?streams={{}}"
+  bitstamp:
+    - pair: btcusd
+      endpoint: "ws://{}/"
+server_port: 0
+log_path: "{}"
+log_level: "Error"
+listeners:
+  - addr: "127.0.0.1"
+    port: 0
+"#,
+            binance.addr,
+            bitstamp.addr,
+            dir.path().join("e2e.log").display()
+        ),
+    )
+    .unwrap();
+    let (mut monitor, addr) = start(&config);
+
+    // both venues make it into the aggregate
+    let summary = until(&format!("http://{}/summary", addr), |status, body| {
+        status == 200 && exchanges(body) == ["binance", "bitstamp"]
+    })
+    .await;
+    assert_eq!(summary["schema_version"], 1);
+    let (status, health) = get(&format!("http://{}/health", addr)).await;
+    assert_eq!(status, 200);
+    assert_eq!(health["status"], "ok");
+
+    // the cached summary comes first, then the ones of the next frames
+    let (_, mut client) = awc::Client::new()
+        .ws(format!("ws://{}/ws", addr))
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(next_summary(&mut client).await, summary);
+    binance
+        .push
+        .send(BINANCE.lines().nth(1).unwrap().to_string())
+        .unwrap();
+    let next = next_summary(&mut client).await;
+    assert_eq!(exchanges(&next), ["binance", "bitstamp"]);
+    assert_ne!(next, summary);
+    // gone without a close frame, the session ends all the same
+    drop(client);
+
+    // a venue going away degrades the health, its last book stays in
+    bitstamp.handle.stop(false).await;
+    let health = until(&format!("http://{}/health", addr), |_, body| {
+        body["status"] == "degraded"
+    })
+    .await;
+    assert_eq!(health["exchanges"], serde_json::json!(["bitstamp"]));
+    let (_, summary) = get(&format!("http://{}/summary", addr)).await;
+    assert_eq!(exchanges(&summary), ["binance", "bitstamp"]);
+
+    // stops on SIGTERM, like under systemd
+    let pid = monitor.0.id().to_string();
+    assert!(Command::new("kill")
+        .args(["-TERM", &pid])
+        .status()
+        .unwrap()
+        .success());
+    let deadline = Instant::now() + DEADLINE;
+    let status = loop {
+        if let Some(status) = monitor.0.try_wait().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "still running after SIGTERM");
+        sleep(Duration::from_millis(100)).await;
+    };
+    assert!(status.success(), "{:?}", status);
+    binance.handle.stop(false).await;
+}
+
+#[actix_web::test]
+async fn test_port_in_use() {
+    let binance = mock(BINANCE, 1);
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.yaml");
+    std::fs::write(
+        &config,
+        format!(
+            r#"
+exchange_pair_map:
+  binance:
+    - pair: btcusdt
+      endpoint: "ws://{}/stream?streams={{}}"
+server_port: 0
+log_path: "{}"
+log_level: "Error"
+listeners:
+  - addr: "127.0.0.1"
+    port: {}
+"#,
+            binance.addr,
+            dir.path().join("e2e.log").display(),
+            port
+        ),
+    )
+    .unwrap();
+    let child = Command::new(env!("CARGO_BIN_EXE_arb_monitor"))
+        .arg("--config-path")
+        .arg(&config)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut monitor = Monitor(child);
+    let deadline = Instant::now() + DEADLINE;
+    let status = loop {
+        if let Some(status) = monitor.0.try_wait().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "still running on a taken port");
+        sleep(Duration::from_millis(50)).await;
+    };
+    let mut stderr = String::new();
+    std::io::Read::read_to_string(monitor.0.stderr.as_mut().unwrap(), &mut stderr).unwrap();
+
+    // one line naming the address, before any venue was connected to
+    assert_eq!(status.code(), Some(5), "{}", stderr);
+    assert_eq!(stderr.lines().count(), 1, "{}", stderr);
+    let expected = format!("bind: cannot bind 127.0.0.1:{}: ", port);
+    assert!(stderr.starts_with(&expected), "{}", stderr);
+    assert!(stderr.contains("os error"), "{}", stderr);
+    assert_eq!(binance.connections.load(Ordering::SeqCst), 0);
+    drop(taken);
+    binance.handle.stop(false).await;
+}
+
+// runs the binary in synthetic mode to its end: its exit code and reports
+fn soak(config: &std::path::Path, spec: &str) -> (Option<i32>, Vec<Value>) {
+    let output = Command::new(env!("CARGO_BIN_EXE_arb_monitor"))
+        .arg("--config-path")
+        .arg(config)
+        .arg("--synthetic")
+        .arg(spec)
+        .output()
+        .unwrap();
+    let reports = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.strip_prefix("soak "))
+        .map(|report| serde_json::from_str(report).unwrap())
+        .collect();
+    (output.status.code(), reports)
+}
+
+#[actix_web::test]
+async fn test_soak() {
+    let binance = mock(BINANCE, 1);
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.yaml");
+    let write = |guardrails: &str| {
+        std::fs::write(
+            &config,
+            format!(
+                r#"
+exchange_pair_map:
+  binance:
+    - pair: btcusdt
+      endpoint: "ws://{}/stream?streams={{}}"
+server_port: 0
+log_path: "{}"
+log_level: "Error"
+listeners:
+  - addr: "127.0.0.1"
+    port: 0
+{}
+"#,
+                binance.addr,
+                dir.path().join("e2e.log").display(),
+                guardrails
+            ),
+        )
+        .unwrap();
+    };
+    write("");
+    let spec = "generators=2,rate=500,secs=2,report_secs=1,seed=3";
+    let (code, reports) = soak(&config, spec);
+    assert_eq!(code, Some(0), "{:?}", reports);
+    // a report a second, the last one at the end
+    assert!(reports.len() >= 2, "{:?}", reports);
+    let last = reports.last().unwrap();
+    assert_eq!(last["done"], true);
+    assert_eq!(last["seed"], 3);
+    assert!(last["ingested"].as_u64().unwrap() > 0, "{}", last);
+    assert!(last["published"].as_u64().unwrap() > 0, "{}", last);
+    assert_eq!(last["dropped"], 0);
+    assert_eq!(last["breaches"], serde_json::json!([]));
+    let fields = |report: &Value| {
+        report
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    assert!(reports.iter().all(|r| fields(r) == fields(last)));
+    // the generators stood for the venues
+    assert_eq!(binance.connections.load(Ordering::SeqCst), 0);
+
+    // over a soft limit no process stays under
+    write("guardrails:\n  memory_soft_limit_mb: 1");
+    let (code, reports) = soak(&config, "generators=1,rate=100,secs=1,seed=3");
+    assert_eq!(code, Some(1), "{:?}", reports);
+    assert_eq!(
+        reports.last().unwrap()["breaches"],
+        serde_json::json!(["memory"])
+    );
+    binance.handle.stop(false).await;
+}
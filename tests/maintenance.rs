@@ -0,0 +1,166 @@
+// Venues announcing maintenance on their feeds, then coming back: recorded
+// frames of kraken and btcmarkets through their parsers, the status
+// registry and the aggregation, under both maintenance policies.
+use arb_monitor::apitree::wsapi::{ParseOutcome, WS_APIMAP};
+use arb_monitor::config::MaintenancePolicy;
+use arb_monitor::orderbook::{get_unixtime, AggregatedOrderbook, Orderbook, Summary};
+use arb_monitor::pipeline;
+use arb_monitor::status::{self, HealthState};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+const KRAKEN: &str = include_str!("fixtures/kraken_maintenance.jsonl");
+const BTCMARKETS: &str = include_str!("fixtures/btcmarkets_maintenance.jsonl");
+
+// (exchange, line of its fixture), in the order they arrive
+const SCRIPT: [(&str, usize); 9] = [
+    ("kraken", 0),
+    ("kraken", 1),
+    ("btcmarkets", 0),
+    // kraken: maintenance
+    ("kraken", 2),
+    // btcmarkets: post only, then cancel only
+    ("btcmarkets", 1),
+    ("btcmarkets", 2),
+    // kraken: online, then a book update
+    ("kraken", 3),
+    ("kraken", 4),
+    // btcmarkets: online
+    ("btcmarkets", 3),
+];
+
+// what the outputs show after a frame
+#[derive(Debug, PartialEq)]
+struct Step {
+    // exchanges with levels in the summary
+    venues: Vec<String>,
+    maintenance: Vec<String>,
+    health: HealthState,
+}
+
+fn venues(summary: &Summary) -> Vec<String> {
+    let venues: BTreeSet<String> = summary
+        .bids
+        .iter()
+        .chain(summary.asks.iter())
+        .map(|l| l.exchange.clone())
+        .collect();
+    venues.into_iter().collect()
+}
+
+fn replay(policy: MaintenancePolicy) -> (Vec<Step>, Summary) {
+    status::set_maintenance_policy(policy);
+    (WS_APIMAP.get("kraken").unwrap().clear)();
+    let mut books: HashMap<String, Arc<Orderbook>> = HashMap::new();
+    let mut agg = AggregatedOrderbook::new();
+    let mut steps = vec![];
+    let mut summary = None;
+    let start = get_unixtime();
+    for (i, (exchange, line)) in SCRIPT.into_iter().enumerate() {
+        let fixture = if exchange == "kraken" {
+            KRAKEN
+        } else {
+            BTCMARKETS
+        };
+        let raw = fixture.lines().nth(line).unwrap();
+        let now = start + i as u128 * 1000;
+        match WS_APIMAP.get(exchange).unwrap().parse_book(None, raw) {
+            ParseOutcome::Book(book) => {
+                status::book_updated(exchange, now);
+                books.insert(exchange.to_string(), book);
+            }
+            ParseOutcome::Status(venue) => status::venue_status(exchange, venue, now),
+            other => panic!("{}: {:?}", raw, other),
+        }
+        pipeline::merge(&mut agg, &books);
+        let mut result = agg.finalize().unwrap();
+        result.stats.maintenance = pipeline::maintenance(&books);
+        steps.push(Step {
+            venues: venues(&result),
+            maintenance: result.stats.maintenance.clone(),
+            health: status::health().status,
+        });
+        summary = Some(result);
+    }
+    (steps, summary.unwrap())
+}
+
+fn step(venues: &[&str], maintenance: &[&str], health: HealthState) -> Step {
+    let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect();
+    Step {
+        venues: strings(venues),
+        maintenance: strings(maintenance),
+        health,
+    }
+}
+
+#[test]
+fn test_maintenance_then_online() {
+    let mut rx = status::events().subscribe();
+    let both = ["btcmarkets", "kraken"];
+    let (ok, degraded) = (HealthState::Ok, HealthState::Degraded);
+
+    let (steps, summary) = replay(MaintenancePolicy::Evict);
+    assert_eq!(
+        steps,
+        vec![
+            step(&[], &[], ok),
+            step(&["kraken"], &[], ok),
+            step(&both, &[], ok),
+            // the frozen books leave the aggregate
+            step(&["btcmarkets"], &["kraken"], degraded),
+            step(&[], &both, degraded),
+            step(&[], &both, degraded),
+            step(&["kraken"], &["btcmarkets"], degraded),
+            step(&["kraken"], &["btcmarkets"], degraded),
+            step(&both, &[], ok),
+        ]
+    );
+    // back with the book as it is now
+    assert_eq!(summary.asks[0].exchange, "kraken");
+    assert_eq!(summary.asks[0].price, "31849.00000");
+    assert!(!serde_json::to_string(&summary)
+        .unwrap()
+        .contains("maintenance"));
+    let events: Vec<(String, String, Value)> = std::iter::from_fn(|| rx.try_recv().ok())
+        .map(|text| {
+            let event: Value = serde_json::from_str(&text).unwrap();
+            let exchange = event["exchange"].as_str().unwrap().to_string();
+            let state = event["state"].as_str().unwrap().to_string();
+            (exchange, state, event["detail"].clone())
+        })
+        .collect();
+    let event = |exchange: &str, state: &str, detail: Value| {
+        (exchange.to_string(), state.to_string(), detail)
+    };
+    assert_eq!(
+        events,
+        vec![
+            event("kraken", "maintenance", "maintenance".into()),
+            event("btcmarkets", "maintenance", "post_only".into()),
+            event("btcmarkets", "maintenance", "cancel_only".into()),
+            event("kraken", "online", Value::Null),
+            event("btcmarkets", "online", Value::Null),
+        ]
+    );
+
+    // the books stay, flagged
+    let (steps, _) = replay(MaintenancePolicy::FlagOnly);
+    assert_eq!(
+        steps,
+        vec![
+            step(&[], &[], ok),
+            step(&["kraken"], &[], ok),
+            step(&both, &[], ok),
+            step(&both, &["kraken"], degraded),
+            step(&both, &both, degraded),
+            step(&both, &both, degraded),
+            step(&both, &["btcmarkets"], degraded),
+            step(&both, &["btcmarkets"], degraded),
+            step(&both, &[], ok),
+        ]
+    );
+    let health = status::health();
+    assert!(health.maintenance.is_empty());
+}
@@ -0,0 +1,108 @@
+// The published summary written from the aggregate: counted by a global
+// allocator, serializing the view into a buffer large enough allocates
+// nothing, finalize formatted the numbers beforehand.
+use arb_monitor::opportunity::tracker::State;
+use arb_monitor::orderbook::{
+    AggregatedOrderbook, OpportunitySummary, Orderbook, Side, Stats, SummaryView,
+};
+use bigdecimal::BigDecimal;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::str::FromStr;
+
+// counts the allocations of the current thread while counting is on, the
+// other tests of the binary aren't counted
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn count() {
+    // the thread locals may be gone when a thread exits
+    let _ = ALLOCATIONS.try_with(|n| n.set(n.get().map(|n| n + 1)));
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// the allocations made by `f`
+fn allocations(f: impl FnOnce()) -> usize {
+    ALLOCATIONS.with(|n| n.set(Some(0)));
+    f();
+    ALLOCATIONS.with(|n| n.take()).unwrap()
+}
+
+fn dec(s: &str) -> BigDecimal {
+    BigDecimal::from_str(s).unwrap()
+}
+
+#[test]
+fn test_view_allocations() {
+    let mut a = Orderbook::new("A");
+    a.insert(Side::Bid, dec("99.50"), dec("1.25"));
+    a.insert(Side::Bid, dec("99"), dec("0.001"));
+    a.insert(Side::Ask, dec("100.0"), dec("2"));
+    a.set_timestamp(1696405428703);
+    let mut b = Orderbook::new("B");
+    b.insert(Side::Bid, dec("99"), dec("3"));
+    b.insert(Side::Ask, dec("100.123456"), dec("0.30000000000000004"));
+    b.set_timestamp(1696405428704);
+    let mut agg = AggregatedOrderbook::new();
+    agg.merge(&a);
+    agg.merge(&b);
+    agg.consolidated_levels = Some(2);
+    agg.index_prices.insert("index".to_string(), dec("99.75"));
+    agg.output_precision = Some(serde_yaml::from_str("{}").unwrap());
+    let mut summary = agg.finalize().unwrap();
+    summary.opportunities = vec![OpportunitySummary {
+        state: State::New,
+        buy_exchange: "B".to_string(),
+        sell_exchange: "A".to_string(),
+        opened: 1,
+        profit_bps: 12.5,
+        quantity: "1".to_string(),
+        max_size: None,
+        confidence: 1.,
+        net: None,
+    }];
+    summary.stats = Stats {
+        leader: Some("A".to_string()),
+        ..Stats::default()
+    };
+    let view = SummaryView {
+        book: &agg,
+        seq: 3,
+        opportunities: &summary.opportunities,
+        stats: &summary.stats,
+    };
+    summary.seq = 3;
+    let golden = serde_json::to_vec(&summary).unwrap();
+
+    let mut buf = Vec::with_capacity(golden.len());
+    let n = allocations(|| serde_json::to_writer(&mut buf, &view).unwrap());
+    assert_eq!(n, 0);
+    assert_eq!(buf, golden);
+    // into a fresh buffer, only the buffer is allocated and grown
+    let mut json = String::new();
+    let n = allocations(|| json = serde_json::to_string(&view).unwrap());
+    assert!(n > 0);
+    assert!(n <= golden.len().ilog2() as usize, "{}", n);
+    assert_eq!(json.as_bytes(), golden);
+}